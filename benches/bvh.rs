@@ -0,0 +1,108 @@
+//! Build and traversal timings of the standalone BVH, run with `cargo bench --bench bvh`.
+//! An optional argument sets the sphere's resolution, so `cargo bench --bench bvh -- 1024` builds
+//! from about two million triangles.
+
+use std::time::{Duration, Instant};
+
+use glam::Vec3;
+use ray_tracer_2::bvh::{Bvh, Quality, Ray, Triangle};
+
+/// Rays cast per traversal timing
+const RAYS: usize = 200_000;
+
+/// UV sphere with `rings` rings of `2 * rings` quads, bumped so the splits aren't all trivial.
+fn sphere(rings: usize) -> Vec<Triangle> {
+    let segments = rings * 2;
+    let point = |ring: usize, segment: usize| {
+        let theta = ring as f32 / rings as f32 * std::f32::consts::PI;
+        let phi = segment as f32 / segments as f32 * std::f32::consts::TAU;
+        let bump = 1.0 + 0.05 * (theta * 17.0).sin() * (phi * 13.0).cos();
+        Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        ) * bump
+    };
+    let mut soup = Vec::with_capacity(rings * segments * 2);
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let [a, b, c, d] = [
+                point(ring, segment),
+                point(ring + 1, segment),
+                point(ring + 1, segment + 1),
+                point(ring, segment + 1),
+            ];
+            soup.push([a, b, c]);
+            soup.push([a, c, d]);
+        }
+    }
+    soup
+}
+
+/// Rays from a shell around the sphere towards points scattered near its centre, so most hit.
+fn rays(count: usize) -> Vec<Ray> {
+    (0..count)
+        .map(|i| {
+            let t = i as f32 * 0.618_034;
+            let origin = Vec3::new(t.sin() * 3.0, (t * 0.37).cos() * 3.0, t.cos() * 3.0);
+            let target = Vec3::new((t * 1.3).sin(), (t * 2.1).cos(), (t * 0.7).sin()) * 0.5;
+            Ray::new(origin, (target - origin).normalize())
+        })
+        .collect()
+}
+
+/// Fastest of `runs` timings, the least disturbed by anything else running.
+fn time<T>(runs: usize, mut f: impl FnMut() -> T) -> (Duration, T) {
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..runs {
+        let start = Instant::now();
+        result = Some(std::hint::black_box(f()));
+        best = best.min(start.elapsed());
+    }
+    (best, result.unwrap())
+}
+
+fn main() {
+    let rings = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(256);
+    let soup = sphere(rings);
+    let rays = rays(RAYS);
+    println!("{} triangles, {} rays", soup.len(), rays.len());
+    for quality in [Quality::Low, Quality::High] {
+        let (build, bvh) = time(3, || Bvh::build(&soup, quality));
+        let stats = bvh.stats();
+        println!(
+            "{:?}: build {:.2?}, {} nodes, depth {}..{}, {:.1} triangles per leaf, SAH cost {:.1}",
+            quality,
+            build,
+            stats.nodes,
+            stats.min_depth,
+            stats.max_depth,
+            stats.mean_leaf_triangles,
+            stats.sah_cost
+        );
+        let (traverse, hits) = time(3, || {
+            rays.iter()
+                .filter(|ray| bvh.intersect(ray, f32::INFINITY).is_some())
+                .count()
+        });
+        println!(
+            "{:?}: {} hits, {:.2} Mrays/s",
+            quality,
+            hits,
+            rays.len() as f64 / traverse.as_secs_f64() / 1e6
+        );
+        let (serialize, bytes) = time(3, || bvh.to_bytes());
+        let (deserialize, _) = time(3, || Bvh::from_bytes(&bytes).unwrap());
+        println!(
+            "{:?}: {} KiB, write {:.2?}, read {:.2?}",
+            quality,
+            bytes.len() / 1024,
+            serialize,
+            deserialize
+        );
+    }
+}
@@ -0,0 +1,98 @@
+struct Settings {
+    width: u32,
+    height: u32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+}
+
+struct Exposure {
+    average_luminance: f32,
+    exposure: f32,
+    _p1: f32,
+    _p2: f32,
+    histogram: array<atomic<u32>, 64>,
+    readback: array<u32, 64>,
+}
+
+@group(0) @binding(0)
+var<uniform> settings: Settings;
+@group(0) @binding(1)
+var texture: texture_2d<f32>;
+@group(0) @binding(2)
+var<storage, read_write> exposure: Exposure;
+
+const BINS: u32 = 64u;
+const KEY_VALUE: f32 = 0.18;
+const EPSILON: f32 = 0.0001;
+
+var<workgroup> local_histogram: array<atomic<u32>, 64>;
+var<workgroup> weighted_bins: array<f32, 64>;
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Bin 0 is reserved for (near) black pixels so empty sky doesn't drag the average down
+fn luminance_to_bin(lum: f32) -> u32 {
+    if lum < EPSILON {
+        return 0u;
+    }
+    let t = clamp((log2(lum) - settings.min_log_luminance) / settings.log_luminance_range, 0.0, 1.0);
+    return u32(t * f32(BINS - 2u) + 1.0);
+}
+
+@compute
+@workgroup_size(16, 16)
+fn build_histogram(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    if local_index < BINS {
+        atomicStore(&local_histogram[local_index], 0u);
+    }
+    workgroupBarrier();
+
+    if global_id.x < settings.width && global_id.y < settings.height {
+        let color = textureLoad(texture, vec2<i32>(global_id.xy), 0);
+        atomicAdd(&local_histogram[luminance_to_bin(luminance(color.rgb))], 1u);
+    }
+    workgroupBarrier();
+
+    if local_index < BINS {
+        atomicAdd(&exposure.histogram[local_index], atomicLoad(&local_histogram[local_index]));
+    }
+}
+
+@compute
+@workgroup_size(64, 1)
+fn average(@builtin(local_invocation_index) local_index: u32) {
+    let count = atomicLoad(&exposure.histogram[local_index]);
+    weighted_bins[local_index] = f32(count) * f32(local_index);
+    exposure.readback[local_index] = count;
+    atomicStore(&exposure.histogram[local_index], 0u);
+    workgroupBarrier();
+
+    // Parallel reduction of the weighted bins
+    for (var stride = BINS / 2u; stride > 0u; stride >>= 1u) {
+        if local_index < stride {
+            weighted_bins[local_index] += weighted_bins[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if local_index == 0u {
+        let lit_pixels = f32(settings.width * settings.height) - f32(count);
+        let average_bin = weighted_bins[0] / max(lit_pixels, 1.0) - 1.0;
+        let log_average = average_bin / f32(BINS - 2u) * settings.log_luminance_range + settings.min_log_luminance;
+        let target_luminance = exp2(log_average);
+
+        var adapted = exposure.average_luminance;
+        if adapted <= 0.0 {
+            adapted = target_luminance;
+        }
+        adapted += (target_luminance - adapted) * settings.adaptation;
+        exposure.average_luminance = adapted;
+        exposure.exposure = KEY_VALUE / max(adapted, EPSILON);
+    }
+}
@@ -0,0 +1,14 @@
+// Texture bindings for GPUs without non-uniform binding array indexing, every texture is resampled
+// into a layer of one array. Replaces the texture bindings of ray_tracer.wgsl, see TextureMode
+@group(1) @binding(0)
+var textures: texture_2d_array<f32>;
+@group(1) @binding(1)
+var samplers: sampler;
+
+fn texture_size(texture: i32) -> vec2<u32> {
+    return textureDimensions(textures);
+}
+
+fn texture_lookup(texture: i32, st: vec2<f32>, lod: f32) -> vec4<f32> {
+    return textureSampleLevel(textures, samplers, st, texture, lod);
+}
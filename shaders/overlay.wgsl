@@ -0,0 +1,188 @@
+struct Overlay {
+    cam_to_world: mat4x4<f32>,
+    view_params: vec3<f32>,
+    grid_spacing: f32,
+    grid_fade: f32,
+    aspect: f32,
+    show_grid: u32,
+    show_axes: u32,
+    world_to_cam: mat4x4<f32>,
+    model: mat4x4<f32>,
+    normal_matrix: mat4x4<f32>,
+    normal_length: f32,
+    focus_band: f32,
+    show_focus: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> overlay: Overlay;
+// Primary hit normals and distances from the ray tracer's guide pass, negative for misses
+@group(0) @binding(1)
+var guide: texture_2d<f32>;
+
+// Part of the image the viewport shows, see ViewWindow in renderer.rs
+struct View {
+    min: vec2<f32>,
+    max: vec2<f32>,
+};
+
+@group(0) @binding(2)
+var<uniform> view: View;
+
+// Moves a clip space position over the whole image to where it is in the part the viewport shows
+fn to_view(position: vec4<f32>) -> vec4<f32> {
+    let image = position.xy * 0.5 + position.w * (0.5 - view.min);
+    return vec4(image / (view.max - view.min) * 2.0 - position.w, position.zw);
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>,
+};
+
+var<private> v_vertices: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(1.0, 1.0), vec2(-1.0, 1.0), vec2(-1.0, -1.0),
+);
+
+@vertex
+fn grid_vert(@builtin(vertex_index) i: u32) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = vec4(v_vertices[i], 0.0, 1.0);
+    out.tex_coord = mix(view.min, view.max, v_vertices[i] * 0.5 + 0.5);
+    return out;
+}
+
+// Anti-aliased line coverage for lines at integer values of coord
+fn grid_lines(coord: vec2<f32>) -> f32 {
+    let derivative = max(fwidth(coord), vec2(1e-6));
+    let grid = abs(fract(coord - 0.5) - 0.5) / derivative;
+    return 1.0 - min(min(grid.x, grid.y), 1.0);
+}
+
+fn axis_line(coord: f32) -> f32 {
+    return 1.0 - min(abs(coord) / max(fwidth(coord), 1e-6), 1.0);
+}
+
+@fragment
+fn grid_frag(i: VertexOutput) -> @location(0) vec4<f32> {
+    // Matches the primary ray generation of the ray tracer so the grid lines up with the image
+    let origin = overlay.cam_to_world[3].xyz;
+    let local_focus_point = vec3(i.tex_coord - 0.5, 1.0) * overlay.view_params;
+    let focus_point = (overlay.cam_to_world * vec4(local_focus_point, 1.0)).xyz;
+    let dir = focus_point - origin;
+
+    // Intersect with the ground plane (y = 0); derivatives must be taken before any branching
+    let t = -origin.y / select(dir.y, 1e-6, abs(dir.y) < 1e-6);
+    let p = origin + dir * t;
+    let distance = length(p - origin);
+
+    let minor = grid_lines(p.xz / overlay.grid_spacing);
+    let major = grid_lines(p.xz / (overlay.grid_spacing * 10.0));
+    let x_axis = axis_line(p.z);
+    let z_axis = axis_line(p.x);
+
+    let visible = f32(t > 0.0) * (1.0 - smoothstep(overlay.grid_fade * 0.5, overlay.grid_fade, distance));
+
+    var color = vec4(0.0);
+    if overlay.show_grid != 0u {
+        color = mix(color, vec4(0.5, 0.5, 0.5, 0.35), minor);
+        color = mix(color, vec4(0.7, 0.7, 0.7, 0.6), major);
+    }
+    if overlay.show_axes != 0u {
+        color = mix(color, vec4(0.9, 0.2, 0.2, 0.9), x_axis);
+        color = mix(color, vec4(0.2, 0.4, 0.9, 0.9), z_axis);
+    }
+    return vec4(color.rgb, color.a * visible);
+}
+
+const FOCUS_COLOR: vec3<f32> = vec3(0.2, 1.0, 0.4);
+
+// Tints the geometry within half the band of the focal plane, what depth of field leaves sharp,
+// and outlines where the band's edges cut through it
+@fragment
+fn focus_frag(i: VertexOutput) -> @location(0) vec4<f32> {
+    let size = textureDimensions(guide);
+    let coords = min(vec2<u32>(i.tex_coord * vec2<f32>(size)), size - 1u);
+    let dst = textureLoad(guide, coords, 0).w;
+    // The focal plane is flat, so compare depth along the view axis rather than distance
+    let local_focus_point = vec3(i.tex_coord - 0.5, 1.0) * overlay.view_params;
+    let depth = dst * overlay.view_params.z / length(local_focus_point);
+    let offset = abs(depth - overlay.view_params.z) - overlay.focus_band * 0.5;
+    // Derivatives must be taken before any branching
+    let edge = 1.0 - min(abs(offset) / max(fwidth(offset), 1e-6), 1.0);
+    let inside = f32(offset < 0.0);
+    let hit = f32(dst >= 0.0 && overlay.show_focus != 0u);
+    return vec4(FOCUS_COLOR, max(inside * 0.3, edge * 0.9) * hit);
+}
+
+struct GizmoOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+const GIZMO_CENTER: vec2<f32> = vec2(-0.88, -0.8);
+const GIZMO_SIZE: f32 = 0.12;
+
+// Three line segments from the gizmo center along the world axes, projected with the camera basis
+@vertex
+fn gizmo_vert(@builtin(vertex_index) i: u32) -> GizmoOutput {
+    var axes = array<vec3<f32>, 3>(
+        vec3(1.0, 0.0, 0.0),
+        vec3(0.0, 1.0, 0.0),
+        vec3(0.0, 0.0, 1.0),
+    );
+    var colors = array<vec4<f32>, 3>(
+        vec4(0.9, 0.2, 0.2, 1.0),
+        vec4(0.2, 0.9, 0.2, 1.0),
+        vec4(0.2, 0.4, 0.9, 1.0),
+    );
+    let axis = axes[i / 2u];
+    let right = normalize(overlay.cam_to_world[0].xyz);
+    let up = normalize(overlay.cam_to_world[1].xyz);
+    let projected = vec2(dot(axis, right) / overlay.aspect, dot(axis, up)) * GIZMO_SIZE;
+
+    var out: GizmoOutput;
+    out.position = to_view(vec4(GIZMO_CENTER + projected * f32(i % 2u), 0.0, 1.0));
+    out.color = colors[i / 2u];
+    return out;
+}
+
+@fragment
+fn gizmo_frag(i: GizmoOutput) -> @location(0) vec4<f32> {
+    return i.color;
+}
+
+// Must match the constants of WireVertex
+const WIRE_EDGE: u32 = 0u;
+const WIRE_NORMAL_HEAD: u32 = 2u;
+// Keeps lines crossing behind the camera from dividing by zero
+const WIRE_NEAR: f32 = 1e-3;
+
+struct WireVertex {
+    @location(0) pos: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) kind: u32,
+};
+
+// Projects a world space point the way the ray tracer generates primary rays, so the lines sit on
+// the traced image. Points behind the camera end up outside the clip volume and are cut off.
+@vertex
+fn wire_vert(v: WireVertex) -> GizmoOutput {
+    var world = (overlay.model * vec4(v.pos, 1.0)).xyz;
+    if v.kind == WIRE_NORMAL_HEAD {
+        world += normalize((overlay.normal_matrix * vec4(v.normal, 0.0)).xyz) * overlay.normal_length;
+    }
+    let local = (overlay.world_to_cam * vec4(world, 1.0)).xyz;
+    let scale = 2.0 * overlay.view_params.z / overlay.view_params.xy;
+
+    var out: GizmoOutput;
+    out.position = to_view(vec4(local.xy * scale, local.z - WIRE_NEAR, local.z));
+    out.color = select(vec4(0.3, 0.8, 1.0, 0.9), vec4(1.0, 0.6, 0.1, 0.6), v.kind == WIRE_EDGE);
+    return out;
+}
+
+@fragment
+fn wire_frag(i: GizmoOutput) -> @location(0) vec4<f32> {
+    return i.color;
+}
@@ -0,0 +1,166 @@
+struct PostSettings {
+    width: u32,
+    height: u32,
+    // Valid region of the source texture, the accumulation texture is only partly filled while moving
+    source_width: u32,
+    source_height: u32,
+    frame: u32,
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    bloom_radius: f32,
+    vignette_strength: f32,
+    vignette_radius: f32,
+    chromatic_aberration: f32,
+    grain_strength: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> settings: PostSettings;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+// Blurred bright pass, only read when compositing bloom
+@group(0) @binding(2)
+var aux: texture_2d<f32>;
+@group(0) @binding(3)
+var output: texture_storage_2d<rgba32float, write>;
+
+const BLOOM_TAPS: i32 = 12;
+
+fn in_bounds(id: vec3<u32>) -> bool {
+    return id.x < settings.width && id.y < settings.height;
+}
+
+fn load(pos: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(i32(settings.width), i32(settings.height));
+    return textureLoad(source, clamp(pos, vec2(0), size - 1), 0);
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Scales the source's valid region up to the full output, matching how the display samples it
+@compute
+@workgroup_size(8,8)
+fn resolve(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let scale = vec2<f32>(f32(settings.source_width), f32(settings.source_height))
+        / vec2<f32>(f32(settings.width), f32(settings.height));
+    let pos = vec2<i32>(vec2<f32>(id.xy) * scale);
+    textureStore(output, id.xy, textureLoad(source, pos, 0));
+}
+
+@compute
+@workgroup_size(8,8)
+fn bloom_extract(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let color = load(vec2<i32>(id.xy));
+    // Soft knee so highlights fade in rather than popping at the threshold
+    let brightness = luminance(color.rgb) * settings.exposure;
+    let knee = max(settings.bloom_threshold * 0.5, 1e-4);
+    let soft = clamp(brightness - settings.bloom_threshold + knee, 0.0, 2.0 * knee);
+    let weight = max(soft * soft / (4.0 * knee), brightness - settings.bloom_threshold) / max(brightness, 1e-4);
+    textureStore(output, id.xy, vec4(color.rgb * weight, 1.0));
+}
+
+fn blur(id: vec3<u32>, direction: vec2<i32>) -> vec4<f32> {
+    let stride = max(settings.bloom_radius / f32(BLOOM_TAPS), 1.0);
+    let sigma = f32(BLOOM_TAPS) * 0.5;
+    var total = vec4<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = -BLOOM_TAPS; i <= BLOOM_TAPS; i += 1) {
+        let weight = exp(-f32(i * i) / (2.0 * sigma * sigma));
+        let offset = vec2<i32>(vec2<f32>(direction) * f32(i) * stride);
+        total += load(vec2<i32>(id.xy) + offset) * weight;
+        total_weight += weight;
+    }
+    return total / total_weight;
+}
+
+@compute
+@workgroup_size(8,8)
+fn bloom_blur_h(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    textureStore(output, id.xy, blur(id, vec2(1, 0)));
+}
+
+@compute
+@workgroup_size(8,8)
+fn bloom_blur_v(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    textureStore(output, id.xy, blur(id, vec2(0, 1)));
+}
+
+@compute
+@workgroup_size(8,8)
+fn bloom_composite(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let color = load(vec2<i32>(id.xy));
+    let bloom = textureLoad(aux, id.xy, 0);
+    textureStore(output, id.xy, vec4(color.rgb + bloom.rgb * settings.bloom_intensity, color.a));
+}
+
+@compute
+@workgroup_size(8,8)
+fn vignette(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let size = vec2<f32>(f32(settings.width), f32(settings.height));
+    let uv = (vec2<f32>(id.xy) + 0.5) / size - 0.5;
+    // Corrected for aspect so the falloff is round rather than following the frame
+    let d = length(uv * vec2(size.x / size.y, 1.0)) / length(vec2(size.x / size.y, 1.0) * 0.5);
+    let falloff = smoothstep(settings.vignette_radius, 1.0, d);
+    let color = load(vec2<i32>(id.xy));
+    textureStore(output, id.xy, vec4(color.rgb * (1.0 - falloff * settings.vignette_strength), color.a));
+}
+
+@compute
+@workgroup_size(8,8)
+fn chromatic_aberration(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let size = vec2<f32>(f32(settings.width), f32(settings.height));
+    let from_centre = vec2<f32>(id.xy) - size * 0.5;
+    // Red and blue are pushed apart radially, strongest at the edges
+    let offset = vec2<i32>(from_centre / (size.x * 0.5) * settings.chromatic_aberration);
+    let centre = load(vec2<i32>(id.xy));
+    let r = load(vec2<i32>(id.xy) + offset).r;
+    let b = load(vec2<i32>(id.xy) - offset).b;
+    textureStore(output, id.xy, vec4(r, centre.g, b, centre.a));
+}
+
+fn hash(p: vec3<u32>) -> f32 {
+    var h = p.x * 1664525u + p.y * 1013904223u + p.z * 22695477u;
+    h ^= h >> 16u;
+    h *= 2246822519u;
+    h ^= h >> 13u;
+    h *= 3266489917u;
+    h ^= h >> 16u;
+    return f32(h) / 4294967295.0;
+}
+
+@compute
+@workgroup_size(8,8)
+fn film_grain(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_bounds(id) {
+        return;
+    }
+    let color = load(vec2<i32>(id.xy));
+    let noise = hash(vec3(id.xy, settings.frame)) - 0.5;
+    // Grain is scaled by brightness so it reads as film noise instead of a constant overlay
+    let grain = noise * settings.grain_strength * sqrt(max(luminance(color.rgb), 0.0));
+    textureStore(output, id.xy, vec4(max(color.rgb + grain, vec3(0.0)), color.a));
+}
@@ -9,6 +9,25 @@ struct Params {
     accumulate: i32,
     debug_flag: i32,
     debug_scale: i32,
+    exposure: f32,
+    auto_exposure: i32,
+    tile_x: u32,
+    tile_y: u32,
+    processed: u32,
+    integrator: u32,
+    seed: u32,
+    caustic_guiding: f32,
+    caustic_casters: u32,
+    jitter: u32,
+    taa_blend: f32,
+    interleave: u32,
+    clamp_direct: f32,
+    clamp_indirect: f32,
+    despeckle: u32,
+    clay: u32,
+    _p1: u32,
+    _p2: u32,
+    _p3: u32,
 }
 
 struct Material {
@@ -24,12 +43,30 @@ struct Material {
     flag: i32,
     diffuse_index: i32,
     normal_index: i32,
+    blend_a: i32,
+    blend_b: i32,
+    blend_mask: i32,
+    blend_coverage: f32,
+    blend_softness: f32,
+    blend_scale: f32,
+    orm_index: i32,
+    orm_channels: u32,
+    light_flags: u32,
+    film_thickness: f32,
+    film_ior: f32,
+    visibility: u32,
+    address_mode: u32,
+    // Which of overlapping glass objects owns the space they share, 0 for none, see `Interface`
+    priority: u32,
+    _p1: vec2<u32>,
 }
 
 struct Sphere {
     position: vec3<f32>,
     radius: f32,
     material: Material,
+    texture_rotation: f32,
+    texture_tilt: f32,
 }
 
 struct Mesh {
@@ -38,7 +75,10 @@ struct Mesh {
     node_offset: u32,
     triangles: u32,
     triangle_offset: u32,
+    uv_view: u32,
     material: Material,
+    // Width and height of a rect light, zero for other meshes
+    rect_size: vec2<f32>,
 }
 
 struct Camera {
@@ -46,6 +86,9 @@ struct Camera {
     view_params: vec3<f32>,
     defocus_strength: f32,
     diverge_strength: f32,
+    // Distances along the view axis camera rays start and stop at
+    near: f32,
+    far: f32,
 }
 
 struct Scene {
@@ -55,6 +98,14 @@ struct Scene {
     meshes: u32,
     camera: Camera,
     n_nodes: u32,
+    volumes: u32,
+    portals: u32,
+    // Direction to the sun, brightness of its disc in w
+    sun: vec4<f32>,
+    // Density, height falloff and base height of the height fog, zero density when off
+    fog: vec4<f32>,
+    // Fog colour, w set to take it from the sky instead
+    fog_color: vec4<f32>,
 }
 
 struct BVHNode {
@@ -79,6 +130,49 @@ struct Triangle {
     u30: f32,
     n3: vec3<f32>,
     u31: f32,
+    // sRGB vertex colours packed as bytes
+    c1: u32,
+    c2: u32,
+    c3: u32,
+    _p1: u32,
+}
+
+struct Volume {
+    bounds_min: vec3<f32>,
+    // Upper bound on the extinction inside the volume, zero disables it
+    majorant: f32,
+    bounds_max: vec3<f32>,
+    max_emission: f32,
+    color: vec4<f32>,
+    emission: vec4<f32>,
+    dims: vec3<u32>,
+    // Offsets into voxels of the brick table and of the first brick
+    table: u32,
+    data: u32,
+}
+
+// Opening the environment is seen through, a parallelogram spanned by u and v from corner
+struct Portal {
+    corner: vec3<f32>,
+    u: vec3<f32>,
+    v: vec3<f32>,
+}
+
+struct VolumeHit {
+    hit: bool,
+    dst: f32,
+    volume: u32,
+    emission: f32,
+}
+
+struct Reprojection {
+    world_to_cam: mat4x4<f32>,
+    cam_to_world: mat4x4<f32>,
+    view_params: vec3<f32>,
+    // Set on frames the history was written and should be blended in
+    enabled: u32,
+    size: vec2<u32>,
+    _p1: vec2<u32>,
 }
 
 struct FragInput {
@@ -92,6 +186,11 @@ struct Ray {
     inv_dir: vec3<f32>,
     transmittance: vec4<f32>,
     bounces: u32,
+    // Primary ray from the camera, emitters hidden from the camera let it through
+    camera: bool,
+    // Width of the ray's cone at its origin and how much it widens per unit travelled. Rays that
+    // don't track one leave it zero and sample textures at full resolution
+    cone: vec2<f32>,
 }
 
 struct Hit {
@@ -99,9 +198,23 @@ struct Hit {
     dst: f32,
     hit_point: vec3<f32>,
     normal: vec3<f32>,
+    // Face normal from the winding, or outward on spheres, never flipped towards the ray
+    geometric_normal: vec3<f32>,
     uv: vec2<f32>,
+    // Hit point in the entity's own space, where procedural blend masks are evaluated
+    local_point: vec3<f32>,
     backface: bool,
     material: Material,
+    // Spheres first then meshes, matching the entity list
+    entity: u32,
+    // Fraction of the albedo taken away by baked occlusion, zero unless an ORM texture routes it
+    cavity: f32,
+    // Fraction of each albedo channel taken away by vertex colours, zero off coloured meshes
+    vertex_shade: vec3<f32>,
+    // Texture coordinates covered per unit of surface, around the hit
+    uv_scale: f32,
+    // Width in texture coordinates of the ray's cone where it meets the surface, picks texture levels
+    footprint: f32,
 }
 
 @group(0) @binding(0)
@@ -118,20 +231,142 @@ var<storage,read> triangles: array<Triangle>;
 var<storage,read> meshes: array<Mesh>;
 @group(0) @binding(6)
 var<storage,read> nodes: array<BVHNode>;
+@group(0) @binding(7)
+var guide_texture: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(8)
+var<storage,read> volumes: array<Volume>;
+// Brick tables followed by bricks of 16 bit density and emission pairs
+@group(0) @binding(9)
+var<storage,read> voxels: array<u32>;
+// Must match MAX_PORTALS in portal.rs
+@group(0) @binding(10)
+var<uniform> portals: array<Portal, 8>;
+// Must match MAX_MATERIALS in material.rs
+@group(0) @binding(11)
+var<uniform> materials: array<Material, 32>;
+// Previous frame's camera, see ReprojectionUniform
+@group(0) @binding(12)
+var<uniform> reprojection: Reprojection;
+// Last frame's accumulation moved to where this frame sees it, alpha zero where it wasn't visible
+@group(0) @binding(13)
+var history: texture_storage_2d<rgba32float, read_write>;
+// Alias table over the sky, threshold, alias and probability of each texel, see EnvironmentDistribution
+@group(0) @binding(14)
+var environment_table: texture_2d<f32>;
+// Texture bindings, swapped for layered_textures.wgsl on GPUs that can't index binding arrays
+// per pixel, see TextureMode in ray_tracer.rs
 @group(1) @binding(0)
 var textures: binding_array<texture_2d<f32>>;
 @group(1) @binding(1)
 var samplers: binding_array<sampler>;
 
+fn texture_size(texture: i32) -> vec2<u32> {
+    return textureDimensions(textures[texture]);
+}
+
+fn texture_lookup(texture: i32, st: vec2<f32>, lod: f32) -> vec4<f32> {
+    return textureSampleLevel(textures[texture], samplers[0], st, lod);
+}
+// End of texture bindings
+
+struct BakeSettings {
+    width: u32,
+    height: u32,
+    frame: u32,
+    samples_per_frame: u32,
+}
+
+// World space surface point covered by a lightmap texel
+struct BakeTexel {
+    position: vec3<f32>,
+    valid: u32,
+    normal: vec3<f32>,
+}
+
+@group(2) @binding(0)
+var<uniform> bake_settings: BakeSettings;
+@group(2) @binding(1)
+var<storage, read> bake_texels: array<BakeTexel>;
+@group(2) @binding(2)
+var lightmap: texture_storage_2d<rgba32float, read_write>;
+
+struct IdSettings {
+    width: u32,
+    height: u32,
+    samples_per_axis: u32,
+}
+
+@group(2) @binding(3)
+var<uniform> id_settings: IdSettings;
+@group(2) @binding(4)
+var<storage, read_write> id_samples: array<u32>;
+
+struct FeatureSettings {
+    width: u32,
+    height: u32,
+    samples_per_axis: u32,
+    // Which feature this dispatch writes, they share one texture
+    feature: u32,
+}
+
+@group(2) @binding(5)
+var<uniform> feature_settings: FeatureSettings;
+@group(2) @binding(6)
+var feature_texture: texture_storage_2d<rgba32float, write>;
+
 const SKY_HORIZON: vec4<f32> = vec4<f32>(1.0, 1.0, 1.0, 0.0);
 const SKY_ZENITH: vec4<f32> = vec4<f32>(0.0788092, 0.36480793, 0.7264151, 0.0);
 const GROUND_COLOR: vec4<f32> = vec4<f32>(0.35, 0.3, 0.35, 0.0);
-const SUN_INTENSITY: f32 = 0.1;
 const SUN_FOCUS: f32 = 500.0;
+// Horizon and sun colours as the sun sets, and how much sky light is left at night
+const SUNSET_HORIZON: vec4<f32> = vec4<f32>(1.0, 0.45, 0.2, 0.0);
+const SUNSET_SUN: vec4<f32> = vec4<f32>(1.0, 0.5, 0.25, 1.0);
+const NIGHT_LEVEL: f32 = 0.01;
+// Rays that miss everything are fogged over this far, which covers the horizon in any sane fog
+const FOG_MAX_DISTANCE: f32 = 1e5;
 const EPSILON: f32 = 1e-5;
+// Widening of a ray cone per unit travelled after a fully rough bounce. Indirect light off diffuse
+// surfaces averages over a wide area, so the textures it sees are read at coarse levels
+const ROUGH_CONE_SPREAD: f32 = 0.2;
+// Steepest a cone is stretched across a surface it meets at a glancing angle
+const MIN_CONE_COS: f32 = 0.05;
 const INF: f32 = 0x1p+127f;  // Hexadecimal float literal
+const MATERIAL_DEFAULT: i32 = 0;
 const MATERIAL_GLASS: i32 = 1;
 const MATERIAL_TEXTURE: i32 = 2;
+const MATERIAL_BLEND: i32 = 3;
+
+// `AddressMode` of a material's textures
+const ADDRESS_REPEAT: u32 = 0u;
+const ADDRESS_CLAMP: u32 = 1u;
+const ADDRESS_MIRROR: u32 = 2u;
+
+// Mirrors LightFlag
+const LIGHT_HIDDEN_FROM_CAMERA: u32 = 1u;
+const LIGHT_SINGLE_SIDED: u32 = 2u;
+const LIGHT_HIDDEN_FROM_SPECULAR: u32 = 4u;
+// Mirrors RayVisibility
+const VISIBILITY_CAMERA: u32 = 1u;
+const VISIBILITY_SHADOW: u32 = 2u;
+const VISIBILITY_BOUNCE: u32 = 4u;
+// Surfaces a ray may pass through before whatever is behind them counts as a hit
+const MAX_SKIPPED_SURFACES: i32 = 8;
+// Below this a rect light is sampled by area, the solid angle construction loses precision
+const RECT_MIN_SOLID_ANGLE: f32 = 1e-4;
+const MAX_MATERIALS: i32 = 32;
+
+const BRICK_SIZE: u32 = 8u;
+const EMPTY_BRICK: u32 = 0xffffffffu;
+// Caps the null collisions per volume so a thin ray through a dense majorant can't stall the frame
+const MAX_VOLUME_STEPS: i32 = 256;
+
+// `ClayMode`
+const CLAY_OFF: u32 = 0u;
+const CLAY_TEXTURED: u32 = 2u;
+// Albedo of every surface in plain clay mode
+const CLAY_ALBEDO: f32 = 0.5;
+const INTEGRATOR_DIRECT: u32 = 1u;
+const INTEGRATOR_WHITTED: u32 = 2u;
 
 const DEBUG_NORMALS: i32 = 1;
 const DEBUG_DEPTH: i32 = 2;
@@ -140,27 +375,284 @@ const DEBUG_FOCUS_DST: i32 = 4;
 const DEBUG_NODES: i32 = 5;
 const DEBUG_TRIANGLES: i32 = 6;
 const DEBUG_NODES_TRIANGLES: i32 = 7;
+const DEBUG_GEOMETRIC_NORMALS: i32 = 8;
+const DEBUG_BACKFACES: i32 = 9;
+const DEBUG_UV_CHECKER: i32 = 10;
+const DEBUG_UV_FALSE_COLOR: i32 = 11;
+// Path cost views, written as raw counts for renderer.wgsl to show as a heatmap
+const DEBUG_COST_NODES: i32 = 12;
+const DEBUG_COST_TRIANGLES: i32 = 13;
+const DEBUG_COST_BOUNCES: i32 = 14;
+
+const UV_VIEW_CHECKER: u32 = 1u;
+const UV_VIEW_FALSE_COLOR: u32 = 2u;
+const UV_CHECKER_CELLS: f32 = 8.0;
+
+// Largest difference in hit distance, relative to the distance, still taken for the same surface
+const REPROJECT_TOLERANCE: f32 = 0.02;
+const FEATURE_ALBEDO: u32 = 0u;
+const FEATURE_NORMAL: u32 = 1u;
+// Mirrors and glass at least this smooth show a sharp image of what is behind them, so the
+// denoiser's features are taken from that instead
+const DELTA_SMOOTHNESS: f32 = 0.95;
+// Sharp surfaces a feature ray passes through at most, two facing mirrors give up here
+const MAX_FEATURE_BOUNCES: i32 = 8;
+
+// Overridden by the pipeline, see RayTracer::WORKGROUP_SIZES
+override WORKGROUP_X: u32 = 8u;
+override WORKGROUP_Y: u32 = 8u;
+// Set by specialised main pipelines to 0 or 1 so the branches they decide fold away, see
+// PipelineFeatures. Left at -1 the params decide at runtime, as every other entry point does
+override SKYBOX: i32 = -1;
+override ACCUMULATING: i32 = -1;
+override DEBUG_VIEW: i32 = -1;
+
+fn skybox_enabled() -> bool {
+    if SKYBOX >= 0 {
+        return SKYBOX != 0;
+    }
+    return params.skybox != 0;
+}
+
+// Blending into the passes before this one rather than starting the frame
+fn accumulating() -> bool {
+    if ACCUMULATING >= 0 {
+        return ACCUMULATING != 0;
+    }
+    return params.frames >= 1;
+}
+
+fn debug_view() -> bool {
+    if DEBUG_VIEW >= 0 {
+        return DEBUG_VIEW != 0;
+    }
+    return params.debug_flag != 0;
+}
 
 @compute
-@workgroup_size(8,8)
+@workgroup_size(WORKGROUP_X, WORKGROUP_Y)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var i: FragInput;
 
-    i.pos = vec2<f32>(f32(global_id.x), f32(global_id.y));
+    var local = global_id.xy;
+    // Mirrors Params::interleaving, the dispatch is narrowed to one column in `interleave`
+    let interleaving = accumulating() && params.interleave > 1u;
+    let interleave = select(1u, params.interleave, interleaving);
+    // Passes after the first, which traced every pixel
+    let sweep = u32(max(params.frames - 1, 0));
+    if interleaving {
+        // Pixel (x, y) is traced on passes where (x + y * stride) % interleave matches, shifting
+        // every row so the traced pixels spread out like a checkerboard
+        let stride = max(interleave / 2u, 1u);
+        let shift = (sweep % interleave + interleave - (local.y * stride) % interleave) % interleave;
+        local.x = local.x * interleave + shift;
+        if local.x >= params.width {
+            return;
+        }
+    }
+    let pixel = local + vec2(params.tile_x, params.tile_y);
+    i.pos = vec2<f32>(f32(pixel.x), f32(pixel.y));
     i.size = vec2<f32>(f32(params.width), f32(params.height));
 
     let pos = vec2<i32>(i32(i.pos.x), i32(i.pos.y));
-    let current_sample = frag(i);
-    if params.frames >= 1 {
+    var current_sample = frag(i);
+    if accumulating() {
         let prev_color = textureLoad(texture, pos);
-        let weight = 1.0 / f32(params.frames + 1);
+        // The pixel's samples so far, one from the first pass and one per interleaved round
+        let weight = 1.0 / f32(2u + sweep / interleave);
         let new_color = prev_color * (1.0 - weight) + current_sample * weight;
         textureStore(texture, pos, new_color);
     } else {
+        if reprojection.enabled != 0u {
+            let prev_color = textureLoad(history, pos);
+            if prev_color.a > 0.0 {
+                current_sample = vec4(mix(prev_color.rgb, current_sample.rgb, params.taa_blend), current_sample.a);
+            }
+        }
         textureStore(texture, pos, current_sample);
     }
 }
 
+// Moves the last frame's accumulation to where the current camera sees the same surfaces, so a
+// moving preview can keep most of it instead of starting from a single sample
+@compute
+@workgroup_size(8,8)
+fn reproject(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= params.width || global_id.y >= params.height {
+        return;
+    }
+    let pos = vec2<i32>(global_id.xy);
+    let size = vec2<f32>(f32(params.width), f32(params.height));
+    let uv = vec2<f32>(global_id.xy) / (size - 1.0);
+    let local_focus_point = vec3(uv - 0.5, 1.0) * scene.camera.view_params;
+    var ray: Ray;
+    ray.origin = scene.camera.cam_to_world[3].xyz;
+    ray.dir = normalize((scene.camera.cam_to_world * vec4(local_focus_point, 1.0)).xyz - ray.origin);
+    ray.inv_dir = 1.0 / ray.dir;
+    var stats = vec2<i32>(0, 0);
+    let hit = camera_hit(ray, &stats);
+
+    // Where the previous camera saw the surface, or the sky past everything
+    var prev_dir: vec3<f32>;
+    if hit.hit {
+        prev_dir = (reprojection.world_to_cam * vec4(hit.hit_point, 1.0)).xyz;
+    } else {
+        prev_dir = (reprojection.world_to_cam * vec4(ray.dir, 0.0)).xyz;
+    }
+    if prev_dir.z <= EPSILON {
+        textureStore(history, pos, vec4(0.0));
+        return;
+    }
+    let prev_uv = prev_dir.xy / prev_dir.z * reprojection.view_params.z / reprojection.view_params.xy + 0.5;
+    let prev_size = vec2<f32>(reprojection.size);
+    let prev_pos = prev_uv * (prev_size - 1.0);
+    if any(prev_pos < vec2(0.0)) || any(prev_pos > prev_size - 1.0) {
+        textureStore(history, pos, vec4(0.0));
+        return;
+    }
+
+    // Disoccluded when the previous camera's ray through that pixel stops somewhere else
+    var prev_ray: Ray;
+    prev_ray.origin = reprojection.cam_to_world[3].xyz;
+    let prev_focus_point = vec3(prev_uv - 0.5, 1.0) * reprojection.view_params;
+    prev_ray.dir = normalize((reprojection.cam_to_world * vec4(prev_focus_point, 1.0)).xyz - prev_ray.origin);
+    prev_ray.inv_dir = 1.0 / prev_ray.dir;
+    let prev_hit = calculate_ray_collions(prev_ray, &stats);
+    var visible = !prev_hit.hit;
+    if hit.hit {
+        let expected = distance(prev_ray.origin, hit.hit_point);
+        visible = prev_hit.hit && abs(prev_hit.dst - expected) <= expected * REPROJECT_TOLERANCE;
+    }
+    if !visible {
+        textureStore(history, pos, vec4(0.0));
+        return;
+    }
+
+    // Bilinear so slow pans don't snap to whole pixels
+    let base = vec2<i32>(floor(prev_pos));
+    let last = vec2<i32>(reprojection.size) - 1;
+    let f = fract(prev_pos);
+    let top = mix(textureLoad(texture, base), textureLoad(texture, min(base + vec2(1, 0), last)), f.x);
+    let bottom = mix(textureLoad(texture, min(base + vec2(0, 1), last)), textureLoad(texture, min(base + vec2(1, 1), last)), f.x);
+    textureStore(history, pos, vec4(mix(top, bottom, f.y).rgb, 1.0));
+}
+
+// Primary hit normal and distance at full resolution, guiding the preview upscale. Misses store a negative distance
+@compute
+@workgroup_size(8,8)
+fn guide(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = textureDimensions(guide_texture);
+    if global_id.x >= size.x || global_id.y >= size.y {
+        return;
+    }
+    let uv = vec2<f32>(global_id.xy) / (vec2<f32>(size) - 1.0);
+    let local_focus_point = vec3(uv - 0.5, 1.0) * scene.camera.view_params;
+    var ray: Ray;
+    ray.origin = scene.camera.cam_to_world[3].xyz;
+    ray.dir = normalize((scene.camera.cam_to_world * vec4(local_focus_point, 1.0)).xyz - ray.origin);
+    ray.inv_dir = 1.0 / ray.dir;
+    var stats = vec2<i32>(0, 0);
+    let hit = camera_hit(ray, &stats);
+    if hit.hit {
+        textureStore(guide_texture, global_id.xy, vec4(hit.normal, hit.dst));
+    } else {
+        textureStore(guide_texture, global_id.xy, vec4(0.0, 0.0, 0.0, -1.0));
+    }
+}
+
+// Entity hit by each of a pixel's stratified subpixel primary rays, offset by one so zero is a miss
+@compute
+@workgroup_size(8,8)
+fn ids(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= id_settings.width || global_id.y >= id_settings.height {
+        return;
+    }
+    let n = id_settings.samples_per_axis;
+    let size = vec2<f32>(f32(id_settings.width), f32(id_settings.height));
+    let first = (global_id.y * id_settings.width + global_id.x) * n * n;
+    for (var sy = 0u; sy < n; sy += 1u) {
+        for (var sx = 0u; sx < n; sx += 1u) {
+            let offset = (vec2<f32>(f32(sx), f32(sy)) + 0.5) / f32(n) - 0.5;
+            let uv = (vec2<f32>(global_id.xy) + offset) / (size - 1.0);
+            let local_focus_point = vec3(uv - 0.5, 1.0) * scene.camera.view_params;
+            var ray: Ray;
+            ray.origin = scene.camera.cam_to_world[3].xyz;
+            ray.dir = normalize((scene.camera.cam_to_world * vec4(local_focus_point, 1.0)).xyz - ray.origin);
+            ray.inv_dir = 1.0 / ray.dir;
+            var stats = vec2<i32>(0, 0);
+            let hit = camera_hit(ray, &stats);
+            id_samples[first + sy * n + sx] = select(0u, hit.entity + 1u, hit.hit);
+        }
+    }
+}
+
+// Albedo or normal seen through each pixel, averaged over stratified subpixel samples, as the
+// auxiliary features denoisers like OIDN use to tell noise from detail
+@compute
+@workgroup_size(8,8)
+fn features(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= feature_settings.width || global_id.y >= feature_settings.height {
+        return;
+    }
+    let n = feature_settings.samples_per_axis;
+    let size = vec2<f32>(f32(feature_settings.width), f32(feature_settings.height));
+    let pixel_spread = scene.camera.view_params.y / (scene.camera.view_params.z * (size.y - 1.0));
+    let first = (global_id.y * feature_settings.width + global_id.x) * n * n;
+    var total = vec3<f32>(0.0);
+    for (var sy = 0u; sy < n; sy += 1u) {
+        for (var sx = 0u; sx < n; sx += 1u) {
+            // Seeded by the sample alone, so both features follow the same paths through glass
+            var rng_state = (first + sy * n + sx) * 719393u;
+            let offset = (vec2<f32>(f32(sx), f32(sy)) + 0.5) / f32(n) - 0.5;
+            let uv = (vec2<f32>(global_id.xy) + offset) / (size - 1.0);
+            let local_focus_point = vec3(uv - 0.5, 1.0) * scene.camera.view_params;
+            var ray: Ray;
+            ray.origin = scene.camera.cam_to_world[3].xyz;
+            ray.dir = normalize((scene.camera.cam_to_world * vec4(local_focus_point, 1.0)).xyz - ray.origin);
+            ray.inv_dir = 1.0 / ray.dir;
+            ray.camera = true;
+            ray.cone = vec2(0.0, pixel_spread);
+            var features = denoise_features(ray, &rng_state);
+            total += features[feature_settings.feature];
+        }
+    }
+    textureStore(feature_texture, global_id.xy, vec4(total / f32(n * n), 1.0));
+}
+
+// Accumulates cosine weighted incoming light (irradiance / pi) for every texel of a mesh's lightmap
+@compute
+@workgroup_size(8,8)
+fn bake(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= bake_settings.width || global_id.y >= bake_settings.height {
+        return;
+    }
+    let index = global_id.y * bake_settings.width + global_id.x;
+    let texel = bake_texels[index];
+    let pos = vec2<i32>(global_id.xy);
+    if texel.valid == 0u {
+        textureStore(lightmap, pos, vec4(0.0));
+        return;
+    }
+    var rng_state = index + bake_settings.frame * 719393u;
+    var total_incoming_light = vec4<f32>(0.0);
+    for (var j = 0u; j < bake_settings.samples_per_frame; j += 1u) {
+        var ray: Ray;
+        ray.origin = texel.position + texel.normal * 1e-3;
+        ray.dir = normalize(texel.normal + rand_direction(&rng_state));
+        ray.inv_dir = 1.0 / ray.dir;
+        ray.bounces = 0u;
+        total_incoming_light += trace(ray, &rng_state);
+    }
+    let current_sample = vec4(total_incoming_light.rgb / f32(bake_settings.samples_per_frame), 1.0);
+    if bake_settings.frame >= 1u {
+        let prev_color = textureLoad(lightmap, pos);
+        let weight = 1.0 / f32(bake_settings.frame + 1u);
+        textureStore(lightmap, pos, prev_color * (1.0 - weight) + current_sample * weight);
+    } else {
+        textureStore(lightmap, pos, current_sample);
+    }
+}
+
 fn rand(seed: ptr<function,u32>) -> f32 {
     return f32(next_random_number(seed)) / 4294967295.0; // 2^32 - 1
 }
@@ -205,22 +697,429 @@ fn rand_in_unit_disk(seed: ptr<function, u32>) -> vec2<f32> {
     return point_on_circle * sqrt(rand(seed));
 }
 
+// Solid angle density of picking dir from p by aiming at a uniformly chosen portal
+fn portal_pdf(p: vec3<f32>, dir: vec3<f32>) -> f32 {
+    var pdf = 0.0;
+    for (var i = 0u; i < scene.portals; i += 1u) {
+        let portal = portals[i];
+        let n = cross(portal.u, portal.v);
+        let denom = dot(n, dir);
+        if abs(denom) < EPSILON {
+            continue;
+        }
+        let t = dot(n, portal.corner - p) / denom;
+        let local = p + dir * t - portal.corner;
+        let a = dot(cross(local, portal.v), n) / dot(n, n);
+        let b = dot(cross(portal.u, local), n) / dot(n, n);
+        if t <= 0.0 || a < 0.0 || a > 1.0 || b < 0.0 || b > 1.0 {
+            continue;
+        }
+        // Distance squared over the projected area, |n| cancels with the cosine
+        pdf += t * t / abs(denom);
+    }
+    return pdf / f32(scene.portals);
+}
+
+// Direction through a texel of the environment table chosen in proportion to the sky's brightness
+// there, so small bright suns are found without waiting for a bounce to stumble into them
+fn sample_environment(seed: ptr<function, u32>) -> vec3<f32> {
+    let size = textureDimensions(environment_table);
+    let count = size.x * size.y;
+    let i = min(u32(rand(seed) * f32(count)), count - 1u);
+    let entry = textureLoad(environment_table, vec2(i % size.x, i / size.x), 0);
+    let chosen = select(bitcast<u32>(entry.y), i, rand(seed) < entry.x);
+    let uv = (vec2(f32(chosen % size.x), f32(chosen / size.x)) + vec2(rand(seed), rand(seed))) / vec2<f32>(size);
+    let phi = uv.x * 2.0 * 3.1415926;
+    let theta = uv.y * 3.1415926;
+    return vec3(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
+}
+
+// Solid angle density of sample_environment picking dir
+fn environment_pdf(dir: vec3<f32>) -> f32 {
+    let size = textureDimensions(environment_table);
+    let theta = acos(clamp(dir.y, -1.0, 1.0));
+    let sin_theta = sin(theta);
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+    var phi = atan2(dir.z, dir.x);
+    phi = select(phi, phi + 2.0 * 3.1415926, phi < 0.0);
+    let texel = min(vec2<u32>(vec2(phi / (2.0 * 3.1415926), theta / 3.1415926) * vec2<f32>(size)), size - 1u);
+    let pdf = textureLoad(environment_table, texel, 0).z;
+    return pdf * f32(size.x * size.y) / (2.0 * 3.1415926 * 3.1415926 * sin_theta);
+}
+
+// Glass spheres diffuse bounces can be guided towards, the first `params.caustic_casters` of them
+// while caustic guiding is on
+fn caustic_casters() -> u32 {
+    if params.caustic_guiding <= 0.0 {
+        return 0u;
+    }
+    var count = 0u;
+    for (var i = 0u; i < scene.spheres && count < params.caustic_casters; i += 1u) {
+        count += u32(spheres[i].material.flag == MATERIAL_GLASS);
+    }
+    return count;
+}
+
+// Index of the `n`th caustic caster in the sphere buffer
+fn caustic_caster(n: u32) -> u32 {
+    var count = 0u;
+    for (var i = 0u; i < scene.spheres; i += 1u) {
+        if spheres[i].material.flag == MATERIAL_GLASS {
+            if count == n {
+                return i;
+            }
+            count += 1u;
+        }
+    }
+    return 0u;
+}
+
+// Solid angle density of picking dir from p by aiming at a uniformly chosen caustic caster
+fn caster_pdf(p: vec3<f32>, dir: vec3<f32>, casters: u32) -> f32 {
+    var pdf = 0.0;
+    var count = 0u;
+    for (var i = 0u; i < scene.spheres && count < casters; i += 1u) {
+        let sphere = spheres[i];
+        if sphere.material.flag != MATERIAL_GLASS {
+            continue;
+        }
+        count += 1u;
+        let to_centre = sphere.position - p;
+        let dst_sqr = dot(to_centre, to_centre);
+        if dst_sqr <= sphere.radius * sphere.radius {
+            continue;
+        }
+        let cos_max = sqrt(1.0 - sphere.radius * sphere.radius / dst_sqr);
+        if dot(dir, to_centre) >= cos_max * sqrt(dst_sqr) {
+            pdf += 1.0 / (2.0 * 3.1415926 * (1.0 - cos_max));
+        }
+    }
+    return pdf / f32(casters);
+}
+
+// Direction uniformly within the cone a caustic caster covers from p, zero from inside it
+fn sample_caster(p: vec3<f32>, casters: u32, seed: ptr<function, u32>) -> vec3<f32> {
+    let sphere = spheres[caustic_caster(min(u32(rand(seed) * f32(casters)), casters - 1u))];
+    let to_centre = sphere.position - p;
+    let dst_sqr = dot(to_centre, to_centre);
+    if dst_sqr <= sphere.radius * sphere.radius {
+        return vec3(0.0);
+    }
+    let cos_max = sqrt(1.0 - sphere.radius * sphere.radius / dst_sqr);
+    let cos_theta = 1.0 - rand(seed) * (1.0 - cos_max);
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * 3.1415926 * rand(seed);
+    let w = normalize(to_centre);
+    let u = normalize(cross(select(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), abs(w.x) > 0.9), w));
+    let v = cross(w, u);
+    return normalize(u * cos(phi) * sin_theta + v * sin(phi) * sin_theta + w * cos_theta);
+}
+
+// Diffuse bounce direction in xyz and, in w, the factor correcting the uniform hemisphere weighting.
+// With portals and a sky, half the remaining bounces aim through a portal. Under an open sky half
+// of them sample the environment table instead. With `caustics` and caustic guiding on,
+// `params.caustic_guiding` of them aim at a glass sphere so light refracted through it is found
+// far more often. Every strategy is weighted by the mixture density, the balance heuristic, so
+// the estimate stays unbiased and whichever strategy suits a direction best dominates it.
+fn sample_bounce(p: vec3<f32>, normal: vec3<f32>, caustics: bool, seed: ptr<function, u32>) -> vec4<f32> {
+    let use_portals = scene.portals > 0u && skybox_enabled();
+    let use_environment = scene.portals == 0u && skybox_enabled();
+    let casters = select(0u, caustic_casters(), caustics);
+    if !use_portals && !use_environment && casters == 0u {
+        return vec4(rand_hemisphere(normal, seed), 1.0);
+    }
+    let guide = select(0.0, min(params.caustic_guiding, 1.0), casters > 0u);
+    let portal_share = select(0.0, 0.5 * (1.0 - guide), use_portals);
+    let environment_share = select(0.0, 0.5 * (1.0 - guide), use_environment);
+    let strategy = rand(seed);
+    var dir: vec3<f32>;
+    if strategy < guide {
+        dir = sample_caster(p, casters, seed);
+    } else if strategy < guide + portal_share {
+        let portal = portals[min(u32(rand(seed) * f32(scene.portals)), scene.portals - 1u)];
+        dir = normalize(portal.corner + portal.u * rand(seed) + portal.v * rand(seed) - p);
+    } else if strategy < guide + environment_share {
+        dir = sample_environment(seed);
+    } else {
+        dir = rand_hemisphere(normal, seed);
+    }
+    if dot(dir, normal) <= 0.0 {
+        return vec4(normal, 0.0);
+    }
+    let hemisphere_pdf = 1.0 / (2.0 * 3.1415926);
+    var pdf = (1.0 - guide - portal_share - environment_share) * hemisphere_pdf;
+    if use_portals {
+        pdf += portal_share * portal_pdf(p, dir);
+    }
+    if use_environment {
+        pdf += environment_share * environment_pdf(dir);
+    }
+    if guide > 0.0 {
+        pdf += guide * caster_pdf(p, dir, casters);
+    }
+    return vec4(dir, hemisphere_pdf / pdf);
+}
+
 fn reflectance(cos_theta: f32, ior: f32) -> f32 {
     var r0 = (1.0 - ior) / (1.0 + ior);
     r0 *= r0;
     return r0 + (1.0 - r0) * pow((1.0 - cos_theta), 5.0);
 }
 
+// Wavelengths in nanometres standing in for the red, green and blue channels of a thin film
+const FILM_WAVELENGTHS = vec3<f32>(650.0, 510.0, 475.0);
+
+// Cosine of the angle from the normal after refracting from `n1` into `n2`, 0 past the critical angle
+fn refracted_cos(cos_i: f32, n1: f32, n2: f32) -> f32 {
+    let sin2 = (n1 / n2) * (n1 / n2) * (1.0 - cos_i * cos_i);
+    return sqrt(max(0.0, 1.0 - sin2));
+}
+
+// Fresnel amplitude coefficients (s, p) at an interface
+fn fresnel_amplitude(n1: f32, n2: f32, cos1: f32, cos2: f32) -> vec2<f32> {
+    let rs = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+    let rp = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+    return vec2(rs, rp);
+}
+
+// Reflectance of a film whose two interfaces reflect with amplitudes r12 and r23, after the two
+// reflections have interfered with a phase difference of delta
+fn airy_reflectance(r12: f32, r23: vec3<f32>, delta: vec3<f32>) -> vec3<f32> {
+    let cross_term = 2.0 * r12 * r23 * cos(delta);
+    return (r12 * r12 + r23 * r23 + cross_term) / (1.0 + r12 * r12 * r23 * r23 + cross_term);
+}
+
+// Film phase difference per channel for a ray crossing it at cos_film
+fn film_phase(film_ior: f32, thickness: f32, cos_film: f32) -> vec3<f32> {
+    return 4.0 * 3.1415926 * film_ior * thickness * cos_film / FILM_WAVELENGTHS;
+}
+
+// Unpolarised reflectance of a film between dielectrics `n_out`, where the ray comes from, and `n_base`
+fn thin_film_dielectric(cos_i: f32, n_out: f32, film_ior: f32, thickness: f32, n_base: f32) -> vec3<f32> {
+    let cos_i_safe = max(cos_i, 1e-4);
+    let cos_film = refracted_cos(cos_i_safe, n_out, film_ior);
+    let cos_base = refracted_cos(cos_i_safe, n_out, n_base);
+    let r12 = fresnel_amplitude(n_out, film_ior, cos_i_safe, cos_film);
+    let r23 = fresnel_amplitude(film_ior, n_base, cos_film, cos_base);
+    let delta = film_phase(film_ior, thickness, cos_film);
+    return 0.5 * (airy_reflectance(r12.x, vec3(r23.x), delta) + airy_reflectance(r12.y, vec3(r23.y), delta));
+}
+
+// Thin film in air over a conductor, the base reflects with amplitude sqrt(f0) and the phase of a
+// denser medium
+fn thin_film_metal(cos_i: f32, film_ior: f32, thickness: f32, f0: vec3<f32>) -> vec3<f32> {
+    let cos_i_safe = max(cos_i, 1e-4);
+    let cos_film = refracted_cos(cos_i_safe, 1.0, film_ior);
+    let r12 = fresnel_amplitude(1.0, film_ior, cos_i_safe, cos_film);
+    let r23 = sqrt(clamp(f0, vec3(0.0), vec3(1.0)));
+    let delta = film_phase(film_ior, thickness, cos_film);
+    return 0.5 * (airy_reflectance(r12.x, -r23, delta) + airy_reflectance(r12.y, r23, delta));
+}
+
+// Fresnel reflectance per channel going from `n1` into `n2`, coloured by the thin film if the
+// material has one
+fn dielectric_reflectance(hit: Hit, cos_theta: f32, n1: f32, n2: f32) -> vec3<f32> {
+    if hit.material.film_thickness <= 0.0 {
+        return vec3(reflectance(cos_theta, n1 / n2));
+    }
+    return thin_film_dielectric(cos_theta, n1, hit.material.film_ior, hit.material.film_thickness, n2);
+}
+
+// A glass object the ray is inside, see `Interface`
+struct Interior {
+    entity: u32,
+    priority: u32,
+    ior: f32,
+    absorption: vec3<f32>,
+}
+
+// Glass objects a ray can be inside at once, any deeper are treated as air
+const MAX_INTERIOR: i32 = 4;
+// False surfaces a path passes through without spending a bounce
+const MAX_FALSE_SURFACES: i32 = 8;
+// No entity has it, for `interior_top` to skip none
+const NO_INTERIOR: u32 = 0xffffffffu;
+
+// Glass objects with a priority the current path is inside, in the order they were entered.
+// Cleared at the start of every path
+var<private> interior: array<Interior, MAX_INTERIOR>;
+var<private> interior_len: i32;
+
+// The medium the ray is in, the highest priority object it is inside with the most recently
+// entered winning ties, ignoring `skip`. -1 for air
+fn interior_top(skip: u32) -> i32 {
+    var top = -1;
+    for (var i = 0; i < interior_len; i += 1) {
+        if interior[i].entity != skip && (top == -1 || interior[i].priority >= interior[top].priority) {
+            top = i;
+        }
+    }
+    return top;
+}
+
+fn interior_find(entity: u32) -> i32 {
+    for (var i = 0; i < interior_len; i += 1) {
+        if interior[i].entity == entity {
+            return i;
+        }
+    }
+    return -1;
+}
+
+// What a ray meeting a glass surface crosses. Glass with a priority can overlap other glass, the
+// highest priority object owns the space they share and surfaces inside it are false, so liquid
+// can fill a glass without an air gap and ice can float in it. Glass without one is always a real
+// surface and never remembered as being inside, which leaves lone objects refracting against air.
+struct Interface {
+    // False surfaces are passed straight through
+    real: bool,
+    // Index of refraction the ray leaves and enters
+    n1: f32,
+    n2: f32,
+    // Absorption of the medium the ray travelled through to get here
+    absorption: vec3<f32>,
+}
+
+fn dielectric_interface(hit: Hit) -> Interface {
+    let tracked = hit.material.priority > 0u;
+    let top = interior_top(NO_INTERIOR);
+    var boundary = Interface(true, 1.0, hit.material.ior, vec3(0.0));
+    if top >= 0 {
+        boundary.n1 = interior[top].ior;
+        boundary.absorption = interior[top].absorption;
+    }
+    if !hit.backface {
+        boundary.real = !tracked || top < 0 || hit.material.priority >= interior[top].priority;
+        return boundary;
+    }
+    let inside = select(-1, interior_find(hit.entity), tracked);
+    if inside < 0 {
+        // Leaving something never seen entered, a lone object or one the camera starts in
+        boundary.n1 = hit.material.ior;
+        boundary.absorption = hit.material.absorption.rgb * hit.material.absorption_strength;
+        boundary.n2 = select(1.0, interior[max(top, 0)].ior, top >= 0);
+        return boundary;
+    }
+    boundary.real = inside == top;
+    let outside = interior_top(hit.entity);
+    boundary.n2 = select(1.0, interior[max(outside, 0)].ior, outside >= 0);
+    return boundary;
+}
+
+// Records the ray passing through a glass surface, entering or leaving its object.
+fn cross_interface(hit: Hit) {
+    if hit.material.priority == 0u {
+        return;
+    }
+    if !hit.backface {
+        if interior_len < MAX_INTERIOR {
+            interior[interior_len] = Interior(
+                hit.entity,
+                hit.material.priority,
+                hit.material.ior,
+                hit.material.absorption.rgb * hit.material.absorption_strength,
+            );
+            interior_len += 1;
+        }
+        return;
+    }
+    let inside = interior_find(hit.entity);
+    if inside < 0 {
+        return;
+    }
+    for (var i = inside; i < interior_len - 1; i += 1) {
+        interior[i] = interior[i + 1];
+    }
+    interior_len -= 1;
+}
+
+// Reflection is picked with the mean of a coloured reflectance, this reweights the chosen branch
+// so the colour comes out unbiased
+fn fresnel_weight(hit: Hit, fresnel: vec3<f32>, reflected: bool) -> vec4<f32> {
+    if hit.material.film_thickness <= 0.0 {
+        return vec4(1.0);
+    }
+    let p = (fresnel.r + fresnel.g + fresnel.b) / 3.0;
+    let weight = select((1.0 - fresnel) / max(1.0 - p, 1e-4), fresnel / max(p, 1e-4), reflected);
+    return vec4(weight, 1.0);
+}
+
+// Colour of the specular lobe for a ray arriving along dir
+fn specular_tint(hit: Hit, dir: vec3<f32>) -> vec4<f32> {
+    let color = hit.material.specular_color;
+    if hit.material.film_thickness <= 0.0 {
+        return color;
+    }
+    let film = thin_film_metal(abs(dot(dir, hit.normal)), hit.material.film_ior, hit.material.film_thickness, color.rgb);
+    return vec4(film, color.a);
+}
+
+// The sky darkens once the sun is below the horizon (x) and warms as it gets low (y)
+fn sky_daylight() -> vec2<f32> {
+    return vec2(max(smoothstep(-0.1, 0.15, scene.sun.y), NIGHT_LEVEL), 1.0 - smoothstep(0.0, 0.3, scene.sun.y));
+}
+
 fn get_environment_light(ray: Ray) -> vec4<f32> {
     let sky_gradient_t = pow(smoothstep(0.0, 0.4, ray.dir.y), 0.35);
     let ground_to_sky_t = smoothstep(-0.01, 0.0, ray.dir.y);
-    let sky_gradient = mix(SKY_HORIZON, SKY_ZENITH, sky_gradient_t);
-    let sun = pow(max(0.0, dot(ray.dir, vec3<f32>(0.1, 1.0, 0.1))), SUN_FOCUS) * SUN_INTENSITY;
-    let composite = mix(GROUND_COLOR, sky_gradient, ground_to_sky_t) + sun * f32(ground_to_sky_t >= 1.0);
+    let daylight = sky_daylight();
+    let day = daylight.x;
+    let dusk = daylight.y;
+    let sky_gradient = mix(mix(SKY_HORIZON, SUNSET_HORIZON, dusk), SKY_ZENITH, sky_gradient_t);
+    let sun = pow(max(0.0, dot(ray.dir, scene.sun.xyz)), SUN_FOCUS) * scene.sun.w * mix(vec4(1.0), SUNSET_SUN, dusk);
+    let composite = mix(GROUND_COLOR, sky_gradient, ground_to_sky_t) * day + sun * f32(ground_to_sky_t >= 1.0);
     return composite;
 }
 
-fn ray_sphere(ray: Ray, centre: vec3<f32>, radius: f32, cull_backface: bool) -> Hit {
+// Light scattered towards the viewer by the height fog along `dst` of the ray, weighted by the
+// path so far. Attenuates `transmittance` by the fog's optical depth, integrated in closed form.
+fn apply_fog(ray: Ray, dst: f32, transmittance: ptr<function, vec4<f32>>) -> vec4<f32> {
+    let density = scene.fog.x;
+    if density <= 0.0 {
+        return vec4(0.0);
+    }
+    let falloff = scene.fog.y;
+    let length = min(dst, FOG_MAX_DISTANCE);
+    let base = density * exp(-falloff * (ray.origin.y - scene.fog.z));
+    let rise = falloff * ray.dir.y * length;
+    // Straight line limit when the ray runs level or the fog doesn't thin out
+    let depth = select(base * length * (1.0 - exp(-rise)) / rise, base * length, abs(rise) < 1e-4);
+    let fog_transmittance = exp(-min(depth, 80.0));
+    var color = scene.fog_color;
+    if scene.fog_color.w != 0.0 {
+        let daylight = sky_daylight();
+        color = select(vec4(0.0), mix(SKY_HORIZON, SUNSET_HORIZON, daylight.y) * daylight.x, skybox_enabled());
+    }
+    let inscattered = *transmittance * vec4(color.rgb, 0.0) * (1.0 - fog_transmittance);
+    *transmittance *= vec4(vec3(fog_transmittance), 1.0);
+    return inscattered;
+}
+
+// Equirectangular mapping: u wraps around the Y axis starting at -X, v runs from the -Y pole (0) to the +Y pole (1)
+fn sphere_uv(outward_normal: vec3<f32>, rotation: f32, tilt: f32) -> vec2<f32> {
+    // Undo the texture tilt (around X) then the spin (around Y)
+    let ct = cos(tilt);
+    let st = sin(tilt);
+    let tilted = vec3(outward_normal.x, ct * outward_normal.y + st * outward_normal.z, -st * outward_normal.y + ct * outward_normal.z);
+    let cr = cos(rotation);
+    let sr = sin(rotation);
+    let n = vec3(cr * tilted.x - sr * tilted.z, tilted.y, sr * tilted.x + cr * tilted.z);
+
+    let pi = 3.1415926;
+    let theta = acos(clamp(-n.y, -1.0, 1.0));
+    // Longitude is undefined at the poles, pin it so the pole pixel doesn't flicker between samples
+    var phi = pi;
+    if abs(n.x) > 1e-6 || abs(n.z) > 1e-6 {
+        phi = atan2(-n.z, -n.x) + pi;
+    }
+    return vec2(fract(phi / (2.0 * pi)), theta / pi);
+}
+
+fn ray_sphere(ray: Ray, sphere: Sphere, cull_backface: bool) -> Hit {
+    let centre = sphere.position;
+    let radius = sphere.radius;
     var hit: Hit;
     hit.dst = INF;
 
@@ -244,46 +1143,106 @@ fn ray_sphere(ray: Ray, centre: vec3<f32>, radius: f32, cull_backface: bool) ->
             hit.dst = select(dst_near, dst_far, is_inside);
             hit.hit_point = ray.origin + ray.dir * hit.dst;
             hit.normal = select(normalize(hit.hit_point - centre), -normalize(hit.hit_point - centre), is_inside);
+            hit.geometric_normal = normalize(hit.hit_point - centre);
             hit.backface = is_inside;
-            let theta = acos(-hit.normal.y);
-            let pi = 3.1415926;
-            let phi = atan2(-hit.normal.z, -hit.normal.x) + pi;
-            hit.uv = vec2(phi / (2.0 * pi), theta / pi);
+            hit.uv = sphere_uv(normalize(hit.hit_point - centre), sphere.texture_rotation, sphere.texture_tilt);
+            // The unit square of texture wraps the whole surface
+            hit.uv_scale = inverseSqrt(4.0 * 3.1415926 * radius * radius);
         }
     }
 
     return hit;
 }
 
-fn ray_triangle(ray: Ray, tri: Triangle, cull_backface: bool) -> Hit {
+// Per-ray setup for the watertight intersection test (Woop et al. 2013)
+struct WatertightRay {
+    k: vec3<u32>,
+    shear: vec3<f32>,
+}
+
+fn watertight_ray(dir: vec3<f32>) -> WatertightRay {
+    var wt: WatertightRay;
+    let a = abs(dir);
+    // Largest component of the direction becomes z
+    var kz = 2u;
+    if a.x > a.y && a.x > a.z {
+        kz = 0u;
+    } else if a.y > a.z {
+        kz = 1u;
+    }
+    var kx = (kz + 1u) % 3u;
+    var ky = (kx + 1u) % 3u;
+    // Swap to preserve the triangle winding
+    if dir[kz] < 0.0 {
+        let tmp = kx;
+        kx = ky;
+        ky = tmp;
+    }
+    wt.k = vec3(kx, ky, kz);
+    wt.shear = vec3(dir[kx] / dir[kz], dir[ky] / dir[kz], 1.0 / dir[kz]);
+    return wt;
+}
+
+fn ray_triangle(ray: Ray, wt: WatertightRay, tri: Triangle, cull_backface: bool) -> Hit {
     var hit: Hit;
     hit.hit = false;
-    let edge_ab = tri.v2 - tri.v1;
-    let edge_ac = tri.v3 - tri.v1;
-    let normal = cross(edge_ab, edge_ac);
-    let ao = ray.origin - tri.v1;
-    let dao = cross(ao, ray.dir);
-    let determinant = -dot(ray.dir, normal);
 
-    let keep = select(abs(determinant) >= 1e-8, determinant >= 1e-8, cull_backface);
+    // Vertices relative to the ray origin
+    let a = tri.v1 - ray.origin;
+    let b = tri.v2 - ray.origin;
+    let c = tri.v3 - ray.origin;
+
+    // Shear and scale so the ray points down +z
+    let ax = a[wt.k.x] - wt.shear.x * a[wt.k.z];
+    let ay = a[wt.k.y] - wt.shear.y * a[wt.k.z];
+    let bx = b[wt.k.x] - wt.shear.x * b[wt.k.z];
+    let by = b[wt.k.y] - wt.shear.y * b[wt.k.z];
+    let cx = c[wt.k.x] - wt.shear.x * c[wt.k.z];
+    let cy = c[wt.k.y] - wt.shear.y * c[wt.k.z];
+
+    // Scaled barycentrics, edges shared by two triangles evaluate identically so nothing slips between them
+    let e1 = cx * by - cy * bx;
+    let e2 = ax * cy - ay * cx;
+    let e3 = bx * ay - by * ax;
+
+    if cull_backface {
+        if e1 < 0.0 || e2 < 0.0 || e3 < 0.0 {
+            return hit;
+        }
+    } else if (e1 < 0.0 || e2 < 0.0 || e3 < 0.0) && (e1 > 0.0 || e2 > 0.0 || e3 > 0.0) {
+        return hit;
+    }
 
-    if !keep {
+    // Positive when the ray travels against the geometric normal (front face)
+    let determinant = e1 + e2 + e3;
+    if determinant == 0.0 {
         return hit;
     }
+
+    let az = wt.shear.z * a[wt.k.z];
+    let bz = wt.shear.z * b[wt.k.z];
+    let cz = wt.shear.z * c[wt.k.z];
     let inverse_determinant = 1.0 / determinant;
+    let dst = (e1 * az + e2 * bz + e3 * cz) * inverse_determinant;
 
-    let dst = dot(ao, normal) * inverse_determinant;
-    let u = dot(edge_ac, dao) * inverse_determinant;
-    let v = -dot(edge_ab, dao) * inverse_determinant;
-    let w = 1.0 - u - v;
+    let w = e1 * inverse_determinant;
+    let u = e2 * inverse_determinant;
+    let v = e3 * inverse_determinant;
 
-    if dst > EPSILON && u >= 0.0 && v >= 0.0 && w >= 0.0 {
+    if dst > EPSILON {
         hit.hit = true;
         hit.normal = normalize(tri.n1 * w + tri.n2 * u + tri.n3 * v) * sign(determinant);
+        hit.geometric_normal = normalize(cross(tri.v2 - tri.v1, tri.v3 - tri.v1));
         hit.backface = determinant < 0.0;
         hit.hit_point = ray.origin + ray.dir * dst;
         hit.dst = dst;
         hit.uv = vec2(tri.u10, tri.u11) * w + vec2(tri.u20, tri.u21) * u + vec2(tri.u30, tri.u31) * v;
+        let uv_edge_1 = vec2(tri.u20 - tri.u10, tri.u21 - tri.u11);
+        let uv_edge_2 = vec2(tri.u30 - tri.u10, tri.u31 - tri.u11);
+        let uv_area = abs(uv_edge_1.x * uv_edge_2.y - uv_edge_1.y * uv_edge_2.x);
+        hit.uv_scale = sqrt(uv_area / max(length(cross(tri.v2 - tri.v1, tri.v3 - tri.v1)), 1e-12));
+        let color = unpack4x8unorm(tri.c1).rgb * w + unpack4x8unorm(tri.c2).rgb * u + unpack4x8unorm(tri.c3).rgb * v;
+        hit.vertex_shade = 1.0 - srgb_to_linear(color);
     }
 
     return hit;
@@ -293,6 +1252,7 @@ fn ray_BVH(ray: Ray, ray_length: f32, node_offset: u32, tri_offset: u32, cull_ba
     var closest_hit: Hit;
     closest_hit.hit = false;
     closest_hit.dst = ray_length;
+    let watertight = watertight_ray(ray.dir);
 
     var stack: array<u32,32>;
     var stack_index: u32 = 0u;
@@ -307,7 +1267,7 @@ fn ray_BVH(ray: Ray, ray_length: f32, node_offset: u32, tri_offset: u32, cull_ba
             (*stats)[1] += i32(node.count); // Track triangle checks
             for (var j: u32 = 0u; j < node.count; j += 1u) {
                 let tri = triangles[tri_offset + node.first + j];
-                let hit = ray_triangle(ray, tri, cull_backface);
+                let hit = ray_triangle(ray, watertight, tri, cull_backface);
                 if hit.hit && hit.dst < closest_hit.dst {
                     closest_hit = hit;
                 }
@@ -355,11 +1315,13 @@ fn calculate_ray_collions(ray: Ray, stats: ptr<function, vec2<i32>>) -> Hit {
     closest_hit.hit = false;
     closest_hit.dst = INF;
     for (var i: u32 = 0u; i < scene.spheres; i += 1u) {
-        var cull_backface = spheres[i].material.flag != MATERIAL_GLASS;
-        let hit: Hit = ray_sphere(ray, spheres[i].position, spheres[i].radius, cull_backface);
+        var cull_backface = cull_material(spheres[i].material);
+        let hit: Hit = ray_sphere(ray, spheres[i], cull_backface);
         if hit.hit && hit.dst < closest_hit.dst {
             closest_hit = hit;
+            closest_hit.local_point = (hit.hit_point - spheres[i].position) / spheres[i].radius;
             closest_hit.material = spheres[i].material;
+            closest_hit.entity = i;
         }
     }
     var local_ray: Ray;
@@ -372,7 +1334,7 @@ fn calculate_ray_collions(ray: Ray, stats: ptr<function, vec2<i32>>) -> Hit {
         local_ray.dir = normalize((mesh.world_to_model * vec4<f32>(ray.dir, 0.0)).xyz);
         local_ray.inv_dir = 1.0 / local_ray.dir;
         // Transform using matrices here instead of cpu, do later...
-        var cull_backface = mesh.material.flag != MATERIAL_GLASS;
+        var cull_backface = cull_material(mesh.material);
 
         let hit: Hit = ray_BVH(local_ray, INF, mesh.node_offset, mesh.triangle_offset, cull_backface, stats);
         if hit.hit {
@@ -384,10 +1346,19 @@ fn calculate_ray_collions(ray: Ray, stats: ptr<function, vec2<i32>>) -> Hit {
                 closest_hit.hit = true;
                 closest_hit.backface = hit.backface;
                 closest_hit.normal = normalize((mesh.model_to_world * vec4<f32>(hit.normal, 0.0)).xyz);
+                closest_hit.geometric_normal = normalize((mesh.model_to_world * vec4<f32>(hit.geometric_normal, 0.0)).xyz);
                 closest_hit.hit_point = world_hit_point;
+                closest_hit.local_point = local_hit_point;
                 closest_hit.dst = world_dst;
                 closest_hit.material = mesh.material;
+                if mesh.uv_view != 0u {
+                    closest_hit.material = uv_view_material(mesh.material, hit.uv, mesh.uv_view);
+                }
                 closest_hit.uv = hit.uv;
+                // Areas grow by the square of the transform's scale, taken as uniform
+                let model_to_world = mat3x3(mesh.model_to_world[0].xyz, mesh.model_to_world[1].xyz, mesh.model_to_world[2].xyz);
+                closest_hit.uv_scale = hit.uv_scale / pow(abs(determinant(model_to_world)), 1.0 / 3.0);
+                closest_hit.entity = scene.spheres + i;
             }
         }
     }
@@ -395,68 +1366,254 @@ fn calculate_ray_collions(ray: Ray, stats: ptr<function, vec2<i32>>) -> Hit {
     return closest_hit;
 }
 
+// Distances along a camera ray to the near and far clip planes
+fn clip_range(dir: vec3<f32>) -> vec2<f32> {
+    let cos_view = max(dot(dir, normalize(scene.camera.cam_to_world[2].xyz)), 1e-4);
+    return vec2(scene.camera.near, scene.camera.far) / cos_view;
+}
+
+// Closest hit of a camera ray between the clip planes, ignoring materials and visibility
+fn camera_hit(incident_ray: Ray, stats: ptr<function, vec2<i32>>) -> Hit {
+    var ray = incident_ray;
+    let clip = clip_range(ray.dir);
+    ray.origin += ray.dir * clip.x;
+    var hit = calculate_ray_collions(ray, stats);
+    hit.dst += clip.x;
+    hit.hit = hit.hit && hit.dst <= clip.y;
+    return hit;
+}
+
+// Opaque surfaces only need their front faces, except emitters which light both sides unless
+// single sided
+fn cull_material(material: Material) -> bool {
+    let two_sided_light = material.emission_strength > 0.0 && (material.light_flags & LIGHT_SINGLE_SIDED) == 0u;
+    return material.flag != MATERIAL_GLASS && material.flag != MATERIAL_BLEND && !two_sided_light;
+}
+
+// Closest hit with its material resolved, passing through emitters carrying any of the `hidden`
+// light flags and entities hidden from rays of this `kind` of visibility. Hidden emitters' light
+// still arrives through light sampling and diffuse bounces.
+fn trace_visible(incident_ray: Ray, hidden: u32, kind: u32, stats: ptr<function, vec2<i32>>, seed: ptr<function, u32>) -> Hit {
+    var ray = incident_ray;
+    var travelled = 0.0;
+    var far = INF;
+    if kind == VISIBILITY_CAMERA {
+        let clip = clip_range(ray.dir);
+        travelled = clip.x;
+        far = clip.y;
+        ray.origin += ray.dir * clip.x;
+    }
+    var hit: Hit;
+    for (var i = 0; i < MAX_SKIPPED_SURFACES; i += 1) {
+        hit = calculate_ray_collions(ray, stats);
+        if !hit.hit {
+            break;
+        }
+        // Read before a blend swaps in its palette material
+        let invisible = (hit.material.visibility & kind) != 0u;
+        hit.footprint = cone_footprint(hit, incident_ray, hit.dst + travelled);
+        resolve_material(&hit, seed);
+        hit.dst += travelled;
+        if hit.dst > far {
+            hit.hit = false;
+            hit.dst = far;
+            break;
+        }
+        let hidden_light = hidden != 0u && hit.material.emission_strength > 0.0 && (hit.material.light_flags & hidden) != 0u;
+        if !invisible && !hidden_light {
+            break;
+        }
+        if i == MAX_SKIPPED_SURFACES - 1 {
+            hit.hit = false;
+            break;
+        }
+        travelled = hit.dst + 1e-4;
+        ray.origin = hit.hit_point + ray.dir * 1e-4;
+    }
+    return hit;
+}
+
+// Density and emission of one voxel, normalised to each channel's maximum and zero outside the grid
+fn volume_voxel(volume: Volume, voxel: vec3<i32>) -> vec2<f32> {
+    if any(voxel < vec3(0)) || any(voxel >= vec3<i32>(volume.dims)) {
+        return vec2(0.0);
+    }
+    let v = vec3<u32>(voxel);
+    let bricks = (volume.dims + BRICK_SIZE - 1u) / BRICK_SIZE;
+    let b = v / BRICK_SIZE;
+    let brick = voxels[volume.table + (b.z * bricks.y + b.y) * bricks.x + b.x];
+    if brick == EMPTY_BRICK {
+        return vec2(0.0);
+    }
+    let l = v % BRICK_SIZE;
+    return unpack2x16unorm(voxels[volume.data + brick * BRICK_SIZE * BRICK_SIZE * BRICK_SIZE + (l.z * BRICK_SIZE + l.y) * BRICK_SIZE + l.x]);
+}
+
+fn sample_volume(volume: Volume, p: vec3<f32>) -> vec2<f32> {
+    let uvw = (p - volume.bounds_min) / (volume.bounds_max - volume.bounds_min);
+    let g = uvw * vec3<f32>(volume.dims) - 0.5;
+    let base = floor(g);
+    let f = g - base;
+    let i = vec3<i32>(base);
+    let c00 = mix(volume_voxel(volume, i), volume_voxel(volume, i + vec3(1, 0, 0)), f.x);
+    let c10 = mix(volume_voxel(volume, i + vec3(0, 1, 0)), volume_voxel(volume, i + vec3(1, 1, 0)), f.x);
+    let c01 = mix(volume_voxel(volume, i + vec3(0, 0, 1)), volume_voxel(volume, i + vec3(1, 0, 1)), f.x);
+    let c11 = mix(volume_voxel(volume, i + vec3(0, 1, 1)), volume_voxel(volume, i + vec3(1, 1, 1)), f.x);
+    return mix(mix(c00, c10, f.y), mix(c01, c11, f.y), f.z);
+}
+
+// Delta tracking through every volume the ray crosses before max_dst, keeping the nearest real collision
+fn ray_volumes(ray: Ray, max_dst: f32, seed: ptr<function, u32>) -> VolumeHit {
+    var result: VolumeHit;
+    result.hit = false;
+    result.dst = max_dst;
+    let inv_dir = 1.0 / ray.dir;
+    for (var i: u32 = 0u; i < scene.volumes; i += 1u) {
+        let volume = volumes[i];
+        if volume.majorant <= 0.0 {
+            continue;
+        }
+        let t1 = (volume.bounds_min - ray.origin) * inv_dir;
+        let t2 = (volume.bounds_max - ray.origin) * inv_dir;
+        let t_near = max(max(min(t1.x, t2.x), min(t1.y, t2.y)), min(t1.z, t2.z));
+        let t_far = min(min(max(t1.x, t2.x), max(t1.y, t2.y)), max(t1.z, t2.z));
+        let t_exit = min(t_far, result.dst);
+        var t = max(t_near, 0.0);
+        for (var step = 0; step < MAX_VOLUME_STEPS && t < t_exit; step += 1) {
+            t -= log(1.0 - rand(seed)) / volume.majorant;
+            if t >= t_exit {
+                break;
+            }
+            let s = sample_volume(volume, ray.origin + ray.dir * t);
+            // Density is stored relative to its maximum, which is exactly the real/majorant ratio
+            if rand(seed) < s.x {
+                result.hit = true;
+                result.dst = t;
+                result.volume = i;
+                result.emission = s.y * volume.max_emission;
+                break;
+            }
+        }
+    }
+    return result;
+}
+
+// Scales light reaching the camera after `bounce` surfaces down to the direct or indirect clamp,
+// keeping its hue. Emitters seen straight from the camera aren't lighting anything so never clamp
+fn clamp_light(light: vec4<f32>, bounce: i32) -> vec4<f32> {
+    let limit = select(params.clamp_indirect, params.clamp_direct, bounce <= 1);
+    let peak = max(light.r, max(light.g, light.b));
+    if bounce == 0 || limit <= 0.0 || peak <= limit {
+        return light;
+    }
+    return light * (limit / peak);
+}
+
+// BVH nodes visited, triangles tested and segments traced by this invocation's paths, for the
+// cost views. Never read otherwise, so the stores are dropped from the normal pipeline
+var<private> path_cost: vec3<u32>;
+
 fn trace(incident_ray: Ray, seed: ptr<function, u32>) -> vec4<f32> {
     var ray: Ray = incident_ray;
+    let first = i32(ray.bounces);
     ray.dir = normalize(ray.dir);
     ray.transmittance = vec4<f32>(1.0);
     var incoming_light = vec4<f32>(0.0);
-    var _stats = vec2<i32>(0, 0);
+    var stats = vec2<i32>(0, 0);
+    var hidden = select(0u, LIGHT_HIDDEN_FROM_CAMERA, ray.camera);
+    var kind = select(VISIBILITY_BOUNCE, VISIBILITY_CAMERA, ray.camera);
+    var false_surfaces = 0;
+    interior_len = 0;
     for (var i = i32(ray.bounces); i <= params.number_of_bounces; i += 1) {
-        var hit = calculate_ray_collions(ray, &_stats);
-        if !hit.hit {
-            // Use get_environment_light if skybox is enabled
-            if params.skybox != 0 {
-                incoming_light += ray.transmittance * get_environment_light(ray);
+        path_cost.z += 1u;
+        let hit = trace_visible(ray, hidden, kind, &stats, seed);
+        hidden = 0u;
+        kind = VISIBILITY_BOUNCE;
+        let medium = ray_volumes(ray, hit.dst, seed);
+        let bounce = i - first;
+        incoming_light += clamp_light(apply_fog(ray, select(select(FOG_MAX_DISTANCE, hit.dst, hit.hit), medium.dst, medium.hit), &ray.transmittance), bounce);
+        if medium.hit {
+            let volume = volumes[medium.volume];
+            incoming_light += clamp_light(volume.emission * medium.emission * ray.transmittance, bounce);
+            ray.transmittance *= volume.color;
+            advance_cone(&ray, medium.dst, 1.0);
+            ray.origin += ray.dir * medium.dst;
+            // Isotropic phase function
+            ray.dir = rand_direction(seed);
+        } else {
+            if !hit.hit {
+                // Use get_environment_light if skybox is enabled
+                if skybox_enabled() {
+                    incoming_light += clamp_light(ray.transmittance * get_environment_light(ray), bounce);
+                }
+                break;
             }
-            break;
-        }
-        ray.origin = hit.hit_point;
-        if hit.material.flag == MATERIAL_GLASS {
-            if hit.backface {
-                let x = ray.transmittance.rgb * exp(-hit.dst * hit.material.absorption.rgb * hit.material.absorption_strength);
+            ray.origin = hit.hit_point;
+            if hit.material.flag == MATERIAL_GLASS {
+                let boundary = dielectric_interface(hit);
+                let x = ray.transmittance.rgb * exp(-hit.dst * boundary.absorption);
                 ray.transmittance = vec4(x.r, x.g, x.b, 1.0);
-            }
+                if !boundary.real {
+                    cross_interface(hit);
+                    ray.origin = hit.hit_point + 1e-4 * hit.normal * sign(dot(hit.normal, ray.dir));
+                    advance_cone(&ray, hit.dst, 0.0);
+                    if false_surfaces < MAX_FALSE_SURFACES {
+                        false_surfaces += 1;
+                        i -= 1;
+                    }
+                    continue;
+                }
 
-            let ior = select(1.0 / hit.material.ior, hit.material.ior, hit.backface);
+                let ior = boundary.n1 / boundary.n2;
 
-            var reflect_dir = reflect(ray.dir, hit.normal);
-            var refract_dir = refract(ray.dir, hit.normal, ior);
-            let cos_theta = min(dot(-ray.dir, hit.normal), 1.0);
-            let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
-            let cannot_refract = ior * sin_theta > 1.0;
+                var reflect_dir = reflect(ray.dir, hit.normal);
+                var refract_dir = refract(ray.dir, hit.normal, ior);
+                let cos_theta = min(dot(-ray.dir, hit.normal), 1.0);
+                let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+                let cannot_refract = ior * sin_theta > 1.0;
 
-            let follow_reflection = cannot_refract || reflectance(cos_theta, ior) > rand(seed);
+                let fresnel = dielectric_reflectance(hit, cos_theta, boundary.n1, boundary.n2);
+                let follow_reflection = cannot_refract || (fresnel.r + fresnel.g + fresnel.b) / 3.0 > rand(seed);
+                if !cannot_refract {
+                    ray.transmittance *= fresnel_weight(hit, fresnel, follow_reflection);
+                }
+                if !follow_reflection {
+                    cross_interface(hit);
+                }
 
-            let diffuse_dir = normalize(hit.normal + rand_direction(seed));
+                let diffuse_dir = normalize(hit.normal + rand_direction(seed));
 
-            reflect_dir = normalize(mix(diffuse_dir, reflect_dir, hit.material.specular));
-            refract_dir = normalize(mix(-diffuse_dir, refract_dir, hit.material.smoothness));
+                reflect_dir = normalize(mix(diffuse_dir, reflect_dir, hit.material.specular));
+                refract_dir = normalize(mix(-diffuse_dir, refract_dir, hit.material.smoothness));
 
-            ray.dir = select(refract_dir, reflect_dir, follow_reflection);
-            ray.origin = hit.hit_point + 1e-4 * hit.normal * sign(dot(hit.normal, ray.dir));
-        } else {
-            let is_specular_bounce = hit.material.specular >= rand(seed);
-            var normal: vec3<f32>;
-            if hit.material.flag == MATERIAL_TEXTURE && hit.material.normal_index != -1{
-                // let x = textureSampleLevel(textures[hit.material.normal_index], samplers[0], hit.uv, 0.0);
-                // normal = 2.0 * vec3(x.r, x.g, x.b) - 1.0;
-                // TODO: Correctly handle normal map textures
-            }else{
-                normal = hit.normal;
-            }
-            normal = hit.normal;
-            let diffuse_dir = rand_hemisphere(normal, seed);
-            let specular_dir = reflect(ray.dir, normal);
-            let emitted_light = hit.material.emission_color * hit.material.emission_strength;
-            ray.dir = normalize(mix(diffuse_dir, specular_dir, hit.material.smoothness * f32(is_specular_bounce)));
-            incoming_light += emitted_light * ray.transmittance;
-            var color: vec4<f32>;
-            if hit.material.flag == MATERIAL_TEXTURE && hit.material.diffuse_index != -1{
-                color = textureSampleLevel(textures[hit.material.diffuse_index], samplers[0], hit.uv, 0.0);
+                ray.dir = select(refract_dir, reflect_dir, follow_reflection);
+                ray.origin = hit.hit_point + 1e-4 * hit.normal * sign(dot(hit.normal, ray.dir));
+                advance_cone(&ray, hit.dst, 1.0 - select(hit.material.smoothness, hit.material.specular, follow_reflection));
+                hidden = LIGHT_HIDDEN_FROM_SPECULAR;
             } else {
-                color = hit.material.color;
+                let is_specular_bounce = hit.material.specular >= rand(seed);
+                var normal: vec3<f32>;
+                if hit.material.flag == MATERIAL_TEXTURE && hit.material.normal_index != -1{
+                    // let x = textureSampleLevel(textures[hit.material.normal_index], samplers[0], hit.uv, 0.0);
+                    // normal = 2.0 * vec3(x.r, x.g, x.b) - 1.0;
+                    // TODO: Correctly handle normal map textures
+                }else{
+                    normal = hit.normal;
+                }
+                normal = hit.normal;
+                var diffuse = vec4(rand_hemisphere(normal, seed), 1.0);
+                if !is_specular_bounce {
+                    diffuse = sample_bounce(hit.hit_point, normal, true, seed);
+                }
+                let specular_dir = reflect(ray.dir, normal);
+                let specular_color = specular_tint(hit, ray.dir);
+                let emitted_light = emission(hit);
+                ray.dir = normalize(mix(diffuse.xyz, specular_dir, hit.material.smoothness * f32(is_specular_bounce)));
+                advance_cone(&ray, hit.dst, 1.0 - hit.material.smoothness * f32(is_specular_bounce));
+                incoming_light += clamp_light(emitted_light * ray.transmittance, bounce);
+                hidden = select(0u, LIGHT_HIDDEN_FROM_SPECULAR, is_specular_bounce);
+                ray.transmittance *= select(albedo(hit) * diffuse.w, specular_color, is_specular_bounce);
             }
-            ray.transmittance *= select(color, hit.material.specular_color, is_specular_bounce);
         }
 
         let p = max(ray.transmittance.r, max(ray.transmittance.g, ray.transmittance.b));
@@ -466,34 +1623,588 @@ fn trace(incident_ray: Ray, seed: ptr<function, u32>) -> vec4<f32> {
         ray.transmittance *= 1.0 / p;
         ray.inv_dir = 1.0 / ray.dir;
     }
+    path_cost += vec3(vec2<u32>(stats), 0u);
 
     return incoming_light;
 }
 
+// Albedo and shading normal a camera ray sees, indexed by FEATURE_ALBEDO and FEATURE_NORMAL. Sharp
+// mirrors and glass pass through to the first rougher surface beyond, tinted by what they reflect
+// or transmit, since their own features say nothing about the detail shown in them. Emitters and
+// the sky give their colour clamped to one, and no normal.
+fn denoise_features(incident_ray: Ray, seed: ptr<function, u32>) -> array<vec3<f32>, 2> {
+    var ray = incident_ray;
+    var throughput = vec3<f32>(1.0);
+    var hidden = LIGHT_HIDDEN_FROM_CAMERA;
+    var kind = VISIBILITY_CAMERA;
+    var stats = vec2<i32>(0, 0);
+    interior_len = 0;
+    for (var i = 0; i < MAX_FEATURE_BOUNCES; i += 1) {
+        let hit = trace_visible(ray, hidden, kind, &stats, seed);
+        hidden = LIGHT_HIDDEN_FROM_SPECULAR;
+        kind = VISIBILITY_BOUNCE;
+        if !hit.hit {
+            let sky = select(vec3(0.0), min(get_environment_light(ray).rgb, vec3(1.0)), skybox_enabled());
+            return array(throughput * sky, vec3(0.0));
+        }
+        let emitted = emission(hit).rgb;
+        if any(emitted > vec3(0.0)) {
+            return array(throughput * min(emitted, vec3(1.0)), hit.normal);
+        }
+        let sharp = hit.material.smoothness >= DELTA_SMOOTHNESS;
+        if hit.material.flag == MATERIAL_GLASS {
+            if !sharp {
+                return array(throughput * hit.material.color.rgb, hit.normal);
+            }
+            throughput *= dielectric_bounce(&ray, hit, seed).rgb;
+            continue;
+        }
+        // The specular lobe is picked as often as the path tracer picks it, so partly mirrored
+        // surfaces average out to a blend of both
+        if sharp && hit.material.specular >= rand(seed) {
+            throughput *= specular_tint(hit, ray.dir).rgb;
+            ray.dir = reflect(ray.dir, hit.normal);
+            ray.origin = hit.hit_point + hit.normal * 1e-4;
+            ray.inv_dir = 1.0 / ray.dir;
+            advance_cone(&ray, hit.dst, 0.0);
+            continue;
+        }
+        let surface = mix(albedo(hit).rgb, specular_tint(hit, ray.dir).rgb, hit.material.specular);
+        return array(throughput * surface, hit.normal);
+    }
+    return array(vec3(0.0), vec3(0.0));
+}
+
+// Width in texture coordinates of the ray's cone where it meets the surface at `dst`, stretched by
+// the angle it meets it at. Ray cones (Akenine-Möller et al. 2021) without surface curvature.
+fn cone_footprint(hit: Hit, ray: Ray, dst: f32) -> f32 {
+    let width = ray.cone.x + ray.cone.y * dst;
+    let cos_theta = max(abs(dot(hit.geometric_normal, ray.dir)), MIN_CONE_COS);
+    return width / cos_theta * hit.uv_scale;
+}
+
+// Moves the ray's cone on to a surface `dst` away that it bounces off, widening it the rougher
+// the bounce was
+fn advance_cone(ray: ptr<function, Ray>, dst: f32, roughness: f32) {
+    (*ray).cone = vec2((*ray).cone.x + (*ray).cone.y * dst, (*ray).cone.y + roughness * ROUGH_CONE_SPREAD);
+}
+
+// Picks the palette material of a blend, then applies any channel packed textures
+fn resolve_material(hit: ptr<function, Hit>, seed: ptr<function, u32>) {
+    resolve_blend(hit, seed);
+    apply_orm(hit);
+    apply_clay(hit);
+}
+
+// Swaps every material but the lights for matte grey while a `ClayMode` is on, so the lighting
+// can be judged apart from the surfaces. The textured variant keeps the brightness of the
+// surface's colour, texture and vertex colours
+fn apply_clay(hit: ptr<function, Hit>) {
+    let material = (*hit).material;
+    if params.clay == CLAY_OFF || material.emission_strength > 0.0 {
+        return;
+    }
+    var grey = CLAY_ALBEDO;
+    if params.clay == CLAY_TEXTURED {
+        var base = material.color;
+        if material.flag == MATERIAL_TEXTURE && material.diffuse_index != -1 {
+            base = sample_texture(material.diffuse_index, (*hit).uv, material.address_mode, (*hit).footprint);
+        }
+        grey = dot(base.rgb * (1.0 - (*hit).vertex_shade), vec3(0.2126, 0.7152, 0.0722));
+    }
+    var clay = material;
+    clay.color = vec4(vec3(grey), 1.0);
+    clay.flag = MATERIAL_DEFAULT;
+    clay.specular = 0.0;
+    clay.smoothness = 0.0;
+    clay.diffuse_index = -1;
+    clay.normal_index = -1;
+    clay.orm_index = -1;
+    clay.film_thickness = 0.0;
+    (*hit).material = clay;
+    (*hit).vertex_shade = vec3(0.0);
+    (*hit).cavity = 0.0;
+}
+
+// Blend materials take one of their two palette materials per sample, weighted by the mask, which
+// averages to the mix of both once accumulated
+fn resolve_blend(hit: ptr<function, Hit>, seed: ptr<function, u32>) {
+    let material = (*hit).material;
+    if material.flag != MATERIAL_BLEND {
+        return;
+    }
+    var mask: f32;
+    if material.blend_mask != -1 {
+        mask = sample_texture(material.blend_mask, (*hit).uv, material.address_mode, (*hit).footprint).r;
+    } else {
+        mask = fractal_noise((*hit).local_point * material.blend_scale);
+    }
+    let threshold = 1.0 - material.blend_coverage;
+    let half_width = max(material.blend_softness, 1e-4);
+    let t = smoothstep(threshold - half_width, threshold + half_width, mask);
+    let index = select(material.blend_a, material.blend_b, rand(seed) < t);
+    if index < 0 || index >= MAX_MATERIALS {
+        return;
+    }
+    (*hit).material = materials[index];
+}
+
+const ORM_UNUSED: u32 = 0xffu;
+
+// Channel of the ORM texel routed to the property at `shift` in `orm_channels`, or `fallback`
+fn orm_channel(texel: vec4<f32>, channels: u32, shift: u32, fallback: f32) -> f32 {
+    let channel = (channels >> shift) & 0xffu;
+    if channel == ORM_UNUSED {
+        return fallback;
+    }
+    return texel[min(channel, 3u)];
+}
+
+// Occlusion/roughness/metallic from one texture, following glTF where roughness and metallic
+// scale the material's own factors
+fn apply_orm(hit: ptr<function, Hit>) {
+    let material = (*hit).material;
+    if material.orm_index == -1 {
+        return;
+    }
+    let sampled = sample_texture(material.orm_index, (*hit).uv, material.address_mode, (*hit).footprint);
+    // Textures are bound as sRGB, undo the decode to get the stored values back
+    let texel = vec4(linear_to_srgb(sampled.rgb), sampled.a);
+    let occlusion = orm_channel(texel, material.orm_channels, 0u, 1.0);
+    let roughness = orm_channel(texel, material.orm_channels, 8u, 1.0);
+    let metallic = orm_channel(texel, material.orm_channels, 16u, 0.0);
+    (*hit).material.smoothness = 1.0 - (1.0 - material.smoothness) * roughness;
+    (*hit).material.specular = mix(material.specular, 1.0, metallic);
+    (*hit).material.specular_color = mix(material.specular_color, albedo(*hit), metallic);
+    (*hit).cavity = 1.0 - occlusion;
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3(0.0031308));
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3(2.4));
+    return select(high, low, c <= vec3(0.04045));
+}
+
+fn hash_cell(cell: vec3<i32>) -> f32 {
+    var h = (u32(cell.x) * 73856093u) ^ (u32(cell.y) * 19349663u) ^ (u32(cell.z) * 83492791u);
+    return f32(next_random_number(&h)) / 4294967295.0;
+}
+
+// Trilinear value noise in [0, 1]
+fn value_noise(p: vec3<f32>) -> f32 {
+    let cell = vec3<i32>(floor(p));
+    let f = fract(p);
+    let w = f * f * (3.0 - 2.0 * f);
+    let x00 = mix(hash_cell(cell), hash_cell(cell + vec3(1, 0, 0)), w.x);
+    let x10 = mix(hash_cell(cell + vec3(0, 1, 0)), hash_cell(cell + vec3(1, 1, 0)), w.x);
+    let x01 = mix(hash_cell(cell + vec3(0, 0, 1)), hash_cell(cell + vec3(1, 0, 1)), w.x);
+    let x11 = mix(hash_cell(cell + vec3(0, 1, 1)), hash_cell(cell + vec3(1, 1, 1)), w.x);
+    return mix(mix(x00, x10, w.y), mix(x01, x11, w.y), w.z);
+}
+
+fn fractal_noise(p: vec3<f32>) -> f32 {
+    var sum = 0.0;
+    var amplitude = 0.5;
+    var q = p;
+    for (var i = 0; i < 4; i += 1) {
+        sum += amplitude * value_noise(q);
+        q *= 2.03;
+        amplitude *= 0.5;
+    }
+    return sum / 0.9375;
+}
+
+// Samples a material texture with its address mode applied, filtered over `footprint`, a width in
+// texture coordinates. The sampler itself repeats, so clamped and mirrored coordinates are kept
+// half a texel of the level sampled inside the edges, where filtering would otherwise blend in the
+// opposite side.
+fn sample_texture(texture: i32, uv: vec2<f32>, mode: u32, footprint: f32) -> vec4<f32> {
+    let size = vec2<f32>(texture_size(texture));
+    // The level whose texels are as wide as the footprint, blended with the next
+    let lod = log2(max(footprint * sqrt(size.x * size.y), 1e-6));
+    var st = uv;
+    if mode != ADDRESS_REPEAT {
+        if mode == ADDRESS_MIRROR {
+            st = 1.0 - abs(fract(uv * 0.5) * 2.0 - 1.0);
+        }
+        // Texels double in width each level, the next level down is blended in too
+        let margin = min(0.5 * exp2(max(ceil(lod), 0.0)) / size, vec2(0.5));
+        st = clamp(st, margin, 1.0 - margin);
+    }
+    return texture_lookup(texture, st, lod);
+}
+
+// Pattern laid out by texture coordinates for spotting bad UVs. Checker squares are shaded by the
+// coordinates so a flipped or rotated layout shows, and false colour stripes whatever falls
+// outside the 0 to 1 square.
+fn uv_pattern(uv: vec2<f32>, view: u32) -> vec3<f32> {
+    let st = fract(uv);
+    if view == UV_VIEW_CHECKER {
+        let cell = vec2<i32>(floor(uv * UV_CHECKER_CELLS));
+        let light = vec3(0.35 + 0.6 * st, 0.9);
+        return select(light, light * 0.2, ((cell.x + cell.y) & 1) == 1);
+    }
+    let outside = any(floor(uv) != vec2(0.0)) && fract((uv.x + uv.y) * UV_CHECKER_CELLS) < 0.5;
+    return vec3(st, select(0.0, 1.0, outside));
+}
+
+// Plain diffuse material coloured by `uv_pattern`, standing in for a mesh's own while it shows its UVs
+fn uv_view_material(material: Material, uv: vec2<f32>, view: u32) -> Material {
+    var m = material;
+    m.color = vec4(uv_pattern(uv, view), 1.0);
+    m.emission_strength = 0.0;
+    m.specular = 0.0;
+    m.smoothness = 0.0;
+    m.flag = 0;
+    m.orm_index = -1;
+    return m;
+}
+
+fn albedo(hit: Hit) -> vec4<f32> {
+    var base = hit.material.color;
+    if hit.material.flag == MATERIAL_TEXTURE && hit.material.diffuse_index != -1 {
+        base = sample_texture(hit.material.diffuse_index, hit.uv, hit.material.address_mode, hit.footprint);
+    }
+    return vec4(base.rgb * (1.0 - hit.cavity) * (1.0 - hit.vertex_shade), base.a);
+}
+
+fn emission(hit: Hit) -> vec4<f32> {
+    if hit.backface && (hit.material.light_flags & LIGHT_SINGLE_SIDED) != 0u {
+        return vec4(0.0);
+    }
+    return hit.material.emission_color * hit.material.emission_strength;
+}
+
+// Picks reflection or refraction by Fresnel, leaving the ray just past the surface. Returns the
+// transmittance through the medium the ray has just left. False surfaces of overlapping glass are
+// passed straight through.
+fn dielectric_bounce(ray: ptr<function, Ray>, hit: Hit, seed: ptr<function, u32>) -> vec4<f32> {
+    let boundary = dielectric_interface(hit);
+    var absorbed = vec4(exp(-hit.dst * boundary.absorption), 1.0);
+    if !boundary.real {
+        cross_interface(hit);
+        (*ray).origin = hit.hit_point + 1e-4 * hit.normal * sign(dot(hit.normal, (*ray).dir));
+        advance_cone(ray, hit.dst, 0.0);
+        return absorbed;
+    }
+    let ior = boundary.n1 / boundary.n2;
+    let cos_theta = min(dot(-(*ray).dir, hit.normal), 1.0);
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let cannot_refract = ior * sin_theta > 1.0;
+    let fresnel = dielectric_reflectance(hit, cos_theta, boundary.n1, boundary.n2);
+    let follow_reflection = cannot_refract || (fresnel.r + fresnel.g + fresnel.b) / 3.0 > rand(seed);
+    if !cannot_refract {
+        absorbed *= fresnel_weight(hit, fresnel, follow_reflection);
+    }
+    if !follow_reflection {
+        cross_interface(hit);
+    }
+    (*ray).dir = select(refract((*ray).dir, hit.normal, ior), reflect((*ray).dir, hit.normal), follow_reflection);
+    (*ray).origin = hit.hit_point + 1e-4 * hit.normal * sign(dot(hit.normal, (*ray).dir));
+    (*ray).inv_dir = 1.0 / (*ray).dir;
+    advance_cone(ray, hit.dst, 0.0);
+    return absorbed;
+}
+
+// Whether nothing blocks the segment from p to the light, the first thing hit must be the light
+// itself. `light` is past the last entity for the sky, which must be reached without hitting anything.
+fn light_visible(p: vec3<f32>, dir: vec3<f32>, light: u32) -> bool {
+    var ray: Ray;
+    ray.origin = p;
+    ray.dir = dir;
+    ray.inv_dir = 1.0 / dir;
+    var stats = vec2<i32>(0, 0);
+    for (var i = 0; i < MAX_SKIPPED_SURFACES; i += 1) {
+        let hit = calculate_ray_collions(ray, &stats);
+        if !hit.hit || hit.entity == light {
+            return hit.hit == (light < scene.spheres + scene.meshes);
+        }
+        if (hit.material.visibility & VISIBILITY_SHADOW) == 0u {
+            return false;
+        }
+        ray.origin = hit.hit_point + dir * 1e-4;
+    }
+    return false;
+}
+
+// Direction from p to a point on the rectangle at `corner` spanned by the perpendicular edges
+// `ex` and `ey`, uniform over the solid angle it covers (Urena et al. 2013), with its solid angle
+// pdf in w. Falls back to a uniform point on the rectangle when it covers too little of the
+// sphere for the spherical construction to stay precise.
+fn sample_rect(p: vec3<f32>, corner: vec3<f32>, ex: vec3<f32>, ey: vec3<f32>, seed: ptr<function, u32>) -> vec4<f32> {
+    let pi = 3.1415926;
+    let ex_len = length(ex);
+    let ey_len = length(ey);
+    let x = ex / ex_len;
+    let y = ey / ey_len;
+    var z = cross(x, y);
+    // Local frame at p, flipped so the rectangle lies below it
+    let d = corner - p;
+    var z0 = dot(d, z);
+    if z0 > 0.0 {
+        z = -z;
+        z0 = -z0;
+    }
+    let x0 = dot(d, x);
+    let y0 = dot(d, y);
+    let x1 = x0 + ex_len;
+    let y1 = y0 + ey_len;
+    let v00 = vec3(x0, y0, z0);
+    let v01 = vec3(x0, y1, z0);
+    let v10 = vec3(x1, y0, z0);
+    let v11 = vec3(x1, y1, z0);
+    // Normals of the planes through p and each edge, and the angles between them
+    let n0 = normalize(cross(v00, v10));
+    let n1 = normalize(cross(v10, v11));
+    let n2 = normalize(cross(v11, v01));
+    let n3 = normalize(cross(v01, v00));
+    let g0 = acos(clamp(-dot(n0, n1), -1.0, 1.0));
+    let g1 = acos(clamp(-dot(n1, n2), -1.0, 1.0));
+    let g2 = acos(clamp(-dot(n2, n3), -1.0, 1.0));
+    let g3 = acos(clamp(-dot(n3, n0), -1.0, 1.0));
+    let k = 2.0 * pi - g2 - g3;
+    let solid_angle = g0 + g1 - k;
+    let u = rand(seed);
+    let v = rand(seed);
+    if !(solid_angle > RECT_MIN_SOLID_ANGLE) {
+        let to_light = corner + ex * u + ey * v - p;
+        let dst_sqr = dot(to_light, to_light);
+        let dir = to_light / sqrt(dst_sqr);
+        let cos_light = max(abs(dot(z, dir)), 1e-6);
+        return vec4(dir, dst_sqr / (ex_len * ey_len * cos_light));
+    }
+    // Invert the solid angle of the part of the rectangle left of xu, then go up the column
+    let b0 = n0.z;
+    let b1 = n2.z;
+    let au = u * solid_angle + k;
+    let fu = (cos(au) * b0 - b1) / sin(au);
+    let cu = clamp(sign(fu) / sqrt(fu * fu + b0 * b0), -1.0, 1.0);
+    let xu = clamp(-(cu * z0) / max(sqrt(1.0 - cu * cu), 1e-6), x0, x1);
+    let dd = sqrt(xu * xu + z0 * z0);
+    let h0 = y0 / sqrt(dd * dd + y0 * y0);
+    let h1 = y1 / sqrt(dd * dd + y1 * y1);
+    let hv = h0 + v * (h1 - h0);
+    let yv = select(y1, hv * dd / sqrt(1.0 - hv * hv), hv * hv < 1.0 - 1e-6);
+    return vec4(normalize(x * xu + y * yv + z * z0), 1.0 / solid_angle);
+}
+
+// Lambertian reflection of one sample on every emissive sphere and mesh, the light sampling half
+// of the direct lighting and Whitted integrators
+fn direct_light(hit: Hit, seed: ptr<function, u32>) -> vec4<f32> {
+    let pi = 3.1415926;
+    let p = hit.hit_point + hit.normal * 1e-4;
+    var total = vec4<f32>(0.0);
+    for (var i: u32 = 0u; i < scene.spheres; i += 1u) {
+        let sphere = spheres[i];
+        let radiance = sphere.material.emission_color * sphere.material.emission_strength;
+        let to_centre = sphere.position - p;
+        let dst_sqr = dot(to_centre, to_centre);
+        if sphere.material.emission_strength <= 0.0 || dst_sqr <= sphere.radius * sphere.radius {
+            continue;
+        }
+        // Uniform over the cone of directions the sphere covers
+        let cos_max = sqrt(1.0 - sphere.radius * sphere.radius / dst_sqr);
+        let cos_theta = 1.0 - rand(seed) * (1.0 - cos_max);
+        let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+        let phi = 2.0 * pi * rand(seed);
+        let w = normalize(to_centre);
+        let u = normalize(cross(select(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), abs(w.x) > 0.9), w));
+        let v = cross(w, u);
+        let dir = normalize(u * cos(phi) * sin_theta + v * sin(phi) * sin_theta + w * cos_theta);
+        let cos_surface = dot(hit.normal, dir);
+        if cos_surface <= 0.0 || !light_visible(p, dir, i) {
+            continue;
+        }
+        let pdf = 1.0 / (2.0 * pi * (1.0 - cos_max));
+        total += radiance * cos_surface / pdf;
+    }
+    for (var i: u32 = 0u; i < scene.meshes; i += 1u) {
+        let mesh = meshes[i];
+        if mesh.material.emission_strength <= 0.0 || mesh.triangles == 0u {
+            continue;
+        }
+        if mesh.rect_size.x > 0.0 {
+            // Rect lights lie in their model XZ plane around the origin, facing down -Y
+            let corner = (mesh.model_to_world * vec4(-0.5 * mesh.rect_size.x, 0.0, -0.5 * mesh.rect_size.y, 1.0)).xyz;
+            let ex = (mesh.model_to_world * vec4(mesh.rect_size.x, 0.0, 0.0, 0.0)).xyz;
+            let ey = (mesh.model_to_world * vec4(0.0, 0.0, mesh.rect_size.y, 0.0)).xyz;
+            let behind = dot(p - corner, cross(ex, ey)) <= 0.0;
+            if behind && (mesh.material.light_flags & LIGHT_SINGLE_SIDED) != 0u {
+                continue;
+            }
+            let sample = sample_rect(p, corner, ex, ey, seed);
+            let cos_surface = dot(hit.normal, sample.xyz);
+            if cos_surface <= 0.0 || !light_visible(p, sample.xyz, scene.spheres + i) {
+                continue;
+            }
+            let radiance = mesh.material.emission_color * mesh.material.emission_strength;
+            total += radiance * cos_surface / sample.w;
+            continue;
+        }
+        // Uniform triangle then uniform point on it, triangles are in model space
+        let tri = triangles[mesh.triangle_offset + min(u32(rand(seed) * f32(mesh.triangles)), mesh.triangles - 1u)];
+        let v1 = (mesh.model_to_world * vec4(tri.v1, 1.0)).xyz;
+        let v2 = (mesh.model_to_world * vec4(tri.v2, 1.0)).xyz;
+        let v3 = (mesh.model_to_world * vec4(tri.v3, 1.0)).xyz;
+        var a = rand(seed);
+        var b = rand(seed);
+        if a + b > 1.0 {
+            a = 1.0 - a;
+            b = 1.0 - b;
+        }
+        let point = v1 + (v2 - v1) * a + (v3 - v1) * b;
+        let area_normal = cross(v2 - v1, v3 - v1);
+        let area = length(area_normal) * 0.5;
+        let to_light = point - p;
+        let dst_sqr = dot(to_light, to_light);
+        let dir = to_light / sqrt(dst_sqr);
+        let cos_surface = dot(hit.normal, dir);
+        var cos_light = abs(dot(normalize(area_normal), dir));
+        if (mesh.material.light_flags & LIGHT_SINGLE_SIDED) != 0u {
+            // Only the front face emits, triangles wind counter clockwise
+            cos_light = max(-dot(normalize(area_normal), dir), 0.0);
+        }
+        if area <= 0.0 || cos_light <= 0.0 || cos_surface <= 0.0 || !light_visible(p, dir, scene.spheres + i) {
+            continue;
+        }
+        let pdf_area = 1.0 / (f32(mesh.triangles) * area);
+        let radiance = mesh.material.emission_color * mesh.material.emission_strength;
+        total += radiance * cos_surface * cos_light / (dst_sqr * pdf_area);
+    }
+    return albedo(hit) * total / pi;
+}
+
+// One bounce: emission at the first hit plus sampled lights, with a single extra ray for the sky
+fn trace_direct(incident_ray: Ray, seed: ptr<function, u32>) -> vec4<f32> {
+    var ray: Ray = incident_ray;
+    ray.dir = normalize(ray.dir);
+    ray.inv_dir = 1.0 / ray.dir;
+    var stats = vec2<i32>(0, 0);
+    interior_len = 0;
+    let hit = trace_visible(ray, select(0u, LIGHT_HIDDEN_FROM_CAMERA, ray.camera), select(VISIBILITY_BOUNCE, VISIBILITY_CAMERA, ray.camera), &stats, seed);
+    // Only the first segment is fogged, the rest of the light is gathered from the surface
+    var fog = vec4(1.0);
+    let haze = apply_fog(ray, select(FOG_MAX_DISTANCE, hit.dst, hit.hit), &fog);
+    if !hit.hit {
+        return haze + fog * select(vec4(0.0), get_environment_light(ray), skybox_enabled());
+    }
+    if hit.material.flag == MATERIAL_GLASS {
+        // Glass has no diffuse lobe, show whatever the one refracted or reflected ray sees
+        let transmittance = dielectric_bounce(&ray, hit, seed);
+        let next = trace_visible(ray, LIGHT_HIDDEN_FROM_SPECULAR, VISIBILITY_BOUNCE, &stats, seed);
+        if !next.hit {
+            return haze + fog * select(vec4(0.0), transmittance * get_environment_light(ray), skybox_enabled());
+        }
+        return haze + fog * transmittance * emission(next);
+    }
+    var light = emission(hit) + clamp_light(direct_light(hit, seed), 1);
+    if skybox_enabled() {
+        // Emitters are covered by light sampling, so this bounce only counts if it escapes
+        let bounce = sample_bounce(hit.hit_point, hit.normal, false, seed);
+        ray.dir = bounce.xyz;
+        ray.origin = hit.hit_point + hit.normal * 1e-4;
+        ray.inv_dir = 1.0 / ray.dir;
+        if bounce.w > 0.0 && light_visible(ray.origin, ray.dir, scene.spheres + scene.meshes) {
+            light += clamp_light(albedo(hit) * get_environment_light(ray) * 2.0 * dot(hit.normal, ray.dir) * bounce.w, 1);
+        }
+    }
+    return haze + fog * light;
+}
+
+// Whitted style: perfect mirror and refraction chains, diffuse surfaces only see sampled lights
+fn trace_whitted(incident_ray: Ray, seed: ptr<function, u32>) -> vec4<f32> {
+    var ray: Ray = incident_ray;
+    ray.dir = normalize(ray.dir);
+    ray.inv_dir = 1.0 / ray.dir;
+    var transmittance = vec4<f32>(1.0);
+    var light = vec4<f32>(0.0);
+    var stats = vec2<i32>(0, 0);
+    interior_len = 0;
+    for (var i = 0; i <= params.number_of_bounces; i += 1) {
+        // Every ray after the first left a mirror or glass surface
+        let hidden = select(LIGHT_HIDDEN_FROM_SPECULAR, select(0u, LIGHT_HIDDEN_FROM_CAMERA, ray.camera), i == 0);
+        let kind = select(VISIBILITY_BOUNCE, VISIBILITY_CAMERA, i == 0 && ray.camera);
+        let hit = trace_visible(ray, hidden, kind, &stats, seed);
+        light += apply_fog(ray, select(FOG_MAX_DISTANCE, hit.dst, hit.hit), &transmittance);
+        if !hit.hit {
+            if skybox_enabled() {
+                light += transmittance * get_environment_light(ray);
+            }
+            break;
+        }
+        light += transmittance * emission(hit);
+        if hit.material.flag == MATERIAL_GLASS {
+            transmittance *= dielectric_bounce(&ray, hit, seed);
+            continue;
+        }
+        // Only the smooth part of the specular lobe is kept as a perfect mirror
+        let mirror = hit.material.specular * hit.material.smoothness;
+        // Light sampled at the end of a mirror chain still only bounced once off a diffuse surface
+        light += clamp_light(transmittance * (1.0 - mirror) * direct_light(hit, seed), 1);
+        if mirror <= 0.0 {
+            break;
+        }
+        transmittance *= specular_tint(hit, ray.dir) * mirror;
+        advance_cone(&ray, hit.dst, 0.0);
+        ray.dir = reflect(ray.dir, hit.normal);
+        ray.origin = hit.hit_point + hit.normal * 1e-4;
+        ray.inv_dir = 1.0 / ray.dir;
+    }
+    return light;
+}
+
 fn frag(i: FragInput) -> vec4<f32> {
     let pixel_coord = i.pos;
-    var rng_state = u32(pixel_coord.y * i.size.x + pixel_coord.x) + u32(abs(params.frames)) * 719393u;
-    if params.debug_flag != 0 {
+    var rng_state = u32(pixel_coord.y * i.size.x + pixel_coord.x) + u32(abs(params.frames)) * 719393u + params.seed * 2654435761u;
+    if debug_view() && params.debug_flag < DEBUG_COST_NODES {
         return debug_trace(i);
     }
-    let uv = i.pos / (i.size - 1.0);
+    var pixel = i.pos;
+    if params.jitter != 0u {
+        pixel += vec2(rand(&rng_state), rand(&rng_state)) - 0.5;
+    }
+    let uv = pixel / (i.size - 1.0);
     let cam_origin = scene.camera.cam_to_world[3].xyz;
     let local_focus_point = vec3(uv - 0.5, 1.0) * scene.camera.view_params;
     let focus_point = (scene.camera.cam_to_world * vec4(local_focus_point, 1.0)).xyz;
     let cam_right = scene.camera.cam_to_world[0].xyz;
     let cam_up = scene.camera.cam_to_world[1].xyz;
+    // Angle between neighbouring pixels' rays, camera rays start as cones that wide
+    let pixel_spread = scene.camera.view_params.y / (scene.camera.view_params.z * (i.size.y - 1.0));
 
     var total_incoming_light = vec4<f32>(0.0);
     for (var j = 0; j < params.rays_per_pixel; j += 1) {
         let defocus_jitter = rand_in_unit_disk(&rng_state) * scene.camera.defocus_strength / i.size.x;
         var ray: Ray;
         ray.origin = cam_origin + cam_right * defocus_jitter.x + cam_up * defocus_jitter.y;
+        ray.camera = true;
+        ray.cone = vec2(0.0, pixel_spread);
 
         let diverge_jitter = rand_in_unit_disk(&rng_state) * scene.camera.diverge_strength / i.size.x;
         let jittered_focus_point = focus_point + cam_right * diverge_jitter.x + cam_up * diverge_jitter.y;
         ray.dir = normalize(jittered_focus_point - ray.origin);
 
-        total_incoming_light += trace(ray, &rng_state);
+        // The cost views measure the path tracer, whichever integrator is picked
+        switch select(params.integrator, 0u, debug_view()) {
+            case INTEGRATOR_DIRECT: {
+                total_incoming_light += trace_direct(ray, &rng_state);
+            }
+            case INTEGRATOR_WHITTED: {
+                total_incoming_light += trace_whitted(ray, &rng_state);
+            }
+            default: {
+                total_incoming_light += trace(ray, &rng_state);
+            }
+        }
+    }
+    if debug_view() {
+        return vec4(vec3<f32>(path_cost) / f32(params.rays_per_pixel), 1.0);
     }
     let color = total_incoming_light / f32(params.rays_per_pixel);
     return color;
@@ -540,7 +2251,7 @@ fn debug_trace(i: FragInput) -> vec4<f32> {
             var n: vec3<f32>;
 
             if hit.material.flag == MATERIAL_TEXTURE && hit.material.normal_index != -1{
-                let x = textureSampleLevel(textures[hit.material.normal_index], samplers[0], hit.uv, 0.0);
+                let x = sample_texture(hit.material.normal_index, hit.uv, hit.material.address_mode, hit.footprint);
                 n = 0.5 * (2.0 * vec3(x.r, x.g, x.b)-1.0) + 0.5;
             }else{
                 n= hit.normal * 0.5 + 0.5;
@@ -566,6 +2277,29 @@ fn debug_trace(i: FragInput) -> vec4<f32> {
             if !hit.hit {return vec4<f32>(0.0); }
             return vec4(hit.uv, 0.0, 1.0);
         }
+        case DEBUG_GEOMETRIC_NORMALS: {
+            if !hit.hit {return vec4<f32>(0.0); }
+            return vec4(hit.geometric_normal * 0.5 + 0.5, 1.0);
+        }
+        case DEBUG_BACKFACES: {
+            if !hit.hit {return vec4<f32>(0.0); }
+            // Shaded by facing ratio so the shape stays readable
+            let facing = 0.3 + 0.7 * abs(dot(ray.dir, hit.geometric_normal));
+            return select(vec4(0.1, 0.8, 0.1, 1.0), vec4(0.9, 0.1, 0.1, 1.0), hit.backface) * vec4(vec3(facing), 1.0);
+        }
+        case DEBUG_UV_CHECKER, DEBUG_UV_FALSE_COLOR: {
+            if !hit.hit {return vec4<f32>(0.0); }
+            let facing = 0.3 + 0.7 * abs(dot(ray.dir, hit.geometric_normal));
+            var color = vec3(0.5);
+            if hit.material.flag == MATERIAL_TEXTURE {
+                let view = select(UV_VIEW_FALSE_COLOR, UV_VIEW_CHECKER, params.debug_flag == DEBUG_UV_CHECKER);
+                color = uv_pattern(hit.uv, view);
+            } else if hit.entity >= scene.spheres && meshes[hit.entity - scene.spheres].uv_view != 0u {
+                // Already swapped for the mesh's own pattern
+                color = hit.material.color.rgb;
+            }
+            return vec4(color * facing, 1.0);
+        }
     default: {
             return vec4<f32>(1.0, 0.0, 1.0, 1.0);
         }
@@ -20,11 +20,20 @@ var<private> v_indices: array<u32, 6> = array<u32, 6>(
     0, 1, 2, 2, 3, 0
 );
 
+// Part of the image the viewport shows, see ViewWindow in renderer.rs
+struct View {
+    min: vec2<f32>,
+    max: vec2<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> view: View;
+
 @vertex
 fn vert(@builtin(vertex_index) i: u32) -> VertexOutput {
     var out: VertexOutput;
     out.position = v_vertices[v_indices[i]];
-    out.tex_coord = v_texcoords[v_indices[i]];
+    out.tex_coord = mix(view.min, view.max, v_texcoords[v_indices[i]]);
     return out;
 }
 
@@ -35,12 +44,170 @@ struct Params {
     rays_per_pixel: i32,
     skybox: i32,
     frames: u32,
+    accumulate: i32,
+    debug_flag: i32,
+    debug_scale: i32,
+    exposure: f32,
+    auto_exposure: i32,
+    tile_x: u32,
+    tile_y: u32,
+    processed: u32,
+    integrator: u32,
+    seed: u32,
+    caustic_guiding: f32,
+    caustic_casters: u32,
+    jitter: u32,
+    taa_blend: f32,
+    interleave: u32,
+    clamp_direct: f32,
+    clamp_indirect: f32,
+    despeckle: u32,
+    clay: u32,
+    _p1: u32,
+    _p2: u32,
+    _p3: u32,
 };
 
+struct Exposure {
+    average_luminance: f32,
+    exposure: f32,
+}
+
 @group(0) @binding(0)
 var<uniform> params: Params;
 @group(0) @binding(1)
 var texture: texture_2d<f32>;
+@group(0) @binding(2)
+var<storage, read> exposure: Exposure;
+@group(0) @binding(3)
+var processed: texture_2d<f32>;
+
+// Must match PICK_SCALE in picking.rs
+const PICK_SCALE: i32 = 2;
+const HOVER_COLOR: vec3<f32> = vec3(0.35, 0.7, 1.0);
+const SELECTED_COLOR: vec3<f32> = vec3(1.0, 0.6, 0.1);
+
+struct Pick {
+    width: u32,
+    height: u32,
+    samples_per_axis: u32,
+    _p1: u32,
+    hovered: i32,
+    selected: i32,
+}
+
+@group(0) @binding(4)
+var<uniform> pick: Pick;
+@group(0) @binding(5)
+var<storage, read> pick_ids: array<u32>;
+
+// Entity of the id buffer texel covering a render pixel, -1 for a miss or outside the buffer
+fn picked_entity(coords: vec2<i32>) -> i32 {
+    let p = coords / PICK_SCALE;
+    if p.x < 0 || p.y < 0 || p.x >= i32(pick.width) || p.y >= i32(pick.height) {
+        return -1;
+    }
+    return i32(pick_ids[u32(p.y) * pick.width + u32(p.x)]) - 1;
+}
+
+// Tints the hovered entity and outlines it and the selected entity where their ids stop
+fn highlight(color: vec3<f32>, coords: vec2<i32>) -> vec3<f32> {
+    let entity = picked_entity(coords);
+    var edge = false;
+    for (var i = 0; i < 4; i += 1) {
+        let offset = array(vec2(1, 0), vec2(-1, 0), vec2(0, 1), vec2(0, -1))[i] * PICK_SCALE;
+        edge = edge || picked_entity(coords + offset) != entity;
+    }
+    if entity == pick.selected && entity != -1 && edge {
+        return SELECTED_COLOR;
+    }
+    if entity == pick.hovered && entity != -1 {
+        if edge {
+            return HOVER_COLOR;
+        }
+        return mix(color, HOVER_COLOR, 0.15);
+    }
+    return color;
+}
+
+// Must match Despeckle in renderer.rs
+const DESPECKLE_MEDIAN: u32 = 1u;
+const DESPECKLE_FIREFLY_CLAMP: u32 = 2u;
+
+// Pairs to order, leaving the median of nine values in the middle, from Devillard's opt_med9
+const MEDIAN_NETWORK: array<vec2<u32>, 19> = array(
+    vec2(1u, 2u), vec2(4u, 5u), vec2(7u, 8u), vec2(0u, 1u), vec2(3u, 4u), vec2(6u, 7u),
+    vec2(1u, 2u), vec2(4u, 5u), vec2(7u, 8u), vec2(0u, 3u), vec2(5u, 8u), vec2(4u, 7u),
+    vec2(3u, 6u), vec2(1u, 4u), vec2(2u, 5u), vec2(4u, 7u), vec2(4u, 2u), vec2(6u, 4u),
+    vec2(4u, 2u),
+);
+
+// Must match DEBUG_COST_NODES in ray_tracer.wgsl, the triangle and bounce views follow it. The
+// ray tracer writes their counts to the red, green and blue channels
+const DEBUG_COST_NODES: i32 = 12;
+
+// Dark blue through green and yellow to red from 0 to 1, white past the top of the scale
+fn heatmap(t: f32) -> vec3<f32> {
+    if t > 1.0 {
+        return vec3(1.0);
+    }
+    var stops = array(
+        vec3(0.0, 0.0, 0.3), vec3(0.0, 0.4, 1.0), vec3(0.0, 0.9, 0.3), vec3(1.0, 0.9, 0.0),
+        vec3(1.0, 0.1, 0.0),
+    );
+    let x = max(t, 0.0) * 4.0;
+    let i = min(u32(x), 3u);
+    return mix(stops[i], stops[i + 1u], x - f32(i));
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Texel of the shown image, the processed one or the accumulation, clamped to the edges of the
+// render rather than of the textures, which can be larger
+fn shown(coords: vec2<i32>) -> vec4<f32> {
+    let clamped = clamp(coords, vec2(0), vec2<i32>(i32(params.width), i32(params.height)) - 1);
+    if params.processed != 0u {
+        return textureLoad(processed, clamped, 0);
+    }
+    return textureLoad(texture, clamped, 0);
+}
+
+// The shown texel at coords with the despeckle filter of params applied
+fn despeckled(coords: vec2<i32>) -> vec4<f32> {
+    let center = shown(coords);
+    var neighbours: array<vec3<f32>, 9>;
+    for (var i = 0; i < 9; i += 1) {
+        neighbours[i] = shown(coords + vec2(i % 3 - 1, i / 3 - 1)).rgb;
+    }
+    if params.despeckle == DESPECKLE_MEDIAN {
+        // Per channel, which can shift the hue of an edge slightly but never invents a colour
+        var network = MEDIAN_NETWORK;
+        for (var i = 0; i < 19; i += 1) {
+            let pair = network[i];
+            let low = min(neighbours[pair.x], neighbours[pair.y]);
+            neighbours[pair.y] = max(neighbours[pair.x], neighbours[pair.y]);
+            neighbours[pair.x] = low;
+        }
+        return vec4(neighbours[4], center.a);
+    }
+    if params.despeckle != DESPECKLE_FIREFLY_CLAMP {
+        return center;
+    }
+    // A firefly outshines everything around it, a highlight has neighbours nearly as bright
+    var brightest = 0.0;
+    for (var i = 0; i < 9; i += 1) {
+        if i != 4 {
+            brightest = max(brightest, luminance(neighbours[i]));
+        }
+    }
+    let lum = luminance(center.rgb);
+    if lum > brightest {
+        return vec4(center.rgb * (brightest / lum), center.a);
+    }
+    return center;
+}
 
 @fragment
 fn frag(i: VertexOutput) -> @location(0) vec4<f32> {
@@ -48,6 +215,24 @@ fn frag(i: VertexOutput) -> @location(0) vec4<f32> {
         i32(i.tex_coord.x * f32(params.width)),
         i32(i.tex_coord.y * f32(params.height))
     );
-    var color = textureLoad(texture, coords, 0);
-    return color;
+    // The processed image can be a different size to the accumulation
+    var source = coords;
+    if params.processed != 0u {
+        source = vec2<i32>(i.tex_coord * vec2<f32>(textureDimensions(processed)));
+    }
+    var color: vec4<f32>;
+    if params.despeckle != 0u {
+        color = despeckled(source);
+    } else {
+        color = shown(source);
+    }
+    if params.debug_flag >= DEBUG_COST_NODES {
+        let count = color[params.debug_flag - DEBUG_COST_NODES];
+        return vec4(highlight(heatmap(count / f32(params.debug_scale)), coords), 1.0);
+    }
+    var scale = exp2(params.exposure);
+    if params.auto_exposure != 0 {
+        scale *= exposure.exposure;
+    }
+    return vec4<f32>(highlight(color.rgb * scale, coords), color.a);
 }
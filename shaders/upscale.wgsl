@@ -0,0 +1,90 @@
+struct Params {
+    width: u32,
+    height: u32,
+    number_of_bounces: i32,
+    rays_per_pixel: i32,
+    skybox: i32,
+    frames: i32,
+    accumulate: i32,
+    debug_flag: i32,
+    debug_scale: i32,
+    exposure: f32,
+    auto_exposure: i32,
+    tile_x: u32,
+    tile_y: u32,
+    processed: u32,
+    integrator: u32,
+    seed: u32,
+    caustic_guiding: f32,
+    caustic_casters: u32,
+    jitter: u32,
+    taa_blend: f32,
+    interleave: u32,
+    clamp_direct: f32,
+    clamp_indirect: f32,
+    despeckle: u32,
+    clay: u32,
+    _p1: u32,
+    _p2: u32,
+    _p3: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var color_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var guide_texture: texture_2d<f32>;
+@group(0) @binding(3)
+var output: texture_storage_2d<rgba32float, write>;
+
+// Depth differences are relative to the centre pixel's distance
+const DEPTH_SIGMA: f32 = 0.05;
+const NORMAL_POWER: f32 = 16.0;
+
+fn guide_weight(centre: vec4<f32>, sample: vec4<f32>) -> f32 {
+    let centre_miss = centre.w < 0.0;
+    let sample_miss = sample.w < 0.0;
+    if centre_miss || sample_miss {
+        return select(0.0, 1.0, centre_miss && sample_miss);
+    }
+    let depth = exp(-abs(centre.w - sample.w) / (DEPTH_SIGMA * centre.w + 1e-4));
+    let normal = pow(max(dot(centre.xyz, sample.xyz), 0.0), NORMAL_POWER);
+    return depth * normal;
+}
+
+// Joint bilateral upsample of the low resolution frame, rejecting neighbours across depth and normal edges
+@compute
+@workgroup_size(8,8)
+fn resolve(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let full_size = textureDimensions(output);
+    if global_id.x >= full_size.x || global_id.y >= full_size.y {
+        return;
+    }
+    let low_size = vec2<i32>(i32(params.width), i32(params.height));
+    // Matches the uv = pos / (size - 1) mapping the ray tracer uses at both resolutions
+    let to_low = vec2<f32>(low_size - 1) / vec2<f32>(full_size - 1u);
+    let low_pos = vec2<f32>(global_id.xy) * to_low;
+    let base = vec2<i32>(floor(low_pos));
+    let f = fract(low_pos);
+    let centre = textureLoad(guide_texture, global_id.xy, 0);
+
+    var total = vec4<f32>(0.0);
+    var total_weight = 0.0;
+    var fallback = vec4<f32>(0.0);
+    for (var dy = 0; dy < 2; dy += 1) {
+        for (var dx = 0; dx < 2; dx += 1) {
+            let q = clamp(base + vec2(dx, dy), vec2(0), low_size - 1);
+            let bilinear = select(1.0 - f.x, f.x, dx == 1) * select(1.0 - f.y, f.y, dy == 1);
+            let color = textureLoad(color_texture, q, 0);
+            let guide_pos = min(vec2<u32>(round(vec2<f32>(q) / to_low)), full_size - 1u);
+            let weight = bilinear * guide_weight(centre, textureLoad(guide_texture, guide_pos, 0));
+            total += color * weight;
+            total_weight += weight;
+            fallback += color * bilinear;
+        }
+    }
+    // Every neighbour lies across an edge, e.g. thin geometry missed at low resolution
+    let result = select(fallback, total / total_weight, total_weight > 1e-4);
+    textureStore(output, global_id.xy, result);
+}
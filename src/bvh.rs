@@ -0,0 +1,836 @@
+//! Bounding volume hierarchy over a triangle soup, split by the surface area heuristic. It only
+//! depends on glam, rayon and bytemuck, so it can be used without the renderer by building with
+//! `default-features = false, features = ["bvh"]`.
+//!
+//! ```
+//! use glam::Vec3;
+//! use ray_tracer_2::bvh::{Aabb, Bvh, Quality, Ray};
+//!
+//! let soup = [
+//!     [Vec3::ZERO, Vec3::X, Vec3::Y],
+//!     [Vec3::Z * 2.0, Vec3::Z * 2.0 + Vec3::X, Vec3::Z * 2.0 + Vec3::Y],
+//! ];
+//! let bvh = Bvh::build(&soup, Quality::High);
+//! let hit = bvh.intersect(&Ray::new(Vec3::new(0.2, 0.2, 5.0), Vec3::NEG_Z), f32::INFINITY);
+//! assert_eq!(hit.map(|hit| hit.triangle), Some(1));
+//!
+//! let bytes = bvh.to_bytes();
+//! let bvh = Bvh::from_bytes(&bytes).unwrap();
+//! let near_origin = Aabb { min: Vec3::splat(-0.1), max: Vec3::splat(0.1) };
+//! assert_eq!(bvh.query(&near_origin), vec![0]);
+//! ```
+
+use glam::Vec3;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+/// Three corners, in the order that decides which side a hit's `u` and `v` are measured from
+pub type Triangle = [Vec3; 3];
+
+/// Deepest a leaf can be, also bounds the traversal stack
+pub const MAX_DEPTH: usize = 32;
+/// Most split positions `Quality::High` tries along the longest axis of a node
+const TEST_SPLITS: u32 = 50;
+/// Triangles whose edges are closer to parallel than this (sine of the angle between them) are dropped
+const DEGENERATE_SINE: f32 = 1e-6;
+/// Start of `Bvh::to_bytes`, the last byte is the format version
+const MAGIC: [u8; 4] = *b"BVH\x01";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_triangle(triangle: &Triangle) -> Self {
+        let [a, b, c] = *triangle;
+        Self {
+            min: a.min(b).min(c),
+            max: a.max(b).max(c),
+        }
+    }
+    pub fn half_area(&self) -> f32 {
+        let e = self.max - self.min;
+        e.x * e.y + e.y * e.z + e.x * e.z
+    }
+    pub fn grow_point(&mut self, p: Vec3) {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+    pub fn grow_aabb(&mut self, other: &Aabb) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+    /// False until at least one point has been added
+    pub fn is_valid(&self) -> bool {
+        self.min.cmple(self.max).all()
+    }
+    pub fn centre(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).length() * 0.5
+    }
+    /// Whether the boxes share any point, touching faces included
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.cmple(other.max).all() && other.min.cmple(self.max).all()
+    }
+    /// Distance along `ray` to where it enters the box, zero if it starts inside, or `None` when
+    /// it misses or only gets there after `max_t`.
+    pub fn intersect(&self, ray: &Ray, max_t: f32) -> Option<f32> {
+        let t1 = (self.min - ray.origin) * ray.inv_dir;
+        let t2 = (self.max - ray.origin) * ray.inv_dir;
+        let near = t1.min(t2).max_element().max(0.0);
+        // Widened by a few ulps so rays grazing an edge or corner still enter
+        let far = t1.max(t2).min_element().min(max_t) * (1.0 + 4.0 * f32::EPSILON);
+        (near <= far).then_some(near)
+    }
+}
+impl Default for Aabb {
+    fn default() -> Self {
+        Self {
+            min: Vec3::INFINITY,
+            max: Vec3::NEG_INFINITY,
+        }
+    }
+}
+
+/// A node as laid out for the shader. Leaves have a non-zero `count` of triangles starting at
+/// `first`, inner nodes point at their `left` and `right` children, always stored after them.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
+pub struct Node {
+    pub left: u32,
+    pub right: u32,
+    pub first: u32,
+    pub count: u32,
+    pub aabb_min: [f32; 3],
+    _p1: f32,
+    pub aabb_max: [f32; 3],
+    _p2: f32,
+}
+
+impl Node {
+    fn leaf(bounds: &Aabb, first: usize, count: usize) -> Self {
+        Self {
+            first: first as u32,
+            count: count as u32,
+            aabb_min: bounds.min.to_array(),
+            aabb_max: bounds.max.to_array(),
+            ..Default::default()
+        }
+    }
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+    pub fn bounds(&self) -> Aabb {
+        Aabb {
+            min: Vec3::from_array(self.aabb_min),
+            max: Vec3::from_array(self.aabb_max),
+        }
+    }
+    /// Surface area heuristic cost of testing every triangle in the node
+    pub fn cost(&self) -> f32 {
+        self.bounds().half_area() * self.count as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Quality {
+    /// Splits each node down the middle of its longest axis
+    Low,
+    /// Tries up to 50 splits along every axis and keeps the cheapest
+    #[default]
+    High,
+    /// A single leaf holding every triangle
+    Disabled,
+}
+
+/// Ray with its reciprocal direction precomputed for box tests.
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+    inv_dir: Vec3,
+}
+
+impl Ray {
+    /// `dir` needn't be normalised, hit distances are measured in multiples of it
+    pub fn new(origin: Vec3, dir: Vec3) -> Self {
+        Self {
+            origin,
+            dir,
+            inv_dir: dir.recip(),
+        }
+    }
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RayHit {
+    /// Index into the soup the BVH was built from
+    pub triangle: u32,
+    pub t: f32,
+    /// Barycentric weights of the second and third corners
+    pub u: f32,
+    pub v: f32,
+}
+
+/// Shape of a built tree, for judging a build's quality.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BvhStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub min_depth: usize,
+    pub max_depth: usize,
+    pub mean_depth: f32,
+    pub max_leaf_triangles: usize,
+    pub mean_leaf_triangles: f32,
+    /// Surface area heuristic cost relative to the root's area, lower traverses faster
+    pub sah_cost: f32,
+}
+
+/// Triangle while it is being sorted into leaves.
+#[derive(Debug, Copy, Clone)]
+struct BuildTriangle {
+    centroid: Vec3,
+    bounds: Aabb,
+    index: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    pub nodes: Vec<Node>,
+    /// The triangles in leaf order, the range `first..first + count` of a leaf are its triangles
+    pub triangles: Vec<Triangle>,
+    /// Soup index of each triangle in `triangles`
+    pub order: Vec<u32>,
+    /// Zero-area triangles left out of the tree
+    pub degenerate: u32,
+}
+
+impl Bvh {
+    /// Whether a triangle's edges are too close to parallel to be hit reliably.
+    pub fn is_degenerate(triangle: &Triangle) -> bool {
+        let [a, b, c] = *triangle;
+        let ab = b - a;
+        let ac = c - a;
+        let normal = ab.cross(ac);
+        if !normal.is_finite() {
+            return true;
+        }
+        let limit = ab.length_squared() * ac.length_squared() * DEGENERATE_SINE.powi(2);
+        normal.length_squared() <= limit
+    }
+    pub fn build(soup: &[Triangle], quality: Quality) -> Self {
+        Self::build_with(soup.len(), |i| soup[i], quality)
+    }
+    /// Builds over triangles given as three `positions` indices each, as meshes store them.
+    pub fn build_indexed(positions: &[Vec3], indices: &[u32], quality: Quality) -> Self {
+        Self::build_with(
+            indices.len() / 3,
+            |i| [0, 1, 2].map(|corner| positions[indices[i * 3 + corner] as usize]),
+            quality,
+        )
+    }
+    fn build_with(
+        count: usize,
+        triangle: impl Fn(usize) -> Triangle + Sync,
+        quality: Quality,
+    ) -> Self {
+        let mut build_triangles: Vec<BuildTriangle> = (0..count)
+            .into_par_iter()
+            .filter_map(|i| {
+                let corners = triangle(i);
+                if Self::is_degenerate(&corners) {
+                    return None;
+                }
+                Some(BuildTriangle {
+                    centroid: (corners[0] + corners[1] + corners[2]) / 3.0,
+                    bounds: Aabb::from_triangle(&corners),
+                    index: i as u32,
+                })
+            })
+            .collect();
+        let degenerate = (count - build_triangles.len()) as u32;
+        if build_triangles.is_empty() {
+            return Self {
+                degenerate,
+                ..Default::default()
+            };
+        }
+
+        let bounds = fit_bounds(&build_triangles);
+        let mut nodes = vec![Node::leaf(&bounds, 0, build_triangles.len())];
+        if quality != Quality::Disabled {
+            subdivide(&mut nodes, &mut build_triangles, 0, 0, quality);
+        }
+        let order: Vec<u32> = build_triangles.iter().map(|tri| tri.index).collect();
+        Self {
+            nodes,
+            triangles: order.iter().map(|&i| triangle(i as usize)).collect(),
+            order,
+            degenerate,
+        }
+    }
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map(Node::bounds).unwrap_or_default()
+    }
+    /// Closest two-sided hit along `ray` nearer than `max_t`.
+    pub fn intersect(&self, ray: &Ray, max_t: f32) -> Option<RayHit> {
+        let mut closest: Option<RayHit> = None;
+        let mut max_t = max_t;
+        let mut stack = Vec::with_capacity(MAX_DEPTH + 1);
+        if self
+            .nodes
+            .first()
+            .is_some_and(|root| root.bounds().intersect(ray, max_t).is_some())
+        {
+            stack.push(0u32);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if node.is_leaf() {
+                let range = node.first as usize..(node.first + node.count) as usize;
+                for (i, triangle) in self.triangles[range.clone()].iter().enumerate() {
+                    if let Some((t, u, v)) = intersect_triangle(ray, triangle)
+                        && t < max_t
+                    {
+                        max_t = t;
+                        closest = Some(RayHit {
+                            triangle: self.order[range.start + i],
+                            t,
+                            u,
+                            v,
+                        });
+                    }
+                }
+                continue;
+            }
+            let mut children = [node.left, node.right].map(|child| {
+                let t = self.nodes[child as usize].bounds().intersect(ray, max_t);
+                (child, t.unwrap_or(f32::INFINITY))
+            });
+            // The near child is pushed last so it is popped first and can shorten `max_t`
+            if children[1].1 > children[0].1 {
+                children.swap(0, 1);
+            }
+            for (child, t) in children {
+                if t.is_finite() {
+                    stack.push(child);
+                }
+            }
+        }
+        closest
+    }
+    /// Soup indices of every triangle whose bounding box overlaps `bounds`, in leaf order.
+    pub fn query(&self, bounds: &Aabb) -> Vec<u32> {
+        let mut found = vec![];
+        if self.nodes.is_empty() {
+            return found;
+        }
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if !node.bounds().overlaps(bounds) {
+                continue;
+            }
+            if node.is_leaf() {
+                let range = node.first as usize..(node.first + node.count) as usize;
+                found.extend(
+                    range
+                        .filter(|&i| Aabb::from_triangle(&self.triangles[i]).overlaps(bounds))
+                        .map(|i| self.order[i]),
+                );
+            } else {
+                stack.push(node.right);
+                stack.push(node.left);
+            }
+        }
+        found
+    }
+    pub fn stats(&self) -> BvhStats {
+        let Some(root) = self.nodes.first() else {
+            return BvhStats::default();
+        };
+        let root_area = root.bounds().half_area().max(f32::MIN_POSITIVE);
+        let mut stats = BvhStats {
+            nodes: self.nodes.len(),
+            min_depth: usize::MAX,
+            ..Default::default()
+        };
+        let (mut depth_sum, mut triangle_sum) = (0, 0);
+        let mut stack = vec![(0u32, 0usize)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if node.is_leaf() {
+                stats.leaves += 1;
+                stats.min_depth = stats.min_depth.min(depth);
+                stats.max_depth = stats.max_depth.max(depth);
+                stats.max_leaf_triangles = stats.max_leaf_triangles.max(node.count as usize);
+                depth_sum += depth;
+                triangle_sum += node.count as usize;
+                stats.sah_cost += node.cost() / root_area;
+            } else {
+                stats.sah_cost += node.bounds().half_area() / root_area;
+                stack.push((node.left, depth + 1));
+                stack.push((node.right, depth + 1));
+            }
+        }
+        stats.mean_depth = depth_sum as f32 / stats.leaves as f32;
+        stats.mean_leaf_triangles = triangle_sum as f32 / stats.leaves as f32;
+        stats
+    }
+    /// Little endian encoding read back by `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for count in [self.nodes.len(), self.triangles.len()] {
+            bytes.extend((count as u32).to_le_bytes());
+        }
+        bytes.extend(self.degenerate.to_le_bytes());
+        for node in &self.nodes {
+            for word in [node.left, node.right, node.first, node.count] {
+                bytes.extend(word.to_le_bytes());
+            }
+            for v in node.aabb_min.iter().chain(&node.aabb_max) {
+                bytes.extend(v.to_le_bytes());
+            }
+        }
+        for triangle in &self.triangles {
+            for v in triangle.iter().flat_map(|corner| corner.to_array()) {
+                bytes.extend(v.to_le_bytes());
+            }
+        }
+        for index in &self.order {
+            bytes.extend(index.to_le_bytes());
+        }
+        bytes
+    }
+    /// Reads a BVH written by `to_bytes`, rejecting anything truncated or unsafe to traverse.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let Some(body) = bytes.strip_prefix(&MAGIC) else {
+            return Err("not a serialized BVH".to_owned());
+        };
+        let mut words = body
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        let mut next = || words.next().ok_or("truncated BVH");
+        let (node_count, triangle_count) = (next()? as usize, next()? as usize);
+        let degenerate = next()?;
+        let expected = node_count
+            .checked_mul(10)
+            .zip(triangle_count.checked_mul(10))
+            .and_then(|(a, b)| a.checked_add(b))
+            .and_then(|n| n.checked_add(3))
+            .and_then(|n| n.checked_mul(4));
+        if expected != Some(body.len()) {
+            return Err(format!(
+                "{} bytes for {} nodes and {} triangles",
+                bytes.len(),
+                node_count,
+                triangle_count
+            ));
+        }
+        let mut nodes = Vec::with_capacity(node_count);
+        for _ in 0..node_count {
+            let [left, right, first, count] = [next()?, next()?, next()?, next()?];
+            let mut floats = [0.0; 6];
+            for v in &mut floats {
+                *v = f32::from_bits(next()?);
+            }
+            nodes.push(Node {
+                left,
+                right,
+                first,
+                count,
+                aabb_min: [floats[0], floats[1], floats[2]],
+                aabb_max: [floats[3], floats[4], floats[5]],
+                ..Default::default()
+            });
+        }
+        let mut triangles = Vec::with_capacity(triangle_count);
+        for _ in 0..triangle_count {
+            let mut corners = [Vec3::ZERO; 3];
+            for corner in &mut corners {
+                let [x, y, z] = [next()?, next()?, next()?].map(f32::from_bits);
+                *corner = Vec3::new(x, y, z);
+            }
+            triangles.push(corners);
+        }
+        let order = (0..triangle_count)
+            .map(|_| next())
+            .collect::<Result<Vec<u32>, _>>()?;
+        validate_nodes(&nodes, triangles.len())?;
+        Ok(Self {
+            nodes,
+            triangles,
+            order,
+            degenerate,
+        })
+    }
+}
+
+/// Checks nodes read from outside can be traversed safely: every child and triangle index is in
+/// range, children come after their parent so traversal always terminates, and no box is NaN.
+pub fn validate_nodes(nodes: &[Node], triangles: usize) -> Result<(), String> {
+    if nodes.is_empty() {
+        return match triangles {
+            0 => Ok(()),
+            _ => Err("no nodes".to_owned()),
+        };
+    }
+    for (i, node) in nodes.iter().enumerate() {
+        let in_bounds = match node.count {
+            0 => {
+                let child = |c: u32| (c as usize) > i && (c as usize) < nodes.len();
+                child(node.left) && child(node.right)
+            }
+            count => node
+                .first
+                .checked_add(count)
+                .is_some_and(|end| end as usize <= triangles),
+        };
+        if !in_bounds {
+            return Err(format!("node {} points out of range", i));
+        }
+        let finite = node
+            .aabb_min
+            .iter()
+            .chain(&node.aabb_max)
+            .all(|v| !v.is_nan());
+        if !finite {
+            return Err(format!("node {} has a NaN bounding box", i));
+        }
+    }
+    Ok(())
+}
+
+/// Möller–Trumbore, returning the distance and the barycentric weights of the second and third corners.
+fn intersect_triangle(ray: &Ray, triangle: &Triangle) -> Option<(f32, f32, f32)> {
+    let [a, b, c] = *triangle;
+    let ab = b - a;
+    let ac = c - a;
+    let p = ray.dir.cross(ac);
+    let det = ab.dot(p);
+    if det.abs() < f32::EPSILON * ab.length_squared().max(ac.length_squared()) {
+        return None;
+    }
+    let inv_det = det.recip();
+    let ao = ray.origin - a;
+    let u = ao.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = ao.cross(ab);
+    let v = ray.dir.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = ac.dot(q) * inv_det;
+    (t > 0.0).then_some((t, u, v))
+}
+
+fn fit_bounds(triangles: &[BuildTriangle]) -> Aabb {
+    let mut bounds = Aabb::default();
+    for tri in triangles {
+        bounds.grow_aabb(&tri.bounds);
+    }
+    bounds
+}
+
+/// Surface area heuristic cost of splitting `triangles` at `pos` along `axis`. Splits leaving
+/// one side empty are never worth it and cost infinity.
+fn evaluate_sah(triangles: &[BuildTriangle], axis: usize, pos: f32) -> f32 {
+    let mut left = Aabb::default();
+    let mut right = Aabb::default();
+    let (mut left_count, mut right_count) = (0, 0);
+    for tri in triangles {
+        if tri.centroid[axis] < pos {
+            left_count += 1;
+            left.grow_aabb(&tri.bounds);
+        } else {
+            right_count += 1;
+            right.grow_aabb(&tri.bounds);
+        }
+    }
+    if left_count == 0 || right_count == 0 {
+        return f32::INFINITY;
+    }
+    left_count as f32 * left.half_area() + right_count as f32 * right.half_area()
+}
+
+/// Cheapest split of a node's triangles as (cost, axis, position).
+fn find_best_split(
+    node: &Node,
+    triangles: &[BuildTriangle],
+    quality: Quality,
+) -> (f32, usize, f32) {
+    if triangles.len() <= 1 {
+        return (f32::INFINITY, 0, 0.0);
+    }
+    let bounds = node.bounds();
+    let size = bounds.max - bounds.min;
+    match quality {
+        Quality::Low => {
+            let axis = size.max_position();
+            let pos = bounds.min[axis] + size[axis] * 0.5;
+            (evaluate_sah(triangles, axis, pos), axis, pos)
+        }
+        Quality::High => {
+            let mut best = (f32::INFINITY, 0, 0.0);
+            let longest = size.max_element();
+            for axis in 0..3 {
+                if size[axis] == 0.0 {
+                    continue;
+                }
+                let tests = ((size[axis] / longest * TEST_SPLITS as f32).ceil() as u32)
+                    .clamp(1, TEST_SPLITS);
+                for i in 0..tests {
+                    let pos = bounds.min[axis] + size[axis] * (i + 1) as f32 / (tests + 1) as f32;
+                    let cost = evaluate_sah(triangles, axis, pos);
+                    if cost < best.0 {
+                        best = (cost, axis, pos);
+                    }
+                }
+            }
+            best
+        }
+        Quality::Disabled => (f32::INFINITY, 0, 0.0),
+    }
+}
+
+/// Splits the leaf at `index` while that lowers its cost, partitioning its triangles in place.
+fn subdivide(
+    nodes: &mut Vec<Node>,
+    triangles: &mut [BuildTriangle],
+    index: usize,
+    depth: usize,
+    quality: Quality,
+) {
+    let node = nodes[index];
+    let (first, count) = (node.first as usize, node.count as usize);
+    let (cost, axis, pos) = find_best_split(&node, &triangles[first..first + count], quality);
+    if cost >= node.cost() || depth >= MAX_DEPTH {
+        return;
+    }
+    let mut left_count = 0;
+    for i in first..first + count {
+        if triangles[i].centroid[axis] < pos {
+            triangles.swap(first + left_count, i);
+            left_count += 1;
+        }
+    }
+    let (left, right) = triangles[first..first + count].split_at(left_count);
+    let left_index = nodes.len();
+    nodes.push(Node::leaf(&fit_bounds(left), first, left.len()));
+    nodes.push(Node::leaf(
+        &fit_bounds(right),
+        first + left_count,
+        right.len(),
+    ));
+    let parent = &mut nodes[index];
+    parent.left = left_index as u32;
+    parent.right = left_index as u32 + 1;
+    parent.count = 0;
+    subdivide(nodes, triangles, left_index, depth + 1, quality);
+    subdivide(nodes, triangles, left_index + 1, depth + 1, quality);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic jumble of small triangles spread through a 10 unit cube
+    fn soup(count: usize) -> Vec<Triangle> {
+        let mut state = 0x9e3779b9u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32
+        };
+        let mut point = move || Vec3::new(random(), random(), random());
+        (0..count)
+            .map(|_| {
+                let centre = point() * 10.0;
+                [0, 1, 2].map(|_| centre + point() - 0.5)
+            })
+            .collect()
+    }
+
+    fn brute_force(soup: &[Triangle], ray: &Ray) -> Option<(u32, f32)> {
+        soup.iter()
+            .enumerate()
+            .filter(|(_, triangle)| !Bvh::is_degenerate(triangle))
+            .filter_map(|(i, triangle)| {
+                intersect_triangle(ray, triangle).map(|hit| (i as u32, hit.0))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    fn rays(count: usize) -> Vec<Ray> {
+        let targets = soup(count);
+        targets
+            .iter()
+            .enumerate()
+            .map(|(i, corners)| {
+                let origin = Vec3::new(-5.0 + i as f32 % 7.0, 15.0, -5.0);
+                Ray::new(origin, corners[0] - origin)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_soup_has_no_nodes() {
+        let bvh = Bvh::build(&[], Quality::High);
+        assert!(bvh.nodes.is_empty());
+        assert_eq!(
+            bvh.intersect(&Ray::new(Vec3::ZERO, Vec3::X), f32::INFINITY),
+            None
+        );
+        assert!(
+            bvh.query(&Aabb {
+                min: Vec3::splat(-1.0),
+                max: Vec3::ONE
+            })
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn degenerate_triangles_are_dropped() {
+        let mut triangles = soup(10);
+        triangles.push([Vec3::ZERO, Vec3::X, Vec3::X * 2.0]);
+        triangles.push([Vec3::ONE; 3]);
+        let bvh = Bvh::build(&triangles, Quality::High);
+        assert_eq!(bvh.degenerate, 2);
+        assert_eq!(bvh.triangles.len(), 10);
+        assert!(bvh.order.iter().all(|&i| i < 10));
+    }
+
+    #[test]
+    fn every_triangle_is_in_one_leaf() {
+        for quality in [Quality::Low, Quality::High, Quality::Disabled] {
+            let triangles = soup(500);
+            let bvh = Bvh::build(&triangles, quality);
+            let mut order = bvh.order.clone();
+            order.sort_unstable();
+            assert_eq!(order, (0..500).collect::<Vec<u32>>());
+            for (slot, &i) in bvh.order.iter().enumerate() {
+                assert_eq!(bvh.triangles[slot], triangles[i as usize]);
+            }
+            validate_nodes(&bvh.nodes, bvh.triangles.len()).unwrap();
+            let stats = bvh.stats();
+            assert_eq!(stats.leaves * 2 - 1, stats.nodes);
+            assert!(stats.max_depth <= MAX_DEPTH);
+        }
+        assert_eq!(Bvh::build(&soup(500), Quality::Disabled).nodes.len(), 1);
+    }
+
+    #[test]
+    fn node_bounds_contain_their_triangles() {
+        let bvh = Bvh::build(&soup(1000), Quality::High);
+        for node in bvh.nodes.iter().filter(|node| node.is_leaf()) {
+            let bounds = node.bounds();
+            for triangle in &bvh.triangles[node.first as usize..(node.first + node.count) as usize]
+            {
+                let triangle = Aabb::from_triangle(triangle);
+                assert!(
+                    bounds.min.cmple(triangle.min).all() && bounds.max.cmpge(triangle.max).all()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn intersect_matches_brute_force() {
+        let triangles = soup(2000);
+        for quality in [Quality::Low, Quality::High] {
+            let bvh = Bvh::build(&triangles, quality);
+            for ray in rays(300) {
+                let hit = bvh.intersect(&ray, f32::INFINITY);
+                let expected = brute_force(&triangles, &ray);
+                assert_eq!(hit.map(|hit| (hit.triangle, hit.t)), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn intersect_respects_max_t() {
+        let triangles = [[
+            Vec3::new(-1.0, -1.0, 2.0),
+            Vec3::new(1.0, -1.0, 2.0),
+            Vec3::new(0.0, 1.0, 2.0),
+        ]];
+        let bvh = Bvh::build(&triangles, Quality::High);
+        let ray = Ray::new(Vec3::ZERO, Vec3::Z);
+        let hit = bvh.intersect(&ray, f32::INFINITY).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-6);
+        assert!(
+            (ray.at(hit.t)
+                - (triangles[0][0] * (1.0 - hit.u - hit.v)
+                    + triangles[0][1] * hit.u
+                    + triangles[0][2] * hit.v))
+                .length()
+                < 1e-5
+        );
+        assert_eq!(bvh.intersect(&ray, 1.5), None);
+        assert_eq!(
+            bvh.intersect(&Ray::new(Vec3::ZERO, Vec3::NEG_Z), f32::INFINITY),
+            None
+        );
+    }
+
+    #[test]
+    fn query_matches_brute_force() {
+        let triangles = soup(2000);
+        let bvh = Bvh::build(&triangles, Quality::High);
+        for (i, centre) in soup(50).iter().map(|corners| corners[0]).enumerate() {
+            let bounds = Aabb {
+                min: centre - i as f32 * 0.05,
+                max: centre + i as f32 * 0.05,
+            };
+            let mut found = bvh.query(&bounds);
+            found.sort_unstable();
+            let expected: Vec<u32> = (0..triangles.len() as u32)
+                .filter(|&i| Aabb::from_triangle(&triangles[i as usize]).overlaps(&bounds))
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let bvh = Bvh::build(&soup(300), Quality::High);
+        let read = Bvh::from_bytes(&bvh.to_bytes()).unwrap();
+        assert_eq!(read.triangles, bvh.triangles);
+        assert_eq!(read.order, bvh.order);
+        assert_eq!(read.degenerate, bvh.degenerate);
+        assert_eq!(
+            bytemuck::cast_slice::<Node, u8>(&read.nodes),
+            bytemuck::cast_slice::<Node, u8>(&bvh.nodes)
+        );
+        let empty = Bvh::build(&[], Quality::High);
+        assert!(Bvh::from_bytes(&empty.to_bytes()).unwrap().nodes.is_empty());
+    }
+
+    #[test]
+    fn rejects_corrupt_bytes() {
+        let bvh = Bvh::build(&soup(300), Quality::High);
+        let bytes = bvh.to_bytes();
+        assert!(Bvh::from_bytes(&bytes[..bytes.len() - 4]).is_err());
+        assert!(Bvh::from_bytes(&bytes[1..]).is_err());
+        // Points the root's left child back at itself, which would never terminate
+        let mut looped = bytes.clone();
+        looped[16..20].copy_from_slice(&0u32.to_le_bytes());
+        assert!(Bvh::from_bytes(&looped).is_err());
+        // A leaf reaching past the last triangle
+        let leaf = bvh.nodes.iter().position(Node::is_leaf).unwrap();
+        let mut overrun = bytes;
+        let count = 16 + leaf * 40 + 12;
+        overrun[count..count + 4].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(Bvh::from_bytes(&overrun).is_err());
+    }
+}
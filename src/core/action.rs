@@ -0,0 +1,199 @@
+use winit::keyboard::KeyCode;
+
+/// Editor actions shared by the keybindings and the command palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    CommandPalette,
+    ReleaseMouse,
+    NextScene,
+    CycleDebugMode,
+    NextView,
+    SaveRender,
+    FrameSelection,
+    DropToFloor,
+    ToggleGrid,
+    ToggleThirds,
+    ToggleMagnifier,
+    ToggleLowRes,
+    ToggleSkybox,
+    ToggleAccumulate,
+    ToggleFullscreen,
+    RebuildBvh,
+    AutoTune,
+    NewTab,
+    ToggleTextureCache,
+    ExportCryptomatte,
+    ExportDenoiseFeatures,
+    QueueRender,
+    TakeSnapshot,
+    ToggleWalkthrough,
+}
+
+/// A key, optionally held with Ctrl.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shortcut {
+    pub key: KeyCode,
+    pub ctrl: bool,
+}
+
+impl Shortcut {
+    const fn key(key: KeyCode) -> Self {
+        Self { key, ctrl: false }
+    }
+    const fn ctrl(key: KeyCode) -> Self {
+        Self { key, ctrl: true }
+    }
+    pub fn label(&self) -> String {
+        let name = format!("{:?}", self.key);
+        let key = match self.key {
+            KeyCode::Escape => "Esc",
+            _ => name
+                .strip_prefix("Key")
+                .or(name.strip_prefix("Digit"))
+                .unwrap_or(&name),
+        };
+        match self.ctrl {
+            true => format!("Ctrl+{}", key),
+            false => key.to_owned(),
+        }
+    }
+}
+
+impl Action {
+    pub const ALL: [Action; 24] = [
+        Action::CommandPalette,
+        Action::ReleaseMouse,
+        Action::NextScene,
+        Action::CycleDebugMode,
+        Action::NextView,
+        Action::SaveRender,
+        Action::FrameSelection,
+        Action::DropToFloor,
+        Action::ToggleGrid,
+        Action::ToggleThirds,
+        Action::ToggleMagnifier,
+        Action::ToggleLowRes,
+        Action::ToggleSkybox,
+        Action::ToggleAccumulate,
+        Action::ToggleFullscreen,
+        Action::RebuildBvh,
+        Action::AutoTune,
+        Action::NewTab,
+        Action::ToggleTextureCache,
+        Action::ExportCryptomatte,
+        Action::ExportDenoiseFeatures,
+        Action::QueueRender,
+        Action::TakeSnapshot,
+        Action::ToggleWalkthrough,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::CommandPalette => "Command Palette",
+            Action::ReleaseMouse => "Release Mouse",
+            Action::NextScene => "Load Next Scene",
+            Action::CycleDebugMode => "Cycle Debug Mode",
+            Action::NextView => "Next Camera View",
+            Action::SaveRender => "Save Render",
+            Action::FrameSelection => "Frame Selection",
+            Action::DropToFloor => "Drop Selection to Floor",
+            Action::ToggleGrid => "Toggle Grid",
+            Action::ToggleThirds => "Toggle Rule of Thirds",
+            Action::ToggleMagnifier => "Toggle Magnifier",
+            Action::ToggleLowRes => "Toggle Low Resolution",
+            Action::ToggleSkybox => "Toggle Skybox",
+            Action::ToggleAccumulate => "Toggle Accumulation",
+            Action::ToggleFullscreen => "Toggle Fullscreen",
+            Action::RebuildBvh => "Rebuild BVH",
+            Action::AutoTune => "Auto Tune Workgroups",
+            Action::NewTab => "New Tab",
+            Action::ToggleTextureCache => "Toggle Texture Cache",
+            Action::ExportCryptomatte => "Export Cryptomatte",
+            Action::ExportDenoiseFeatures => "Export Denoiser Features",
+            Action::QueueRender => "Add Render to Queue",
+            Action::TakeSnapshot => "Take Snapshot",
+            Action::ToggleWalkthrough => "Toggle Walkthrough",
+        }
+    }
+
+    /// Key that runs the action, actions without one are only reachable from the palette or UI.
+    pub fn shortcut(self) -> Option<Shortcut> {
+        match self {
+            Action::CommandPalette => Some(Shortcut::ctrl(KeyCode::KeyP)),
+            Action::ReleaseMouse => Some(Shortcut::key(KeyCode::Escape)),
+            Action::NextScene => Some(Shortcut::key(KeyCode::KeyQ)),
+            Action::CycleDebugMode => Some(Shortcut::key(KeyCode::KeyE)),
+            Action::NextView => Some(Shortcut::key(KeyCode::KeyC)),
+            Action::SaveRender => Some(Shortcut::key(KeyCode::KeyP)),
+            // Falls back to toggling fullscreen when nothing is selected
+            Action::FrameSelection => Some(Shortcut::key(KeyCode::KeyF)),
+            Action::ToggleGrid => Some(Shortcut::key(KeyCode::KeyG)),
+            Action::ToggleMagnifier => Some(Shortcut::key(KeyCode::KeyM)),
+            Action::ToggleLowRes => Some(Shortcut::key(KeyCode::KeyR)),
+            Action::ToggleSkybox => Some(Shortcut::key(KeyCode::Digit1)),
+            Action::ToggleAccumulate => Some(Shortcut::key(KeyCode::Digit2)),
+            Action::ToggleWalkthrough => Some(Shortcut::key(KeyCode::KeyV)),
+            _ => None,
+        }
+    }
+
+    pub fn from_key(key: KeyCode, ctrl: bool) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|a| a.shortcut() == Some(Shortcut { key, ctrl }))
+    }
+}
+
+/// Fuzzy search over `Action::ALL`, opened with Ctrl+P.
+#[derive(Default)]
+pub struct CommandPalette {
+    pub open: bool,
+    pub query: String,
+    /// Index into the current matches, moved with the arrow keys
+    pub selected: usize,
+    /// Set by the palette window, run by the app at the start of the next update
+    pub run_requested: Option<Action>,
+}
+
+impl CommandPalette {
+    pub fn show(&mut self) {
+        if !self.open {
+            self.open = true;
+            self.query.clear();
+            self.selected = 0;
+        }
+    }
+
+    /// Actions matching the query, best first. An empty query lists everything in registry order.
+    pub fn matches(&self) -> Vec<Action> {
+        let mut scored: Vec<(i32, Action)> = Action::ALL
+            .into_iter()
+            .filter_map(|a| fuzzy_score(&self.query, a.name()).map(|s| (s, a)))
+            .collect();
+        // Stable, so ties keep registry order
+        scored.sort_by_key(|(score, _)| -score);
+        scored.into_iter().map(|(_, a)| a).collect()
+    }
+}
+
+/// Scores `text` if every character of `query` appears in it in order, ignoring case and spaces.
+/// Consecutive characters and matches at the start of a word score higher.
+fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last: Option<usize> = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let i = next + text[next..].iter().position(|&c| c == q)?;
+        score += 1;
+        if i == 0 || text[i - 1] == ' ' {
+            score += 3;
+        }
+        if last.is_some_and(|l| l + 1 == i) {
+            score += 2;
+        }
+        last = Some(i);
+        next = i + 1;
+    }
+    Some(score)
+}
@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont, point};
+use glam::{EulerRot, Vec3};
+use image::RgbaImage;
+
+use crate::core::app::Params;
+use crate::scene::{camera::Camera, scene::SceneName};
+
+const FONT_SIZE: f32 = 18.0;
+const PADDING: u32 = 8;
+
+/// Render settings burned into the corner of an exported image.
+pub struct RenderAnnotation {
+    pub scene: SceneName,
+    pub samples_per_pixel: i32,
+    pub bounces: i32,
+    pub render_time: Duration,
+    pub camera_pos: Vec3,
+    /// Yaw and pitch in degrees
+    pub camera_angles: (f32, f32),
+    pub fov: f32,
+}
+
+impl RenderAnnotation {
+    pub fn new(scene: SceneName, params: &Params, camera: &Camera, render_time: Duration) -> Self {
+        let (yaw, pitch, _) = camera.transform.rot.to_euler(EulerRot::YXZ);
+        Self {
+            scene,
+            samples_per_pixel: params.samples(),
+            bounces: params.number_of_bounces,
+            render_time,
+            camera_pos: camera.transform.pos,
+            camera_angles: (yaw.to_degrees(), pitch.to_degrees()),
+            fov: camera.fov,
+        }
+    }
+    pub fn lines(&self) -> Vec<String> {
+        vec![
+            format!("{:?}", self.scene),
+            format!(
+                "{} spp  {} bounces  {:.1}s",
+                self.samples_per_pixel,
+                self.bounces,
+                self.render_time.as_secs_f32()
+            ),
+            format!(
+                "pos ({:.2}, {:.2}, {:.2})  yaw {:.1}  pitch {:.1}  fov {:.0}",
+                self.camera_pos.x,
+                self.camera_pos.y,
+                self.camera_pos.z,
+                self.camera_angles.0,
+                self.camera_angles.1,
+                self.fov
+            ),
+        ]
+    }
+    /// Draws the annotation into a darkened strip in the bottom left corner of `image`.
+    pub fn burn_in(&self, image: &mut RgbaImage) {
+        let fonts = egui::FontDefinitions::default();
+        let Some(font) = fonts
+            .font_data
+            .get("Hack")
+            .and_then(|data| FontRef::try_from_slice(&data.font).ok())
+        else {
+            log::warn!("Failed to load annotation font");
+            return;
+        };
+        let font = font.as_scaled(PxScale::from(FONT_SIZE));
+        let lines = self.lines();
+
+        let line_height = (font.height() + font.line_gap()).ceil() as u32;
+        let text_width = lines
+            .iter()
+            .map(|line| {
+                line.chars()
+                    .map(|c| font.h_advance(font.glyph_id(c)))
+                    .sum::<f32>()
+            })
+            .fold(0.0, f32::max)
+            .ceil() as u32;
+        let strip_width = (text_width + PADDING * 2).min(image.width());
+        let strip_height = (line_height * lines.len() as u32 + PADDING * 2).min(image.height());
+        let strip_top = image.height() - strip_height;
+
+        for y in strip_top..image.height() {
+            for x in 0..strip_width {
+                let pixel = image.get_pixel_mut(x, y);
+                for c in 0..3 {
+                    pixel[c] = (pixel[c] as f32 * 0.35) as u8;
+                }
+            }
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            let line_top = strip_top as f32 + PADDING as f32 + i as f32 * line_height as f32;
+            let mut caret = point(PADDING as f32, line_top + font.ascent());
+            for c in line.chars() {
+                let id = font.glyph_id(c);
+                let glyph = id.with_scale_and_position(font.scale(), caret);
+                caret.x += font.h_advance(id);
+                let Some(outline) = font.outline_glyph(glyph) else {
+                    continue;
+                };
+                let bounds = outline.px_bounds();
+                outline.draw(|gx, gy, coverage| {
+                    let x = bounds.min.x as i32 + gx as i32;
+                    let y = bounds.min.y as i32 + gy as i32;
+                    if x < 0 || y < 0 || x as u32 >= image.width() || y as u32 >= image.height() {
+                        return;
+                    }
+                    let pixel = image.get_pixel_mut(x as u32, y as u32);
+                    for c in 0..3 {
+                        let value = pixel[c] as f32;
+                        pixel[c] = (value + (255.0 - value) * coverage) as u8;
+                    }
+                });
+            }
+        }
+    }
+}
@@ -1,27 +1,37 @@
 use std::{
-    sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
-    },
+    path::Path,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
-use egui_wgpu::wgpu::{
-    self, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, Origin3d,
-    TexelCopyBufferInfo, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect,
-};
-use image::ImageBuffer;
+use egui_wgpu::wgpu;
 use winit::{
     application::ApplicationHandler,
     dpi::PhysicalSize,
     event::{DeviceEvent, KeyEvent, WindowEvent},
-    keyboard::{KeyCode, PhysicalKey},
+    keyboard::{ModifiersState, PhysicalKey},
     window::{Fullscreen, Window},
 };
 
 use crate::{
-    core::engine::{Engine, RENDER_SIZE},
-    rendering::{egui::UiContext, ray_tracer::DebugMode},
+    core::{
+        action::Action,
+        annotation::RenderAnnotation,
+        crash,
+        engine::{Engine, FrameEvent, GpuError, RENDER_SIZE},
+        gamepad::SPEED_STEP,
+        settings,
+        time_limit::TimeLimit,
+    },
+    rendering::{
+        cpu_tracer::CpuTracer,
+        egui::UiContext,
+        frame_graph::FrameResource,
+        ray_tracer::{ClayMode, DebugMode, Integrator},
+        readback::{read_render_rgba8, read_render_rgba32f},
+        renderer::Despeckle,
+    },
+    scene::scene::SceneName,
 };
 
 #[repr(C)]
@@ -36,10 +46,80 @@ pub struct Params {
     pub accumulate: i32,
     pub debug_flag: i32,
     pub debug_scale: i32,
-    pub _p1: [f32; 3],
+    /// Exposure compensation in stops, applied on display and export
+    pub exposure: f32,
+    pub auto_exposure: i32,
+    /// Pixel offset of the dispatch, non-zero only when rendering a single tile
+    pub tile_x: u32,
+    pub tile_y: u32,
+    /// Display the post processed image, or the guided upscale of a moving frame, instead of the accumulation texture
+    pub processed: u32,
+    /// `Integrator` used by the main pass
+    pub integrator: u32,
+    /// Offsets every pixel's random sequence, set from a `SeedSchedule`
+    pub seed: u32,
+    /// Share of diffuse bounces aimed at glass spheres to find caustics, 0 turns it off
+    pub caustic_guiding: f32,
+    /// Most glass spheres guided towards, each one adds to the cost of every diffuse bounce
+    pub caustic_casters: u32,
+    /// Spreads primary rays over their pixel instead of one fixed point, anti-aliasing the accumulation
+    pub jitter: u32,
+    /// Weight of the new sample when a moving frame is blended over the last one reprojected to
+    /// the new camera, 0 turns reprojection off
+    pub taa_blend: f32,
+    /// While accumulating, each frame after the first traces one pixel in this many, interleaved
+    /// so the whole image refreshes sooner at the same cost per sample. 1 traces every pixel
+    pub interleave: u32,
+    /// Brightest a path's light from its first bounce may be, keeping its hue, 0 turns it off
+    pub clamp_direct: f32,
+    /// Brightest a path's light from any later bounce may be, clamped harder than direct light to
+    /// tame fireflies without dulling highlights. 0 turns it off
+    pub clamp_indirect: f32,
+    /// `Despeckle` filter applied to the shown image only, never to the accumulation or exports
+    pub despeckle: u32,
+    /// `ClayMode` swapping every material but the lights for matte grey
+    pub clay: u32,
+    pub _p1: [u32; 3],
+}
+
+/// What a change to `Params` affects, so settings that only change how the accumulated image is
+/// shown can be edited without throwing away a converged render.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamsChange {
+    None,
+    /// Exposure and the like, applied over the same accumulated samples
+    Display,
+    /// The traced image itself, accumulation has to start again
+    Accumulation,
 }
 
 impl Params {
+    /// How `self` differs from `old`. A `reset_frame` since counts as an accumulation change.
+    pub fn change_from(&self, old: &Params) -> ParamsChange {
+        if self == old {
+            return ParamsChange::None;
+        }
+        let harmless = Params {
+            exposure: old.exposure,
+            auto_exposure: old.auto_exposure,
+            processed: old.processed,
+            despeckle: old.despeckle,
+            // Turned off, every frame starts again anyway, turned on it carries on from this one
+            accumulate: old.accumulate,
+            // Only blends moving frames, which are never accumulated
+            taa_blend: old.taa_blend,
+            // Only read by the debug views
+            debug_scale: match self.debug_flag {
+                0 => old.debug_scale,
+                _ => self.debug_scale,
+            },
+            ..*self
+        };
+        match harmless == *old {
+            true => ParamsChange::Display,
+            false => ParamsChange::Accumulation,
+        }
+    }
     pub fn update(&mut self, is_moving: bool) -> bool {
         if is_moving {
             self.reset_frame();
@@ -55,6 +135,25 @@ impl Params {
     pub fn reset_frame(&mut self) {
         self.frames = -1;
     }
+    /// Whether this frame traces only some pixels, see `interleave`.
+    pub fn interleaving(&self) -> bool {
+        self.frames >= 1 && self.interleave > 1
+    }
+    /// Columns of pixels the main pass traces this frame.
+    pub fn traced_width(&self) -> u32 {
+        match self.interleaving() {
+            true => self.width.div_ceil(self.interleave),
+            false => self.width,
+        }
+    }
+    /// Frames every pixel has accumulated, an interleaved frame only counts once it has been
+    /// traced at all of them.
+    pub fn passes(&self) -> i32 {
+        1 + self.frames.max(0) / self.interleave.max(1) as i32
+    }
+    pub fn samples(&self) -> i32 {
+        self.passes() * self.rays_per_pixel
+    }
     pub fn for_buffer(&self, is_moving: bool) -> Self {
         let mut params = self.clone();
         params.number_of_bounces = if is_moving { 1 } else { self.number_of_bounces };
@@ -85,15 +184,69 @@ impl Default for Params {
             accumulate: 1,
             debug_flag: 0,
             debug_scale: 0,
-            _p1: [0.0; 3],
+            exposure: 0.0,
+            auto_exposure: 0,
+            tile_x: 0,
+            tile_y: 0,
+            processed: 0,
+            integrator: Integrator::PathTracing as u32,
+            seed: 0,
+            caustic_guiding: 0.0,
+            caustic_casters: 8,
+            jitter: 0,
+            taa_blend: 0.0,
+            interleave: 1,
+            clamp_direct: 0.0,
+            clamp_indirect: 0.0,
+            despeckle: Despeckle::Off as u32,
+            clay: ClayMode::Off as u32,
+            _p1: [0; 3],
+        }
+    }
+}
+/// How `Params::seed` changes between animation frames. Accumulated samples always differ, this
+/// only decides whether separate renders reuse the same noise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeedSchedule {
+    /// Always the same seed, so re-renders of a still come out identical
+    Stable,
+    /// Follows the timeline frame, so an animation's noise changes every frame instead of
+    /// staying fixed to the screen while stills keep a stable seed. Each frame's noise depends
+    /// on nothing else, so a bad frame of a sequence can be rendered again without a seam
+    PerFrame,
+    /// A user chosen seed
+    Fixed(u32),
+}
+
+impl SeedSchedule {
+    pub fn seed(self, frame: u32) -> u32 {
+        match self {
+            SeedSchedule::Stable => 0,
+            SeedSchedule::PerFrame => frame,
+            SeedSchedule::Fixed(seed) => seed,
         }
     }
 }
-pub const DEBUG_MODES: u32 = DebugMode::NodesAndTriangles as u32 + 1;
+
+/// Highest `Params::debug_flag`, the modes are numbered from 1
+pub const DEBUG_MODES: u32 = DebugMode::ALL.len() as u32;
+/// Time the CPU tracer gets each frame, the rest of the frame is left to the editor
+const CPU_FRAME_BUDGET: Duration = Duration::from_millis(12);
 
 pub struct App {
     engine: Option<Engine>,
     window: Option<Arc<Window>>,
+    modifiers: ModifiersState,
+    /// Render on the CPU from the start, for `--cpu`
+    pub start_on_cpu: bool,
+    /// Minutes to render before saving and closing, for `--render-for`
+    pub render_for: Option<f32>,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl App {
@@ -101,27 +254,99 @@ impl App {
         Self {
             window: None,
             engine: None,
+            modifiers: ModifiersState::empty(),
+            start_on_cpu: false,
+            render_for: None,
         }
     }
-    pub async fn set_window(&mut self, window: Window) {
+    pub async fn set_window(&mut self, window: Window) -> Result<(), GpuError> {
         let window = Arc::new(window);
         let initial_width = 800;
         let initial_height = 600;
 
         let _ = window.request_inner_size(PhysicalSize::new(initial_width, initial_height));
+        self.window.get_or_insert(window.clone());
 
-        let engine = Engine::new(window.clone(), RENDER_SIZE.0, RENDER_SIZE.1).await;
-
-        self.window.get_or_insert(window);
+        let scene = SceneName::CornellBox;
+        let mut engine =
+            match Engine::new(window.clone(), RENDER_SIZE.0, RENDER_SIZE.1, scene, false).await {
+                Ok(engine) => engine,
+                Err(error) => {
+                    // Without a usable GPU the window is drawn in software and traced on the CPU
+                    log::error!("{}, falling back to the CPU", error);
+                    let mut engine =
+                        Engine::new(window, RENDER_SIZE.0, RENDER_SIZE.1, scene, true).await?;
+                    engine.cpu = Some(CpuTracer::default());
+                    engine.tmp.error = Some(format!(
+                        "{}.\nThe GPU couldn't be used, so the scene is rendered on the CPU.",
+                        error
+                    ));
+                    engine
+                }
+            };
+        if self.start_on_cpu {
+            engine.cpu = Some(CpuTracer::default());
+        }
+        if let Some(minutes) = self.render_for {
+            engine.tmp.time_limit = TimeLimit::unattended(minutes);
+        }
         self.engine.get_or_insert(engine);
+        Ok(())
+    }
+    /// Rebuilds the engine on a fresh device after a GPU failure and tells the user what happened.
+    /// Only fails if no device can be created at all.
+    fn recover(&mut self, error: GpuError) -> Result<(), GpuError> {
+        log::error!("{}", error);
+        let Some(window) = self.window.clone() else {
+            return Err(error);
+        };
+        let report = crash::write_report(&error.to_string());
+        // The scene, view and settings come back as they were. An unattended render still has to
+        // stop and save after the restart
+        let carried = self.engine.as_ref().map(|engine| {
+            (
+                engine.scene_manager.selected_scene,
+                engine.scene_manager.scene.camera,
+                engine.params,
+                engine.tmp.time_limit,
+                engine.cpu.is_some(),
+            )
+        });
+        // The old device has to go before a new one is requested
+        self.engine = None;
+        let scene = carried.map_or(SceneName::CornellBox, |(scene, ..)| scene);
+        let mut engine = pollster::block_on(Engine::new(
+            window,
+            RENDER_SIZE.0,
+            RENDER_SIZE.1,
+            scene,
+            false,
+        ))?;
+        let mut message = format!(
+            "{}.\nThe renderer was restarted and the scene reloaded.",
+            error
+        );
+        if let Some(report) = report {
+            message += &format!("\nA crash report was written to {}", report.display());
+        }
+        engine.tmp.error = Some(message);
+        if let Some((_, camera, mut params, time_limit, cpu)) = carried {
+            params.reset_frame();
+            engine.restore = Some((camera, params));
+            engine.tmp.time_limit = time_limit;
+            if cpu {
+                engine.cpu = Some(CpuTracer::default());
+            }
+        }
+        self.engine = Some(engine);
+        Ok(())
     }
     fn handle_resized(&mut self, width: u32, height: u32) {
-        if width > 0 && height > 0 {
-            self.engine
-                .as_mut()
-                .unwrap()
-                .resources
-                .resize_surface(width, height);
+        if width > 0
+            && height > 0
+            && let Some(engine) = self.engine.as_mut()
+        {
+            engine.resources.resize_surface(width, height);
         }
     }
 
@@ -129,43 +354,327 @@ impl App {
         let Some(engine) = self.engine.as_mut() else {
             return;
         };
-        let timing = &mut engine.timing;
-        timing.update(dt);
+        engine.timing.update(dt);
+        crash::set_params(&engine.params);
+        if let Some(report) = crash::take_written() {
+            engine.tmp.error = Some(format!(
+                "Something went wrong but the renderer kept going.\nA crash report was written to {}",
+                report.display()
+            ));
+        }
+        if let Some(action) = engine.palette.run_requested.take() {
+            App::run_action(engine, self.window.as_ref().unwrap(), action);
+        }
+        engine.auto_exposure.poll_readback();
+        engine.picker.poll_readback();
+        let exposure = engine.display_exposure();
+        engine.magnifier.update(
+            &engine.resources.device,
+            &engine.resources.queue,
+            &engine.resources.target.texture,
+            (engine.params.width, engine.params.height),
+            engine.picker.cursor,
+            exposure,
+            engine.params.frames,
+        );
 
-        if let Ok(scene) = engine.scene_manager.rx_loaded.try_recv() {
-            engine.scene_manager.scene = scene;
-            engine
-                .ray_tracer
-                .load_scene_gpu_resources(&engine.scene_manager.scene);
-            timing.reset();
+        if let Ok((tab_id, scene)) = engine.scene_manager.rx_loaded.try_recv() {
+            engine.receive_scene(tab_id, scene);
+        }
+        while let Ok((tab_id, chunk)) = engine.scene_manager.rx_chunks.try_recv() {
+            engine.receive_chunk(tab_id, chunk);
+        }
+        if engine.scene_manager.update_bvh() {
+            engine.timing.mark(FrameEvent::BvhRebuild);
+        }
+        if engine.tabs.open_requested {
+            engine.tabs.open_requested = false;
+            engine.open_tab();
+        }
+        if let Some(index) = engine.tabs.close_requested.take() {
+            engine.close_tab(index);
+        }
+        if engine.tabs.selected != engine.tabs.active {
+            engine.switch_tab(engine.tabs.selected);
+        }
+        if let Some(path) = engine.scene_manager.texture_reload_requested.take() {
+            engine.reload_texture(&path);
+        }
+        engine.reload_changed_assets();
+        if engine.settings.apply_requested {
+            engine.settings.apply_requested = false;
+            engine.resources.scale_factor = engine.settings.scale;
+            engine.settings.apply(engine.egui.context());
+            if let Err(e) = engine.settings.save(Path::new(settings::SETTINGS_FILE)) {
+                log::error!("Failed to save {}: {}", settings::SETTINGS_FILE, e);
+            }
+        }
+        if let Some(index) = engine.lightmap.bake_requested.take()
+            && let Some(mesh) = engine.scene_manager.scene.meshes.get(index)
+        {
+            let label = mesh.label.clone().unwrap_or(format!("mesh_{}", index));
+            engine.lightmap.start(mesh, label, &engine.jobs);
+        }
+        engine.lightmap.poll();
+        if let Some(format) = engine.lightmap.export_requested.take()
+            && let Err(e) = engine.lightmap.export(format)
+        {
+            log::error!("Failed to export lightmap: {}", e);
+        }
+        if engine.cryptomatte.export_requested {
+            engine.cryptomatte.export_requested = false;
+            match engine.cryptomatte.export(
+                &engine.ray_tracer,
+                &engine.scene_manager.scene,
+                engine.scene_manager.selected_scene,
+                &engine.resources.target.texture,
+                engine.params.width.min(RENDER_SIZE.0),
+                engine.params.height.min(RENDER_SIZE.1),
+            ) {
+                Ok(path) => log::info!("Saved cryptomatte to {}", path.display()),
+                Err(e) => log::error!("Failed to export cryptomatte: {}", e),
+            }
+        }
+        if engine.denoise_features.export_requested {
+            engine.denoise_features.export_requested = false;
+            match engine.denoise_features.export(
+                &engine.ray_tracer,
+                engine.scene_manager.selected_scene,
+                &engine.resources.target.texture,
+                engine.params.width.min(RENDER_SIZE.0),
+                engine.params.height.min(RENDER_SIZE.1),
+            ) {
+                Ok(path) => log::info!("Saved denoiser features to {}", path.display()),
+                Err(e) => log::error!("Failed to export denoiser features: {}", e),
+            }
+        }
+        let timeline = &engine.scene_manager.scene.timeline;
+        if timeline.sequence.is_some()
+            && !timeline.dirty
+            && engine.params.passes() >= timeline.samples_per_frame as i32
+        {
+            App::save_sequence_frame(engine);
+        }
+        let timeline = &mut engine.scene_manager.scene.timeline;
+        if let Some((first, last)) = timeline.sequence_requested.take() {
+            timeline.start_sequence(
+                Path::new("renders").join(format!(
+                    "sequence_{:?}",
+                    engine.scene_manager.selected_scene
+                )),
+                first,
+                last,
+            );
+            engine.params.accumulate = 1;
+        }
+        let textures_animated = !engine.scene_manager.animated_textures.is_empty();
+        let timeline = &mut engine.scene_manager.scene.timeline;
+        let timeline_changed = timeline.update(dt.as_secs_f32(), textures_animated);
+        if timeline_changed {
+            engine.scene_manager.scene.apply_timeline();
             engine.params.reset_frame();
+            engine.timing.reset();
         }
-
-        let camera_moved = engine.scene_manager.scene.camera.update_camera(dt);
-        let reset_frame = engine.params.update(camera_moved);
-        if camera_moved || reset_frame {
+        let timeline = &engine.scene_manager.scene.timeline;
+        let audio_changed = engine.audio.update(dt.as_secs_f32(), timeline.time());
+        // Reapplied after the timeline too, keys would otherwise overwrite the bound properties
+        if (audio_changed || timeline_changed) && !timeline.audio.is_empty() {
+            engine.scene_manager.scene.apply_audio(&engine.audio.levels);
+            engine.params.reset_frame();
+            engine.timing.reset();
+        }
+        if engine.update_animated_textures() {
+            engine.params.reset_frame();
+            engine.timing.reset();
+        }
+        let seed = engine
+            .tmp
+            .seed_schedule
+            .seed(engine.scene_manager.scene.timeline.frame);
+        if engine.params.seed != seed {
+            engine.params.seed = seed;
+            engine.params.reset_frame();
+        }
+        engine.distributed.poll();
+        if engine.distributed.start_requested {
+            engine.distributed.start_requested = false;
+            let exposure = engine.display_exposure();
+            engine.distributed.start(
+                engine.scene_manager.selected_scene,
+                &engine.scene_manager.scene,
+                &engine.params,
+                exposure,
+            );
+        }
+        engine.render_queue.poll();
+        if engine.render_queue.add_requested {
+            engine.render_queue.add_requested = false;
+            let exposure = engine.display_exposure();
+            engine.render_queue.add(
+                engine.scene_manager.selected_scene,
+                &engine.scene_manager.scene.camera,
+                &engine.params,
+                exposure,
+            );
+        }
+        if engine.render_queue.start_requested {
+            engine.render_queue.start_requested = false;
+            engine.render_queue.start();
+        }
+        if engine.snapshots.take_requested {
+            engine.snapshots.take_requested = false;
+            App::take_snapshot(engine);
+        }
+        let gamepad = engine.gamepad.update();
+        let controller = &mut engine.scene_manager.scene.camera.controller;
+        controller.process_gamepad(gamepad.movement, gamepad.look);
+        if gamepad.speed_steps != 0 {
+            controller.scale_speed(SPEED_STEP.powi(gamepad.speed_steps));
+        }
+        if gamepad.screenshot {
+            App::run_action(engine, self.window.as_ref().unwrap(), Action::SaveRender);
+        }
+        let width = engine.params.width;
+        if engine.scene_manager.scene.camera.apply_aperture(width) {
+            engine.params.reset_frame();
+        }
+        let from = engine.scene_manager.scene.camera.transform.pos;
+        let mut camera_moved = engine.scene_manager.scene.camera.update_camera(dt);
+        let walkthrough = &mut engine.tmp.walkthrough;
+        if walkthrough.enabled && (camera_moved || walkthrough.snap_requested) {
+            walkthrough.snap_requested = false;
+            let scene = &engine.scene_manager.scene;
+            let pos = walkthrough.constrain(scene, from, scene.camera.transform.pos);
+            engine.scene_manager.scene.camera.transform.pos = pos;
+            camera_moved |= pos != from;
+        }
+        // Anything restarting accumulation this frame restarts the time limit with it
+        let restarting = camera_moved || engine.params.frames < 0 || engine.params.accumulate == 0;
+        let elapsed = match restarting {
+            true => Duration::ZERO,
+            false => engine.timing.render_start.elapsed(),
+        };
+        if engine.tmp.time_limit.update(elapsed) {
+            log::info!(
+                "Time limit of {} minutes reached after {} samples",
+                engine.tmp.time_limit.minutes,
+                engine.params.samples()
+            );
+            if engine.tmp.time_limit.save {
+                App::run_action(engine, self.window.as_ref().unwrap(), Action::SaveRender);
+            }
+        }
+        let timing = &mut engine.timing;
+        // Past the time limit the frame count holds still and nothing is traced, as it does while
+        // the CPU is part way through a frame
+        let cpu_tracing = engine.cpu.as_ref().is_some_and(CpuTracer::tracing) && !camera_moved;
+        let reset_frame =
+            !engine.tmp.time_limit.stopped && !cpu_tracing && engine.params.update(camera_moved);
+        if camera_moved || reset_frame || (restarting && engine.tmp.time_limit.enabled) {
             timing.reset();
         }
+        if !camera_moved {
+            engine.refine_textures();
+        }
 
         if engine.scene_manager.selected_scene != engine.scene_manager.prev_scene {
             engine
                 .scene_manager
                 .request_scene(engine.scene_manager.selected_scene.clone());
         }
+        engine.tabs.tabs[engine.tabs.active].title = engine.scene_manager.selected_scene;
+        let mut buffer_params = engine.params.for_buffer(camera_moved || engine.tmp.low_res);
+        engine.upscaler.active = camera_moved && engine.upscaler.enabled;
+        let stack = engine
+            .scene_manager
+            .scene
+            .post
+            .clone()
+            .unwrap_or_else(|| engine.post.stack.clone());
+        let exposure = engine.display_exposure();
+        engine.post.prepare(
+            &stack,
+            engine.upscaler.active,
+            (buffer_params.width, buffer_params.height),
+            exposure,
+        );
+        // The cost views are raw counts, shown as they are
+        buffer_params.processed =
+            (engine.post.active && !DebugMode::is_cost(buffer_params.debug_flag)) as u32;
+        buffer_params.exposure += engine.scene_manager.scene.camera.exposure.stops();
         engine.resources.queue.write_buffer(
-            &engine.resources.params_buffer,
+            &engine.resources.target.params_buffer,
             0,
-            bytemuck::cast_slice(&[engine.params.for_buffer(camera_moved || engine.tmp.low_res)]),
+            bytemuck::cast_slice(&[buffer_params]),
         );
+        engine.ray_tracer.specialise(&buffer_params);
+        let scene = &engine.scene_manager.scene;
+        let selected_mesh = usize::try_from(engine.scene_manager.selected_entity)
+            .ok()
+            .and_then(|entity| scene.meshes.get(entity.checked_sub(scene.spheres.len())?));
+        engine.overlay.set_selection(selected_mesh);
+        engine.overlay.update(&scene.camera);
         engine
             .ray_tracer
             .update_buffers(&engine.resources.queue, &mut engine.scene_manager.scene);
+        if engine.ray_tracer.take_reallocated() {
+            engine.timing.mark(FrameEvent::BufferUpload);
+        }
+        if let Some(cpu) = engine.cpu.as_mut()
+            && !engine.tmp.time_limit.stopped
+        {
+            cpu.trace_for(
+                &engine.scene_manager.scene,
+                &buffer_params,
+                (buffer_params.width, buffer_params.height),
+                CPU_FRAME_BUDGET,
+            );
+            cpu.upload(&engine.resources.queue, &engine.resources.target.texture);
+        }
+        engine.ray_tracer.update_reprojection(
+            &engine.resources.queue,
+            engine.scene_manager.scene.camera.to_uniform(),
+            (buffer_params.width, buffer_params.height),
+            camera_moved,
+            // Sequence frames start clean, so any one of them can be rendered again on its own
+            engine.params.taa_blend > 0.0 && engine.scene_manager.scene.timeline.sequence.is_none(),
+        );
+        let scene = &engine.scene_manager.scene;
+        if engine.ray_tracer.auto_tune_requested
+            && (!scene.spheres.is_empty() || !scene.meshes.is_empty())
+        {
+            engine
+                .ray_tracer
+                .auto_tune(engine.params.width, engine.params.height);
+            engine.params.reset_frame();
+            engine.timing.reset();
+        }
     }
 
     fn handle_input(&mut self, event: &WindowEvent) -> bool {
         let Some(engine) = self.engine.as_mut() else {
             return false;
         };
+        let window = self.window.as_ref().unwrap();
+        let ctrl = self.modifiers.control_key();
+        // Ctrl shortcuts work over the UI too, plain keys only while the mouse is captured
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(key),
+                    state: key_state,
+                    ..
+                },
+            ..
+        } = event
+            && (engine.tmp.use_mouse || ctrl)
+            && let Some(action) = Action::from_key(*key, ctrl)
+        {
+            if key_state.is_pressed() {
+                App::run_action(engine, window, action);
+            }
+            return true;
+        }
         if !engine.tmp.use_mouse {
             return false;
         }
@@ -178,113 +687,158 @@ impl App {
                         ..
                     },
                 ..
-            } => match key {
-                KeyCode::Escape => {
-                    engine.tmp.use_mouse = false;
-                    let window = self.window.as_mut().unwrap();
-                    window.set_cursor_visible(true);
-                    window
-                        .set_cursor_grab(winit::window::CursorGrabMode::None)
-                        .unwrap();
-                    true
-                }
-                KeyCode::KeyQ => {
-                    if key_state.is_pressed() {
-                        engine.scene_manager.selected_scene =
-                            engine.scene_manager.selected_scene.next();
-                        engine.params.reset_frame();
-                        engine.timing.reset();
-                    }
-                    true
-                }
-                KeyCode::KeyE => {
-                    if key_state.is_pressed() {
-                        engine.params.debug_flag += 1;
-                        if engine.params.debug_flag > DEBUG_MODES as i32 {
-                            engine.params.debug_flag = 0;
-                        }
-                        engine.params.reset_frame();
-                        engine.timing.reset();
-                    }
-                    true
+            } => engine
+                .scene_manager
+                .scene
+                .camera
+                .controller
+                .process_keyboard(*key, *key_state),
+            WindowEvent::MouseInput {
+                button: winit::event::MouseButton::Left,
+                state: button_state,
+                ..
+            } => {
+                engine.tmp.mouse_pressed = *button_state == winit::event::ElementState::Pressed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs an action from a keybinding or the command palette.
+    fn run_action(engine: &mut Engine, window: &Window, action: Action) {
+        match action {
+            Action::CommandPalette => {
+                engine.palette.show();
+                App::release_mouse(engine, window);
+            }
+            Action::ReleaseMouse => App::release_mouse(engine, window),
+            Action::NextScene => {
+                engine.scene_manager.selected_scene = engine.scene_manager.selected_scene.next();
+                engine.params.reset_frame();
+                engine.timing.reset();
+            }
+            Action::CycleDebugMode => {
+                engine.params.debug_flag += 1;
+                if engine.params.debug_flag > DEBUG_MODES as i32 {
+                    engine.params.debug_flag = 0;
                 }
-                KeyCode::KeyP => {
-                    if key_state.is_pressed() {
-                        log::info!("Saving Render to file");
-                        let _ = App::save_render_to_file(
-                            &engine.resources.texture,
+                engine.params.reset_frame();
+                engine.timing.reset();
+            }
+            Action::SaveRender => {
+                log::info!("Saving Render to file");
+                let exposure = engine.display_exposure();
+                let annotation = engine.tmp.annotate_screenshots.then(|| {
+                    RenderAnnotation::new(
+                        engine.scene_manager.selected_scene,
+                        &engine.params,
+                        &engine.scene_manager.scene.camera,
+                        engine.timing.render_start.elapsed(),
+                    )
+                });
+                let dir = Path::new("renders");
+                let path = dir.join(format!("render_{}.png", engine.params.frames));
+                let saved = std::fs::create_dir_all(dir)
+                    .map_err(|e| e.into())
+                    .and_then(|()| {
+                        App::save_render_to_file(
+                            &engine.resources.target.texture,
                             &engine.resources.device,
                             &engine.resources.queue,
-                            format!(
-                                "C:/users/addis/photos/ray_tracer/render_{}",
-                                engine.params.frames
-                            ),
+                            (engine.params.width, engine.params.height),
+                            &path,
+                            exposure,
+                            annotation,
                         )
-                        .unwrap();
-                    }
-                    true
+                    });
+                if let Err(e) = saved {
+                    log::error!("Failed to save render: {}", e);
                 }
-                KeyCode::KeyF => {
-                    if key_state.is_pressed() {
-                        let window = self.window.as_mut().unwrap();
-                        engine.tmp.fullscreen = match engine.tmp.fullscreen {
-                            true => {
-                                window.set_fullscreen(None);
-                                false
-                            }
-                            false => {
-                                window.set_fullscreen(Some(Fullscreen::Borderless(None)));
-                                true
-                            }
-                        };
-                    }
-                    true
+            }
+            Action::FrameSelection => {
+                // Frame the selection if there is one, otherwise toggle fullscreen
+                let scene = &mut engine.scene_manager.scene;
+                if let Some(bounds) = scene.entity_bounds(engine.scene_manager.selected_entity) {
+                    scene.camera.frame_bounds(&bounds);
+                    engine.params.reset_frame();
+                    engine.timing.reset();
+                } else {
+                    App::run_action(engine, window, Action::ToggleFullscreen);
                 }
-                KeyCode::KeyR => {
-                    if key_state.is_pressed() {
-                        engine.tmp.low_res = !engine.tmp.low_res;
-                        engine.params.reset_frame();
-                        engine.timing.reset();
-                    }
-                    true
+            }
+            Action::DropToFloor => {
+                let scene_manager = &mut engine.scene_manager;
+                scene_manager.sync_selection();
+                if scene_manager.scene.drop_to_floor(&scene_manager.selection) > 0 {
+                    engine.params.reset_frame();
+                    engine.timing.reset();
                 }
-                KeyCode::Digit1 => {
-                    if key_state.is_pressed() {
-                        engine.params.skybox = if engine.params.skybox != 0 { 0 } else { 1 };
-                        engine.params.reset_frame();
-                        engine.timing.reset();
+            }
+            Action::ToggleFullscreen => {
+                engine.tmp.fullscreen = match engine.tmp.fullscreen {
+                    true => {
+                        window.set_fullscreen(None);
+                        false
                     }
-                    true
-                }
-                KeyCode::Digit2 => {
-                    if key_state.is_pressed() {
-                        engine.params.accumulate =
-                            if engine.params.accumulate != 0 { 0 } else { 1 };
+                    false => {
+                        window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+                        true
                     }
-                    true
-                }
-                _ => engine
-                    .scene_manager
-                    .scene
-                    .camera
-                    .controller
-                    .process_keyboard(*key, *key_state),
-            },
-            WindowEvent::MouseInput {
-                button: winit::event::MouseButton::Left,
-                state: button_state,
-                ..
-            } => {
-                engine.tmp.mouse_pressed = *button_state == winit::event::ElementState::Pressed;
-                true
+                };
             }
-            _ => false,
+            Action::ToggleGrid => {
+                engine.overlay.show_grid = !engine.overlay.show_grid;
+                engine.overlay.show_axes = engine.overlay.show_grid;
+            }
+            Action::ToggleThirds => engine.overlay.show_thirds = !engine.overlay.show_thirds,
+            Action::ToggleMagnifier => engine.magnifier.enabled = !engine.magnifier.enabled,
+            Action::NextView => {
+                let scene = &mut engine.scene_manager.scene;
+                scene.select_view((scene.active_view + 1) % scene.views.len().max(1));
+                engine.params.reset_frame();
+                engine.timing.reset();
+            }
+            Action::ToggleLowRes => {
+                engine.tmp.low_res = !engine.tmp.low_res;
+                engine.params.reset_frame();
+                engine.timing.reset();
+            }
+            Action::ToggleSkybox => {
+                engine.params.skybox = if engine.params.skybox != 0 { 0 } else { 1 };
+                engine.params.reset_frame();
+                engine.timing.reset();
+            }
+            Action::ToggleAccumulate => {
+                engine.params.accumulate = if engine.params.accumulate != 0 { 0 } else { 1 };
+            }
+            Action::RebuildBvh => {
+                engine.scene_manager.scene.built_bvh = false;
+                engine.params.reset_frame();
+                engine.timing.reset();
+            }
+            Action::AutoTune => engine.ray_tracer.auto_tune_requested = true,
+            Action::NewTab => engine.tabs.open_requested = true,
+            Action::ToggleTextureCache => engine.tmp.show_textures = !engine.tmp.show_textures,
+            Action::ExportCryptomatte => engine.cryptomatte.export_requested = true,
+            Action::ExportDenoiseFeatures => engine.denoise_features.export_requested = true,
+            Action::QueueRender => engine.render_queue.add_requested = true,
+            Action::TakeSnapshot => engine.snapshots.take_requested = true,
+            Action::ToggleWalkthrough => engine.tmp.walkthrough.toggle(),
         }
     }
 
-    fn handle_redraw(&mut self) {
+    fn release_mouse(engine: &mut Engine, window: &Window) {
+        engine.tmp.use_mouse = false;
+        window.set_cursor_visible(true);
+        window
+            .set_cursor_grab(winit::window::CursorGrabMode::None)
+            .unwrap();
+    }
+
+    fn handle_redraw(&mut self) -> Result<(), GpuError> {
         let Some(engine) = self.engine.as_mut() else {
-            return;
+            return Ok(());
         };
 
         // Skip if window is minimized (maybe unwanted behaviour)
@@ -292,7 +846,7 @@ impl App {
             if let Some(min) = window.is_minimized() {
                 if min {
                     log::warn!("Skipping, Window minimised");
-                    return;
+                    return Ok(());
                 }
             }
         }
@@ -301,166 +855,151 @@ impl App {
             .resources
             .create_screen_descriptor(self.window.as_ref().unwrap().clone());
 
-        let (surface_texture, surface_view) = engine.resources.get_surface_view_and_texture();
+        let Some((surface_texture, surface_view)) =
+            engine.resources.get_surface_view_and_texture()?
+        else {
+            return Ok(());
+        };
 
         let mut encoder = engine.resources.create_command_encoder();
 
         let window = self.window.as_mut().unwrap();
 
-        // Ray Tracer Pass
-        engine
-            .ray_tracer
-            .render(&mut encoder, engine.params.width, engine.params.height);
-
+        let mut graph = engine.frame_graph();
         // Render egui and Ray Tracer output
-        {
-            engine.egui.begin_frame(window);
-            let mut ui_ctx = UiContext {
-                renderer: &mut engine.renderer,
-                scene_manager: &mut engine.scene_manager,
-                timing: &mut engine.timing,
-                tmp: &mut engine.tmp,
-                params: &mut engine.params,
-                window: window.clone(),
-            };
-            engine.egui.render_ui(&mut ui_ctx);
+        graph.add_pass(
+            "Egui",
+            &[
+                FrameResource::Accumulation,
+                FrameResource::Post,
+                FrameResource::Luminance,
+                FrameResource::Ids,
+            ],
+            &[FrameResource::Surface],
+            move |engine: &mut Engine, encoder| {
+                engine.egui.begin_frame(window);
+                let mut ui_ctx = UiContext {
+                    renderer: &mut engine.renderer,
+                    ray_tracer: &mut engine.ray_tracer,
+                    scene_manager: &mut engine.scene_manager,
+                    jobs: &engine.jobs,
+                    timing: &mut engine.timing,
+                    tmp: &mut engine.tmp,
+                    tabs: &mut engine.tabs,
+                    params: &mut engine.params,
+                    auto_exposure: &mut engine.auto_exposure,
+                    upscaler: &mut engine.upscaler,
+                    post: &mut engine.post,
+                    overlay: &mut engine.overlay,
+                    lightmap: &mut engine.lightmap,
+                    cryptomatte: &mut engine.cryptomatte,
+                    denoise_features: &mut engine.denoise_features,
+                    picker: &mut engine.picker,
+                    magnifier: &mut engine.magnifier,
+                    distributed: &mut engine.distributed,
+                    render_queue: &mut engine.render_queue,
+                    snapshots: &mut engine.snapshots,
+                    watcher: &mut engine.watcher,
+                    audio: &mut engine.audio,
+                    gamepad: &mut engine.gamepad,
+                    palette: &mut engine.palette,
+                    settings: &mut engine.settings,
+                    cpu: &mut engine.cpu,
+                    window: window.clone(),
+                };
+                engine.egui.render_ui(&mut ui_ctx);
 
-            engine.egui.end_frame_and_draw(
-                &engine.resources.device,
-                &engine.resources.queue,
-                &mut encoder,
-                window,
-                &surface_view,
-                screen_descriptor,
-            );
-        }
+                engine.egui.end_frame_and_draw(
+                    &engine.resources.device,
+                    &engine.resources.queue,
+                    encoder,
+                    window,
+                    &surface_view,
+                    screen_descriptor,
+                );
+            },
+        );
+        graph.execute(engine, &mut encoder);
 
         engine.resources.queue.submit(Some(encoder.finish()));
+        engine.auto_exposure.request_readback();
+        engine.picker.request_readback();
         surface_texture.present();
+        Ok(())
+    }
+    /// Saves the accumulated image as the current sequence frame and moves the timeline on.
+    fn save_sequence_frame(engine: &mut Engine) {
+        let exposure = engine.display_exposure();
+        let timeline = &mut engine.scene_manager.scene.timeline;
+        let Some(sequence) = timeline.sequence.as_ref() else {
+            return;
+        };
+        if let Err(e) = std::fs::create_dir_all(&sequence.dir) {
+            log::error!("Failed to create sequence directory: {}", e);
+            timeline.sequence = None;
+            return;
+        }
+        let path = sequence
+            .dir
+            .join(format!("frame_{:04}.png", sequence.frame));
+        if let Err(e) = App::save_render_to_file(
+            &engine.resources.target.texture,
+            &engine.resources.device,
+            &engine.resources.queue,
+            (engine.params.width, engine.params.height),
+            &path,
+            exposure,
+            None,
+        ) {
+            log::error!("Failed to save sequence frame: {}", e);
+            timeline.sequence = None;
+            return;
+        }
+        if !timeline.next_sequence_frame() {
+            log::info!("Finished rendering sequence");
+        }
     }
+    /// Keeps the current render and its settings in the session's snapshot gallery.
+    fn take_snapshot(engine: &mut Engine) {
+        let image = match read_render_rgba32f(
+            &engine.resources.device,
+            &engine.resources.queue,
+            &engine.resources.target.texture,
+            engine.params.width,
+            engine.params.height,
+        ) {
+            Ok(image) => image,
+            Err(e) => {
+                log::error!("Failed to take snapshot: {}", e);
+                return;
+            }
+        };
+        let exposure = engine.display_exposure();
+        engine.snapshots.add(
+            image,
+            engine.scene_manager.selected_scene,
+            engine.params,
+            engine.scene_manager.scene.camera,
+            exposure,
+            engine.timing.render_start.elapsed(),
+        );
+    }
+    /// Saves the `size` render in `texture` as an 8-bit image, the format taken from `path`'s extension.
     pub fn save_render_to_file(
         texture: &wgpu::Texture,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        path: String,
+        size: (u32, u32),
+        path: &Path,
+        exposure: f32,
+        annotation: Option<RenderAnnotation>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        // Calculate aligned bytes per row (wgpu requires 256-byte alignment)
-        let bytes_per_pixel = 16; // RGBA
-        let unpadded_bytes_per_row = RENDER_SIZE.0 * bytes_per_pixel;
-        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u32;
-        let bytes_per_row = ((unpadded_bytes_per_row + align - 1) / align) * align;
-        let buffer_size = (bytes_per_row * RENDER_SIZE.1) as wgpu::BufferAddress;
-
-        let buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("Final Render Buffer"),
-            size: buffer_size,
-            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
-
-        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
-            label: Some("Final Render Encoder"),
-        });
-
-        encoder.copy_texture_to_buffer(
-            TexelCopyTextureInfo {
-                texture,
-                mip_level: 0,
-                origin: Origin3d::ZERO,
-                aspect: TextureAspect::All,
-            },
-            TexelCopyBufferInfo {
-                buffer: &buffer,
-                layout: TexelCopyBufferLayout {
-                    offset: 0,
-                    bytes_per_row: Some(bytes_per_row),
-                    rows_per_image: Some(RENDER_SIZE.1),
-                },
-            },
-            Extent3d {
-                width: RENDER_SIZE.0,
-                height: RENDER_SIZE.1,
-                depth_or_array_layers: 1,
-            },
-        );
-        queue.submit(std::iter::once(encoder.finish()));
-
-        let buffer_slice = buffer.slice(..);
-
-        let map_complete = Arc::new(AtomicBool::new(false));
-        let map_error = Arc::new(std::sync::Mutex::new(None));
-
-        let map_complete_clone = Arc::clone(&map_complete);
-        let map_error_clone = Arc::clone(&map_error);
-
-        buffer_slice.map_async(wgpu::MapMode::Read, move |result| match result {
-            Ok(()) => map_complete_clone.store(true, Ordering::SeqCst),
-            Err(e) => *map_error_clone.lock().unwrap() = Some(e),
-        });
-
-        while !map_complete.load(Ordering::SeqCst) {
-            device.poll(wgpu::MaintainBase::Wait)?;
-            if let Some(err) = map_error.lock().unwrap().take() {
-                return Err(Box::new(err));
-            }
-        }
-
-        let data = buffer_slice.get_mapped_range();
-        let mut image_data = Vec::with_capacity((RENDER_SIZE.0 * RENDER_SIZE.1 * 4) as usize);
-
-        for y in 0..RENDER_SIZE.1 {
-            let row_start = (y * bytes_per_row) as usize;
-
-            for x in (0..RENDER_SIZE.0).rev() {
-                let pixel_start = row_start + (x * bytes_per_pixel) as usize;
-
-                let r = f32::from_ne_bytes([
-                    data[pixel_start],
-                    data[pixel_start + 1],
-                    data[pixel_start + 2],
-                    data[pixel_start + 3],
-                ]);
-                let g = f32::from_ne_bytes([
-                    data[pixel_start + 4],
-                    data[pixel_start + 5],
-                    data[pixel_start + 6],
-                    data[pixel_start + 7],
-                ]);
-                let b = f32::from_ne_bytes([
-                    data[pixel_start + 8],
-                    data[pixel_start + 9],
-                    data[pixel_start + 10],
-                    data[pixel_start + 11],
-                ]);
-                let a = f32::from_ne_bytes([
-                    data[pixel_start + 12],
-                    data[pixel_start + 13],
-                    data[pixel_start + 14],
-                    data[pixel_start + 15],
-                ]);
-
-                let r_byte = (r.powf(1.0 / 2.2).clamp(0.0, 1.0) * 255.0) as u8;
-                let g_byte = (g.powf(1.0 / 2.2).clamp(0.0, 1.0) * 255.0) as u8;
-                let b_byte = (b.powf(1.0 / 2.2).clamp(0.0, 1.0) * 255.0) as u8;
-                let a_byte = (a.powf(1.0 / 2.2).clamp(0.0, 1.0) * 255.0) as u8;
-
-                image_data.push(r_byte);
-                image_data.push(g_byte);
-                image_data.push(b_byte);
-                image_data.push(a_byte);
-            }
+        let mut image = read_render_rgba8(device, queue, texture, size.0, size.1, exposure)?;
+        if let Some(annotation) = annotation {
+            annotation.burn_in(&mut image);
         }
-
-        let mut image =
-            ImageBuffer::<image::Rgba<u8>, _>::from_raw(RENDER_SIZE.0, RENDER_SIZE.1, image_data)
-                .ok_or("Failed to create image from buffer")
-                .unwrap();
-        image::imageops::flip_horizontal_in_place(&mut image);
-        image::imageops::flip_vertical_in_place(&mut image);
-        image.save(path.clone()).unwrap();
-        drop(data);
-        buffer.unmap();
-        log::info!("Saved Render to {}", path);
+        image.save(path)?;
+        log::info!("Saved Render to {}", path.display());
         Ok(())
     }
 }
@@ -472,7 +1011,13 @@ impl ApplicationHandler for App {
             .unwrap();
         window.focus_window();
         window.set_title("Ray Tracer");
-        pollster::block_on(self.set_window(window));
+        if let Err(e) = pollster::block_on(self.set_window(window)) {
+            log::error!("{}", e);
+            log::info!(
+                "Not even a software adapter could open the window, --worker, --queue and --furnace render on the CPU with --cpu"
+            );
+            event_loop.exit();
+        }
     }
     fn device_event(
         &mut self,
@@ -513,35 +1058,58 @@ impl ApplicationHandler for App {
         _: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
-        if !self
-            .engine
-            .as_mut()
-            .unwrap()
-            .egui
-            .handle_input(self.window.as_ref().unwrap(), &event)
+        if let (Some(engine), Some(window)) = (self.engine.as_mut(), self.window.as_ref())
+            && !engine.egui.handle_input(window, &event)
         {
             self.handle_input(&event);
         }
 
         match event {
             WindowEvent::CloseRequested => {
+                // Panel sizes change while dragging without asking for a save, keep where they were left
+                if let Some(engine) = self.engine.as_ref()
+                    && let Err(e) = engine.settings.save(Path::new(settings::SETTINGS_FILE))
+                {
+                    log::error!("Failed to save {}: {}", settings::SETTINGS_FILE, e);
+                }
                 event_loop.exit();
             }
             WindowEvent::RedrawRequested => {
-                self.handle_redraw();
+                if let Err(error) = self.handle_redraw()
+                    && let Err(e) = self.recover(error)
+                {
+                    log::error!("Failed to restart the renderer: {}", e);
+                    event_loop.exit();
+                }
+                if self
+                    .engine
+                    .as_ref()
+                    .is_some_and(|engine| engine.tmp.time_limit.exit_requested)
+                {
+                    log::info!("Time limit reached, exiting");
+                    event_loop.exit();
+                }
             }
             WindowEvent::Resized(new_size) => {
                 self.handle_resized(new_size.width, new_size.height);
             }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
             _ => (),
         }
     }
     fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
-        let timing = &mut self.engine.as_mut().unwrap().timing;
+        let Some(engine) = self.engine.as_mut() else {
+            return;
+        };
+        let timing = &mut engine.timing;
         let now = Instant::now();
         let dt = now - timing.last_render_time;
         timing.last_render_time = now;
         self.update(dt);
-        self.window.as_ref().unwrap().request_redraw();
+        if let Some(window) = self.window.as_ref() {
+            window.request_redraw();
+        }
     }
 }
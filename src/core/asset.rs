@@ -2,6 +2,7 @@ use std::{
     f32::NAN,
     fs::File,
     io::Read,
+    path::{Path, PathBuf},
     sync::{Arc, atomic::AtomicU32},
 };
 
@@ -12,51 +13,142 @@ use rayon::iter::{
     IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
 };
 
+use crate::core::{
+    cache, download,
+    mip_cache::{MipChain, ResidentTexture},
+    stream,
+};
 use crate::rendering::ray_tracer::MAX_TEXTURES;
 use crate::scene::components::{
     geometry::{
-        mesh::{MeshData, MeshInstance},
+        mesh::{MeshData, MeshInstance, UvView},
+        normals,
         vertex::Vertex,
     },
     material::{MaterialFlag, MaterialUniform},
+    material_rules::MaterialRule,
+    texture::AnimatedTexture,
     transform::Transform,
+    volume::VoxelGrid,
 };
+use crate::scene::entity::EntityLabel;
+
+/// A mesh of a loaded model with the name of the MTL material its faces use, if any.
+pub struct ModelPart {
+    pub mesh: MeshInstance,
+    pub material_name: Option<String>,
+}
 
 pub struct AssetManager {
-    loaded_meshes: Arc<DashMap<String, Arc<MeshData>>>,
+    /// Parts of every model loaded this session by name, shared with the `SceneManager` so a
+    /// model changed on disk can replace them
+    pub loaded_meshes: Arc<DashMap<String, Arc<MeshData>>>,
     pub loaded_textures: Arc<DashMap<String, i32>>,
-    pub cpu_textures: DashMap<String, Arc<RgbaImage>>,
+    /// Shared with the `SceneManager` so the UI can list and reload textures. Holds the level of
+    /// each texture last put in a texture array, not necessarily the full resolution.
+    pub cpu_textures: Arc<DashMap<String, Arc<RgbaImage>>>,
+    /// Cached mip levels of every loaded texture, by path
+    pub mip_chains: Arc<DashMap<String, MipChain>>,
+    /// Image sequences by the texture slot their first frame was loaded into
+    pub animated_textures: Arc<DashMap<i32, AnimatedTexture>>,
     next_texture_index: AtomicU32,
 }
 impl AssetManager {
-    pub fn create_texture_array(&self) -> Vec<Arc<RgbaImage>> {
+    /// Texture array for a scene, with each texture read back at the level `needed` picks for its
+    /// index. Textures the scene doesn't use keep whatever level they had, new ones start full size.
+    pub fn create_texture_array(
+        &self,
+        needed: impl Fn(usize, &MipChain) -> Option<u32> + Sync,
+    ) -> (Vec<Arc<RgbaImage>>, Vec<Option<ResidentTexture>>) {
         let mut texture_array: Vec<Arc<RgbaImage>> =
             vec![Arc::new(ImageBuffer::new(1, 1)); MAX_TEXTURES as usize];
-
-        for entry in self.cpu_textures.iter() {
-            let key = entry.key();
-            if let Some(index) = self.loaded_textures.get(key) {
-                let index = index.clone() as usize;
-                if index < MAX_TEXTURES as usize {
-                    texture_array[index] = entry.value().clone();
-                }
-            }
+        let mut resident = vec![None; MAX_TEXTURES as usize];
+        let chains: Vec<(String, MipChain, usize)> = self
+            .mip_chains
+            .iter()
+            .filter_map(|entry| {
+                let index = *self.loaded_textures.get(entry.key())? as usize;
+                (index < MAX_TEXTURES as usize)
+                    .then(|| (entry.key().clone(), *entry.value(), index))
+            })
+            .collect();
+        let loaded: Vec<_> = chains
+            .into_par_iter()
+            .filter_map(|(path, chain, index)| {
+                let current = self.cpu_textures.get(&path).map(|image| image.clone());
+                let level = needed(index, &chain)
+                    .or(current.as_ref().map(|image| chain.level_of(image)))
+                    .unwrap_or(0);
+                let image = match current {
+                    Some(image) if chain.level_of(&image) == level => image,
+                    _ => match chain.load(&path, level) {
+                        Ok(image) => Arc::new(image),
+                        Err(e) => {
+                            log::error!("Failed to load texture {}: {}", path, e);
+                            return None;
+                        }
+                    },
+                };
+                self.cpu_textures.insert(path.clone(), image.clone());
+                Some((index, image, ResidentTexture { path, chain, level }))
+            })
+            .collect();
+        for (index, image, texture) in loaded {
+            texture_array[index] = image;
+            resident[index] = Some(texture);
         }
-
-        texture_array
+        (texture_array, resident)
     }
 }
 
 pub const FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"));
+
+/// Where an asset is on disk, relative to the assets folder or downloaded into the cache first when
+/// it is an http or https URL.
+pub fn asset_path(path: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    match download::is_url(path) {
+        true => download::fetch(path),
+        false => Ok(Path::new(FILE).join("assets").join(path)),
+    }
+}
+
+/// Decodes a texture from the assets folder, flipped to match the UVs the loaders produce.
+pub fn read_texture(path: &str) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+    let mut buffer = vec![];
+    File::open(asset_path(path)?)?.read_to_end(&mut buffer)?;
+    decode_texture(&buffer)
+}
+
+pub fn decode_texture(bytes: &[u8]) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+    Ok(image::imageops::flip_horizontal(&image::load_from_memory(
+        bytes,
+    )?))
+}
+
+impl Default for AssetManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AssetManager {
     pub fn new() -> Self {
         Self {
             loaded_meshes: Arc::new(DashMap::new()),
             loaded_textures: Arc::new(DashMap::new()),
-            cpu_textures: DashMap::new(),
+            cpu_textures: Arc::new(DashMap::new()),
+            mip_chains: Arc::new(DashMap::new()),
+            animated_textures: Arc::new(DashMap::new()),
             next_texture_index: AtomicU32::new(0),
         }
     }
+    /// Loads models into the same session wide parts as another manager, but textures on its own.
+    pub fn sharing_meshes(loaded_meshes: Arc<DashMap<String, Arc<MeshData>>>) -> Self {
+        Self {
+            loaded_meshes,
+            ..Self::new()
+        }
+    }
     pub fn load_texture(&self, path: &String) -> i32 {
         if self.loaded_textures.len() == MAX_TEXTURES as usize {
             log::warn!("Cannot load more than {} textures", MAX_TEXTURES);
@@ -67,56 +159,156 @@ impl AssetManager {
         if let Some(loaded_ref) = self.loaded_textures.get(path) {
             return loaded_ref.clone();
         }
-        let mut buffer = vec![];
-        let file_path = std::path::Path::new(FILE).join("assets").join(path.clone());
-        File::open(file_path)
-            .unwrap()
-            .read_to_end(&mut buffer)
-            .unwrap();
-
-        let image = image::imageops::flip_horizontal(&image::load_from_memory(&buffer).unwrap());
+        // Only the chain is prepared here, levels are read once the scene's needs are known
+        let chain = match MipChain::prepare(path) {
+            Ok(chain) => chain,
+            Err(e) => {
+                log::error!("Failed to load texture {}: {}", path, e);
+                return -1;
+            }
+        };
         let index = self
             .next_texture_index
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst) as i32;
 
         self.loaded_textures.insert(path.clone(), index.clone());
-        self.cpu_textures.insert(path.clone(), Arc::new(image));
+        self.mip_chains.insert(path.clone(), chain);
         index
     }
+    /// Loads an image sequence's first frame into a texture slot, which later frames are swapped
+    /// into as the timeline plays, see `Engine::update_animated_textures`.
+    pub fn load_sequence(&self, pattern: &str, fps: f32) -> i32 {
+        let animation = match AnimatedTexture::find(pattern, fps) {
+            Ok(animation) => animation,
+            Err(e) => {
+                log::error!("Failed to load texture sequence {}: {}", pattern, e);
+                return -1;
+            }
+        };
+        let index = self.load_texture(&animation.frames[0]);
+        if index != -1 {
+            self.animated_textures.insert(index, animation);
+        }
+        index
+    }
+    pub fn load_volume(&self, path: &str) -> Result<VoxelGrid, Box<dyn std::error::Error>> {
+        VoxelGrid::from_vol(&std::fs::read(asset_path(path)?)?)
+    }
+    /// Loads a model's parts, giving each the material of the first rule matching its group or
+    /// material name, otherwise `material` unless `use_mtl` keeps the one from the MTL.
     pub fn load_model_with_material(
         &self,
         path: &String,
         transform: Transform,
         use_mtl: bool,
         material: MaterialUniform,
+        rules: &[(&MaterialRule, MaterialUniform)],
+        crease_angle: Option<f32>,
     ) -> Vec<MeshInstance> {
-        let mut meshes = self.load_model(path, transform, use_mtl);
-        if !use_mtl {
-            meshes.iter_mut().for_each(|mesh| {
-                mesh.material = material;
-            });
+        let parts = self.load_model(path, transform, use_mtl, crease_angle);
+        let count = parts.len();
+        let mut matched = 0;
+        let meshes = parts
+            .into_iter()
+            .map(|part| {
+                let mut mesh = part.mesh;
+                let names = mesh
+                    .label
+                    .as_deref()
+                    .into_iter()
+                    .chain(part.material_name.as_deref());
+                if let Some((_, rule_material)) =
+                    rules.iter().find(|(rule, _)| rule.matches(names.clone()))
+                {
+                    mesh.material = *rule_material;
+                    matched += 1;
+                } else if !use_mtl {
+                    mesh.material = material;
+                }
+                mesh
+            })
+            .collect();
+        if !rules.is_empty() {
+            log::info!(
+                "Material rules matched {} of {} parts of {}",
+                matched,
+                count,
+                path
+            );
         }
         meshes
     }
 
+    /// Loads every part of a model. Normals missing from an OBJ are generated following its
+    /// smoothing groups, with edges sharper than `crease_angle` degrees kept hard.
     pub fn load_model(
         &self,
         path: &String,
         transform: Transform,
         load_materials: bool,
-    ) -> Vec<MeshInstance> {
-        let file_path = std::path::Path::new(FILE).join("assets").join(path);
+        crease_angle: Option<f32>,
+    ) -> Vec<ModelPart> {
+        let file_path = match asset_path(path) {
+            Ok(file_path) => file_path,
+            Err(e) => {
+                log::error!("Failed to load {}: {}", path, e);
+                return vec![];
+            }
+        };
+        if file_path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("ply"))
+        {
+            // PLY has no materials, so it goes through the streaming parser in one go
+            return match stream::load_mesh(path) {
+                Ok(data) => vec![ModelPart {
+                    mesh: MeshInstance {
+                        label: Some(path.clone()),
+                        data: Arc::new(data),
+                        transform,
+                        material: MaterialUniform::default(),
+                        uv_view: UvView::Off,
+                        source: Some(path.clone()),
+                        rect_light: None,
+                        crease_angle: None,
+                        user_label: EntityLabel::default(),
+                    },
+                    material_name: None,
+                }],
+                Err(e) => {
+                    log::error!("Failed to load {}: {}", path, e);
+                    vec![]
+                }
+            };
+        }
+        let cache_key = AssetManager::model_cache_key(&file_path, load_materials, crease_angle);
+        if let Some(key) = cache_key
+            && let Some(meshes) = self.load_cached_model(key, path, transform, crease_angle)
+        {
+            log::info!("Loaded {} from cache", path);
+            return meshes;
+        }
 
-        let (models, materials) = tobj::load_obj(
-            file_path,
+        let loaded = tobj::load_obj(
+            &file_path,
             &tobj::LoadOptions {
                 triangulate: true,
                 single_index: false,
                 ..Default::default()
             },
-        )
-        .expect("Failed to load OBJ File");
+        );
+        let (models, materials) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                log::error!("Failed to load {}: {}", path, e);
+                return vec![];
+            }
+        };
 
+        let material_names: Vec<String> = match &materials {
+            Ok(materials) => materials.iter().map(|m| m.name.clone()).collect(),
+            Err(_) => vec![],
+        };
         let material_map: DashMap<usize, MaterialUniform> = DashMap::new();
 
         // Must get index before textures are added,
@@ -126,14 +318,15 @@ impl AssetManager {
             materials.par_iter().for_each(|m| {
                 if let Some(diffuse_path) = &m.diffuse_texture {
                     if !texture_refs.contains_key(diffuse_path) {
-                        let texture_ref = self.load_texture(diffuse_path);
+                        let texture_ref =
+                            self.load_texture(&download::relative(path, diffuse_path));
                         texture_refs.insert(diffuse_path.clone(), texture_ref.clone());
                     }
                 }
 
                 if let Some(normal_path) = m.unknown_param.get("map_Disp") {
                     if !texture_refs.contains_key(normal_path) {
-                        let texture_ref = self.load_texture(normal_path);
+                        let texture_ref = self.load_texture(&download::relative(path, normal_path));
                         texture_refs.insert(normal_path.clone(), texture_ref.clone());
                     }
                 }
@@ -205,9 +398,37 @@ impl AssetManager {
             });
         }
 
-        let meshes: Vec<MeshInstance> = models
+        // tobj drops the smoothing groups, so they are read separately and lined up by triangle
+        let mut smoothing_groups = None;
+        if models.iter().any(|m| m.mesh.normals.is_empty()) {
+            let triangles: usize = models.iter().map(|m| m.mesh.indices.len() / 3).sum();
+            smoothing_groups = std::fs::read_to_string(&file_path)
+                .ok()
+                .and_then(|obj| normals::obj_smoothing_groups(&obj))
+                .filter(|groups| {
+                    let matched = groups.len() == triangles;
+                    if !matched {
+                        log::warn!(
+                            "Smoothing groups of {} don't line up with its faces, ignoring them",
+                            path
+                        );
+                    }
+                    matched
+                });
+        }
+        let first_triangles: Vec<usize> = models
+            .iter()
+            .scan(0, |first, m| {
+                let start = *first;
+                *first += m.mesh.indices.len() / 3;
+                Some(start)
+            })
+            .collect();
+
+        let meshes: Vec<ModelPart> = models
             .into_par_iter()
-            .map(|m| {
+            .zip(first_triangles)
+            .map(|(m, first_triangle)| {
                 let mut mesh_data = MeshData {
                     vertices: Arc::new(vec![]),
                     indices: Arc::new(vec![]),
@@ -217,47 +438,24 @@ impl AssetManager {
                     mesh_data.vertices = mesh_ref.vertices.clone();
                     mesh_data.indices = mesh_ref.indices.clone();
                 } else {
-                    let num_vertices = m.mesh.positions.len() / 3;
-                    let mut calculated_normals = vec![Vec3::ZERO; num_vertices];
-
-                    // Pre calculate normals if needed
+                    let mut calculated_normals = vec![];
                     if m.mesh.normals.is_empty() {
-                        for tri in m.mesh.indices.chunks_exact(3) {
-                            let i0 = tri[0] as usize;
-                            let i1 = tri[1] as usize;
-                            let i2 = tri[2] as usize;
-
-                            let v0 = Vec3::new(
-                                m.mesh.positions[3 * i0],
-                                m.mesh.positions[3 * i0 + 1],
-                                m.mesh.positions[3 * i0 + 2],
-                            );
-
-                            let v1 = Vec3::new(
-                                m.mesh.positions[3 * i1],
-                                m.mesh.positions[3 * i1 + 1],
-                                m.mesh.positions[3 * i1 + 2],
-                            );
-
-                            let v2 = Vec3::new(
-                                m.mesh.positions[3 * i2],
-                                m.mesh.positions[3 * i2 + 1],
-                                m.mesh.positions[3 * i2 + 2],
-                            );
-                            let e1 = v1 - v0;
-                            let e2 = v2 - v1;
-                            let normal = e1.cross(e2);
-                            calculated_normals[i0] += normal;
-                            calculated_normals[i1] += normal;
-                            calculated_normals[i2] += normal;
-                        }
-                        // Normalize normals
-                        for n in &mut calculated_normals {
-                            let len = n.length();
-                            if len > 0.0 {
-                                *n /= len;
-                            }
-                        }
+                        let positions: Vec<Vec3> = m
+                            .mesh
+                            .positions
+                            .chunks_exact(3)
+                            .map(Vec3::from_slice)
+                            .collect();
+                        let triangles = m.mesh.indices.len() / 3;
+                        let groups = smoothing_groups
+                            .as_ref()
+                            .map(|groups| &groups[first_triangle..first_triangle + triangles]);
+                        calculated_normals = normals::smooth_normals(
+                            &positions,
+                            &m.mesh.indices,
+                            groups,
+                            crease_angle,
+                        );
                     }
                     mesh_data.vertices = Arc::new(
                         m.mesh
@@ -291,7 +489,7 @@ impl AssetManager {
                                     )
                                 } else {
                                     // If no normals are found, use computed normals
-                                    calculated_normals[pi]
+                                    calculated_normals[j]
                                 };
 
                                 let uv = if !m.mesh.texcoords.is_empty()
@@ -303,7 +501,17 @@ impl AssetManager {
                                     [0.0, 0.0] // no texcoords given
                                 };
 
-                                Vertex::with_uv(pos, normal, uv)
+                                let color = if m.mesh.vertex_color.len() >= 3 * (pi + 1) {
+                                    Vec3::new(
+                                        m.mesh.vertex_color[3 * pi],
+                                        m.mesh.vertex_color[3 * pi + 1],
+                                        m.mesh.vertex_color[3 * pi + 2],
+                                    )
+                                } else {
+                                    Vec3::ONE
+                                };
+
+                                Vertex::with_uv(pos, normal, uv).with_color(color)
                             })
                             .collect(),
                     );
@@ -317,15 +525,133 @@ impl AssetManager {
                 let mesh_data = Arc::new(mesh_data);
                 self.loaded_meshes
                     .insert(format!("{}", m.name), mesh_data.clone());
-                MeshInstance {
-                    label: Some(m.name),
-                    transform,
-                    data: mesh_data.clone(),
-                    material,
+                ModelPart {
+                    mesh: MeshInstance {
+                        label: Some(m.name),
+                        transform,
+                        data: mesh_data.clone(),
+                        material,
+                        uv_view: UvView::Off,
+                        source: Some(path.clone()),
+                        rect_light: None,
+                        crease_angle,
+                        user_label: EntityLabel::default(),
+                    },
+                    material_name: m
+                        .mesh
+                        .material_id
+                        .and_then(|id| material_names.get(id).cloned()),
                 }
             })
             .collect();
 
+        if let Some(key) = cache_key {
+            self.store_cached_model(key, &meshes);
+        }
         return meshes;
     }
+    /// Hash of the OBJ and the material libraries it references, `None` if the OBJ can't be read.
+    fn model_cache_key(
+        file_path: &Path,
+        load_materials: bool,
+        crease_angle: Option<f32>,
+    ) -> Option<u64> {
+        let obj = std::fs::read(file_path).ok()?;
+        let mut libraries = vec![];
+        if load_materials {
+            let dir = file_path.parent().unwrap_or(Path::new(""));
+            for line in String::from_utf8_lossy(&obj).lines() {
+                if let Some(name) = line.trim().strip_prefix("mtllib ") {
+                    libraries.push(std::fs::read(dir.join(name.trim())).unwrap_or_default());
+                }
+            }
+        }
+        let crease_angle = crease_angle.map_or(vec![], |angle| angle.to_le_bytes().to_vec());
+        let mut parts = vec![obj, vec![load_materials as u8], crease_angle];
+        parts.append(&mut libraries);
+        let parts: Vec<&[u8]> = parts.iter().map(|p| p.as_slice()).collect();
+        Some(cache::hash(&parts))
+    }
+    fn texture_path(&self, index: i32) -> Option<String> {
+        if index < 0 {
+            return None;
+        }
+        self.loaded_textures
+            .iter()
+            .find(|entry| *entry.value() == index)
+            .map(|entry| entry.key().clone())
+    }
+    /// Stores processed meshes with texture paths in place of indices, as indices depend on load order.
+    fn store_cached_model(&self, key: u64, parts: &[ModelPart]) {
+        let mut writer = cache::Writer::default();
+        writer.u32(parts.len() as u32);
+        for ModelPart {
+            mesh,
+            material_name,
+        } in parts
+        {
+            writer.option_string(mesh.label.as_deref());
+            writer.option_string(material_name.as_deref());
+            writer.vertices(&mesh.data.vertices);
+            writer.pod_slice(&mesh.data.indices);
+            writer.pod_slice(&[mesh.material]);
+            writer.option_string(self.texture_path(mesh.material.diffuse_index).as_deref());
+            writer.option_string(self.texture_path(mesh.material.normal_index).as_deref());
+        }
+        cache::store("model", key, &writer.bytes);
+    }
+    fn load_cached_model(
+        &self,
+        key: u64,
+        path: &str,
+        transform: Transform,
+        crease_angle: Option<f32>,
+    ) -> Option<Vec<ModelPart>> {
+        let bytes = cache::load("model", key)?;
+        let mut reader = cache::Reader::new(&bytes);
+        let count = reader.u32()?;
+        let mut meshes = vec![];
+        for _ in 0..count {
+            let label = reader.option_string()?;
+            let material_name = reader.option_string()?;
+            let vertices = reader.vertices()?;
+            let indices = reader.pod_slice::<u32>()?;
+            let mut material = *reader.pod_slice::<MaterialUniform>()?.first()?;
+            let diffuse_path = reader.option_string()?;
+            let normal_path = reader.option_string()?;
+            if let Some(path) = diffuse_path {
+                material.diffuse_index = self.load_texture(&path);
+            }
+            if let Some(path) = normal_path {
+                material.normal_index = self.load_texture(&path);
+            }
+            let key = label.clone().unwrap_or_default();
+            let data = match self.loaded_meshes.get(&key) {
+                Some(data) => data.clone(),
+                None => {
+                    let data = Arc::new(MeshData {
+                        vertices: Arc::new(vertices),
+                        indices: Arc::new(indices),
+                    });
+                    self.loaded_meshes.insert(key, data.clone());
+                    data
+                }
+            };
+            meshes.push(ModelPart {
+                mesh: MeshInstance {
+                    label,
+                    data,
+                    transform,
+                    material,
+                    uv_view: UvView::Off,
+                    source: Some(path.to_owned()),
+                    rect_light: None,
+                    crease_angle,
+                    user_label: EntityLabel::default(),
+                },
+                material_name,
+            });
+        }
+        Some(meshes)
+    }
 }
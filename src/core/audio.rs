@@ -0,0 +1,430 @@
+#[cfg(feature = "audio")]
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+use std::{
+    error::Error,
+    f32::consts::TAU,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "audio")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rayon::prelude::*;
+
+use crate::scene::timeline::AnimProperty;
+
+/// Frequency bands the spectrum is split into
+pub const BANDS: usize = 8;
+pub const BAND_NAMES: [&str; BANDS] = [
+    "Sub",
+    "Bass",
+    "Low Mid",
+    "Mid",
+    "High Mid",
+    "Presence",
+    "Brilliance",
+    "Air",
+];
+/// Edges of the bands in Hz, spaced roughly evenly in pitch
+const BAND_EDGES: [f32; BANDS + 1] = [
+    20.0, 60.0, 150.0, 400.0, 1000.0, 2500.0, 6000.0, 12000.0, 20000.0,
+];
+/// Samples per FFT, about 46ms at 44.1kHz, enough to tell the lowest bands apart
+const WINDOW: usize = 2048;
+/// Samples between the windows analysed from a clip
+const HOP: usize = 512;
+#[cfg(feature = "audio")]
+/// Share of its recent peak a live band keeps after a second, lower adapts faster to quiet music
+const PEAK_DECAY: f32 = 0.5;
+#[cfg(feature = "audio")]
+/// Smallest peak a live band is normalised to, so silence isn't amplified into noise
+const MIN_PEAK: f32 = 1e-4;
+
+/// Loudness of each band from 0 to 1
+pub type Levels = [f32; BANDS];
+
+/// In place radix-2 FFT, `re` and `im` must share a power of two length.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -TAU / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// RMS magnitude of each band over the last `WINDOW` of `samples`, Hann windowed. Bands above
+/// the Nyquist frequency stay at zero.
+fn band_energy(samples: &[f32], sample_rate: u32) -> Levels {
+    let mut re = vec![0.0; WINDOW];
+    let mut im = vec![0.0; WINDOW];
+    let samples = &samples[samples.len().saturating_sub(WINDOW)..];
+    for (i, sample) in samples.iter().enumerate() {
+        let hann = 0.5 - 0.5 * (TAU * i as f32 / (WINDOW - 1) as f32).cos();
+        re[i] = sample * hann;
+    }
+    fft(&mut re, &mut im);
+    let bin_hz = sample_rate as f32 / WINDOW as f32;
+    let mut energy = [0.0; BANDS];
+    for (band, energy) in energy.iter_mut().enumerate() {
+        let low = ((BAND_EDGES[band] / bin_hz) as usize).max(1);
+        // At least one bin, the lowest bands are narrower than a bin at high sample rates
+        let high = ((BAND_EDGES[band + 1] / bin_hz) as usize)
+            .max(low + 1)
+            .min(WINDOW / 2);
+        if low >= high {
+            continue;
+        }
+        let power: f32 = (low..high).map(|i| re[i] * re[i] + im[i] * im[i]).sum();
+        *energy = (power / (high - low) as f32).sqrt() / (WINDOW / 2) as f32;
+    }
+    energy
+}
+
+/// PCM or float WAV mixed down to mono, with its sample rate.
+fn decode_wav(bytes: &[u8]) -> Result<(Vec<f32>, u32), Box<dyn Error>> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("Not a WAV file".into());
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+    let u32_at =
+        |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    let mut format = None;
+    let mut data = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let size = u32_at(offset + 4) as usize;
+        let body = offset + 8..offset.saturating_add(8 + size).min(bytes.len());
+        match &bytes[offset..offset + 4] {
+            b"fmt " if size >= 16 => {
+                // The chunk says how long it is, but the file may end before it does
+                if body.len() < 16 {
+                    return Err("WAV format chunk is cut short".into());
+                }
+                let mut tag = u16_at(offset + 8);
+                // WAVE_FORMAT_EXTENSIBLE keeps the real format at the start of its sub-format GUID
+                if tag == 0xfffe && size >= 26 {
+                    if body.len() < 26 {
+                        return Err("WAV format chunk is cut short".into());
+                    }
+                    tag = u16_at(offset + 32);
+                }
+                format = Some((
+                    tag,
+                    u16_at(offset + 10) as usize,
+                    u32_at(offset + 12),
+                    u16_at(offset + 22),
+                ));
+            }
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        offset = offset.saturating_add(8 + size + size % 2);
+    }
+    let (tag, channels, sample_rate, bits) = format.ok_or("WAV file has no format chunk")?;
+    let data = &bytes[data.ok_or("WAV file has no data chunk")?];
+    let width = bits as usize / 8;
+    if channels == 0 || sample_rate == 0 || width == 0 {
+        return Err("WAV file has an invalid format".into());
+    }
+    if !matches!((tag, bits), (1, 8 | 16 | 24 | 32) | (3, 32)) {
+        return Err(format!("Unsupported WAV format {} with {} bit samples", tag, bits).into());
+    }
+    let sample = |s: &[u8]| match (tag, bits) {
+        (1, 8) => (s[0] as f32 - 128.0) / 128.0,
+        (1, 16) => i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0,
+        // Shifted into the top of an i32 so the sign comes along
+        (1, 24) => i32::from_le_bytes([0, s[0], s[1], s[2]]) as f32 / 2147483648.0,
+        (1, _) => i32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f32 / 2147483648.0,
+        _ => f32::from_le_bytes([s[0], s[1], s[2], s[3]]),
+    };
+    let samples = data
+        .chunks_exact(width * channels)
+        .map(|frame| frame.chunks_exact(width).map(sample).sum::<f32>() / channels as f32)
+        .collect();
+    Ok((samples, sample_rate))
+}
+
+/// Sound file analysed up front and read at the timeline's time, so a sequence render lines up
+/// with the music and comes out the same every time.
+pub struct AudioClip {
+    pub path: PathBuf,
+    sample_rate: u32,
+    /// Band energies every `HOP` samples, divided by the loudest of each band in the clip
+    frames: Vec<Levels>,
+}
+
+impl AudioClip {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let (samples, sample_rate) = decode_wav(&fs::read(path)?)?;
+        let mut frames: Vec<Levels> = (0..samples.len().div_ceil(HOP))
+            .into_par_iter()
+            .map(|i| band_energy(&samples[..(i * HOP + HOP).min(samples.len())], sample_rate))
+            .collect();
+        for band in 0..BANDS {
+            let peak = frames.iter().map(|f| f[band]).fold(0.0, f32::max);
+            if peak > 0.0 {
+                frames.iter_mut().for_each(|f| f[band] /= peak);
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            sample_rate,
+            frames,
+        })
+    }
+    pub fn duration(&self) -> f32 {
+        (self.frames.len() * HOP) as f32 / self.sample_rate as f32
+    }
+    /// Levels `time` seconds in, each band falling away over `release` seconds after a hit.
+    /// Only looks back through the clip so scrubbing gives the same levels as playing.
+    pub fn levels(&self, time: f32, release: f32) -> Levels {
+        let hop_time = HOP as f32 / self.sample_rate as f32;
+        let mut levels = [0.0; BANDS];
+        let current = (time / hop_time) as usize;
+        if current >= self.frames.len() {
+            return levels;
+        }
+        let lookback = ((release * 4.0 / hop_time) as usize).min(current);
+        for age in 0..=lookback {
+            let falloff = (-(age as f32) * hop_time / release.max(1e-3)).exp();
+            for (level, energy) in levels.iter_mut().zip(self.frames[current - age]) {
+                *level = level.max(energy * falloff);
+            }
+        }
+        levels
+    }
+}
+
+/// Default input device, loopback and monitor devices let it follow whatever the system is playing.
+#[cfg(feature = "audio")]
+pub struct LiveInput {
+    pub device: String,
+    _stream: cpal::Stream,
+    /// Newest samples mixed down to mono, at most `WINDOW` of them
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+    peaks: Levels,
+    levels: Levels,
+}
+
+#[cfg(feature = "audio")]
+impl LiveInput {
+    pub fn open() -> Result<Self, Box<dyn Error>> {
+        let device = cpal::default_host()
+            .default_input_device()
+            .ok_or("No audio input device")?;
+        let config = device.default_input_config()?;
+        let samples = Arc::new(Mutex::new(VecDeque::with_capacity(WINDOW)));
+        let stream_config = config.config();
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => {
+                LiveInput::build_stream::<f32>(&device, &stream_config, samples.clone())?
+            }
+            cpal::SampleFormat::I16 => {
+                LiveInput::build_stream::<i16>(&device, &stream_config, samples.clone())?
+            }
+            cpal::SampleFormat::U16 => {
+                LiveInput::build_stream::<u16>(&device, &stream_config, samples.clone())?
+            }
+            format => return Err(format!("Unsupported sample format {:?}", format).into()),
+        };
+        stream.play()?;
+        Ok(Self {
+            device: device
+                .name()
+                .unwrap_or_else(|_| "Unknown device".to_owned()),
+            _stream: stream,
+            samples,
+            sample_rate: stream_config.sample_rate.0,
+            peaks: [MIN_PEAK; BANDS],
+            levels: [0.0; BANDS],
+        })
+    }
+    fn build_stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        samples: Arc<Mutex<VecDeque<f32>>>,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError>
+    where
+        T: cpal::SizedSample,
+        f32: cpal::FromSample<T>,
+    {
+        let channels = config.channels as usize;
+        device.build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                let mut samples = samples.lock().unwrap();
+                for frame in data.chunks(channels) {
+                    let sum: f32 = frame
+                        .iter()
+                        .map(|&s| <f32 as cpal::FromSample<T>>::from_sample_(s))
+                        .sum();
+                    if samples.len() == WINDOW {
+                        samples.pop_front();
+                    }
+                    samples.push_back(sum / channels as f32);
+                }
+            },
+            |e| log::warn!("Audio input failed: {}", e),
+            None,
+        )
+    }
+    /// Levels normalised to each band's recent peak, rising at once and falling over `release`
+    /// seconds.
+    fn update(&mut self, dt: f32, release: f32) -> Levels {
+        let samples: Vec<f32> = self.samples.lock().unwrap().iter().copied().collect();
+        let energy = band_energy(&samples, self.sample_rate);
+        let falloff = (-dt / release.max(1e-3)).exp();
+        for ((peak, level), energy) in self.peaks.iter_mut().zip(&mut self.levels).zip(energy) {
+            *peak = (*peak * PEAK_DECAY.powf(dt)).max(energy).max(MIN_PEAK);
+            *level = (energy / *peak).max(*level * falloff);
+        }
+        self.levels
+    }
+}
+
+pub enum AudioSource {
+    #[cfg(feature = "audio")]
+    Live(LiveInput),
+    Clip(AudioClip),
+}
+
+/// Band levels from live input or a sound file, which drive the scene properties bound to them
+/// in the timeline, see `AudioBinding`.
+pub struct AudioInput {
+    pub source: Option<AudioSource>,
+    /// Path typed into the audio panel for `open_clip`
+    pub clip_path: String,
+    /// Multiplies every level before it is clamped to 1
+    pub gain: f32,
+    /// Seconds a level takes to fall most of the way back after a hit
+    pub release: f32,
+    pub levels: Levels,
+    /// Property bound by the audio panel's Bind button
+    pub bind_property: AnimProperty,
+    /// Why the last source failed to open
+    pub error: Option<String>,
+}
+
+impl Default for AudioInput {
+    fn default() -> Self {
+        Self {
+            source: None,
+            clip_path: String::new(),
+            gain: 1.0,
+            release: 0.25,
+            levels: [0.0; BANDS],
+            bind_property: AnimProperty::EmissionStrength,
+            error: None,
+        }
+    }
+}
+
+impl AudioInput {
+    #[cfg(feature = "audio")]
+    pub fn open_live(&mut self) {
+        match LiveInput::open() {
+            Ok(input) => {
+                log::info!("Listening to {}", input.device);
+                self.source = Some(AudioSource::Live(input));
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+    pub fn open_clip(&mut self) {
+        match AudioClip::load(Path::new(&self.clip_path)) {
+            Ok(clip) => {
+                log::info!("Loaded {} ({:.1}s)", clip.path.display(), clip.duration());
+                self.source = Some(AudioSource::Clip(clip));
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("{}: {}", self.clip_path, e)),
+        }
+    }
+    pub fn close(&mut self) {
+        self.source = None;
+        self.levels = [0.0; BANDS];
+    }
+    /// Reads the source, a clip at `time` seconds into the timeline. Returns whether any level
+    /// changed, so a still clip doesn't keep resetting the accumulation.
+    #[cfg_attr(not(feature = "audio"), allow(unused_variables))]
+    pub fn update(&mut self, dt: f32, time: f32) -> bool {
+        let levels = match self.source.as_mut() {
+            None => return false,
+            #[cfg(feature = "audio")]
+            Some(AudioSource::Live(input)) => input.update(dt, self.release),
+            Some(AudioSource::Clip(clip)) => clip.levels(time, self.release),
+        };
+        let levels = levels.map(|level| (level * self.gain).clamp(0.0, 1.0));
+        let changed = levels != self.levels;
+        self.levels = levels;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(fmt: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend(b"fmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend(fmt);
+        if !data.is_empty() {
+            bytes.extend(b"data");
+            bytes.extend((data.len() as u32).to_le_bytes());
+            bytes.extend(data);
+        }
+        bytes
+    }
+
+    /// PCM, mono, 8 kHz, 16 bit
+    const FORMAT: [u8; 16] = [1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x80, 0x3e, 0, 0, 2, 0, 16, 0];
+
+    #[test]
+    fn decodes_pcm() {
+        let (samples, rate) = decode_wav(&wav(&FORMAT, &[0, 0x40, 0, 0xc0])).unwrap();
+        assert_eq!(rate, 8000);
+        assert_eq!(samples, [0.5, -0.5]);
+    }
+
+    #[test]
+    fn truncated_format_chunk_is_an_error() {
+        for len in 0..FORMAT.len() {
+            assert!(decode_wav(&wav(&FORMAT[..len], &[])).is_err());
+        }
+        let mut extensible = FORMAT;
+        extensible[0..2].copy_from_slice(&0xfffeu16.to_le_bytes());
+        let mut bytes = wav(&extensible, &[]);
+        bytes[16..20].copy_from_slice(&40u32.to_le_bytes());
+        bytes.extend([22, 0, 16, 0]);
+        assert!(decode_wav(&bytes).is_err());
+    }
+}
@@ -1,21 +1,16 @@
-use std::{collections::HashMap, sync::Arc, time::Instant};
+use std::{collections::HashMap, sync::Arc};
 
 use glam::Vec3;
-use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
+pub use crate::bvh::{Aabb, Node, Quality};
+use crate::bvh::{Bvh, validate_nodes};
+use crate::core::cache;
 use crate::scene::components::geometry::{
-    mesh::{MeshInstance, MeshUniform},
+    mesh::{MeshData, MeshInstance, MeshUniform},
     vertex::Vertex,
 };
 
-#[derive(Debug, Copy, Clone, Default)]
-pub struct BVHTriangle {
-    pub centroid: Vec3,
-    pub min: Vec3,
-    pub max: Vec3,
-    pub i: i32,
-}
-
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
 pub struct PackedTriangle {
@@ -31,6 +26,11 @@ pub struct PackedTriangle {
     pub uv30: f32,
     pub n3: [f32; 3],
     pub uv31: f32,
+    /// Vertex colours as sRGB bytes, decoded alongside the textures
+    pub c1: u32,
+    pub c2: u32,
+    pub c3: u32,
+    _p1: u32,
 }
 
 impl PackedTriangle {
@@ -48,70 +48,175 @@ impl PackedTriangle {
             uv21: v2.uv[1],
             uv30: v3.uv[0],
             uv31: v3.uv[1],
+            c1: pack_color(v1.color),
+            c2: pack_color(v2.color),
+            c3: pack_color(v3.color),
+            _p1: 0,
         }
     }
 }
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
-pub struct Node {
-    pub left: u32,
-    pub right: u32,
-    pub first: u32,
-    pub count: u32,
-    pub aabb_min: [f32; 3],
-    _p1: f32,
-    pub aabb_max: [f32; 3],
-    _p2: f32,
+/// Quantises an sRGB colour to bytes, red in the lowest to match `unpack4x8unorm`.
+fn pack_color(color: Vec3) -> u32 {
+    let [r, g, b] = color
+        .clamp(Vec3::ZERO, Vec3::ONE)
+        .to_array()
+        .map(|c| (c * 255.0).round() as u32);
+    r | g << 8 | b << 16 | 255 << 24
 }
 
-impl Node {
-    pub fn cost(&self) -> f32 {
-        let e = Vec3::from_array(self.aabb_max) - Vec3::from_array(self.aabb_min);
-        let half_area = e.x * e.y + e.y * e.z + e.x * e.z;
-        half_area * self.count as f32
-    }
+/// Builds the BVHs of meshes with `crate::bvh`, packing their triangles in leaf order for the shader.
+#[derive(Debug)]
+pub struct BVH;
+
+#[derive(Debug)]
+pub struct MeshDataList {
+    pub triangles: Vec<PackedTriangle>,
+    pub nodes: Vec<Node>,
+    pub mesh_uniforms: Vec<MeshUniform>,
+    /// What each mesh's BVH was built from, compared against the instance to spot stale BVHs
+    pub built: Vec<BuiltBlas>,
+    /// Index of the mesh whose nodes and triangles each mesh traverses, itself unless it is an
+    /// instance sharing an earlier mesh's BVH. Meshes with their own are laid out in index order.
+    pub blas_owner: Vec<usize>,
+    pub degenerate_triangles: u32,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct Aabb {
-    pub min: Vec3,
-    pub max: Vec3,
+/// Geometry and instance scale a mesh's BVH was built for.
+#[derive(Debug, Clone)]
+pub struct BuiltBlas {
+    pub data: Arc<MeshData>,
+    pub scale: Vec3,
 }
 
-impl Aabb {
-    pub fn grow(&mut self, p: &BVHTriangle) {
-        self.min = self.min.min(p.min);
-        self.max = self.max.max(p.max);
+impl BuiltBlas {
+    pub fn new(mesh: &MeshInstance, scale: Vec3) -> Self {
+        Self {
+            data: mesh.data.clone(),
+            scale,
+        }
     }
-    pub fn half_area(&self) -> f32 {
-        let e = self.max - self.min;
-        e.x * e.y + e.y * e.z + e.x * e.z
+    /// Whether the BVH no longer suits the instance. Moving, rotating or uniformly scaling only
+    /// needs new matrices, but replaced geometry needs a rebuild, as does stretching one axis
+    /// against another by more than `BVH::REBUILD_DISTORTION` since the build, which skews the
+    /// surface areas the splits were chosen by.
+    pub fn needs_rebuild(&self, mesh: &MeshInstance) -> bool {
+        if !Arc::ptr_eq(&self.data, &mesh.data) {
+            return true;
+        }
+        let ratio = (mesh.transform.scale / self.scale).abs();
+        if !ratio.is_finite() || ratio.min_element() <= 0.0 {
+            return false;
+        }
+        ratio.max_element() / ratio.min_element() > BVH::REBUILD_DISTORTION
     }
 }
-impl Default for Aabb {
-    fn default() -> Self {
-        Self {
-            min: Vec3::INFINITY,
-            max: Vec3::NEG_INFINITY,
+impl MeshDataList {
+    /// Refreshes matrices and materials after instances change, without rebuilding any BVH.
+    pub fn update_instances(&mut self, meshes: &[MeshInstance]) {
+        for (uniform, mesh) in self.mesh_uniforms.iter_mut().zip(meshes) {
+            let model_to_world = mesh.transform.to_matrix();
+            uniform.world_to_model = model_to_world.inverse().to_cols_array_2d();
+            uniform.model_to_world = model_to_world.to_cols_array_2d();
+            uniform.material = mesh.material;
+            uniform.uv_view = mesh.uv_view as u32;
+            uniform.rect_size = mesh.rect_light.unwrap_or_default();
+        }
+    }
+    /// Whether a mesh traverses another's BVH or has others traversing its own, in which case it
+    /// can't be replaced or removed on its own.
+    pub fn is_shared(&self, index: usize) -> bool {
+        self.blas_owner[index] != index
+            || self
+                .blas_owner
+                .iter()
+                .enumerate()
+                .any(|(other, &owner)| owner == index && other != index)
+    }
+    /// Appends a mesh whose BVH was built elsewhere, such as a streamed chunk, leaving the rest as is.
+    pub fn push_mesh(
+        &mut self,
+        mesh: &MeshInstance,
+        mut triangles: Vec<PackedTriangle>,
+        mut nodes: Vec<Node>,
+    ) {
+        self.blas_owner.push(self.mesh_uniforms.len());
+        let model_to_world = mesh.transform.to_matrix();
+        self.mesh_uniforms.push(MeshUniform {
+            world_to_model: model_to_world.inverse().to_cols_array_2d(),
+            model_to_world: model_to_world.to_cols_array_2d(),
+            node_offset: self.nodes.len() as u32,
+            triangle_offset: self.triangles.len() as u32,
+            triangles: triangles.len() as u32,
+            material: mesh.material,
+            uv_view: mesh.uv_view as u32,
+            rect_size: mesh.rect_light.unwrap_or_default(),
+            _p1: [0.0; 2],
+        });
+        self.built.push(BuiltBlas::new(mesh, Vec3::ONE));
+        self.triangles.append(&mut triangles);
+        self.nodes.append(&mut nodes);
+    }
+    /// Swaps in a rebuilt BVH for one mesh that isn't `is_shared`, shifting the offsets of the
+    /// meshes after it.
+    pub fn replace_mesh(
+        &mut self,
+        index: usize,
+        triangles: Vec<PackedTriangle>,
+        nodes: Vec<Node>,
+        built: BuiltBlas,
+    ) {
+        let (node_range, triangle_range) = self.mesh_ranges(index);
+        let node_delta = nodes.len() as i64 - node_range.len() as i64;
+        let triangle_delta = triangles.len() as i64 - triangle_range.len() as i64;
+        self.mesh_uniforms[index].triangles = triangles.len() as u32;
+        self.nodes.splice(node_range, nodes);
+        self.triangles.splice(triangle_range, triangles);
+        for (later, &owner) in self.mesh_uniforms.iter_mut().zip(&self.blas_owner) {
+            if owner > index {
+                later.node_offset = (later.node_offset as i64 + node_delta) as u32;
+                later.triangle_offset = (later.triangle_offset as i64 + triangle_delta) as u32;
+            }
+        }
+        self.built[index] = built;
+    }
+    /// Node and triangle ranges a mesh occupies in the shared buffers, empty for instances
+    /// traversing another mesh's.
+    fn mesh_ranges(&self, index: usize) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let uniform = &self.mesh_uniforms[index];
+        if self.blas_owner[index] != index {
+            let (nodes, triangles) = (
+                uniform.node_offset as usize,
+                uniform.triangle_offset as usize,
+            );
+            return (nodes..nodes, triangles..triangles);
+        }
+        let node_end = (index + 1..self.mesh_uniforms.len())
+            .find(|&next| self.blas_owner[next] == next)
+            .map_or(self.nodes.len(), |next| {
+                self.mesh_uniforms[next].node_offset as usize
+            });
+        let triangles = uniform.triangle_offset as usize
+            ..(uniform.triangle_offset + uniform.triangles) as usize;
+        (uniform.node_offset as usize..node_end, triangles)
+    }
+    /// Drops the triangles and nodes of a mesh that isn't `is_shared`, shifting the offsets of the
+    /// meshes after it.
+    pub fn remove_mesh(&mut self, index: usize) {
+        let (nodes, triangles) = self.mesh_ranges(index);
+        self.mesh_uniforms.remove(index);
+        self.built.remove(index);
+        self.blas_owner.remove(index);
+        self.nodes.drain(nodes.clone());
+        self.triangles.drain(triangles.clone());
+        for (later, owner) in self.mesh_uniforms.iter_mut().zip(&mut self.blas_owner) {
+            if *owner > index {
+                *owner -= 1;
+                later.node_offset -= nodes.len() as u32;
+                later.triangle_offset -= triangles.len() as u32;
+            }
         }
     }
-}
-
-#[derive(Debug)]
-pub struct BVH {
-    pub build_triangles: Vec<BVHTriangle>,
-    pub packed_triangles: Vec<PackedTriangle>,
-    pub nodes: Vec<Node>,
-    pub n_nodes: u32,
-    pub quality: Quality,
-}
-
-#[derive(Debug)]
-pub struct MeshDataList {
-    pub triangles: Vec<PackedTriangle>,
-    pub nodes: Vec<Node>,
-    pub mesh_uniforms: Vec<MeshUniform>,
 }
 impl Default for MeshDataList {
     fn default() -> Self {
@@ -119,58 +224,56 @@ impl Default for MeshDataList {
             triangles: vec![],
             nodes: vec![],
             mesh_uniforms: vec![],
+            built: vec![],
+            blas_owner: vec![],
+            degenerate_triangles: 0,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum Quality {
-    Low,
-    High,
-    Disabled,
-}
-
-impl Default for Quality {
-    fn default() -> Self {
-        Quality::High
-    }
-}
-
 impl BVH {
     pub const MAX_NODES: u32 = 520000 * 5;
-    pub const MAX_DEPTH: u64 = 32;
-    pub const TEST_SPLITS: u32 = 50;
-    pub fn empty() -> Self {
-        Self {
-            build_triangles: vec![],
-            packed_triangles: vec![],
-            nodes: vec![],
-            n_nodes: 0,
-            quality: Quality::Disabled,
-        }
-    }
+    /// Smaller meshes build faster than their cache entry loads
+    pub const MIN_CACHED_TRIANGLES: usize = 4096;
+    /// How far an instance may be stretched along one axis relative to another before its BVH is rebuilt
+    pub const REBUILD_DISTORTION: f32 = 2.0;
     pub fn build_per_mesh(meshes: &[MeshInstance], quality: Quality) -> MeshDataList {
         log::info!("Building BVH [Quality: {:#?}]", quality);
         let mut data = MeshDataList::default();
         let mut mesh_lookup: HashMap<String, (usize, usize)> = HashMap::new();
+        data.blas_owner = BVH::blas_owners(meshes);
 
-        let mesh_results: Vec<(MeshInstance, Vec<PackedTriangle>, Vec<Node>)> = meshes
+        let mesh_results: Vec<(MeshInstance, Vec<PackedTriangle>, Vec<Node>, u32)> = meshes
             .par_iter()
-            .map(|mesh_instance| {
-                let mut stats = BVHStats::start();
-                let bvh = BVH::build(
-                    mesh_instance.data.vertices.clone(),
-                    mesh_instance.data.indices.clone(),
-                    quality,
-                    &mut stats,
-                );
-                (mesh_instance.clone(), bvh.packed_triangles, bvh.nodes)
+            .enumerate()
+            .map(|(i, mesh_instance)| {
+                if data.blas_owner[i] != i {
+                    return (mesh_instance.clone(), vec![], vec![], 0);
+                }
+                let (triangles, nodes, degenerate_triangles) =
+                    BVH::build_cached(mesh_instance, quality);
+                if degenerate_triangles > 0 {
+                    log::warn!(
+                        "Removed {} degenerate triangles from {}",
+                        degenerate_triangles,
+                        mesh_instance.label.as_deref().unwrap_or("mesh")
+                    );
+                }
+                (
+                    mesh_instance.clone(),
+                    triangles,
+                    nodes,
+                    degenerate_triangles,
+                )
             })
             .collect();
         let mut triangle_offset = 0;
         let mut node_offset = 0;
 
-        for (i, (mesh_instance, mut triangles, mut nodes)) in mesh_results.into_iter().enumerate() {
+        for (i, (mesh_instance, mut triangles, mut nodes, degenerate)) in
+            mesh_results.into_iter().enumerate()
+        {
+            data.degenerate_triangles += degenerate;
             let num_triangles = triangles.len() as u32;
             let num_nodes = nodes.len();
 
@@ -188,16 +291,27 @@ impl BVH {
 
             // Compute model matrix
             let model_to_world = mesh_instance.transform.to_matrix();
+            // Instances point at the BVH already laid out for their owner
+            let (blas_nodes, blas_triangles, blas_count) = match data.blas_owner[i] {
+                owner if owner == i => (node_offset as u32, triangle_offset as u32, num_triangles),
+                owner => {
+                    let shared = &data.mesh_uniforms[owner];
+                    (shared.node_offset, shared.triangle_offset, shared.triangles)
+                }
+            };
             let mesh_uniform = MeshUniform {
                 world_to_model: model_to_world.inverse().to_cols_array_2d(),
                 model_to_world: model_to_world.to_cols_array_2d(),
-                node_offset: node_offset as u32,
-                triangle_offset: triangle_offset as u32,
-                triangles: num_triangles,
+                node_offset: blas_nodes,
+                triangle_offset: blas_triangles,
+                triangles: blas_count,
                 material: mesh_instance.material,
-                ..Default::default()
+                uv_view: mesh_instance.uv_view as u32,
+                rect_size: mesh_instance.rect_light.unwrap_or_default(),
+                _p1: [0.0; 2],
             };
             data.mesh_uniforms.push(mesh_uniform);
+            data.built.push(BuiltBlas::new(&mesh_instance, Vec3::ONE));
 
             triangle_offset += num_triangles as usize;
             node_offset += num_nodes;
@@ -205,326 +319,122 @@ impl BVH {
 
         data
     }
-    pub fn build(
-        vertices: Arc<Vec<Vertex>>,
-        indices: Arc<Vec<u32>>,
-        quality: Quality,
-        stats: &mut BVHStats,
-    ) -> Self {
-        let n_tris = indices.len() / 3;
-        let packed_triangles = Vec::with_capacity(n_tris);
-        if n_tris == 0 {
-            return Self::empty();
-        }
-
-        let positions = (indices.len() + 3 - 1) / 3;
-        let build_triangles: Vec<BVHTriangle> = (0..positions)
-            .into_par_iter()
-            .map(|j| {
-                let i = j * 3;
-                let index1 = indices[i + 0] as usize;
-                let index2 = indices[i + 1] as usize;
-                let index3 = indices[i + 2] as usize;
-
-                let v1 = vertices[index1].pos;
-                let v2 = vertices[index2].pos;
-                let v3 = vertices[index3].pos;
-
-                let centroid = (v1 + v2 + v3) * (1.0 / 3.0);
-
-                BVHTriangle {
-                    centroid: centroid,
-                    max: v1.max(v2.max(v3)),
-                    min: v1.min(v2.min(v3)),
-                    i: i as i32,
-                }
-            })
-            .collect();
-
-        let mut min: [f32; 3] = [f32::MAX; 3];
-        let mut max: [f32; 3] = [f32::MIN; 3];
-
-        // Fit bounding box
-        for tri in build_triangles.iter() {
-            BVH::fit_bounds(&mut min, &mut max, &tri);
-        }
-
-        let mut nodes = Vec::new();
-        nodes.push(Node {
-            aabb_min: min,
-            aabb_max: max,
-            left: 0,
-            right: 0,
-            first: 0,
-            count: n_tris as u32,
-            ..Default::default()
-        });
-
-        let mut bvh = Self {
-            build_triangles,
-            nodes,
-            packed_triangles,
-            n_nodes: 1,
-            quality,
-        };
-        match quality {
-            Quality::Disabled => {
-                return bvh;
-            }
-            _ => {
-                bvh.subdivide(0, 0, bvh.build_triangles.len(), 0, stats);
-            }
-        }
-        bvh.packed_triangles = (0..bvh.build_triangles.len())
-            .into_par_iter()
-            .map(|i| {
-                let built_tri = bvh.build_triangles[i];
-                let vert1 = vertices[indices[built_tri.i as usize + 0] as usize];
-                let vert2 = vertices[indices[built_tri.i as usize + 1] as usize];
-                let vert3 = vertices[indices[built_tri.i as usize + 2] as usize];
-                PackedTriangle::new(vert1, vert2, vert3)
-            })
-            .collect();
-
-        bvh
-    }
-
-    pub fn fit_bounds(min: &mut [f32; 3], max: &mut [f32; 3], tri: &BVHTriangle) {
-        for axis in 0..3 {
-            min[axis] = min[axis].min(tri.min[axis]);
-            max[axis] = max[axis].max(tri.max[axis]);
+    /// Which mesh's BVH each mesh can traverse, see `MeshDataList::blas_owner`. Instances of the same
+    /// geometry share the first one's, unless stretched enough to want their own.
+    fn blas_owners(meshes: &[MeshInstance]) -> Vec<usize> {
+        let undistorted =
+            |mesh: &MeshInstance| !BuiltBlas::new(mesh, Vec3::ONE).needs_rebuild(mesh);
+        let mut owners: Vec<usize> = Vec::with_capacity(meshes.len());
+        for (i, mesh) in meshes.iter().enumerate() {
+            let owner = match undistorted(mesh) {
+                true => (0..i).find(|&other| {
+                    owners[other] == other
+                        && Arc::ptr_eq(&meshes[other].data, &mesh.data)
+                        && undistorted(&meshes[other])
+                }),
+                false => None,
+            };
+            owners.push(owner.unwrap_or(i));
         }
+        owners
     }
-
-    pub fn find_best_split(
-        &self,
-        node: &Node,
-        axis: &mut usize,
-        split_pos: &mut f32,
-        start: usize,
-        count: usize,
-    ) -> f32 {
-        if node.count <= 1 {
-            *axis = 0;
-            *split_pos = 0.0;
-            return f32::INFINITY;
+    /// Builds a mesh's BVH, or loads it from the cache when the same geometry was built before.
+    pub fn build_cached(
+        mesh: &MeshInstance,
+        quality: Quality,
+    ) -> (Vec<PackedTriangle>, Vec<Node>, u32) {
+        if mesh.data.indices.len() / 3 < BVH::MIN_CACHED_TRIANGLES {
+            return BVH::build(&mesh.data.vertices, &mesh.data.indices, quality);
         }
-        let bounds = (Vec3::from_array(node.aabb_max) - Vec3::from_array(node.aabb_min)).to_array();
-        return match self.quality {
-            Quality::Low => {
-                *axis = if bounds[0] > bounds[1] && bounds[0] > bounds[2] {
-                    0
-                } else {
-                    if bounds[1] > bounds[2] { 1 } else { 2 }
-                };
-                *split_pos = node.aabb_min[*axis] + bounds[*axis] * 0.5;
-                self.evaluate_sah(axis.clone() as i32, split_pos.clone(), start, count)
-            }
-            Quality::High => {
-                let mut best_cost = f32::INFINITY;
-                let max_axis = bounds[0].max(bounds[1].max(bounds[2]));
-                for a in 0..3 {
-                    let axis_size = bounds[a];
-                    let axis_min = node.aabb_min[a];
-                    if axis_size == 0.0 {
-                        continue;
-                    }
-                    let n_split_tests = ((axis_size / max_axis * BVH::TEST_SPLITS as f32).ceil()
-                        as u32)
-                        .clamp(1, BVH::TEST_SPLITS);
-
-                    for i in 0..n_split_tests {
-                        let split_t = (i + 1) as f32 / (n_split_tests as f32 + 1.0);
-                        let test_split_pos = axis_min + axis_size * split_t;
-                        let cost = self.evaluate_sah(a as i32, test_split_pos, start, count);
-                        if cost < best_cost {
-                            *split_pos = test_split_pos;
-                            *axis = a;
-                            best_cost = cost;
-                        }
-                    }
+        let mut source = cache::Writer::default();
+        source.vertices(&mesh.data.vertices);
+        source.pod_slice(&mesh.data.indices);
+        let key = cache::hash(&[&source.bytes, &[quality as u8]]);
+        if let Some(bytes) = cache::load("bvh", key) {
+            let mut reader = cache::Reader::new(&bytes);
+            if let (Some(triangles), Some(nodes), Some(degenerate_triangles)) =
+                (reader.pod_slice(), reader.pod_slice(), reader.u32())
+            {
+                let expected =
+                    (mesh.data.indices.len() / 3).checked_sub(degenerate_triangles as usize);
+                match BVH::validate(&triangles, &nodes, expected) {
+                    Ok(()) => return (triangles, nodes, degenerate_triangles),
+                    Err(e) => log::warn!("Rebuilding invalid cached BVH: {}", e),
                 }
-                best_cost
-            }
-            Quality::Disabled => f32::INFINITY,
-        };
-    }
-    pub fn evaluate_sah(&self, axis: i32, pos: f32, start: usize, count: usize) -> f32 {
-        let mut left_bounds = Aabb::default();
-        let mut right_bounds = Aabb::default();
-        let mut left_count = 0.0;
-        let mut right_count = 0.0;
-        let end = start + count;
-        for i in start..end {
-            let tri = &self.build_triangles[i];
-            if tri.centroid[axis as usize] < pos {
-                left_count += 1.0;
-                left_bounds.grow(&tri);
-            } else {
-                right_count += 1.0;
-                right_bounds.grow(&tri);
             }
         }
-        let cost = left_count * left_bounds.half_area() + right_count * right_bounds.half_area();
-        cost
-    }
-
-    pub fn subdivide(
-        &mut self,
-        node_idx: usize,
-        tri_global_start: usize,
-        n_tris: usize,
-        depth: u64,
-        stats: &mut BVHStats,
-    ) {
-        let parent_cost = self.nodes[node_idx].cost();
-
-        let mut axis = 0;
-        let mut split_pos = 0.0;
-        let cost = self.find_best_split(
-            &self.nodes[node_idx],
-            &mut axis,
-            &mut split_pos,
-            tri_global_start,
-            n_tris,
-        );
-        if cost < parent_cost && depth < BVH::MAX_DEPTH {
-            let mut left_min: [f32; 3] = [f32::MAX; 3];
-            let mut left_max: [f32; 3] = [f32::MIN; 3];
-
-            let mut right_min: [f32; 3] = [f32::MAX; 3];
-            let mut right_max: [f32; 3] = [f32::MIN; 3];
-
-            let mut left_count = 0;
-
-            for i in tri_global_start..tri_global_start + n_tris {
-                let tri = &self.build_triangles[i];
-
-                if tri.centroid[axis] < split_pos {
-                    BVH::fit_bounds(&mut left_min, &mut left_max, &tri);
-                    self.build_triangles
-                        .swap(tri_global_start + left_count, i as usize);
-                    left_count += 1;
-                } else {
-                    BVH::fit_bounds(&mut right_min, &mut right_max, &tri);
-                }
-            }
-            let right_count = (n_tris - left_count) as u32;
-            let left_first = tri_global_start as u32;
-            let right_first = left_first + left_count as u32;
 
-            let left_index = self.n_nodes;
-            let right_index = self.n_nodes + 1;
-            self.n_nodes += 2;
-
-            // TODO: resize nodes vector if needed
-
-            {
-                // Left child
-                self.nodes.push(Node {
-                    aabb_min: left_min,
-                    aabb_max: left_max,
-                    left: 0,
-                    right: 0,
-                    first: left_first,
-                    count: left_count as u32,
-                    ..Default::default()
-                })
-            }
-            {
-                // Right child
-                self.nodes.push(Node {
-                    aabb_min: right_min,
-                    aabb_max: right_max,
-                    left: 0,
-                    right: 0,
-                    first: right_first,
-                    count: right_count,
-                    ..Default::default()
-                })
-            }
-            {
-                let parent = &mut self.nodes[node_idx];
-                parent.left = left_index;
-                parent.right = right_index;
-                parent.count = 0;
-                stats.record_node();
-            }
-            self.subdivide(
-                left_index as usize,
-                tri_global_start,
-                left_count,
-                depth + 1,
-                stats,
-            );
-            self.subdivide(
-                right_index as usize,
-                tri_global_start + left_count,
-                right_count as usize,
-                depth + 1,
-                stats,
-            );
-        } else {
-            stats.record_leaf_node(n_tris as u32, depth as u32);
-        }
+        let (triangles, nodes, degenerate_triangles) =
+            BVH::build(&mesh.data.vertices, &mesh.data.indices, quality);
+        let mut writer = cache::Writer::default();
+        writer.pod_slice(&triangles);
+        writer.pod_slice(&nodes);
+        writer.u32(degenerate_triangles);
+        cache::store("bvh", key, &writer.bytes);
+        (triangles, nodes, degenerate_triangles)
     }
-}
-
-#[allow(unused)]
-pub struct BVHStats {
-    start_time: Instant,
-    leaf_count: u32,
-    leaf_min_depth: u32,
-    leaf_max_depth: u32,
-    sum_depth: f32,
-    min_tris: u32,
-    max_tris: u32,
-    sum_tris: f32,
-    node_count: u32,
-}
-
-#[allow(unused)]
-impl BVHStats {
-    pub fn start() -> Self {
-        Self {
-            start_time: Instant::now(),
-            leaf_count: 0,
-            leaf_min_depth: u32::MAX,
-            leaf_max_depth: 0,
-            sum_depth: 0.0,
-            min_tris: u32::MAX,
-            max_tris: 0,
-            sum_tris: 0.0,
-            node_count: 0,
+    /// Builds a mesh's BVH with the splits chosen for how it looks under `scale`, so a stretched
+    /// instance isn't traversed with a tree fitted to its unstretched shape. The nodes and triangles
+    /// are still in model space, as the shader expects.
+    pub fn build_scaled(
+        mesh: &MeshInstance,
+        quality: Quality,
+        scale: Vec3,
+    ) -> (Vec<PackedTriangle>, Vec<Node>, u32) {
+        let vertices = &mesh.data.vertices;
+        let indices = &mesh.data.indices;
+        let scaled: Vec<Vec3> = vertices.iter().map(|v| v.pos * scale).collect();
+        let mut bvh = Bvh::build_indexed(&scaled, indices, quality);
+        let inverse = Vec3::ONE / scale;
+        for node in &mut bvh.nodes {
+            let a = Vec3::from(node.aabb_min) * inverse;
+            let b = Vec3::from(node.aabb_max) * inverse;
+            node.aabb_min = a.min(b).to_array();
+            node.aabb_max = a.max(b).to_array();
         }
+        let triangles = BVH::pack(vertices, indices, &bvh.order);
+        (triangles, bvh.nodes, bvh.degenerate)
     }
-
-    pub fn record_leaf_node(&mut self, triangle_count: u32, depth: u32) {
-        self.record_node();
-        self.leaf_count += 1;
-        self.sum_depth += depth as f32;
-        self.leaf_min_depth = self.leaf_min_depth.min(depth);
-        self.leaf_max_depth = self.leaf_max_depth.max(depth);
-        self.sum_tris += triangle_count as f32;
-        self.max_tris = self.max_tris.max(triangle_count);
-        self.min_tris = self.min_tris.min(triangle_count);
+    /// Checks a BVH read back from the cache has as many triangles as the mesh it was built from
+    /// and can be traversed safely, see `validate_nodes`.
+    fn validate(
+        triangles: &[PackedTriangle],
+        nodes: &[Node],
+        expected_triangles: Option<usize>,
+    ) -> Result<(), String> {
+        if expected_triangles != Some(triangles.len()) {
+            return Err(format!(
+                "{} triangles, expected {:?}",
+                triangles.len(),
+                expected_triangles
+            ));
+        }
+        validate_nodes(nodes, triangles.len())
     }
-    pub fn record_node(&mut self) {
-        self.node_count += 1;
+    /// Builds a mesh's BVH from scratch, returning its packed triangles, nodes and how many
+    /// degenerate triangles were left out.
+    pub fn build(
+        vertices: &[Vertex],
+        indices: &[u32],
+        quality: Quality,
+    ) -> (Vec<PackedTriangle>, Vec<Node>, u32) {
+        let positions: Vec<Vec3> = vertices.iter().map(|v| v.pos).collect();
+        let bvh = Bvh::build_indexed(&positions, indices, quality);
+        let triangles = BVH::pack(vertices, indices, &bvh.order);
+        (triangles, bvh.nodes, bvh.degenerate)
     }
-    pub fn print(&self) {
-        let now = Instant::now();
-        println!("BVH: ({:#?})", now - self.start_time);
-        println!("Node Count: {}", self.node_count);
-        println!("Leaf Count: {}", self.leaf_count);
-        println!("Leaf Depth:");
-        println!(" - Max: {}", self.leaf_max_depth);
-        println!(" - Min: {}", self.leaf_min_depth);
-        println!(" - Mean: {}", self.sum_depth / self.leaf_count as f32);
-        println!("Leaf Triangles: ");
-        println!(" - Max: {}", self.max_tris);
-        println!(" - Min: {}", self.min_tris);
-        println!(" - mean: {}", self.sum_tris / self.leaf_count as f32);
-        println!(" - Total: {}", self.sum_tris);
+    /// Triangles in the order a BVH's leaves refer to them.
+    fn pack(vertices: &[Vertex], indices: &[u32], order: &[u32]) -> Vec<PackedTriangle> {
+        order
+            .par_iter()
+            .map(|&triangle| {
+                let i = triangle as usize * 3;
+                PackedTriangle::new(
+                    vertices[indices[i] as usize],
+                    vertices[indices[i + 1] as usize],
+                    vertices[indices[i + 2] as usize],
+                )
+            })
+            .collect()
     }
 }
@@ -0,0 +1,207 @@
+use std::{
+    hash::{DefaultHasher, Hasher},
+    path::{Path, PathBuf},
+};
+
+use crate::scene::components::geometry::vertex::Vertex;
+
+/// Processed meshes and BVHs are cached here between runs, delete it to force a rebuild.
+pub const CACHE_DIR: &str = "cache";
+const MAGIC: [u8; 4] = *b"RTC1";
+/// Bump whenever the layout of anything cached changes, older files are then ignored
+const VERSION: u32 = 10;
+/// Magic, version, then the uncompressed length and checksum of the data
+const HEADER_LEN: usize = 24;
+
+/// Hashes everything a cached result was derived from. Only stable for a given build,
+/// so a new toolchain simply misses the cache once.
+pub fn hash(parts: &[&[u8]]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write_u32(VERSION);
+    for part in parts {
+        hasher.write_usize(part.len());
+        hasher.write(part);
+    }
+    hasher.finish()
+}
+
+fn path(kind: &str, key: u64) -> PathBuf {
+    Path::new(CACHE_DIR).join(format!("{}_{:016x}.bin", kind, key))
+}
+
+/// FNV-1a, stable across builds unlike `hash`, so a checksum never misses because of the toolchain.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Reads and decompresses a cache entry, `None` if it is missing, outdated or corrupt.
+pub fn load(kind: &str, key: u64) -> Option<Vec<u8>> {
+    let path = path(kind, key);
+    let bytes = std::fs::read(&path).ok()?;
+    if bytes.len() < 8 || bytes[..4] != MAGIC {
+        log::warn!("Ignoring cache {}: not a cache file", path.display());
+        return None;
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if version != VERSION {
+        log::info!(
+            "Ignoring cache {}: version {} is not {}",
+            path.display(),
+            version,
+            VERSION
+        );
+        return None;
+    }
+    if bytes.len() < HEADER_LEN {
+        log::warn!("Ignoring cache {}: truncated header", path.display());
+        return None;
+    }
+    let len = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+    let sum = u64::from_le_bytes(bytes[16..24].try_into().unwrap());
+    let data = miniz_oxide::inflate::decompress_to_vec(&bytes[HEADER_LEN..]).ok();
+    match data {
+        Some(data) if data.len() as u64 == len && checksum(&data) == sum => Some(data),
+        _ => {
+            log::warn!("Ignoring cache {}: checksum mismatch", path.display());
+            None
+        }
+    }
+}
+
+/// Compresses and writes a cache entry. Failures only cost the next run a rebuild, so they are logged and ignored.
+pub fn store(kind: &str, key: u64, data: &[u8]) {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + data.len() / 2);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&checksum(data).to_le_bytes());
+    bytes.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(data, 1));
+    let path = path(kind, key);
+    // Written beside the target then renamed so a crash never leaves a truncated entry
+    let tmp = path.with_extension("tmp");
+    let result = std::fs::create_dir_all(CACHE_DIR)
+        .and_then(|_| std::fs::write(&tmp, bytes))
+        .and_then(|_| std::fs::rename(&tmp, &path));
+    if let Err(e) = result {
+        log::warn!("Failed to write cache {}: {}", path.display(), e);
+    }
+}
+
+/// Little endian encoder for cache entries.
+#[derive(Default)]
+pub struct Writer {
+    pub bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u32(&mut self, v: u32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn f32(&mut self, v: f32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn string(&mut self, v: &str) {
+        self.u32(v.len() as u32);
+        self.bytes.extend_from_slice(v.as_bytes());
+    }
+    pub fn option_string(&mut self, v: Option<&str>) {
+        match v {
+            Some(v) => {
+                self.u32(1);
+                self.string(v);
+            }
+            None => self.u32(0),
+        }
+    }
+    /// Plain old data made of 4 byte fields, written word by word as little endian
+    pub fn pod_slice<T: bytemuck::Pod>(&mut self, v: &[T]) {
+        self.u32(v.len() as u32);
+        let words: &[u32] = bytemuck::cast_slice(v);
+        self.bytes
+            .extend(words.iter().flat_map(|w| w.to_le_bytes()));
+    }
+    pub fn vertices(&mut self, v: &[Vertex]) {
+        self.u32(v.len() as u32);
+        for vertex in v {
+            for f in vertex.pos.to_array() {
+                self.f32(f);
+            }
+            for f in vertex.normal.to_array() {
+                self.f32(f);
+            }
+            self.f32(vertex.uv[0]);
+            self.f32(vertex.uv[1]);
+            for f in vertex.color.to_array() {
+                self.f32(f);
+            }
+        }
+    }
+}
+
+/// Decoder matching `Writer`, every read returns `None` once the data runs out.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < n {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Some(head)
+    }
+    pub fn u32(&mut self) -> Option<u32> {
+        let b = self.take(4)?;
+        Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    pub fn f32(&mut self) -> Option<f32> {
+        self.u32().map(f32::from_bits)
+    }
+    pub fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+    pub fn option_string(&mut self) -> Option<Option<String>> {
+        match self.u32()? {
+            0 => Some(None),
+            _ => self.string().map(Some),
+        }
+    }
+    pub fn pod_slice<T: bytemuck::Pod>(&mut self) -> Option<Vec<T>> {
+        let len = self.u32()? as usize;
+        let size = std::mem::size_of::<T>();
+        let bytes = self.take(len.checked_mul(size)?)?;
+        let mut values = vec![T::zeroed(); len];
+        let words: &mut [u32] = bytemuck::cast_slice_mut(&mut values);
+        for (w, b) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+            *w = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+        Some(values)
+    }
+    pub fn vertices(&mut self) -> Option<Vec<Vertex>> {
+        let len = self.u32()? as usize;
+        let mut vertices = Vec::with_capacity(len.min(self.bytes.len() / 44));
+        for _ in 0..len {
+            let mut f = [0.0; 11];
+            for v in f.iter_mut() {
+                *v = self.f32()?;
+            }
+            vertices.push(
+                Vertex::with_uv(
+                    glam::Vec3::new(f[0], f[1], f[2]),
+                    glam::Vec3::new(f[3], f[4], f[5]),
+                    [f[6], f[7]],
+                )
+                .with_color(glam::Vec3::new(f[8], f[9], f[10])),
+            );
+        }
+        Some(vertices)
+    }
+}
@@ -0,0 +1,216 @@
+use image::{RgbaImage, imageops::FilterType};
+use rayon::prelude::*;
+
+use crate::core::snapshot::Snapshot;
+use crate::rendering::readback::encode_rgba8;
+
+/// Spread of the Gaussian window SSIM gathers local statistics over, and how far it reaches, as in
+/// the original paper
+const SSIM_SIGMA: f32 = 1.5;
+const SSIM_RADIUS: usize = 5;
+/// Keep SSIM stable where the means or variances are near zero, for values running 0 to 1
+const SSIM_C1: f32 = 0.01 * 0.01;
+const SSIM_C2: f32 = 0.03 * 0.03;
+/// Colours the heatmap runs through from no difference to the most, roughly inferno
+const HEATMAP_RAMP: [[f32; 3]; 5] = [
+    [0.0, 0.0, 0.0],
+    [0.34, 0.06, 0.43],
+    [0.73, 0.21, 0.33],
+    [0.98, 0.55, 0.04],
+    [0.99, 1.0, 0.64],
+];
+
+/// How far apart two images are, for checking a render against a previous version of itself or
+/// another renderer.
+pub struct Difference {
+    /// Root mean square difference of the linear colour channels
+    pub rmse: f32,
+    /// Mean structural similarity of the displayed luminance, 1 when the images match
+    pub ssim: f32,
+    /// Set when B was a different size and had to be resized to A, the numbers are then only rough
+    pub resized: bool,
+    width: u32,
+    height: u32,
+    /// Largest difference of any channel of each displayed pixel, for `heatmap`
+    deltas: Vec<u8>,
+}
+
+impl Difference {
+    /// Per pixel difference of the displayed images, brighter where they differ more, the
+    /// differences scaled by `gain` before colouring.
+    pub fn heatmap(&self, gain: f32) -> RgbaImage {
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let d = self.deltas[(y * self.width + x) as usize];
+            heat(d as f32 / 255.0 * gain)
+        })
+    }
+}
+
+/// Compares `b` against `a`. RMSE is taken on the linear radiance, while SSIM and the heatmap use
+/// the images as displayed at their own exposures, which is what SSIM's constants expect and what
+/// an eye would notice.
+pub fn difference(a: &Snapshot, b: &Snapshot) -> Difference {
+    let (width, height) = a.image.dimensions();
+    let resized = b.image.dimensions() != (width, height);
+    let b_image = match resized {
+        true => image::imageops::resize(&b.image, width, height, FilterType::Triangle),
+        false => b.image.clone(),
+    };
+    let squared: f64 = a
+        .image
+        .pixels()
+        .zip(b_image.pixels())
+        .flat_map(|(p, q)| (0..3).map(move |c| (p[c] - q[c]) as f64))
+        .map(|d| d * d)
+        .sum();
+    let rmse = (squared / (width as f64 * height as f64 * 3.0).max(1.0)).sqrt() as f32;
+
+    let a_ldr = a.ldr();
+    let b_ldr = encode_rgba8(&b_image, b.exposure);
+    let deltas = a_ldr
+        .pixels()
+        .zip(b_ldr.pixels())
+        .map(|(p, q)| (0..3).map(|c| p[c].abs_diff(q[c])).max().unwrap_or(0))
+        .collect();
+    Difference {
+        rmse,
+        ssim: ssim(&luminance(&a_ldr), &luminance(&b_ldr), width as usize),
+        resized,
+        width,
+        height,
+        deltas,
+    }
+}
+
+/// Rec. 709 luminance of gamma encoded pixels, 0 to 1.
+fn luminance(image: &RgbaImage) -> Vec<f32> {
+    image
+        .pixels()
+        .map(|p| (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32) / 255.0)
+        .collect()
+}
+
+/// Mean SSIM of two same sized luminance images, `width` pixels to a row.
+fn ssim(x: &[f32], y: &[f32], width: usize) -> f32 {
+    if x.is_empty() {
+        return 1.0;
+    }
+    let product =
+        |f: fn(f32, f32) -> f32| -> Vec<f32> { x.iter().zip(y).map(|(&a, &b)| f(a, b)).collect() };
+    let [mu_x, mu_y, xx, yy, xy] = [
+        x.to_vec(),
+        y.to_vec(),
+        product(|a, _| a * a),
+        product(|_, b| b * b),
+        product(|a, b| a * b),
+    ]
+    .map(|image| blur(&image, width));
+    let sum: f64 = (0..x.len())
+        .into_par_iter()
+        .map(|i| {
+            let (mx, my) = (mu_x[i], mu_y[i]);
+            let var_x = xx[i] - mx * mx;
+            let var_y = yy[i] - my * my;
+            let cov = xy[i] - mx * my;
+            ((2.0 * mx * my + SSIM_C1) * (2.0 * cov + SSIM_C2)
+                / ((mx * mx + my * my + SSIM_C1) * (var_x + var_y + SSIM_C2))) as f64
+        })
+        .sum();
+    (sum / x.len() as f64) as f32
+}
+
+/// Separable Gaussian blur, edges clamped.
+fn blur(image: &[f32], width: usize) -> Vec<f32> {
+    let weights: Vec<f32> = (0..=2 * SSIM_RADIUS)
+        .map(|i| {
+            let d = i as f32 - SSIM_RADIUS as f32;
+            (-d * d / (2.0 * SSIM_SIGMA * SSIM_SIGMA)).exp()
+        })
+        .collect();
+    let total: f32 = weights.iter().sum();
+    let height = image.len() / width;
+    let tap =
+        |i: usize, offset: usize, len: usize| (i + offset).saturating_sub(SSIM_RADIUS).min(len - 1);
+    let mut rows = vec![0.0; image.len()];
+    rows.par_chunks_mut(width)
+        .zip(image.par_chunks(width))
+        .for_each(|(out, row)| {
+            for (x, out) in out.iter_mut().enumerate() {
+                *out = weights
+                    .iter()
+                    .enumerate()
+                    .map(|(k, w)| w * row[tap(x, k, width)])
+                    .sum::<f32>()
+                    / total;
+            }
+        });
+    let mut out = vec![0.0; image.len()];
+    out.par_chunks_mut(width).enumerate().for_each(|(y, out)| {
+        for (x, out) in out.iter_mut().enumerate() {
+            *out = weights
+                .iter()
+                .enumerate()
+                .map(|(k, w)| w * rows[tap(y, k, height) * width + x])
+                .sum::<f32>()
+                / total;
+        }
+    });
+    out
+}
+
+/// Colour of a 0 to 1 difference along `HEATMAP_RAMP`.
+fn heat(t: f32) -> image::Rgba<u8> {
+    let t = t.clamp(0.0, 1.0) * (HEATMAP_RAMP.len() - 1) as f32;
+    let i = (t as usize).min(HEATMAP_RAMP.len() - 2);
+    let f = t - i as f32;
+    let (low, high) = (HEATMAP_RAMP[i], HEATMAP_RAMP[i + 1]);
+    let channel = |c: usize| ((low[c] + (high[c] - low[c]) * f) * 255.0) as u8;
+    image::Rgba([channel(0), channel(1), channel(2), 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(width: usize, height: usize) -> Vec<f32> {
+        (0..width * height)
+            .map(|i| (i % width) as f32 / width as f32)
+            .collect()
+    }
+
+    #[test]
+    fn heatmap_scales_with_gain() {
+        let difference = Difference {
+            rmse: 0.0,
+            ssim: 1.0,
+            resized: false,
+            width: 2,
+            height: 1,
+            deltas: vec![0, 16],
+        };
+        let faint = difference.heatmap(1.0);
+        let bright = difference.heatmap(8.0);
+        assert_eq!(faint.dimensions(), (2, 1));
+        assert_eq!(faint.get_pixel(0, 0), bright.get_pixel(0, 0));
+        let level = |p: &image::Rgba<u8>| p[0] as u32 + p[1] as u32 + p[2] as u32;
+        assert!(level(bright.get_pixel(1, 0)) > level(faint.get_pixel(1, 0)));
+    }
+
+    #[test]
+    fn identical_images_are_fully_similar() {
+        let image = ramp(32, 16);
+        assert!((ssim(&image, &image, 32) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn noise_lowers_similarity() {
+        let image = ramp(32, 16);
+        let noisy: Vec<f32> = image
+            .iter()
+            .enumerate()
+            .map(|(i, v)| v + if i % 2 == 0 { 0.2 } else { -0.2 })
+            .collect();
+        let score = ssim(&image, &noisy, 32);
+        assert!(score < 0.9, "ssim {}", score);
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    panic,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::core::app::Params;
+use crate::scene::scene::{Scene, SceneName};
+
+/// Where crash reports are written, a folder each
+pub const REPORT_DIR: &str = "crash_reports";
+/// Most recent log lines kept for a report
+const LOG_LINES: usize = 400;
+
+/// What a crash report says about the app, kept up to date as it runs since a panic hook has no
+/// other way to reach the engine.
+struct Context {
+    log: VecDeque<String>,
+    gpu: Vec<(&'static str, String)>,
+    params: Option<Params>,
+    /// `describe_scene` of the active scene
+    scene: String,
+    /// Reports the panic hook wrote that the UI hasn't shown yet
+    written: Vec<PathBuf>,
+}
+
+static CONTEXT: Mutex<Context> = Mutex::new(Context {
+    log: VecDeque::new(),
+    gpu: Vec::new(),
+    params: None,
+    scene: String::new(),
+    written: Vec::new(),
+});
+
+/// The context even if a panic poisoned it, a half written report beats none.
+fn context() -> MutexGuard<'static, Context> {
+    CONTEXT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Logs through env_logger while keeping the last `LOG_LINES` lines for crash reports.
+struct TailLogger {
+    inner: env_logger::Logger,
+}
+
+impl log::Log for TailLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+    fn log(&self, record: &log::Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        let line = format!("[{} {}] {}", record.level(), record.target(), record.args());
+        {
+            let mut context = context();
+            if context.log.len() == LOG_LINES {
+                context.log.pop_front();
+            }
+            context.log.push_back(line);
+        }
+        self.inner.log(record);
+    }
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the logger `builder` describes, remembering its output for crash reports.
+pub fn init_logger(builder: &mut env_logger::Builder) {
+    let inner = builder.build();
+    let level = inner.filter();
+    if log::set_boxed_logger(Box::new(TailLogger { inner })).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Writes a report for every panic, after the usual message. Panics the app survives, like those
+/// of jobs, are shown by the UI through `take_written`.
+pub fn install_panic_hook() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default(info);
+        let thread = std::thread::current();
+        let reason = format!(
+            "Panicked on thread {}: {}",
+            thread.name().unwrap_or("unnamed"),
+            info
+        );
+        if let Some(path) = write_report(&reason) {
+            context().written.push(path);
+        }
+    }));
+}
+
+pub fn set_gpu(report: &[(&'static str, String)]) {
+    context().gpu = report.to_vec();
+}
+
+pub fn set_params(params: &Params) {
+    context().params = Some(*params);
+}
+
+/// Records the scene a report would describe, called when the active scene changes.
+pub fn set_scene(name: SceneName, scene: &Scene) {
+    let description = describe_scene(name, scene);
+    context().scene = description;
+}
+
+/// A report the panic hook wrote since the last call.
+pub fn take_written() -> Option<PathBuf> {
+    context().written.pop()
+}
+
+/// Writes the log tail, GPU, `Params` and scene into a new timestamped folder in `REPORT_DIR`,
+/// returning the folder. Failures are logged rather than returned, there's nothing left to do
+/// about them.
+pub fn write_report(reason: &str) -> Option<PathBuf> {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let dir = PathBuf::from(REPORT_DIR).join(format!("crash_{}", millis));
+    let (report, scene, log) = {
+        let context = context();
+        let mut report = format!(
+            "{}\n\nRay Tracer {} on {} {}\n\n",
+            reason,
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        );
+        for (name, value) in &context.gpu {
+            let _ = writeln!(report, "{}: {}", name, value);
+        }
+        let _ = writeln!(report, "\n{:#?}", context.params);
+        let log: Vec<&str> = context.log.iter().map(String::as_str).collect();
+        (report, context.scene.clone(), log.join("\n"))
+    };
+    let written = std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(dir.join("report.txt"), report))
+        .and_then(|_| std::fs::write(dir.join("scene.txt"), scene))
+        .and_then(|_| std::fs::write(dir.join("log.txt"), log));
+    match written {
+        Ok(()) => {
+            log::error!("Crash report written to {}", dir.display());
+            Some(dir)
+        }
+        Err(e) => {
+            log::error!("Failed to write crash report to {}: {}", dir.display(), e);
+            None
+        }
+    }
+}
+
+/// The scene's entities, camera, environment and BVH as text, enough to rebuild what was on
+/// screen from the scene's definition.
+fn describe_scene(name: SceneName, scene: &Scene) -> String {
+    let mut text = format!("Scene {:?}\n", name);
+    let _ = writeln!(text, "{:#?}", scene.camera);
+    let _ = writeln!(text, "{:#?}", scene.sky);
+    let _ = writeln!(text, "{:#?}", scene.fog);
+    let _ = writeln!(text, "{:#?}", scene.settings);
+    let _ = writeln!(
+        text,
+        "\n{} spheres, {} meshes, {} textures, {} volumes, {} portals",
+        scene.spheres.len(),
+        scene.meshes.len(),
+        scene.textures.len(),
+        scene.volumes.len(),
+        scene.portals.len()
+    );
+    for (i, sphere) in scene.spheres.iter().enumerate() {
+        let _ = writeln!(
+            text,
+            "Sphere {} {:?}: centre {:?}, radius {}",
+            i,
+            scene.entity_name(i as i32).unwrap_or_default(),
+            sphere.pos,
+            sphere.radius
+        );
+    }
+    for (i, mesh) in scene.meshes.iter().enumerate() {
+        let _ = writeln!(
+            text,
+            "Mesh {} {:?}: {}, {} triangles, {:?}",
+            i,
+            scene
+                .entity_name((scene.spheres.len() + i) as i32)
+                .unwrap_or_default(),
+            mesh.source.as_deref().unwrap_or("generated"),
+            mesh.data.indices.len() / 3,
+            mesh.transform
+        );
+    }
+    let bvh = &scene.bvh_data;
+    let owners = (0..bvh.blas_owner.len())
+        .filter(|&i| bvh.blas_owner[i] == i)
+        .count();
+    let _ = writeln!(
+        text,
+        "\nBVH {:?}: {} nodes, {} triangles, {} degenerate removed, {} BLASes for {} meshes",
+        scene.bvh_quality,
+        bvh.nodes.len(),
+        bvh.triangles.len(),
+        bvh.degenerate_triangles,
+        owners,
+        bvh.blas_owner.len()
+    );
+    for (name, report) in &scene.diagnostics {
+        let _ = writeln!(text, "{}: {:?}", name, report);
+    }
+    text
+}
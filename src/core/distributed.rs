@@ -0,0 +1,767 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io::{self, BufReader, BufWriter, Read, Write},
+    mem,
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use egui_wgpu::wgpu;
+use glam::{Quat, Vec3};
+use image::Rgba32FImage;
+
+use crate::core::{
+    app::Params,
+    asset::AssetManager,
+    engine::{GraphicsResources, RenderTarget},
+    jobs::Jobs,
+};
+use crate::rendering::{
+    cpu_tracer::CpuTracer,
+    ray_tracer::RayTracer,
+    readback::{encode_rgba8, read_texture_region_rgba32f},
+};
+use crate::scene::{
+    camera::Camera,
+    scene::{Scene, SceneDefinition, SceneManager, SceneName},
+};
+
+pub const DEFAULT_PORT: u16 = 7878;
+/// Bumped whenever the wire format changes so mismatched builds refuse each other
+const PROTOCOL_VERSION: u32 = 4;
+const MESSAGE_TILE: u32 = 1;
+const MESSAGE_RESULT: u32 = 2;
+const MESSAGE_ERROR: u32 = 3;
+const PARAMS_WORDS: usize = mem::size_of::<Params>() / 4;
+/// Largest tile a worker will accept, guards against allocating garbage sizes
+const MAX_TILE_PIXELS: u32 = 4096 * 4096;
+/// Frames submitted before the worker waits on the GPU, so long tiles don't queue thousands of dispatches
+const FRAMES_PER_WAIT: u32 = 16;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Camera state sent to workers, which rebuild the rest of the scene from its name.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraState {
+    pos: Vec3,
+    rot: Quat,
+    fov: f32,
+    pub aspect: f32,
+    focus_dist: f32,
+    defocus_strength: f32,
+    diverge_strength: f32,
+    /// Clip distances, 0 and `f32::MAX` when clipping is off
+    near: f32,
+    far: f32,
+}
+
+impl CameraState {
+    /// Length of `to_floats`
+    pub const FLOATS: usize = 14;
+
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            pos: camera.transform.pos,
+            rot: camera.transform.rot,
+            fov: camera.fov,
+            aspect: camera.aspect,
+            focus_dist: camera.focus_dist,
+            defocus_strength: camera.defocus_strength,
+            diverge_strength: camera.diverge_strength,
+            near: if camera.clip { camera.near } else { 0.0 },
+            far: if camera.clip { camera.far } else { f32::MAX },
+        }
+    }
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.transform.pos = self.pos;
+        camera.transform.rot = self.rot;
+        camera.fov = self.fov;
+        camera.aspect = self.aspect;
+        camera.focus_dist = self.focus_dist;
+        camera.defocus_strength = self.defocus_strength;
+        camera.diverge_strength = self.diverge_strength;
+        camera.clip = self.near > 0.0 || self.far < f32::MAX;
+        if camera.clip {
+            camera.near = self.near;
+            camera.far = self.far;
+        }
+    }
+    pub fn to_floats(self) -> [f32; Self::FLOATS] {
+        [
+            self.pos.x,
+            self.pos.y,
+            self.pos.z,
+            self.rot.x,
+            self.rot.y,
+            self.rot.z,
+            self.rot.w,
+            self.fov,
+            self.aspect,
+            self.focus_dist,
+            self.defocus_strength,
+            self.diverge_strength,
+            self.near,
+            self.far,
+        ]
+    }
+    pub fn from_floats(f: [f32; Self::FLOATS]) -> Self {
+        Self {
+            pos: Vec3::new(f[0], f[1], f[2]),
+            rot: Quat::from_xyzw(f[3], f[4], f[5], f[6]),
+            fov: f[7],
+            aspect: f[8],
+            focus_dist: f[9],
+            defocus_strength: f[10],
+            diverge_strength: f[11],
+            near: f[12],
+            far: f[13],
+        }
+    }
+}
+
+/// Pixel rectangle of the full frame, in accumulation texture coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Tile {
+    fn pixels(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+}
+
+/// Everything a worker needs to render one tile.
+#[derive(Clone, Copy)]
+struct TileRequest {
+    scene: SceneName,
+    camera: CameraState,
+    params: Params,
+    frames: u32,
+    tile: Tile,
+}
+
+enum Message {
+    Tile(TileRequest),
+    Result(Tile, Vec<f32>),
+    Error(String),
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u32(w: &mut impl Write, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_tile(w: &mut impl Write, tile: Tile) -> io::Result<()> {
+    for value in [tile.x, tile.y, tile.width, tile.height] {
+        write_u32(w, value)?;
+    }
+    Ok(())
+}
+
+fn read_tile(r: &mut impl Read) -> io::Result<Tile> {
+    let tile = Tile {
+        x: read_u32(r)?,
+        y: read_u32(r)?,
+        width: read_u32(r)?,
+        height: read_u32(r)?,
+    };
+    if tile.width == 0
+        || tile.height == 0
+        || tile.width.saturating_mul(tile.height) > MAX_TILE_PIXELS
+    {
+        return Err(invalid_data("Invalid tile size"));
+    }
+    Ok(tile)
+}
+
+/// Every value is written as little endian 32 bit words so machines of either endianness can mix.
+fn write_message(w: &mut impl Write, message: &Message) -> io::Result<()> {
+    match message {
+        Message::Tile(request) => {
+            write_u32(w, MESSAGE_TILE)?;
+            write_u32(w, PROTOCOL_VERSION)?;
+            let scene = SceneName::ALL
+                .iter()
+                .position(|s| *s == request.scene)
+                .ok_or_else(|| invalid_data("Scene can't be rendered remotely"))?;
+            write_u32(w, scene as u32)?;
+            for value in request.camera.to_floats() {
+                write_u32(w, value.to_bits())?;
+            }
+            let words: [u32; PARAMS_WORDS] = bytemuck::cast(request.params);
+            for word in words {
+                write_u32(w, word)?;
+            }
+            write_u32(w, request.frames)?;
+            write_tile(w, request.tile)?;
+        }
+        Message::Result(tile, pixels) => {
+            write_u32(w, MESSAGE_RESULT)?;
+            write_tile(w, *tile)?;
+            let bytes: Vec<u8> = pixels.iter().flat_map(|v| v.to_le_bytes()).collect();
+            w.write_all(&bytes)?;
+        }
+        Message::Error(error) => {
+            write_u32(w, MESSAGE_ERROR)?;
+            write_u32(w, error.len() as u32)?;
+            w.write_all(error.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_message(r: &mut impl Read) -> io::Result<Message> {
+    match read_u32(r)? {
+        MESSAGE_TILE => {
+            if read_u32(r)? != PROTOCOL_VERSION {
+                return Err(invalid_data("Protocol version mismatch"));
+            }
+            let scene = *SceneName::ALL
+                .get(read_u32(r)? as usize)
+                .ok_or_else(|| invalid_data("Unknown scene"))?;
+            let mut floats = [0.0; CameraState::FLOATS];
+            for value in floats.iter_mut() {
+                *value = f32::from_bits(read_u32(r)?);
+            }
+            let mut words = [0; PARAMS_WORDS];
+            for word in words.iter_mut() {
+                *word = read_u32(r)?;
+            }
+            Ok(Message::Tile(TileRequest {
+                scene,
+                camera: CameraState::from_floats(floats),
+                params: bytemuck::cast(words),
+                frames: read_u32(r)?,
+                tile: read_tile(r)?,
+            }))
+        }
+        MESSAGE_RESULT => {
+            let tile = read_tile(r)?;
+            let mut bytes = vec![0; tile.pixels() * 16];
+            r.read_exact(&mut bytes)?;
+            let pixels = bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            Ok(Message::Result(tile, pixels))
+        }
+        MESSAGE_ERROR => {
+            let len = read_u32(r)?.min(4096) as usize;
+            let mut bytes = vec![0; len];
+            r.read_exact(&mut bytes)?;
+            Ok(Message::Error(String::from_utf8_lossy(&bytes).into_owned()))
+        }
+        _ => Err(invalid_data("Unknown message")),
+    }
+}
+
+/// Runs a headless worker that renders tiles for any coordinator that connects to `address`.
+pub async fn run_worker(address: &str, cpu: bool) {
+    let mut worker = Worker::headless(cpu).await;
+    let listener = TcpListener::bind(address).expect("Failed to bind worker address");
+    log::info!("Worker listening on {}", address);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = worker.serve(stream) {
+                    log::warn!("Coordinator connection failed: {}", e);
+                }
+            }
+            Err(e) => log::warn!("Failed to accept connection: {}", e),
+        }
+    }
+}
+
+/// The GPU side of a worker.
+struct GpuWorker {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    ray_tracer: RayTracer,
+    target: Option<RenderTarget>,
+    target_size: (u32, u32),
+}
+
+/// Headless renderer that loads scenes by name, used by workers and the render queue.
+pub struct Worker {
+    /// `None` traces on the CPU instead
+    gpu: Option<GpuWorker>,
+    cpu: CpuTracer,
+    scene_manager: SceneManager,
+    loaded_scene: Option<SceneName>,
+}
+
+impl Worker {
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
+        let ray_tracer = RayTracer::new(device.clone(), queue.clone());
+        Self {
+            gpu: Some(GpuWorker {
+                device,
+                queue,
+                ray_tracer,
+                target: None,
+                target_size: (0, 0),
+            }),
+            cpu: CpuTracer::default(),
+            scene_manager: SceneManager::new(AssetManager::new(), Jobs::default()),
+            loaded_scene: None,
+        }
+    }
+    /// Traces with `CpuTracer`, many times slower and without every feature of the shader.
+    pub fn cpu() -> Self {
+        Self {
+            gpu: None,
+            cpu: CpuTracer::default(),
+            scene_manager: SceneManager::new(AssetManager::new(), Jobs::default()),
+            loaded_scene: None,
+        }
+    }
+    /// A worker on a headless device, or on the CPU when `cpu` is set or no device can be created.
+    pub async fn headless(cpu: bool) -> Self {
+        if cpu {
+            return Self::cpu();
+        }
+        match GraphicsResources::create_headless_device().await {
+            Ok((device, queue)) => Self::new(device, queue),
+            Err(e) => {
+                log::warn!("{}, rendering on the CPU instead", e);
+                Self::cpu()
+            }
+        }
+    }
+    pub fn on_cpu(&self) -> bool {
+        self.gpu.is_none()
+    }
+    /// Answers tile requests from one coordinator until it disconnects.
+    fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        let peer = stream.peer_addr()?;
+        log::info!("Coordinator connected from {}", peer);
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        loop {
+            let request = match read_message(&mut reader) {
+                Ok(Message::Tile(request)) => request,
+                Ok(_) => return Err(invalid_data("Expected a tile request")),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    log::info!("Coordinator {} disconnected", peer);
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+            let reply = match self.render_tile(&request) {
+                Ok(pixels) => Message::Result(request.tile, pixels),
+                Err(e) => Message::Error(e.to_string()),
+            };
+            write_message(&mut writer, &reply)?;
+            writer.flush()?;
+        }
+    }
+    /// Accumulates `frames` frames of the whole image, returning linear RGBA rows bottom first.
+    pub fn render_frame(
+        &mut self,
+        scene: SceneName,
+        camera: CameraState,
+        params: Params,
+        frames: u32,
+    ) -> Result<Vec<f32>, Box<dyn Error>> {
+        self.render_tile(&TileRequest {
+            scene,
+            camera,
+            params,
+            frames,
+            tile: Tile {
+                x: 0,
+                y: 0,
+                width: params.width,
+                height: params.height,
+            },
+        })
+    }
+    /// Like `render_frame`, for a scene built in code rather than one of the named scenes.
+    pub fn render_definition(
+        &mut self,
+        definition: &SceneDefinition,
+        params: Params,
+        frames: u32,
+    ) -> Result<Vec<f32>, Box<dyn Error>> {
+        let scene = Scene::instantiate_scene(definition, &mut AssetManager::new());
+        let mut camera = CameraState::from_camera(&scene.camera);
+        camera.aspect = params.width as f32 / params.height as f32;
+        self.install_scene(scene);
+        // The next named scene has to be loaded again
+        self.loaded_scene = None;
+        let tile = Tile {
+            x: 0,
+            y: 0,
+            width: params.width,
+            height: params.height,
+        };
+        self.accumulate(camera, params, tile, frames)
+    }
+    fn install_scene(&mut self, scene: Scene) {
+        self.scene_manager.scene = scene;
+        if let Some(gpu) = self.gpu.as_mut() {
+            gpu.ray_tracer.unload_scene();
+            gpu.ray_tracer
+                .load_scene_gpu_resources(&self.scene_manager.scene);
+        }
+    }
+    fn render_tile(&mut self, request: &TileRequest) -> Result<Vec<f32>, Box<dyn Error>> {
+        if self.loaded_scene != Some(request.scene) {
+            self.scene_manager.request_scene(request.scene);
+            let (_, mut scene) = self.scene_manager.rx_loaded.recv()?;
+            // Renders need the whole scene, so wait for any streamed meshes
+            while scene.streaming > 0 {
+                let (_, chunk) = self.scene_manager.rx_chunks.recv()?;
+                if chunk.stream_id == scene.stream_id {
+                    scene.apply_chunk(chunk);
+                }
+            }
+            self.install_scene(scene);
+            self.loaded_scene = Some(request.scene);
+        }
+        self.accumulate(request.camera, request.params, request.tile, request.frames)
+    }
+    fn accumulate(
+        &mut self,
+        camera: CameraState,
+        params: Params,
+        tile: Tile,
+        frames: u32,
+    ) -> Result<Vec<f32>, Box<dyn Error>> {
+        if tile.x.saturating_add(tile.width) > params.width
+            || tile.y.saturating_add(tile.height) > params.height
+        {
+            return Err("Tile lies outside the frame".into());
+        }
+        let scene = &mut self.scene_manager.scene;
+        camera.apply(&mut scene.camera);
+        let frame_params = |frame: u32| Params {
+            frames: frame as i32,
+            accumulate: 1,
+            tile_x: tile.x,
+            tile_y: tile.y,
+            interleave: 1,
+            ..params
+        };
+        let Some(gpu) = self.gpu.as_mut() else {
+            for frame in 0..frames.max(1) {
+                self.cpu
+                    .render(scene, &frame_params(frame), (tile.width, tile.height));
+            }
+            return Ok(self.cpu.pixels.iter().flatten().copied().collect());
+        };
+        gpu.ray_tracer.update_buffers(&gpu.queue, scene);
+
+        if gpu.target.is_none() || gpu.target_size != (params.width, params.height) {
+            let target =
+                GraphicsResources::create_render_target(&gpu.device, params.width, params.height);
+            gpu.ray_tracer
+                .set_target(&target.texture_view, &target.params_buffer);
+            gpu.target = Some(target);
+            gpu.target_size = (params.width, params.height);
+        }
+        let target = gpu.target.as_ref().unwrap();
+
+        for frame in 0..frames.max(1) {
+            let params = frame_params(frame);
+            gpu.queue
+                .write_buffer(&target.params_buffer, 0, bytemuck::bytes_of(&params));
+            gpu.ray_tracer.specialise(&params);
+            let mut encoder = gpu
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Worker Tile Encoder"),
+                });
+            gpu.ray_tracer.render(&mut encoder, tile.width, tile.height);
+            gpu.queue.submit(std::iter::once(encoder.finish()));
+            if (frame + 1) % FRAMES_PER_WAIT == 0 {
+                gpu.device.poll(wgpu::MaintainBase::Wait)?;
+            }
+        }
+        read_texture_region_rgba32f(
+            &gpu.device,
+            &gpu.queue,
+            &target.texture,
+            (tile.x, tile.y),
+            tile.width,
+            tile.height,
+        )
+    }
+}
+
+struct CoordinatorJob {
+    tiles_done: Arc<AtomicUsize>,
+    tiles_total: usize,
+    handle: JoinHandle<Result<PathBuf, String>>,
+}
+
+/// Coordinator side of a distributed render: splits the frame into tiles and farms them out
+/// to workers started with `--worker`.
+///
+/// Workers rebuild the scene from its name, so only the camera and render settings carry over.
+/// Scenes that no longer match their definition are refused rather than rendered as they were
+/// first loaded, see `Scene::matches_definition`.
+pub struct DistributedRender {
+    /// Comma separated `host:port` list, the port defaults to [`DEFAULT_PORT`]
+    pub workers: String,
+    pub tile_size: u32,
+    /// Accumulated frames per tile
+    pub frames: u32,
+    pub start_requested: bool,
+    job: Option<CoordinatorJob>,
+}
+
+impl Default for DistributedRender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DistributedRender {
+    pub fn new() -> Self {
+        Self {
+            workers: format!("127.0.0.1:{}", DEFAULT_PORT),
+            tile_size: 256,
+            frames: 256,
+            start_requested: false,
+            job: None,
+        }
+    }
+    pub fn is_running(&self) -> bool {
+        self.job.is_some()
+    }
+    pub fn progress(&self) -> f32 {
+        self.job.as_ref().map_or(0.0, |job| {
+            job.tiles_done.load(Ordering::Relaxed) as f32 / job.tiles_total.max(1) as f32
+        })
+    }
+    pub fn start(&mut self, name: SceneName, scene: &Scene, params: &Params, exposure: f32) {
+        if self.is_running() {
+            return;
+        }
+        if scene.streaming > 0 {
+            log::error!("Wait for the scene to finish loading before rendering it remotely");
+            return;
+        }
+        if !scene.matches_definition() {
+            log::error!(
+                "{:?} has been edited since it was loaded, workers can only render it as defined",
+                name
+            );
+            return;
+        }
+        let workers: Vec<String> = self
+            .workers
+            .split(',')
+            .map(str::trim)
+            .filter(|w| !w.is_empty())
+            .map(|w| {
+                if w.contains(':') {
+                    w.to_owned()
+                } else {
+                    format!("{}:{}", w, DEFAULT_PORT)
+                }
+            })
+            .collect();
+        if workers.is_empty() {
+            log::error!("No workers to render on");
+            return;
+        }
+        if !SceneName::ALL.contains(&name) {
+            log::error!("{:?} can't be rendered remotely", name);
+            return;
+        }
+
+        let template = TileRequest {
+            scene: name,
+            camera: CameraState::from_camera(&scene.camera),
+            params: Params {
+                tile_x: 0,
+                tile_y: 0,
+                ..*params
+            },
+            frames: self.frames.max(1),
+            tile: Tile {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            },
+        };
+        let tiles = split_tiles(params.width, params.height, self.tile_size.max(8));
+        let tiles_total = tiles.len();
+        let tiles_done = Arc::new(AtomicUsize::new(0));
+        let progress = tiles_done.clone();
+        log::info!(
+            "Rendering {} tiles across {} workers",
+            tiles_total,
+            workers.len()
+        );
+        let handle = thread::spawn(move || {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let name = format!("distributed_{:?}_{}", template.scene, timestamp);
+            let (width, height) = (template.params.width, template.params.height);
+            coordinate(&workers, template, tiles, &progress)
+                .and_then(|pixels| save_render(&name, width, height, pixels, exposure))
+        });
+        self.job = Some(CoordinatorJob {
+            tiles_done,
+            tiles_total,
+            handle,
+        });
+    }
+    /// Reports the result once the coordinator thread finishes.
+    pub fn poll(&mut self) {
+        if !self
+            .job
+            .as_ref()
+            .is_some_and(|job| job.handle.is_finished())
+        {
+            return;
+        }
+        let job = self.job.take().unwrap();
+        match job.handle.join() {
+            Ok(Ok(path)) => log::info!("Saved distributed render to {}", path.display()),
+            Ok(Err(e)) => log::error!("Distributed render failed: {}", e),
+            Err(_) => log::error!("Distributed render thread panicked"),
+        }
+    }
+}
+
+fn split_tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(size as usize) {
+        for x in (0..width).step_by(size as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: size.min(width - x),
+                height: size.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+/// Hands tiles out to every worker until none are left, returning the merged frame.
+fn coordinate(
+    workers: &[String],
+    template: TileRequest,
+    tiles: Vec<Tile>,
+    progress: &AtomicUsize,
+) -> Result<Vec<f32>, String> {
+    let width = template.params.width;
+    let queue = Mutex::new(VecDeque::from(tiles));
+    let pixels = Mutex::new(vec![0.0; (width * template.params.height * 4) as usize]);
+    thread::scope(|s| {
+        for address in workers {
+            let (queue, pixels) = (&queue, &pixels);
+            s.spawn(move || {
+                if let Err(e) = render_on_worker(address, template, queue, pixels, progress) {
+                    log::warn!("Worker {} dropped out: {}", address, e);
+                }
+            });
+        }
+    });
+    let remaining = queue.into_inner().unwrap().len();
+    if remaining > 0 {
+        return Err(format!("{} tiles were not rendered", remaining));
+    }
+    Ok(pixels.into_inner().unwrap())
+}
+
+/// Renders tiles on one worker, returning a tile to the queue if the worker fails on it.
+fn render_on_worker(
+    address: &str,
+    template: TileRequest,
+    queue: &Mutex<VecDeque<Tile>>,
+    pixels: &Mutex<Vec<f32>>,
+    progress: &AtomicUsize,
+) -> io::Result<()> {
+    let socket_address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid_data("Address did not resolve"))?;
+    let stream = TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let width = template.params.width;
+
+    while let Some(tile) = queue.lock().unwrap().pop_front() {
+        let reply = write_message(
+            &mut writer,
+            &Message::Tile(TileRequest { tile, ..template }),
+        )
+        .and_then(|_| writer.flush())
+        .and_then(|_| read_message(&mut reader));
+        let error = match reply {
+            Ok(Message::Result(rendered, data))
+                if rendered == tile && data.len() == tile.pixels() * 4 =>
+            {
+                let mut pixels = pixels.lock().unwrap();
+                let row_len = (tile.width * 4) as usize;
+                for row in 0..tile.height {
+                    let src = row as usize * row_len;
+                    let dst = (((tile.y + row) * width + tile.x) * 4) as usize;
+                    pixels[dst..dst + row_len].copy_from_slice(&data[src..src + row_len]);
+                }
+                progress.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            Ok(Message::Error(e)) => io::Error::other(e),
+            Ok(_) => invalid_data("Unexpected reply"),
+            Err(e) => e,
+        };
+        queue.lock().unwrap().push_back(tile);
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Writes a frame to `renders/<name>` as a linear EXR and an exposed PNG, returning the PNG's path.
+pub fn save_render(
+    name: &str,
+    width: u32,
+    height: u32,
+    pixels: Vec<f32>,
+    exposure: f32,
+) -> Result<PathBuf, String> {
+    let mut image =
+        Rgba32FImage::from_raw(width, height, pixels).ok_or("Failed to create image from tiles")?;
+    image::imageops::flip_vertical_in_place(&mut image);
+
+    let dir = Path::new("renders");
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    image
+        .save(dir.join(format!("{}.exr", name)))
+        .map_err(|e| e.to_string())?;
+    let ldr = encode_rgba8(&image, exposure);
+    let path = dir.join(format!("{}.png", name));
+    ldr.save(&path).map_err(|e| e.to_string())?;
+    Ok(path)
+}
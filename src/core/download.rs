@@ -0,0 +1,167 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::core::cache::CACHE_DIR;
+
+const MAX_REDIRECTS: u32 = 5;
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest file downloaded, well past any model or texture a scene should reference
+const MAX_SIZE: u64 = 1 << 30;
+
+/// Whether an asset path is a link to download rather than a file in the assets folder.
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// `path` as referenced from inside the asset at `base`, resolved against its URL when `base` was
+/// downloaded. Local assets reference each other relative to the assets folder, so stay as they are.
+pub fn relative(base: &str, path: &str) -> String {
+    if !is_url(base) || is_url(path) {
+        return path.to_owned();
+    }
+    let dir = &base[..base.rfind('/').map_or(base.len(), |i| i + 1)];
+    format!("{}{}", dir, path.trim_start_matches("./"))
+}
+
+/// Downloaded files mirror their URL under `cache/downloads`, so files referencing each other by
+/// relative path still find each other. Queries are dropped.
+fn cached_path(url: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let (_, rest) = url.split_once("://").ok_or("not a URL")?;
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    let mut path = Path::new(CACHE_DIR).join("downloads");
+    for segment in rest
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+    {
+        path.push(segment.replace(':', "_"));
+    }
+    if rest.ends_with('/') || !rest.contains('/') {
+        path.push("index");
+    }
+    Ok(path)
+}
+
+/// Where `url` is on disk, downloading it into the cache the first time it is asked for. Delete
+/// `cache/downloads` to fetch everything again. An OBJ brings the material libraries it references
+/// along, so tobj finds them next to it.
+pub fn fetch(url: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let path = cached_path(url)?;
+    if path.exists() {
+        return Ok(path);
+    }
+    log::info!("Downloading {}", url);
+    let body = get(url)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Written aside then moved, so an interrupted download is never taken for a finished one
+    let mut partial = path.clone().into_os_string();
+    partial.push(".part");
+    std::fs::write(&partial, &body)?;
+    std::fs::rename(&partial, &path)?;
+    if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("obj"))
+    {
+        for line in String::from_utf8_lossy(&body).lines() {
+            if let Some(name) = line.trim().strip_prefix("mtllib ")
+                && let Err(e) = fetch(&relative(url, name.trim()))
+            {
+                log::warn!("Failed to download materials of {}: {}", url, e);
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Whether `url` is there to download, asked with a HEAD request so nothing is fetched. Files
+/// already in the cache count without asking.
+pub fn exists(url: &str) -> bool {
+    if cached_path(url).is_ok_and(|path| path.is_file()) {
+        return true;
+    }
+    agent().head(url).call().is_ok()
+}
+
+fn agent() -> ureq::Agent {
+    ureq::Agent::config_builder()
+        .max_redirects(MAX_REDIRECTS)
+        .timeout_connect(Some(TIMEOUT))
+        .timeout_recv_response(Some(TIMEOUT))
+        .user_agent("ray_tracer_2")
+        .build()
+        .into()
+}
+
+/// Body of `url` over HTTP or HTTPS, following redirects.
+fn get(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut response = agent().get(url).call()?;
+    let body = response
+        .body_mut()
+        .with_config()
+        .limit(MAX_SIZE)
+        .read_to_vec()?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_urls() {
+        assert!(is_url("https://example.com/chair.obj"));
+        assert!(is_url("http://example.com/chair.obj"));
+        assert!(!is_url("models/chair.obj"));
+        assert!(!is_url("ftp://example.com/chair.obj"));
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_the_url() {
+        let base = "https://example.com/models/chair.obj";
+        assert_eq!(
+            relative(base, "chair.mtl"),
+            "https://example.com/models/chair.mtl"
+        );
+        assert_eq!(
+            relative(base, "./textures/wood.png"),
+            "https://example.com/models/textures/wood.png"
+        );
+        assert_eq!(
+            relative(base, "https://cdn.example.com/wood.png"),
+            "https://cdn.example.com/wood.png"
+        );
+        // Local assets stay relative to the assets folder
+        assert_eq!(relative("models/chair.obj", "chair.mtl"), "chair.mtl");
+    }
+
+    #[test]
+    fn cached_paths_mirror_the_url() {
+        let downloads = Path::new(CACHE_DIR).join("downloads");
+        assert_eq!(
+            cached_path("https://example.com:8080/models/chair.obj?v=2#top").unwrap(),
+            downloads.join("example.com_8080/models/chair.obj")
+        );
+        assert_eq!(
+            cached_path("http://example.com/models/").unwrap(),
+            downloads.join("example.com/models/index")
+        );
+        assert_eq!(
+            cached_path("http://example.com").unwrap(),
+            downloads.join("example.com/index")
+        );
+        assert!(cached_path("models/chair.obj").is_err());
+    }
+
+    #[test]
+    fn cached_paths_stay_in_the_cache() {
+        let path = cached_path("https://example.com/../../etc/./passwd").unwrap();
+        assert_eq!(
+            path,
+            Path::new(CACHE_DIR).join("downloads/example.com/etc/passwd")
+        );
+    }
+}
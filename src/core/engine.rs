@@ -1,5 +1,8 @@
 use std::{
-    sync::Arc,
+    collections::{HashSet, VecDeque},
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
@@ -11,19 +14,73 @@ use egui_wgpu::{
 };
 use winit::window::Window;
 
-use crate::core::{app::Params, asset::AssetManager};
+use crate::core::{
+    action::CommandPalette,
+    app::{Params, SeedSchedule},
+    asset::AssetManager,
+    audio::AudioInput,
+    crash,
+    distributed::DistributedRender,
+    gamepad::GamepadInput,
+    jobs::Jobs,
+    mip_cache::{MipChain, ResidentTexture},
+    queue::RenderQueue,
+    settings::{SETTINGS_FILE, UiSettings},
+    snapshot::Snapshots,
+    stream::MeshChunk,
+    tabs::{ParkedTab, SceneTab, TabManager},
+    time_limit::TimeLimit,
+    watcher::AssetWatcher,
+};
 use crate::rendering::{
+    cpu_tracer::CpuTracer,
+    cryptomatte::Cryptomatte,
+    denoise_features::DenoiseFeatures,
     egui::EguiRenderer,
-    ray_tracer::{MAX_TEXTURES, RayTracer},
+    exposure::AutoExposure,
+    frame_graph::{FrameGraph, FrameResource},
+    lightmap::LightmapBaker,
+    magnifier::Magnifier,
+    overlay::Overlay,
+    picking::Picker,
+    post::PostProcess,
+    ray_tracer::{LAYER_SIZE, MAX_TEXTURES, RayTracer, TextureMode, TextureSlots},
     renderer::Renderer,
+    upscale::GuidedUpscaler,
+};
+use crate::scene::{
+    camera::Camera,
+    components::{
+        material::LightUnit,
+        texture::{PREFETCH_FRAMES, decode_frame},
+    },
+    randomize::RandomizeSettings,
+    scatter::ScatterSettings,
+    scene::{Scene, SceneManager, SceneName},
+    walkthrough::Walkthrough,
 };
-use crate::scene::scene::{SceneManager, SceneName};
 
 pub struct TmpResources {
     pub use_mouse: bool,
     pub mouse_pressed: bool,
     pub fullscreen: bool,
     pub low_res: bool,
+    /// Burn render settings into saved screenshots
+    pub annotate_screenshots: bool,
+    /// Shown in a dialog until dismissed, set after recovering from a GPU failure
+    pub error: Option<String>,
+    pub show_textures: bool,
+    /// Unit the inspector shows emission strengths in
+    pub light_unit: LightUnit,
+    /// Colour temperature in Kelvin last picked for an emissive colour
+    pub light_temperature: f32,
+    pub seed_schedule: SeedSchedule,
+    /// Copied from `GraphicsResources::gpu_report` for the Debug panel
+    pub gpu_report: Vec<(&'static str, String)>,
+    pub walkthrough: Walkthrough,
+    pub scatter: ScatterSettings,
+    pub randomize: RandomizeSettings,
+    pub time_limit: TimeLimit,
 }
 
 impl Default for TmpResources {
@@ -33,20 +90,112 @@ impl Default for TmpResources {
             mouse_pressed: false,
             fullscreen: false,
             low_res: false,
+            annotate_screenshots: false,
+            error: None,
+            show_textures: false,
+            light_unit: LightUnit::Unitless,
+            light_temperature: 6500.0,
+            seed_schedule: SeedSchedule::PerFrame,
+            gpu_report: vec![],
+            walkthrough: Walkthrough::default(),
+            scatter: ScatterSettings::default(),
+            randomize: RandomizeSettings::default(),
+            time_limit: TimeLimit::default(),
+        }
+    }
+}
+
+/// Failures setting up the GPU or presenting to the window.
+#[derive(Debug)]
+pub enum GpuError {
+    Surface(wgpu::CreateSurfaceError),
+    Adapter(wgpu::RequestAdapterError),
+    Device(wgpu::RequestDeviceError),
+    /// The surface offers no formats to present with
+    IncompatibleSurface,
+    Renderer,
+    /// The driver reset or the GPU went away, everything on it must be recreated
+    DeviceLost(String),
+    OutOfMemory,
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuError::Surface(e) => write!(f, "Failed to create surface: {}", e),
+            GpuError::Adapter(e) => write!(f, "Failed to find appropriate adapter: {}", e),
+            GpuError::Device(e) => write!(f, "Failed to find device: {}", e),
+            GpuError::IncompatibleSurface => write!(f, "Surface has no supported texture format"),
+            GpuError::Renderer => write!(f, "Failed to create the display renderer"),
+            GpuError::DeviceLost(reason) => write!(f, "GPU device lost: {}", reason),
+            GpuError::OutOfMemory => write!(f, "GPU ran out of memory"),
         }
     }
 }
 
+impl std::error::Error for GpuError {}
+
+/// What the GPU is and which renderer features run on it, logged when a device is created and
+/// listed in the Debug panel.
+pub fn gpu_report(
+    adapter: &wgpu::AdapterInfo,
+    device: &wgpu::Device,
+) -> Vec<(&'static str, String)> {
+    let vendor = match adapter.vendor {
+        0x10de => "NVIDIA".to_owned(),
+        0x1002 | 0x1022 => "AMD".to_owned(),
+        0x8086 => "Intel".to_owned(),
+        0x106b => "Apple".to_owned(),
+        id => format!("{:#06x}", id),
+    };
+    let textures = match TextureMode::of(device) {
+        TextureMode::BindingArray => format!("{} separate textures", MAX_TEXTURES),
+        TextureMode::Layered => format!(
+            "{} layers of up to {}x{}, no non-uniform indexing",
+            MAX_TEXTURES, LAYER_SIZE, LAYER_SIZE
+        ),
+    };
+    vec![
+        ("GPU", adapter.name.clone()),
+        ("Vendor", vendor),
+        ("Backend", format!("{:?}", adapter.backend)),
+        (
+            "Driver",
+            format!("{} {}", adapter.driver, adapter.driver_info)
+                .trim()
+                .to_owned(),
+        ),
+        ("Textures", textures),
+        (
+            "Storage Buffers",
+            format!(
+                "{} MB each",
+                device.limits().max_storage_buffer_binding_size >> 20
+            ),
+        ),
+    ]
+}
+
 pub struct GraphicsResources {
     pub device: Arc<wgpu::Device>,
     pub queue: Arc<wgpu::Queue>,
     pub surface_config: wgpu::SurfaceConfiguration,
     pub surface: wgpu::Surface<'static>,
+    pub target: RenderTarget,
+    pub scale_factor: f32,
+    /// Listed in the Debug panel, see `gpu_report`
+    pub gpu_report: Vec<(&'static str, String)>,
+    /// Set by the device lost callback, checked before each frame
+    device_lost: Arc<Mutex<Option<String>>>,
+}
+
+/// Accumulation texture and params uniform owned by a single scene tab.
+pub struct RenderTarget {
     pub texture: wgpu::Texture,
     pub texture_view: wgpu::TextureView,
     pub params_buffer: wgpu::Buffer,
-    pub scale_factor: f32,
 }
+
 impl GraphicsResources {
     pub fn create_screen_descriptor(&mut self, window: Arc<Window>) -> ScreenDescriptor {
         ScreenDescriptor {
@@ -54,31 +203,45 @@ impl GraphicsResources {
             pixels_per_point: window.scale_factor() as f32 * self.scale_factor,
         }
     }
-    pub fn get_surface_view_and_texture(&mut self) -> (SurfaceTexture, TextureView) {
-        let surface_texture = self.surface.get_current_texture();
-
-        match surface_texture {
-            Err(SurfaceError::Outdated) => {
-                panic!("Wgpu Surface Outdated");
+    /// Acquires the next swap chain texture. An outdated or lost surface is reconfigured and
+    /// the frame skipped with `Ok(None)`, only device loss and running out of memory are errors.
+    pub fn get_surface_view_and_texture(
+        &mut self,
+    ) -> Result<Option<(SurfaceTexture, TextureView)>, GpuError> {
+        if let Some(reason) = self.device_lost.lock().unwrap().take() {
+            return Err(GpuError::DeviceLost(reason));
+        }
+        match self.surface.get_current_texture() {
+            Ok(surface_texture) => {
+                let surface_view = surface_texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                Ok(Some((surface_texture, surface_view)))
             }
-            Err(_) => {
-                surface_texture.expect("Failed to aquire next swap chain texture");
-                panic!("Failed to aquire next swap chain texture");
+            Err(SurfaceError::Timeout) => {
+                log::warn!("Timed out acquiring the next swap chain texture");
+                Ok(None)
             }
-            Ok(_) => {}
-        };
-
-        let surface_texture = surface_texture.unwrap();
-        let surface_view = surface_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        (surface_texture, surface_view)
+            Err(SurfaceError::OutOfMemory) => Err(GpuError::OutOfMemory),
+            Err(e) => {
+                log::warn!("{}, reconfiguring surface", e);
+                self.surface.configure(&self.device, &self.surface_config);
+                Ok(None)
+            }
+        }
     }
     pub fn create_command_encoder(&mut self) -> CommandEncoder {
         self.device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None })
     }
-    pub async fn create_graphics_resources(window: Arc<Window>, width: u32, height: u32) -> Self {
+    /// Device and surface for `window`. `fallback` asks for the software adapter instead, slow
+    /// but there on machines whose GPU can't be used, to display what the CPU tracer renders.
+    pub async fn create_graphics_resources(
+        window: Arc<Window>,
+        width: u32,
+        height: u32,
+        fallback: bool,
+    ) -> Result<Self, GpuError> {
         let instance = egui_wgpu::wgpu::Instance::new(&wgpu::InstanceDescriptor {
             backends: wgpu::Backends::VULKAN,
             ..Default::default()
@@ -86,40 +249,39 @@ impl GraphicsResources {
 
         let surface = instance
             .create_surface(window.clone())
-            .expect("Failed to create surface");
+            .map_err(GpuError::Surface)?;
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
+                force_fallback_adapter: fallback,
                 compatible_surface: Some(&surface),
             })
             .await
-            .expect("Failed to find appropriate adapter");
+            .map_err(GpuError::Adapter)?;
 
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
-                    | wgpu::Features::TEXTURE_BINDING_ARRAY
-                    | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
-                required_limits: Limits {
-                    max_binding_array_elements_per_shader_stage: MAX_TEXTURES as u32,
-                    ..Default::default()
-                },
-                memory_hints: Default::default(),
-                trace: Default::default(),
-            })
-            .await
-            .expect("Failed to find device");
+        let (device, queue) = GraphicsResources::request_device(&adapter).await?;
+        let gpu_report = gpu_report(&adapter.get_info(), &device);
+        crash::set_gpu(&gpu_report);
+        let device_lost = Arc::new(Mutex::new(None));
+        let lost = device_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping the device on purpose also reports it as lost
+            if reason != wgpu::DeviceLostReason::Destroyed {
+                *lost.lock().unwrap() = Some(message);
+            }
+        });
 
         let swapchain_capabilities = surface.get_capabilities(&adapter);
         let selected_format = wgpu::TextureFormat::Bgra8UnormSrgb;
+        // Fall back to whatever sRGB format the surface has rather than refusing to start
         let swapchain_format = swapchain_capabilities
             .formats
             .iter()
             .find(|d| **d == selected_format)
-            .expect("Failed to select proper surface texture format");
+            .or_else(|| swapchain_capabilities.formats.iter().find(|d| d.is_srgb()))
+            .or(swapchain_capabilities.formats.first())
+            .ok_or(GpuError::IncompatibleSurface)?;
 
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -133,6 +295,71 @@ impl GraphicsResources {
         };
 
         surface.configure(&device, &surface_config);
+        let target = GraphicsResources::create_render_target(&device, width, height);
+
+        let device = Arc::new(device);
+        let queue = Arc::new(queue);
+
+        Ok(Self {
+            device,
+            queue,
+            surface_config,
+            surface,
+            target,
+            scale_factor: 1.0,
+            gpu_report,
+            device_lost,
+        })
+    }
+    /// Requests a device with the features and limits the ray tracer needs, binding textures as
+    /// one array of layers when the adapter can't index separate ones per pixel. Logs what the
+    /// renderer ends up running with.
+    pub async fn request_device(
+        adapter: &wgpu::Adapter,
+    ) -> Result<(wgpu::Device, wgpu::Queue), GpuError> {
+        let binding_array = adapter.features().contains(TextureMode::FEATURES);
+        let (texture_features, max_binding_array_elements) = match binding_array {
+            true => (TextureMode::FEATURES, MAX_TEXTURES as u32),
+            false => (wgpu::Features::empty(), 0),
+        };
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                    | texture_features,
+                required_limits: Limits {
+                    max_binding_array_elements_per_shader_stage: max_binding_array_elements,
+                    ..Default::default()
+                },
+                memory_hints: Default::default(),
+                trace: Default::default(),
+            })
+            .await
+            .map_err(GpuError::Device)?;
+        for (name, value) in gpu_report(&adapter.get_info(), &device) {
+            log::info!("{}: {}", name, value);
+        }
+        Ok((device, queue))
+    }
+    /// Device and queue without a window or surface, for rendering offscreen.
+    pub async fn create_headless_device() -> Result<(Arc<wgpu::Device>, Arc<wgpu::Queue>), GpuError>
+    {
+        let instance = egui_wgpu::wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::VULKAN,
+            ..Default::default()
+        });
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            })
+            .await
+            .map_err(GpuError::Adapter)?;
+        let (device, queue) = GraphicsResources::request_device(&adapter).await?;
+        Ok((Arc::new(device), Arc::new(queue)))
+    }
+    pub fn create_render_target(device: &wgpu::Device, width: u32, height: u32) -> RenderTarget {
         let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Param buffer"),
             contents: bytemuck::bytes_of(&Params::default()),
@@ -157,18 +384,10 @@ impl GraphicsResources {
         });
         let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        let device = Arc::new(device);
-        let queue = Arc::new(queue);
-
-        Self {
-            device,
-            queue,
-            surface_config,
-            surface,
+        RenderTarget {
             texture,
             texture_view,
             params_buffer,
-            scale_factor: 1.0,
         }
     }
     pub fn resize_surface(&mut self, width: u32, height: u32) {
@@ -177,26 +396,91 @@ impl GraphicsResources {
         self.surface.configure(&self.device, &self.surface_config);
     }
 }
+/// Frames kept for the frame time graph
+pub const FRAME_HISTORY: usize = 240;
+
+/// Work that can stall a frame, marked on the frame time graph so a spike can be traced back to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameEvent {
+    /// Scene textures or GPU buffers were created or fully rewritten
+    BufferUpload = 1,
+    /// A mesh BVH was rebuilt and swapped in, or a streamed chunk arrived with its own
+    BvhRebuild = 2,
+    /// A different scene or tab became active
+    SceneSwitch = 4,
+}
+
+impl FrameEvent {
+    pub const ALL: [FrameEvent; 3] = [
+        FrameEvent::BufferUpload,
+        FrameEvent::BvhRebuild,
+        FrameEvent::SceneSwitch,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            FrameEvent::BufferUpload => "Buffer Upload",
+            FrameEvent::BvhRebuild => "BVH Rebuild",
+            FrameEvent::SceneSwitch => "Scene Switch",
+        }
+    }
+}
+
+/// How long a frame took and the `FrameEvent`s of the frame before it, whose work the time covers.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameSample {
+    pub time: Duration,
+    pub events: u32,
+}
+
+impl FrameSample {
+    pub fn has(&self, event: FrameEvent) -> bool {
+        self.events & event as u32 != 0
+    }
+}
+
 pub struct FrameTiming {
     pub last_render_time: Instant,
     pub dt: Duration,
-    pub average_frame_time: Duration,
+    /// When accumulation last restarted
+    pub render_start: Instant,
+    /// The last `FRAME_HISTORY` frames, oldest first
+    pub history: VecDeque<FrameSample>,
+    /// Events marked since the last `update`
+    events: u32,
+}
+
+impl Default for FrameTiming {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl FrameTiming {
     pub fn new() -> Self {
         Self {
             last_render_time: Instant::now(),
             dt: Duration::ZERO,
-            average_frame_time: Duration::ZERO,
+            render_start: Instant::now(),
+            history: VecDeque::with_capacity(FRAME_HISTORY),
+            events: 0,
         }
     }
     pub fn update(&mut self, dt: Duration) {
         self.dt = dt;
-        self.average_frame_time += dt;
-        self.average_frame_time /= 2;
+        if self.history.len() == FRAME_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(FrameSample {
+            time: dt,
+            events: std::mem::take(&mut self.events),
+        });
+    }
+    /// Notes that `event` happened this frame, shown against the time of the next one.
+    pub fn mark(&mut self, event: FrameEvent) {
+        self.events |= event as u32;
     }
     pub fn reset(&mut self) {
-        self.average_frame_time = Duration::ZERO;
+        self.render_start = Instant::now();
     }
 }
 pub const RENDER_SIZE: (u32, u32) = (1920, 1080);
@@ -208,16 +492,75 @@ pub struct Engine {
     pub egui: EguiRenderer,
     pub timing: FrameTiming,
     pub scene_manager: SceneManager,
+    /// Background CPU work polled each update, shared with the scene manager
+    pub jobs: Jobs,
     pub params: Params,
     pub tmp: TmpResources,
+    pub tabs: TabManager,
+    pub auto_exposure: AutoExposure,
+    pub upscaler: GuidedUpscaler,
+    pub post: PostProcess,
+    pub overlay: Overlay,
+    pub lightmap: LightmapBaker,
+    pub cryptomatte: Cryptomatte,
+    pub denoise_features: DenoiseFeatures,
+    pub picker: Picker,
+    pub magnifier: Magnifier,
+    pub distributed: DistributedRender,
+    pub render_queue: RenderQueue,
+    pub snapshots: Snapshots,
+    pub watcher: AssetWatcher,
+    pub audio: AudioInput,
+    pub gamepad: GamepadInput,
+    pub palette: CommandPalette,
+    pub settings: UiSettings,
+    /// Renders on the CPU in place of the compute shader while set
+    pub cpu: Option<CpuTracer>,
+    /// Camera and settings put back once the first scene has loaded, carried over from the
+    /// engine this one replaced, see `App::recover`
+    pub restore: Option<(Camera, Params)>,
 }
 
 impl Engine {
-    pub async fn new(window: Arc<Window>, width: u32, height: u32) -> Self {
-        let resources =
-            GraphicsResources::create_graphics_resources(window.clone(), width, height).await;
+    pub async fn new(
+        window: Arc<Window>,
+        width: u32,
+        height: u32,
+        scene: SceneName,
+        fallback: bool,
+    ) -> Result<Self, GpuError> {
+        let mut resources =
+            GraphicsResources::create_graphics_resources(window.clone(), width, height, fallback)
+                .await?;
         let mut ray_tracer = RayTracer::new(resources.device.clone(), resources.queue.clone());
-        ray_tracer.create_gpu_resources(&resources.texture_view, &resources.params_buffer);
+        ray_tracer.create_gpu_resources(
+            &resources.target.texture_view,
+            &resources.target.params_buffer,
+        );
+
+        let lightmap = LightmapBaker::new(&ray_tracer);
+        let cryptomatte = Cryptomatte::new(&ray_tracer);
+        let denoise_features = DenoiseFeatures::new(&ray_tracer);
+        let picker = Picker::new(&ray_tracer);
+
+        let mut auto_exposure =
+            AutoExposure::new(resources.device.clone(), resources.queue.clone());
+        auto_exposure.set_target(&resources.target.texture_view);
+
+        let mut upscaler =
+            GuidedUpscaler::new(resources.device.clone(), RENDER_SIZE.0, RENDER_SIZE.1);
+        upscaler.set_target(
+            &resources.target.texture_view,
+            &resources.target.params_buffer,
+            &ray_tracer.guide_view,
+        );
+        let mut post = PostProcess::new(
+            resources.device.clone(),
+            resources.queue.clone(),
+            RENDER_SIZE.0,
+            RENDER_SIZE.1,
+        );
+        post.set_target(&resources.target.texture_view, &upscaler.view);
 
         let mut egui_renderer = EguiRenderer::new(
             resources.device.clone(),
@@ -226,19 +569,33 @@ impl Engine {
             1,
             window.clone(),
         );
+        let settings = UiSettings::load(Path::new(SETTINGS_FILE));
+        resources.scale_factor = settings.scale;
+        settings.apply(egui_renderer.context());
 
         let renderer = Renderer::new(
             resources.device.clone(),
             &mut egui_renderer.renderer,
-            &resources.texture_view,
+            &resources.target.texture_view,
             &resources.surface_config,
-            &resources.params_buffer,
+            &resources.target.params_buffer,
+            &auto_exposure.buffer,
+            &post.view,
+            &picker,
         )
-        .unwrap();
+        .ok_or(GpuError::Renderer)?;
+        let overlay = Overlay::new(
+            resources.device.clone(),
+            resources.queue.clone(),
+            &mut egui_renderer.renderer,
+            &resources.surface_config,
+            &ray_tracer.guide_view,
+        );
 
         let asset_manager = AssetManager::new();
-        let mut scene_manager = SceneManager::new(asset_manager);
-        scene_manager.request_scene(SceneName::CornellBox);
+        let jobs = Jobs::default();
+        let mut scene_manager = SceneManager::new(asset_manager, jobs.clone());
+        scene_manager.request_scene(scene);
 
         let timing = FrameTiming::new();
         let params = Params {
@@ -249,17 +606,491 @@ impl Engine {
             skybox: 1,
             ..Default::default()
         };
-        let tmp = TmpResources::default();
+        let tmp = TmpResources {
+            gpu_report: resources.gpu_report.clone(),
+            ..Default::default()
+        };
+        let tabs = TabManager::new(scene);
 
-        Self {
+        Ok(Self {
             resources,
             ray_tracer,
             renderer,
             egui: egui_renderer,
             timing,
             scene_manager,
+            jobs,
             params,
             tmp,
+            tabs,
+            auto_exposure,
+            upscaler,
+            post,
+            overlay,
+            lightmap,
+            cryptomatte,
+            denoise_features,
+            picker,
+            magnifier: Magnifier::default(),
+            distributed: DistributedRender::new(),
+            render_queue: RenderQueue::new(),
+            snapshots: Snapshots::default(),
+            watcher: AssetWatcher::new(),
+            audio: AudioInput::default(),
+            gamepad: GamepadInput::default(),
+            palette: CommandPalette::default(),
+            settings,
+            cpu: None,
+            restore: None,
+        })
+    }
+    /// Opens a new tab with its own accumulation texture, loading the same scene as the active tab.
+    pub fn open_tab(&mut self) {
+        let id = self.tabs.next_id();
+        let target = GraphicsResources::create_render_target(
+            &self.resources.device,
+            RENDER_SIZE.0,
+            RENDER_SIZE.1,
+        );
+        let display_bind_group = self
+            .renderer
+            .create_bind_group(&target.texture_view, &target.params_buffer);
+        let title = self.scene_manager.selected_scene;
+        let mut params = self.params;
+        params.reset_frame();
+
+        self.tabs.tabs.push(SceneTab {
+            id,
+            title,
+            parked: Some(ParkedTab {
+                scene: Scene::new(),
+                selected_scene: title,
+                prev_scene: title,
+                selected_entity: -1,
+                params,
+                target,
+                textures_bind_group: None,
+                texture_slots: TextureSlots::default(),
+                display_bind_group,
+            }),
+        });
+        self.scene_manager.request_scene_for_tab(id, title);
+        self.tabs.selected = self.tabs.tabs.len() - 1;
+    }
+    /// Parks the active tab and makes the tab at `index` active, keeping both accumulation states intact.
+    pub fn switch_tab(&mut self, index: usize) {
+        if index == self.tabs.active || index >= self.tabs.tabs.len() {
+            self.tabs.selected = self.tabs.active;
+            return;
+        }
+        let Some(mut tab) = self.tabs.tabs[index].parked.take() else {
+            return;
+        };
+        self.lightmap.cancel();
+        let scene_manager = &mut self.scene_manager;
+        std::mem::swap(&mut scene_manager.scene, &mut tab.scene);
+        std::mem::swap(&mut scene_manager.selected_scene, &mut tab.selected_scene);
+        std::mem::swap(&mut scene_manager.prev_scene, &mut tab.prev_scene);
+        std::mem::swap(&mut scene_manager.selected_entity, &mut tab.selected_entity);
+        scene_manager.selection.clear();
+        std::mem::swap(&mut self.params, &mut tab.params);
+        std::mem::swap(&mut self.resources.target, &mut tab.target);
+        // Rebound rather than parked with the tab, the scene buffers may have been replaced since
+        self.ray_tracer.set_target(
+            &self.resources.target.texture_view,
+            &self.resources.target.params_buffer,
+        );
+        std::mem::swap(
+            &mut self.ray_tracer.textures_bind_group,
+            &mut tab.textures_bind_group,
+        );
+        std::mem::swap(&mut self.ray_tracer.texture_slots, &mut tab.texture_slots);
+        self.renderer
+            .swap_bind_group(&mut self.egui.renderer, &mut tab.display_bind_group);
+
+        self.auto_exposure
+            .set_target(&self.resources.target.texture_view);
+        self.upscaler.set_target(
+            &self.resources.target.texture_view,
+            &self.resources.target.params_buffer,
+            &self.ray_tracer.guide_view,
+        );
+        self.post
+            .set_target(&self.resources.target.texture_view, &self.upscaler.view);
+
+        if self.ray_tracer.textures_bind_group.is_none() {
+            self.ray_tracer
+                .load_scene_gpu_resources(&self.scene_manager.scene);
+        }
+
+        let previous = self.tabs.active;
+        self.tabs.tabs[previous].parked = Some(tab);
+        self.scene_manager.tab_id = self.tabs.tabs[index].id;
+        self.tabs.active = index;
+        self.tabs.selected = index;
+        self.timing.reset();
+        self.timing.mark(FrameEvent::SceneSwitch);
+        crash::set_scene(self.scene_manager.selected_scene, &self.scene_manager.scene);
+    }
+    /// The frame's compute passes, callers add the passes drawing to the surface before executing it.
+    pub fn frame_graph<'a>(&self) -> FrameGraph<'a, Engine> {
+        let mut graph = FrameGraph::default();
+        // The CPU tracer's accumulation is uploaded during the update instead
+        if self.cpu.is_none() && !self.tmp.time_limit.stopped {
+            graph.add_pass(
+                "Ray Tracer",
+                &[],
+                &[FrameResource::Accumulation],
+                |engine: &mut Engine, encoder| {
+                    engine.ray_tracer.render(
+                        encoder,
+                        engine.params.traced_width(),
+                        engine.params.height,
+                    );
+                },
+            );
+        }
+        // The focal plane overlay reads the guide's distances too
+        if self.upscaler.active || self.overlay.show_focus {
+            graph.add_pass(
+                "Guide",
+                &[],
+                &[FrameResource::Guide],
+                |engine: &mut Engine, encoder| engine.ray_tracer.render_guide(encoder),
+            );
+        }
+        if self.upscaler.active {
+            graph.add_pass(
+                "Upscale",
+                &[FrameResource::Accumulation, FrameResource::Guide],
+                &[FrameResource::Preview],
+                |engine: &mut Engine, encoder| engine.upscaler.resolve(encoder),
+            );
+        }
+        if self.post.active {
+            graph.add_pass(
+                "Post Process",
+                &[FrameResource::Accumulation, FrameResource::Preview],
+                &[FrameResource::Post],
+                |engine: &mut Engine, encoder| engine.post.render(encoder),
+            );
+        }
+        graph.add_pass(
+            "Auto Exposure",
+            &[FrameResource::Accumulation],
+            &[FrameResource::Luminance],
+            |engine: &mut Engine, encoder| {
+                engine
+                    .auto_exposure
+                    .render(encoder, engine.params.width, engine.params.height);
+            },
+        );
+        graph.add_pass(
+            "Picking",
+            &[],
+            &[FrameResource::Ids],
+            |engine: &mut Engine, encoder| {
+                engine.picker.render(
+                    encoder,
+                    &engine.ray_tracer,
+                    engine.params.width,
+                    engine.params.height,
+                    engine.scene_manager.selected_entity,
+                );
+            },
+        );
+        if self.lightmap.is_baking() {
+            graph.add_pass(
+                "Lightmap Bake",
+                &[],
+                &[FrameResource::Lightmap],
+                |engine: &mut Engine, encoder| engine.lightmap.bake(encoder, &engine.ray_tracer),
+            );
+        }
+        graph
+    }
+    /// Linear scale applied to the accumulated radiance when displaying or exporting.
+    pub fn display_exposure(&self) -> f32 {
+        let scale =
+            (self.params.exposure + self.scene_manager.scene.camera.exposure.stops()).exp2();
+        if self.params.auto_exposure != 0 {
+            scale * self.auto_exposure.exposure
+        } else {
+            scale
+        }
+    }
+    pub fn close_tab(&mut self, index: usize) {
+        if self.tabs.tabs.len() <= 1 || index >= self.tabs.tabs.len() {
+            return;
+        }
+        if index == self.tabs.active {
+            self.switch_tab(if index == 0 { 1 } else { index - 1 });
+        }
+        self.tabs.tabs.remove(index);
+        if self.tabs.active > index {
+            self.tabs.active -= 1;
+        }
+        self.tabs.selected = self.tabs.active;
+    }
+    /// Hands a loaded scene to the tab that requested it.
+    pub fn receive_scene(&mut self, tab_id: usize, scene: Scene) {
+        if tab_id == self.scene_manager.tab_id {
+            self.lightmap.cancel();
+            // Free the old scene's textures and buffers before allocating for the new one
+            self.ray_tracer.unload_scene();
+            self.scene_manager.scene = scene;
+            Self::take_scene_settings(
+                &mut self.scene_manager.scene,
+                &mut self.params,
+                self.scene_manager.keep_settings,
+            );
+            if let Some((camera, params)) = self.restore.take() {
+                self.scene_manager.scene.camera = camera;
+                self.params = params;
+            }
+            self.ray_tracer
+                .load_scene_gpu_resources(&self.scene_manager.scene);
+            self.timing.reset();
+            self.params.reset_frame();
+            self.timing.mark(FrameEvent::SceneSwitch);
+            crash::set_scene(self.scene_manager.selected_scene, &self.scene_manager.scene);
+        } else if let Some(tab) = self.tabs.find(tab_id)
+            && let Some(parked) = tab.parked.as_mut()
+        {
+            parked.scene = scene;
+            Self::take_scene_settings(
+                &mut parked.scene,
+                &mut parked.params,
+                self.scene_manager.keep_settings,
+            );
+            parked.textures_bind_group = None;
+            parked.params.reset_frame();
+        }
+    }
+    /// Loads a freshly loaded scene's render settings into `params`, or drops its post stack too
+    /// when the current settings are to be kept.
+    fn take_scene_settings(scene: &mut Scene, params: &mut Params, keep: bool) {
+        if keep {
+            scene.post = None;
+        } else {
+            scene.settings.apply(params);
+        }
+    }
+    /// Adds a streamed mesh chunk to the tab it was loaded for, if that tab still shows the scene.
+    pub fn receive_chunk(&mut self, tab_id: usize, chunk: MeshChunk) {
+        if tab_id == self.scene_manager.tab_id {
+            let scene = &mut self.scene_manager.scene;
+            if scene.stream_id != chunk.stream_id {
+                return;
+            }
+            let first_mesh = scene.spheres.len() as i32;
+            if chunk.complete && self.scene_manager.selected_entity >= first_mesh {
+                // Swapping the parts for the whole mesh shifts the mesh indices
+                self.scene_manager.selected_entity = -1;
+            }
+            scene.apply_chunk(chunk);
+            self.timing.reset();
+            self.params.reset_frame();
+            self.timing.mark(FrameEvent::BvhRebuild);
+        } else if let Some(tab) = self.tabs.find(tab_id)
+            && let Some(parked) = tab.parked.as_mut()
+            && parked.scene.stream_id == chunk.stream_id
+        {
+            if chunk.complete && parked.selected_entity >= parked.scene.spheres.len() as i32 {
+                parked.selected_entity = -1;
+            }
+            parked.scene.apply_chunk(chunk);
+            parked.params.reset_frame();
+        }
+    }
+    /// Swaps in finer mip levels for textures the camera has come close enough to resolve. Levels
+    /// are never dropped back down while a scene is open, so orbiting doesn't keep re-uploading.
+    pub fn refine_textures(&mut self) {
+        let scene = &mut self.scene_manager.scene;
+        let mut refined = false;
+        for (index, resident) in scene.texture_levels.iter_mut().enumerate() {
+            let Some(resident) = resident.as_mut().filter(|resident| resident.level > 0) else {
+                continue;
+            };
+            let animated = self
+                .scene_manager
+                .animated_textures
+                .contains_key(&(index as i32));
+            let uses = scene
+                .texture_uses
+                .iter()
+                .filter(|texture_use| texture_use.texture == index);
+            let needed = resident
+                .chain
+                .needed_level(uses, &scene.camera, self.params.height);
+            if needed >= resident.level {
+                continue;
+            }
+            // Sequence frames skip the mip cache, the following frames decode at the new level
+            if animated {
+                resident.level = needed;
+                continue;
+            }
+            match resident.chain.load(&resident.path, needed) {
+                Ok(image) => {
+                    log::info!("Loaded mip {} of {}", needed, resident.path);
+                    let image = Arc::new(image);
+                    self.scene_manager
+                        .cpu_textures
+                        .insert(resident.path.clone(), image.clone());
+                    scene.textures[index] = image;
+                    resident.level = needed;
+                    refined = true;
+                }
+                Err(e) => {
+                    log::error!("Failed to load texture {}: {}", resident.path, e);
+                    resident.level = 0;
+                }
+            }
+        }
+        if refined {
+            self.ray_tracer.update_textures(&scene.textures);
+            self.params.reset_frame();
+        }
+    }
+    /// Watches the active scene's models and textures, reloading any that changed on disk.
+    pub fn reload_changed_assets(&mut self) {
+        if self.watcher.enabled {
+            let scene_manager = &self.scene_manager;
+            let textures: Vec<String> = scene_manager
+                .loaded_textures
+                .iter()
+                .map(|entry| entry.key().clone())
+                .collect();
+            let models = scene_manager
+                .scene
+                .meshes
+                .iter()
+                .filter_map(|mesh| mesh.source.as_deref());
+            self.watcher
+                .watch(textures.iter().map(String::as_str).chain(models));
+        }
+        for path in self.watcher.poll() {
+            self.scene_manager.reload_model(&path);
+            if self.scene_manager.loaded_textures.contains_key(&path) {
+                self.reload_texture(&path);
+                self.timing.reset();
+            }
+        }
+        if self.scene_manager.poll_reloads() > 0 {
+            self.params.reset_frame();
+            self.timing.reset();
+        }
+    }
+    /// Swaps the timeline's current frame of every image sequence into its texture slot, at the
+    /// level the slot has resident, returning whether any changed. Frames are decoded on the job
+    /// pool a few ahead of the timeline, a slot keeps its last frame until the next is ready.
+    /// Rendering a sequence can't show a frame late, so it decodes any missing one on the spot.
+    pub fn update_animated_textures(&mut self) -> bool {
+        let frames = &mut self.scene_manager.sequence_frames;
+        frames.poll();
+        let scene = &mut self.scene_manager.scene;
+        let time = scene.timeline.time();
+        let rendering = scene.timeline.sequence.is_some();
+        let mut wanted = HashSet::new();
+        let mut changed = false;
+        for entry in self.scene_manager.animated_textures.iter() {
+            let index = *entry.key() as usize;
+            let animation = entry.value();
+            let Some(Some(resident)) = scene.texture_levels.get_mut(index) else {
+                continue;
+            };
+            let path = animation.frame_at(time);
+            if resident.path != path {
+                let image = match frames.get(index, path) {
+                    Some(image) => Some(image),
+                    None if rendering => match decode_frame(path, resident.level) {
+                        Ok(image) => Some(Arc::new(image)),
+                        Err(e) => {
+                            log::error!("Failed to load texture sequence frame {}: {}", path, e);
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                if let Some(image) = image {
+                    resident.path = path.to_owned();
+                    scene.textures[index] = image;
+                    changed = true;
+                }
+            }
+            for ahead in 0..=PREFETCH_FRAMES {
+                let path = animation.frame_after(time, ahead);
+                wanted.insert((index, path.to_owned()));
+                if resident.path != path {
+                    frames.request(&self.jobs, index, path, resident.level);
+                }
+            }
+        }
+        frames.retain(&wanted);
+        if changed {
+            self.ray_tracer.update_textures(&scene.textures);
+        }
+        changed
+    }
+    /// Re-reads a texture from disk and swaps it into every open tab that uses its slot, at the
+    /// level the active scene had resident.
+    pub fn reload_texture(&mut self, path: &str) {
+        let Some(index) = self
+            .scene_manager
+            .loaded_textures
+            .get(path)
+            .map(|i| *i as usize)
+        else {
+            return;
+        };
+        let scene = &mut self.scene_manager.scene;
+        let level = scene
+            .texture_levels
+            .get(index)
+            .and_then(|resident| resident.as_ref().map(|resident| resident.level))
+            .unwrap_or(0);
+        let loaded =
+            MipChain::prepare(path).and_then(|chain| Ok((chain, chain.load(path, level)?)));
+        let (chain, image) = match loaded {
+            Ok((chain, image)) => (chain, Arc::new(image)),
+            Err(e) => {
+                log::error!("Failed to reload texture {}: {}", path, e);
+                return;
+            }
+        };
+        log::info!("Reloaded texture {}", path);
+        self.scene_manager
+            .mip_chains
+            .insert(path.to_string(), chain);
+        self.scene_manager
+            .cpu_textures
+            .insert(path.to_string(), image.clone());
+        let resident = ResidentTexture {
+            path: path.to_string(),
+            chain,
+            level: chain.level_of(&image),
+        };
+        let scene = &mut self.scene_manager.scene;
+        if let Some(slot) = scene.textures.get_mut(index) {
+            *slot = image.clone();
+            if let Some(levels) = scene.texture_levels.get_mut(index) {
+                *levels = Some(resident.clone());
+            }
+            self.ray_tracer.update_textures(&scene.textures);
+            self.params.reset_frame();
+        }
+        for tab in self.tabs.tabs.iter_mut() {
+            if let Some(parked) = tab.parked.as_mut()
+                && let Some(slot) = parked.scene.textures.get_mut(index)
+            {
+                *slot = image.clone();
+                if let Some(levels) = parked.scene.texture_levels.get_mut(index) {
+                    *levels = Some(resident.clone());
+                }
+                parked.textures_bind_group = None;
+                parked.params.reset_frame();
+            }
         }
     }
 }
@@ -0,0 +1,126 @@
+use crate::core::{
+    app::Params,
+    distributed::{CameraState, Worker},
+};
+use crate::scene::scene::{Scene, SceneName};
+
+/// Radiance every pixel of the furnace scene converges to
+pub const EXPECTED: f32 = 1.0;
+/// Furthest any pixel may end up from `EXPECTED`. In the furnace every path carries exactly the
+/// radiance it started with, so there is no noise to wait out and this only has to absorb
+/// rounding.
+pub const TOLERANCE: f32 = 0.01;
+const SIZE: u32 = 64;
+const SAMPLES: u32 = 64;
+
+/// Mean and worst deviation from `EXPECTED` of the furnace rendered with the path tracer.
+pub struct FurnaceResult {
+    pub mean: f32,
+    pub worst: f32,
+}
+
+impl FurnaceResult {
+    pub fn passed(&self) -> bool {
+        (self.mean - EXPECTED).abs() <= TOLERANCE && self.worst <= TOLERANCE
+    }
+}
+
+/// Renders `SceneName::Furnace` on `worker` and measures how far it is from converging to
+/// `EXPECTED`. Clamps are left off, they remove energy by design.
+pub fn check(worker: &mut Worker) -> Result<FurnaceResult, String> {
+    let params = Params {
+        width: SIZE,
+        height: SIZE,
+        rays_per_pixel: 8,
+        // Light only ever bounces off the sphere once, the rest are to catch paths that don't end
+        number_of_bounces: 8,
+        ..Default::default()
+    };
+    let mut camera = CameraState::from_camera(Scene::from_name(SceneName::Furnace).camera());
+    camera.aspect = 1.0;
+    let frames = SAMPLES / params.rays_per_pixel as u32;
+    let pixels = worker
+        .render_frame(SceneName::Furnace, camera, params, frames)
+        .map_err(|e| e.to_string())?;
+    let mut sum = 0.0;
+    let mut worst: f32 = 0.0;
+    for pixel in pixels.chunks_exact(4) {
+        for &channel in &pixel[..3] {
+            sum += channel as f64;
+            worst = worst.max((channel - EXPECTED).abs());
+        }
+    }
+    Ok(FurnaceResult {
+        mean: (sum / (pixels.len() / 4 * 3).max(1) as f64) as f32,
+        worst,
+    })
+}
+
+/// Runs `check` on a headless device, or the CPU, for `--furnace`, returning whether it passed.
+pub async fn run_check(cpu: bool) -> bool {
+    let mut worker = Worker::headless(cpu).await;
+    match check(&mut worker) {
+        Ok(result) if result.passed() => {
+            log::info!(
+                "Furnace passed, mean {:.4}, worst pixel off by {:.4}",
+                result.mean,
+                result.worst
+            );
+            true
+        }
+        Ok(result) => {
+            log::error!(
+                "Furnace failed, mean {:.4} and worst pixel off by {:.4}, expected {} within {}",
+                result.mean,
+                result.worst,
+                EXPECTED,
+                TOLERANCE
+            );
+            false
+        }
+        Err(e) => {
+            log::error!("Furnace render failed: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui_wgpu::wgpu;
+
+    use super::*;
+    use crate::core::engine::GraphicsResources;
+
+    /// Needs a GPU, machines without one skip it rather than fail.
+    #[test]
+    fn furnace_converges() {
+        if wgpu::Instance::enabled_backend_features().is_empty() {
+            eprintln!("No GPU backend built in, skipping the furnace check");
+            return;
+        }
+        let Ok((device, queue)) = pollster::block_on(GraphicsResources::create_headless_device())
+        else {
+            eprintln!("No GPU, skipping the furnace check");
+            return;
+        };
+        let result = check(&mut Worker::new(device, queue)).unwrap();
+        assert!(
+            result.passed(),
+            "mean {} worst {}",
+            result.mean,
+            result.worst
+        );
+    }
+
+    #[test]
+    fn cpu_furnace_converges() {
+        let result = check(&mut Worker::cpu()).unwrap();
+        assert!(
+            result.passed(),
+            "mean {} worst {}",
+            result.mean,
+            result.worst
+        );
+    }
+}
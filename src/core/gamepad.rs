@@ -0,0 +1,136 @@
+#[cfg(feature = "gamepad")]
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use glam::{Vec2, Vec3};
+
+/// Stick deflection ignored around the centre, worn sticks rarely rest at zero
+const DEAD_ZONE: f32 = 0.15;
+/// Factor each shoulder button press changes the fly speed by
+pub const SPEED_STEP: f32 = 1.5;
+
+/// What the gamepad asked for this update, see `GamepadInput::update`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GamepadFrame {
+    /// Camera space movement from -1 to 1 on each axis: the left stick strafes and moves forward,
+    /// the right and left triggers rise and sink
+    pub movement: Vec3,
+    /// Turn rate in radians per second from the right stick, yaw then pitch
+    pub look: Vec2,
+    /// Presses of the right shoulder button less the left, each speeding up by `SPEED_STEP`
+    pub speed_steps: i32,
+    /// The south face button, A or cross, saves a render
+    pub screenshot: bool,
+}
+
+/// Flies the camera from a gamepad, for demos on a TV or a fly-through from the couch. The most
+/// recently used pad drives it when several are connected.
+pub struct GamepadInput {
+    /// Turn rate at full right stick deflection in radians per second
+    pub look_speed: f32,
+    /// Pushing the right stick up looks down, like a flight stick
+    pub invert_y: bool,
+    /// Name of the pad in use, `None` when none is connected
+    pub name: Option<String>,
+    /// Why gamepads couldn't be opened
+    pub error: Option<String>,
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<Gilrs>,
+    #[cfg(feature = "gamepad")]
+    active: Option<GamepadId>,
+}
+
+impl Default for GamepadInput {
+    /// Opens the platform's gamepads, connected and any plugged in later.
+    fn default() -> Self {
+        #[cfg(feature = "gamepad")]
+        let (gilrs, error) = match Gilrs::new() {
+            Ok(gilrs) => (Some(gilrs), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        #[cfg(not(feature = "gamepad"))]
+        let error = None;
+        Self {
+            look_speed: 2.5,
+            invert_y: false,
+            name: None,
+            error,
+            #[cfg(feature = "gamepad")]
+            gilrs,
+            #[cfg(feature = "gamepad")]
+            active: None,
+        }
+    }
+}
+
+impl GamepadInput {
+    /// Reads the pad's events and current stick and trigger positions. Without the `gamepad`
+    /// feature nothing is ever pressed.
+    #[cfg(feature = "gamepad")]
+    pub fn update(&mut self) -> GamepadFrame {
+        let mut frame = GamepadFrame::default();
+        let Some(gilrs) = self.gilrs.as_mut() else {
+            return frame;
+        };
+        while let Some(gilrs::Event { id, event, .. }) = gilrs.next_event() {
+            match event {
+                EventType::Connected => self.active = Some(id),
+                EventType::Disconnected if self.active == Some(id) => self.active = None,
+                EventType::ButtonPressed(button, _) => {
+                    self.active = Some(id);
+                    match button {
+                        Button::RightTrigger => frame.speed_steps += 1,
+                        Button::LeftTrigger => frame.speed_steps -= 1,
+                        Button::South => frame.screenshot = true,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Pads plugged in before the app started never send `Connected`
+        if self.active.is_none() {
+            self.active = gilrs.gamepads().next().map(|(id, _)| id);
+        }
+        let Some(pad) = self.active.and_then(|id| gilrs.connected_gamepad(id)) else {
+            if let Some(name) = self.name.take() {
+                log::info!("Gamepad {} disconnected", name);
+            }
+            return frame;
+        };
+        if self.name.as_deref() != Some(pad.name()) {
+            log::info!("Flying the camera with {}", pad.name());
+            self.name = Some(pad.name().to_owned());
+        }
+        let stick = |x: Axis, y: Axis| dead_zone(Vec2::new(pad.value(x), pad.value(y)));
+        let trigger = |button: Button| pad.button_data(button).map_or(0.0, |data| data.value());
+        let movement = stick(Axis::LeftStickX, Axis::LeftStickY);
+        frame.movement = Vec3::new(
+            movement.x,
+            trigger(Button::RightTrigger2) - trigger(Button::LeftTrigger2),
+            movement.y,
+        );
+        let look = stick(Axis::RightStickX, Axis::RightStickY);
+        // Pitch grows looking down, the stick's Y grows pushing up
+        let pitch = match self.invert_y {
+            true => look.y,
+            false => -look.y,
+        };
+        // Squared so small deflections aim finely while a full push still turns quickly
+        frame.look = Vec2::new(look.x, pitch) * look.length() * self.look_speed;
+        frame
+    }
+    #[cfg(not(feature = "gamepad"))]
+    pub fn update(&mut self) -> GamepadFrame {
+        GamepadFrame::default()
+    }
+}
+
+/// `stick` with the dead zone cut out of its radius and the rest stretched back to 0 to 1, so
+/// leaving the dead zone doesn't jump.
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+fn dead_zone(stick: Vec2) -> Vec2 {
+    let length = stick.length();
+    if length <= DEAD_ZONE {
+        return Vec2::ZERO;
+    }
+    stick / length * ((length - DEAD_ZONE) / (1.0 - DEAD_ZONE)).min(1.0)
+}
@@ -0,0 +1,159 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{Receiver, TryRecvError, sync_channel},
+    },
+    time::{Duration, Instant},
+};
+
+/// Runs CPU work on rayon's pool for `App::update` to poll, so anything slow enough to drop
+/// frames stays off the main thread. Clones share the list of running jobs shown in the UI.
+#[derive(Clone, Default)]
+pub struct Jobs {
+    running: Arc<Mutex<Vec<(u64, String, Instant)>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+/// Result of a job still to be collected, see `Job::poll`. Dropping it leaves the job to finish
+/// with nobody waiting for it.
+pub struct Job<T> {
+    pub name: String,
+    rx: Receiver<Result<T, String>>,
+}
+
+impl Jobs {
+    pub fn spawn<T: Send + 'static>(
+        &self,
+        name: impl Into<String>,
+        work: impl FnOnce() -> T + Send + 'static,
+    ) -> Job<T> {
+        let name = name.into();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.running
+            .lock()
+            .unwrap()
+            .push((id, name.clone(), Instant::now()));
+        let running = self.running.clone();
+        let (tx, rx) = sync_channel(1);
+        rayon::spawn(move || {
+            // A panicking job shouldn't take the pool's thread or the app with it
+            let result = panic::catch_unwind(AssertUnwindSafe(work)).map_err(|panic| {
+                panic
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or("panicked".to_owned())
+            });
+            running.lock().unwrap().retain(|(job, ..)| *job != id);
+            let _ = tx.send(result);
+        });
+        Job { name, rx }
+    }
+    /// Names of the jobs still going and how long each has run, oldest first.
+    pub fn running(&self) -> Vec<(String, Duration)> {
+        self.running
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, name, started)| (name.clone(), started.elapsed()))
+            .collect()
+    }
+}
+
+impl<T> Job<T> {
+    /// `None` while the job runs, then its result, or why it failed, exactly once.
+    pub fn poll(&mut self) -> Option<Result<T, String>> {
+        match self.rx.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("already collected".to_owned())),
+        }
+    }
+}
+
+/// Results of the jobs in `jobs` that have finished, which are removed. Failures are logged.
+pub fn finished<T>(jobs: &mut Vec<Job<T>>) -> Vec<T> {
+    let mut finished = vec![];
+    jobs.retain_mut(|job| match job.poll() {
+        Some(Ok(result)) => {
+            finished.push(result);
+            false
+        }
+        Some(Err(e)) => {
+            log::error!("Job {} failed: {}", job.name, e);
+            false
+        }
+        None => true,
+    });
+    finished
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Polls until the job is done, the pool may not have started it yet.
+    fn wait<T>(job: &mut Job<T>) -> Result<T, String> {
+        let started = Instant::now();
+        loop {
+            if let Some(result) = job.poll() {
+                return result;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "job never finished"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn spawn_and_poll() {
+        let jobs = Jobs::default();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let mut job = jobs.spawn("Answer", move || {
+            rx.recv().unwrap();
+            42
+        });
+        assert_eq!(job.poll(), None);
+        assert_eq!(jobs.running().len(), 1);
+        assert_eq!(jobs.running()[0].0, "Answer");
+        tx.send(()).unwrap();
+        assert_eq!(wait(&mut job), Ok(42));
+        assert!(jobs.running().is_empty());
+        assert!(job.poll().is_some_and(|result| result.is_err()));
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let jobs = Jobs::default();
+        let mut job = jobs.spawn("Boom", || -> u32 { panic!("boom") });
+        assert_eq!(wait(&mut job), Err("boom".to_owned()));
+        let mut job = jobs.spawn("Formatted", || -> u32 { panic!("{} failed", 7) });
+        assert_eq!(wait(&mut job), Err("7 failed".to_owned()));
+        assert!(jobs.running().is_empty());
+    }
+
+    #[test]
+    fn finished_collects_results_and_drops_failures() {
+        let jobs = Jobs::default();
+        let mut pending = vec![
+            jobs.spawn("One", || 1),
+            jobs.spawn("Fails", || -> i32 { panic!("no") }),
+            jobs.spawn("Two", || 2),
+        ];
+        let started = Instant::now();
+        let mut results = vec![];
+        while !pending.is_empty() {
+            results.extend(finished(&mut pending));
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "jobs never finished"
+            );
+        }
+        results.sort();
+        assert_eq!(results, [1, 2]);
+    }
+}
@@ -0,0 +1,214 @@
+use std::error::Error;
+
+use glam::Vec3;
+use image::{RgbaImage, imageops};
+
+use crate::core::{
+    asset::{asset_path, decode_texture},
+    bvh::Aabb,
+    cache,
+};
+use crate::scene::{
+    camera::Camera,
+    components::{
+        geometry::{mesh::MeshInstance, sphere::Sphere},
+        material::MaterialUniform,
+    },
+};
+
+/// Levels stop once either side is this small, smaller ones are never worth the lookup
+const MIN_SIZE: u32 = 16;
+
+/// A texture filtered down to every power of two and cached one level per file, so a scene only
+/// decodes the file once and afterwards reads back just the levels it can resolve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MipChain {
+    /// Hash of the file's contents, editing the texture starts a new chain
+    key: u64,
+    pub width: u32,
+    pub height: u32,
+    pub levels: u32,
+}
+
+impl MipChain {
+    /// Makes sure every level of the texture at `path` is cached, decoding and filtering it the
+    /// first time it is seen.
+    pub fn prepare(path: &str) -> Result<MipChain, Box<dyn Error>> {
+        let bytes = std::fs::read(asset_path(path)?)?;
+        let key = cache::hash(&[&bytes]);
+        if let Some(header) = cache::load("mips", key) {
+            let mut reader = cache::Reader::new(&header);
+            if let (Some(width), Some(height), Some(levels)) =
+                (reader.u32(), reader.u32(), reader.u32())
+            {
+                return Ok(MipChain {
+                    key,
+                    width,
+                    height,
+                    levels,
+                });
+            }
+        }
+        let mut image = decode_texture(&bytes)?;
+        let chain = MipChain {
+            key,
+            width: image.width(),
+            height: image.height(),
+            levels: Self::level_count(image.width(), image.height()),
+        };
+        log::info!("Caching {} mip levels of {}", chain.levels, path);
+        for level in 0..chain.levels {
+            if level > 0 {
+                let (width, height) = chain.level_size(level);
+                image = imageops::resize(&image, width, height, imageops::FilterType::Triangle);
+            }
+            cache::store(&Self::level_kind(level), key, image.as_raw());
+        }
+        let mut header = cache::Writer::default();
+        header.u32(chain.width);
+        header.u32(chain.height);
+        header.u32(chain.levels);
+        cache::store("mips", key, &header.bytes);
+        Ok(chain)
+    }
+    fn level_count(width: u32, height: u32) -> u32 {
+        let mut levels = 1;
+        while (width >> levels).min(height >> levels) >= MIN_SIZE {
+            levels += 1;
+        }
+        levels
+    }
+    fn level_kind(level: u32) -> String {
+        format!("mip{}", level)
+    }
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+    /// Level an image of this chain was read from.
+    pub fn level_of(&self, image: &RgbaImage) -> u32 {
+        (0..self.levels)
+            .find(|&level| self.level_size(level).0 <= image.width())
+            .unwrap_or(0)
+    }
+    /// Reads one level back, re-caching the texture at `path` if the entry has gone missing.
+    pub fn load(&self, path: &str, level: u32) -> Result<RgbaImage, Box<dyn Error>> {
+        let level = level.min(self.levels - 1);
+        let (width, height) = self.level_size(level);
+        let read = |chain: &MipChain| {
+            cache::load(&Self::level_kind(level), chain.key)
+                .and_then(|data| RgbaImage::from_raw(width, height, data))
+        };
+        if let Some(image) = read(self) {
+            return Ok(image);
+        }
+        let chain = MipChain::prepare(path)?;
+        if chain != *self {
+            return Err(format!("{} changed on disk", path).into());
+        }
+        read(&chain).ok_or(format!("Failed to cache mip {} of {}", level, path).into())
+    }
+    /// Coarsest level that still has a texel per pixel everywhere `uses` can be seen from
+    /// `camera` at `height` pixels tall, the full resolution when nothing says otherwise.
+    pub fn needed_level<'a>(
+        &self,
+        uses: impl Iterator<Item = &'a TextureUse>,
+        camera: &Camera,
+        height: u32,
+    ) -> u32 {
+        let pos = camera.transform.pos;
+        let pixels_per_unit_at_1 = height as f32 / (2.0 * (camera.fov.to_radians() * 0.5).tan());
+        let texels = self.width.max(self.height) as f32;
+        uses.map(|texture_use| {
+            let nearest = pos.clamp(texture_use.bounds.min, texture_use.bounds.max);
+            let distance = pos.distance(nearest).max(camera.near);
+            let texels_per_unit = texture_use.uv_density * texels;
+            let ratio = texels_per_unit / (pixels_per_unit_at_1 / distance);
+            match ratio > 1.0 {
+                true => ratio.log2().floor() as u32,
+                false => 0,
+            }
+        })
+        .min()
+        .unwrap_or(0)
+        .min(self.levels - 1)
+    }
+}
+
+/// An entity a texture is mapped onto, enough to tell how large its texels can appear.
+#[derive(Debug, Clone, Copy)]
+pub struct TextureUse {
+    pub texture: usize,
+    pub bounds: Aabb,
+    /// Square root of UV area over world area, so texture widths per world unit
+    pub uv_density: f32,
+}
+
+fn material_textures(material: &MaterialUniform) -> impl Iterator<Item = usize> {
+    [
+        material.diffuse_index,
+        material.normal_index,
+        material.orm_index,
+        material.blend_mask,
+    ]
+    .into_iter()
+    .filter_map(|index| usize::try_from(index).ok())
+}
+
+/// Where every texture of the scene's spheres and meshes is used.
+pub fn texture_uses(spheres: &[Sphere], meshes: &[MeshInstance]) -> Vec<TextureUse> {
+    let mut uses = vec![];
+    for sphere in spheres {
+        let centre = Vec3::from_array(sphere.pos);
+        let bounds = Aabb {
+            min: centre - Vec3::splat(sphere.radius),
+            max: centre + Vec3::splat(sphere.radius),
+        };
+        // The whole texture wraps the sphere once
+        let uv_density = (4.0 * std::f32::consts::PI).sqrt().recip() / sphere.radius;
+        uses.extend(
+            material_textures(&sphere.material).map(|texture| TextureUse {
+                texture,
+                bounds,
+                uv_density,
+            }),
+        );
+    }
+    for mesh in meshes {
+        if material_textures(&mesh.material).next().is_none() {
+            continue;
+        }
+        let model_to_world = mesh.transform.to_matrix();
+        let mut bounds = Aabb::default();
+        let (mut uv_area, mut world_area) = (0.0, 0.0);
+        for triangle in mesh.data.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                .map(|index| &mesh.data.vertices[index as usize]);
+            let [pa, pb, pc] = [a, b, c].map(|v| model_to_world.transform_point3(v.pos));
+            [pa, pb, pc].into_iter().for_each(|p| bounds.grow_point(p));
+            world_area += (pb - pa).cross(pc - pa).length() * 0.5;
+            let (ab, ac) = (
+                [b.uv[0] - a.uv[0], b.uv[1] - a.uv[1]],
+                [c.uv[0] - a.uv[0], c.uv[1] - a.uv[1]],
+            );
+            uv_area += (ab[0] * ac[1] - ab[1] * ac[0]).abs() * 0.5;
+        }
+        if !bounds.is_valid() || world_area <= 0.0 {
+            continue;
+        }
+        let uv_density = (uv_area / world_area).sqrt();
+        uses.extend(material_textures(&mesh.material).map(|texture| TextureUse {
+            texture,
+            bounds,
+            uv_density,
+        }));
+    }
+    uses
+}
+
+/// The level of a chain currently in a scene's texture array.
+#[derive(Debug, Clone)]
+pub struct ResidentTexture {
+    pub path: String,
+    pub chain: MipChain,
+    pub level: u32,
+}
@@ -1,4 +1,23 @@
+pub mod action;
+pub mod annotation;
 pub mod app;
 pub mod asset;
+pub mod audio;
 pub mod bvh;
+pub mod cache;
+pub mod compare;
+pub mod crash;
+pub mod distributed;
+pub mod download;
 pub mod engine;
+pub mod furnace;
+pub mod gamepad;
+pub mod jobs;
+pub mod mip_cache;
+pub mod queue;
+pub mod settings;
+pub mod snapshot;
+pub mod stream;
+pub mod tabs;
+pub mod time_limit;
+pub mod watcher;
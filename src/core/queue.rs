@@ -0,0 +1,339 @@
+use std::{
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::core::{
+    app::Params,
+    distributed::{CameraState, Worker, save_render},
+    engine::GraphicsResources,
+};
+use crate::scene::{
+    camera::Camera,
+    scene::{Scene, SceneName},
+};
+
+/// Where the queue window saves and loads jobs, also accepted by `--queue`
+pub const QUEUE_FILE: &str = "render_queue.txt";
+
+/// One offline render, saved to `renders/<name>.png` and `.exr`.
+#[derive(Debug, Clone)]
+pub struct RenderJob {
+    pub name: String,
+    pub scene: SceneName,
+    /// `None` renders from the scene's own camera
+    pub camera: Option<CameraState>,
+    /// Named view of the scene to render from when `camera` isn't given
+    pub view: Option<String>,
+    /// Resolution, bounces, integrator and the rest of the render settings
+    pub params: Params,
+    /// Samples per pixel, rounded up to whole frames of `params.rays_per_pixel`
+    pub samples: u32,
+    /// Linear scale applied to the saved PNG
+    pub exposure: f32,
+}
+
+impl RenderJob {
+    /// Parses `name scene WIDTHxHEIGHT samples [bounces=N] [exposure=STOPS] [camera=14 floats]
+    /// [view=NAME]`.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut tokens = line.split_whitespace();
+        let mut next = |what: &str| tokens.next().ok_or(format!("missing {}", what));
+        let name = next("name")?.to_owned();
+        let scene_token = next("scene")?;
+        let scene = *SceneName::ALL
+            .iter()
+            .find(|s| format!("{:?}", s).eq_ignore_ascii_case(scene_token))
+            .ok_or(format!("unknown scene {}", scene_token))?;
+        let (width, height) = next("resolution")?
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+            .filter(|(w, h)| *w > 0 && *h > 0)
+            .ok_or("resolution must look like 1920x1080")?;
+        let samples = next("samples")?
+            .parse::<u32>()
+            .map_err(|e| format!("samples: {}", e))?;
+        let mut job = Self {
+            name,
+            scene,
+            camera: None,
+            view: None,
+            params: Params {
+                width,
+                height,
+                skybox: 1,
+                ..Default::default()
+            },
+            samples,
+            exposure: 1.0,
+        };
+        for option in tokens {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or(format!("expected key=value, found {}", option))?;
+            match key {
+                "bounces" => {
+                    job.params.number_of_bounces =
+                        value.parse().map_err(|e| format!("bounces: {}", e))?
+                }
+                "exposure" => {
+                    job.exposure = value
+                        .parse::<f32>()
+                        .map_err(|e| format!("exposure: {}", e))?
+                        .exp2()
+                }
+                "camera" => {
+                    let mut floats: Vec<f32> = value
+                        .split(',')
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .map_err(|e| format!("camera: {}", e))?;
+                    // Queues saved before clipping leave it off
+                    if floats.len() == CameraState::FLOATS - 2 {
+                        floats.extend([0.0, f32::MAX]);
+                    }
+                    let floats: [f32; CameraState::FLOATS] = floats
+                        .try_into()
+                        .map_err(|_| format!("camera needs {} values", CameraState::FLOATS))?;
+                    job.camera = Some(CameraState::from_floats(floats));
+                }
+                "view" => {
+                    if Scene::from_name(scene).view(value).is_none() {
+                        return Err(format!("{:?} has no view {}", scene, value));
+                    }
+                    job.view = Some(value.to_owned());
+                }
+                _ => return Err(format!("unknown option {}", key)),
+            }
+        }
+        Ok(job)
+    }
+    /// Inverse of `parse`, render settings other than the bounces are not kept.
+    pub fn to_line(&self) -> String {
+        let mut line = format!(
+            "{} {:?} {}x{} {} bounces={} exposure={}",
+            self.name,
+            self.scene,
+            self.params.width,
+            self.params.height,
+            self.samples,
+            self.params.number_of_bounces,
+            self.exposure.log2()
+        );
+        if let Some(camera) = self.camera {
+            let floats: Vec<String> = camera.to_floats().iter().map(f32::to_string).collect();
+            line += &format!(" camera={}", floats.join(","));
+        }
+        if let Some(view) = &self.view {
+            line += &format!(" view={}", view.replace(' ', "_"));
+        }
+        line
+    }
+}
+
+/// Reads jobs one per line, blank lines and lines starting with `#` are skipped.
+pub fn load_jobs(path: &Path) -> Result<Vec<RenderJob>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| RenderJob::parse(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+pub fn save_jobs(path: &Path, jobs: &[RenderJob]) -> Result<(), String> {
+    let text: String = jobs.iter().map(|job| job.to_line() + "\n").collect();
+    std::fs::write(path, text).map_err(|e| e.to_string())
+}
+
+/// Renders every job in order on `worker`, a failed job is logged and skipped.
+fn run_jobs(worker: &mut Worker, jobs: &[RenderJob], done: &AtomicUsize, current: &Mutex<String>) {
+    for job in jobs {
+        *current.lock().unwrap() = job.name.clone();
+        log::info!("Rendering job {} ({:?})", job.name, job.scene);
+        let mut camera = job.camera.unwrap_or_else(|| {
+            let definition = Scene::from_name(job.scene);
+            let view = job.view.as_deref().and_then(|name| definition.view(name));
+            CameraState::from_camera(view.unwrap_or(definition.camera()))
+        });
+        camera.aspect = job.params.width as f32 / job.params.height as f32;
+        let frames = job
+            .samples
+            .div_ceil(job.params.rays_per_pixel.max(1) as u32)
+            .max(1);
+        let result = worker
+            .render_frame(job.scene, camera, job.params, frames)
+            .map_err(|e| e.to_string())
+            .and_then(|pixels| {
+                save_render(
+                    &job.name,
+                    job.params.width,
+                    job.params.height,
+                    pixels,
+                    job.exposure,
+                )
+            });
+        match result {
+            Ok(path) => log::info!("Saved job {} to {}", job.name, path.display()),
+            Err(e) => log::error!("Job {} failed: {}", job.name, e),
+        }
+        done.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Renders a queue file without opening a window, for `--queue <file>`.
+pub async fn run_queue_file(path: &str, cpu: bool) {
+    let jobs = match load_jobs(Path::new(path)) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            log::error!("Failed to read queue {}: {}", path, e);
+            return;
+        }
+    };
+    let mut worker = Worker::headless(cpu).await;
+    run_jobs(
+        &mut worker,
+        &jobs,
+        &AtomicUsize::new(0),
+        &Mutex::new(String::new()),
+    );
+    log::info!("Finished {} jobs", jobs.len());
+}
+
+struct QueueRun {
+    done: Arc<AtomicUsize>,
+    total: usize,
+    current: Arc<Mutex<String>>,
+    handle: JoinHandle<()>,
+}
+
+/// Jobs queued from the UI, rendered one after another on a separate headless device so the
+/// interactive view stays usable. Scenes are rebuilt from their names, like distributed renders.
+pub struct RenderQueue {
+    pub jobs: Vec<RenderJob>,
+    /// Name given to the next job added from the current view
+    pub job_name: String,
+    pub samples: u32,
+    pub add_requested: bool,
+    pub start_requested: bool,
+    run: Option<QueueRun>,
+}
+
+impl Default for RenderQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        Self {
+            jobs: vec![],
+            job_name: "job_0".to_owned(),
+            samples: 1024,
+            add_requested: false,
+            start_requested: false,
+            run: None,
+        }
+    }
+    /// Queues the current view, bumping a trailing number in the job name for the next one.
+    pub fn add(&mut self, scene: SceneName, camera: &Camera, params: &Params, exposure: f32) {
+        if !SceneName::ALL.contains(&scene) {
+            log::error!("{:?} can't be queued", scene);
+            return;
+        }
+        let name: String = self
+            .job_name
+            .chars()
+            .map(|c| if c.is_whitespace() { '_' } else { c })
+            .collect();
+        self.jobs.push(RenderJob {
+            name: name.clone(),
+            scene,
+            camera: Some(CameraState::from_camera(camera)),
+            view: None,
+            params: Params {
+                frames: 0,
+                tile_x: 0,
+                tile_y: 0,
+                processed: 0,
+                debug_flag: 0,
+                interleave: 1,
+                ..*params
+            },
+            samples: self.samples.max(1),
+            exposure,
+        });
+        let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        let (stem, number) = name.split_at(name.len() - digits);
+        self.job_name = match number.parse::<u64>() {
+            Ok(n) => format!("{}{}", stem, n + 1),
+            Err(_) => format!("{}_1", name),
+        };
+    }
+    pub fn is_running(&self) -> bool {
+        self.run.is_some()
+    }
+    /// Jobs finished, jobs in the run and the name of the job being rendered.
+    pub fn progress(&self) -> Option<(usize, usize, String)> {
+        self.run.as_ref().map(|run| {
+            (
+                run.done.load(Ordering::Relaxed),
+                run.total,
+                run.current.lock().unwrap().clone(),
+            )
+        })
+    }
+    /// Hands every queued job to a background thread.
+    pub fn start(&mut self) {
+        if self.is_running() || self.jobs.is_empty() {
+            return;
+        }
+        let jobs = std::mem::take(&mut self.jobs);
+        let done = Arc::new(AtomicUsize::new(0));
+        let current = Arc::new(Mutex::new(String::new()));
+        let (thread_done, thread_current) = (done.clone(), current.clone());
+        let total = jobs.len();
+        let handle = thread::spawn(move || {
+            let (device, queue) =
+                match pollster::block_on(GraphicsResources::create_headless_device()) {
+                    Ok(device) => device,
+                    Err(e) => {
+                        log::error!("Render queue could not get a device: {}", e);
+                        return;
+                    }
+                };
+            let mut worker = Worker::new(device, queue);
+            run_jobs(&mut worker, &jobs, &thread_done, &thread_current);
+        });
+        self.run = Some(QueueRun {
+            done,
+            total,
+            current,
+            handle,
+        });
+    }
+    /// Reports once the queue thread finishes.
+    pub fn poll(&mut self) {
+        if !self
+            .run
+            .as_ref()
+            .is_some_and(|run| run.handle.is_finished())
+        {
+            return;
+        }
+        let run = self.run.take().unwrap();
+        match run.handle.join() {
+            Ok(()) => log::info!(
+                "Render queue finished {} of {} jobs",
+                run.done.load(Ordering::Relaxed),
+                run.total
+            ),
+            Err(_) => log::error!("Render queue thread panicked"),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::path::Path;
+
+/// Where interface settings are kept between runs, one `key=value` per line
+pub const SETTINGS_FILE: &str = "settings.txt";
+
+/// Body text size egui starts with, other text styles scale along with it
+const DEFAULT_FONT_SIZE: f32 = 12.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 2] = [Theme::Dark, Theme::Light];
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+        }
+    }
+}
+
+/// Where an editor panel is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dock {
+    Left,
+    Right,
+    Bottom,
+    /// A window over the render
+    Floating,
+    Hidden,
+}
+
+impl Dock {
+    pub const ALL: [Dock; 5] = [
+        Dock::Left,
+        Dock::Right,
+        Dock::Bottom,
+        Dock::Floating,
+        Dock::Hidden,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Dock::Left => "Left",
+            Dock::Right => "Right",
+            Dock::Bottom => "Bottom",
+            Dock::Floating => "Floating",
+            Dock::Hidden => "Hidden",
+        }
+    }
+    /// Width of a panel first docked here, or its height at the bottom.
+    pub fn default_size(self) -> f32 {
+        match self {
+            Dock::Bottom => 220.0,
+            _ => 280.0,
+        }
+    }
+}
+
+/// Editor panels that can be docked, in the order they claim space from the edges of the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Panel {
+    Inspector,
+    Debug,
+    /// Frame times, jobs and GPU details
+    Profiler,
+    EntityList,
+    /// Ready made materials to give the selection
+    MaterialLibrary,
+    Timeline,
+}
+
+impl Panel {
+    pub const ALL: [Panel; 6] = [
+        Panel::Inspector,
+        Panel::Debug,
+        Panel::Profiler,
+        Panel::EntityList,
+        Panel::MaterialLibrary,
+        Panel::Timeline,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Panel::Inspector => "Inspector",
+            Panel::Debug => "Debug",
+            Panel::Profiler => "Profiler",
+            Panel::EntityList => "Entity List",
+            Panel::MaterialLibrary => "Material Library",
+            Panel::Timeline => "Timeline",
+        }
+    }
+    fn key(self) -> &'static str {
+        match self {
+            Panel::Inspector => "panel_inspector",
+            Panel::Debug => "panel_debug",
+            Panel::Profiler => "panel_profiler",
+            Panel::EntityList => "panel_entity_list",
+            Panel::MaterialLibrary => "panel_material_library",
+            Panel::Timeline => "panel_timeline",
+        }
+    }
+    fn default_dock(self) -> Dock {
+        match self {
+            Panel::Inspector | Panel::MaterialLibrary => Dock::Right,
+            Panel::Debug | Panel::Profiler | Panel::EntityList => Dock::Left,
+            Panel::Timeline => Dock::Bottom,
+        }
+    }
+}
+
+/// Where a panel is docked and how wide it is, or how tall at the bottom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PanelLayout {
+    pub dock: Dock,
+    pub size: f32,
+}
+
+impl PanelLayout {
+    pub fn docked(dock: Dock) -> Self {
+        Self {
+            dock,
+            size: dock.default_size(),
+        }
+    }
+}
+
+/// Interface scale, theme, text size and panel layout, applied on top of the window's own scale
+/// factor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UiSettings {
+    /// Multiplies the window's scale factor, 0.75 to 2
+    pub scale: f32,
+    pub theme: Theme,
+    /// Body text size in points
+    pub font_size: f32,
+    /// Indexed by `Panel`
+    pub panels: [PanelLayout; Panel::ALL.len()],
+    /// Set by the UI once an edit is finished, applies and saves the settings
+    pub apply_requested: bool,
+}
+
+impl Default for UiSettings {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            theme: Theme::Dark,
+            font_size: DEFAULT_FONT_SIZE,
+            panels: Panel::ALL.map(|panel| PanelLayout::docked(panel.default_dock())),
+            apply_requested: false,
+        }
+    }
+}
+
+impl UiSettings {
+    pub const SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.75..=2.0;
+    pub const FONT_SIZE_RANGE: std::ops::RangeInclusive<f32> = 8.0..=24.0;
+    pub const PANEL_SIZE_RANGE: std::ops::RangeInclusive<f32> = 120.0..=1600.0;
+
+    /// Reads `path`, keeping the default for anything missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        let mut settings = Self::default();
+        let Ok(text) = std::fs::read_to_string(path) else {
+            return settings;
+        };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "scale" => {
+                    if let Ok(scale) = value.parse::<f32>() {
+                        settings.scale =
+                            scale.clamp(*Self::SCALE_RANGE.start(), *Self::SCALE_RANGE.end());
+                    }
+                }
+                "theme" => {
+                    if let Some(theme) = Theme::ALL
+                        .into_iter()
+                        .find(|theme| theme.name().eq_ignore_ascii_case(value))
+                    {
+                        settings.theme = theme;
+                    }
+                }
+                "font_size" => {
+                    if let Ok(size) = value.parse::<f32>() {
+                        settings.font_size = size
+                            .clamp(*Self::FONT_SIZE_RANGE.start(), *Self::FONT_SIZE_RANGE.end());
+                    }
+                }
+                key => match Panel::ALL.into_iter().find(|panel| panel.key() == key) {
+                    Some(panel) => {
+                        if let Some(layout) = Self::parse_panel(value) {
+                            settings.panels[panel as usize] = layout;
+                        }
+                    }
+                    None => log::warn!("Unknown setting {} in {}", key, path.display()),
+                },
+            }
+        }
+        settings
+    }
+    /// The dock then the size, `right 280`. The size is optional.
+    fn parse_panel(value: &str) -> Option<PanelLayout> {
+        let mut parts = value.split_whitespace();
+        let dock = parts.next()?;
+        let dock = Dock::ALL
+            .into_iter()
+            .find(|d| d.name().eq_ignore_ascii_case(dock))?;
+        let mut layout = PanelLayout::docked(dock);
+        if let Some(size) = parts.next().and_then(|size| size.parse::<f32>().ok()) {
+            layout.size = size.clamp(
+                *Self::PANEL_SIZE_RANGE.start(),
+                *Self::PANEL_SIZE_RANGE.end(),
+            );
+        }
+        Some(layout)
+    }
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut text = format!(
+            "scale={}\ntheme={}\nfont_size={}\n",
+            self.scale,
+            self.theme.name(),
+            self.font_size
+        );
+        for panel in Panel::ALL {
+            let layout = self.panels[panel as usize];
+            text += &format!(
+                "{}={} {}\n",
+                panel.key(),
+                layout.dock.name().to_lowercase(),
+                layout.size.round()
+            );
+        }
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+    /// Sets the theme and text sizes of `context`. The scale is applied through
+    /// `GraphicsResources::scale_factor` as it also sizes the screen descriptor.
+    pub fn apply(&self, context: &egui::Context) {
+        context.set_theme(match self.theme {
+            Theme::Dark => egui::ThemePreference::Dark,
+            Theme::Light => egui::ThemePreference::Light,
+        });
+        let ratio = self.font_size / DEFAULT_FONT_SIZE;
+        let defaults = egui::Style::default().text_styles;
+        context.all_styles_mut(|style| {
+            for (text_style, font) in style.text_styles.iter_mut() {
+                if let Some(default) = defaults.get(text_style) {
+                    font.size = default.size * ratio;
+                }
+            }
+        });
+    }
+}
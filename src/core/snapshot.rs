@@ -0,0 +1,241 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
+
+use crate::core::app::Params;
+use crate::rendering::{
+    ray_tracer::Integrator,
+    readback::{encode_rgba8, srgb_to_linear},
+};
+use crate::scene::{camera::Camera, scene::SceneName};
+
+/// File format a snapshot is exported to, the PNG at the exposure it was taken with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnapshotFormat {
+    Png,
+    Exr,
+}
+
+/// A render kept for the rest of the session along with what it was rendered with, so lighting
+/// iterations can be compared and exported later without rendering them again.
+pub struct Snapshot {
+    /// Unique for the session, keys the gallery's thumbnails
+    pub id: usize,
+    pub name: String,
+    pub scene: SceneName,
+    pub params: Params,
+    pub camera: Camera,
+    /// Linear display exposure the render was seen at, applied when exporting to PNG
+    pub exposure: f32,
+    /// How long the render had been accumulating
+    pub render_time: Duration,
+    /// Linear radiance, top row first
+    pub image: Rgba32FImage,
+    /// File a reference image was loaded from, nothing is known about how those were rendered
+    pub reference: Option<PathBuf>,
+}
+
+impl Snapshot {
+    /// The image as it was displayed.
+    pub fn ldr(&self) -> RgbaImage {
+        encode_rgba8(&self.image, self.exposure)
+    }
+    pub fn samples(&self) -> i32 {
+        self.params.samples()
+    }
+    /// Labelled settings shown under the snapshot, lined up between two snapshots to compare them.
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        let resolution = format!("{}x{}", self.image.width(), self.image.height());
+        if let Some(path) = &self.reference {
+            let file = path.file_name().unwrap_or_default().to_string_lossy();
+            return self
+                .rendered_settings()
+                .into_iter()
+                .map(|(label, _)| match label {
+                    "Scene" => (label, file.to_string()),
+                    "Resolution" => (label, resolution.clone()),
+                    _ => (label, "-".to_owned()),
+                })
+                .collect();
+        }
+        self.rendered_settings()
+    }
+    fn rendered_settings(&self) -> Vec<(&'static str, String)> {
+        let params = &self.params;
+        let position = self.camera.transform.pos;
+        let integrator = Integrator::ALL
+            .into_iter()
+            .find(|i| *i as u32 == params.integrator)
+            .map_or("Unknown", |i| i.name());
+        vec![
+            ("Scene", format!("{:?}", self.scene)),
+            ("Resolution", format!("{}x{}", params.width, params.height)),
+            ("Samples", self.samples().to_string()),
+            (
+                "Render Time",
+                format!("{:.1}s", self.render_time.as_secs_f32()),
+            ),
+            ("Integrator", integrator.to_owned()),
+            ("Bounces", params.number_of_bounces.to_string()),
+            ("Exposure", format!("{:.2}x", self.exposure)),
+            ("Skybox", (params.skybox != 0).to_string()),
+            (
+                "Camera",
+                format!("{:.2}, {:.2}, {:.2}", position.x, position.y, position.z),
+            ),
+            ("FOV", format!("{:.1}", self.camera.fov)),
+            ("Focus Distance", format!("{:.2}", self.camera.focus_dist)),
+            ("Defocus", format!("{:.2}", self.camera.defocus_strength)),
+        ]
+    }
+    /// Writes the snapshot to `renders/`, returning where it went.
+    pub fn export(&self, format: SnapshotFormat) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let dir = Path::new("renders");
+        std::fs::create_dir_all(dir)?;
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = match format {
+            SnapshotFormat::Png => dir.join(format!("{}.png", name)),
+            SnapshotFormat::Exr => dir.join(format!("{}.exr", name)),
+        };
+        match format {
+            SnapshotFormat::Png => self.ldr().save(&path)?,
+            SnapshotFormat::Exr => self.image.save(&path)?,
+        }
+        log::info!("Saved snapshot to {}", path.display());
+        Ok(path)
+    }
+}
+
+/// Session gallery of snapshots, shown in the snapshots window. Nothing is written to disk unless
+/// a snapshot is exported.
+pub struct Snapshots {
+    pub snapshots: Vec<Snapshot>,
+    pub open: bool,
+    /// Set by the UI, the app reads the render back and adds it at the start of the next update
+    pub take_requested: bool,
+    /// Ids of the snapshots shown side by side
+    pub compare: [Option<usize>; 2],
+    /// Image file typed in to load as a reference
+    pub reference_path: String,
+    /// Show the difference heatmap of the compared pair
+    pub show_difference: bool,
+    /// Scale of the differences coloured in the heatmap, so small ones can be seen
+    pub heatmap_gain: f32,
+    next_id: usize,
+}
+
+impl Default for Snapshots {
+    fn default() -> Self {
+        Self {
+            snapshots: Vec::new(),
+            open: false,
+            take_requested: false,
+            compare: [None; 2],
+            reference_path: String::new(),
+            show_difference: false,
+            heatmap_gain: 4.0,
+            next_id: 0,
+        }
+    }
+}
+
+impl Snapshots {
+    pub fn add(
+        &mut self,
+        image: Rgba32FImage,
+        scene: SceneName,
+        params: Params,
+        camera: Camera,
+        exposure: f32,
+        render_time: Duration,
+    ) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.snapshots.push(Snapshot {
+            id,
+            name: format!("snapshot_{}", id),
+            scene,
+            params,
+            camera,
+            exposure,
+            render_time,
+            image,
+            reference: None,
+        });
+        // Fill the comparison as snapshots come in, newest against the one before
+        self.compare = [self.compare[1].or(self.compare[0]), Some(id)];
+        self.open = true;
+    }
+    /// Loads an image rendered elsewhere, or by an earlier version, into the gallery and takes a
+    /// snapshot of the current render to compare it against. Floating point files are taken as
+    /// linear, anything else as gamma encoded the way renders are exported. The scene, settings
+    /// and camera are only placeholders, references can't be applied.
+    pub fn load_reference(
+        &mut self,
+        path: &Path,
+        scene: SceneName,
+        params: Params,
+        camera: Camera,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let loaded = image::open(path)?;
+        let image = match loaded {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => loaded.into_rgba32f(),
+            _ => {
+                let mut image = loaded.into_rgba32f();
+                for p in image.pixels_mut() {
+                    for c in &mut p.0[..3] {
+                        *c = srgb_to_linear(*c);
+                    }
+                }
+                image
+            }
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        self.snapshots.push(Snapshot {
+            id,
+            name: path.file_stem().map_or_else(
+                || format!("reference_{}", id),
+                |s| s.to_string_lossy().into(),
+            ),
+            scene,
+            params,
+            camera,
+            exposure: 1.0,
+            render_time: Duration::ZERO,
+            image,
+            reference: Some(path.to_owned()),
+        });
+        // The snapshot taken next fills B
+        self.compare = [Some(id), None];
+        self.take_requested = true;
+        self.open = true;
+        log::info!("Loaded reference image {}", path.display());
+        Ok(())
+    }
+    pub fn get(&self, id: usize) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|s| s.id == id)
+    }
+    pub fn remove(&mut self, id: usize) {
+        self.snapshots.retain(|s| s.id != id);
+        for slot in self.compare.iter_mut() {
+            if *slot == Some(id) {
+                *slot = None;
+            }
+        }
+    }
+    /// Bytes held by the gallery's images.
+    pub fn memory(&self) -> usize {
+        self.snapshots
+            .iter()
+            .map(|s| s.image.as_raw().len() * size_of::<f32>())
+            .sum()
+    }
+}
@@ -0,0 +1,619 @@
+use std::{
+    error::Error,
+    fs::File,
+    path::Path,
+    sync::{Arc, mpsc::Sender},
+};
+
+use glam::Vec3;
+use memmap2::Mmap;
+
+use crate::core::{
+    asset::{FILE, asset_path},
+    bvh::{BVH, Node, PackedTriangle, Quality},
+    download,
+};
+use crate::scene::components::{
+    geometry::{
+        mesh::{MeshData, MeshInstance, UvView},
+        vertex::Vertex,
+    },
+    material::MaterialUniform,
+    transform::Transform,
+};
+use crate::scene::entity::EntityLabel;
+
+/// Meshes from files at least this large are streamed in after the rest of the scene appears
+pub const STREAM_THRESHOLD: u64 = 32 << 20;
+/// Triangles parsed before a chunk is handed to the renderer
+const CHUNK_TRIANGLES: usize = 1 << 16;
+
+/// A mesh entity left out of the initial scene so it can be streamed in afterwards.
+pub struct PendingStream {
+    pub path: String,
+    pub transform: Transform,
+    pub material: MaterialUniform,
+    pub label: EntityLabel,
+}
+
+/// Part of a streamed mesh with a quickly built BVH, or the whole mesh once parsing finishes.
+pub struct MeshChunk {
+    /// Matches `Scene::stream_id` of the scene the mesh was loaded for
+    pub stream_id: u64,
+    /// Path of the streamed file, chunks are labelled `<source> [part n]`
+    pub source: String,
+    pub mesh: MeshInstance,
+    pub triangles: Vec<PackedTriangle>,
+    pub nodes: Vec<Node>,
+    pub degenerate_triangles: u32,
+    /// The whole mesh with a high quality BVH, replacing every earlier chunk from `source`. Empty
+    /// if the file failed to parse part way through.
+    pub complete: bool,
+}
+
+/// Downloaded models load whole, their size isn't known without fetching them first.
+pub fn should_stream(path: &str) -> bool {
+    !download::is_url(path)
+        && std::fs::metadata(Path::new(FILE).join("assets").join(path))
+            .is_ok_and(|metadata| metadata.len() >= STREAM_THRESHOLD)
+}
+
+/// Loads a whole OBJ or PLY file with the streaming parser, used for PLY files that tobj can't read.
+pub fn load_mesh(path: &str) -> Result<MeshData, Box<dyn Error>> {
+    parse(path, &mut |_| true)?.ok_or("cancelled".into())
+}
+
+/// Parses `stream`, sending a chunk every `CHUNK_TRIANGLES` triangles and the whole mesh with a
+/// high quality BVH at the end. Stops early when `cancelled` returns true.
+pub fn stream_model(
+    stream: &PendingStream,
+    stream_id: u64,
+    tab_id: usize,
+    tx: &Sender<(usize, MeshChunk)>,
+    cancelled: &mut dyn FnMut() -> bool,
+) {
+    log::info!("Streaming {}", stream.path);
+    let mut part = 0;
+    let mut send = |vertices: Vec<Vertex>, quality: Quality, complete: bool| {
+        let indices: Vec<u32> = (0..vertices.len() as u32).collect();
+        let data = Arc::new(MeshData {
+            vertices: Arc::new(vertices),
+            indices: Arc::new(indices),
+        });
+        let label = if complete {
+            stream.path.clone()
+        } else {
+            part += 1;
+            format!("{} [part {}]", stream.path, part)
+        };
+        let mesh = MeshInstance {
+            label: Some(label),
+            data,
+            transform: stream.transform,
+            material: stream.material,
+            uv_view: UvView::Off,
+            source: Some(stream.path.clone()),
+            rect_light: None,
+            crease_angle: None,
+            user_label: stream.label.clone(),
+        };
+        let (triangles, nodes, degenerate_triangles) = if mesh.data.vertices.is_empty() {
+            (vec![], vec![], 0)
+        } else if complete {
+            BVH::build_cached(&mesh, quality)
+        } else {
+            BVH::build(&mesh.data.vertices, &mesh.data.indices, quality)
+        };
+        let chunk = MeshChunk {
+            stream_id,
+            source: stream.path.clone(),
+            mesh,
+            triangles,
+            nodes,
+            degenerate_triangles,
+            complete,
+        };
+        tx.send((tab_id, chunk)).is_ok()
+    };
+    let result = parse(&stream.path, &mut |vertices| {
+        !cancelled() && send(vertices.to_vec(), Quality::Low, false)
+    });
+    match result {
+        Ok(Some(data)) => {
+            let vertices = Arc::unwrap_or_clone(data.vertices);
+            log::info!(
+                "Streamed {} ({} triangles), building final BVH",
+                stream.path,
+                vertices.len() / 3
+            );
+            send(vertices, Quality::High, true);
+        }
+        Ok(None) => log::info!("Stopped streaming {}", stream.path),
+        Err(e) => {
+            log::error!("Failed to stream {}: {}", stream.path, e);
+            // An empty final chunk still clears the parts already shown
+            send(vec![], Quality::High, true);
+        }
+    }
+}
+
+/// Memory maps the file and parses it by extension. `on_chunk` sees the vertices of each batch of
+/// triangles and returns false to stop, which makes this return `None`.
+fn parse(
+    path: &str,
+    on_chunk: &mut dyn FnMut(&[Vertex]) -> bool,
+) -> Result<Option<MeshData>, Box<dyn Error>> {
+    let file = File::open(asset_path(path)?)?;
+    // SAFETY: assets aren't expected to change while loading, a truncated file only yields
+    // garbage geometry or a parse error
+    let bytes = unsafe { Mmap::map(&file)? };
+    let mut sink = TriangleSink::new(on_chunk);
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "obj" => parse_obj(&bytes, &mut sink)?,
+        "ply" => parse_ply(&bytes, &mut sink)?,
+        _ => return Err(format!("can't stream .{} files", extension).into()),
+    }
+    Ok(sink.finish())
+}
+
+#[derive(Clone, Copy)]
+struct Corner {
+    /// Index into the file's positions, for smoothing normals
+    position: usize,
+    pos: Vec3,
+    normal: Option<Vec3>,
+    uv: [f32; 2],
+    /// sRGB, white when the file has no vertex colours
+    color: Vec3,
+}
+
+/// Collects parsed triangles as unindexed vertices, like `AssetManager::load_model`, handing each
+/// full chunk to `on_chunk`.
+struct TriangleSink<'a> {
+    vertices: Vec<Vertex>,
+    chunk_start: usize,
+    /// Area weighted normals summed per position
+    position_normals: Vec<Vec3>,
+    /// Vertices whose file had no normal, with their position index
+    smooth: Vec<(usize, usize)>,
+    on_chunk: &'a mut dyn FnMut(&[Vertex]) -> bool,
+    cancelled: bool,
+}
+
+impl<'a> TriangleSink<'a> {
+    fn new(on_chunk: &'a mut dyn FnMut(&[Vertex]) -> bool) -> Self {
+        Self {
+            vertices: vec![],
+            chunk_start: 0,
+            position_normals: vec![],
+            smooth: vec![],
+            on_chunk,
+            cancelled: false,
+        }
+    }
+    /// Adds a polygon as a triangle fan, returns false once parsing should stop.
+    fn polygon(&mut self, corners: &[Corner]) -> bool {
+        for i in 1..corners.len().saturating_sub(1) {
+            self.triangle([corners[0], corners[i], corners[i + 1]]);
+        }
+        if self.vertices.len() - self.chunk_start >= CHUNK_TRIANGLES * 3 {
+            self.flush();
+        }
+        !self.cancelled
+    }
+    fn triangle(&mut self, corners: [Corner; 3]) {
+        let [a, b, c] = corners.map(|corner| corner.pos);
+        let face_normal = (b - a).cross(c - a);
+        for corner in corners {
+            if corner.position >= self.position_normals.len() {
+                self.position_normals
+                    .resize(corner.position + 1, Vec3::ZERO);
+            }
+            self.position_normals[corner.position] += face_normal;
+            let normal = corner.normal.unwrap_or_else(|| {
+                // Flat until the whole mesh is known
+                self.smooth.push((self.vertices.len(), corner.position));
+                face_normal.normalize_or_zero()
+            });
+            self.vertices
+                .push(Vertex::with_uv(corner.pos, normal, corner.uv).with_color(corner.color));
+        }
+    }
+    fn flush(&mut self) {
+        if self.chunk_start < self.vertices.len() && !self.cancelled {
+            self.cancelled = !(self.on_chunk)(&self.vertices[self.chunk_start..]);
+            self.chunk_start = self.vertices.len();
+        }
+    }
+    /// The whole mesh with smoothed normals where the file had none, `None` if stopped early.
+    fn finish(mut self) -> Option<MeshData> {
+        self.flush();
+        if self.cancelled {
+            return None;
+        }
+        for &(vertex, position) in &self.smooth {
+            let normal = self.position_normals[position].normalize_or_zero();
+            if normal != Vec3::ZERO {
+                self.vertices[vertex].normal = normal;
+            }
+        }
+        let indices = (0..self.vertices.len() as u32).collect();
+        Some(MeshData {
+            vertices: Arc::new(self.vertices),
+            indices: Arc::new(indices),
+        })
+    }
+}
+
+fn parse_obj(bytes: &[u8], sink: &mut TriangleSink) -> Result<(), Box<dyn Error>> {
+    let mut positions: Vec<Vec3> = vec![];
+    // Only filled once a position has a colour, `v x y z r g b` being a common extension
+    let mut colors: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<[f32; 2]> = vec![];
+    let mut corners: Vec<Corner> = vec![];
+    for (i, line) in bytes.split(|b| *b == b'\n').enumerate() {
+        let line = String::from_utf8_lossy(line);
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next();
+        let mut float = || -> Result<f32, String> {
+            let token = tokens
+                .next()
+                .ok_or(format!("line {}: missing value", i + 1))?;
+            token
+                .parse()
+                .map_err(|e| format!("line {}: {}: {}", i + 1, token, e))
+        };
+        match keyword {
+            Some("v") => {
+                positions.push(Vec3::new(float()?, float()?, float()?));
+                if let (Ok(r), Ok(g), Ok(b)) = (float(), float(), float()) {
+                    colors.resize(positions.len() - 1, Vec3::ONE);
+                    colors.push(Vec3::new(r, g, b));
+                }
+            }
+            Some("vn") => {
+                normals.push(Vec3::new(float()?, float()?, float()?).normalize_or_zero());
+            }
+            Some("vt") => {
+                uvs.push([float()?, float().unwrap_or(0.0)]);
+            }
+            Some("f") => {
+                corners.clear();
+                for token in line.split_whitespace().skip(1) {
+                    let mut parts = token.split('/');
+                    let position = obj_index(parts.next(), positions.len())
+                        .map_err(|e| format!("line {}: {}", i + 1, e))?
+                        .ok_or(format!("line {}: face without a position", i + 1))?;
+                    let uv = obj_index(parts.next(), uvs.len())
+                        .map_err(|e| format!("line {}: {}", i + 1, e))?
+                        .map_or([0.0, 0.0], |t| uvs[t]);
+                    let normal = obj_index(parts.next(), normals.len())
+                        .map_err(|e| format!("line {}: {}", i + 1, e))?
+                        .map(|n| normals[n]);
+                    corners.push(Corner {
+                        position,
+                        pos: positions[position],
+                        normal,
+                        uv,
+                        color: colors.get(position).copied().unwrap_or(Vec3::ONE),
+                    });
+                }
+                if !sink.polygon(&corners) {
+                    return Ok(());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Resolves a one based or negative relative OBJ index, `None` when the slot is empty.
+fn obj_index(token: Option<&str>, count: usize) -> Result<Option<usize>, String> {
+    let Some(token) = token.filter(|t| !t.is_empty()) else {
+        return Ok(None);
+    };
+    let index: i64 = token.parse().map_err(|e| format!("{}: {}", token, e))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!("index {} out of range", index));
+    }
+    Ok(Some(resolved as usize))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => PlyType::I8,
+            "uchar" | "uint8" => PlyType::U8,
+            "short" | "int16" => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32" => PlyType::I32,
+            "uint" | "uint32" => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return Err(format!("unknown property type {}", name)),
+        })
+    }
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    kind: PlyType,
+    /// Type of the length prefix for list properties
+    list: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+/// Reads values of any PLY encoding from just after the header.
+struct PlyReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    format: PlyFormat,
+}
+
+impl PlyReader<'_> {
+    fn read(&mut self, kind: PlyType) -> Result<f64, String> {
+        if self.format == PlyFormat::Ascii {
+            let rest = &self.bytes[self.offset..];
+            let start = rest
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .ok_or("unexpected end of file")?;
+            let length = rest[start..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(rest.len() - start);
+            self.offset += start + length;
+            let token = String::from_utf8_lossy(&rest[start..start + length]);
+            return token.parse().map_err(|e| format!("{}: {}", token, e));
+        }
+        let size = kind.size();
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + size)
+            .ok_or("unexpected end of file")?;
+        self.offset += size;
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.format == PlyFormat::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+        let b = buffer;
+        Ok(match kind {
+            PlyType::I8 => b[0] as i8 as f64,
+            PlyType::U8 => b[0] as f64,
+            PlyType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::F64 => f64::from_le_bytes(b),
+        })
+    }
+    /// Reads one property, calling `value` with each entry of a list or the single scalar.
+    fn property(
+        &mut self,
+        property: &PlyProperty,
+        value: &mut dyn FnMut(f64),
+    ) -> Result<(), String> {
+        let count = match property.list {
+            Some(count_kind) => self.read(count_kind)? as usize,
+            None => 1,
+        };
+        for _ in 0..count {
+            value(self.read(property.kind)?);
+        }
+        Ok(())
+    }
+}
+
+fn parse_ply_header(bytes: &[u8]) -> Result<(PlyFormat, Vec<PlyElement>, usize), String> {
+    let end = bytes
+        .windows(b"end_header".len())
+        .position(|w| w == b"end_header")
+        .ok_or("missing end_header")?;
+    let body = end
+        + b"end_header".len()
+        + bytes[end..]
+            .iter()
+            .skip(b"end_header".len())
+            .position(|b| *b == b'\n')
+            .map_or(0, |p| p + 1);
+    let header = String::from_utf8_lossy(&bytes[..end]);
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("not a PLY file".to_owned());
+    }
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", name, ..] => {
+                format = Some(match *name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(format!("unknown format {}", name)),
+                })
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|e| format!("element {}: {}", name, e))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_kind, kind, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::parse(kind)?,
+                    list: Some(PlyType::parse(count_kind)?),
+                }),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::parse(kind)?,
+                    list: None,
+                }),
+            _ => {}
+        }
+    }
+    Ok((format.ok_or("missing format")?, elements, body))
+}
+
+fn parse_ply(bytes: &[u8], sink: &mut TriangleSink) -> Result<(), Box<dyn Error>> {
+    let (format, elements, body) = parse_ply_header(bytes)?;
+    let mut reader = PlyReader {
+        bytes,
+        offset: body,
+        format,
+    };
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<[f32; 2]> = vec![];
+    let mut colors: Vec<Vec3> = vec![];
+    let mut indices: Vec<usize> = vec![];
+    let mut corners: Vec<Corner> = vec![];
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let has = |names: &[&str]| {
+                    element
+                        .properties
+                        .iter()
+                        .any(|p| names.contains(&p.name.as_str()))
+                };
+                let has_normals = has(&["nx"]);
+                let has_uvs = has(&["u", "s", "texture_u"]);
+                let has_colors = has(&["red", "diffuse_red"]);
+                for _ in 0..element.count {
+                    let (mut pos, mut normal, mut uv) = (Vec3::ZERO, Vec3::ZERO, [0.0f32; 2]);
+                    let mut color = Vec3::ONE;
+                    for property in &element.properties {
+                        // Integer colours span their type's range, floats are already 0 to 1
+                        let color_scale = match property.kind {
+                            PlyType::U8 => 1.0 / 255.0,
+                            PlyType::U16 => 1.0 / 65535.0,
+                            _ => 1.0,
+                        };
+                        reader.property(property, &mut |v| {
+                            let v = v as f32;
+                            match property.name.as_str() {
+                                "x" => pos.x = v,
+                                "y" => pos.y = v,
+                                "z" => pos.z = v,
+                                "nx" => normal.x = v,
+                                "ny" => normal.y = v,
+                                "nz" => normal.z = v,
+                                "u" | "s" | "texture_u" => uv[0] = v,
+                                "v" | "t" | "texture_v" => uv[1] = v,
+                                "red" | "diffuse_red" => color.x = v * color_scale,
+                                "green" | "diffuse_green" => color.y = v * color_scale,
+                                "blue" | "diffuse_blue" => color.z = v * color_scale,
+                                _ => {}
+                            }
+                        })?;
+                    }
+                    positions.push(pos);
+                    if has_normals {
+                        normals.push(normal.normalize_or_zero());
+                    }
+                    if has_uvs {
+                        uvs.push(uv);
+                    }
+                    if has_colors {
+                        colors.push(color);
+                    }
+                }
+            }
+            "face" => {
+                for _ in 0..element.count {
+                    indices.clear();
+                    for property in &element.properties {
+                        let is_indices =
+                            matches!(property.name.as_str(), "vertex_indices" | "vertex_index");
+                        reader.property(property, &mut |v| {
+                            if is_indices {
+                                indices.push(v as usize);
+                            }
+                        })?;
+                    }
+                    corners.clear();
+                    for &index in &indices {
+                        let pos = *positions
+                            .get(index)
+                            .ok_or(format!("vertex index {} out of range", index))?;
+                        corners.push(Corner {
+                            position: index,
+                            pos,
+                            normal: normals.get(index).copied(),
+                            uv: uvs.get(index).copied().unwrap_or([0.0, 0.0]),
+                            color: colors.get(index).copied().unwrap_or(Vec3::ONE),
+                        });
+                    }
+                    if !sink.polygon(&corners) {
+                        return Ok(());
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        reader.property(property, &mut |_| {})?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use egui_wgpu::wgpu;
+
+use crate::core::{app::Params, engine::RenderTarget};
+use crate::rendering::ray_tracer::TextureSlots;
+use crate::scene::scene::{Scene, SceneName};
+
+/// State of a tab that is not currently being rendered.
+/// The active tab's state lives directly in the engine and is swapped in and out when switching.
+pub struct ParkedTab {
+    pub scene: Scene,
+    pub selected_scene: SceneName,
+    pub prev_scene: SceneName,
+    pub selected_entity: i32,
+    pub params: Params,
+    pub target: RenderTarget,
+    /// `None` when the tab's scene finished loading while parked and its textures still need uploading.
+    pub textures_bind_group: Option<wgpu::BindGroup>,
+    pub texture_slots: TextureSlots,
+    pub display_bind_group: wgpu::BindGroup,
+}
+
+pub struct SceneTab {
+    pub id: usize,
+    pub title: SceneName,
+    pub parked: Option<ParkedTab>,
+}
+
+pub struct TabManager {
+    pub tabs: Vec<SceneTab>,
+    /// Index of the tab currently held by the engine
+    pub active: usize,
+    /// Index of the tab selected in the UI, switched to on the next update
+    pub selected: usize,
+    /// Set by the UI to request a new tab
+    pub open_requested: bool,
+    /// Set by the UI to request closing the tab at this index
+    pub close_requested: Option<usize>,
+    next_id: usize,
+}
+
+impl TabManager {
+    pub fn new(title: SceneName) -> Self {
+        Self {
+            tabs: vec![SceneTab {
+                id: 0,
+                title,
+                parked: None,
+            }],
+            active: 0,
+            selected: 0,
+            open_requested: false,
+            close_requested: None,
+            next_id: 1,
+        }
+    }
+    pub fn next_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+    pub fn find(&mut self, id: usize) -> Option<&mut SceneTab> {
+        self.tabs.iter_mut().find(|t| t.id == id)
+    }
+}
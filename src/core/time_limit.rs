@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+/// Accumulates for a fixed wall-clock time then stops tracing, for overnight renders where the
+/// samples each scene needs are hard to guess. Anything that restarts accumulation restarts the
+/// clock too, so moving the camera after the limit renders again.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeLimit {
+    pub enabled: bool,
+    pub minutes: f32,
+    /// Save the render once the time is up
+    pub save: bool,
+    /// Close the app once the time is up, after saving
+    pub exit: bool,
+    /// Time ran out on the current accumulation, no more frames are traced
+    pub stopped: bool,
+    /// Set when the time ran out with `exit` on, the app closes at the end of the frame
+    pub exit_requested: bool,
+}
+
+impl Default for TimeLimit {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            minutes: 30.0,
+            save: true,
+            exit: false,
+            stopped: false,
+            exit_requested: false,
+        }
+    }
+}
+
+impl TimeLimit {
+    /// Renders for `minutes` then saves and closes, for `--render-for`.
+    pub fn unattended(minutes: f32) -> Self {
+        Self {
+            enabled: true,
+            minutes,
+            save: true,
+            exit: true,
+            ..Default::default()
+        }
+    }
+    pub fn budget(&self) -> Duration {
+        Duration::from_secs_f32(self.minutes.max(0.0) * 60.0)
+    }
+    /// Time left of an accumulation that started `elapsed` ago.
+    pub fn remaining(&self, elapsed: Duration) -> Duration {
+        self.budget().saturating_sub(elapsed)
+    }
+    /// Stops once an accumulation has been running `elapsed`, returning true only on the frame
+    /// the time runs out.
+    pub fn update(&mut self, elapsed: Duration) -> bool {
+        let stopped = self.enabled && elapsed >= self.budget();
+        let expired = stopped && !self.stopped;
+        self.stopped = stopped;
+        if expired && self.exit {
+            self.exit_requested = true;
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(exit: bool) -> TimeLimit {
+        TimeLimit {
+            enabled: true,
+            minutes: 1.0,
+            exit,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn expires_exactly_once() {
+        let mut limit = limit(false);
+        assert!(!limit.update(Duration::from_secs(59)));
+        assert!(!limit.stopped);
+        assert!(limit.update(Duration::from_secs(60)));
+        assert!(limit.stopped);
+        assert!(!limit.update(Duration::from_secs(61)));
+        assert!(!limit.update(Duration::from_secs(3600)));
+        assert!(limit.stopped);
+    }
+
+    #[test]
+    fn restarts_when_accumulation_does() {
+        let mut limit = limit(false);
+        assert!(limit.update(Duration::from_secs(90)));
+        assert!(!limit.update(Duration::ZERO));
+        assert!(!limit.stopped);
+        assert_eq!(
+            limit.remaining(Duration::from_secs(15)),
+            Duration::from_secs(45)
+        );
+        assert!(limit.update(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn only_requests_exit_when_asked_to() {
+        let mut stay = limit(false);
+        stay.update(Duration::from_secs(60));
+        assert!(!stay.exit_requested);
+        let mut leave = limit(true);
+        leave.update(Duration::from_secs(30));
+        assert!(!leave.exit_requested);
+        leave.update(Duration::from_secs(60));
+        assert!(leave.exit_requested);
+        let mut disabled = TimeLimit::default();
+        assert!(!disabled.update(Duration::from_secs(u32::MAX as u64)));
+        assert!(!disabled.stopped);
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, channel},
+    time::{Duration, Instant},
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::core::{asset::FILE, download};
+
+/// Quiet time after the last change to a file before it is reported, editors often save in
+/// several writes and a half written model would fail to parse.
+const SETTLE: Duration = Duration::from_millis(250);
+
+/// Watches loaded models and textures for changes on disk, for reloading them while they are
+/// edited in another program. Folders are watched rather than files, since many editors save by
+/// replacing the file, which would silently end a watch on the old one.
+pub struct AssetWatcher {
+    pub enabled: bool,
+    watcher: Option<RecommendedWatcher>,
+    rx_changed: Receiver<(PathBuf, Instant)>,
+    folders: HashSet<PathBuf>,
+    /// Asset path of every watched file, by its full path
+    files: HashMap<PathBuf, String>,
+    /// When each changed file last changed
+    pending: HashMap<String, Instant>,
+}
+
+impl AssetWatcher {
+    pub fn new() -> Self {
+        let (tx_changed, rx_changed) = channel();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                let now = Instant::now();
+                for path in event.paths {
+                    let _ = tx_changed.send((path, now));
+                }
+            }
+        });
+        let watcher = match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                log::warn!("Assets won't reload when changed: {}", e);
+                None
+            }
+        };
+        Self {
+            enabled: true,
+            watcher,
+            rx_changed,
+            folders: HashSet::new(),
+            files: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+    /// Starts watching `paths`, relative to the assets folder like the scene definitions.
+    pub fn watch<'a>(&mut self, paths: impl IntoIterator<Item = &'a str>) {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
+        let assets = Path::new(FILE).join("assets");
+        for path in paths {
+            // Downloads are fetched once, there is nothing on disk being edited
+            if download::is_url(path) {
+                continue;
+            }
+            let full = assets.join(path);
+            if self.files.contains_key(&full) {
+                continue;
+            }
+            if let Some(folder) = full.parent()
+                && !self.folders.contains(folder)
+            {
+                match watcher.watch(folder, RecursiveMode::NonRecursive) {
+                    Ok(()) => {
+                        self.folders.insert(folder.to_path_buf());
+                    }
+                    Err(e) => log::warn!("Failed to watch {}: {}", folder.display(), e),
+                }
+            }
+            self.files.insert(full, path.to_owned());
+        }
+    }
+    /// Asset paths of watched files that changed and have since settled, each reported once per
+    /// burst of writes. Changes made while disabled are dropped.
+    pub fn poll(&mut self) -> Vec<String> {
+        while let Ok((path, changed)) = self.rx_changed.try_recv() {
+            if self.enabled
+                && let Some(asset) = self.files.get(&path)
+            {
+                self.pending.insert(asset.clone(), changed);
+            }
+        }
+        let now = Instant::now();
+        let settled: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= SETTLE)
+            .map(|(asset, _)| asset.clone())
+            .collect();
+        for asset in &settled {
+            self.pending.remove(asset);
+        }
+        settled
+    }
+}
+
+impl Default for AssetWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,10 @@
+#[cfg(feature = "bvh")]
+pub mod bvh;
+#[cfg(feature = "app")]
+pub mod core;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "app")]
+pub mod rendering;
+#[cfg(feature = "app")]
+pub mod scene;
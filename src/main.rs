@@ -1,10 +1,6 @@
 use winit::event_loop::{ControlFlow, EventLoop};
 
-use crate::core::app;
-
-mod core;
-mod rendering;
-mod scene;
+use ray_tracer_2::core::{app, crash, distributed, furnace, queue};
 
 fn main() {
     #[cfg(not(target_arch = "wasm32"))]
@@ -14,17 +10,51 @@ fn main() {
 }
 
 async fn run() {
-    env_logger::builder()
-        .filter_module("ray_tracer_2", log::LevelFilter::Info)
-        .filter_module("wgpu_core", log::LevelFilter::Warn)
-        .init();
+    crash::init_logger(
+        env_logger::builder()
+            .filter_module("ray_tracer_2", log::LevelFilter::Info)
+            .filter_module("wgpu_core", log::LevelFilter::Warn),
+    );
+    crash::install_panic_hook();
     log::info!("Starting Ray Tracer");
 
+    // `--worker [address]` renders tiles for a distributed coordinator instead of opening a window
+    let args: Vec<String> = std::env::args().collect();
+    // `--cpu` traces on the CPU, headless renders also fall back to it when there is no GPU
+    let cpu = args.iter().any(|arg| arg == "--cpu");
+    if let Some(i) = args.iter().position(|arg| arg == "--worker") {
+        let address = args
+            .get(i + 1)
+            .cloned()
+            .unwrap_or(format!("0.0.0.0:{}", distributed::DEFAULT_PORT));
+        distributed::run_worker(&address, cpu).await;
+        return;
+    }
+    // `--queue [file]` renders every job in a queue file, then exits
+    if let Some(i) = args.iter().position(|arg| arg == "--queue") {
+        let path = args.get(i + 1).map_or(queue::QUEUE_FILE, String::as_str);
+        queue::run_queue_file(path, cpu).await;
+        return;
+    }
+    // `--furnace` checks the white furnace converges, exiting with an error when it doesn't
+    if args.iter().any(|arg| arg == "--furnace") {
+        let passed = furnace::run_check(cpu).await;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
     let event_loop = EventLoop::new().unwrap();
 
     event_loop.set_control_flow(ControlFlow::Poll);
 
     let mut app = app::App::new();
+    app.start_on_cpu = cpu;
+    // `--render-for <minutes>` accumulates that long, saves the render and exits
+    if let Some(i) = args.iter().position(|arg| arg == "--render-for") {
+        match args.get(i + 1).map(|minutes| minutes.parse::<f32>()) {
+            Some(Ok(minutes)) if minutes > 0.0 => app.render_for = Some(minutes),
+            _ => log::error!("--render-for needs a number of minutes"),
+        }
+    }
 
     event_loop.run_app(&mut app).expect("Failed to run App");
 }
@@ -0,0 +1,352 @@
+//! Python module for building scenes and rendering them headlessly, enabled by the `python`
+//! feature and built with `maturin develop --features python`.
+//!
+//! ```python
+//! import ray_tracer_2 as rt
+//! scene = rt.SceneDefinition()
+//! scene.set_camera((0.0, 1.0, -4.0), (0.0, 0.5, 0.0), fov=40.0)
+//! scene.add_sphere((0.0, 0.5, 0.0), 0.5, rt.Material(color=(0.9, 0.2, 0.2, 1.0)))
+//! image = rt.Renderer().render(scene, 640, 360, samples=256)  # float32, (360, 640, 4)
+//! ```
+
+use std::sync::Mutex;
+
+use glam::{Quat, Vec3};
+use numpy::{PyArray1, PyArray3, PyArrayMethods};
+use pyo3::{exceptions::PyRuntimeError, exceptions::PyValueError, prelude::*};
+
+use crate::core::{app::Params, distributed::Worker, engine::GraphicsResources};
+use crate::scene::{
+    camera::CameraDescriptor,
+    components::{
+        geometry::mesh::MeshDefinition,
+        material::MaterialDefinition,
+        material_rules::{MaterialPreset, MaterialRule},
+        transform::Transform,
+    },
+    scene::{Scene, SceneDefinition, SceneName},
+    sky::Sky,
+};
+
+type Vec3Tuple = (f32, f32, f32);
+type Color = (f32, f32, f32, f32);
+
+/// Surface settings shared by spheres and meshes, mirroring `MaterialDefinition`.
+#[pyclass(name = "Material", from_py_object)]
+#[derive(Clone)]
+struct PyMaterial {
+    #[pyo3(get, set)]
+    color: Color,
+    #[pyo3(get, set)]
+    emission_color: Color,
+    #[pyo3(get, set)]
+    emission_strength: f32,
+    #[pyo3(get, set)]
+    specular_color: Color,
+    #[pyo3(get, set)]
+    specular: f32,
+    #[pyo3(get, set)]
+    smoothness: f32,
+    /// Index of refraction, makes the material glass when set
+    #[pyo3(get, set)]
+    glass: Option<f32>,
+}
+
+#[pymethods]
+impl PyMaterial {
+    #[new]
+    #[pyo3(signature = (
+        color = (0.7, 0.7, 0.7, 1.0),
+        emission_color = (1.0, 1.0, 1.0, 1.0),
+        emission_strength = 0.0,
+        specular_color = (1.0, 1.0, 1.0, 1.0),
+        specular = 0.0,
+        smoothness = 1.0,
+        glass = None,
+    ))]
+    fn new(
+        color: Color,
+        emission_color: Color,
+        emission_strength: f32,
+        specular_color: Color,
+        specular: f32,
+        smoothness: f32,
+        glass: Option<f32>,
+    ) -> Self {
+        Self {
+            color,
+            emission_color,
+            emission_strength,
+            specular_color,
+            specular,
+            smoothness,
+            glass,
+        }
+    }
+}
+
+impl PyMaterial {
+    fn to_definition(&self) -> MaterialDefinition {
+        let array = |(r, g, b, a): Color| [r, g, b, a];
+        let material = MaterialDefinition {
+            color: array(self.color),
+            emission_color: array(self.emission_color),
+            emission_strength: self.emission_strength,
+            specular_color: array(self.specular_color),
+            specular: self.specular,
+            smoothness: self.smoothness,
+            ..Default::default()
+        };
+        match self.glass {
+            Some(ior) => material.glass(ior),
+            None => material,
+        }
+    }
+}
+
+/// Material of an `add_mesh` rule, given in full or as the name of a `MaterialPreset`.
+#[derive(FromPyObject)]
+enum RuleMaterial {
+    Material(PyMaterial),
+    Preset(String),
+}
+
+fn material_or_default(material: Option<&PyMaterial>) -> MaterialDefinition {
+    material.map_or_else(MaterialDefinition::default, PyMaterial::to_definition)
+}
+
+/// Scene under construction, see `SceneDefinition`.
+#[pyclass(name = "SceneDefinition")]
+struct PySceneDefinition {
+    inner: SceneDefinition,
+}
+
+#[pymethods]
+impl PySceneDefinition {
+    #[new]
+    fn new() -> Self {
+        Self {
+            inner: SceneDefinition::default(),
+        }
+    }
+    /// One of the scenes built into the app, named as in `scene_names()`.
+    #[staticmethod]
+    fn builtin(name: &str) -> PyResult<Self> {
+        let scene = parse_scene_name(name)?;
+        Ok(Self {
+            inner: Scene::from_name(scene),
+        })
+    }
+    #[pyo3(signature = (position, target, fov = 45.0, focus_dist = 1.0, defocus_strength = 0.0))]
+    fn set_camera(
+        &mut self,
+        position: Vec3Tuple,
+        target: Vec3Tuple,
+        fov: f32,
+        focus_dist: f32,
+        defocus_strength: f32,
+    ) {
+        self.inner.set_camera(&CameraDescriptor {
+            transform: Transform::cam(Vec3::from(position), Vec3::from(target)),
+            fov,
+            focus_dist,
+            defocus_strength,
+            ..Default::default()
+        });
+    }
+    /// `name` and `notes` label the sphere in the editor's entity list and inspector.
+    #[pyo3(signature = (centre, radius, material = None, name = "", notes = ""))]
+    fn add_sphere(
+        &mut self,
+        centre: Vec3Tuple,
+        radius: f32,
+        material: Option<PyMaterial>,
+        name: &str,
+        notes: &str,
+    ) {
+        self.inner
+            .add_sphere(
+                Vec3::from(centre),
+                radius,
+                material_or_default(material.as_ref()),
+            )
+            .named(name)
+            .notes(notes);
+    }
+    /// Loads an OBJ from the assets folder. `rotation` is in degrees about X, Y then Z, and
+    /// `use_mtl` takes the materials from the file instead of `material`. `rules` is a list of
+    /// `(pattern, material)` pairs overriding both for parts whose group or material name
+    /// matches the glob pattern, such as `("*glass*", rt.Material(glass=1.5))`, where the
+    /// material can also be a preset named in `material_presets()`, as in `("*tyre*", "rubber")`.
+    /// `common_rules` follows them with presets for names like "glass" and "chrome". Normals missing
+    /// from the file are generated with edges sharper than `crease_angle` degrees kept hard.
+    /// `name` and `notes` label the model like `add_sphere`.
+    #[pyo3(signature = (
+        path,
+        position = (0.0, 0.0, 0.0),
+        rotation = (0.0, 0.0, 0.0),
+        scale = (1.0, 1.0, 1.0),
+        material = None,
+        use_mtl = false,
+        rules = None,
+        common_rules = false,
+        crease_angle = None,
+        name = "",
+        notes = "",
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn add_mesh(
+        &mut self,
+        path: String,
+        position: Vec3Tuple,
+        rotation: Vec3Tuple,
+        scale: Vec3Tuple,
+        material: Option<PyMaterial>,
+        use_mtl: bool,
+        rules: Option<Vec<(String, RuleMaterial)>>,
+        common_rules: bool,
+        crease_angle: Option<f32>,
+        name: &str,
+        notes: &str,
+    ) -> PyResult<()> {
+        let mut rules = rules
+            .unwrap_or_default()
+            .iter()
+            .map(|(pattern, material)| match material {
+                RuleMaterial::Material(material) => {
+                    Ok(MaterialRule::new(pattern, material.to_definition()))
+                }
+                RuleMaterial::Preset(name) => MaterialPreset::from_name(name)
+                    .map(|preset| MaterialRule::preset(pattern, preset))
+                    .ok_or_else(|| PyValueError::new_err(format!("unknown preset {}", name))),
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        if common_rules {
+            rules.extend(MaterialRule::common());
+        }
+        let (x, y, z) = rotation;
+        self.inner
+            .add_mesh(
+                Transform {
+                    pos: Vec3::from(position),
+                    rot: Quat::from_euler(
+                        glam::EulerRot::XYZ,
+                        x.to_radians(),
+                        y.to_radians(),
+                        z.to_radians(),
+                    ),
+                    scale: Vec3::from(scale),
+                },
+                MeshDefinition::FromFile {
+                    path,
+                    use_mtl,
+                    rules,
+                    crease_angle,
+                },
+                match use_mtl {
+                    true => MaterialDefinition::texture_from_obj(),
+                    false => material_or_default(material.as_ref()),
+                },
+            )
+            .named(name)
+            .notes(notes);
+        Ok(())
+    }
+    /// Places the sky's sun by local solar time in hours, see `Sky`.
+    #[pyo3(signature = (time_of_day, day_of_year = 172.0, latitude = 51.5))]
+    fn set_sun(&mut self, time_of_day: f32, day_of_year: f32, latitude: f32) {
+        self.inner.set_sky(Sky {
+            enabled: true,
+            time_of_day,
+            day_of_year,
+            latitude,
+            ..Default::default()
+        });
+    }
+}
+
+fn parse_scene_name(name: &str) -> PyResult<SceneName> {
+    SceneName::ALL
+        .iter()
+        .find(|scene| format!("{:?}", scene).eq_ignore_ascii_case(name))
+        .copied()
+        .ok_or_else(|| PyValueError::new_err(format!("unknown scene {}", name)))
+}
+
+/// Headless GPU renderer. Creating one picks an adapter, so keep it around between renders.
+#[pyclass(name = "Renderer")]
+struct PyRenderer {
+    worker: Mutex<Worker>,
+}
+
+#[pymethods]
+impl PyRenderer {
+    #[new]
+    fn new() -> PyResult<Self> {
+        let (device, queue) = pollster::block_on(GraphicsResources::create_headless_device())
+            .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        Ok(Self {
+            worker: Mutex::new(Worker::new(device, queue)),
+        })
+    }
+    /// Renders `scene` from its camera, returning linear float RGBA with the top row first.
+    #[pyo3(signature = (scene, width = 512, height = 512, samples = 64, bounces = 8, skybox = true))]
+    #[allow(clippy::too_many_arguments)]
+    fn render<'py>(
+        &self,
+        py: Python<'py>,
+        scene: &PySceneDefinition,
+        width: u32,
+        height: u32,
+        samples: u32,
+        bounces: i32,
+        skybox: bool,
+    ) -> PyResult<Bound<'py, PyArray3<f32>>> {
+        if width == 0 || height == 0 {
+            return Err(PyValueError::new_err("width and height must be positive"));
+        }
+        let params = Params {
+            width,
+            height,
+            number_of_bounces: bounces,
+            skybox: skybox as i32,
+            ..Default::default()
+        };
+        let frames = samples.div_ceil(params.rays_per_pixel.max(1) as u32).max(1);
+        let pixels = self
+            .worker
+            .lock()
+            .unwrap()
+            .render_definition(&scene.inner, params, frames)
+            .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        // The accumulation texture's rows run bottom first
+        let row = width as usize * 4;
+        let flipped: Vec<f32> = pixels.chunks_exact(row).rev().flatten().copied().collect();
+        PyArray1::from_vec(py, flipped).reshape([height as usize, width as usize, 4])
+    }
+}
+
+/// Names accepted by `SceneDefinition.builtin`.
+#[pyfunction]
+fn scene_names() -> Vec<String> {
+    SceneName::ALL
+        .iter()
+        .map(|scene| format!("{:?}", scene))
+        .collect()
+}
+
+/// Names of the materials a mesh rule can take instead of a `Material`.
+#[pyfunction]
+fn material_presets() -> Vec<&'static str> {
+    MaterialPreset::ALL.map(MaterialPreset::name).to_vec()
+}
+
+#[pymodule]
+fn ray_tracer_2(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyMaterial>()?;
+    module.add_class::<PySceneDefinition>()?;
+    module.add_class::<PyRenderer>()?;
+    module.add_function(wrap_pyfunction!(scene_names, module)?)?;
+    module.add_function(wrap_pyfunction!(material_presets, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,604 @@
+use std::{
+    f32::consts::PI,
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use egui_wgpu::wgpu;
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use rayon::prelude::*;
+
+use crate::bvh::{Bvh, Quality, Ray as BvhRay};
+use crate::core::app::Params;
+use crate::rendering::{ray_tracer::ClayMode, readback::srgb_to_linear};
+use crate::scene::{
+    camera::{Camera, CameraUniform},
+    components::{
+        geometry::{mesh::MeshData, vertex::Vertex},
+        material::{LightFlag, MaterialFlag, MaterialUniform, RayVisibility},
+    },
+    scene::Scene,
+    sky,
+};
+
+/// Rows of pixels handed to a thread at a time, few enough that `trace_for` keeps to its budget
+const TILE_ROWS: usize = 2;
+const MAX_SKIPPED_SURFACES: usize = 8;
+const EPSILON: f32 = 1e-5;
+/// Albedo of every surface in plain clay mode, `CLAY_ALBEDO` in the shader
+const CLAY_ALBEDO: f32 = 0.5;
+
+/// Path tracer on the CPU, for machines whose GPU can't run the compute shader and as a reference
+/// to check the shader against. It follows `trace` in `ray_tracer.wgsl` closely enough to share
+/// its random numbers, but only for spheres and meshes with plain and glass materials: textures,
+/// blends, thin films, volumes, fog and portals are left out, and every integrator path traces.
+#[derive(Default)]
+pub struct CpuTracer {
+    /// Each mesh's BVH in its own space, alongside the data it was built from so replaced meshes
+    /// are noticed
+    blas: Vec<(Arc<MeshData>, Bvh)>,
+    /// Accumulated radiance of the region last rendered, in the accumulation texture's row order
+    pub pixels: Vec<[f32; 4]>,
+    origin: (u32, u32),
+    size: (u32, u32),
+    /// Pass `trace_for` has part traced
+    pass: Option<Pass>,
+}
+
+/// A frame traced a few tiles at a time over several calls to `trace_for`.
+struct Pass {
+    params: Params,
+    camera: Camera,
+    /// Tile the pass started at, the next one traced after a pass is cut short
+    first: usize,
+    /// Tiles traced so far, wrapping around from `first`
+    traced: usize,
+}
+
+struct Ray {
+    origin: Vec3,
+    dir: Vec3,
+}
+
+struct Hit {
+    dst: f32,
+    point: Vec3,
+    normal: Vec3,
+    backface: bool,
+    material: MaterialUniform,
+    /// Linear vertex colour multiplied into the albedo
+    tint: Vec3,
+}
+
+/// What one frame reads, gathered once rather than per pixel.
+struct Frame<'a> {
+    scene: &'a Scene,
+    blas: &'a [(Arc<MeshData>, Bvh)],
+    /// World to model and model to world of every mesh
+    transforms: Vec<(Mat4, Mat4)>,
+    camera: CameraUniform,
+    cam_to_world: Mat4,
+    sun: Vec4,
+    params: Params,
+}
+
+impl CpuTracer {
+    /// Traces one frame of the `size` pixels from `params.tile_x` and `tile_y`, like a dispatch of
+    /// the shader, and adds it to the accumulation the same way, interleaving included. Starts
+    /// again when `params.frames` is below 1.
+    pub fn render(&mut self, scene: &Scene, params: &Params, size: (u32, u32)) {
+        let tiles = self.start_pass(scene, params, size);
+        self.trace_tiles(scene, params, 0..tiles);
+    }
+    /// Traces the frame `render` would a few tiles at a time, stopping once `budget` has run out
+    /// so the window stays responsive. Returns true once the frame is complete, until then the
+    /// same `params` should be passed again. Different params or a moved camera abandon the
+    /// frame and start the next where it left off, so every part of the image keeps refreshing.
+    pub fn trace_for(
+        &mut self,
+        scene: &Scene,
+        params: &Params,
+        size: (u32, u32),
+        budget: Duration,
+    ) -> bool {
+        let start = Instant::now();
+        let (mut first, mut traced) = (0, 0);
+        if let Some(pass) = self.pass.take() {
+            first = pass.first + pass.traced;
+            if pass.params == *params && pass.camera == scene.camera {
+                (first, traced) = (pass.first, pass.traced);
+            }
+        }
+        let tiles = self.start_pass(scene, params, size);
+        let first = first % tiles;
+        let batch = rayon::current_num_threads();
+        while traced < tiles {
+            let count = batch.min(tiles - traced);
+            let from = (first + traced) % tiles;
+            match from + count <= tiles {
+                true => self.trace_tiles(scene, params, from..from + count),
+                false => {
+                    self.trace_tiles(scene, params, from..tiles);
+                    self.trace_tiles(scene, params, 0..from + count - tiles);
+                }
+            }
+            traced += count;
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        if traced < tiles {
+            self.pass = Some(Pass {
+                params: *params,
+                camera: scene.camera,
+                first,
+                traced,
+            });
+            return false;
+        }
+        true
+    }
+    /// Whether `trace_for` is part way through a frame.
+    pub fn tracing(&self) -> bool {
+        self.pass.is_some()
+    }
+    /// Fits the accumulation to the region about to be traced, clearing it when that moved, and
+    /// returns how many tiles it is split into.
+    fn start_pass(&mut self, scene: &Scene, params: &Params, size: (u32, u32)) -> usize {
+        let (width, height) = (size.0.max(1), size.1.max(1));
+        let origin = (params.tile_x, params.tile_y);
+        if self.size != (width, height) || self.origin != origin {
+            self.size = (width, height);
+            self.origin = origin;
+            self.pixels = vec![[0.0; 4]; (width * height) as usize];
+        }
+        self.update_blas(scene);
+        (height as usize).div_ceil(TILE_ROWS)
+    }
+    /// Traces the pixels of `tiles` and blends them into the accumulation.
+    fn trace_tiles(&mut self, scene: &Scene, params: &Params, tiles: Range<usize>) {
+        let (width, origin) = (self.size.0, self.origin);
+        let frame = Frame {
+            scene,
+            blas: &self.blas,
+            transforms: scene
+                .meshes
+                .iter()
+                .map(|mesh| {
+                    let model_to_world = mesh.transform.to_matrix();
+                    (model_to_world.inverse(), model_to_world)
+                })
+                .collect(),
+            camera: scene.camera.to_uniform(),
+            cam_to_world: Mat4::from_cols_array_2d(&scene.camera.to_uniform().cam_to_world),
+            sun: Vec4::from_array(scene.sky.to_uniform()),
+            params: *params,
+        };
+        let interleave = match params.interleaving() {
+            true => params.interleave,
+            false => 1,
+        };
+        let sweep = (params.frames - 1).max(0) as u32;
+        let stride = (interleave / 2).max(1);
+        let weight = match params.frames >= 1 {
+            true => 1.0 / (2 + sweep / interleave) as f32,
+            false => 1.0,
+        };
+        self.pixels
+            .par_chunks_mut(width as usize * TILE_ROWS)
+            .enumerate()
+            .filter(|(tile, _)| tiles.contains(tile))
+            .for_each(|(tile, rows)| {
+                for (i, pixel) in rows.iter_mut().enumerate() {
+                    let x = (i % width as usize) as u32;
+                    let y = (tile * TILE_ROWS + i / width as usize) as u32;
+                    // The same checkerboard of pixels the shader traces this pass
+                    if (x + y * stride) % interleave != sweep % interleave {
+                        continue;
+                    }
+                    let sample = frame.pixel(origin.0 + x, origin.1 + y);
+                    *pixel = (Vec4::from_array(*pixel) * (1.0 - weight) + sample * weight).into();
+                }
+            });
+    }
+    /// Copies the accumulation into `texture`, in place of the shader's output.
+    pub fn upload(&self, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        let (width, height) = self.size;
+        if self.pixels.is_empty() {
+            return;
+        }
+        let (x, y) = self.origin;
+        if x + width > texture.width() || y + height > texture.height() {
+            return;
+        }
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&self.pixels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 16),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    fn update_blas(&mut self, scene: &Scene) {
+        self.blas.truncate(scene.meshes.len());
+        for (i, mesh) in scene.meshes.iter().enumerate() {
+            if self
+                .blas
+                .get(i)
+                .is_some_and(|(data, _)| Arc::ptr_eq(data, &mesh.data))
+            {
+                continue;
+            }
+            let positions: Vec<Vec3> = mesh.data.vertices.iter().map(|v| v.pos).collect();
+            let bvh = Bvh::build_indexed(&positions, &mesh.data.indices, Quality::High);
+            match i < self.blas.len() {
+                true => self.blas[i] = (mesh.data.clone(), bvh),
+                false => self.blas.push((mesh.data.clone(), bvh)),
+            }
+        }
+    }
+}
+
+impl Frame<'_> {
+    fn pixel(&self, x: u32, y: u32) -> Vec4 {
+        let params = &self.params;
+        let size = Vec2::new(params.width as f32, params.height as f32);
+        let mut seed = (y * params.width + x)
+            .wrapping_add(params.frames.unsigned_abs().wrapping_mul(719393))
+            .wrapping_add(params.seed.wrapping_mul(2654435761));
+        let mut pixel = Vec2::new(x as f32, y as f32);
+        if params.jitter != 0 {
+            pixel += Vec2::new(rand(&mut seed), rand(&mut seed)) - 0.5;
+        }
+        let uv = pixel / (size - 1.0);
+        let view_params = Vec3::from_array(self.camera.view_params);
+        let cam_origin = self.cam_to_world.w_axis.xyz();
+        let focus_point = self
+            .cam_to_world
+            .transform_point3((uv - 0.5).extend(1.0) * view_params);
+        let cam_right = self.cam_to_world.x_axis.xyz();
+        let cam_up = self.cam_to_world.y_axis.xyz();
+
+        let mut total = Vec4::ZERO;
+        for _ in 0..params.rays_per_pixel {
+            let defocus = rand_in_unit_disk(&mut seed) * self.camera.defocus_strength / size.x;
+            let origin = cam_origin + cam_right * defocus.x + cam_up * defocus.y;
+            let diverge = rand_in_unit_disk(&mut seed) * self.camera.diverge_strength / size.x;
+            let target = focus_point + cam_right * diverge.x + cam_up * diverge.y;
+            let ray = Ray {
+                origin,
+                dir: (target - origin).normalize(),
+            };
+            total += self.trace(ray, &mut seed);
+        }
+        total / params.rays_per_pixel.max(1) as f32
+    }
+    fn trace(&self, mut ray: Ray, seed: &mut u32) -> Vec4 {
+        let mut transmittance = Vec4::ONE;
+        let mut light = Vec4::ZERO;
+        let mut hidden = LightFlag::HiddenFromCamera as u32;
+        let mut kind = RayVisibility::HiddenFromCamera as u32;
+        for bounce in 0..=self.params.number_of_bounces.max(0) {
+            let hit = self.trace_visible(&ray, hidden, kind);
+            kind = RayVisibility::HiddenFromBounces as u32;
+            let Some(hit) = hit else {
+                if self.params.skybox != 0 {
+                    light +=
+                        self.clamp_light(transmittance * sky::radiance(self.sun, ray.dir), bounce);
+                }
+                break;
+            };
+            let hit = self.apply_clay(hit);
+            let material = &hit.material;
+            if material.flag == MaterialFlag::GLASS as i32 {
+                if hit.backface {
+                    let absorption = Vec4::from_array(material.absorption).xyz();
+                    let absorbed = (-hit.dst * absorption * material.absorption_stength).exp();
+                    transmittance = (transmittance.xyz() * absorbed).extend(1.0);
+                }
+                let ior = match hit.backface {
+                    true => material.ior,
+                    false => 1.0 / material.ior,
+                };
+                let reflect_dir = reflect(ray.dir, hit.normal);
+                let refract_dir = refract(ray.dir, hit.normal, ior);
+                let cos_theta = (-ray.dir).dot(hit.normal).min(1.0);
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let cannot_refract = ior * sin_theta > 1.0;
+                let follow_reflection = cannot_refract || reflectance(cos_theta, ior) > rand(seed);
+                let diffuse_dir = (hit.normal + rand_direction(seed)).normalize();
+                let reflect_dir = diffuse_dir.lerp(reflect_dir, material.specular).normalize();
+                let refract_dir = (-diffuse_dir)
+                    .lerp(refract_dir, material.smoothness)
+                    .normalize();
+                ray.dir = match follow_reflection {
+                    true => reflect_dir,
+                    false => refract_dir,
+                };
+                ray.origin = hit.point + 1e-4 * hit.normal * hit.normal.dot(ray.dir).signum();
+                hidden = LightFlag::HiddenFromSpecular as u32;
+            } else {
+                let is_specular_bounce = material.specular >= rand(seed);
+                // Drawn twice for diffuse bounces like the shader, whose second draw is
+                // `sample_bounce`, plain hemisphere sampling without portals or caustic casters
+                let mut diffuse_dir = rand_hemisphere(hit.normal, seed);
+                if !is_specular_bounce {
+                    diffuse_dir = rand_hemisphere(hit.normal, seed);
+                }
+                let specular_dir = reflect(ray.dir, hit.normal);
+                let emitted = match hit.backface
+                    && material.light_flags & LightFlag::SingleSided as u32 != 0
+                {
+                    true => Vec4::ZERO,
+                    false => Vec4::from_array(material.emission_color) * material.emission_strength,
+                };
+                let smoothness = material.smoothness * is_specular_bounce as u32 as f32;
+                ray.dir = diffuse_dir.lerp(specular_dir, smoothness).normalize();
+                ray.origin = hit.point;
+                light += self.clamp_light(emitted * transmittance, bounce);
+                hidden = match is_specular_bounce {
+                    true => LightFlag::HiddenFromSpecular as u32,
+                    false => 0,
+                };
+                transmittance *= match is_specular_bounce {
+                    true => Vec4::from_array(material.specular_color),
+                    false => {
+                        let color = Vec4::from_array(material.color);
+                        (color.xyz() * hit.tint).extend(color.w)
+                    }
+                };
+            }
+            let p = transmittance.xyz().max_element();
+            if rand(seed) >= p {
+                break;
+            }
+            transmittance *= 1.0 / p;
+        }
+        light
+    }
+    /// Closest hit passing through hidden emitters and invisible entities, clipped to the camera's
+    /// near and far planes for camera rays. Mirrors `trace_visible`.
+    fn trace_visible(&self, incident: &Ray, hidden: u32, kind: u32) -> Option<Hit> {
+        let mut origin = incident.origin;
+        let mut travelled = 0.0;
+        let mut far = f32::INFINITY;
+        if kind == RayVisibility::HiddenFromCamera as u32 {
+            let forward = self.cam_to_world.z_axis.xyz().normalize();
+            let cos_view = incident.dir.dot(forward).max(1e-4);
+            travelled = self.camera.near / cos_view;
+            far = self.camera.far / cos_view;
+            origin += incident.dir * travelled;
+        }
+        for _ in 0..MAX_SKIPPED_SURFACES {
+            let ray = Ray {
+                origin,
+                dir: incident.dir,
+            };
+            let mut hit = self.closest_hit(&ray)?;
+            hit.dst += travelled;
+            if hit.dst > far {
+                return None;
+            }
+            let invisible = hit.material.visibility & kind != 0;
+            let hidden_light = hidden != 0
+                && hit.material.emission_strength > 0.0
+                && hit.material.light_flags & hidden != 0;
+            if !invisible && !hidden_light {
+                return Some(hit);
+            }
+            travelled = hit.dst + 1e-4;
+            origin = hit.point + incident.dir * 1e-4;
+        }
+        None
+    }
+    fn closest_hit(&self, ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        for sphere in &self.scene.spheres {
+            let dst = closest.as_ref().map_or(f32::INFINITY, |hit| hit.dst);
+            if let Some(hit) = ray_sphere(ray, sphere.pos.into(), sphere.radius)
+                && hit.0 < dst
+            {
+                let (dst, point, normal, backface) = hit;
+                closest = Some(Hit {
+                    dst,
+                    point,
+                    normal,
+                    backface,
+                    material: sphere.material,
+                    tint: Vec3::ONE,
+                });
+            }
+        }
+        for (i, mesh) in self.scene.meshes.iter().enumerate() {
+            let (Some((_, bvh)), Some((world_to_model, model_to_world))) =
+                (self.blas.get(i), self.transforms.get(i))
+            else {
+                continue;
+            };
+            let mut local = BvhRay::new(
+                world_to_model.transform_point3(ray.origin),
+                world_to_model.transform_vector3(ray.dir).normalize(),
+            );
+            let cull = cull_material(&mesh.material);
+            // The BVH hits both sides, back faces of culled materials are stepped through
+            for _ in 0..MAX_SKIPPED_SURFACES {
+                let Some(found) = bvh.intersect(&local, f32::INFINITY) else {
+                    break;
+                };
+                if found.t <= EPSILON {
+                    local = BvhRay::new(local.at(EPSILON * 2.0), local.dir);
+                    continue;
+                }
+                let corners = [0, 1, 2].map(|corner| {
+                    let index = mesh.data.indices[found.triangle as usize * 3 + corner];
+                    &mesh.data.vertices[index as usize]
+                });
+                let face = (corners[1].pos - corners[0].pos).cross(corners[2].pos - corners[0].pos);
+                let backface = face.dot(local.dir) > 0.0;
+                if cull && backface {
+                    local = BvhRay::new(local.at(found.t + EPSILON), local.dir);
+                    continue;
+                }
+                let local_point = local.at(found.t);
+                let point = model_to_world.transform_point3(local_point);
+                let dst = ray.origin.distance(point);
+                if closest.as_ref().is_some_and(|hit| hit.dst <= dst) {
+                    break;
+                }
+                let weights = [1.0 - found.u - found.v, found.u, found.v];
+                let interpolate = |value: fn(&Vertex) -> Vec3| {
+                    corners
+                        .iter()
+                        .zip(weights)
+                        .map(|(vertex, w)| value(vertex) * w)
+                        .sum::<Vec3>()
+                };
+                let normal = interpolate(|v| v.normal).normalize_or_zero();
+                let normal = match backface {
+                    true => -normal,
+                    false => normal,
+                };
+                closest = Some(Hit {
+                    dst,
+                    point,
+                    normal: model_to_world.transform_vector3(normal).normalize_or_zero(),
+                    backface,
+                    material: mesh.material,
+                    tint: interpolate(|v| v.color).map(srgb_to_linear),
+                });
+                break;
+            }
+        }
+        closest
+    }
+    /// Mirrors `apply_clay`. Textures aren't sampled here, so textured clay takes the brightness
+    /// of the material's colour and vertex colours alone.
+    fn apply_clay(&self, mut hit: Hit) -> Hit {
+        let material = &mut hit.material;
+        if self.params.clay == ClayMode::Off as u32 || material.emission_strength > 0.0 {
+            return hit;
+        }
+        let grey = match self.params.clay == ClayMode::Textured as u32 {
+            true => (Vec4::from_array(material.color).xyz() * hit.tint)
+                .dot(Vec3::new(0.2126, 0.7152, 0.0722)),
+            false => CLAY_ALBEDO,
+        };
+        material.color = [grey, grey, grey, 1.0];
+        material.flag = MaterialFlag::DEFAULT as i32;
+        material.specular = 0.0;
+        material.smoothness = 0.0;
+        hit.tint = Vec3::ONE;
+        hit
+    }
+    fn clamp_light(&self, light: Vec4, bounce: i32) -> Vec4 {
+        let limit = match bounce <= 1 {
+            true => self.params.clamp_direct,
+            false => self.params.clamp_indirect,
+        };
+        let peak = light.xyz().max_element();
+        if bounce == 0 || limit <= 0.0 || peak <= limit {
+            return light;
+        }
+        light * (limit / peak)
+    }
+}
+
+/// Mirrors `cull_material`, emitters light both sides unless single sided.
+fn cull_material(material: &MaterialUniform) -> bool {
+    let two_sided_light = material.emission_strength > 0.0
+        && material.light_flags & LightFlag::SingleSided as u32 == 0;
+    material.flag != MaterialFlag::GLASS as i32
+        && material.flag != MaterialFlag::BLEND as i32
+        && !two_sided_light
+}
+
+/// Mirrors `ray_sphere`, returning the distance, point, normal facing the ray and whether the ray
+/// started inside.
+fn ray_sphere(ray: &Ray, centre: Vec3, radius: f32) -> Option<(f32, Vec3, Vec3, bool)> {
+    let offset = ray.origin - centre;
+    let a = ray.dir.dot(ray.dir);
+    let b = 2.0 * offset.dot(ray.dir);
+    let c = offset.dot(offset) - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let s = discriminant.sqrt();
+    let dst_near = ((-b - s) / (2.0 * a)).max(0.0);
+    let dst_far = (-b + s) / (2.0 * a);
+    if dst_far < 0.001 {
+        return None;
+    }
+    let inside = dst_near == 0.0;
+    let dst = if inside { dst_far } else { dst_near };
+    let point = ray.origin + ray.dir * dst;
+    let outward = (point - centre).normalize();
+    Some((dst, point, if inside { -outward } else { outward }, inside))
+}
+
+fn reflect(dir: Vec3, normal: Vec3) -> Vec3 {
+    dir - 2.0 * normal.dot(dir) * normal
+}
+
+/// WGSL's `refract`, zero on total internal reflection.
+fn refract(dir: Vec3, normal: Vec3, eta: f32) -> Vec3 {
+    let cos = normal.dot(dir);
+    let k = 1.0 - eta * eta * (1.0 - cos * cos);
+    if k < 0.0 {
+        return Vec3::ZERO;
+    }
+    eta * dir - (eta * cos + k.sqrt()) * normal
+}
+
+/// Schlick's approximation, as in the shader.
+fn reflectance(cos_theta: f32, ior: f32) -> f32 {
+    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
+}
+
+/// The shader's PCG hash, so both draw the same random numbers from the same seed.
+fn next_random_number(seed: &mut u32) -> u32 {
+    *seed = seed.wrapping_mul(747796405).wrapping_add(2891336453);
+    let result = ((*seed >> ((*seed >> 28) + 4)) ^ *seed).wrapping_mul(277803737);
+    (result >> 22) ^ result
+}
+
+fn rand(seed: &mut u32) -> f32 {
+    next_random_number(seed) as f32 / 4294967295.0
+}
+
+fn rand_normal_dist(seed: &mut u32) -> f32 {
+    let theta = 2.0 * PI * rand(seed);
+    let rho = (-2.0 * rand(seed).ln()).sqrt();
+    rho * theta.cos()
+}
+
+fn rand_direction(seed: &mut u32) -> Vec3 {
+    let x = rand_normal_dist(seed);
+    let y = rand_normal_dist(seed);
+    let z = rand_normal_dist(seed);
+    Vec3::new(x, y, z).normalize()
+}
+
+fn rand_hemisphere(normal: Vec3, seed: &mut u32) -> Vec3 {
+    let dir = rand_direction(seed);
+    dir * normal.dot(dir).signum()
+}
+
+fn rand_in_unit_disk(seed: &mut u32) -> Vec2 {
+    let angle = rand(seed) * 2.0 * PI;
+    Vec2::new(angle.cos(), angle.sin()) * rand(seed).sqrt()
+}
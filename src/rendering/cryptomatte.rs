@@ -0,0 +1,371 @@
+use std::{
+    collections::BTreeMap,
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use egui_wgpu::wgpu::{self, PipelineCompilationOptions, util::DeviceExt};
+use exr::prelude::{
+    AnyChannel, AnyChannels, AttributeValue, Encoding, FlatSamples, Image, ImageAttributes,
+    IntegerBounds, Layer, LayerAttributes, SmallVec, Text, WritableImage,
+};
+
+use crate::rendering::{
+    ray_tracer::RayTracer,
+    readback::{flip_rows, read_buffer_u32, read_texture_rgba32f},
+};
+use crate::scene::scene::{Scene, SceneName};
+
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+/// Subpixel samples per axis are capped so the id buffer stays under the default storage binding limit.
+pub const MAX_SAMPLES_PER_AXIS: u32 = 3;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct IdSettings {
+    width: u32,
+    height: u32,
+    samples_per_axis: u32,
+    _p1: u32,
+}
+
+/// Exports object and material id mattes in the Cryptomatte layout, so compositors can
+/// isolate any object or material of a render.
+pub struct Cryptomatte {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pub samples_per_axis: u32,
+    /// Id and coverage pairs kept per pixel, the most covering ids first
+    pub ranks: u32,
+    pub export_requested: bool,
+}
+
+impl Cryptomatte {
+    pub fn new(ray_tracer: &RayTracer) -> Self {
+        let device = ray_tracer.device.clone();
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cryptomatte Shader"),
+            source: wgpu::ShaderSource::Wgsl(ray_tracer.texture_mode.shader_source().into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cryptomatte Bind Group Layout"),
+            entries: &[
+                // Settings
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(mem::size_of::<IdSettings>() as _),
+                    },
+                    count: None,
+                },
+                // Id Samples
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cryptomatte Pipeline Layout"),
+            bind_group_layouts: &[
+                &ray_tracer.bind_group_layout,
+                &ray_tracer.textures_bind_group_layout,
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cryptomatte Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("ids"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            device,
+            queue: ray_tracer.queue.clone(),
+            pipeline,
+            bind_group_layout,
+            samples_per_axis: MAX_SAMPLES_PER_AXIS,
+            ranks: 6,
+            export_requested: false,
+        }
+    }
+    /// Traces the id pass for the current camera and writes it alongside the accumulated image to
+    /// `renders/cryptomatte_<scene>_<time>.exr`.
+    pub fn export(
+        &self,
+        ray_tracer: &RayTracer,
+        scene: &Scene,
+        scene_name: SceneName,
+        beauty: &wgpu::Texture,
+        width: u32,
+        height: u32,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let samples_per_axis = self.samples_per_axis.clamp(1, MAX_SAMPLES_PER_AXIS);
+        let ids = self.render_ids(ray_tracer, width, height, samples_per_axis)?;
+        let beauty = read_texture_rgba32f(&self.device, &self.queue, beauty, width, height)?;
+
+        let object_names: Vec<String> = (0..(scene.spheres.len() + scene.meshes.len()) as i32)
+            .filter_map(|i| scene.entity_name(i))
+            .collect();
+        // Materials are stored inline, so entities with identical materials share a name
+        let mut unique_materials: Vec<&[u8]> = vec![];
+        let material_names: Vec<String> = scene
+            .spheres
+            .iter()
+            .map(|s| bytemuck::bytes_of(&s.material))
+            .chain(scene.meshes.iter().map(|m| bytemuck::bytes_of(&m.material)))
+            .map(|bytes| {
+                let index = match unique_materials.iter().position(|m| *m == bytes) {
+                    Some(index) => index,
+                    None => {
+                        unique_materials.push(bytes);
+                        unique_materials.len() - 1
+                    }
+                };
+                format!("Material {}", index)
+            })
+            .collect();
+
+        let samples = (samples_per_axis * samples_per_axis) as usize;
+        let (width, height) = (width as usize, height as usize);
+        let mut channels: Vec<AnyChannel<FlatSamples>> = vec![];
+        for (i, name) in ["R", "G", "B", "A"].into_iter().enumerate() {
+            let values = flip_rows(width, height, |x, y| beauty[(y * width + x) * 4 + i]);
+            channels.push(AnyChannel::new(name, FlatSamples::F32(values)));
+        }
+        let mut attributes = ImageAttributes::new(IntegerBounds::from_dimensions((width, height)));
+        for (layer, names) in [
+            ("CryptoObject", &object_names),
+            ("CryptoMaterial", &material_names),
+        ] {
+            let hashes: Vec<f32> = names.iter().map(|n| hash_to_float(n)).collect();
+            // Two ranks fit in each RGBA channel group
+            let mut ranked = vec![vec![0.0; width * height]; self.ranks.div_ceil(2) as usize * 4];
+            for y in 0..height {
+                for x in 0..width {
+                    let first = (y * width + x) * samples;
+                    let mut coverage: Vec<(u32, f32)> = vec![];
+                    for &id in ids[first..first + samples].iter().filter(|id| **id != 0) {
+                        // Entities sharing a name share an id, so coverage adds up per hash
+                        let hash = hashes[id as usize - 1].to_bits();
+                        match coverage.iter_mut().find(|(h, _)| *h == hash) {
+                            Some((_, c)) => *c += 1.0 / samples as f32,
+                            None => coverage.push((hash, 1.0 / samples as f32)),
+                        }
+                    }
+                    coverage.sort_by(|a, b| b.1.total_cmp(&a.1));
+                    let index = (height - 1 - y) * width + x;
+                    for (rank, (hash, c)) in coverage.iter().take(self.ranks as usize).enumerate() {
+                        ranked[rank * 2][index] = f32::from_bits(*hash);
+                        ranked[rank * 2 + 1][index] = *c;
+                    }
+                }
+            }
+            for (i, values) in ranked.into_iter().enumerate() {
+                let name = format!("{}{:02}.{}", layer, i / 4, ["R", "G", "B", "A"][i % 4]);
+                channels.push(AnyChannel::new(
+                    Text::new_or_panic(name),
+                    FlatSamples::F32(values),
+                ));
+            }
+
+            let manifest: BTreeMap<&str, f32> = names
+                .iter()
+                .zip(hashes.iter())
+                .map(|(n, h)| (n.as_str(), *h))
+                .collect();
+            let manifest = manifest
+                .iter()
+                .map(|(name, hash)| format!("{}:\"{:08x}\"", json_string(name), hash.to_bits()))
+                .collect::<Vec<_>>()
+                .join(",");
+            let key = &format!("{:08x}", murmur3_32(layer.as_bytes(), 0))[..7];
+            for (field, value) in [
+                ("name", layer.to_owned()),
+                ("hash", "MurmurHash3_32".to_owned()),
+                ("conversion", "uint32_to_float32".to_owned()),
+                ("manifest", format!("{{{}}}", manifest)),
+            ] {
+                attributes.other.insert(
+                    Text::new_or_panic(format!("cryptomatte/{}/{}", key, field)),
+                    AttributeValue::Text(Text::new_or_panic(value)),
+                );
+            }
+        }
+
+        let layer = Layer::new(
+            (width, height),
+            LayerAttributes::default(),
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(SmallVec::from_vec(channels)),
+        );
+        let image = Image::new(attributes, layer);
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let dir = Path::new("renders");
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("cryptomatte_{:?}_{}.exr", scene_name, timestamp));
+        image.write().to_file(&path)?;
+        Ok(path)
+    }
+    /// Entity ids of every subpixel sample, row by row.
+    fn render_ids(
+        &self,
+        ray_tracer: &RayTracer,
+        width: u32,
+        height: u32,
+        samples_per_axis: u32,
+    ) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+        let settings_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Cryptomatte Settings Buffer"),
+                contents: bytemuck::cast_slice(&[IdSettings {
+                    width,
+                    height,
+                    samples_per_axis,
+                    _p1: 0,
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let ids_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cryptomatte Id Buffer"),
+            size: (width * height * samples_per_axis * samples_per_axis) as wgpu::BufferAddress
+                * mem::size_of::<u32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Cryptomatte Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: ids_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Cryptomatte Encoder"),
+            });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Cryptomatte Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &ray_tracer.bind_group, &[]);
+            compute_pass.set_bind_group(1, &ray_tracer.textures_bind_group, &[]);
+            compute_pass.set_bind_group(2, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE.0),
+                height.div_ceil(WORKGROUP_SIZE.1),
+                1,
+            );
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+        read_buffer_u32(&self.device, &self.queue, &ids_buffer)
+    }
+}
+
+/// `text` as a quoted JSON string. Anything outside printable ASCII is escaped too, as EXR text
+/// attributes can only hold single bytes.
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            ' '..='~' => json.push(c),
+            _ => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    json.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Cryptomatte id of a name, MurmurHash3 bits nudged away from denormals, infinities and NaNs.
+fn hash_to_float(name: &str) -> f32 {
+    let mut hash = murmur3_32(name.as_bytes(), 0);
+    let exponent = (hash >> 23) & 255;
+    if exponent == 0 || exponent == 255 {
+        hash ^= 1 << 23;
+    }
+    f32::from_bits(hash)
+}
+
+fn murmur3_32(bytes: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+    let scramble = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    let mut hash = seed;
+    let chunks = bytes.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        hash ^= scramble(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe6546b64);
+    }
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .enumerate()
+            .fold(0u32, |k, (i, b)| k | (*b as u32) << (8 * i));
+        hash ^= scramble(k);
+    }
+    hash ^= bytes.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85ebca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2ae35);
+    hash ^ (hash >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_names_are_escaped() {
+        assert_eq!(json_string("Sphere 1"), r#""Sphere 1""#);
+        assert_eq!(json_string(r#"say "hi"\now"#), r#""say \"hi\"\\now""#);
+        assert_eq!(json_string("a\nb\tc\u{1}"), r#""a\nb\tc\u0001""#);
+        assert_eq!(json_string("café 🙂"), r#""caf\u00e9 \ud83d\ude42""#);
+    }
+}
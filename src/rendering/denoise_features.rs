@@ -0,0 +1,234 @@
+use std::{
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use egui_wgpu::wgpu::{self, PipelineCompilationOptions, util::DeviceExt};
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, ImageAttributes, IntegerBounds, Layer,
+    LayerAttributes, SmallVec, Text, WritableImage,
+};
+
+use crate::rendering::{
+    ray_tracer::RayTracer,
+    readback::{flip_rows, read_texture_rgba32f},
+};
+use crate::scene::scene::SceneName;
+
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+pub const MAX_SAMPLES_PER_AXIS: u32 = 8;
+
+/// Features in the order the shader indexes them, with the EXR layer and channels each is saved as
+const FEATURES: [(&str, [&str; 3]); 2] = [("albedo", ["R", "G", "B"]), ("normal", ["X", "Y", "Z"])];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FeatureSettings {
+    width: u32,
+    height: u32,
+    samples_per_axis: u32,
+    feature: u32,
+}
+
+/// Exports the albedo and normal a denoiser such as OIDN takes alongside the noisy render to
+/// prefilter it. Sharp mirrors and glass are looked through to the first rough surface, so the
+/// denoiser keeps the detail reflected or refracted in them instead of smearing it.
+pub struct DenoiseFeatures {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// Stratified primary rays per pixel axis, antialiasing the features like the render
+    pub samples_per_axis: u32,
+    pub export_requested: bool,
+}
+
+impl DenoiseFeatures {
+    pub fn new(ray_tracer: &RayTracer) -> Self {
+        let device = ray_tracer.device.clone();
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Denoise Features Shader"),
+            source: wgpu::ShaderSource::Wgsl(ray_tracer.texture_mode.shader_source().into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Denoise Features Bind Group Layout"),
+            entries: &[
+                // Settings
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            mem::size_of::<FeatureSettings>() as _
+                        ),
+                    },
+                    count: None,
+                },
+                // Feature
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Denoise Features Pipeline Layout"),
+            bind_group_layouts: &[
+                &ray_tracer.bind_group_layout,
+                &ray_tracer.textures_bind_group_layout,
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Denoise Features Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("features"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            device,
+            queue: ray_tracer.queue.clone(),
+            pipeline,
+            bind_group_layout,
+            samples_per_axis: 4,
+            export_requested: false,
+        }
+    }
+    /// Traces the features for the current camera and writes them alongside the accumulated image
+    /// to `renders/features_<scene>_<time>.exr`, as `albedo` and `normal` layers.
+    pub fn export(
+        &self,
+        ray_tracer: &RayTracer,
+        scene_name: SceneName,
+        beauty: &wgpu::Texture,
+        width: u32,
+        height: u32,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let samples_per_axis = self.samples_per_axis.clamp(1, MAX_SAMPLES_PER_AXIS);
+        let beauty = read_texture_rgba32f(&self.device, &self.queue, beauty, width, height)?;
+        let (w, h) = (width as usize, height as usize);
+        let mut channels: Vec<AnyChannel<FlatSamples>> = vec![];
+        for (i, name) in ["R", "G", "B", "A"].into_iter().enumerate() {
+            let values = flip_rows(w, h, |x, y| beauty[(y * w + x) * 4 + i]);
+            channels.push(AnyChannel::new(name, FlatSamples::F32(values)));
+        }
+        for (feature, (layer, names)) in FEATURES.into_iter().enumerate() {
+            let texels =
+                self.render_feature(ray_tracer, width, height, samples_per_axis, feature as u32)?;
+            for (i, name) in names.into_iter().enumerate() {
+                let values = flip_rows(w, h, |x, y| texels[(y * w + x) * 4 + i]);
+                channels.push(AnyChannel::new(
+                    Text::new_or_panic(format!("{}.{}", layer, name)),
+                    FlatSamples::F32(values),
+                ));
+            }
+        }
+
+        let layer = Layer::new(
+            (w, h),
+            LayerAttributes::default(),
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(SmallVec::from_vec(channels)),
+        );
+        let image = Image::new(
+            ImageAttributes::new(IntegerBounds::from_dimensions((w, h))),
+            layer,
+        );
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let dir = Path::new("renders");
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("features_{:?}_{}.exr", scene_name, timestamp));
+        image.write().to_file(&path)?;
+        Ok(path)
+    }
+    /// One feature for every pixel, laid out like the render texture.
+    fn render_feature(
+        &self,
+        ray_tracer: &RayTracer,
+        width: u32,
+        height: u32,
+        samples_per_axis: u32,
+        feature: u32,
+    ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let settings_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Denoise Features Settings Buffer"),
+                contents: bytemuck::cast_slice(&[FeatureSettings {
+                    width,
+                    height,
+                    samples_per_axis,
+                    feature,
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Denoise Features Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Denoise Features Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Denoise Features Encoder"),
+            });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Denoise Features Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &ray_tracer.bind_group, &[]);
+            compute_pass.set_bind_group(1, &ray_tracer.textures_bind_group, &[]);
+            compute_pass.set_bind_group(2, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE.0),
+                height.div_ceil(WORKGROUP_SIZE.1),
+                1,
+            );
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+        read_texture_rgba32f(&self.device, &self.queue, &texture, width, height)
+    }
+}
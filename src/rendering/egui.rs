@@ -1,4 +1,8 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use egui::Context;
 use egui_wgpu::{
@@ -6,22 +10,95 @@ use egui_wgpu::{
     wgpu::{self, CommandEncoder, Device, Queue, TextureFormat, TextureView},
 };
 use egui_winit::State;
-use glam::Quat;
+use glam::{Quat, Vec3};
+use image::RgbaImage;
 use winit::{event::WindowEvent, window::Window};
 
 use crate::core::{
-    app::{DEBUG_MODES, Params},
-    bvh,
+    action::{Action, CommandPalette},
+    app::{Params, ParamsChange, SeedSchedule},
+    audio::{AudioInput, AudioSource, BAND_NAMES},
+    bvh, compare,
+    distributed::DistributedRender,
     engine::{FrameTiming, RENDER_SIZE, TmpResources},
+    gamepad::GamepadInput,
+    jobs::Jobs,
+    queue::{self, RenderQueue},
+    settings::{Dock, Panel, PanelLayout, Theme, UiSettings},
+    snapshot::{Snapshot, SnapshotFormat, Snapshots},
+    tabs::TabManager,
+    time_limit::TimeLimit,
+    watcher::AssetWatcher,
+};
+use crate::rendering::{
+    cpu_tracer::CpuTracer,
+    cryptomatte::{Cryptomatte, MAX_SAMPLES_PER_AXIS},
+    denoise_features::{self, DenoiseFeatures},
+    exposure::{AutoExposure, MAX_LOG_LUMINANCE, MIN_LOG_LUMINANCE},
+    frame_time_plot,
+    lightmap::{LightmapBaker, LightmapFormat},
+    magnifier::{self, Magnifier},
+    material_plot,
+    overlay::{LETTERBOX_RATIOS, Overlay},
+    picking::Picker,
+    post::{PostEffect, PostProcess, PostStack},
+    ray_tracer::{ClayMode, DebugMode, Integrator, MAX_MESHES, MAX_TEXTURES, RayTracer},
+    renderer::{Despeckle, ImageView},
+    upscale::GuidedUpscaler,
 };
-use crate::scene::scene::{SceneManager, SceneName};
+use crate::scene::{
+    camera::Camera,
+    components::{
+        geometry::mesh::UvView,
+        material::{
+            AddressMode, LightFlag, LightUnit, MaterialFlag, MaterialUniform, OrmChannels,
+            RayVisibility, TEMPERATURE_PRESETS, kelvin_to_rgb,
+        },
+        material_rules::MaterialPreset,
+        portal::{MAX_PORTALS, Portal},
+    },
+    entity::EntityLabel,
+    fog::HeightFog,
+    randomize::{self, RandomizeSettings},
+    scatter::{self, ScatterMode, ScatterSettings},
+    scene::{Scene, SceneManager, SceneName},
+    sky::Sky,
+    timeline::{AnimProperty, AnimTarget, AnimValue, AudioBinding, Interpolation},
+};
+
+/// Accessor for one scalar of a material, used to edit it across a selection
+type MaterialField = fn(&mut MaterialUniform) -> &mut f32;
+
+/// Width in points of a thumbnail in the snapshot gallery
+const SNAPSHOT_THUMBNAIL_WIDTH: f32 = 128.0;
 
 pub struct UiContext<'a> {
     pub renderer: &'a mut crate::rendering::renderer::Renderer,
+    pub ray_tracer: &'a mut RayTracer,
     pub scene_manager: &'a mut SceneManager,
+    pub jobs: &'a Jobs,
     pub timing: &'a mut FrameTiming,
     pub tmp: &'a mut TmpResources,
+    pub tabs: &'a mut TabManager,
     pub params: &'a mut Params,
+    pub auto_exposure: &'a mut AutoExposure,
+    pub upscaler: &'a mut GuidedUpscaler,
+    pub post: &'a mut PostProcess,
+    pub overlay: &'a mut Overlay,
+    pub lightmap: &'a mut LightmapBaker,
+    pub cryptomatte: &'a mut Cryptomatte,
+    pub denoise_features: &'a mut DenoiseFeatures,
+    pub picker: &'a mut Picker,
+    pub magnifier: &'a mut Magnifier,
+    pub distributed: &'a mut DistributedRender,
+    pub render_queue: &'a mut RenderQueue,
+    pub snapshots: &'a mut Snapshots,
+    pub watcher: &'a mut AssetWatcher,
+    pub audio: &'a mut AudioInput,
+    pub gamepad: &'a mut GamepadInput,
+    pub palette: &'a mut CommandPalette,
+    pub settings: &'a mut UiSettings,
+    pub cpu: &'a mut Option<CpuTracer>,
     pub window: Arc<Window>,
 }
 
@@ -29,6 +106,23 @@ pub struct EguiRenderer {
     state: State,
     pub renderer: Renderer,
     frame_started: bool,
+    /// Thumbnails for the texture cache window, keyed by path and rebuilt when the image is replaced
+    texture_previews: HashMap<String, (*const RgbaImage, egui::TextureHandle)>,
+    /// Gallery thumbnails and full size images of the snapshots being compared, keyed by id
+    snapshot_thumbnails: HashMap<usize, egui::TextureHandle>,
+    snapshot_images: HashMap<usize, egui::TextureHandle>,
+    /// Metrics of the compared pair, kept until the pair changes
+    snapshot_difference: Option<DifferenceView>,
+}
+
+/// `compare::Difference` of two snapshots, with its heatmap uploaded once it is shown.
+struct DifferenceView {
+    /// Ids of A and B
+    key: (usize, usize),
+    difference: compare::Difference,
+    /// The heatmap gain's bits and the heatmap drawn with it, redrawn when the gain changes
+    /// without comparing the pair again
+    heatmap: Option<(u32, egui::TextureHandle)>,
 }
 
 impl EguiRenderer {
@@ -64,16 +158,40 @@ impl EguiRenderer {
             state,
             renderer,
             frame_started: false,
+            texture_previews: HashMap::new(),
+            snapshot_thumbnails: HashMap::new(),
+            snapshot_images: HashMap::new(),
+            snapshot_difference: None,
         }
     }
 
     pub fn render_ui(&mut self, ctx: &mut UiContext) {
+        ctx.scene_manager.sync_selection();
         let mut camera = ctx.scene_manager.scene.camera.clone();
         let mut params = ctx.params.clone();
 
         let mut skybox = params.skybox != 0;
         let mut accumulate = params.accumulate != 0;
+        let mut auto_exposure = params.auto_exposure != 0;
 
+        if let Some(error) = ctx.tmp.error.clone() {
+            egui::Window::new("GPU Error")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(self.context(), |ui| {
+                    ui.label(error);
+                    if ui.button("OK").clicked() {
+                        ctx.tmp.error = None;
+                    }
+                });
+        }
+        if ctx.tmp.show_textures {
+            self.texture_cache_window(ctx);
+        }
+        if ctx.palette.open {
+            EguiRenderer::command_palette(self.context(), ctx.palette);
+        }
         if !ctx.tmp.fullscreen {
             egui::TopBottomPanel::top("menu").show(self.context(), |ui| {
                 egui::MenuBar::new().ui(ui, |ui| {
@@ -82,12 +200,46 @@ impl EguiRenderer {
                             log::warn!("idk how to close the window like this..");
                         }
                     });
+                    ui.menu_button("View", |ui| {
+                        ui.checkbox(&mut ctx.tmp.show_textures, "Texture Cache");
+                        ui.checkbox(&mut ctx.snapshots.open, "Snapshots");
+                        let palette = Action::CommandPalette;
+                        let mut button = egui::Button::new(palette.name());
+                        if let Some(shortcut) = palette.shortcut() {
+                            button = button.shortcut_text(shortcut.label());
+                        }
+                        if ui.add(button).clicked() {
+                            ctx.palette.run_requested = Some(palette);
+                        }
+                    });
+                    ui.menu_button("Panels", |ui| Self::panel_layout(ui, ctx.settings));
+                    ui.menu_button("Settings", |ui| Self::interface_settings(ui, ctx.settings));
+                });
+                ui.horizontal(|ui| {
+                    let closable = ctx.tabs.tabs.len() > 1;
+                    for (i, tab) in ctx.tabs.tabs.iter().enumerate() {
+                        let selected = i == ctx.tabs.selected;
+                        if ui
+                            .selectable_label(selected, format!("{:?}", tab.title))
+                            .clicked()
+                        {
+                            ctx.tabs.selected = i;
+                        }
+                        if closable && ui.small_button("x").clicked() {
+                            ctx.tabs.close_requested = Some(i);
+                        }
+                        ui.separator();
+                    }
+                    if ui.button("+").on_hover_text("New Tab").clicked() {
+                        ctx.tabs.open_requested = true;
+                    }
                 });
             });
-            egui::SidePanel::right("Inspector")
-                .resizable(true)
-                .width_range(200.0..=400.0)
-                .show(self.context(), |ui| {
+            Self::docked_panel(
+                self.context(),
+                Panel::Inspector,
+                &mut ctx.settings.panels[Panel::Inspector as usize],
+                |ui| {
                     ui.heading("Inspector");
                     ui.separator();
                     ui.heading("Camera");
@@ -104,6 +256,175 @@ impl EguiRenderer {
                         ui.label(format!("Look At"));
                     });
                     ui.add(egui::Slider::new(&mut camera.fov, 10.0..=90.0).text("Fov"));
+                    ui.horizontal(|ui| {
+                        if ui.button("Frame Scene").clicked()
+                            && let Some(bounds) = ctx.scene_manager.scene.compute_bounds()
+                        {
+                            camera.frame_bounds(&bounds);
+                        }
+                        let selected = ctx.scene_manager.selected_entity;
+                        if ui
+                            .add_enabled(selected != -1, egui::Button::new("Frame Selected"))
+                            .on_hover_text("F")
+                            .clicked()
+                            && let Some(bounds) = ctx.scene_manager.scene.entity_bounds(selected)
+                        {
+                            camera.frame_bounds(&bounds);
+                        }
+                    });
+                    let walkthrough = &mut ctx.tmp.walkthrough;
+                    if ui
+                        .checkbox(&mut walkthrough.enabled, "Walkthrough")
+                        .on_hover_text(
+                            "V, walk at eye height and stop at walls instead of flying through them",
+                        )
+                        .changed()
+                    {
+                        walkthrough.snap_requested = walkthrough.enabled;
+                    }
+                    if walkthrough.enabled {
+                        ui.horizontal(|ui| {
+                            let eye = ui.add(
+                                egui::DragValue::new(&mut walkthrough.eye_height)
+                                    .range(0.1..=10.0)
+                                    .speed(0.01)
+                                    .prefix("Eye "),
+                            );
+                            let step = ui
+                                .add(
+                                    egui::DragValue::new(&mut walkthrough.step_height)
+                                        .range(0.0..=2.0)
+                                        .speed(0.01)
+                                        .prefix("Step "),
+                                )
+                                .on_hover_text("Tallest ledge walked up onto rather than into");
+                            ui.add(
+                                egui::DragValue::new(&mut walkthrough.radius)
+                                    .range(0.01..=2.0)
+                                    .speed(0.01)
+                                    .prefix("Radius "),
+                            );
+                            if eye.changed() || step.changed() {
+                                walkthrough.snap_requested = true;
+                            }
+                        });
+                    }
+                    let gamepad = match (&ctx.gamepad.name, &ctx.gamepad.error) {
+                        (Some(name), _) => format!("Gamepad: {}", name),
+                        (None, Some(error)) => format!("Gamepad: {}", error),
+                        (None, None) if cfg!(feature = "gamepad") => {
+                            "Gamepad: none connected".to_owned()
+                        }
+                        (None, None) => "Gamepad: built without the gamepad feature".to_owned(),
+                    };
+                    ui.label(gamepad).on_hover_text(
+                        "Left stick moves, right stick looks, triggers rise and sink, shoulder \
+                        buttons change speed and A saves a render",
+                    );
+                    if ctx.gamepad.name.is_some() {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::Slider::new(&mut ctx.gamepad.look_speed, 0.5..=8.0)
+                                    .text("Look Speed"),
+                            );
+                            ui.checkbox(&mut ctx.gamepad.invert_y, "Invert Y");
+                        });
+                        ui.label(format!("Speed: {:.1}", camera.controller.speed()));
+                    }
+                    if Self::camera_views(ui, &mut ctx.scene_manager.scene, &mut camera) {
+                        params.reset_frame();
+                    }
+                    let integrator = Integrator::ALL
+                        .into_iter()
+                        .find(|i| *i as u32 == params.integrator)
+                        .unwrap_or(Integrator::PathTracing);
+                    egui::ComboBox::from_label("Integrator")
+                        .selected_text(integrator.name())
+                        .show_ui(ui, |ui| {
+                            for integrator in Integrator::ALL {
+                                ui.selectable_value(
+                                    &mut params.integrator,
+                                    integrator as u32,
+                                    integrator.name(),
+                                );
+                            }
+                        });
+                    let clay = ClayMode::ALL
+                        .into_iter()
+                        .find(|c| *c as u32 == params.clay)
+                        .unwrap_or(ClayMode::Off);
+                    egui::ComboBox::from_label("Clay")
+                        .selected_text(clay.name())
+                        .show_ui(ui, |ui| {
+                            for clay in ClayMode::ALL {
+                                ui.selectable_value(&mut params.clay, clay as u32, clay.name());
+                            }
+                        })
+                        .response
+                        .on_hover_text(
+                            "Render every material but the lights as matte grey, to judge the \
+                             lighting alone",
+                        );
+                    let mut on_cpu = ctx.cpu.is_some();
+                    if ui
+                        .checkbox(&mut on_cpu, "CPU Reference")
+                        .on_hover_text(
+                            "Path traces on the CPU instead, slowly, for checking the shader \
+                             against. Spheres and meshes with plain and glass materials only",
+                        )
+                        .changed()
+                    {
+                        *ctx.cpu = on_cpu.then(CpuTracer::default);
+                        params.reset_frame();
+                    }
+                    if params.integrator == Integrator::PathTracing as u32 {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::Slider::new(&mut params.caustic_guiding, 0.0..=0.9)
+                                    .text("Caustic Guiding"),
+                            )
+                            .on_hover_text(
+                                "Share of diffuse bounces aimed at glass spheres, finds the light \
+                                 they focus at the cost of noise elsewhere",
+                            );
+                            ui.add_enabled(
+                                params.caustic_guiding > 0.0,
+                                egui::DragValue::new(&mut params.caustic_casters).range(1..=64),
+                            )
+                            .on_hover_text(
+                                "Most glass spheres guided towards, each adds to the cost of a bounce",
+                            );
+                        });
+                    }
+                    ui.horizontal(|ui| {
+                        let schedule = &mut ctx.tmp.seed_schedule;
+                        egui::ComboBox::from_label("Seed")
+                            .selected_text(match schedule {
+                                SeedSchedule::Stable => "Stable",
+                                SeedSchedule::PerFrame => "Per Frame",
+                                SeedSchedule::Fixed(_) => "Fixed",
+                            })
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(schedule, SeedSchedule::Stable, "Stable");
+                                ui.selectable_value(schedule, SeedSchedule::PerFrame, "Per Frame")
+                                    .on_hover_text(
+                                        "Decorrelates the noise of animation frames, while any \
+                                         one frame rendered again keeps its noise",
+                                    );
+                                if ui
+                                    .selectable_label(
+                                        matches!(schedule, SeedSchedule::Fixed(_)),
+                                        "Fixed",
+                                    )
+                                    .clicked()
+                                {
+                                    *schedule = SeedSchedule::Fixed(params.seed);
+                                }
+                            });
+                        if let SeedSchedule::Fixed(seed) = schedule {
+                            ui.add(egui::DragValue::new(seed));
+                        }
+                    });
                     ui.add(
                         egui::Slider::new(&mut params.number_of_bounces, 0..=100).text("Bounces"),
                     );
@@ -111,6 +432,48 @@ impl EguiRenderer {
                         egui::Slider::new(&mut params.rays_per_pixel, 0..=100)
                             .text("Rays Per Pixel"),
                     );
+                    ui.horizontal(|ui| {
+                        ui.label("Clamp");
+                        ui.add(
+                            egui::DragValue::new(&mut params.clamp_direct)
+                                .range(0.0..=f32::MAX)
+                                .speed(0.1)
+                                .prefix("Direct "),
+                        )
+                        .on_hover_text(
+                            "Most light a path brings back from its first bounce, 0 turns it off",
+                        );
+                        ui.add(
+                            egui::DragValue::new(&mut params.clamp_indirect)
+                                .range(0.0..=f32::MAX)
+                                .speed(0.1)
+                                .prefix("Indirect "),
+                        )
+                        .on_hover_text(
+                            "Most light a path brings back from later bounces. Clamping it harder \
+                             than direct light removes fireflies and keeps bright highlights, 0 \
+                             turns it off",
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        let mut jitter = params.jitter != 0;
+                        ui.checkbox(&mut jitter, "Jitter")
+                            .on_hover_text("Spread rays over each pixel, anti-aliasing edges");
+                        params.jitter = jitter as u32;
+                        ui.add(
+                            egui::Slider::new(&mut params.taa_blend, 0.0..=1.0)
+                                .text("Temporal Blend"),
+                        )
+                        .on_hover_text(
+                            "While moving, blend each frame over the last one reprojected to the \
+                             new view. Lower is smoother but smears more, 0 turns it off",
+                        );
+                    });
+                    ui.add(egui::Slider::new(&mut params.interleave, 1..=16).text("Interleave"))
+                        .on_hover_text(
+                            "While accumulating, trace one pixel in this many each frame, so the \
+                             whole image refreshes sooner at the same cost per sample",
+                        );
                     ui.horizontal(|ui| {
                         ui.checkbox(&mut accumulate, "Accumulate");
                         params.accumulate = accumulate as i32;
@@ -119,26 +482,85 @@ impl EguiRenderer {
                             ctx.timing.reset();
                         }
                     });
+                    Self::time_limit(ui, &mut ctx.tmp.time_limit, ctx.timing, &params);
 
                     ui.add(
                         egui::Slider::new(&mut camera.diverge_strength, 0.0..=500.0)
                             .step_by(0.1)
                             .text("Diverge Strength"),
                     );
-                    ui.add(
+                    ui.add_enabled(
+                        !camera.exposure.enabled,
                         egui::Slider::new(&mut camera.defocus_strength, 0.0..=500.0)
                             .step_by(0.1)
                             .text("Defocus Strength"),
-                    );
+                    )
+                    .on_disabled_hover_text("Set by the f-stop");
                     ui.add(
                         egui::Slider::new(&mut camera.focus_dist, 0.0..=10.0)
                             .step_by(0.01)
                             .text("Focus Distance"),
                     );
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut ctx.overlay.show_focus, "Show Focal Plane")
+                            .on_hover_text(
+                                "Tint what lies within the band around the focus distance, \
+                                 the part of the image depth of field keeps sharp",
+                            );
+                        ui.add_enabled(
+                            ctx.overlay.show_focus,
+                            egui::DragValue::new(&mut ctx.overlay.focus_band)
+                                .speed(0.01)
+                                .range(0.001..=100.0),
+                        )
+                        .on_hover_text("Depth of the band in world units");
+                    });
+                    Self::clip_planes(ui, &ctx.scene_manager.scene, &mut camera);
+                    ui.checkbox(&mut camera.exposure.enabled, "Physical Exposure")
+                        .on_hover_text(
+                            "Expose for lights in cd/m² and lumens, the f-stop sets the depth of field",
+                        );
+                    if camera.exposure.enabled {
+                        let mut shutter = 1.0 / camera.exposure.shutter;
+                        ui.add(
+                            egui::Slider::new(&mut camera.exposure.iso, 50.0..=25600.0)
+                                .logarithmic(true)
+                                .text("ISO"),
+                        );
+                        if ui
+                            .add(
+                                egui::Slider::new(&mut shutter, 1.0..=8000.0)
+                                    .logarithmic(true)
+                                    .custom_formatter(|v, _| format!("1/{:.0}", v))
+                                    .text("Shutter (s)"),
+                            )
+                            .changed()
+                        {
+                            // Only write back on edits, the round trip isn't exact
+                            camera.exposure.shutter = 1.0 / shutter;
+                        }
+                        ui.add(
+                            egui::Slider::new(&mut camera.exposure.f_stop, 1.0..=22.0)
+                                .logarithmic(true)
+                                .custom_formatter(|v, _| format!("f/{:.1}", v))
+                                .text("Aperture"),
+                        );
+                        ui.label(format!(
+                            "EV100 {:.2}, {:.0}mm lens",
+                            camera.exposure.ev100(),
+                            camera.focal_length() * 1000.0
+                        ));
+                    }
                     ui.separator();
                     ui.heading("Scene");
                     ui.checkbox(&mut skybox, "Skybox");
                     params.skybox = skybox as i32;
+                    if skybox && Self::sky(ui, &mut ctx.scene_manager.scene.sky) {
+                        params.reset_frame();
+                    }
+                    if Self::height_fog(ui, &mut ctx.scene_manager.scene.fog) {
+                        params.reset_frame();
+                    }
                     ui.horizontal(|ui| {
                         ui.label("Scene ID");
                         egui::ComboBox::from_label("Scene")
@@ -153,14 +575,75 @@ impl EguiRenderer {
                                 }
                             });
                     });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut ctx.scene_manager.keep_settings, "Keep My Settings")
+                            .on_hover_text(
+                                "Don't load the bounces, clamps and sky each scene prefers \
+                                 when it's selected",
+                            );
+                        let scene = &mut ctx.scene_manager.scene;
+                        if ui
+                            .add_enabled(
+                                !scene.settings.is_empty(),
+                                egui::Button::new("Scene Settings"),
+                            )
+                            .on_hover_text(scene.settings.summary())
+                            .on_disabled_hover_text("This scene has no preferred settings")
+                            .clicked()
+                        {
+                            scene.settings.apply(&mut params);
+                            if scene.settings.post.is_some() {
+                                scene.post = scene.settings.post.clone();
+                            }
+                        }
+                    });
                     if ctx.scene_manager.selected_entity != -1 {
                         ui.separator();
-                        if ctx.scene_manager.selected_entity
-                            < ctx.scene_manager.scene.spheres.len() as i32
+                        if ui
+                            .button("Drop to Floor")
+                            .on_hover_text(
+                                "Lower the selection straight down until it rests on whatever \
+                                 is below",
+                            )
+                            .clicked()
+                        {
+                            let scene_manager = &mut *ctx.scene_manager;
+                            scene_manager.sync_selection();
+                            if scene_manager.scene.drop_to_floor(&scene_manager.selection) > 0 {
+                                params.reset_frame();
+                            }
+                        }
+                        if ctx.scene_manager.selection.len() <= 1
+                            && Self::scatter_tool(ui, ctx.scene_manager, &mut ctx.tmp.scatter)
                         {
+                            params.reset_frame();
+                        }
+                        if Self::randomize_tool(ui, ctx.scene_manager, &mut ctx.tmp.randomize) {
+                            params.reset_frame();
+                        }
+                    }
+                    if ctx.scene_manager.selection.len() > 1 {
+                        ui.separator();
+                        Self::selection_inspector(ui, ctx.scene_manager);
+                    } else if ctx.scene_manager.selected_entity != -1 {
+                        ui.separator();
+                        let palette = ctx.scene_manager.scene.materials.len();
+                        let area = ctx
+                            .scene_manager
+                            .scene
+                            .entity_area(ctx.scene_manager.selected_entity)
+                            .filter(|_| ctx.tmp.light_unit == LightUnit::Lumens)
+                            .unwrap_or(0.0);
+                        let selected = ctx.scene_manager.selected_entity;
+                        let is_sphere = selected < ctx.scene_manager.scene.spheres.len() as i32;
+                        ui.heading(if is_sphere { "Sphere" } else { "Mesh" });
+                        let placeholder = ctx.scene_manager.scene.entity_name(selected);
+                        if let Some(label) = ctx.scene_manager.scene.entity_label_mut(selected) {
+                            Self::label_editor(ui, label, placeholder.unwrap_or_default());
+                        }
+                        if is_sphere {
                             let s = &mut ctx.scene_manager.scene.spheres
                                 [ctx.scene_manager.selected_entity as usize];
-                            ui.heading("Sphere");
                             ui.horizontal(|ui| {
                                 ui.add(egui::DragValue::new(&mut s.pos[0]).speed(0.01));
                                 ui.add(egui::DragValue::new(&mut s.pos[1]).speed(0.01));
@@ -207,6 +690,14 @@ impl EguiRenderer {
                                 );
                                 ui.label(format!("Emission Strength"));
                             });
+                            Self::light_units(ui, &mut s.material, area, &mut ctx.tmp.light_unit);
+                            Self::color_temperature(
+                                ui,
+                                &mut s.material.emission_color,
+                                &mut ctx.tmp.light_temperature,
+                            );
+                            Self::light_flags(ui, &mut s.material);
+                            Self::ray_visibility(ui, &mut s.material);
                             ui.horizontal(|ui| {
                                 ui.add(
                                     egui::DragValue::new(&mut s.material.specular_color[0])
@@ -242,17 +733,38 @@ impl EguiRenderer {
                                 ui.add(egui::DragValue::new(&mut s.material.ior).speed(0.01));
                                 ui.label(format!("Refractive Index"));
                             });
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut s.material.priority).speed(0.05));
+                                ui.label("Glass Priority").on_hover_text(
+                                    "Where glass overlaps, the higher priority fills the space \
+                                     both take up. 0 never nests",
+                                );
+                            });
                             ui.horizontal(|ui| {
                                 ui.add(egui::DragValue::new(&mut s.material.flag).speed(1));
                                 ui.label(format!("Flag"));
                             });
+                            Self::blend_material(ui, &mut s.material, palette);
+                            Self::orm_channels(ui, &mut s.material);
+                            Self::address_mode(ui, &mut s.material);
+                            Self::thin_film(ui, &mut s.material);
+                            material_plot::show(ui, &s.material);
+                            if s.material.diffuse_index != -1 {
+                                ui.horizontal(|ui| {
+                                    ui.drag_angle(&mut s.texture_rotation);
+                                    ui.label("Texture Rotation");
+                                });
+                                ui.horizontal(|ui| {
+                                    ui.drag_angle(&mut s.texture_tilt);
+                                    ui.label("Texture Tilt");
+                                });
+                            }
                         } else {
                             let m = &mut ctx.scene_manager.scene.meshes[ctx
                                 .scene_manager
                                 .selected_entity
                                 as usize
                                 - ctx.scene_manager.scene.spheres.len()];
-                            ui.heading("Mesh");
                             ui.horizontal(|ui| {
                                 ui.add(egui::DragValue::new(&mut m.transform.pos.x).speed(0.01));
                                 ui.add(egui::DragValue::new(&mut m.transform.pos.y).speed(0.01));
@@ -324,6 +836,14 @@ impl EguiRenderer {
                                 );
                                 ui.label(format!("Emission Strength"));
                             });
+                            Self::light_units(ui, &mut m.material, area, &mut ctx.tmp.light_unit);
+                            Self::color_temperature(
+                                ui,
+                                &mut m.material.emission_color,
+                                &mut ctx.tmp.light_temperature,
+                            );
+                            Self::light_flags(ui, &mut m.material);
+                            Self::ray_visibility(ui, &mut m.material);
                             ui.horizontal(|ui| {
                                 ui.add(
                                     egui::DragValue::new(&mut m.material.specular_color[0])
@@ -357,13 +877,206 @@ impl EguiRenderer {
                                 ui.add(egui::DragValue::new(&mut m.material.ior).speed(0.01));
                                 ui.label(format!("Refractive Index"));
                             });
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut m.material.priority).speed(0.05));
+                                ui.label("Glass Priority").on_hover_text(
+                                    "Where glass overlaps, the higher priority fills the space \
+                                     both take up. 0 never nests",
+                                );
+                            });
                             ui.horizontal(|ui| {
                                 ui.add(egui::DragValue::new(&mut m.material.flag).speed(1));
                                 ui.label(format!("Flag"));
                             });
+                            Self::blend_material(ui, &mut m.material, palette);
+                            Self::orm_channels(ui, &mut m.material);
+                            Self::address_mode(ui, &mut m.material);
+                            let uv_view = m.uv_view;
+                            egui::ComboBox::from_label("UV View")
+                                .selected_text(m.uv_view.name())
+                                .show_ui(ui, |ui| {
+                                    for view in UvView::ALL {
+                                        ui.selectable_value(&mut m.uv_view, view, view.name());
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "Show a pattern laid out by the mesh's texture coordinates \
+                                     in place of its material",
+                                );
+                            if m.uv_view != uv_view {
+                                params.reset_frame();
+                            }
+                            Self::thin_film(ui, &mut m.material);
+                            material_plot::show(ui, &m.material);
+                            ui.separator();
+                            ui.label("Lightmap");
+                            egui::ComboBox::from_label("Resolution")
+                                .selected_text(format!("{}", ctx.lightmap.resolution))
+                                .show_ui(ui, |ui| {
+                                    for resolution in [256, 512, 1024, 2048] {
+                                        ui.selectable_value(
+                                            &mut ctx.lightmap.resolution,
+                                            resolution,
+                                            format!("{}", resolution),
+                                        );
+                                    }
+                                });
+                            ui.add(
+                                egui::Slider::new(&mut ctx.lightmap.target_frames, 1..=4096)
+                                    .logarithmic(true)
+                                    .text("Frames"),
+                            );
+                            ui.add(
+                                egui::Slider::new(&mut ctx.lightmap.samples_per_frame, 1..=64)
+                                    .text("Samples Per Frame"),
+                            );
+                            ui.horizontal(|ui| {
+                                if ui.button("Bake").clicked() {
+                                    ctx.lightmap.bake_requested = Some(
+                                        ctx.scene_manager.selected_entity as usize
+                                            - ctx.scene_manager.scene.spheres.len(),
+                                    );
+                                }
+                                if ctx.lightmap.is_baking() && ui.button("Stop").clicked() {
+                                    ctx.lightmap.stop();
+                                }
+                                if ctx.lightmap.is_rasterizing() {
+                                    ui.spinner();
+                                }
+                            });
+                            if let Some(progress) = ctx.lightmap.progress() {
+                                ui.add(egui::ProgressBar::new(progress).show_percentage());
+                                ui.horizontal(|ui| {
+                                    if ui.button("Export PNG").clicked() {
+                                        ctx.lightmap.export_requested = Some(LightmapFormat::Png);
+                                    }
+                                    if ui.button("Export EXR").clicked() {
+                                        ctx.lightmap.export_requested = Some(LightmapFormat::Exr);
+                                    }
+                                });
+                            }
+                        }
+                    }
+                    if !ctx.scene_manager.scene.volumes.is_empty() {
+                        ui.separator();
+                        ui.heading("Volumes");
+                        for (i, volume) in ctx.scene_manager.scene.volumes.iter_mut().enumerate() {
+                            ui.collapsing(format!("Volume {}", i), |ui| {
+                                let dims = volume.grid.dims;
+                                ui.label(format!(
+                                    "{} x {} x {} voxels, {} bricks",
+                                    dims.x,
+                                    dims.y,
+                                    dims.z,
+                                    volume.grid.brick_count()
+                                ));
+                                let mut changed = false;
+                                for (bounds, label) in [
+                                    (&mut volume.bounds_min, "Min"),
+                                    (&mut volume.bounds_max, "Max"),
+                                ] {
+                                    ui.horizontal(|ui| {
+                                        changed |= ui
+                                            .add(egui::DragValue::new(&mut bounds.x).speed(0.01))
+                                            .changed();
+                                        changed |= ui
+                                            .add(egui::DragValue::new(&mut bounds.y).speed(0.01))
+                                            .changed();
+                                        changed |= ui
+                                            .add(egui::DragValue::new(&mut bounds.z).speed(0.01))
+                                            .changed();
+                                        ui.label(label);
+                                    });
+                                }
+                                changed |= ui
+                                    .add(
+                                        egui::Slider::new(&mut volume.density_scale, 0.0..=100.0)
+                                            .logarithmic(true)
+                                            .text("Density"),
+                                    )
+                                    .changed();
+                                ui.horizontal(|ui| {
+                                    changed |= ui
+                                        .color_edit_button_rgba_unmultiplied(&mut volume.color)
+                                        .changed();
+                                    ui.label("Albedo");
+                                });
+                                ui.horizontal(|ui| {
+                                    changed |= ui
+                                        .color_edit_button_rgba_unmultiplied(
+                                            &mut volume.emission_color,
+                                        )
+                                        .changed();
+                                    ui.label("Emissive Color");
+                                });
+                                changed |= Self::color_temperature(
+                                    ui,
+                                    &mut volume.emission_color,
+                                    &mut ctx.tmp.light_temperature,
+                                );
+                                changed |= ui
+                                    .add(
+                                        egui::Slider::new(
+                                            &mut volume.emission_strength,
+                                            0.0..=50.0,
+                                        )
+                                        .text("Emission Strength"),
+                                    )
+                                    .changed();
+                                if changed {
+                                    params.reset_frame();
+                                }
+                            });
                         }
                     }
                     ui.separator();
+                    ui.heading("Portals").on_hover_text(
+                        "Quads over windows and openings, the sky is sampled through them",
+                    );
+                    let portals = &mut ctx.scene_manager.scene.portals;
+                    let mut changed = false;
+                    let mut removed = None;
+                    for (i, portal) in portals.iter_mut().enumerate() {
+                        ui.collapsing(format!("Portal {}", i), |ui| {
+                            for (v, label) in [
+                                (&mut portal.corner, "Corner"),
+                                (&mut portal.u, "Edge U"),
+                                (&mut portal.v, "Edge V"),
+                            ] {
+                                ui.horizontal(|ui| {
+                                    changed |= ui
+                                        .add(egui::DragValue::new(&mut v.x).speed(0.01))
+                                        .changed();
+                                    changed |= ui
+                                        .add(egui::DragValue::new(&mut v.y).speed(0.01))
+                                        .changed();
+                                    changed |= ui
+                                        .add(egui::DragValue::new(&mut v.z).speed(0.01))
+                                        .changed();
+                                    ui.label(label);
+                                });
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = removed {
+                        portals.remove(i);
+                        changed = true;
+                    }
+                    if ui
+                        .add_enabled(portals.len() < MAX_PORTALS, egui::Button::new("Add Portal"))
+                        .clicked()
+                    {
+                        portals.push(Portal::new(Vec3::new(-0.5, 1.0, 0.0), Vec3::X, Vec3::Y));
+                        changed = true;
+                    }
+                    if changed {
+                        params.reset_frame();
+                    }
+                    ui.separator();
                     ui.heading("Entities");
                     ui.label(format!(
                         "Meshes: {:#?}",
@@ -373,23 +1086,56 @@ impl EguiRenderer {
                         "Spheres: {:#?}",
                         ctx.scene_manager.scene.spheres.len()
                     ));
-                });
-
-            egui::SidePanel::left("Debug")
-                .resizable(true)
-                .width_range(200.0..=350.0)
-                .show(self.context(), |ui| {
-                    ui.heading("Debug");
-                    ui.separator();
-                    ui.label(format!("Frame: {}", params.frames));
                     ui.label(format!(
-                        "FPS: {:.0}",
-                        1.0 / (1.0 * ctx.timing.dt.as_secs_f64())
+                        "Volumes: {:#?}",
+                        ctx.scene_manager.scene.volumes.len()
                     ));
+                },
+            );
+            Self::docked_panel(
+                self.context(),
+                Panel::Debug,
+                &mut ctx.settings.panels[Panel::Debug as usize],
+                |ui| {
+                    ui.heading("Debug");
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        let current = ctx.ray_tracer.workgroup_size;
+                        egui::ComboBox::from_label("Workgroup")
+                            .selected_text(format!("{}x{}", current.0, current.1))
+                            .show_ui(ui, |ui| {
+                                for size in RayTracer::WORKGROUP_SIZES {
+                                    if ui
+                                        .selectable_label(
+                                            size == current,
+                                            format!("{}x{}", size.0, size.1),
+                                        )
+                                        .clicked()
+                                    {
+                                        ctx.ray_tracer.set_workgroup_size(size);
+                                    }
+                                }
+                            });
+                        if ui.button("Auto Tune").clicked() {
+                            ctx.ray_tracer.auto_tune_requested = true;
+                        }
+                    });
+                    for ((x, y), time) in ctx.ray_tracer.auto_tune_results.iter() {
+                        ui.label(format!("{}x{}: {:.2?}", x, y, time));
+                    }
+                    ui.checkbox(&mut ctx.ray_tracer.specialised, "Specialised Pipelines")
+                        .on_hover_text(
+                            "Compile the ray tracer for the current skybox, accumulation and \
+                            debug settings instead of branching on them per ray",
+                        );
                     ui.label(format!(
-                        "Avg Frame Time: {:#?}",
-                        ctx.timing.average_frame_time
+                        "{} pipelines compiled",
+                        ctx.ray_tracer.pipeline_count()
                     ));
+                    ui.checkbox(&mut ctx.upscaler.enabled, "Guided Preview")
+                        .on_hover_text(
+                            "Upscale the low resolution frames shown while moving using full resolution normals and depth",
+                        );
                     ui.separator();
                     ui.heading("BVH");
                     ui.label(format!(
@@ -400,6 +1146,11 @@ impl EguiRenderer {
                         "Triangle: {}",
                         ctx.scene_manager.scene.bvh_data.triangles.len()
                     ));
+                    ui.label(format!(
+                        "Degenerate Removed: {}",
+                        ctx.scene_manager.scene.bvh_data.degenerate_triangles
+                    ));
+                    Self::mesh_diagnostics(ui, ctx.scene_manager);
 
                     egui::ComboBox::from_label("Quality")
                         .selected_text(format!("{:?}", ctx.scene_manager.scene.bvh_quality))
@@ -426,6 +1177,199 @@ impl EguiRenderer {
                         params.reset_frame();
                         ctx.timing.reset();
                     }
+                    ui.checkbox(&mut ctx.scene_manager.auto_rebuild_bvh, "Auto Rebuild")
+                        .on_hover_text(
+                            "Rebuild a mesh's BVH in the background after it is stretched unevenly",
+                        );
+                    ui.checkbox(&mut ctx.watcher.enabled, "Reload Changed Assets")
+                        .on_hover_text(
+                            "Reload models and textures when they are saved over on disk",
+                        );
+                    ui.separator();
+                    ui.heading("Exposure");
+                    ui.checkbox(&mut auto_exposure, "Auto Exposure");
+                    params.auto_exposure = auto_exposure as i32;
+                    ui.add(
+                        egui::Slider::new(&mut params.exposure, -10.0..=10.0)
+                            .step_by(0.1)
+                            .text("Exposure (EV)"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut ctx.auto_exposure.adaptation, 0.01..=1.0)
+                            .text("Adaptation"),
+                    );
+                    ui.label(format!(
+                        "Avg Luminance: {:.4}",
+                        ctx.auto_exposure.average_luminance
+                    ));
+                    ui.label(format!("Auto Exposure: {:.3}", ctx.auto_exposure.exposure));
+                    EguiRenderer::luminance_histogram(ui, ctx.auto_exposure);
+                    let despeckle = Despeckle::ALL
+                        .into_iter()
+                        .find(|d| *d as u32 == params.despeckle)
+                        .unwrap_or(Despeckle::Off);
+                    egui::ComboBox::from_label("Despeckle")
+                        .selected_text(despeckle.name())
+                        .show_ui(ui, |ui| {
+                            for despeckle in Despeckle::ALL {
+                                ui.selectable_value(
+                                    &mut params.despeckle,
+                                    despeckle as u32,
+                                    despeckle.name(),
+                                );
+                            }
+                        })
+                        .response
+                        .on_hover_text(
+                            "Cleans up noise in the shown image only, saved renders keep every sample",
+                        );
+                    ui.checkbox(&mut ctx.tmp.annotate_screenshots, "Annotate Screenshots")
+                        .on_hover_text("Burn scene, samples and camera info into saved renders");
+                    ui.separator();
+                    ui.heading("Cryptomatte");
+                    ui.add(
+                        egui::Slider::new(
+                            &mut ctx.cryptomatte.samples_per_axis,
+                            1..=MAX_SAMPLES_PER_AXIS,
+                        )
+                        .text("Samples Per Axis"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut ctx.cryptomatte.ranks, 2..=16)
+                            .step_by(2.0)
+                            .text("Ranks"),
+                    );
+                    if ui
+                        .button("Export Mattes")
+                        .on_hover_text(
+                            "Saves the render with object and material id layers to renders/",
+                        )
+                        .clicked()
+                    {
+                        ctx.cryptomatte.export_requested = true;
+                    }
+                    ui.separator();
+                    ui.heading("Denoiser Features");
+                    ui.add(
+                        egui::Slider::new(
+                            &mut ctx.denoise_features.samples_per_axis,
+                            1..=denoise_features::MAX_SAMPLES_PER_AXIS,
+                        )
+                        .text("Samples Per Axis"),
+                    );
+                    if ui
+                        .button("Export Features")
+                        .on_hover_text(
+                            "Saves the render with the albedo and normal layers OIDN denoises \
+                             with to renders/, looking through sharp mirrors and glass",
+                        )
+                        .clicked()
+                    {
+                        ctx.denoise_features.export_requested = true;
+                    }
+                    ui.separator();
+                    ui.heading("Post Process");
+                    let scene = &mut ctx.scene_manager.scene;
+                    let mut scene_override = scene.post.is_some();
+                    if ui
+                        .checkbox(&mut scene_override, "Scene Override")
+                        .on_hover_text("Give this scene its own stack instead of the global one")
+                        .changed()
+                    {
+                        scene.post = scene_override.then(|| ctx.post.stack.clone());
+                    }
+                    let stack = scene.post.as_mut().unwrap_or(&mut ctx.post.stack);
+                    EguiRenderer::post_stack(ui, stack);
+                    ui.separator();
+                    ui.heading("Overlay");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut ctx.overlay.show_grid, "Grid");
+                        ui.checkbox(&mut ctx.overlay.show_axes, "Axes");
+                        ui.checkbox(&mut ctx.picker.enabled, "Hover Highlight")
+                            .on_hover_text("Right click an entity in the viewport to select it");
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut ctx.overlay.grid_spacing, 0.1..=10.0)
+                            .logarithmic(true)
+                            .text("Grid Spacing"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut ctx.overlay.grid_fade, 10.0..=1000.0)
+                            .logarithmic(true)
+                            .text("Grid Fade"),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut ctx.overlay.show_wireframe, "Wireframe");
+                        ui.checkbox(&mut ctx.overlay.show_normals, "Normals");
+                        ui.add_enabled(
+                            ctx.overlay.show_normals,
+                            egui::DragValue::new(&mut ctx.overlay.normal_length)
+                                .speed(0.005)
+                                .range(0.001..=10.0),
+                        )
+                        .on_hover_text("Normal length in world units");
+                    })
+                    .response
+                    .on_hover_text("Drawn for the selected mesh");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut ctx.overlay.show_thirds, "Thirds");
+                        ui.checkbox(&mut ctx.overlay.show_center, "Center");
+                        ui.checkbox(&mut ctx.overlay.show_safe_areas, "Safe Areas");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut ctx.magnifier.enabled, "Magnifier");
+                        ui.add_enabled(
+                            ctx.magnifier.enabled,
+                            egui::Slider::new(&mut ctx.magnifier.zoom, magnifier::ZOOM_RANGE)
+                                .suffix("×"),
+                        );
+                    });
+                    egui::ComboBox::from_label("Letterbox")
+                        .selected_text(
+                            LETTERBOX_RATIOS
+                                .iter()
+                                .find(|(_, r)| Some(*r) == ctx.overlay.letterbox)
+                                .map_or("None", |(name, _)| name),
+                        )
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut ctx.overlay.letterbox, None, "None");
+                            for (name, ratio) in LETTERBOX_RATIOS {
+                                ui.selectable_value(&mut ctx.overlay.letterbox, Some(ratio), name);
+                            }
+                        });
+                    ui.separator();
+                    ui.heading("Distributed");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut ctx.distributed.workers)
+                            .hint_text("host:port, host:port"),
+                    )
+                    .on_hover_text("Machines running with --worker");
+                    ui.add(
+                        egui::Slider::new(&mut ctx.distributed.tile_size, 32..=512)
+                            .text("Tile Size"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut ctx.distributed.frames, 1..=16384)
+                            .logarithmic(true)
+                            .text("Frames Per Tile"),
+                    );
+                    if ctx.distributed.is_running() {
+                        ui.add(
+                            egui::ProgressBar::new(ctx.distributed.progress()).show_percentage(),
+                        );
+                    } else if ui
+                        .button("Render Distributed")
+                        .on_hover_text(
+                            "Workers load the scene by name with this camera and render \
+                             settings, edited scenes are refused",
+                        )
+                        .clicked()
+                    {
+                        ctx.distributed.start_requested = true;
+                    }
+                    ui.separator();
+                    ui.heading("Render Queue");
+                    EguiRenderer::render_queue(ui, ctx.render_queue);
                     ui.separator();
                     ui.horizontal(|ui| {
                         ui.label("Resolution");
@@ -440,52 +1384,153 @@ impl EguiRenderer {
                                 .range(1..=RENDER_SIZE.1),
                         );
                     });
-                    ui.horizontal(|ui| {
-                        ui.label("Debug Mode:");
-                        ui.add(
-                            egui::DragValue::new(&mut params.debug_flag)
-                                .speed(1)
-                                .range(0..=DEBUG_MODES),
-                        );
-                    });
-                    ui.add(
-                        egui::Slider::new(&mut params.debug_scale, 1..=1000)
-                            .text("Depth Threshold"),
-                    );
+                    egui::ComboBox::from_label("Debug Mode")
+                        .selected_text(
+                            DebugMode::ALL
+                                .iter()
+                                .find(|mode| **mode as i32 == params.debug_flag)
+                                .map_or("Off", |mode| mode.name()),
+                        )
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut params.debug_flag, 0, "Off");
+                            for mode in DebugMode::ALL {
+                                ui.selectable_value(
+                                    &mut params.debug_flag,
+                                    mode as i32,
+                                    mode.name(),
+                                );
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut params.debug_scale, 1..=1000).text(
+                        match DebugMode::is_cost(params.debug_flag) {
+                            true => "Heatmap Maximum",
+                            false => "Depth Threshold",
+                        },
+                    ));
+                },
+            );
+            Self::docked_panel(
+                self.context(),
+                Panel::Profiler,
+                &mut ctx.settings.panels[Panel::Profiler as usize],
+                |ui| {
+                    ui.heading("Profiler");
                     ui.separator();
+                    ui.label(format!("Frame: {}", params.frames));
+                    ui.label(format!(
+                        "FPS: {:.0}",
+                        1.0 / (1.0 * ctx.timing.dt.as_secs_f64())
+                    ));
+                    frame_time_plot::show(ui, ctx.timing);
+                    for (name, elapsed) in ctx.jobs.running() {
+                        ui.label(format!("{} ({:.1?})", name, elapsed));
+                    }
+                    ui.label(format!(
+                        "Scene GPU Memory: {:.1} MB",
+                        ctx.ray_tracer.scene_memory() as f64 / (1 << 20) as f64
+                    ));
+                    egui::CollapsingHeader::new("GPU").show(ui, |ui| {
+                        egui::Grid::new("gpu_report").show(ui, |ui| {
+                            for (name, value) in &ctx.tmp.gpu_report {
+                                ui.label(*name);
+                                ui.label(value);
+                                ui.end_row();
+                            }
+                        });
+                    });
+                },
+            );
+            Self::docked_panel(
+                self.context(),
+                Panel::EntityList,
+                &mut ctx.settings.panels[Panel::EntityList as usize],
+                |ui| {
                     ui.heading("Entity List");
+                    ui.weak("Ctrl+click to add to the selection, Shift+click for a range");
                     egui::ScrollArea::vertical().show(ui, |ui| {
-                        let nothing_selected = ctx.scene_manager.selected_entity == -1;
-                        for (i, _) in ctx.scene_manager.scene.spheres.iter().enumerate() {
-                            let selected =
-                                ctx.scene_manager.selected_entity == i as i32 && !nothing_selected;
-                            if ui.selectable_label(selected, "Sphere").clicked() {
-                                ctx.scene_manager.selected_entity = i as i32;
+                        let scene_manager = &mut *ctx.scene_manager;
+                        let scene = &scene_manager.scene;
+                        let entities = scene.spheres.len() + scene.meshes.len();
+                        let names: Vec<(String, String)> = (0..entities as i32)
+                            .map(|entity| {
+                                let notes = scene
+                                    .entity_label(entity)
+                                    .map(|label| label.notes.clone())
+                                    .unwrap_or_default();
+                                (scene.entity_name(entity).unwrap_or_default(), notes)
+                            })
+                            .collect();
+                        for (i, (name, notes)) in names.into_iter().enumerate() {
+                            let entity = i as i32;
+                            let mut response =
+                                ui.selectable_label(scene_manager.is_selected(entity), name);
+                            if !notes.is_empty() {
+                                response = response.on_hover_text(notes);
                             }
-                        }
-
-                        for (i, m) in ctx.scene_manager.scene.meshes.iter().enumerate() {
-                            let selected = ctx.scene_manager.selected_entity
-                                - ctx.scene_manager.scene.spheres.len() as i32
-                                == i as i32
-                                && !nothing_selected;
-                            if ui
-                                .selectable_label(
-                                    selected,
-                                    m.label.clone().unwrap_or("Mesh".to_owned()),
-                                )
-                                .clicked()
-                            {
-                                ctx.scene_manager.selected_entity =
-                                    (ctx.scene_manager.scene.spheres.len() + i) as i32;
+                            if response.clicked() {
+                                let modifiers = ui.input(|i| i.modifiers);
+                                scene_manager.select(entity, modifiers.command, modifiers.shift);
                             }
                         }
                     });
-                });
+                },
+            );
+            Self::docked_panel(
+                self.context(),
+                Panel::MaterialLibrary,
+                &mut ctx.settings.panels[Panel::MaterialLibrary as usize],
+                |ui| {
+                    if EguiRenderer::material_library(ui, ctx.scene_manager) {
+                        params.reset_frame();
+                    }
+                },
+            );
+            Self::docked_panel(
+                self.context(),
+                Panel::Timeline,
+                &mut ctx.settings.panels[Panel::Timeline as usize],
+                |ui| {
+                    EguiRenderer::timeline_panel(ui, ctx.scene_manager);
+                    EguiRenderer::audio_panel(ui, ctx.audio, ctx.scene_manager);
+                },
+            );
         }
         egui::CentralPanel::default().show(self.context(), |ui| {
+            EguiRenderer::zoom_bar(ui, &mut ctx.renderer.view);
             egui::Frame::canvas(ui.style()).show(ui, |ui| {
-                if ctx.renderer.render_ray_traced_image(ui) {
+                let (mut response, image) = ctx
+                    .renderer
+                    .render_ray_traced_image(ui, (params.width, params.height));
+                let visible = image.intersect(response.rect);
+                ctx.overlay.paint(ui, image, visible);
+                let pointer = response
+                    .hover_pos()
+                    .filter(|pos| !ctx.tmp.use_mouse && visible.contains(*pos));
+                if let Some(pointer) = pointer {
+                    ctx.magnifier
+                        .paint(ui.ctx(), pointer, ui.ctx().screen_rect());
+                }
+                ctx.picker.cursor = pointer.map(|pos| {
+                    // The image is drawn bottom row first
+                    let uv = (pos - image.min) / image.size();
+                    (
+                        (uv.x * params.width as f32) as u32,
+                        ((1.0 - uv.y) * params.height as f32) as u32,
+                    )
+                });
+                let hovered = ctx.picker.hovered;
+                if ctx.picker.cursor.is_some()
+                    && let Some(name) = ctx.scene_manager.scene.entity_name(hovered)
+                {
+                    response = response.on_hover_text_at_pointer(name);
+                }
+                if response.secondary_clicked() {
+                    match hovered != -1 && ui.input(|i| i.modifiers.command) {
+                        true => ctx.scene_manager.select(hovered, true, false),
+                        false => ctx.scene_manager.selected_entity = hovered,
+                    }
+                }
+                if response.clicked() {
                     ctx.tmp.use_mouse = true;
                     ctx.window.set_cursor_visible(!ctx.tmp.use_mouse);
                     ctx.window
@@ -495,10 +1540,18 @@ impl EguiRenderer {
             });
         });
 
-        if *ctx.params != params {
-            *ctx.params = params;
-            ctx.params.reset_frame();
-            ctx.timing.reset();
+        // After the panels, which write their own copies of the settings back into `params`
+        if ctx.snapshots.open {
+            self.snapshot_window(ctx, &mut params, &mut camera);
+        }
+        match params.change_from(ctx.params) {
+            ParamsChange::None => {}
+            ParamsChange::Display => *ctx.params = params,
+            ParamsChange::Accumulation => {
+                *ctx.params = params;
+                ctx.params.reset_frame();
+                ctx.timing.reset();
+            }
         }
         if camera != ctx.scene_manager.scene.camera {
             ctx.scene_manager.scene.camera = camera;
@@ -507,6 +1560,1824 @@ impl EguiRenderer {
         }
     }
 
+    /// Fit and 100% buttons for the central panel's image, which also zooms with command + scroll
+    /// and pans with a middle drag.
+    fn zoom_bar(ui: &mut egui::Ui, view: &mut ImageView) {
+        ui.horizontal(|ui| {
+            if ui.selectable_label(view.zoom.is_none(), "Fit").clicked() {
+                *view = ImageView::default();
+            }
+            if ui
+                .selectable_label(view.zoom == Some(1.0), "100%")
+                .clicked()
+            {
+                view.zoom = Some(1.0);
+                view.pan = egui::Vec2::ZERO;
+            }
+            match view.zoom {
+                Some(zoom) => ui.label(format!("{:.0}%", zoom * 100.0)),
+                None => ui.weak("Command + scroll to zoom"),
+            }
+            .on_hover_text("Command + scroll zooms about the pointer, a middle drag pans");
+        });
+    }
+    /// Name and notes of the selected entity. Neither reaches the GPU, so editing them leaves the
+    /// accumulation alone.
+    fn label_editor(ui: &mut egui::Ui, label: &mut EntityLabel, placeholder: String) {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut label.name).hint_text(placeholder));
+            ui.label("Name");
+        });
+        ui.add(
+            egui::TextEdit::multiline(&mut label.notes)
+                .hint_text("Notes")
+                .desired_rows(2),
+        );
+    }
+
+    /// Copies the selected entity over another's surface, see `scatter::scatter`. Returns whether
+    /// the scene changed.
+    fn scatter_tool(
+        ui: &mut egui::Ui,
+        scene_manager: &mut SceneManager,
+        settings: &mut ScatterSettings,
+    ) -> bool {
+        let mut scattered = false;
+        egui::CollapsingHeader::new("Scatter").show(ui, |ui| {
+            let scene = &mut scene_manager.scene;
+            let source = scene_manager.selected_entity;
+            let entities = (scene.spheres.len() + scene.meshes.len()) as i32;
+            egui::ComboBox::from_label("Over")
+                .selected_text(scene.entity_name(settings.target).unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    for entity in (0..entities).filter(|&entity| entity != source) {
+                        let name = scene.entity_name(entity).unwrap_or_default();
+                        ui.selectable_value(&mut settings.target, entity, name);
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut settings.count)
+                        .range(1..=MAX_MESHES as u32)
+                        .prefix("Count "),
+                );
+                egui::ComboBox::from_id_salt("scatter_mode")
+                    .selected_text(settings.mode.name())
+                    .show_ui(ui, |ui| {
+                        for mode in ScatterMode::ALL {
+                            ui.selectable_value(&mut settings.mode, mode, mode.name());
+                        }
+                    });
+                ui.add(egui::DragValue::new(&mut settings.seed).prefix("Seed "));
+            });
+            ui.add(egui::Slider::new(&mut settings.scale_jitter, 0.0..=0.9).text("Scale Jitter"));
+            ui.add(
+                egui::Slider::new(&mut settings.rotation_jitter, 0.0..=180.0)
+                    .text("Rotation Jitter"),
+            );
+            ui.checkbox(&mut settings.align_to_normal, "Align to Normal")
+                .on_hover_text("Stand copies along the surface rather than upright");
+            if ui
+                .add_enabled(
+                    settings.target != -1 && settings.target != source,
+                    egui::Button::new("Scatter"),
+                )
+                .clicked()
+            {
+                let spheres = scene.spheres.len() as i32;
+                match scatter::scatter(scene, source, settings) {
+                    Ok(added) => {
+                        log::info!("Scattered {} copies", added);
+                        // New spheres push the meshes along
+                        if source < spheres && settings.target >= spheres {
+                            settings.target += scene.spheres.len() as i32 - spheres;
+                        }
+                        scattered = added > 0;
+                    }
+                    Err(e) => log::warn!("Failed to scatter: {}", e),
+                }
+            }
+        });
+        scattered
+    }
+
+    /// Gives each selected entity a random colour and finish, see
+    /// `randomize::randomize_materials`. Returns whether any material changed.
+    fn randomize_tool(
+        ui: &mut egui::Ui,
+        scene_manager: &mut SceneManager,
+        settings: &mut RandomizeSettings,
+    ) -> bool {
+        let mut randomized = false;
+        egui::CollapsingHeader::new("Randomize Materials").show(ui, |ui| {
+            let range = |ui: &mut egui::Ui, enabled: bool, range: &mut [f32; 2]| {
+                for end in range {
+                    ui.add_enabled(
+                        enabled,
+                        egui::DragValue::new(end).speed(0.01).range(0.0..=1.0),
+                    );
+                }
+            };
+            ui.checkbox(&mut settings.color, "Color");
+            ui.horizontal(|ui| {
+                range(ui, settings.color, &mut settings.hue);
+                ui.label("Hue").on_hover_text(
+                    "Around the colour wheel from red, wrapping past red when the first is larger",
+                );
+            });
+            ui.horizontal(|ui| {
+                range(ui, settings.color, &mut settings.saturation);
+                ui.label("Saturation");
+            });
+            ui.horizontal(|ui| {
+                range(ui, settings.color, &mut settings.value);
+                ui.label("Brightness");
+            });
+            ui.horizontal(|ui| {
+                range(ui, settings.randomize_smoothness, &mut settings.smoothness);
+                ui.checkbox(&mut settings.randomize_smoothness, "Smoothness");
+            });
+            ui.horizontal(|ui| {
+                range(ui, settings.randomize_specular, &mut settings.specular);
+                ui.checkbox(&mut settings.randomize_specular, "Specular Probability");
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut settings.seed).prefix("Seed "));
+                if ui.button("New Seed").clicked() {
+                    settings.seed = rand::random();
+                }
+            });
+            if ui.button("Randomize").clicked() {
+                scene_manager.sync_selection();
+                let changed = randomize::randomize_materials(
+                    &mut scene_manager.scene,
+                    &scene_manager.selection,
+                    settings,
+                );
+                log::info!("Randomized {} materials", changed);
+                randomized = changed > 0;
+            }
+        });
+        randomized
+    }
+    /// Group edits for several selected entities. Moving applies the same offset to each, while
+    /// material properties show the last clicked entity's value and set it on all of them.
+    fn selection_inspector(ui: &mut egui::Ui, scene_manager: &mut SceneManager) {
+        let selection = scene_manager.selection.clone();
+        let scene = &mut scene_manager.scene;
+        ui.heading(format!("{} Entities", selection.len()));
+        let mut delta = Vec3::ZERO;
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut delta.x).speed(0.01));
+            ui.add(egui::DragValue::new(&mut delta.y).speed(0.01));
+            ui.add(egui::DragValue::new(&mut delta.z).speed(0.01));
+            ui.label("Move");
+        });
+        if delta != Vec3::ZERO {
+            for &entity in &selection {
+                scene.translate_entity(entity, delta);
+            }
+        }
+        let Some(mut shared) = scene
+            .entity_material_mut(scene_manager.selected_entity)
+            .copied()
+        else {
+            return;
+        };
+        let before = shared;
+        ui.horizontal(|ui| {
+            let mut color = [shared.color[0], shared.color[1], shared.color[2]];
+            if ui.color_edit_button_rgb(&mut color).changed() {
+                shared.color[..3].copy_from_slice(&color);
+            }
+            ui.label("Color");
+        });
+        let fields: [(&str, MaterialField); 5] = [
+            ("Emission Strength", |m| &mut m.emission_strength),
+            ("Specular Probability", |m| &mut m.specular),
+            ("Smoothness", |m| &mut m.smoothness),
+            ("Refractive Index", |m| &mut m.ior),
+            ("Film Thickness", |m| &mut m.film_thickness),
+        ];
+        let mut changed = vec![];
+        for (name, field) in fields {
+            let mixed = selection.iter().any(|&entity| {
+                scene
+                    .entity_material_mut(entity)
+                    .is_some_and(|m| *field(m) != *field(&mut shared))
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .add(egui::DragValue::new(field(&mut shared)).speed(0.01))
+                    .changed()
+                {
+                    changed.push(field);
+                }
+                ui.label(name);
+                if mixed {
+                    ui.weak("(mixed)");
+                }
+            });
+        }
+        if shared.color != before.color || !changed.is_empty() {
+            for &entity in &selection {
+                let Some(material) = scene.entity_material_mut(entity) else {
+                    continue;
+                };
+                if shared.color != before.color {
+                    material.color[..3].copy_from_slice(&shared.color[..3]);
+                }
+                for field in &changed {
+                    *field(material) = *field(&mut shared);
+                }
+            }
+        }
+    }
+
+    /// The material presets, each given to every selected entity at once. Returns whether one
+    /// was applied.
+    fn material_library(ui: &mut egui::Ui, scene_manager: &mut SceneManager) -> bool {
+        ui.heading("Material Library");
+        let selection = scene_manager.selection.clone();
+        if selection.is_empty() {
+            ui.weak("Select entities to give them a material");
+        }
+        let mut applied = false;
+        egui::Grid::new("material_library").show(ui, |ui| {
+            for preset in MaterialPreset::ALL {
+                ui.label(preset.name());
+                if ui
+                    .add_enabled(!selection.is_empty(), egui::Button::new("Apply"))
+                    .clicked()
+                {
+                    for &entity in &selection {
+                        scene_manager.scene.apply_preset(entity, preset);
+                    }
+                    applied = true;
+                }
+                ui.end_row();
+            }
+        });
+        applied
+    }
+
+    /// Shows `panel` where `layout` docks it, resizable along its inner edge. The size it was left
+    /// at is kept in the layout, which is saved with the rest of the settings.
+    fn docked_panel(
+        context: &Context,
+        panel: Panel,
+        layout: &mut PanelLayout,
+        add_contents: impl FnOnce(&mut egui::Ui),
+    ) {
+        // Each dock has its own id, egui would otherwise carry a bottom panel's width to the side
+        let id = egui::Id::new((panel.name(), layout.dock.name()));
+        let range = UiSettings::PANEL_SIZE_RANGE;
+        let size = match layout.dock {
+            Dock::Left | Dock::Right => {
+                let side = match layout.dock {
+                    Dock::Left => egui::panel::Side::Left,
+                    _ => egui::panel::Side::Right,
+                };
+                egui::SidePanel::new(side, id)
+                    .resizable(true)
+                    .default_width(layout.size)
+                    .width_range(range)
+                    .show(context, add_contents)
+                    .response
+                    .rect
+                    .width()
+            }
+            Dock::Bottom => egui::TopBottomPanel::bottom(id)
+                .resizable(true)
+                .default_height(layout.size)
+                .height_range(range)
+                .show(context, add_contents)
+                .response
+                .rect
+                .height(),
+            Dock::Floating => match egui::Window::new(panel.name())
+                .id(id)
+                .default_width(layout.size)
+                .vscroll(true)
+                .show(context, |ui| {
+                    // The window's default width is of its contents, not its frame
+                    let width = ui.available_width();
+                    add_contents(ui);
+                    width
+                })
+                .and_then(|window| window.inner)
+            {
+                Some(width) => width,
+                None => return,
+            },
+            Dock::Hidden => return,
+        };
+        layout.size = size.round();
+    }
+
+    fn panel_layout(ui: &mut egui::Ui, settings: &mut UiSettings) {
+        egui::Grid::new("panel_layout").show(ui, |ui| {
+            for panel in Panel::ALL {
+                let layout = &mut settings.panels[panel as usize];
+                ui.label(panel.name());
+                egui::ComboBox::from_id_salt(panel.name())
+                    .selected_text(layout.dock.name())
+                    .show_ui(ui, |ui| {
+                        for dock in Dock::ALL {
+                            if ui
+                                .selectable_label(layout.dock == dock, dock.name())
+                                .clicked()
+                                && layout.dock != dock
+                            {
+                                *layout = PanelLayout::docked(dock);
+                                settings.apply_requested = true;
+                            }
+                        }
+                    });
+                ui.end_row();
+            }
+        });
+        if ui.button("Reset Layout").clicked() {
+            settings.panels = UiSettings::default().panels;
+            settings.apply_requested = true;
+        }
+    }
+
+    /// Interface scale, theme and font size. Sliders only ask for the settings to be applied once
+    /// let go, as rescaling mid drag moves the slider out from under the cursor.
+    fn interface_settings(ui: &mut egui::Ui, settings: &mut UiSettings) {
+        let finished = |response: egui::Response| {
+            response.drag_stopped() || (response.changed() && !response.dragged())
+        };
+        let scale = ui.add(
+            egui::Slider::new(&mut settings.scale, UiSettings::SCALE_RANGE)
+                .step_by(0.05)
+                .suffix("×")
+                .text("Interface Scale"),
+        );
+        settings.apply_requested |= finished(scale);
+        let font_size = ui.add(
+            egui::Slider::new(&mut settings.font_size, UiSettings::FONT_SIZE_RANGE)
+                .step_by(0.5)
+                .text("Font Size"),
+        );
+        settings.apply_requested |= finished(font_size);
+        ui.horizontal(|ui| {
+            for theme in Theme::ALL {
+                if ui
+                    .selectable_value(&mut settings.theme, theme, theme.name())
+                    .clicked()
+                {
+                    settings.apply_requested = true;
+                }
+            }
+        });
+        if ui.button("Reset").clicked() {
+            *settings = UiSettings {
+                panels: settings.panels,
+                apply_requested: true,
+                ..Default::default()
+            };
+        }
+    }
+
+    fn command_palette(context: &Context, palette: &mut CommandPalette) {
+        let matches = palette.matches();
+        palette.selected = palette.selected.min(matches.len().saturating_sub(1));
+        let (up, down, enter, escape) = context.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+        if up {
+            palette.selected = palette.selected.saturating_sub(1);
+        }
+        if down && palette.selected + 1 < matches.len() {
+            palette.selected += 1;
+        }
+        let mut run = enter
+            .then(|| matches.get(palette.selected).copied())
+            .flatten();
+        egui::Window::new("Command Palette")
+            .title_bar(false)
+            .collapsible(false)
+            .resizable(false)
+            .fixed_size([320.0, 0.0])
+            .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 60.0))
+            .show(context, |ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut palette.query)
+                        .hint_text("Search actions...")
+                        .desired_width(f32::INFINITY),
+                );
+                response.request_focus();
+                if response.changed() {
+                    palette.selected = 0;
+                }
+                ui.separator();
+                if matches.is_empty() {
+                    ui.label("No matching actions");
+                }
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        for (i, action) in matches.iter().enumerate() {
+                            ui.horizontal(|ui| {
+                                let selected = i == palette.selected;
+                                let label = ui.selectable_label(selected, action.name());
+                                if selected && (up || down) {
+                                    label.scroll_to_me(None);
+                                }
+                                if label.clicked() {
+                                    run = Some(*action);
+                                }
+                                if let Some(shortcut) = action.shortcut() {
+                                    ui.with_layout(
+                                        egui::Layout::right_to_left(egui::Align::Center),
+                                        |ui| ui.weak(shortcut.label()),
+                                    );
+                                }
+                            });
+                        }
+                    });
+            });
+        if escape || run.is_some() {
+            palette.open = false;
+        }
+        if run.is_some() {
+            palette.run_requested = run;
+        }
+    }
+
+    fn texture_cache_window(&mut self, ctx: &mut UiContext) {
+        let scene_manager = &mut *ctx.scene_manager;
+        let mut textures: Vec<(String, i32, Arc<RgbaImage>)> = scene_manager
+            .loaded_textures
+            .iter()
+            .filter_map(|entry| {
+                let image = scene_manager.cpu_textures.get(entry.key())?.clone();
+                Some((entry.key().clone(), *entry.value(), image))
+            })
+            .collect();
+        textures.sort_by_key(|(_, index, _)| *index);
+        self.texture_previews
+            .retain(|path, _| textures.iter().any(|(p, _, _)| p == path));
+
+        let context = self.context().clone();
+        let total: usize = textures
+            .iter()
+            .map(|(_, _, image)| image.as_raw().len())
+            .sum();
+        let mut open = true;
+        egui::Window::new("Texture Cache")
+            .open(&mut open)
+            .default_width(360.0)
+            .show(&context, |ui| {
+                ui.label(format!(
+                    "{} / {} textures, {:.1} MiB",
+                    textures.len(),
+                    MAX_TEXTURES,
+                    total as f32 / (1024.0 * 1024.0)
+                ));
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (path, index, image) in textures.iter() {
+                        let preview = match self.texture_previews.get(path) {
+                            Some((ptr, handle)) if *ptr == Arc::as_ptr(image) => handle.clone(),
+                            _ => {
+                                let handle = Self::texture_preview(&context, path, image);
+                                self.texture_previews
+                                    .insert(path.clone(), (Arc::as_ptr(image), handle.clone()));
+                                handle
+                            }
+                        };
+                        let scene = &scene_manager.scene;
+                        let users: Vec<String> = scene
+                            .spheres
+                            .iter()
+                            .map(|s| s.material)
+                            .chain(scene.meshes.iter().map(|m| m.material))
+                            .enumerate()
+                            .filter(|(_, m)| {
+                                [m.diffuse_index, m.normal_index, m.orm_index].contains(index)
+                            })
+                            .filter_map(|(entity, _)| scene.entity_name(entity as i32))
+                            .collect();
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::Image::new(&preview)
+                                    .fit_to_exact_size(egui::vec2(64.0, 64.0)),
+                            );
+                            ui.vertical(|ui| {
+                                ui.label(path);
+                                ui.label(format!(
+                                    "#{}  {}x{}  {:.1} KiB",
+                                    index,
+                                    image.width(),
+                                    image.height(),
+                                    image.as_raw().len() as f32 / 1024.0
+                                ));
+                                if let Some(animation) = scene_manager.animated_textures.get(index)
+                                {
+                                    ui.weak(format!(
+                                        "Sequence of {} frames at {} fps",
+                                        animation.frames.len(),
+                                        animation.fps
+                                    ));
+                                }
+                                if users.is_empty() {
+                                    ui.weak("Unused by this scene");
+                                } else {
+                                    ui.label(users.join(", "));
+                                }
+                                if ui.small_button("Reload").clicked() {
+                                    scene_manager.texture_reload_requested = Some(path.clone());
+                                }
+                            });
+                        });
+                        ui.separator();
+                    }
+                });
+            });
+        ctx.tmp.show_textures = open;
+    }
+
+    /// Small copy of a texture for egui, flipped back since textures are stored mirrored for the shader.
+    fn texture_preview(context: &Context, path: &str, image: &RgbaImage) -> egui::TextureHandle {
+        let thumbnail =
+            image::imageops::flip_horizontal(&image::imageops::thumbnail(image, 64, 64));
+        context.load_texture(
+            format!("preview_{}", path),
+            egui::ColorImage::from_rgba_unmultiplied(
+                [thumbnail.width() as usize, thumbnail.height() as usize],
+                thumbnail.as_raw(),
+            ),
+            egui::TextureOptions::LINEAR,
+        )
+    }
+
+    /// Strip of snapshot thumbnails, with the two picked as A and B side by side below and the
+    /// settings that differ between them highlighted.
+    fn snapshot_window(&mut self, ctx: &mut UiContext, params: &mut Params, camera: &mut Camera) {
+        let context = self.context().clone();
+        let scene = ctx.scene_manager.selected_scene;
+        let snapshots = &mut *ctx.snapshots;
+        self.snapshot_thumbnails
+            .retain(|id, _| snapshots.get(*id).is_some());
+        self.snapshot_images
+            .retain(|id, _| snapshots.compare.contains(&Some(*id)));
+        let mut compare = snapshots.compare;
+        let (mut apply, mut export, mut remove) = (None, None, None);
+        let mut load_reference = false;
+        let (mut show_difference, mut heatmap_gain) =
+            (snapshots.show_difference, snapshots.heatmap_gain);
+        let mut open = true;
+        egui::Window::new("Snapshots")
+            .open(&mut open)
+            .default_width(560.0)
+            .show(&context, |ui| {
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Take Snapshot")
+                        .on_hover_text("Keep the current render and its settings for this session")
+                        .clicked()
+                    {
+                        snapshots.take_requested = true;
+                    }
+                    ui.label(format!(
+                        "{} snapshots, {:.1} MiB",
+                        snapshots.snapshots.len(),
+                        snapshots.memory() as f32 / (1024.0 * 1024.0)
+                    ));
+                });
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut snapshots.reference_path)
+                            .hint_text("reference.exr")
+                            .desired_width(240.0),
+                    );
+                    if ui
+                        .button("Load Reference")
+                        .on_hover_text(
+                            "Compare an image from another renderer or an earlier version \
+                             against the current render",
+                        )
+                        .clicked()
+                    {
+                        load_reference = true;
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::horizontal()
+                    .id_salt("snapshot_strip")
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            for snapshot in snapshots.snapshots.iter_mut() {
+                                let thumbnail = self
+                                    .snapshot_thumbnails
+                                    .entry(snapshot.id)
+                                    .or_insert_with(|| {
+                                        Self::snapshot_texture(&context, snapshot, true)
+                                    })
+                                    .clone();
+                                ui.vertical(|ui| {
+                                    ui.set_width(SNAPSHOT_THUMBNAIL_WIDTH);
+                                    ui.add(
+                                        egui::Image::new(&thumbnail)
+                                            .max_width(SNAPSHOT_THUMBNAIL_WIDTH),
+                                    );
+                                    ui.add(
+                                        egui::TextEdit::singleline(&mut snapshot.name)
+                                            .desired_width(SNAPSHOT_THUMBNAIL_WIDTH),
+                                    );
+                                    match snapshot.reference {
+                                        Some(_) => ui.weak("Reference"),
+                                        None => ui.weak(format!(
+                                            "{} spp, {:.1}s",
+                                            snapshot.samples(),
+                                            snapshot.render_time.as_secs_f32()
+                                        )),
+                                    };
+                                    ui.horizontal(|ui| {
+                                        for (slot, label) in ["A", "B"].into_iter().enumerate() {
+                                            let selected = compare[slot] == Some(snapshot.id);
+                                            if ui
+                                                .selectable_label(selected, label)
+                                                .on_hover_text("Compare side by side")
+                                                .clicked()
+                                            {
+                                                compare[slot] = (!selected).then_some(snapshot.id);
+                                            }
+                                        }
+                                        if ui
+                                            .add_enabled(
+                                                snapshot.scene == scene
+                                                    && snapshot.reference.is_none(),
+                                                egui::Button::new("Apply"),
+                                            )
+                                            .on_hover_text("Restore the camera and render settings")
+                                            .on_disabled_hover_text(
+                                                "Taken in another scene, or loaded from a file",
+                                            )
+                                            .clicked()
+                                        {
+                                            apply = Some(snapshot.id);
+                                        }
+                                    });
+                                    ui.horizontal(|ui| {
+                                        if ui.button("PNG").clicked() {
+                                            export = Some((snapshot.id, SnapshotFormat::Png));
+                                        }
+                                        if ui.button("EXR").clicked() {
+                                            export = Some((snapshot.id, SnapshotFormat::Exr));
+                                        }
+                                        if ui.button("Delete").clicked() {
+                                            remove = Some(snapshot.id);
+                                        }
+                                    });
+                                });
+                            }
+                        });
+                    });
+                let pair = compare.map(|id| id.and_then(|id| snapshots.get(id)));
+                let [Some(a), Some(b)] = pair else {
+                    if !snapshots.snapshots.is_empty() {
+                        ui.weak("Pick an A and a B snapshot to compare them");
+                    }
+                    return;
+                };
+                ui.separator();
+                let width = (ui.available_width() - ui.spacing().item_spacing.x) / 2.0;
+                ui.horizontal(|ui| {
+                    for snapshot in [a, b] {
+                        let image = self
+                            .snapshot_images
+                            .entry(snapshot.id)
+                            .or_insert_with(|| Self::snapshot_texture(&context, snapshot, false))
+                            .clone();
+                        ui.add(egui::Image::new(&image).max_width(width));
+                    }
+                });
+                let key = (a.id, b.id);
+                let view = match &mut self.snapshot_difference {
+                    Some(view) if view.key == key => view,
+                    _ => self.snapshot_difference.insert(DifferenceView {
+                        key,
+                        difference: compare::difference(a, b),
+                        heatmap: None,
+                    }),
+                };
+                let difference = &view.difference;
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "RMSE {:.5}   SSIM {:.4}",
+                        difference.rmse, difference.ssim
+                    ))
+                    .on_hover_text(
+                        "RMSE of the linear radiance, SSIM of the displayed luminance, \
+                         1 when B matches A",
+                    );
+                    if difference.resized {
+                        ui.colored_label(ui.visuals().warn_fg_color, "B resized to A");
+                    }
+                    ui.checkbox(&mut show_difference, "Heatmap");
+                    if show_difference {
+                        ui.add(
+                            egui::Slider::new(&mut heatmap_gain, 1.0..=64.0)
+                                .logarithmic(true)
+                                .text("Gain"),
+                        );
+                    }
+                });
+                if show_difference {
+                    let gain = heatmap_gain.to_bits();
+                    if view
+                        .heatmap
+                        .as_ref()
+                        .is_none_or(|(drawn, _)| *drawn != gain)
+                    {
+                        let heatmap = difference.heatmap(heatmap_gain);
+                        view.heatmap = Some((
+                            gain,
+                            context.load_texture(
+                                "snapshot_difference",
+                                egui::ColorImage::from_rgba_unmultiplied(
+                                    [heatmap.width() as usize, heatmap.height() as usize],
+                                    heatmap.as_raw(),
+                                ),
+                                egui::TextureOptions::LINEAR,
+                            ),
+                        ));
+                    }
+                    if let Some((_, heatmap)) = &view.heatmap {
+                        ui.add(egui::Image::new(heatmap).max_width(width));
+                    }
+                }
+                egui::Grid::new("snapshot_settings")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("");
+                        ui.strong(&a.name);
+                        ui.strong(&b.name);
+                        ui.end_row();
+                        let warn = ui.visuals().warn_fg_color;
+                        for ((label, a), (_, b)) in a.settings().into_iter().zip(b.settings()) {
+                            ui.label(label);
+                            if a == b {
+                                ui.label(a);
+                                ui.label(b);
+                            } else {
+                                ui.colored_label(warn, a);
+                                ui.colored_label(warn, b);
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+        snapshots.open = open;
+        snapshots.compare = compare;
+        snapshots.show_difference = show_difference;
+        snapshots.heatmap_gain = heatmap_gain;
+        if load_reference
+            && let path = PathBuf::from(snapshots.reference_path.trim())
+            && let Err(e) = snapshots.load_reference(&path, scene, *params, *camera)
+        {
+            log::error!("Failed to load reference image: {}", e);
+        }
+        if let Some(snapshot) = apply.and_then(|id| snapshots.get(id)) {
+            *params = snapshot.params;
+            params.reset_frame();
+            // Keep the viewport's aspect ratio, as when switching views
+            let aspect = camera.aspect;
+            *camera = snapshot.camera;
+            camera.aspect = aspect;
+        }
+        if let Some((id, format)) = export
+            && let Some(snapshot) = snapshots.get(id)
+            && let Err(e) = snapshot.export(format)
+        {
+            log::error!("Failed to export snapshot: {}", e);
+        }
+        if let Some(id) = remove {
+            snapshots.remove(id);
+        }
+    }
+
+    /// Opaque copy of a snapshot for egui, shrunk to a thumbnail for the gallery strip.
+    fn snapshot_texture(
+        context: &Context,
+        snapshot: &Snapshot,
+        thumbnail: bool,
+    ) -> egui::TextureHandle {
+        let mut image = snapshot.ldr();
+        if thumbnail {
+            let width = (2.0 * SNAPSHOT_THUMBNAIL_WIDTH) as u32;
+            let height = (width * image.height() / image.width()).max(1);
+            image = image::imageops::thumbnail(&image, width, height);
+        }
+        let rgb = image::DynamicImage::ImageRgba8(image).to_rgb8();
+        context.load_texture(
+            format!("snapshot_{}_{}", snapshot.id, thumbnail),
+            egui::ColorImage::from_rgb([rgb.width() as usize, rgb.height() as usize], rgb.as_raw()),
+            egui::TextureOptions::LINEAR,
+        )
+    }
+
+    fn render_queue(ui: &mut egui::Ui, render_queue: &mut RenderQueue) {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut render_queue.job_name).desired_width(100.0));
+            ui.label("Job Name");
+        });
+        ui.add(
+            egui::Slider::new(&mut render_queue.samples, 1..=65536)
+                .logarithmic(true)
+                .text("Samples"),
+        );
+        if ui
+            .button("Add Current View")
+            .on_hover_text("Queues the scene, camera, resolution and render settings")
+            .clicked()
+        {
+            render_queue.add_requested = true;
+        }
+        let mut removed = None;
+        for (i, job) in render_queue.jobs.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} ({:?}, {}x{}, {} spp)",
+                    job.name, job.scene, job.params.width, job.params.height, job.samples
+                ));
+                if ui.small_button("x").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            render_queue.jobs.remove(i);
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked()
+                && let Err(e) = queue::save_jobs(Path::new(queue::QUEUE_FILE), &render_queue.jobs)
+            {
+                log::error!("Failed to save render queue: {}", e);
+            }
+            if ui.button("Load").clicked() {
+                match queue::load_jobs(Path::new(queue::QUEUE_FILE)) {
+                    Ok(jobs) => render_queue.jobs.extend(jobs),
+                    Err(e) => log::error!("Failed to load render queue: {}", e),
+                }
+            }
+        })
+        .response
+        .on_hover_text(queue::QUEUE_FILE);
+        if let Some((done, total, current)) = render_queue.progress() {
+            ui.add(
+                egui::ProgressBar::new(done as f32 / total.max(1) as f32).text(format!(
+                    "{} ({}/{})",
+                    current,
+                    done + 1,
+                    total
+                )),
+            );
+        } else if ui
+            .add_enabled(
+                !render_queue.jobs.is_empty(),
+                egui::Button::new("Start Queue"),
+            )
+            .on_hover_text("Renders every job in turn on a separate device, saving to renders/")
+            .clicked()
+        {
+            render_queue.start_requested = true;
+        }
+    }
+
+    fn timeline_panel(ui: &mut egui::Ui, scene_manager: &mut SceneManager) {
+        let selected_entity = scene_manager.selected_entity;
+        let scene = &mut scene_manager.scene;
+        let sphere_count = scene.spheres.len();
+        let entity_names: Vec<String> = (0..sphere_count + scene.meshes.len())
+            .filter_map(|entity| scene.entity_name(entity as i32))
+            .collect();
+        let timeline = &mut scene.timeline;
+        ui.horizontal(|ui| {
+            ui.heading("Timeline");
+            if ui
+                .button(if timeline.playing { "Pause" } else { "Play" })
+                .clicked()
+            {
+                timeline.playing = !timeline.playing;
+            }
+            let mut frame = timeline.frame;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut frame)
+                        .range(timeline.start..=timeline.end)
+                        .prefix("Frame "),
+                )
+                .changed()
+            {
+                timeline.set_frame(frame);
+            }
+            ui.add(egui::DragValue::new(&mut timeline.start).prefix("Start "));
+            ui.add(egui::DragValue::new(&mut timeline.end).prefix("End "));
+            timeline.end = timeline.end.max(timeline.start);
+            ui.add(
+                egui::DragValue::new(&mut timeline.fps)
+                    .range(1.0..=120.0)
+                    .suffix(" fps"),
+            );
+            egui::ComboBox::from_id_salt("Key Interpolation")
+                .selected_text(format!("{:?}", timeline.interpolation))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut timeline.interpolation,
+                        Interpolation::Linear,
+                        "Linear",
+                    );
+                    ui.selectable_value(
+                        &mut timeline.interpolation,
+                        Interpolation::Bezier,
+                        "Bezier",
+                    );
+                })
+                .response
+                .on_hover_text("Interpolation of new keys");
+        });
+        ui.horizontal(|ui| {
+            let shake = &mut timeline.shake;
+            let before = *shake;
+            ui.checkbox(&mut shake.enabled, "Camera Shake")
+                .on_hover_text("Handheld camera noise while playing or rendering a sequence");
+            ui.add_enabled_ui(shake.enabled, |ui| {
+                ui.add(
+                    egui::DragValue::new(&mut shake.rotation)
+                        .range(0.0..=10.0)
+                        .speed(0.01)
+                        .prefix("Rotation ")
+                        .suffix("°"),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut shake.translation)
+                        .range(0.0..=1.0)
+                        .speed(0.001)
+                        .prefix("Translation "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut shake.frequency)
+                        .range(0.01..=20.0)
+                        .speed(0.01)
+                        .prefix("Frequency ")
+                        .suffix(" Hz"),
+                );
+                ui.add(egui::DragValue::new(&mut shake.seed).prefix("Seed "));
+            });
+            if *shake != before {
+                timeline.dirty = true;
+            }
+        });
+
+        // Scrub strip with one row of keys per track
+        let row_height = 14.0;
+        let (rect, response) = ui.allocate_exact_size(
+            egui::Vec2::new(
+                ui.available_width(),
+                row_height * (timeline.tracks.len() + 1) as f32,
+            ),
+            egui::Sense::click_and_drag(),
+        );
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+        let length = (timeline.end - timeline.start).max(1) as f32;
+        let frame_to_x = |frame: u32| {
+            rect.left() + (frame.saturating_sub(timeline.start)) as f32 / length * rect.width()
+        };
+        let tick_color = ui.visuals().widgets.inactive.bg_stroke.color;
+        for frame in (timeline.start..=timeline.end).step_by(10) {
+            painter.vline(
+                frame_to_x(frame),
+                rect.top()..=rect.top() + row_height,
+                egui::Stroke::new(1.0, tick_color),
+            );
+        }
+        let text_color = ui.visuals().text_color();
+        for (row, track) in timeline.tracks.iter().enumerate() {
+            let y = rect.top() + row_height * (row as f32 + 1.5);
+            let target = match track.target {
+                AnimTarget::Camera => "Camera".to_owned(),
+                AnimTarget::Sky => "Sky".to_owned(),
+                AnimTarget::Entity(i) => entity_names
+                    .get(i)
+                    .cloned()
+                    .unwrap_or_else(|| format!("Missing {}", i)),
+            };
+            painter.text(
+                egui::pos2(rect.left() + 4.0, y),
+                egui::Align2::LEFT_CENTER,
+                format!("{} {:?}", target, track.property),
+                egui::FontId::proportional(10.0),
+                text_color.gamma_multiply(0.6),
+            );
+            for key in track.keys.iter() {
+                let color = match key.interpolation {
+                    Interpolation::Linear => egui::Color32::from_rgb(120, 180, 255),
+                    Interpolation::Bezier => egui::Color32::from_rgb(255, 200, 80),
+                };
+                let centre = egui::pos2(frame_to_x(key.frame), y);
+                painter.add(egui::Shape::convex_polygon(
+                    vec![
+                        centre + egui::vec2(0.0, -4.0),
+                        centre + egui::vec2(4.0, 0.0),
+                        centre + egui::vec2(0.0, 4.0),
+                        centre + egui::vec2(-4.0, 0.0),
+                    ],
+                    color,
+                    egui::Stroke::NONE,
+                ));
+            }
+        }
+        painter.vline(
+            frame_to_x(timeline.frame),
+            rect.y_range(),
+            egui::Stroke::new(1.5, egui::Color32::from_rgb(255, 160, 0)),
+        );
+        if timeline.sequence.is_none()
+            && (response.dragged() || response.clicked())
+            && let Some(pos) = response.interact_pointer_pos()
+        {
+            let t = ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0);
+            timeline.set_frame(timeline.start + (t * length).round() as u32);
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Key Camera").clicked() {
+                for property in AnimProperty::CAMERA {
+                    scene.key_property(AnimTarget::Camera, property);
+                }
+            }
+            if scene.sky.enabled && ui.button("Key Sky").clicked() {
+                for property in AnimProperty::SKY {
+                    scene.key_property(AnimTarget::Sky, property);
+                }
+            }
+            if selected_entity != -1 {
+                let target = AnimTarget::Entity(selected_entity as usize);
+                let properties: &[AnimProperty] = if (selected_entity as usize) < sphere_count {
+                    &AnimProperty::SPHERE
+                } else {
+                    &AnimProperty::MESH
+                };
+                if !properties.contains(&scene.timeline.key_property) {
+                    scene.timeline.key_property = properties[0];
+                }
+                egui::ComboBox::from_id_salt("Key Property")
+                    .selected_text(format!("{:?}", scene.timeline.key_property))
+                    .show_ui(ui, |ui| {
+                        for &property in properties {
+                            ui.selectable_value(
+                                &mut scene.timeline.key_property,
+                                property,
+                                format!("{:?}", property),
+                            );
+                        }
+                    });
+                if ui.button("Key Selected").clicked() {
+                    scene.key_property(target, scene.timeline.key_property);
+                }
+                if ui.button("Key All").clicked() {
+                    for &property in properties {
+                        scene.key_property(target, property);
+                    }
+                }
+            }
+            let timeline = &mut scene.timeline;
+            if timeline.has_key_at_frame() {
+                if ui.button("Delete Keys").clicked() {
+                    timeline.remove_keys_at_frame();
+                    timeline.dirty = true;
+                }
+                ui.menu_button("Key Interpolation", |ui| {
+                    for interpolation in [Interpolation::Linear, Interpolation::Bezier] {
+                        if ui.button(format!("{:?}", interpolation)).clicked() {
+                            timeline.set_interpolation_at_frame(interpolation);
+                            timeline.dirty = true;
+                        }
+                    }
+                });
+            }
+        });
+        let timeline = &mut scene.timeline;
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut timeline.samples_per_frame)
+                    .range(1..=65536)
+                    .prefix("Samples "),
+            );
+            if let Some(progress) = timeline.sequence_progress() {
+                ui.add(egui::ProgressBar::new(progress).show_percentage());
+                if ui.button("Stop").clicked() {
+                    timeline.sequence = None;
+                }
+            } else if ui
+                .add_enabled(
+                    !timeline.tracks.is_empty(),
+                    egui::Button::new("Render Sequence"),
+                )
+                .on_hover_text("Saves every frame to renders/")
+                .clicked()
+            {
+                timeline.sequence_requested = Some((timeline.start, timeline.end));
+            } else if ui
+                .add_enabled(
+                    !timeline.tracks.is_empty(),
+                    egui::Button::new("Render Frame"),
+                )
+                .on_hover_text(
+                    "Renders only the current frame of the sequence again, replacing it. With \
+                     the Per Frame seed its noise matches the first render",
+                )
+                .clicked()
+            {
+                timeline.sequence_requested = Some((timeline.frame, timeline.frame));
+            }
+        });
+    }
+    /// Audio source, band meters and the properties bound to bands, see `AudioInput`.
+    fn audio_panel(ui: &mut egui::Ui, audio: &mut AudioInput, scene_manager: &mut SceneManager) {
+        egui::CollapsingHeader::new("Audio Reactive").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(cfg!(feature = "audio"), egui::Button::new("Live Input"))
+                    .on_hover_text(
+                        "Listen to the default input device, a loopback or monitor device \
+                         follows whatever is playing",
+                    )
+                    .on_disabled_hover_text("Built without the audio feature")
+                    .clicked()
+                {
+                    #[cfg(feature = "audio")]
+                    audio.open_live();
+                }
+                ui.add(
+                    egui::TextEdit::singleline(&mut audio.clip_path)
+                        .hint_text("music.wav")
+                        .desired_width(160.0),
+                );
+                if ui
+                    .button("Load Clip")
+                    .on_hover_text("Follows the timeline, so sequence renders line up with it")
+                    .clicked()
+                {
+                    audio.open_clip();
+                }
+                if audio.source.is_some() && ui.button("Stop").clicked() {
+                    audio.close();
+                }
+            });
+            match &audio.source {
+                None => ui.label("No audio source"),
+                #[cfg(feature = "audio")]
+                Some(AudioSource::Live(input)) => {
+                    ui.label(format!("Listening to {}", input.device))
+                }
+                Some(AudioSource::Clip(clip)) => {
+                    ui.label(format!("{} ({:.1}s)", clip.path.display(), clip.duration()))
+                }
+            };
+            if let Some(error) = &audio.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::Slider::new(&mut audio.gain, 0.1..=4.0)
+                        .logarithmic(true)
+                        .text("Gain"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut audio.release, 0.02..=2.0)
+                        .logarithmic(true)
+                        .text("Release (s)"),
+                );
+            });
+            ui.horizontal_wrapped(|ui| {
+                for (name, level) in BAND_NAMES.iter().zip(audio.levels) {
+                    ui.add(
+                        egui::ProgressBar::new(level)
+                            .desired_width(64.0)
+                            .text(*name),
+                    );
+                }
+            });
+
+            let selected = scene_manager.selected_entity;
+            let scene = &mut scene_manager.scene;
+            if selected != -1 {
+                let target = AnimTarget::Entity(selected as usize);
+                let properties: &[AnimProperty] = if (selected as usize) < scene.spheres.len() {
+                    &AnimProperty::SPHERE
+                } else {
+                    &AnimProperty::MESH
+                };
+                let bindable: Vec<AnimProperty> = AnimProperty::AUDIO
+                    .into_iter()
+                    .filter(|p| properties.contains(p))
+                    .collect();
+                if !bindable.contains(&audio.bind_property) {
+                    audio.bind_property = bindable[0];
+                }
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_salt("Audio Property")
+                        .selected_text(format!("{:?}", audio.bind_property))
+                        .show_ui(ui, |ui| {
+                            for property in bindable {
+                                ui.selectable_value(
+                                    &mut audio.bind_property,
+                                    property,
+                                    format!("{:?}", property),
+                                );
+                            }
+                        });
+                    let property = audio.bind_property;
+                    if ui.button("Bind Selected").clicked()
+                        && let Some(current) = scene.read_property(target, property)
+                    {
+                        let bindings = &mut scene.timeline.audio;
+                        bindings.retain(|b| b.target != target || b.property != property);
+                        // Bass is what most music is felt through
+                        bindings.push(AudioBinding::new(1, target, property, current));
+                    }
+                });
+            }
+
+            let names: Vec<String> = scene
+                .timeline
+                .audio
+                .iter()
+                .map(|binding| match binding.target {
+                    AnimTarget::Entity(entity) => scene
+                        .entity_name(entity as i32)
+                        .unwrap_or_else(|| format!("Missing {}", entity)),
+                    target => format!("{:?}", target),
+                })
+                .collect();
+            let before = scene.timeline.audio.clone();
+            let mut remove = None;
+            for (i, (binding, name)) in scene.timeline.audio.iter_mut().zip(names).enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} {:?}", name, binding.property));
+                    egui::ComboBox::from_id_salt(("Audio Band", i))
+                        .selected_text(BAND_NAMES[binding.band.min(BAND_NAMES.len() - 1)])
+                        .show_ui(ui, |ui| {
+                            for (band, name) in BAND_NAMES.iter().enumerate() {
+                                ui.selectable_value(&mut binding.band, band, *name);
+                            }
+                        });
+                    ui.label("Quiet");
+                    Self::audio_value(ui, binding.property, &mut binding.quiet);
+                    ui.label("Loud");
+                    Self::audio_value(ui, binding.property, &mut binding.loud);
+                    if ui.button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                scene.timeline.audio.remove(i);
+            }
+            if scene.timeline.audio != before {
+                scene.timeline.dirty = true;
+            }
+        });
+    }
+    fn audio_value(ui: &mut egui::Ui, property: AnimProperty, value: &mut AnimValue) {
+        match property {
+            AnimProperty::Color | AnimProperty::EmissionColor => {
+                ui.color_edit_button_rgba_unmultiplied(value);
+            }
+            AnimProperty::Scale => {
+                for v in value.iter_mut().take(3) {
+                    ui.add(egui::DragValue::new(v).speed(0.01));
+                }
+            }
+            _ => {
+                ui.add(egui::DragValue::new(&mut value[0]).speed(0.01));
+            }
+        }
+    }
+    /// Geometry problems found when the scene's meshes were loaded, clicking one selects the mesh.
+    fn mesh_diagnostics(ui: &mut egui::Ui, scene_manager: &mut SceneManager) {
+        let scene = &scene_manager.scene;
+        egui::CollapsingHeader::new(format!("Diagnostics ({})", scene.diagnostics.len()))
+            .id_salt("Mesh Diagnostics")
+            .show(ui, |ui| {
+                if scene.diagnostics.is_empty() {
+                    ui.label("No problems found");
+                    return;
+                }
+                ui.label(
+                    "Check flagged meshes with the Geometric Normals and Backfaces debug modes",
+                )
+                .on_hover_text(
+                    "Inverted faces show red in Backfaces and differ between the two normal modes",
+                );
+                for (label, report) in scene.diagnostics.iter() {
+                    let mut problems = vec![];
+                    if report.inverted_triangles > 0 {
+                        problems.push(format!("{} inverted", report.inverted_triangles));
+                    }
+                    if report.zero_area_triangles > 0 {
+                        problems.push(format!("{} zero area", report.zero_area_triangles));
+                    }
+                    if report.non_manifold_edges > 0 {
+                        problems.push(format!("{} non-manifold edges", report.non_manifold_edges));
+                    }
+                    if report.missing_uvs {
+                        problems.push("no UVs".to_owned());
+                    }
+                    let response = ui
+                        .selectable_label(false, label)
+                        .on_hover_text(format!("{} triangles", report.triangles));
+                    ui.label(problems.join(", "));
+                    if response.clicked()
+                        && let Some(i) = scene
+                            .meshes
+                            .iter()
+                            .position(|mesh| mesh.label.as_ref() == Some(label))
+                    {
+                        scene_manager.selected_entity = (scene.spheres.len() + i) as i32;
+                    }
+                }
+            });
+    }
+    /// Time of day settings for the procedural sky, returns whether any changed.
+    fn sky(ui: &mut egui::Ui, sky: &mut Sky) -> bool {
+        let before = *sky;
+        ui.checkbox(&mut sky.enabled, "Time of Day")
+            .on_hover_text("Place the sun from the time, date and latitude");
+        if sky.enabled {
+            ui.add(
+                egui::Slider::new(&mut sky.time_of_day, 0.0..=24.0)
+                    .custom_formatter(|v, _| {
+                        format!("{:02}:{:02}", v as u32, (v.fract() * 60.0) as u32)
+                    })
+                    .text("Time"),
+            );
+            ui.add(egui::Slider::new(&mut sky.day_of_year, 1.0..=365.0).text("Day of Year"));
+            ui.add(
+                egui::Slider::new(&mut sky.latitude, -90.0..=90.0)
+                    .suffix("°")
+                    .text("Latitude"),
+            );
+            ui.add(
+                egui::Slider::new(&mut sky.north, 0.0..=360.0)
+                    .suffix("°")
+                    .text("North"),
+            )
+            .on_hover_text("Turns the compass around the scene");
+            ui.add(
+                egui::Slider::new(&mut sky.sun_intensity, 0.0..=100.0)
+                    .logarithmic(true)
+                    .text("Sun Intensity"),
+            );
+        }
+        *sky != before
+    }
+    /// Height fog settings, returns whether any changed.
+    fn height_fog(ui: &mut egui::Ui, fog: &mut HeightFog) -> bool {
+        let before = *fog;
+        ui.checkbox(&mut fog.enabled, "Height Fog");
+        if fog.enabled {
+            ui.add(
+                egui::Slider::new(&mut fog.density, 0.0001..=1.0)
+                    .logarithmic(true)
+                    .text("Density"),
+            );
+            ui.add(
+                egui::Slider::new(&mut fog.height_falloff, 0.0..=5.0)
+                    .text("Height Falloff")
+                    .logarithmic(true),
+            )
+            .on_hover_text("Zero gives an even fog at every height");
+            ui.add(
+                egui::DragValue::new(&mut fog.base_height)
+                    .speed(0.05)
+                    .prefix("Base Height: "),
+            );
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut fog.sky_color, "Sky Colour")
+                    .on_hover_text("Use the sky's horizon colour, needs the skybox");
+                if !fog.sky_color {
+                    ui.color_edit_button_rgb(&mut fog.color);
+                }
+            });
+        }
+        *fog != before
+    }
+    /// Named viewpoint selector, returns whether the camera jumped to another view.
+    fn camera_views(ui: &mut egui::Ui, scene: &mut Scene, camera: &mut Camera) -> bool {
+        let mut selected = scene.active_view;
+        egui::ComboBox::from_label("View")
+            .selected_text(
+                scene
+                    .views
+                    .get(selected)
+                    .map_or("", |(name, _)| name.as_str()),
+            )
+            .show_ui(ui, |ui| {
+                for (i, (name, _)) in scene.views.iter().enumerate() {
+                    ui.selectable_value(&mut selected, i, name);
+                }
+            });
+        let switched = selected != scene.active_view;
+        if switched {
+            scene.select_view(selected);
+            *camera = scene.camera;
+        }
+        ui.horizontal(|ui| {
+            if ui
+                .button("Save View")
+                .on_hover_text("Store the current camera in the selected view")
+                .clicked()
+                && let Some((_, view)) = scene.views.get_mut(scene.active_view)
+            {
+                *view = *camera;
+            }
+            if ui.button("Add View").clicked() {
+                scene
+                    .views
+                    .push((format!("View {}", scene.views.len()), *camera));
+                scene.active_view = scene.views.len() - 1;
+            }
+        });
+        switched
+    }
+    /// Near and far clipping of camera rays, with a strip showing the view depths each entity
+    /// spans on a log scale against the range that is kept.
+    fn clip_planes(ui: &mut egui::Ui, scene: &Scene, camera: &mut Camera) {
+        const MIN_DEPTH: f32 = 0.01;
+        ui.checkbox(&mut camera.clip, "Clip")
+            .on_hover_text("Start camera rays at the near plane and stop them at the far plane");
+        if !camera.clip {
+            return;
+        }
+        ui.add(
+            egui::Slider::new(&mut camera.near, 0.0..=camera.far)
+                .logarithmic(true)
+                .text("Near"),
+        );
+        ui.add(
+            egui::Slider::new(&mut camera.far, camera.near..=100000.0)
+                .logarithmic(true)
+                .text("Far"),
+        );
+        let forward = camera.transform.rot * Vec3::Z;
+        let spans: Vec<(f32, f32)> = (0..(scene.spheres.len() + scene.meshes.len()) as i32)
+            .filter_map(|entity| scene.entity_bounds(entity))
+            .map(|bounds| {
+                (0..8)
+                    .map(|corner| {
+                        let pick = |bit: i32, axis: usize| match corner & bit != 0 {
+                            true => bounds.max[axis],
+                            false => bounds.min[axis],
+                        };
+                        let p = Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2));
+                        (p - camera.transform.pos).dot(forward)
+                    })
+                    .fold((f32::MAX, f32::MIN), |(lo, hi), d| (lo.min(d), hi.max(d)))
+            })
+            .filter(|&(_, far)| far > 0.0)
+            .collect();
+        let deepest = spans
+            .iter()
+            .map(|&(_, far)| far)
+            .fold(camera.far, f32::max)
+            .max(1.0)
+            * 1.5;
+
+        let (rect, _) = ui.allocate_exact_size(
+            egui::Vec2::new(ui.available_width(), 18.0),
+            egui::Sense::hover(),
+        );
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+        let x = |depth: f32| {
+            let t = (depth.max(MIN_DEPTH) / MIN_DEPTH).ln() / (deepest / MIN_DEPTH).ln();
+            rect.left() + t.clamp(0.0, 1.0) * rect.width()
+        };
+        painter.rect_filled(
+            egui::Rect::from_x_y_ranges(x(camera.near)..=x(camera.far), rect.y_range()),
+            2.0,
+            ui.visuals().selection.bg_fill.gamma_multiply(0.5),
+        );
+        let entity_color = ui.visuals().text_color().gamma_multiply(0.35);
+        for &(near, far) in &spans {
+            painter.rect_filled(
+                egui::Rect::from_x_y_ranges(
+                    x(near)..=x(far).max(x(near) + 1.0),
+                    rect.shrink(4.0).y_range(),
+                ),
+                0.0,
+                entity_color,
+            );
+        }
+        let clipped = spans
+            .iter()
+            .filter(|&&(near, far)| far < camera.near || near > camera.far)
+            .count();
+        ui.label(format!(
+            "{} of {} entities in front of the camera are clipped entirely",
+            clipped,
+            spans.len()
+        ));
+    }
+    /// Emission strength of the selected entity in a physical unit, `area` is only needed for lumens.
+    fn light_units(
+        ui: &mut egui::Ui,
+        material: &mut MaterialUniform,
+        area: f32,
+        unit: &mut LightUnit,
+    ) {
+        ui.horizontal(|ui| {
+            let mut value =
+                unit.strength_in(material.emission_strength, material.emission_color, area);
+            if ui
+                .add(egui::DragValue::new(&mut value).speed(1.0))
+                .changed()
+            {
+                material.emission_strength =
+                    unit.to_strength(value.max(0.0), material.emission_color, area);
+            }
+            egui::ComboBox::from_id_salt("Light Unit")
+                .selected_text(unit.name())
+                .show_ui(ui, |ui| {
+                    for option in LightUnit::ALL {
+                        ui.selectable_value(unit, option, option.name());
+                    }
+                });
+        });
+    }
+    /// Sets an emissive colour from a colour temperature, returns whether the colour changed.
+    fn color_temperature(ui: &mut egui::Ui, color: &mut [f32; 4], kelvin: &mut f32) -> bool {
+        let mut changed = ui
+            .add(
+                egui::Slider::new(kelvin, 1000.0..=12000.0)
+                    .step_by(100.0)
+                    .text("Temperature (K)"),
+            )
+            .changed();
+        ui.horizontal_wrapped(|ui| {
+            for (name, preset) in TEMPERATURE_PRESETS {
+                if ui
+                    .small_button(name)
+                    .on_hover_text(format!("{}K", preset))
+                    .clicked()
+                {
+                    *kelvin = preset;
+                    changed = true;
+                }
+            }
+        });
+        if changed {
+            let rgb = kelvin_to_rgb(*kelvin);
+            color[..3].copy_from_slice(&rgb[..3]);
+        }
+        changed
+    }
+    /// Visibility options of an emissive material, nothing for materials that don't emit.
+    fn light_flags(ui: &mut egui::Ui, material: &mut MaterialUniform) {
+        if material.emission_strength <= 0.0 {
+            return;
+        }
+        ui.horizontal_wrapped(|ui| {
+            for flag in LightFlag::ALL {
+                let mut set = material.light_flags & flag as u32 != 0;
+                if ui.checkbox(&mut set, flag.name()).changed() {
+                    material.light_flags ^= flag as u32;
+                }
+            }
+        });
+    }
+    fn ray_visibility(ui: &mut egui::Ui, material: &mut MaterialUniform) {
+        ui.horizontal_wrapped(|ui| {
+            for flag in RayVisibility::ALL {
+                let mut set = material.visibility & flag as u32 != 0;
+                if ui.checkbox(&mut set, flag.name()).changed() {
+                    material.visibility ^= flag as u32;
+                }
+            }
+        });
+    }
+    /// Channel routing of an ORM texture, nothing for materials without one.
+    fn orm_channels(ui: &mut egui::Ui, material: &mut MaterialUniform) {
+        if material.orm_index == -1 {
+            return;
+        }
+        let mut channels = OrmChannels::unpack(material.orm_channels);
+        let name = |c: Option<u8>| match c {
+            Some(0) => "R",
+            Some(1) => "G",
+            Some(2) => "B",
+            Some(3) => "A",
+            _ => "Off",
+        };
+        for (label, channel) in [
+            ("Occlusion", &mut channels.occlusion),
+            ("Roughness", &mut channels.roughness),
+            ("Metallic", &mut channels.metallic),
+        ] {
+            egui::ComboBox::from_label(label)
+                .selected_text(name(*channel))
+                .show_ui(ui, |ui| {
+                    for option in [None, Some(0), Some(1), Some(2), Some(3)] {
+                        ui.selectable_value(channel, option, name(option));
+                    }
+                });
+        }
+        material.orm_channels = channels.packed();
+    }
+    /// Settings for stopping after a while, with the time left or the samples it stopped at.
+    fn time_limit(ui: &mut egui::Ui, limit: &mut TimeLimit, timing: &FrameTiming, params: &Params) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut limit.enabled, "Time Limit")
+                .on_hover_text("Stop tracing once accumulation has run this long");
+            ui.add_enabled(
+                limit.enabled,
+                egui::DragValue::new(&mut limit.minutes)
+                    .range(0.1..=1440.0)
+                    .speed(0.5)
+                    .suffix(" min"),
+            );
+        });
+        if !limit.enabled {
+            return;
+        }
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut limit.save, "Save When Done");
+            ui.checkbox(&mut limit.exit, "Exit When Done");
+        });
+        if limit.stopped {
+            ui.label(format!("Stopped after {} samples", params.samples()));
+        } else {
+            let left = limit.remaining(timing.render_start.elapsed()).as_secs();
+            ui.label(format!("{}:{:02} left", left / 60, left % 60));
+        }
+    }
+    /// How the material's textures wrap outside the 0 to 1 UV range, nothing for untextured ones.
+    fn address_mode(ui: &mut egui::Ui, material: &mut MaterialUniform) {
+        let textures = [
+            material.diffuse_index,
+            material.normal_index,
+            material.orm_index,
+            material.blend_mask,
+        ];
+        if textures.iter().all(|&index| index == -1) {
+            return;
+        }
+        let mut mode = AddressMode::from_u32(material.address_mode);
+        egui::ComboBox::from_label("Texture Wrap")
+            .selected_text(mode.name())
+            .show_ui(ui, |ui| {
+                for option in AddressMode::ALL {
+                    ui.selectable_value(&mut mode, option, option.name());
+                }
+            });
+        material.address_mode = mode as u32;
+    }
+    /// Thickness and index of refraction of an interference coating, a thickness of 0 removes it.
+    fn thin_film(ui: &mut egui::Ui, material: &mut MaterialUniform) {
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut material.film_thickness)
+                    .speed(5.0)
+                    .range(0.0..=2000.0)
+                    .suffix(" nm"),
+            );
+            ui.add(
+                egui::DragValue::new(&mut material.film_ior)
+                    .speed(0.01)
+                    .range(1.0..=3.0),
+            );
+            ui.label("Thin Film");
+        });
+    }
+    /// Palette indices and mask settings of a blend material, nothing for other materials.
+    fn blend_material(ui: &mut egui::Ui, material: &mut MaterialUniform, palette: usize) {
+        if material.flag != MaterialFlag::BLEND as i32 {
+            return;
+        }
+        let last = palette.saturating_sub(1) as i32;
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut material.blend_a).range(0..=last));
+            ui.add(egui::DragValue::new(&mut material.blend_b).range(0..=last));
+            ui.label("Blend Materials");
+        });
+        ui.add(egui::Slider::new(&mut material.blend_coverage, 0.0..=1.0).text("Coverage"));
+        ui.add(egui::Slider::new(&mut material.blend_softness, 0.0..=0.5).text("Softness"));
+        if material.blend_mask == -1 {
+            ui.add(
+                egui::Slider::new(&mut material.blend_scale, 0.1..=64.0)
+                    .logarithmic(true)
+                    .text("Noise Scale"),
+            );
+        } else {
+            ui.label(format!("Mask Texture {}", material.blend_mask));
+        }
+    }
+    fn post_stack(ui: &mut egui::Ui, stack: &mut PostStack) {
+        let mut swap = None;
+        let count = stack.layers.len();
+        for (i, layer) in stack.layers.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut layer.enabled, layer.effect.name());
+                if ui.add_enabled(i > 0, egui::Button::new("⏶")).clicked() {
+                    swap = Some((i, i - 1));
+                }
+                if ui
+                    .add_enabled(i + 1 < count, egui::Button::new("⏷"))
+                    .clicked()
+                {
+                    swap = Some((i, i + 1));
+                }
+            });
+        }
+        if let Some((a, b)) = swap {
+            stack.layers.swap(a, b);
+        }
+        let enabled = |effect| stack.layers.iter().any(|l| l.effect == effect && l.enabled);
+        if enabled(PostEffect::Bloom) {
+            ui.add(
+                egui::Slider::new(&mut stack.bloom_threshold, 0.0..=10.0).text("Bloom Threshold"),
+            );
+            ui.add(
+                egui::Slider::new(&mut stack.bloom_intensity, 0.0..=4.0).text("Bloom Intensity"),
+            );
+            ui.add(egui::Slider::new(&mut stack.bloom_radius, 1.0..=128.0).text("Bloom Radius"));
+        }
+        if enabled(PostEffect::Vignette) {
+            ui.add(
+                egui::Slider::new(&mut stack.vignette_strength, 0.0..=1.0)
+                    .text("Vignette Strength"),
+            );
+            ui.add(
+                egui::Slider::new(&mut stack.vignette_radius, 0.0..=1.0).text("Vignette Radius"),
+            );
+        }
+        if enabled(PostEffect::ChromaticAberration) {
+            ui.add(
+                egui::Slider::new(&mut stack.chromatic_aberration, 0.0..=32.0)
+                    .text("Aberration (px)"),
+            );
+        }
+        if enabled(PostEffect::FilmGrain) {
+            ui.add(egui::Slider::new(&mut stack.grain_strength, 0.0..=1.0).text("Grain Strength"));
+        }
+    }
+    fn luminance_histogram(ui: &mut egui::Ui, auto_exposure: &AutoExposure) {
+        let (rect, response) = ui.allocate_exact_size(
+            egui::Vec2::new(ui.available_width(), 80.0),
+            egui::Sense::hover(),
+        );
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+        // Bin 0 holds black pixels, which are excluded from the average
+        let bins = &auto_exposure.histogram[1..];
+        let max = bins.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let bar_width = rect.width() / bins.len() as f32;
+        for (i, &count) in bins.iter().enumerate() {
+            let height = (count as f32 / max) * rect.height();
+            let x = rect.left() + i as f32 * bar_width;
+            painter.rect_filled(
+                egui::Rect::from_min_max(
+                    egui::pos2(x, rect.bottom() - height),
+                    egui::pos2(x + bar_width, rect.bottom()),
+                ),
+                0.0,
+                ui.visuals().widgets.inactive.fg_stroke.color,
+            );
+        }
+
+        let range = MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE;
+        let log_to_x = |log_luminance: f32| {
+            rect.left()
+                + ((log_luminance - MIN_LOG_LUMINANCE) / range).clamp(0.0, 1.0) * rect.width()
+        };
+        if auto_exposure.average_luminance > 0.0 {
+            let x = log_to_x(auto_exposure.average_luminance.log2());
+            painter.vline(
+                x,
+                rect.y_range(),
+                egui::Stroke::new(1.5, egui::Color32::from_rgb(255, 160, 0)),
+            );
+        }
+        if let Some(pos) = response.hover_pos() {
+            let log_luminance = MIN_LOG_LUMINANCE + (pos.x - rect.left()) / rect.width() * range;
+            response.on_hover_text(format!("Luminance: {:.4}", log_luminance.exp2()));
+        }
+    }
+
     pub fn handle_input(&mut self, window: &Window, event: &WindowEvent) -> bool {
         self.state.on_window_event(window, event).consumed
     }
@@ -0,0 +1,262 @@
+use std::{
+    mem,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use egui_wgpu::wgpu::{self, PipelineCompilationOptions, TextureView};
+
+pub const HISTOGRAM_BINS: usize = 64;
+pub const MIN_LOG_LUMINANCE: f32 = -10.0;
+pub const MAX_LOG_LUMINANCE: f32 = 6.0;
+const WORKGROUP_SIZE: (u32, u32) = (16, 16);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureSettings {
+    width: u32,
+    height: u32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    _p1: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ExposureUniform {
+    pub average_luminance: f32,
+    pub exposure: f32,
+    _p1: [f32; 2],
+    histogram: [u32; HISTOGRAM_BINS],
+    readback: [u32; HISTOGRAM_BINS],
+}
+
+/// Builds a log luminance histogram of the accumulation texture each frame and derives
+/// an exposure from its average, which the display shader reads directly from `buffer`.
+pub struct AutoExposure {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: Option<wgpu::BindGroup>,
+    settings_buffer: wgpu::Buffer,
+    pub buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+    copy_encoded: bool,
+    map_pending: bool,
+    mapped: Arc<AtomicBool>,
+    pub adaptation: f32,
+    pub histogram: [u32; HISTOGRAM_BINS],
+    pub average_luminance: f32,
+    pub exposure: f32,
+}
+
+impl AutoExposure {
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Exposure Compute Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/exposure.wgsl").into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Exposure Bind Group Layout"),
+            entries: &[
+                // Settings
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            mem::size_of::<ExposureSettings>() as _
+                        ),
+                    },
+                    count: None,
+                },
+                // Accumulation Texture
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Exposure
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            mem::size_of::<ExposureUniform>() as _
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Settings Buffer"),
+            size: mem::size_of::<ExposureSettings>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Buffer"),
+            size: mem::size_of::<ExposureUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Staging Buffer"),
+            size: mem::size_of::<ExposureUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let histogram_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Exposure Histogram Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("build_histogram"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        let average_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Exposure Average Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("average"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            device,
+            queue,
+            histogram_pipeline,
+            average_pipeline,
+            bind_group_layout,
+            bind_group: None,
+            settings_buffer,
+            buffer,
+            staging_buffer,
+            copy_encoded: false,
+            map_pending: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            adaptation: 0.05,
+            histogram: [0; HISTOGRAM_BINS],
+            average_luminance: 0.0,
+            exposure: 1.0,
+        }
+    }
+    /// Points the histogram pass at the accumulation texture of the active tab.
+    pub fn set_target(&mut self, texture_view: &TextureView) {
+        self.bind_group = Some(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Exposure Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.buffer.as_entire_binding(),
+                },
+            ],
+        }));
+    }
+    pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder, width: u32, height: u32) {
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::cast_slice(&[ExposureSettings {
+                width,
+                height,
+                min_log_luminance: MIN_LOG_LUMINANCE,
+                log_luminance_range: MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE,
+                adaptation: self.adaptation,
+                _p1: [0.0; 3],
+            }]),
+        );
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Exposure Compute Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_pipeline(&self.histogram_pipeline);
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE.0),
+                height.div_ceil(WORKGROUP_SIZE.1),
+                1,
+            );
+            compute_pass.set_pipeline(&self.average_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        if !self.map_pending {
+            encoder.copy_buffer_to_buffer(
+                &self.buffer,
+                0,
+                &self.staging_buffer,
+                0,
+                mem::size_of::<ExposureUniform>() as wgpu::BufferAddress,
+            );
+            self.copy_encoded = true;
+        }
+    }
+    /// Must be called after the encoder passed to `render` has been submitted.
+    pub fn request_readback(&mut self) {
+        if !self.copy_encoded {
+            return;
+        }
+        self.copy_encoded = false;
+        self.map_pending = true;
+        let mapped = self.mapped.clone();
+        self.staging_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::SeqCst);
+                }
+            });
+    }
+    /// Reads back the latest histogram and exposure once the staging buffer is mapped.
+    pub fn poll_readback(&mut self) {
+        if !self.map_pending {
+            return;
+        }
+        let _ = self.device.poll(wgpu::PollType::Poll);
+        if !self.mapped.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        {
+            let data = self.staging_buffer.slice(..).get_mapped_range();
+            let uniform: ExposureUniform = *bytemuck::from_bytes(&data);
+            self.histogram = uniform.readback;
+            self.average_luminance = uniform.average_luminance;
+            self.exposure = uniform.exposure;
+        }
+        self.staging_buffer.unmap();
+        self.map_pending = false;
+    }
+}
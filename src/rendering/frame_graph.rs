@@ -0,0 +1,105 @@
+use egui_wgpu::wgpu;
+
+/// GPU resources passes declare they read or write, used to derive the order passes run in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameResource {
+    /// The active tab's accumulation texture
+    Accumulation,
+    /// Full resolution normal and depth of the primary hits
+    Guide,
+    /// Upscaled moving preview
+    Preview,
+    /// Post processed image shown in place of the accumulation texture
+    Post,
+    /// Average luminance and histogram used by auto exposure
+    Luminance,
+    Lightmap,
+    /// First hit entity ids used for hover highlighting
+    Ids,
+    /// The swapchain image presented to the window
+    Surface,
+}
+
+type Execute<'a, C> = Box<dyn FnOnce(&mut C, &mut wgpu::CommandEncoder) + 'a>;
+
+struct PassNode<'a, C> {
+    name: &'static str,
+    reads: Vec<FrameResource>,
+    writes: Vec<FrameResource>,
+    execute: Execute<'a, C>,
+}
+
+/// A frame's passes recorded into a single encoder. Passes run after every pass writing a
+/// resource they read, passes writing the same resource keep the order they were added in.
+pub struct FrameGraph<'a, C> {
+    passes: Vec<PassNode<'a, C>>,
+}
+
+impl<'a, C> Default for FrameGraph<'a, C> {
+    fn default() -> Self {
+        Self { passes: vec![] }
+    }
+}
+
+impl<'a, C> FrameGraph<'a, C> {
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        reads: &[FrameResource],
+        writes: &[FrameResource],
+        execute: impl FnOnce(&mut C, &mut wgpu::CommandEncoder) + 'a,
+    ) {
+        self.passes.push(PassNode {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            execute: Box::new(execute),
+        });
+    }
+    pub fn execute(self, context: &mut C, encoder: &mut wgpu::CommandEncoder) {
+        let order = self.sorted();
+        let mut passes: Vec<Option<PassNode<'a, C>>> = self.passes.into_iter().map(Some).collect();
+        for i in order {
+            if let Some(pass) = passes[i].take() {
+                (pass.execute)(context, encoder);
+            }
+        }
+    }
+    /// Topological order of the passes, ties broken by the order they were added in.
+    fn sorted(&self) -> Vec<usize> {
+        let n = self.passes.len();
+        let mut dependencies = vec![vec![]; n];
+        for (i, pass) in self.passes.iter().enumerate() {
+            for (j, other) in self.passes.iter().enumerate() {
+                if i == j {
+                    continue;
+                }
+                let shares_write = pass.writes.iter().any(|r| other.writes.contains(r));
+                let reads_output = pass
+                    .reads
+                    .iter()
+                    .any(|r| other.writes.contains(r) && !pass.writes.contains(r));
+                if (shares_write && j < i) || reads_output {
+                    dependencies[i].push(j);
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(n);
+        let mut done = vec![false; n];
+        while order.len() < n {
+            let next = (0..n).find(|&i| !done[i] && dependencies[i].iter().all(|&d| done[d]));
+            let next = next.unwrap_or_else(|| {
+                let i = (0..n).find(|&i| !done[i]).unwrap();
+                log::error!(
+                    "Frame graph has a cycle through {}, running it in the order it was added",
+                    self.passes[i].name
+                );
+                i
+            });
+            done[next] = true;
+            order.push(next);
+        }
+        order
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use crate::core::engine::{FRAME_HISTORY, FrameEvent, FrameSample, FrameTiming};
+
+const PLOT_HEIGHT: f32 = 80.0;
+/// Frames taking this many times the median are drawn as spikes
+const SPIKE_RATIO: f32 = 2.0;
+/// The graph never zooms in further than 30 FPS, so a steady frame rate stays a flat line
+const MIN_SCALE: Duration = Duration::from_micros(33_333);
+/// Guide lines at 60 and 30 FPS
+const GUIDES: [(Duration, &str); 2] = [
+    (Duration::from_micros(16_667), "60"),
+    (Duration::from_micros(33_333), "30"),
+];
+
+const FRAME_COLOR: egui::Color32 = egui::Color32::from_rgb(120, 180, 255);
+const SPIKE_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 110, 90);
+
+fn event_color(event: FrameEvent) -> egui::Color32 {
+    match event {
+        FrameEvent::BufferUpload => egui::Color32::from_rgb(255, 200, 80),
+        FrameEvent::BvhRebuild => egui::Color32::from_rgb(190, 120, 255),
+        FrameEvent::SceneSwitch => egui::Color32::from_rgb(90, 220, 140),
+    }
+}
+
+fn median(history: &[FrameSample]) -> Duration {
+    let mut times: Vec<Duration> = history.iter().map(|sample| sample.time).collect();
+    times.sort_unstable();
+    times.get(times.len() / 2).copied().unwrap_or_default()
+}
+
+fn describe(sample: &FrameSample) -> String {
+    let events: Vec<&str> = FrameEvent::ALL
+        .into_iter()
+        .filter(|event| sample.has(*event))
+        .map(FrameEvent::name)
+        .collect();
+    match events.is_empty() {
+        true => format!("{:.2?}", sample.time),
+        false => format!("{:.2?} after {}", sample.time, events.join(", ")),
+    }
+}
+
+/// Scrolling graph of the last `FRAME_HISTORY` frame times, newest on the right. Frames following
+/// a `FrameEvent` are marked in its colour so a hitch can be matched to what caused it.
+pub fn show(ui: &mut egui::Ui, timing: &FrameTiming) {
+    let history: Vec<FrameSample> = timing.history.iter().copied().collect();
+    let median = median(&history);
+    let worst = history
+        .iter()
+        .map(|sample| sample.time)
+        .max()
+        .unwrap_or_default();
+    let is_spike = |sample: &FrameSample| {
+        !median.is_zero() && sample.time.as_secs_f32() > median.as_secs_f32() * SPIKE_RATIO
+    };
+    ui.label(format!(
+        "Frame Time: {:.2?} (median {:.2?}, worst {:.2?})",
+        timing.dt, median, worst
+    ));
+
+    let (rect, response) = ui.allocate_exact_size(
+        egui::Vec2::new(ui.available_width(), PLOT_HEIGHT),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let scale = worst.max(MIN_SCALE).as_secs_f32() * 1.1;
+    let y = |time: Duration| rect.bottom() - time.as_secs_f32() / scale * rect.height();
+    let grid = egui::Stroke::new(1.0, ui.visuals().widgets.noninteractive.bg_stroke.color);
+    let text_color = ui.visuals().text_color().gamma_multiply(0.6);
+    for (time, label) in GUIDES {
+        painter.hline(rect.x_range(), y(time), grid);
+        painter.text(
+            egui::pos2(rect.left() + 2.0, y(time)),
+            egui::Align2::LEFT_BOTTOM,
+            label,
+            egui::FontId::proportional(9.0),
+            text_color,
+        );
+    }
+
+    // Filled from the right so the newest frame always sits at the edge
+    let column = rect.width() / FRAME_HISTORY as f32;
+    let x = |i: usize| rect.right() - (history.len() - i) as f32 * column;
+    for (i, sample) in history.iter().enumerate() {
+        let left = x(i);
+        for event in FrameEvent::ALL
+            .into_iter()
+            .filter(|event| sample.has(*event))
+        {
+            painter.vline(
+                left + column * 0.5,
+                rect.y_range(),
+                egui::Stroke::new(1.0, event_color(event).gamma_multiply(0.6)),
+            );
+        }
+        let color = match is_spike(sample) {
+            true => SPIKE_COLOR,
+            false => FRAME_COLOR,
+        };
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(left, y(sample.time).max(rect.top())),
+                egui::pos2((left + column - 1.0).max(left + 1.0), rect.bottom()),
+            ),
+            0.0,
+            color,
+        );
+    }
+    if let Some(pos) = response.hover_pos() {
+        let from_right = ((rect.right() - pos.x) / column) as usize;
+        if let Some(sample) = history
+            .len()
+            .checked_sub(from_right + 1)
+            .map(|i| &history[i])
+        {
+            response.on_hover_text(describe(sample));
+        }
+    }
+
+    ui.horizontal_wrapped(|ui| {
+        for event in FrameEvent::ALL {
+            let count = history.iter().filter(|sample| sample.has(event)).count();
+            ui.colored_label(event_color(event), "■");
+            ui.label(format!("{} ({})", event.name(), count));
+        }
+    });
+    let spikes: Vec<&FrameSample> = history.iter().filter(|sample| is_spike(sample)).collect();
+    if let Some(last) = spikes.last() {
+        let explained = spikes.iter().filter(|sample| sample.events != 0).count();
+        ui.label(format!(
+            "Spikes: {}, {} with a marked cause. Last: {}",
+            spikes.len(),
+            explained,
+            describe(last)
+        ));
+    }
+}
@@ -0,0 +1,426 @@
+use std::{mem, path::Path, sync::Arc};
+
+use egui_wgpu::wgpu::{self, PipelineCompilationOptions, util::DeviceExt};
+use glam::{Mat3, Vec2, Vec3};
+use image::Rgba32FImage;
+
+use crate::core::jobs::{Job, Jobs};
+use crate::rendering::{
+    ray_tracer::RayTracer,
+    readback::{encode_rgba8, read_texture_rgba32f},
+};
+use crate::scene::components::geometry::mesh::MeshInstance;
+
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+/// Texels outside every triangle are filled from their neighbours this many times on export,
+/// so bilinear filtering along UV seams doesn't pull in black.
+const DILATE_ITERATIONS: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BakeSettings {
+    width: u32,
+    height: u32,
+    frame: u32,
+    samples_per_frame: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
+struct BakeTexel {
+    position: [f32; 3],
+    valid: u32,
+    normal: [f32; 3],
+    _p1: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightmapFormat {
+    Png,
+    Exr,
+}
+
+struct BakeJob {
+    label: String,
+    width: u32,
+    height: u32,
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    frame: u32,
+    target_frames: u32,
+}
+
+/// Accumulates irradiance into a lightmap laid out over a mesh's UVs, using the ray tracer's scene buffers.
+pub struct LightmapBaker {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    settings_buffer: wgpu::Buffer,
+    job: Option<BakeJob>,
+    /// Texels of the next bake being laid out off the main thread, with its label and resolution
+    rasterizing: Option<Job<(String, u32, Vec<BakeTexel>)>>,
+    pub resolution: u32,
+    pub samples_per_frame: u32,
+    /// Frames to accumulate before the bake stops
+    pub target_frames: u32,
+    /// Mesh index set by the UI to start a bake on the next update
+    pub bake_requested: Option<usize>,
+    pub export_requested: Option<LightmapFormat>,
+}
+
+impl LightmapBaker {
+    pub fn new(ray_tracer: &RayTracer) -> Self {
+        let device = ray_tracer.device.clone();
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Lightmap Bake Shader"),
+            source: wgpu::ShaderSource::Wgsl(ray_tracer.texture_mode.shader_source().into()),
+        });
+        let bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Lightmap Bind Group Layout"),
+                entries: &[
+                    // Settings
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                mem::size_of::<BakeSettings>() as _
+                            ),
+                        },
+                        count: None,
+                    },
+                    // Texels
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Lightmap
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::ReadWrite,
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Lightmap Pipeline Layout"),
+            bind_group_layouts: &[
+                &ray_tracer.bind_group_layout,
+                &ray_tracer.textures_bind_group_layout,
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Lightmap Bake Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("bake"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lightmap Settings Buffer"),
+            size: mem::size_of::<BakeSettings>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            device,
+            queue: ray_tracer.queue.clone(),
+            pipeline,
+            bind_group_layout,
+            settings_buffer,
+            job: None,
+            rasterizing: None,
+            resolution: 512,
+            samples_per_frame: 4,
+            target_frames: 256,
+            bake_requested: None,
+            export_requested: None,
+        }
+    }
+    pub fn is_baking(&self) -> bool {
+        self.job
+            .as_ref()
+            .is_some_and(|job| job.frame < job.target_frames)
+    }
+    /// Fraction of the target frames accumulated, `None` if nothing has been baked.
+    pub fn progress(&self) -> Option<f32> {
+        self.job
+            .as_ref()
+            .map(|job| (job.frame as f32 / job.target_frames.max(1) as f32).min(1.0))
+    }
+    /// Stops accumulating but keeps the lightmap so far for export.
+    pub fn stop(&mut self) {
+        if let Some(job) = self.job.as_mut() {
+            job.target_frames = job.frame;
+        }
+    }
+    pub fn cancel(&mut self) {
+        self.job = None;
+        self.rasterizing = None;
+    }
+    pub fn is_rasterizing(&self) -> bool {
+        self.rasterizing.is_some()
+    }
+    /// Rasterizes the mesh's triangles in UV space as a job, the bake starts from `poll` once
+    /// they're laid out.
+    pub fn start(&mut self, mesh: &MeshInstance, label: String, jobs: &Jobs) {
+        let resolution = self.resolution;
+        let mesh = mesh.clone();
+        self.rasterizing = Some(jobs.spawn(
+            format!("Rasterize lightmap of {}", label),
+            move || {
+                let texels = LightmapBaker::rasterize(&mesh, resolution, resolution);
+                (label, resolution, texels)
+            },
+        ));
+    }
+    /// Starts accumulating once the texels of `start` are ready.
+    pub fn poll(&mut self) {
+        let Some(result) = self.rasterizing.as_mut().and_then(Job::poll) else {
+            return;
+        };
+        self.rasterizing = None;
+        match result {
+            Ok((label, resolution, texels)) => self.begin(label, resolution, texels),
+            Err(e) => log::error!("Failed to rasterize lightmap: {}", e),
+        }
+    }
+    fn begin(&mut self, label: String, resolution: u32, texels: Vec<BakeTexel>) {
+        let (width, height) = (resolution, resolution);
+        let covered = texels.iter().filter(|t| t.valid != 0).count();
+        if covered == 0 {
+            log::warn!("Mesh {} has no UV coverage, nothing to bake", label);
+            return;
+        }
+        log::info!(
+            "Baking lightmap for {} ({}x{}, {} texels covered)",
+            label,
+            width,
+            height,
+            covered
+        );
+
+        let texel_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Lightmap Texel Buffer"),
+                contents: bytemuck::cast_slice(&texels),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Lightmap Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Lightmap Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: texel_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        });
+        self.job = Some(BakeJob {
+            label,
+            width,
+            height,
+            texture,
+            bind_group,
+            frame: 0,
+            target_frames: self.target_frames,
+        });
+    }
+    fn rasterize(mesh: &MeshInstance, width: u32, height: u32) -> Vec<BakeTexel> {
+        let mut texels = vec![BakeTexel::default(); (width * height) as usize];
+        let model_to_world = mesh.transform.to_matrix();
+        let normal_matrix = Mat3::from_mat4(model_to_world).inverse().transpose();
+        let size = Vec2::new(width as f32, height as f32);
+        let vertices = &mesh.data.vertices;
+
+        for tri in mesh.data.indices.chunks_exact(3) {
+            let [a, b, c] = [
+                vertices[tri[0] as usize],
+                vertices[tri[1] as usize],
+                vertices[tri[2] as usize],
+            ];
+            let (ta, tb, tc) = (
+                Vec2::from(a.uv) * size,
+                Vec2::from(b.uv) * size,
+                Vec2::from(c.uv) * size,
+            );
+            let area = (tb - ta).perp_dot(tc - ta);
+            if area.abs() < f32::EPSILON {
+                continue;
+            }
+            let min = ta.min(tb.min(tc)).floor().max(Vec2::ZERO);
+            let max = ta.max(tb.max(tc)).ceil().min(size);
+            for y in min.y as u32..max.y as u32 {
+                for x in min.x as u32..max.x as u32 {
+                    let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    let w_a = (tb - p).perp_dot(tc - p) / area;
+                    let w_b = (tc - p).perp_dot(ta - p) / area;
+                    let w_c = 1.0 - w_a - w_b;
+                    if w_a < 0.0 || w_b < 0.0 || w_c < 0.0 {
+                        continue;
+                    }
+                    let position = a.pos * w_a + b.pos * w_b + c.pos * w_c;
+                    let normal = a.normal * w_a + b.normal * w_b + c.normal * w_c;
+                    let normal = (normal_matrix * normal).normalize_or(Vec3::Y);
+                    texels[(y * width + x) as usize] = BakeTexel {
+                        position: model_to_world.transform_point3(position).to_array(),
+                        valid: 1,
+                        normal: normal.to_array(),
+                        _p1: 0.0,
+                    };
+                }
+            }
+        }
+        texels
+    }
+    /// Accumulates one frame into the lightmap, expects the ray tracer's scene buffers to be up to date.
+    pub fn bake(&mut self, encoder: &mut wgpu::CommandEncoder, ray_tracer: &RayTracer) {
+        if !self.is_baking() {
+            return;
+        }
+        let Some(job) = self.job.as_mut() else {
+            return;
+        };
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::cast_slice(&[BakeSettings {
+                width: job.width,
+                height: job.height,
+                frame: job.frame,
+                samples_per_frame: self.samples_per_frame.max(1),
+            }]),
+        );
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Lightmap Bake Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &ray_tracer.bind_group, &[]);
+            compute_pass.set_bind_group(1, &ray_tracer.textures_bind_group, &[]);
+            compute_pass.set_bind_group(2, &job.bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                job.width.div_ceil(WORKGROUP_SIZE.0),
+                job.height.div_ceil(WORKGROUP_SIZE.1),
+                1,
+            );
+        }
+        job.frame += 1;
+    }
+    /// Reads the lightmap back and writes it to `lightmaps/<mesh>.png|exr`.
+    pub fn export(&self, format: LightmapFormat) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(job) = self.job.as_ref() else {
+            return Err("No lightmap has been baked".into());
+        };
+        let mut image = Rgba32FImage::from_raw(
+            job.width,
+            job.height,
+            read_texture_rgba32f(
+                &self.device,
+                &self.queue,
+                &job.texture,
+                job.width,
+                job.height,
+            )?,
+        )
+        .ok_or("Failed to create lightmap image")?;
+        for _ in 0..DILATE_ITERATIONS {
+            dilate(&mut image);
+        }
+
+        std::fs::create_dir_all("lightmaps")?;
+        let name: String = job
+            .label
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = match format {
+            LightmapFormat::Png => Path::new("lightmaps").join(format!("{}.png", name)),
+            LightmapFormat::Exr => Path::new("lightmaps").join(format!("{}.exr", name)),
+        };
+        match format {
+            LightmapFormat::Png => {
+                let ldr = encode_rgba8(&image, 1.0);
+                ldr.save(&path)?;
+            }
+            LightmapFormat::Exr => image.save(&path)?,
+        }
+        log::info!("Saved lightmap to {}", path.display());
+        Ok(())
+    }
+}
+
+/// Copies texels into uncovered (zero alpha) neighbours.
+fn dilate(image: &mut Rgba32FImage) {
+    let source = image.clone();
+    let (width, height) = source.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            if source.get_pixel(x, y)[3] > 0.0 {
+                continue;
+            }
+            let mut sum = [0.0; 4];
+            let mut count = 0.0;
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+                let n = source.get_pixel(nx as u32, ny as u32);
+                if n[3] > 0.0 {
+                    for c in 0..4 {
+                        sum[c] += n[c];
+                    }
+                    count += 1.0;
+                }
+            }
+            if count > 0.0 {
+                image.put_pixel(x, y, image::Rgba(sum.map(|v| v / count)));
+            }
+        }
+    }
+}
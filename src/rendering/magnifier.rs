@@ -0,0 +1,279 @@
+use std::{
+    ops::RangeInclusive,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use egui_wgpu::wgpu;
+
+pub const ZOOM_RANGE: RangeInclusive<u32> = 8..=32;
+/// Side of the magnified view in points
+const VIEW_SIZE: f32 = 192.0;
+/// Gap between the cursor and the view
+const VIEW_OFFSET: f32 = 24.0;
+/// Height of the pixel value readout under the view
+const LABEL_HEIGHT: f32 = 56.0;
+const BYTES_PER_PIXEL: u32 = 16;
+
+/// Pixels around the cursor read back from the accumulation texture, rows bottom first like the texture.
+struct Crop {
+    origin: (u32, u32),
+    width: u32,
+    height: u32,
+    cursor: (u32, u32),
+    pixels: Vec<f32>,
+    /// Display exposure the crop is shown with, so it matches the viewport
+    exposure: f32,
+}
+
+/// What the readback being mapped will become, see `Magnifier::update`.
+struct Pending {
+    origin: (u32, u32),
+    width: u32,
+    height: u32,
+    cursor: (u32, u32),
+    bytes_per_row: u32,
+    exposure: f32,
+}
+
+/// Zoomed view of the render around the cursor, with the radiance of the pixel under it, for
+/// looking at noise, fireflies and aliasing without exporting the image.
+pub struct Magnifier {
+    pub enabled: bool,
+    /// Screen points per render pixel
+    pub zoom: u32,
+    crop: Option<Crop>,
+    /// Sized for the widest crop, created on first use
+    staging_buffer: Option<wgpu::Buffer>,
+    pending: Option<Pending>,
+    mapped: Arc<AtomicBool>,
+    /// Cursor, zoom and frame of the last readback, nothing is read again until one changes
+    requested: Option<((u32, u32), u32, i32)>,
+}
+
+impl Default for Magnifier {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            zoom: 16,
+            crop: None,
+            staging_buffer: None,
+            pending: None,
+            mapped: Arc::new(AtomicBool::new(false)),
+            requested: None,
+        }
+    }
+}
+
+impl Magnifier {
+    /// Render pixels along each side of the view, odd so the cursor pixel sits in the middle.
+    fn span(zoom: u32) -> u32 {
+        (VIEW_SIZE / zoom as f32) as u32 | 1
+    }
+    /// Copies the pixels around `cursor` back without waiting on the GPU, the view shows the last
+    /// crop until the copy lands. Only reads again once the cursor moves, the zoom changes or
+    /// another frame is accumulated into `frame`, which holds at the start while every frame is
+    /// new, as when the camera moves.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        (width, height): (u32, u32),
+        cursor: Option<(u32, u32)>,
+        exposure: f32,
+        frame: i32,
+    ) {
+        self.poll_readback(device);
+        let Some((x, y)) = cursor.filter(|&(x, y)| self.enabled && x < width && y < height) else {
+            self.crop = None;
+            self.requested = None;
+            return;
+        };
+        if let Some(crop) = &mut self.crop {
+            crop.exposure = exposure;
+        }
+        let key = ((x, y), self.zoom, frame);
+        if self.pending.is_some() || (frame > 0 && self.requested == Some(key)) {
+            return;
+        }
+        let span = Self::span(self.zoom.max(*ZOOM_RANGE.start()));
+        let (crop_width, crop_height) = (span.min(width), span.min(height));
+        let origin = (
+            x.saturating_sub(span / 2).min(width - crop_width),
+            y.saturating_sub(span / 2).min(height - crop_height),
+        );
+        let bytes_per_row =
+            (crop_width * BYTES_PER_PIXEL).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let staging_buffer = self.staging_buffer.get_or_insert_with(|| {
+            let span = Self::span(*ZOOM_RANGE.start());
+            let bytes_per_row =
+                (span * BYTES_PER_PIXEL).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Magnifier Staging Buffer"),
+                size: (bytes_per_row * span) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Magnifier Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: origin.0,
+                    y: origin.1,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: staging_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(crop_height),
+                },
+            },
+            wgpu::Extent3d {
+                width: crop_width,
+                height: crop_height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+        let mapped = self.mapped.clone();
+        staging_buffer
+            .slice(..(bytes_per_row * crop_height) as wgpu::BufferAddress)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::SeqCst);
+                }
+            });
+        self.pending = Some(Pending {
+            origin,
+            width: crop_width,
+            height: crop_height,
+            cursor: (x, y),
+            bytes_per_row,
+            exposure,
+        });
+        self.requested = Some(key);
+    }
+    /// Replaces the crop once the last copy has been mapped.
+    fn poll_readback(&mut self, device: &wgpu::Device) {
+        let (Some(pending), Some(staging_buffer)) = (&self.pending, &self.staging_buffer) else {
+            return;
+        };
+        let _ = device.poll(wgpu::PollType::Poll);
+        if !self.mapped.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let mut pixels = Vec::with_capacity((pending.width * pending.height * 4) as usize);
+        {
+            let data = staging_buffer
+                .slice(..(pending.bytes_per_row * pending.height) as wgpu::BufferAddress)
+                .get_mapped_range();
+            for row in data.chunks(pending.bytes_per_row as usize) {
+                pixels.extend(
+                    row[..(pending.width * BYTES_PER_PIXEL) as usize]
+                        .chunks_exact(4)
+                        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                );
+            }
+        }
+        staging_buffer.unmap();
+        // Disabled or moved off the render while the copy was in flight
+        if self.requested.is_some() {
+            self.crop = Some(Crop {
+                origin: pending.origin,
+                width: pending.width,
+                height: pending.height,
+                cursor: pending.cursor,
+                pixels,
+                exposure: pending.exposure,
+            });
+        }
+        self.pending = None;
+    }
+    /// Draws the last crop next to `pointer`, kept inside `screen`.
+    pub fn paint(&self, ctx: &egui::Context, pointer: egui::Pos2, screen: egui::Rect) {
+        let Some(crop) = &self.crop else {
+            return;
+        };
+        let painter = ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Tooltip,
+            egui::Id::new("magnifier"),
+        ));
+        let cell = self.zoom as f32;
+        let size = egui::vec2(crop.width as f32, crop.height as f32) * cell;
+        let mut min = pointer + egui::vec2(VIEW_OFFSET, VIEW_OFFSET);
+        // Flip to the other side of the cursor rather than run off the screen
+        if min.x + size.x > screen.max.x {
+            min.x = pointer.x - VIEW_OFFSET - size.x;
+        }
+        if min.y + size.y + LABEL_HEIGHT > screen.max.y {
+            min.y = pointer.y - VIEW_OFFSET - size.y - LABEL_HEIGHT;
+        }
+        let view = egui::Rect::from_min_size(min, size);
+        painter.rect_filled(view.expand(2.0), 2.0, egui::Color32::BLACK);
+        for row in 0..crop.height {
+            for column in 0..crop.width {
+                let i = ((row * crop.width + column) * 4) as usize;
+                let rgb = &crop.pixels[i..i + 3];
+                let color = egui::Rgba::from_rgb(
+                    rgb[0] * crop.exposure,
+                    rgb[1] * crop.exposure,
+                    rgb[2] * crop.exposure,
+                );
+                // The texture's first row is the bottom of the image
+                let cell_min =
+                    view.min + egui::vec2(column as f32, (crop.height - 1 - row) as f32) * cell;
+                let rect = egui::Rect::from_min_size(cell_min, egui::vec2(cell, cell));
+                painter.rect_filled(rect, 0.0, egui::Color32::from(color));
+                if self.zoom >= 16 {
+                    painter.rect_stroke(
+                        rect,
+                        0.0,
+                        egui::Stroke::new(0.5, egui::Color32::from_black_alpha(80)),
+                        egui::StrokeKind::Inside,
+                    );
+                }
+                if (crop.origin.0 + column, crop.origin.1 + row) == crop.cursor {
+                    painter.rect_stroke(
+                        rect,
+                        0.0,
+                        egui::Stroke::new(1.5, egui::Color32::YELLOW),
+                        egui::StrokeKind::Outside,
+                    );
+                }
+            }
+        }
+        let (x, y) = crop.cursor;
+        let i = (((y - crop.origin.1) * crop.width + x - crop.origin.0) * 4) as usize;
+        let [r, g, b] = [crop.pixels[i], crop.pixels[i + 1], crop.pixels[i + 2]];
+        let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let text = format!(
+            "{}, {}  ×{}\nR {:.4}  G {:.4}  B {:.4}\nLuminance {:.4}",
+            x, y, self.zoom, r, g, b, luminance
+        );
+        let label = egui::Rect::from_min_size(
+            egui::pos2(view.min.x, view.max.y + 2.0),
+            egui::vec2(size.x.max(180.0), LABEL_HEIGHT),
+        );
+        painter.rect_filled(label, 2.0, egui::Color32::from_black_alpha(220));
+        painter.text(
+            label.min + egui::vec2(4.0, 4.0),
+            egui::Align2::LEFT_TOP,
+            text,
+            egui::FontId::monospace(12.0),
+            egui::Color32::WHITE,
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use glam::Vec3;
+
+use crate::scene::components::material::{MaterialFlag, MaterialUniform};
+
+/// Points along each Fresnel curve
+const CURVE_POINTS: usize = 64;
+/// Angular bins of the lobe, covering the full circle around the hit point
+const LOBE_BINS: usize = 90;
+/// Samples along each side of the stratified grid the lobe is built from
+const LOBE_SAMPLES: usize = 64;
+/// Samples further out of the plane of incidence than this are left out of the slice
+const PLANE_TOLERANCE: f32 = 0.2;
+const PLOT_HEIGHT: f32 = 110.0;
+
+const REFLECT_COLOR: egui::Color32 = egui::Color32::from_rgb(120, 180, 255);
+const TRANSMIT_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 200, 80);
+
+/// Schlick's approximation, matching `reflectance` in the shader.
+fn schlick(cos_theta: f32, ior: f32) -> f32 {
+    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
+}
+
+/// Chance of a mirror bounce at an incidence angle in radians, split by which side of the surface
+/// the ray arrives from. Other materials reflect with a fixed probability on both sides.
+fn reflect_chance(material: &MaterialUniform, angle: f32) -> (f32, f32) {
+    if material.flag != MaterialFlag::GLASS as i32 {
+        return (material.specular, material.specular);
+    }
+    let cos_theta = angle.cos();
+    let exiting = match material.ior * angle.sin() > 1.0 {
+        true => 1.0,
+        false => schlick(cos_theta, material.ior),
+    };
+    (schlick(cos_theta, 1.0 / material.ior), exiting)
+}
+
+/// Distribution of bounce directions for a ray arriving at `incidence` radians from the normal,
+/// sliced through the plane of incidence. Follows the same mixing of diffuse and mirror directions
+/// the shader uses, with the normal along +Y and the ray travelling towards +X.
+fn lobe(material: &MaterialUniform, incidence: f32) -> Vec<f32> {
+    let normal = Vec3::Y;
+    let dir = Vec3::new(incidence.sin(), -incidence.cos(), 0.0);
+    let mirror = dir.reflect(normal);
+    let glass = material.flag == MaterialFlag::GLASS as i32;
+    let eta = 1.0 / material.ior;
+    let refracted = dir.refract(normal, eta);
+    let fresnel = schlick(incidence.cos(), eta);
+    let mut bins = vec![0.0; LOBE_BINS];
+    let mut add = |out: Vec3, weight: f32| {
+        if out.z.abs() > PLANE_TOLERANCE || weight <= 0.0 {
+            return;
+        }
+        let angle = out.x.atan2(out.y).rem_euclid(TAU);
+        bins[((angle / TAU * LOBE_BINS as f32) as usize).min(LOBE_BINS - 1)] += weight;
+    };
+    for i in 0..LOBE_SAMPLES {
+        for j in 0..LOBE_SAMPLES {
+            let u = (i as f32 + 0.5) / LOBE_SAMPLES as f32;
+            let phi = (j as f32 + 0.5) / LOBE_SAMPLES as f32 * TAU;
+            if glass {
+                // Cosine weighted like `normal + rand_direction`
+                let r = u.sqrt();
+                let diffuse = Vec3::new(r * phi.cos(), (1.0 - u).sqrt(), r * phi.sin());
+                add(
+                    mirror.lerp(diffuse, 1.0 - material.specular).normalize(),
+                    fresnel,
+                );
+                if refracted != Vec3::ZERO {
+                    let out = refracted.lerp(-diffuse, 1.0 - material.smoothness);
+                    add(out.normalize(), 1.0 - fresnel);
+                }
+            } else {
+                let s = (1.0 - u * u).sqrt();
+                let hemisphere = Vec3::new(s * phi.cos(), u, s * phi.sin());
+                let specular = hemisphere.lerp(mirror, material.smoothness).normalize();
+                add(specular, material.specular);
+                add(hemisphere, 1.0 - material.specular);
+            }
+        }
+    }
+    let max = bins.iter().copied().fold(0.0, f32::max);
+    if max > 0.0 {
+        bins.iter_mut().for_each(|bin| *bin /= max);
+    }
+    bins
+}
+
+/// Fresnel curve and lobe shape for a material's current parameters, so the effect of specular,
+/// smoothness and IOR can be seen without waiting for the render to converge.
+pub fn show(ui: &mut egui::Ui, material: &MaterialUniform) {
+    egui::CollapsingHeader::new("Response Curves")
+        .id_salt("Material Response")
+        .show(ui, |ui| {
+            fresnel_plot(ui, material);
+            let id = ui.id().with("Incidence");
+            let mut incidence = ui.data_mut(|data| *data.get_temp_mut_or(id, 45.0f32));
+            ui.add(
+                egui::Slider::new(&mut incidence, 0.0..=89.0)
+                    .suffix("°")
+                    .text("Incidence"),
+            );
+            ui.data_mut(|data| data.insert_temp(id, incidence));
+            lobe_plot(ui, material, incidence.to_radians());
+        });
+}
+
+fn fresnel_plot(ui: &mut egui::Ui, material: &MaterialUniform) {
+    let (rect, response) = ui.allocate_exact_size(
+        egui::Vec2::new(ui.available_width(), PLOT_HEIGHT),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let grid = egui::Stroke::new(1.0, ui.visuals().widgets.noninteractive.bg_stroke.color);
+    for degrees in [30.0, 60.0] {
+        painter.vline(
+            rect.left() + degrees / 90.0 * rect.width(),
+            rect.y_range(),
+            grid,
+        );
+    }
+    painter.hline(rect.x_range(), rect.center().y, grid);
+
+    let to_screen = |angle: f32, value: f32| {
+        egui::pos2(
+            rect.left() + angle / FRAC_PI_2 * rect.width(),
+            rect.bottom() - value.clamp(0.0, 1.0) * rect.height(),
+        )
+    };
+    let (entering, exiting): (Vec<_>, Vec<_>) = (0..=CURVE_POINTS)
+        .map(|i| {
+            let angle = i as f32 / CURVE_POINTS as f32 * FRAC_PI_2;
+            let (entering, exiting) = reflect_chance(material, angle);
+            (to_screen(angle, entering), to_screen(angle, exiting))
+        })
+        .unzip();
+    if material.flag == MaterialFlag::GLASS as i32 {
+        painter.line(exiting, egui::Stroke::new(1.5, TRANSMIT_COLOR));
+    }
+    painter.line(entering, egui::Stroke::new(1.5, REFLECT_COLOR));
+    let text_color = ui.visuals().text_color().gamma_multiply(0.6);
+    painter.text(
+        rect.left_top() + egui::vec2(4.0, 2.0),
+        egui::Align2::LEFT_TOP,
+        match material.flag == MaterialFlag::GLASS as i32 {
+            true => "Reflectance, outside / inside",
+            false => "Specular chance",
+        },
+        egui::FontId::proportional(10.0),
+        text_color,
+    );
+    if let Some(pos) = response.hover_pos() {
+        let angle = ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0) * FRAC_PI_2;
+        let (entering, exiting) = reflect_chance(material, angle);
+        response.on_hover_text(match material.flag == MaterialFlag::GLASS as i32 {
+            true => format!(
+                "{:.0}°: {:.3} outside, {:.3} inside",
+                angle.to_degrees(),
+                entering,
+                exiting
+            ),
+            false => format!("{:.0}°: {:.3}", angle.to_degrees(), entering),
+        });
+    }
+}
+
+fn lobe_plot(ui: &mut egui::Ui, material: &MaterialUniform, incidence: f32) {
+    let (rect, _) = ui.allocate_exact_size(
+        egui::Vec2::new(ui.available_width(), PLOT_HEIGHT * 2.0),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let centre = rect.center();
+    let radius = rect.height().min(rect.width()) * 0.45;
+    let surface = egui::Stroke::new(1.0, ui.visuals().widgets.noninteractive.fg_stroke.color);
+    painter.hline(rect.x_range(), centre.y, surface);
+
+    // Angles are measured from the normal, clockwise towards +X
+    let point =
+        |angle: f32, length: f32| centre + egui::vec2(angle.sin(), -angle.cos()) * length * radius;
+    painter.arrow(
+        point(-incidence, 1.0),
+        centre - point(-incidence, 1.0),
+        egui::Stroke::new(1.0, ui.visuals().text_color().gamma_multiply(0.6)),
+    );
+    let bins = lobe(material, incidence);
+    let bin_angle = TAU / LOBE_BINS as f32;
+    let shape = |range: std::ops::Range<usize>, color: egui::Color32| {
+        let points: Vec<_> = range
+            .map(|i| point((i as f32 + 0.5) * bin_angle, bins[i % LOBE_BINS]))
+            .collect();
+        painter.line(points, egui::Stroke::new(1.5, color));
+    };
+    // Upper half reflects, lower half transmits
+    shape(LOBE_BINS * 3 / 4..LOBE_BINS * 5 / 4 + 1, REFLECT_COLOR);
+    if material.flag == MaterialFlag::GLASS as i32 {
+        shape(LOBE_BINS / 4..LOBE_BINS * 3 / 4 + 1, TRANSMIT_COLOR);
+    }
+}
@@ -1,3 +1,17 @@
+pub mod cpu_tracer;
+pub mod cryptomatte;
+pub mod denoise_features;
 pub mod egui;
+pub mod exposure;
+pub mod frame_graph;
+pub mod frame_time_plot;
+pub mod lightmap;
+pub mod magnifier;
+pub mod material_plot;
+pub mod overlay;
+pub mod picking;
+pub mod post;
 pub mod ray_tracer;
+pub mod readback;
 pub mod renderer;
+pub mod upscale;
@@ -0,0 +1,532 @@
+use std::{mem, sync::Arc};
+
+use egui_wgpu::wgpu::{self, PipelineCompilationOptions, util::DeviceExt};
+use glam::{Mat4, Vec3};
+
+use crate::rendering::renderer::ViewWindow;
+use crate::scene::{
+    camera::Camera,
+    components::geometry::mesh::{MeshData, MeshInstance},
+};
+
+// Must match the constants in overlay.wgsl
+const GIZMO_CENTER: egui::Vec2 = egui::vec2(-0.88, -0.8);
+const GIZMO_SIZE: f32 = 0.12;
+/// Larger meshes only have their first triangles drawn, the line buffer grows with every edge
+const MAX_WIRE_TRIANGLES: usize = 250_000;
+
+/// Aspect ratios offered for the letterbox mask
+pub const LETTERBOX_RATIOS: [(&str, f32); 5] = [
+    ("2.39:1", 2.39),
+    ("1.85:1", 1.85),
+    ("16:9", 16.0 / 9.0),
+    ("4:3", 4.0 / 3.0),
+    ("1:1", 1.0),
+];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayUniform {
+    cam_to_world: [[f32; 4]; 4],
+    view_params: [f32; 3],
+    grid_spacing: f32,
+    grid_fade: f32,
+    aspect: f32,
+    show_grid: u32,
+    show_axes: u32,
+    world_to_cam: [[f32; 4]; 4],
+    model: [[f32; 4]; 4],
+    /// Inverse transpose of `model`, for the normal arrows
+    normal_matrix: [[f32; 4]; 4],
+    normal_length: f32,
+    focus_band: f32,
+    show_focus: u32,
+    _padding: u32,
+}
+
+/// Line vertex of the selected mesh in model space. Normal arrows are a `NORMAL_TAIL` vertex
+/// followed by a `NORMAL_HEAD`, pushed out along the normal in world space by the shader.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct WireVertex {
+    pos: [f32; 3],
+    normal: [f32; 3],
+    kind: u32,
+}
+
+impl WireVertex {
+    // Must match the constants in overlay.wgsl
+    const EDGE: u32 = 0;
+    const NORMAL_TAIL: u32 = 1;
+    const NORMAL_HEAD: u32 = 2;
+
+    const LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: mem::size_of::<WireVertex>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Uint32],
+    };
+}
+
+/// Line buffer built for one mesh, kept until the selection or the mesh's geometry changes.
+struct WireMesh {
+    data: Arc<MeshData>,
+    buffer: wgpu::Buffer,
+    edges: u32,
+    normals: u32,
+}
+
+impl WireMesh {
+    fn new(device: &wgpu::Device, data: Arc<MeshData>) -> Self {
+        let triangles = data.indices.len() / 3;
+        if triangles > MAX_WIRE_TRIANGLES {
+            log::warn!(
+                "Only drawing the wireframe of {} of {} triangles",
+                MAX_WIRE_TRIANGLES,
+                triangles
+            );
+        }
+        let vertex = |index: u32, kind: u32| {
+            let vertex = &data.vertices[index as usize];
+            WireVertex {
+                pos: vertex.pos.to_array(),
+                normal: vertex.normal.to_array(),
+                kind,
+            }
+        };
+        let mut lines: Vec<WireVertex> = data
+            .indices
+            .chunks_exact(3)
+            .take(MAX_WIRE_TRIANGLES)
+            .flat_map(|t| [t[0], t[1], t[1], t[2], t[2], t[0]])
+            .map(|index| vertex(index, WireVertex::EDGE))
+            .collect();
+        let edges = lines.len() as u32;
+        let normal_count = data.vertices.len().min(MAX_WIRE_TRIANGLES * 3) as u32;
+        lines.extend((0..normal_count).flat_map(|index| {
+            [
+                vertex(index, WireVertex::NORMAL_TAIL),
+                vertex(index, WireVertex::NORMAL_HEAD),
+            ]
+        }));
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Wireframe Buffer"),
+            contents: bytemuck::cast_slice(&lines),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        Self {
+            data,
+            buffer,
+            edges,
+            normals: normal_count * 2,
+        }
+    }
+}
+
+/// Ground grid, axis gizmo, focal plane and selected mesh wireframe composited over the ray traced
+/// image.
+pub struct Overlay {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    buffer: wgpu::Buffer,
+    right: Vec3,
+    up: Vec3,
+    aspect: f32,
+    pub show_grid: bool,
+    pub show_axes: bool,
+    pub grid_spacing: f32,
+    /// Distance from the camera at which the grid has fully faded out
+    pub grid_fade: f32,
+    pub show_thirds: bool,
+    pub show_center: bool,
+    /// Action safe (93%) and title safe (90%) frames
+    pub show_safe_areas: bool,
+    /// Masks the image down to this aspect ratio, the other guides follow the masked frame
+    pub letterbox: Option<f32>,
+    /// Edges of the selected mesh, drawn over everything rather than depth tested
+    pub show_wireframe: bool,
+    /// Vertex normals of the selected mesh as lines out from each vertex
+    pub show_normals: bool,
+    /// Length of the normal lines in world units
+    pub normal_length: f32,
+    /// Tints the geometry within `focus_band` of the focal plane, what depth of field keeps sharp
+    pub show_focus: bool,
+    /// Depth in world units of the tinted slab centred on the focal plane
+    pub focus_band: f32,
+    wire: Option<WireMesh>,
+    model: Mat4,
+}
+
+impl Overlay {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        renderer: &mut egui_wgpu::Renderer,
+        surface_config: &wgpu::SurfaceConfiguration,
+        guide_view: &wgpu::TextureView,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Overlay Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            mem::size_of::<OverlayUniform>() as _
+                        ),
+                    },
+                    count: None,
+                },
+                // Primary hit distances for the focal plane
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                ViewWindow::layout_entry(2),
+            ],
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Buffer"),
+            size: mem::size_of::<OverlayUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let view_buffer = ViewWindow::create_buffer(&device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Overlay Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(guide_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: view_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Overlay Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Overlay Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/overlay.wgsl").into()),
+        });
+
+        let create_pipeline = |label: &str,
+                               vertex: &str,
+                               fragment: &str,
+                               topology: wgpu::PrimitiveTopology,
+                               buffers: &[wgpu::VertexBufferLayout]| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some(vertex),
+                    buffers,
+                    compilation_options: PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(fragment),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: surface_config.format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let grid_pipeline = create_pipeline(
+            "Overlay Grid Pipeline",
+            "grid_vert",
+            "grid_frag",
+            wgpu::PrimitiveTopology::TriangleList,
+            &[],
+        );
+        let focus_pipeline = create_pipeline(
+            "Overlay Focal Plane Pipeline",
+            "grid_vert",
+            "focus_frag",
+            wgpu::PrimitiveTopology::TriangleList,
+            &[],
+        );
+        let gizmo_pipeline = create_pipeline(
+            "Overlay Gizmo Pipeline",
+            "gizmo_vert",
+            "gizmo_frag",
+            wgpu::PrimitiveTopology::LineList,
+            &[],
+        );
+        let wire_pipeline = create_pipeline(
+            "Overlay Wireframe Pipeline",
+            "wire_vert",
+            "wire_frag",
+            wgpu::PrimitiveTopology::LineList,
+            &[WireVertex::LAYOUT],
+        );
+        renderer.callback_resources.insert(OverlayResource {
+            grid_pipeline,
+            focus_pipeline,
+            gizmo_pipeline,
+            wire_pipeline,
+            bind_group,
+            view_buffer,
+        });
+
+        Self {
+            device,
+            queue,
+            buffer,
+            right: Vec3::X,
+            up: Vec3::Y,
+            aspect: 16.0 / 9.0,
+            show_grid: false,
+            show_axes: false,
+            grid_spacing: 1.0,
+            grid_fade: 100.0,
+            show_thirds: false,
+            show_center: false,
+            show_safe_areas: false,
+            letterbox: None,
+            show_wireframe: false,
+            show_normals: false,
+            normal_length: 0.05,
+            show_focus: false,
+            focus_band: 0.25,
+            wire: None,
+            model: Mat4::IDENTITY,
+        }
+    }
+    /// Follows the selected mesh, rebuilding its lines only when different geometry is selected.
+    pub fn set_selection(&mut self, mesh: Option<&MeshInstance>) {
+        let mesh = mesh.filter(|_| self.show_wireframe || self.show_normals);
+        let Some(mesh) = mesh else {
+            self.wire = None;
+            return;
+        };
+        self.model = mesh.transform.to_matrix();
+        if !self
+            .wire
+            .as_ref()
+            .is_some_and(|wire| Arc::ptr_eq(&wire.data, &mesh.data))
+        {
+            self.wire = Some(WireMesh::new(&self.device, mesh.data.clone()));
+        }
+    }
+    pub fn update(&mut self, camera: &Camera) {
+        let uniform = camera.to_uniform();
+        let matrix = camera.cam_to_world();
+        self.right = matrix.x_axis.truncate().normalize();
+        self.up = matrix.y_axis.truncate().normalize();
+        self.aspect = camera.aspect;
+        self.queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[OverlayUniform {
+                cam_to_world: uniform.cam_to_world,
+                view_params: uniform.view_params,
+                grid_spacing: self.grid_spacing.max(0.001),
+                grid_fade: self.grid_fade,
+                aspect: self.aspect,
+                show_grid: self.show_grid as u32,
+                show_axes: self.show_axes as u32,
+                world_to_cam: matrix.inverse().to_cols_array_2d(),
+                model: self.model.to_cols_array_2d(),
+                normal_matrix: self.model.inverse().transpose().to_cols_array_2d(),
+                normal_length: self.normal_length,
+                focus_band: self.focus_band.max(0.0),
+                show_focus: self.show_focus as u32,
+                _padding: 0,
+            }]),
+        );
+    }
+    /// Draws over the image at `rect` where `visible` shows it, see `Renderer::render_ray_traced_image`.
+    pub fn paint(&self, ui: &mut egui::Ui, rect: egui::Rect, visible: egui::Rect) {
+        if !visible.is_positive() {
+            return;
+        }
+        let painter = ui.painter().with_clip_rect(visible);
+        self.paint_composition(&painter, rect);
+        let wire = self.wire.as_ref().map(|wire| {
+            let edges = if self.show_wireframe { wire.edges } else { 0 };
+            let normals = if self.show_normals { wire.normals } else { 0 };
+            (wire.buffer.clone(), edges..edges + normals, 0..edges)
+        });
+        if !self.show_grid && !self.show_axes && !self.show_focus && wire.is_none() {
+            return;
+        }
+        painter.add(egui_wgpu::Callback::new_paint_callback(
+            visible,
+            OverlayCallback {
+                window: ViewWindow::new(rect, visible),
+                focus: self.show_focus,
+                grid: self.show_grid || self.show_axes,
+                gizmo: self.show_axes,
+                wire,
+            },
+        ));
+        if self.show_axes {
+            // Axis labels just past the ends of the gizmo lines
+            let to_screen =
+                |ndc: egui::Vec2| rect.center() + egui::vec2(ndc.x, -ndc.y) * rect.size() * 0.5;
+            for (axis, label, color) in [
+                (Vec3::X, "X", egui::Color32::from_rgb(230, 50, 50)),
+                (Vec3::Y, "Y", egui::Color32::from_rgb(50, 230, 50)),
+                (Vec3::Z, "Z", egui::Color32::from_rgb(50, 100, 230)),
+            ] {
+                let projected = egui::vec2(axis.dot(self.right) / self.aspect, axis.dot(self.up))
+                    * GIZMO_SIZE
+                    * 1.25;
+                painter.text(
+                    to_screen(GIZMO_CENTER + projected),
+                    egui::Align2::CENTER_CENTER,
+                    label,
+                    egui::FontId::monospace(11.0),
+                    color,
+                );
+            }
+        }
+    }
+    /// Composition guides, drawn with egui on top of the image rather than in the overlay shader.
+    fn paint_composition(&self, painter: &egui::Painter, rect: egui::Rect) {
+        let frame = match self.letterbox {
+            Some(ratio) => {
+                let frame = match rect.aspect_ratio() > ratio {
+                    true => egui::Rect::from_center_size(
+                        rect.center(),
+                        egui::vec2(rect.height() * ratio, rect.height()),
+                    ),
+                    false => egui::Rect::from_center_size(
+                        rect.center(),
+                        egui::vec2(rect.width(), rect.width() / ratio),
+                    ),
+                };
+                let mask = egui::Color32::from_black_alpha(200);
+                for bar in [
+                    egui::Rect::from_min_max(rect.min, egui::pos2(rect.max.x, frame.min.y)),
+                    egui::Rect::from_min_max(egui::pos2(rect.min.x, frame.max.y), rect.max),
+                    egui::Rect::from_min_max(rect.min, egui::pos2(frame.min.x, rect.max.y)),
+                    egui::Rect::from_min_max(egui::pos2(frame.max.x, rect.min.y), rect.max),
+                ] {
+                    if bar.is_positive() {
+                        painter.rect_filled(bar, 0.0, mask);
+                    }
+                }
+                frame
+            }
+            None => rect,
+        };
+        let stroke = egui::Stroke::new(1.0, egui::Color32::from_white_alpha(120));
+        if self.show_thirds {
+            for t in [1.0 / 3.0, 2.0 / 3.0] {
+                let x = egui::lerp(frame.x_range(), t);
+                let y = egui::lerp(frame.y_range(), t);
+                painter.vline(x, frame.y_range(), stroke);
+                painter.hline(frame.x_range(), y, stroke);
+            }
+        }
+        if self.show_center {
+            let size = frame.height() * 0.03;
+            let center = frame.center();
+            painter.hline(center.x - size..=center.x + size, center.y, stroke);
+            painter.vline(center.x, center.y - size..=center.y + size, stroke);
+        }
+        if self.show_safe_areas {
+            for scale in [0.93, 0.9] {
+                painter.rect_stroke(
+                    egui::Rect::from_center_size(frame.center(), frame.size() * scale),
+                    0.0,
+                    stroke,
+                    egui::StrokeKind::Inside,
+                );
+            }
+        }
+    }
+}
+
+pub struct OverlayResource {
+    grid_pipeline: wgpu::RenderPipeline,
+    focus_pipeline: wgpu::RenderPipeline,
+    gizmo_pipeline: wgpu::RenderPipeline,
+    wire_pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    view_buffer: wgpu::Buffer,
+}
+
+struct OverlayCallback {
+    window: ViewWindow,
+    focus: bool,
+    grid: bool,
+    gizmo: bool,
+    /// Line buffer of the selected mesh with the ranges of its normals and edges to draw
+    wire: Option<(wgpu::Buffer, std::ops::Range<u32>, std::ops::Range<u32>)>,
+}
+
+impl egui_wgpu::CallbackTrait for OverlayCallback {
+    fn prepare(
+        &self,
+        _device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        _screen_descriptor: &egui_wgpu::ScreenDescriptor,
+        _encoder: &mut wgpu::CommandEncoder,
+        resources: &mut egui_wgpu::CallbackResources,
+    ) -> Vec<wgpu::CommandBuffer> {
+        let resources: &OverlayResource = resources.get().unwrap();
+        queue.write_buffer(&resources.view_buffer, 0, bytemuck::bytes_of(&self.window));
+        vec![]
+    }
+    fn paint(
+        &self,
+        _info: egui::PaintCallbackInfo,
+        render_pass: &mut wgpu::RenderPass<'static>,
+        resources: &egui_wgpu::CallbackResources,
+    ) {
+        let resources: &OverlayResource = resources.get().unwrap();
+        render_pass.set_bind_group(0, &resources.bind_group, &[]);
+        if self.focus {
+            render_pass.set_pipeline(&resources.focus_pipeline);
+            render_pass.draw(0..6, 0..1);
+        }
+        if self.grid {
+            render_pass.set_pipeline(&resources.grid_pipeline);
+            render_pass.draw(0..6, 0..1);
+        }
+        if let Some((buffer, normals, edges)) = &self.wire {
+            render_pass.set_pipeline(&resources.wire_pipeline);
+            render_pass.set_vertex_buffer(0, buffer.slice(..));
+            render_pass.draw(edges.clone(), 0..1);
+            render_pass.draw(normals.clone(), 0..1);
+        }
+        if self.gizmo {
+            render_pass.set_pipeline(&resources.gizmo_pipeline);
+            render_pass.draw(0..6, 0..1);
+        }
+    }
+}
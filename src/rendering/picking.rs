@@ -0,0 +1,254 @@
+use std::{
+    mem,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use egui_wgpu::wgpu::{self, PipelineCompilationOptions};
+
+use crate::core::engine::RENDER_SIZE;
+use crate::rendering::ray_tracer::RayTracer;
+
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+/// Render pixels per id buffer texel along each axis, hovering doesn't need full resolution ids.
+pub const PICK_SCALE: u32 = 2;
+
+/// Shared by the id pass, which only reads the leading `IdSettings` fields, and the display shader.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PickSettings {
+    width: u32,
+    height: u32,
+    samples_per_axis: u32,
+    _p1: u32,
+    hovered: i32,
+    selected: i32,
+    _p2: [u32; 2],
+}
+
+/// Traces the first hit entity of every few pixels each frame, so the entity under the cursor can be
+/// highlighted by the display shader and named in a tooltip. Reads back a single id asynchronously.
+pub struct Picker {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    pub settings_buffer: wgpu::Buffer,
+    pub ids_buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+    copy_encoded: bool,
+    map_pending: bool,
+    mapped: Arc<AtomicBool>,
+    pub enabled: bool,
+    /// Render pixel under the mouse, bottom row first like the accumulation texture
+    pub cursor: Option<(u32, u32)>,
+    /// Entity under the cursor, indexed like `SceneManager::selected_entity`
+    pub hovered: i32,
+}
+
+impl Picker {
+    pub fn new(ray_tracer: &RayTracer) -> Self {
+        let device = ray_tracer.device.clone();
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Picking Shader"),
+            source: wgpu::ShaderSource::Wgsl(ray_tracer.texture_mode.shader_source().into()),
+        });
+        let bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Picking Bind Group Layout"),
+                entries: &[
+                    // Settings
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                mem::size_of::<PickSettings>() as _
+                            ),
+                        },
+                        count: None,
+                    },
+                    // Ids
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picking Settings Buffer"),
+            size: mem::size_of::<PickSettings>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let ids_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picking Id Buffer"),
+            size: (RENDER_SIZE.0.div_ceil(PICK_SCALE) * RENDER_SIZE.1.div_ceil(PICK_SCALE))
+                as wgpu::BufferAddress
+                * mem::size_of::<u32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picking Staging Buffer"),
+            size: mem::size_of::<u32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Picking Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: ids_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Picking Pipeline Layout"),
+            bind_group_layouts: &[
+                &ray_tracer.bind_group_layout,
+                &ray_tracer.textures_bind_group_layout,
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Picking Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("ids"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            device,
+            queue: ray_tracer.queue.clone(),
+            pipeline,
+            bind_group,
+            settings_buffer,
+            ids_buffer,
+            staging_buffer,
+            copy_encoded: false,
+            map_pending: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            enabled: true,
+            cursor: None,
+            hovered: -1,
+        }
+    }
+    pub fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        ray_tracer: &RayTracer,
+        width: u32,
+        height: u32,
+        selected: i32,
+    ) {
+        let (width, height) = (width.div_ceil(PICK_SCALE), height.div_ceil(PICK_SCALE));
+        if !self.enabled {
+            self.hovered = -1;
+        }
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::cast_slice(&[PickSettings {
+                width,
+                height,
+                samples_per_axis: 1,
+                _p1: 0,
+                hovered: self.hovered,
+                selected: if self.enabled { selected } else { -1 },
+                _p2: [0; 2],
+            }]),
+        );
+        if !self.enabled {
+            return;
+        }
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Picking Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &ray_tracer.bind_group, &[]);
+            compute_pass.set_bind_group(1, &ray_tracer.textures_bind_group, &[]);
+            compute_pass.set_bind_group(2, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE.0),
+                height.div_ceil(WORKGROUP_SIZE.1),
+                1,
+            );
+        }
+        let Some((x, y)) = self.cursor else {
+            self.hovered = -1;
+            return;
+        };
+        let (x, y) = (x / PICK_SCALE, y / PICK_SCALE);
+        if !self.map_pending && x < width && y < height {
+            let size = mem::size_of::<u32>() as wgpu::BufferAddress;
+            encoder.copy_buffer_to_buffer(
+                &self.ids_buffer,
+                (y * width + x) as wgpu::BufferAddress * size,
+                &self.staging_buffer,
+                0,
+                size,
+            );
+            self.copy_encoded = true;
+        }
+    }
+    /// Must be called after the encoder passed to `render` has been submitted.
+    pub fn request_readback(&mut self) {
+        if !self.copy_encoded {
+            return;
+        }
+        self.copy_encoded = false;
+        self.map_pending = true;
+        let mapped = self.mapped.clone();
+        self.staging_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::SeqCst);
+                }
+            });
+    }
+    /// Updates `hovered` once the id under the cursor has been copied back.
+    pub fn poll_readback(&mut self) {
+        if !self.map_pending {
+            return;
+        }
+        let _ = self.device.poll(wgpu::PollType::Poll);
+        if !self.mapped.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        {
+            let data = self.staging_buffer.slice(..).get_mapped_range();
+            let id = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            // Ids are offset by one so zero is a miss
+            self.hovered = if self.cursor.is_some() {
+                id as i32 - 1
+            } else {
+                -1
+            };
+        }
+        self.staging_buffer.unmap();
+        self.map_pending = false;
+    }
+}
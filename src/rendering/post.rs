@@ -0,0 +1,457 @@
+use std::{collections::HashMap, mem, sync::Arc};
+
+use egui_wgpu::wgpu::{self, PipelineCompilationOptions, TextureView};
+
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostEffect {
+    Bloom,
+    Vignette,
+    ChromaticAberration,
+    FilmGrain,
+}
+
+impl PostEffect {
+    pub fn name(self) -> &'static str {
+        match self {
+            PostEffect::Bloom => "Bloom",
+            PostEffect::Vignette => "Vignette",
+            PostEffect::ChromaticAberration => "Chromatic Aberration",
+            PostEffect::FilmGrain => "Film Grain",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PostLayer {
+    pub effect: PostEffect,
+    pub enabled: bool,
+}
+
+/// Ordered post effects and their settings. The engine keeps a global stack which a scene can override.
+#[derive(Debug, Clone)]
+pub struct PostStack {
+    /// Applied top to bottom
+    pub layers: Vec<PostLayer>,
+    /// Luminance after exposure above which pixels bloom
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    /// Blur radius in pixels
+    pub bloom_radius: f32,
+    pub vignette_strength: f32,
+    /// Distance from the centre, as a fraction of the way to the corners, where darkening starts
+    pub vignette_radius: f32,
+    /// Colour fringe offset in pixels at the edges of the frame
+    pub chromatic_aberration: f32,
+    pub grain_strength: f32,
+}
+
+impl Default for PostStack {
+    fn default() -> Self {
+        Self {
+            layers: [
+                PostEffect::Bloom,
+                PostEffect::ChromaticAberration,
+                PostEffect::Vignette,
+                PostEffect::FilmGrain,
+            ]
+            .map(|effect| PostLayer {
+                effect,
+                enabled: false,
+            })
+            .to_vec(),
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.5,
+            bloom_radius: 32.0,
+            vignette_strength: 0.5,
+            vignette_radius: 0.5,
+            chromatic_aberration: 4.0,
+            grain_strength: 0.1,
+        }
+    }
+}
+
+impl PostStack {
+    pub fn is_active(&self) -> bool {
+        self.layers.iter().any(|l| l.enabled)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostSettings {
+    width: u32,
+    height: u32,
+    source_width: u32,
+    source_height: u32,
+    frame: u32,
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    bloom_radius: f32,
+    vignette_strength: f32,
+    vignette_radius: f32,
+    chromatic_aberration: f32,
+    grain_strength: f32,
+    _p1: [f32; 3],
+}
+
+/// Textures a post pass can read from or write to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Slot {
+    Accumulation,
+    Preview,
+    Ping,
+    Pong,
+    BloomA,
+    BloomB,
+    Output,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Entry {
+    Resolve,
+    BloomExtract,
+    BloomBlurH,
+    BloomBlurV,
+    BloomComposite,
+    Vignette,
+    ChromaticAberration,
+    FilmGrain,
+}
+
+impl Entry {
+    const ALL: [Entry; 8] = [
+        Entry::Resolve,
+        Entry::BloomExtract,
+        Entry::BloomBlurH,
+        Entry::BloomBlurV,
+        Entry::BloomComposite,
+        Entry::Vignette,
+        Entry::ChromaticAberration,
+        Entry::FilmGrain,
+    ];
+    fn entry_point(self) -> &'static str {
+        match self {
+            Entry::Resolve => "resolve",
+            Entry::BloomExtract => "bloom_extract",
+            Entry::BloomBlurH => "bloom_blur_h",
+            Entry::BloomBlurV => "bloom_blur_v",
+            Entry::BloomComposite => "bloom_composite",
+            Entry::Vignette => "vignette",
+            Entry::ChromaticAberration => "chromatic_aberration",
+            Entry::FilmGrain => "film_grain",
+        }
+    }
+}
+
+/// One dispatch. `aux` is only read by the bloom composite but must never alias `output`.
+struct Step {
+    entry: Entry,
+    source: Slot,
+    aux: Slot,
+    output: Slot,
+}
+
+/// Runs the post stack between accumulation and display, leaving the result in `view`.
+pub struct PostProcess {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipelines: HashMap<Entry, wgpu::ComputePipeline>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    settings_buffer: wgpu::Buffer,
+    views: HashMap<Slot, TextureView>,
+    /// Bind groups by (source, aux, output), rebuilt when the accumulation target changes
+    bind_groups: HashMap<(Slot, Slot, Slot), wgpu::BindGroup>,
+    width: u32,
+    height: u32,
+    frame: u32,
+    /// Stack used by scenes without their own
+    pub stack: PostStack,
+    /// Final image, displayed in place of the accumulation texture while active
+    pub view: TextureView,
+    /// Whether the display should show `view` this frame
+    pub active: bool,
+    steps: Vec<Step>,
+}
+
+impl PostProcess {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Post Process Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/post.wgsl").into()),
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Post Process Bind Group Layout"),
+                entries: &[
+                    // Settings
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                mem::size_of::<PostSettings>() as _
+                            ),
+                        },
+                        count: None,
+                    },
+                    // Source
+                    texture_entry(1),
+                    // Aux
+                    texture_entry(2),
+                    // Output
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines = Entry::ALL
+            .into_iter()
+            .map(|entry| {
+                let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry.entry_point()),
+                    layout: Some(&pipeline_layout),
+                    module: &shader,
+                    entry_point: Some(entry.entry_point()),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    cache: None,
+                });
+                (entry, pipeline)
+            })
+            .collect();
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Process Settings Buffer"),
+            size: mem::size_of::<PostSettings>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let create_view = |label| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba32Float,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::STORAGE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let view = create_view("Post Process Output Texture");
+        let views = HashMap::from([
+            (Slot::Ping, create_view("Post Process Ping Texture")),
+            (Slot::Pong, create_view("Post Process Pong Texture")),
+            (Slot::BloomA, create_view("Bloom Texture A")),
+            (Slot::BloomB, create_view("Bloom Texture B")),
+            (Slot::Output, view.clone()),
+        ]);
+
+        Self {
+            device,
+            queue,
+            pipelines,
+            bind_group_layout,
+            settings_buffer,
+            views,
+            bind_groups: HashMap::new(),
+            width,
+            height,
+            frame: 0,
+            stack: PostStack::default(),
+            view,
+            active: false,
+            steps: vec![],
+        }
+    }
+    /// Points the stack at a tab's accumulation texture and the upscaled preview.
+    pub fn set_target(&mut self, accumulation_view: &TextureView, preview_view: &TextureView) {
+        self.views
+            .insert(Slot::Accumulation, accumulation_view.clone());
+        self.views.insert(Slot::Preview, preview_view.clone());
+        self.bind_groups.clear();
+    }
+    /// Plans this frame's passes. `source_size` is the filled region of the accumulation texture,
+    /// ignored when reading the full resolution preview.
+    pub fn prepare(
+        &mut self,
+        stack: &PostStack,
+        preview: bool,
+        source_size: (u32, u32),
+        exposure: f32,
+    ) {
+        self.active = preview || stack.is_active();
+        self.steps.clear();
+        if !self.active {
+            return;
+        }
+        let (source, source_size) = if preview {
+            (Slot::Preview, (self.width, self.height))
+        } else {
+            (Slot::Accumulation, source_size)
+        };
+        self.frame = self.frame.wrapping_add(1);
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::cast_slice(&[PostSettings {
+                width: self.width,
+                height: self.height,
+                source_width: source_size.0,
+                source_height: source_size.1,
+                frame: self.frame,
+                exposure,
+                bloom_threshold: stack.bloom_threshold,
+                bloom_intensity: stack.bloom_intensity,
+                bloom_radius: stack.bloom_radius,
+                vignette_strength: stack.vignette_strength,
+                vignette_radius: stack.vignette_radius,
+                chromatic_aberration: stack.chromatic_aberration,
+                grain_strength: stack.grain_strength,
+                _p1: [0.0; 3],
+            }]),
+        );
+
+        let effects: Vec<PostEffect> = stack
+            .layers
+            .iter()
+            .filter(|l| l.enabled)
+            .map(|l| l.effect)
+            .collect();
+        // Effects ping-pong between two textures, the last one writing straight to the output
+        let count = effects.len();
+        let target = |i: usize| {
+            if i + 1 == count {
+                Slot::Output
+            } else if i.is_multiple_of(2) {
+                Slot::Ping
+            } else {
+                Slot::Pong
+            }
+        };
+        let mut current = if effects.is_empty() {
+            Slot::Output
+        } else {
+            Slot::Pong
+        };
+        self.steps.push(Step {
+            entry: Entry::Resolve,
+            source,
+            aux: Slot::BloomA,
+            output: current,
+        });
+        for (i, effect) in effects.into_iter().enumerate() {
+            let output = target(i);
+            let mut push = |entry, source, aux, output| {
+                self.steps.push(Step {
+                    entry,
+                    source,
+                    aux,
+                    output,
+                })
+            };
+            match effect {
+                PostEffect::Bloom => {
+                    push(Entry::BloomExtract, current, Slot::BloomB, Slot::BloomA);
+                    push(Entry::BloomBlurH, Slot::BloomA, Slot::BloomA, Slot::BloomB);
+                    push(Entry::BloomBlurV, Slot::BloomB, Slot::BloomB, Slot::BloomA);
+                    push(Entry::BloomComposite, current, Slot::BloomA, output);
+                }
+                PostEffect::Vignette => push(Entry::Vignette, current, Slot::BloomA, output),
+                PostEffect::ChromaticAberration => {
+                    push(Entry::ChromaticAberration, current, Slot::BloomA, output)
+                }
+                PostEffect::FilmGrain => push(Entry::FilmGrain, current, Slot::BloomA, output),
+            }
+            current = output;
+        }
+        for step in self.steps.iter() {
+            let key = (step.source, step.aux, step.output);
+            if !self.bind_groups.contains_key(&key) {
+                let bind_group = self.create_bind_group(key);
+                self.bind_groups.insert(key, bind_group);
+            }
+        }
+    }
+    fn create_bind_group(&self, (source, aux, output): (Slot, Slot, Slot)) -> wgpu::BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Post Process Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.views[&source]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.views[&aux]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.views[&output]),
+                },
+            ],
+        })
+    }
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder) {
+        for step in self.steps.iter() {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some(step.entry.entry_point()),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipelines[&step.entry]);
+            compute_pass.set_bind_group(
+                0,
+                &self.bind_groups[&(step.source, step.aux, step.output)],
+                &[],
+            );
+            compute_pass.dispatch_workgroups(
+                self.width.div_ceil(WORKGROUP_SIZE.0),
+                self.height.div_ceil(WORKGROUP_SIZE.1),
+                1,
+            );
+        }
+    }
+}
@@ -1,25 +1,260 @@
-use std::{mem, num::NonZeroU32, sync::Arc};
+use std::{
+    collections::HashMap,
+    mem,
+    num::NonZeroU32,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use glam::{Mat4, Vec4};
+use image::{
+    RgbaImage,
+    imageops::{self, FilterType},
+};
+use rayon::prelude::*;
 
 use crate::core::{
     app::Params,
     bvh::{BVH, Node, PackedTriangle},
+    engine::RENDER_SIZE,
 };
 use crate::scene::{
-    components::geometry::{mesh::MeshUniform, sphere::Sphere},
+    camera::CameraUniform,
+    components::{
+        environment::{AliasEntry, EnvironmentDistribution},
+        geometry::{mesh::MeshUniform, sphere::Sphere},
+        material::{MAX_MATERIALS, MaterialUniform},
+        portal::{MAX_PORTALS, PortalUniform},
+        volume::{BrickMap, MAX_VOLUMES, VolumeUniform},
+    },
     scene::{Scene, SceneUniform},
 };
 use egui_wgpu::wgpu::{
     self, Extent3d, PipelineCompilationOptions, TextureView, wgt::TextureViewDescriptor,
 };
 
-const WORKGROUP_SIZE: (u32, u32) = (8, 8);
-const MAX_MESHES: u64 = 400;
-const MAX_SPHERS: u64 = 500;
-const MAX_TRIANGLES: u64 = 275000 * 5;
+pub const MAX_MESHES: u64 = 400;
+pub const MAX_SPHERS: u64 = 500;
+pub const MAX_TRIANGLES: u64 = 275000 * 5;
 pub const MAX_TEXTURES: u64 = 64;
+/// Largest side of a texture layer in `TextureMode::Layered`, bigger textures are scaled down
+pub const LAYER_SIZE: u32 = 512;
+/// Brick tables and bricks of every volume share this many 4 byte words
+const MAX_VOXEL_WORDS: u64 = 1 << 23;
+/// Smallest number of elements a scene buffer is created with
+const MIN_CAPACITY: u64 = 64;
+
+/// Every level below `image` down to 1x1, each half the size of the one before, for sampling
+/// textures at the level a ray's footprint covers.
+fn mip_levels(image: &RgbaImage) -> Vec<RgbaImage> {
+    let mut levels: Vec<RgbaImage> = vec![];
+    let (mut width, mut height) = image.dimensions();
+    while width > 1 || height > 1 {
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+        let previous = levels.last().unwrap_or(image);
+        levels.push(imageops::resize(
+            previous,
+            width,
+            height,
+            FilterType::Triangle,
+        ));
+    }
+    levels
+}
+
+/// How material textures are bound. Most GPUs index an array of separate textures per pixel, the
+/// rest get every texture resampled into a layer of one texture array instead, which costs detail
+/// on large textures but renders the same scenes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureMode {
+    BindingArray,
+    Layered,
+}
+
+impl TextureMode {
+    /// Device features `BindingArray` needs.
+    pub const FEATURES: wgpu::Features = wgpu::Features::TEXTURE_BINDING_ARRAY
+        .union(wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING);
+    pub fn of(device: &wgpu::Device) -> Self {
+        match device.features().contains(Self::FEATURES) {
+            true => TextureMode::BindingArray,
+            false => TextureMode::Layered,
+        }
+    }
+    /// The ray tracing shader with texture bindings for this mode, shared by every pipeline
+    /// built from it.
+    pub fn shader_source(self) -> String {
+        let source = include_str!("../../shaders/ray_tracer.wgsl");
+        if self == TextureMode::BindingArray {
+            return source.to_owned();
+        }
+        let start = source
+            .find("// Texture bindings")
+            .expect("ray_tracer.wgsl has texture bindings");
+        let end = source[start..]
+            .find("// End of texture bindings")
+            .map(|i| start + i)
+            .expect("ray_tracer.wgsl marks the end of its texture bindings");
+        [
+            &source[..start],
+            include_str!("../../shaders/layered_textures.wgsl"),
+            &source[end..],
+        ]
+        .concat()
+    }
+}
+
+/// GPU copies of a scene's textures, remembered so a slot whose image changes can be uploaded on
+/// its own without recreating the rest. Parked with a tab alongside its textures bind group.
+#[derive(Default)]
+pub struct TextureSlots {
+    /// Image each slot was last uploaded from
+    images: Vec<Arc<RgbaImage>>,
+    /// A texture per slot in `TextureMode::BindingArray`, or the one array holding every slot as
+    /// a layer in `TextureMode::Layered`
+    textures: Vec<wgpu::Texture>,
+}
+
+impl TextureSlots {
+    /// Bytes of the full size images uploaded.
+    pub fn bytes(&self) -> u64 {
+        self.images
+            .iter()
+            .map(|t| t.width() as u64 * t.height() as u64 * 4)
+            .sum()
+    }
+}
+
+/// Elements each scene buffer has room for. Buffers start small, grow to the next power of two
+/// when the scene outgrows them and are fitted again whenever a scene is loaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Capacity {
+    triangles: u64,
+    nodes: u64,
+    spheres: u64,
+    meshes: u64,
+    voxel_words: u64,
+}
+
+impl Capacity {
+    const MIN: Capacity = Capacity {
+        triangles: MIN_CAPACITY,
+        nodes: MIN_CAPACITY,
+        spheres: MIN_CAPACITY,
+        meshes: MIN_CAPACITY,
+        voxel_words: MIN_CAPACITY,
+    };
+    fn fit(need: u64, max: u64) -> u64 {
+        need.max(MIN_CAPACITY).next_power_of_two().min(max)
+    }
+    /// Room for everything in `scene`, up to the limits the shader is written for.
+    fn for_scene(scene: &Scene) -> Self {
+        let voxel_words = scene.volumes[..scene.volumes.len().min(MAX_VOLUMES)]
+            .iter()
+            .map(|v| v.grid.words() as u64)
+            .sum();
+        Self {
+            triangles: Self::fit(scene.bvh_data.triangles.len() as u64, MAX_TRIANGLES),
+            nodes: Self::fit(scene.bvh_data.nodes.len() as u64, BVH::MAX_NODES as u64),
+            spheres: Self::fit(scene.spheres.len() as u64, MAX_SPHERS),
+            meshes: Self::fit(scene.bvh_data.mesh_uniforms.len() as u64, MAX_MESHES),
+            voxel_words: Self::fit(voxel_words, MAX_VOXEL_WORDS),
+        }
+    }
+    fn max(self, other: Capacity) -> Self {
+        Self {
+            triangles: self.triangles.max(other.triangles),
+            nodes: self.nodes.max(other.nodes),
+            spheres: self.spheres.max(other.spheres),
+            meshes: self.meshes.max(other.meshes),
+            voxel_words: self.voxel_words.max(other.voxel_words),
+        }
+    }
+}
+
+/// How light is gathered, selected through `Params::integrator`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    PathTracing = 0,
+    /// Emission plus one light sample per emitter at the first hit
+    DirectLighting,
+    /// Perfect mirror and refraction chains, lit by light samples at diffuse hits
+    Whitted,
+}
+
+impl Integrator {
+    pub const ALL: [Integrator; 3] = [
+        Integrator::PathTracing,
+        Integrator::DirectLighting,
+        Integrator::Whitted,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Integrator::PathTracing => "Path Tracing",
+            Integrator::DirectLighting => "Direct Lighting",
+            Integrator::Whitted => "Whitted",
+        }
+    }
+}
 
-#[allow(unused)]
+/// Whole-scene material override selected through `Params::clay`, for judging the lighting on
+/// its own. Emissive materials are always left as they are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClayMode {
+    Off = 0,
+    /// Every surface the same matte grey
+    Clay,
+    /// Matte, in the brightness of each surface's own colour or texture
+    Textured,
+}
+
+impl ClayMode {
+    pub const ALL: [ClayMode; 3] = [ClayMode::Off, ClayMode::Clay, ClayMode::Textured];
+    pub fn name(self) -> &'static str {
+        match self {
+            ClayMode::Off => "Off",
+            ClayMode::Clay => "Clay",
+            ClayMode::Textured => "Textured Clay",
+        }
+    }
+}
+
+/// Settings the main pipeline is compiled for, so the megakernel drops the branches they would
+/// otherwise take at runtime and the registers those hold. See `RayTracer::specialise`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineFeatures {
+    pub skybox: bool,
+    /// Blending into earlier passes, off on the first pass of every frame
+    pub accumulating: bool,
+    /// Showing one of the `DebugMode`s, which one is still read from the params
+    pub debug: bool,
+}
+
+impl PipelineFeatures {
+    /// The features of a dispatch using `params`, matching the shader's runtime checks.
+    pub fn of(params: &Params) -> Self {
+        Self {
+            skybox: params.skybox != 0,
+            accumulating: params.frames >= 1,
+            debug: params.debug_flag != 0,
+        }
+    }
+    /// Values for the shader's override constants, -1 leaves the choice to the params.
+    fn constants(features: Option<Self>) -> [(&'static str, f64); 3] {
+        let value = |on: Option<bool>| on.map_or(-1.0, |on| on as u8 as f64);
+        [
+            ("SKYBOX", value(features.map(|f| f.skybox))),
+            ("ACCUMULATING", value(features.map(|f| f.accumulating))),
+            ("DEBUG_VIEW", value(features.map(|f| f.debug))),
+        ]
+    }
+}
+
+/// Visualisations selected through `Params::debug_flag`, zero renders normally.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DebugMode {
+    /// Interpolated vertex normals, or the normal map where there is one
     Normals = 1,
     Depth,
     TexCoords,
@@ -27,6 +262,78 @@ pub enum DebugMode {
     Nodes,
     Triangles,
     NodesAndTriangles,
+    /// Face normals from the triangle winding, which differ from `Normals` on inverted faces
+    GeometricNormals,
+    /// Green where a ray hits the front of a surface, red at the back
+    Backfaces,
+    /// `UvView::Checker` over every textured surface, anything else grey
+    UvChecker,
+    /// `UvView::FalseColor` over every textured surface, anything else grey
+    UvFalseColor,
+    /// BVH nodes visited by whole paths, bounces included, as a heatmap up to `Params::debug_scale`.
+    /// Unlike `Nodes` this is what each pixel actually costs the path tracer, averaged over the
+    /// accumulated frames
+    CostNodes,
+    /// Triangles tested by whole paths, as a heatmap
+    CostTriangles,
+    /// Segments traced per path before it escapes or is ended, as a heatmap
+    CostBounces,
+}
+
+impl DebugMode {
+    pub const ALL: [DebugMode; 14] = [
+        DebugMode::Normals,
+        DebugMode::Depth,
+        DebugMode::TexCoords,
+        DebugMode::FocusDst,
+        DebugMode::Nodes,
+        DebugMode::Triangles,
+        DebugMode::NodesAndTriangles,
+        DebugMode::GeometricNormals,
+        DebugMode::Backfaces,
+        DebugMode::UvChecker,
+        DebugMode::UvFalseColor,
+        DebugMode::CostNodes,
+        DebugMode::CostTriangles,
+        DebugMode::CostBounces,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugMode::Normals => "Shading Normals",
+            DebugMode::Depth => "Depth",
+            DebugMode::TexCoords => "Texture Coordinates",
+            DebugMode::FocusDst => "Focus Distance",
+            DebugMode::Nodes => "BVH Nodes",
+            DebugMode::Triangles => "Triangle Tests",
+            DebugMode::NodesAndTriangles => "Nodes and Triangles",
+            DebugMode::GeometricNormals => "Geometric Normals",
+            DebugMode::Backfaces => "Backfaces",
+            DebugMode::UvChecker => "UV Checker",
+            DebugMode::UvFalseColor => "UV False Color",
+            DebugMode::CostNodes => "Path Node Visits",
+            DebugMode::CostTriangles => "Path Triangle Tests",
+            DebugMode::CostBounces => "Path Bounces",
+        }
+    }
+    /// Whether `debug_flag` is a cost heatmap, whose raw counts the display shader colours in and
+    /// post processing would garble.
+    pub fn is_cost(debug_flag: i32) -> bool {
+        debug_flag >= DebugMode::CostNodes as i32
+    }
+}
+
+/// Camera and render size of the previous frame, which the `reproject` pass moves its
+/// accumulation from.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
+struct ReprojectionUniform {
+    world_to_cam: [[f32; 4]; 4],
+    cam_to_world: [[f32; 4]; 4],
+    view_params: [f32; 3],
+    /// Set on frames the history is written and blended in
+    enabled: u32,
+    size: [u32; 2],
+    _p1: [u32; 2],
 }
 
 pub struct RayTracer {
@@ -36,20 +343,66 @@ pub struct RayTracer {
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub bind_group: Option<wgpu::BindGroup>,
     pub textures_bind_group_layout: wgpu::BindGroupLayout,
+    pub texture_mode: TextureMode,
     pub textures_bind_group: Option<wgpu::BindGroup>,
+    /// Textures bound by `textures_bind_group`, see `update_textures`
+    pub texture_slots: TextureSlots,
     pub sampler: wgpu::Sampler,
     pub sphere_buffer: wgpu::Buffer,
     pub triangle_buffer: wgpu::Buffer,
     pub mesh_buffer: wgpu::Buffer,
     pub scene_buffer: wgpu::Buffer,
     pub bvh_nodes_buffer: wgpu::Buffer,
+    pub volume_buffer: wgpu::Buffer,
+    pub voxel_buffer: wgpu::Buffer,
+    pub portal_buffer: wgpu::Buffer,
+    pub material_buffer: wgpu::Buffer,
+    /// Alias table importance sampling the sky, see `EnvironmentDistribution`
+    environment_texture: wgpu::Texture,
+    environment_view: TextureView,
+    /// Sun the environment table was last baked for, it only changes with the sky
+    environment_sun: Option<[f32; 4]>,
+    /// Grids currently in `voxel_buffer`, the bricks are only rewritten when these change
+    uploaded_volumes: Vec<Arc<BrickMap>>,
+    capacity: Capacity,
+    /// Accumulation texture and params the bind group was last made for, so it can be rebuilt
+    /// when the scene buffers are replaced
+    target: Option<(TextureView, wgpu::Buffer)>,
+    /// Fills the unused slots of the texture array
+    dummy_view: TextureView,
+    /// Textures or buffers were created or fully rewritten since `take_reallocated`
+    reallocated: bool,
+    /// Full resolution primary hit normals and distances, see `render_guide`
+    pub guide_view: wgpu::TextureView,
+    guide_pipeline: wgpu::ComputePipeline,
+    /// Last frame's accumulation reprojected to the current camera, see `update_reprojection`
+    history_view: wgpu::TextureView,
+    reprojection_buffer: wgpu::Buffer,
+    reproject_pipeline: wgpu::ComputePipeline,
+    /// Camera and render size the accumulation texture currently holds
+    previous_frame: Option<(CameraUniform, (u32, u32))>,
+    reproject_active: bool,
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    pub workgroup_size: (u32, u32),
+    /// Compile the main pipeline for the current `PipelineFeatures` rather than branching on them
+    pub specialised: bool,
+    /// Features `pipeline` was compiled for, `None` for the generic one
+    features: Option<PipelineFeatures>,
+    /// Every main pipeline compiled so far, a handful at most since toggles are few
+    pipelines: HashMap<((u32, u32), Option<PipelineFeatures>), wgpu::ComputePipeline>,
+    /// Benchmark the workgroup sizes once a scene is loaded
+    pub auto_tune_requested: bool,
+    /// Average dispatch time of each size from the last auto-tune
+    pub auto_tune_results: Vec<((u32, u32), Duration)>,
 }
 
 impl RayTracer {
     pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
+        let texture_mode = TextureMode::of(&device);
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("RayTracer Compute Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/ray_tracer.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(texture_mode.shader_source().into()),
         });
         let bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -134,6 +487,103 @@ impl RayTracer {
                         },
                         count: None,
                     },
+                    // Preview Guide
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    // Volumes
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Voxel Bricks
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Portals
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(mem::size_of::<
+                                [PortalUniform; MAX_PORTALS],
+                            >()
+                                as _),
+                        },
+                        count: None,
+                    },
+                    // Material palette
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 11,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(mem::size_of::<
+                                [MaterialUniform; MAX_MATERIALS],
+                            >()
+                                as _),
+                        },
+                        count: None,
+                    },
+                    // Reprojection
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 12,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(mem::size_of::<
+                                ReprojectionUniform,
+                            >()
+                                as _),
+                        },
+                        count: None,
+                    },
+                    // History
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 13,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::ReadWrite,
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    // Environment alias table, one entry per texel
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 14,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
                 ],
             });
         let textures_bind_group_layout =
@@ -146,10 +596,16 @@ impl RayTracer {
                         visibility: wgpu::ShaderStages::COMPUTE,
                         ty: wgpu::BindingType::Texture {
                             sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
+                            view_dimension: match texture_mode {
+                                TextureMode::BindingArray => wgpu::TextureViewDimension::D2,
+                                TextureMode::Layered => wgpu::TextureViewDimension::D2Array,
+                            },
                             multisampled: false,
                         },
-                        count: NonZeroU32::new(MAX_TEXTURES as u32),
+                        count: match texture_mode {
+                            TextureMode::BindingArray => NonZeroU32::new(MAX_TEXTURES as u32),
+                            TextureMode::Layered => None,
+                        },
                     },
                     // Sampler
                     wgpu::BindGroupLayoutEntry {
@@ -168,31 +624,99 @@ impl RayTracer {
             mapped_at_creation: false,
         });
 
-        let triangle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("RayTracer Triangle Buffer"),
-            size: (MAX_TRIANGLES * std::mem::size_of::<PackedTriangle>() as wgpu::BufferAddress),
+        let capacity = Capacity::MIN;
+        let triangle_buffer = RayTracer::create_triangle_buffer(&device, capacity.triangles);
+        let sphere_buffer = RayTracer::create_sphere_buffer(&device, capacity.spheres);
+        let mesh_buffer = RayTracer::create_mesh_buffer(&device, capacity.meshes);
+        let bvh_nodes_buffer = RayTracer::create_nodes_buffer(&device, capacity.nodes);
+        let volume_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("RayTracer Volume Buffer"),
+            size: (MAX_VOLUMES as u64
+                * std::mem::size_of::<VolumeUniform>() as wgpu::BufferAddress),
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
             mapped_at_creation: false,
         });
+        let voxel_buffer = RayTracer::create_voxel_buffer(&device, capacity.voxel_words);
 
-        let sphere_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("RayTracer Sphere Buffer"),
-            size: (MAX_SPHERS * std::mem::size_of::<Sphere>() as wgpu::BufferAddress),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        let portal_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("RayTracer Portal Buffer"),
+            size: mem::size_of::<[PortalUniform; MAX_PORTALS]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
             mapped_at_creation: false,
         });
-        let mesh_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("RayTracer Mesh Buffer"),
-            size: (MAX_MESHES * std::mem::size_of::<MeshUniform>() as wgpu::BufferAddress),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+        let material_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("RayTracer Material Palette Buffer"),
+            size: mem::size_of::<[MaterialUniform; MAX_MATERIALS]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
             mapped_at_creation: false,
         });
-        let bvh_nodes_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("RayTracer Nodes Buffer"),
-            size: (BVH::MAX_NODES as u64 * std::mem::size_of::<Node>() as wgpu::BufferAddress),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+
+        let guide_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("RayTracer Guide Texture"),
+            size: Extent3d {
+                width: RENDER_SIZE.0,
+                height: RENDER_SIZE.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let guide_view = guide_texture.create_view(&TextureViewDescriptor::default());
+        let history_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("RayTracer History Texture"),
+            size: Extent3d {
+                width: RENDER_SIZE.0,
+                height: RENDER_SIZE.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let history_view = history_texture.create_view(&TextureViewDescriptor::default());
+        let reprojection_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("RayTracer Reprojection Buffer"),
+            size: mem::size_of::<ReprojectionUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
             mapped_at_creation: false,
         });
+        let environment_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("RayTracer Environment Table"),
+            size: Extent3d {
+                width: EnvironmentDistribution::SIZE.0,
+                height: EnvironmentDistribution::SIZE.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let environment_view = environment_texture.create_view(&TextureViewDescriptor::default());
+        let dummy_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("RayTracer Dummy Texture"),
+            size: Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let dummy_view = dummy_texture.create_view(&TextureViewDescriptor::default());
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
@@ -210,14 +734,26 @@ impl RayTracer {
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("RayTracer Pipeline"),
+        let guide_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("RayTracer Guide Pipeline"),
             layout: Some(&pipeline_layout),
             module: &shader,
-            entry_point: Some("main"),
+            entry_point: Some("guide"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        let reproject_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("RayTracer Reproject Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("reproject"),
             compilation_options: PipelineCompilationOptions::default(),
             cache: None,
         });
+        let workgroup_size = RayTracer::WORKGROUP_SIZES[0];
+        let pipeline =
+            RayTracer::create_pipeline(&device, &pipeline_layout, &shader, workgroup_size, None);
+        let pipelines = HashMap::from([((workgroup_size, None), pipeline.clone())]);
         Self {
             device,
             queue,
@@ -225,39 +761,376 @@ impl RayTracer {
             bind_group_layout,
             bind_group: None,
             textures_bind_group_layout,
+            texture_mode,
             textures_bind_group: None,
+            texture_slots: TextureSlots::default(),
             sampler,
             triangle_buffer,
             sphere_buffer,
             mesh_buffer,
             scene_buffer,
             bvh_nodes_buffer,
+            volume_buffer,
+            voxel_buffer,
+            portal_buffer,
+            material_buffer,
+            environment_texture,
+            environment_view,
+            environment_sun: None,
+            uploaded_volumes: vec![],
+            capacity,
+            target: None,
+            dummy_view,
+            reallocated: false,
+            guide_view,
+            guide_pipeline,
+            history_view,
+            reprojection_buffer,
+            reproject_pipeline,
+            previous_frame: None,
+            reproject_active: false,
+            shader,
+            pipeline_layout,
+            workgroup_size,
+            specialised: true,
+            features: None,
+            pipelines,
+            auto_tune_requested: true,
+            auto_tune_results: vec![],
         }
     }
-    pub fn load_scene_gpu_resources(&mut self, scene: &Scene) {
-        let mut gpu_textures = Vec::new();
-        let mut gpu_texture_views = Vec::new();
-        let mut loaded_textures: u32 = 0;
-        for (i, image) in scene.textures.iter().enumerate() {
-            loaded_textures += 1;
-            let t = self.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some(format!("t_{}", i).as_str()),
-                size: Extent3d {
-                    width: image.width(),
-                    height: image.height(),
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
-            });
+    /// Workgroup sizes the main pipeline can be specialised for.
+    pub const WORKGROUP_SIZES: [(u32, u32); 3] = [(8, 8), (16, 8), (16, 16)];
+    /// Dispatches timed per size when auto-tuning, after one untimed warm up.
+    const AUTO_TUNE_DISPATCHES: u32 = 4;
 
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        workgroup_size: (u32, u32),
+        features: Option<PipelineFeatures>,
+    ) -> wgpu::ComputePipeline {
+        let [skybox, accumulating, debug] = PipelineFeatures::constants(features);
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("RayTracer Pipeline"),
+            layout: Some(layout),
+            module: shader,
+            entry_point: Some("main"),
+            compilation_options: PipelineCompilationOptions {
+                constants: &[
+                    ("WORKGROUP_X", workgroup_size.0 as f64),
+                    ("WORKGROUP_Y", workgroup_size.1 as f64),
+                    skybox,
+                    accumulating,
+                    debug,
+                ],
+                ..Default::default()
+            },
+            cache: None,
+        })
+    }
+    /// Makes the main pipeline the one for `workgroup_size` and `features`, compiling it the first
+    /// time the pair is used.
+    fn select_pipeline(&mut self, workgroup_size: (u32, u32), features: Option<PipelineFeatures>) {
+        self.pipeline = self
+            .pipelines
+            .entry((workgroup_size, features))
+            .or_insert_with(|| {
+                log::info!(
+                    "Compiling ray tracer pipeline for {}x{} workgroups with {:?}",
+                    workgroup_size.0,
+                    workgroup_size.1,
+                    features
+                );
+                RayTracer::create_pipeline(
+                    &self.device,
+                    &self.pipeline_layout,
+                    &self.shader,
+                    workgroup_size,
+                    features,
+                )
+            })
+            .clone();
+        self.workgroup_size = workgroup_size;
+        self.features = features;
+    }
+    pub fn set_workgroup_size(&mut self, workgroup_size: (u32, u32)) {
+        if workgroup_size == self.workgroup_size {
+            return;
+        }
+        self.select_pipeline(workgroup_size, self.features);
+        log::info!(
+            "Ray tracer workgroup size set to {}x{}",
+            workgroup_size.0,
+            workgroup_size.1
+        );
+    }
+    /// Switches the main pipeline to the variant for the params the next dispatch is given,
+    /// or the generic one when `specialised` is off.
+    pub fn specialise(&mut self, params: &Params) {
+        let features = self.specialised.then(|| PipelineFeatures::of(params));
+        if features != self.features {
+            self.select_pipeline(self.workgroup_size, features);
+        }
+    }
+    /// Main pipelines compiled so far.
+    pub fn pipeline_count(&self) -> usize {
+        self.pipelines.len()
+    }
+    /// Times full frame dispatches with every workgroup size on this GPU and keeps the fastest.
+    /// Overwrites the accumulation texture, so the frame should be reset afterwards.
+    pub fn auto_tune(&mut self, width: u32, height: u32) {
+        self.auto_tune_requested = false;
+        self.auto_tune_results.clear();
+        for workgroup_size in RayTracer::WORKGROUP_SIZES {
+            self.set_workgroup_size(workgroup_size);
+            let mut elapsed = Duration::ZERO;
+            for i in 0..=RayTracer::AUTO_TUNE_DISPATCHES {
+                let mut encoder =
+                    self.device
+                        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                            label: Some("RayTracer Auto Tune Encoder"),
+                        });
+                self.render(&mut encoder, width, height);
+                let start = Instant::now();
+                self.queue.submit(std::iter::once(encoder.finish()));
+                if let Err(e) = self.device.poll(wgpu::MaintainBase::Wait) {
+                    log::warn!("Auto-tune failed: {}", e);
+                    return;
+                }
+                if i > 0 {
+                    elapsed += start.elapsed();
+                }
+            }
+            self.auto_tune_results
+                .push((workgroup_size, elapsed / RayTracer::AUTO_TUNE_DISPATCHES));
+        }
+        if let Some(&(fastest, time)) = self.auto_tune_results.iter().min_by_key(|(_, t)| *t) {
+            log::info!(
+                "Auto-tune picked {}x{} workgroups ({:?} per frame)",
+                fastest.0,
+                fastest.1,
+                time
+            );
+            self.set_workgroup_size(fastest);
+        }
+    }
+    /// Uploads a newly active scene's textures and fits the scene buffers to it, so memory use
+    /// follows the active scene rather than the largest one loaded so far.
+    pub fn load_scene_gpu_resources(&mut self, scene: &Scene) {
+        self.previous_frame = None;
+        self.update_textures(&scene.textures);
+        self.reallocated = true;
+        self.resize_buffers(Capacity::for_scene(scene));
+    }
+    /// Whether textures or buffers were created since the last call, the uploads that can stall
+    /// a frame rather than the small writes made every frame.
+    pub fn take_reallocated(&mut self) -> bool {
+        std::mem::take(&mut self.reallocated)
+    }
+    /// Releases everything specific to the current scene, leaving minimum sized buffers and an
+    /// empty texture array until the next scene is loaded.
+    pub fn unload_scene(&mut self) {
+        self.texture_slots = TextureSlots::default();
+        // The old bind group holds the textures alive, so it has to go for them to be freed
+        self.textures_bind_group = None;
+        self.update_textures(&[]);
+        self.uploaded_volumes.clear();
+        self.resize_buffers(Capacity::MIN);
+    }
+    /// Bytes of buffers and textures held for the active scene.
+    pub fn scene_memory(&self) -> u64 {
+        [
+            &self.triangle_buffer,
+            &self.sphere_buffer,
+            &self.mesh_buffer,
+            &self.bvh_nodes_buffer,
+            &self.voxel_buffer,
+        ]
+        .iter()
+        .map(|b| b.size())
+        .sum::<u64>()
+            + self.texture_slots.bytes()
+    }
+    /// Replaces any scene buffer whose capacity changed, then rebinds. Contents are not kept,
+    /// `update_buffers` rewrites them every frame.
+    fn resize_buffers(&mut self, capacity: Capacity) {
+        if capacity == self.capacity {
+            return;
+        }
+        let old = self.capacity;
+        self.reallocated = true;
+        if capacity.triangles != old.triangles {
+            self.triangle_buffer =
+                RayTracer::create_triangle_buffer(&self.device, capacity.triangles);
+        }
+        if capacity.nodes != old.nodes {
+            self.bvh_nodes_buffer = RayTracer::create_nodes_buffer(&self.device, capacity.nodes);
+        }
+        if capacity.spheres != old.spheres {
+            self.sphere_buffer = RayTracer::create_sphere_buffer(&self.device, capacity.spheres);
+        }
+        if capacity.meshes != old.meshes {
+            self.mesh_buffer = RayTracer::create_mesh_buffer(&self.device, capacity.meshes);
+        }
+        if capacity.voxel_words != old.voxel_words {
+            self.voxel_buffer = RayTracer::create_voxel_buffer(&self.device, capacity.voxel_words);
+            self.uploaded_volumes.clear();
+        }
+        self.capacity = capacity;
+        if let Some((view, params)) = self.target.clone() {
+            self.bind_group = Some(self.create_bind_group(&view, &params));
+        }
+        log::info!(
+            "Resized scene buffers to {:.1} MB",
+            self.scene_memory() as f64 / (1 << 20) as f64
+        );
+    }
+    fn create_storage_buffer(device: &wgpu::Device, label: &str, size: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+    fn create_triangle_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        let size = capacity * mem::size_of::<PackedTriangle>() as u64;
+        RayTracer::create_storage_buffer(device, "RayTracer Triangle Buffer", size)
+    }
+    fn create_nodes_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        let size = capacity * mem::size_of::<Node>() as u64;
+        RayTracer::create_storage_buffer(device, "RayTracer Nodes Buffer", size)
+    }
+    fn create_sphere_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        let size = capacity * mem::size_of::<Sphere>() as u64;
+        RayTracer::create_storage_buffer(device, "RayTracer Sphere Buffer", size)
+    }
+    fn create_mesh_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        let size = capacity * mem::size_of::<MeshUniform>() as u64;
+        RayTracer::create_storage_buffer(device, "RayTracer Mesh Buffer", size)
+    }
+    fn create_voxel_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        RayTracer::create_storage_buffer(device, "RayTracer Voxel Buffer", capacity * 4)
+    }
+    /// Uploads the slots of `textures` whose image changed since the last upload, writing into
+    /// their existing textures where the size allows and only rebinding when a slot's texture had
+    /// to be replaced. Returns how many slots were uploaded.
+    pub fn update_textures(&mut self, textures: &[Arc<RgbaImage>]) -> usize {
+        let mut slots = mem::take(&mut self.texture_slots);
+        let uploaded = match self.texture_mode {
+            TextureMode::BindingArray => self.update_texture_array(&mut slots, textures),
+            TextureMode::Layered => self.update_texture_layers(&mut slots, textures),
+        };
+        self.texture_slots = slots;
+        if uploaded > 0 {
+            self.reallocated = true;
+        }
+        uploaded
+    }
+    fn update_texture_array(
+        &mut self,
+        slots: &mut TextureSlots,
+        textures: &[Arc<RgbaImage>],
+    ) -> usize {
+        let mut rebind = self.textures_bind_group.is_none() || slots.images.len() > textures.len();
+        slots.images.truncate(textures.len());
+        slots.textures.truncate(textures.len());
+        let changed: Vec<usize> = (0..textures.len())
+            .filter(|&i| {
+                slots
+                    .images
+                    .get(i)
+                    .is_none_or(|old| !Arc::ptr_eq(old, &textures[i]))
+            })
+            .collect();
+        let mips: Vec<Vec<RgbaImage>> = changed
+            .par_iter()
+            .map(|&i| mip_levels(&textures[i]))
+            .collect();
+        // Slots past the end are always changed, so new ones are pushed in order
+        for (&i, mips) in changed.iter().zip(&mips) {
+            let image = &textures[i];
+            let fits = slots
+                .textures
+                .get(i)
+                .is_some_and(|t| t.width() == image.width() && t.height() == image.height());
+            if !fits {
+                let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some(format!("t_{}", i).as_str()),
+                    size: Extent3d {
+                        width: image.width(),
+                        height: image.height(),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1 + mips.len() as u32,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+                match i < slots.textures.len() {
+                    true => slots.textures[i] = texture,
+                    false => slots.textures.push(texture),
+                }
+                rebind = true;
+            }
+            self.write_levels(&slots.textures[i], 0, image, mips);
+            match i < slots.images.len() {
+                true => slots.images[i] = image.clone(),
+                false => slots.images.push(image.clone()),
+            }
+        }
+        if rebind {
+            let mut views: Vec<TextureView> = slots
+                .textures
+                .iter()
+                .map(|t| t.create_view(&TextureViewDescriptor::default()))
+                .collect();
+            views.resize(MAX_TEXTURES as usize, self.dummy_view.clone());
+            self.textures_bind_group =
+                Some(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("RayTracer Textures Bind Group"),
+                    layout: &self.textures_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureViewArray(
+                                &views.iter().collect::<Vec<_>>(),
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                }));
+        }
+        changed.len()
+    }
+    /// Uploads `image` and the smaller levels below it into one layer of `texture`.
+    fn write_levels(
+        &self,
+        texture: &wgpu::Texture,
+        layer: u32,
+        image: &RgbaImage,
+        mips: &[RgbaImage],
+    ) {
+        for (level, image) in std::iter::once(image).chain(mips).enumerate() {
             self.queue.write_texture(
-                t.as_image_copy(),
-                &image,
+                wgpu::TexelCopyTextureInfo {
+                    texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                image,
                 wgpu::TexelCopyBufferLayout {
                     offset: 0,
                     bytes_per_row: Some(image.width() * 4),
@@ -269,56 +1142,111 @@ impl RayTracer {
                     depth_or_array_layers: 1,
                 },
             );
-            let t_view = t.create_view(&TextureViewDescriptor::default());
-            gpu_textures.push(t);
-            gpu_texture_views.push(t_view);
-        }
-        let textures_to_fill = MAX_TEXTURES as u32 - loaded_textures;
-        for i in 0..textures_to_fill {
-            let dummy_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some(format!("d_{}", i).as_str()),
-                size: wgpu::Extent3d {
-                    width: 1,
-                    height: 1,
-                    depth_or_array_layers: 1,
+        }
+    }
+    /// Resamples every texture to one size and uploads them as the layers of a texture array.
+    /// Changed slots are written into their layers until the layer size or count has to change,
+    /// which recreates the array with every layer.
+    fn update_texture_layers(
+        &mut self,
+        slots: &mut TextureSlots,
+        textures: &[Arc<RgbaImage>],
+    ) -> usize {
+        // Slots past the last texture are 1x1 placeholders, left out rather than given full layers
+        let used = textures
+            .iter()
+            .rposition(|image| image.width() > 1 || image.height() > 1)
+            .map_or(0, |i| i + 1);
+        let size = textures[..used]
+            .iter()
+            .map(|image| image.width().max(image.height()))
+            .max()
+            .unwrap_or(1)
+            .min(LAYER_SIZE);
+        let fits = slots
+            .textures
+            .first()
+            .is_some_and(|t| t.width() == size && t.depth_or_array_layers() == used.max(1) as u32);
+        if !fits || self.textures_bind_group.is_none() {
+            *slots = TextureSlots::default();
+            let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("RayTracer Texture Layers"),
+                size: Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: used.max(1) as u32,
                 },
-                mip_level_count: 1,
+                mip_level_count: 1 + size.ilog2(),
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
                 format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
                 view_formats: &[],
             });
-
-            let dummy_view = dummy_texture.create_view(&TextureViewDescriptor::default());
-            gpu_textures.push(dummy_texture);
-            gpu_texture_views.push(dummy_view);
+            let view = texture.create_view(&TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            });
+            self.textures_bind_group =
+                Some(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("RayTracer Textures Bind Group"),
+                    layout: &self.textures_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                }));
+            slots.textures.push(texture);
         }
-
-        self.textures_bind_group =
-            Some(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("RayTracer Textures Bind Group"),
-                layout: &self.textures_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureViewArray(
-                            &gpu_texture_views.iter().collect::<Vec<_>>(),
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&self.sampler),
-                    },
-                ],
-            }));
+        let changed: Vec<usize> = (0..used)
+            .filter(|&i| {
+                slots
+                    .images
+                    .get(i)
+                    .is_none_or(|old| !Arc::ptr_eq(old, &textures[i]))
+            })
+            .collect();
+        let layers: Vec<(RgbaImage, Vec<RgbaImage>)> = changed
+            .par_iter()
+            .map(|&i| {
+                let image =
+                    imageops::resize(textures[i].as_ref(), size, size, FilterType::Triangle);
+                let mips = mip_levels(&image);
+                (image, mips)
+            })
+            .collect();
+        for (&layer, (image, mips)) in changed.iter().zip(&layers) {
+            self.write_levels(&slots.textures[0], layer as u32, image, mips);
+        }
+        slots.images = textures[..used].to_vec();
+        changed.len()
     }
     pub fn create_gpu_resources(
         &mut self,
         texture_view: &TextureView,
         params_buffer: &wgpu::Buffer,
     ) {
-        self.bind_group = Some(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        self.set_target(texture_view, params_buffer);
+        self.update_textures(&[]);
+    }
+    /// Binds the accumulation texture and params the next dispatches render into.
+    pub fn set_target(&mut self, texture_view: &TextureView, params_buffer: &wgpu::Buffer) {
+        self.bind_group = Some(self.create_bind_group(texture_view, params_buffer));
+        self.target = Some((texture_view.clone(), params_buffer.clone()));
+        self.previous_frame = None;
+    }
+    pub fn create_bind_group(
+        &self,
+        texture_view: &TextureView,
+        params_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("RayTracer Bind Group"),
             layout: &self.bind_group_layout,
             entries: &[
@@ -350,51 +1278,46 @@ impl RayTracer {
                     binding: 6,
                     resource: self.bvh_nodes_buffer.as_entire_binding(),
                 },
-            ],
-        }));
-
-        let mut gpu_textures = Vec::new();
-        let mut gpu_texture_views = Vec::new();
-        for i in 0..MAX_TEXTURES as u32 {
-            let dummy_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some(format!("d_{}", i).as_str()),
-                size: wgpu::Extent3d {
-                    width: 1,
-                    height: 1,
-                    depth_or_array_layers: 1,
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&self.guide_view),
                 },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-            });
-
-            let dummy_view = dummy_texture.create_view(&TextureViewDescriptor::default());
-            gpu_textures.push(dummy_texture);
-            gpu_texture_views.push(dummy_view);
-        }
-
-        self.textures_bind_group =
-            Some(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("RayTracer Textures Bind Group"),
-                layout: &self.textures_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureViewArray(
-                            &gpu_texture_views.iter().collect::<Vec<_>>(),
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&self.sampler),
-                    },
-                ],
-            }));
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: self.volume_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: self.voxel_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: self.portal_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: self.material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: self.reprojection_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: wgpu::BindingResource::TextureView(&self.history_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: wgpu::BindingResource::TextureView(&self.environment_view),
+                },
+            ],
+        })
     }
     pub fn update_buffers(&mut self, queue: &wgpu::Queue, scene: &mut Scene) {
+        // Build first so the capacity check sees the BVH about to be written
+        scene.bvh_nodes();
+        // Only grows here, a streamed or edited scene is fitted again when the next scene loads
+        self.resize_buffers(self.capacity.max(Capacity::for_scene(scene)));
         queue.write_buffer(
             &self.triangle_buffer,
             0,
@@ -416,20 +1339,150 @@ impl RayTracer {
             0,
             bytemuck::cast_slice(&[scene.to_uniform()]),
         );
+        let mut portals = [PortalUniform::default(); MAX_PORTALS];
+        for (uniform, portal) in portals.iter_mut().zip(scene.portals.iter()) {
+            *uniform = portal.to_uniform();
+        }
+        queue.write_buffer(&self.portal_buffer, 0, bytemuck::cast_slice(&portals));
+        let mut materials = [MaterialUniform::default(); MAX_MATERIALS];
+        let palette = &scene.materials[..scene.materials.len().min(MAX_MATERIALS)];
+        materials[..palette.len()].copy_from_slice(palette);
+        queue.write_buffer(&self.material_buffer, 0, bytemuck::cast_slice(&materials));
+        self.update_volumes(queue, scene);
+        self.update_environment(queue, scene);
+    }
+    /// Bakes the sky's alias table again when the sun has moved.
+    fn update_environment(&mut self, queue: &wgpu::Queue, scene: &Scene) {
+        let sun = scene.sky.to_uniform();
+        if self.environment_sun == Some(sun) {
+            return;
+        }
+        self.environment_sun = Some(sun);
+        let distribution = EnvironmentDistribution::from_sky(Vec4::from_array(sun));
+        queue.write_texture(
+            self.environment_texture.as_image_copy(),
+            bytemuck::cast_slice(&distribution.table.entries),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(distribution.width * mem::size_of::<AliasEntry>() as u32),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: distribution.width,
+                height: distribution.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    /// Volume parameters are written every frame, the bricks only when the scene's grids change.
+    fn update_volumes(&mut self, queue: &wgpu::Queue, scene: &Scene) {
+        let volumes = &scene.volumes[..scene.volumes.len().min(MAX_VOLUMES)];
+        let grids_changed = self.uploaded_volumes.len() != volumes.len()
+            || self
+                .uploaded_volumes
+                .iter()
+                .zip(volumes)
+                .any(|(uploaded, volume)| !Arc::ptr_eq(uploaded, &volume.grid));
+        let mut uniforms = Vec::with_capacity(volumes.len());
+        let mut offset = 0;
+        for (i, volume) in volumes.iter().enumerate() {
+            let words = volume.grid.words() as u64;
+            if offset + words > self.capacity.voxel_words {
+                // Left with a zero majorant so the shader skips it
+                if grids_changed {
+                    log::warn!("Volume {} does not fit in the voxel buffer", i);
+                }
+                uniforms.push(VolumeUniform::default());
+                continue;
+            }
+            uniforms.push(volume.to_uniform(offset as u32));
+            if grids_changed {
+                let grid = &volume.grid;
+                queue.write_buffer(
+                    &self.voxel_buffer,
+                    offset * 4,
+                    bytemuck::cast_slice(&grid.table),
+                );
+                if !grid.bricks.is_empty() {
+                    queue.write_buffer(
+                        &self.voxel_buffer,
+                        (offset + grid.table.len() as u64) * 4,
+                        bytemuck::cast_slice(&grid.bricks),
+                    );
+                }
+            }
+            offset += words;
+        }
+        if grids_changed {
+            self.uploaded_volumes = volumes.iter().map(|v| v.grid.clone()).collect();
+            self.reallocated = true;
+        }
+        if !uniforms.is_empty() {
+            queue.write_buffer(&self.volume_buffer, 0, bytemuck::cast_slice(&uniforms));
+        }
+    }
+    /// Records the camera and size of the frame about to render, reprojecting the last frame's
+    /// accumulation into it when `blend` is on and the camera moved. Other resets leave the old
+    /// image stale rather than displaced, so they always start again from scratch.
+    pub fn update_reprojection(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: CameraUniform,
+        size: (u32, u32),
+        camera_moved: bool,
+        blend: bool,
+    ) {
+        let previous = self.previous_frame.replace((camera, size));
+        let uniform = match previous.filter(|_| camera_moved && blend) {
+            Some((previous, previous_size)) => ReprojectionUniform {
+                world_to_cam: Mat4::from_cols_array_2d(&previous.cam_to_world)
+                    .inverse()
+                    .to_cols_array_2d(),
+                cam_to_world: previous.cam_to_world,
+                view_params: previous.view_params,
+                enabled: 1,
+                size: [previous_size.0, previous_size.1],
+                _p1: [0; 2],
+            },
+            None => ReprojectionUniform::default(),
+        };
+        self.reproject_active = uniform.enabled != 0;
+        queue.write_buffer(
+            &self.reprojection_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform]),
+        );
     }
     pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder, width: u32, height: u32) {
         let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("RayTracer Compute Pass"),
             timestamp_writes: None,
         });
-        let xdim = width + WORKGROUP_SIZE.0 - 1;
-        let xgroups = xdim / WORKGROUP_SIZE.0;
-        let ydim = height + WORKGROUP_SIZE.1 - 1;
-        let ygroups = ydim / WORKGROUP_SIZE.1;
+        if self.reproject_active {
+            compute_pass.set_pipeline(&self.reproject_pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.textures_bind_group, &[]);
+            compute_pass.dispatch_workgroups(width.div_ceil(8), height.div_ceil(8), 1);
+        }
+        let xdim = width + self.workgroup_size.0 - 1;
+        let xgroups = xdim / self.workgroup_size.0;
+        let ydim = height + self.workgroup_size.1 - 1;
+        let ygroups = ydim / self.workgroup_size.1;
 
         compute_pass.set_pipeline(&self.pipeline);
         compute_pass.set_bind_group(0, &self.bind_group, &[]);
         compute_pass.set_bind_group(1, &self.textures_bind_group, &[]);
         compute_pass.dispatch_workgroups(xgroups, ygroups, 1);
     }
+    /// Traces one primary ray per pixel at full resolution, recording the guide for the preview upscale.
+    pub fn render_guide(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("RayTracer Guide Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.guide_pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.textures_bind_group, &[]);
+        compute_pass.dispatch_workgroups(RENDER_SIZE.0.div_ceil(8), RENDER_SIZE.1.div_ceil(8), 1);
+    }
 }
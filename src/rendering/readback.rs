@@ -0,0 +1,230 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use egui_wgpu::wgpu;
+use image::{Rgba32FImage, RgbaImage};
+
+/// Blocking readback of an `Rgba32Float` texture into tightly packed floats.
+pub fn read_texture_rgba32f(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    width: u32,
+    height: u32,
+) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    read_texture_region_rgba32f(device, queue, texture, (0, 0), width, height)
+}
+
+/// Blocking readback of a `width` x `height` region of an `Rgba32Float` texture starting at `origin`.
+/// WebGPU buffers are little endian whatever the host, so this reads the same on every platform.
+pub fn read_texture_region_rgba32f(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    origin: (u32, u32),
+    width: u32,
+    height: u32,
+) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+    let bytes_per_pixel = 16;
+    let unpadded_bytes_per_row = width * bytes_per_pixel;
+    let bytes_per_row = unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Texture Readback Buffer"),
+        size: (bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Texture Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: origin.0,
+                y: origin.1,
+                z: 0,
+            },
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let mapped = Arc::new(AtomicBool::new(false));
+    let mapped_clone = mapped.clone();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        if result.is_ok() {
+            mapped_clone.store(true, Ordering::SeqCst);
+        }
+    });
+    device.poll(wgpu::MaintainBase::Wait)?;
+    if !mapped.load(Ordering::SeqCst) {
+        return Err("Failed to map readback buffer".into());
+    }
+
+    let data = slice.get_mapped_range();
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for row in data.chunks(bytes_per_row as usize) {
+        pixels.extend(
+            row[..unpadded_bytes_per_row as usize]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+    }
+    drop(data);
+    buffer.unmap();
+    Ok(pixels)
+}
+
+/// Reads the `width` x `height` render in the corner of an `Rgba32Float` target as a gamma encoded
+/// 8-bit image scaled by `exposure`.
+pub fn read_render_rgba8(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    width: u32,
+    height: u32,
+    exposure: f32,
+) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+    let image = read_render_rgba32f(device, queue, texture, width, height)?;
+    Ok(encode_rgba8(&image, exposure))
+}
+
+/// Reads the `width` x `height` render in the corner of an `Rgba32Float` target as linear floats.
+/// The target's rows run bottom first, so they are flipped to put the top of the picture first.
+pub fn read_render_rgba32f(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    width: u32,
+    height: u32,
+) -> Result<Rgba32FImage, Box<dyn std::error::Error>> {
+    let (width, height) = (width.min(texture.width()), height.min(texture.height()));
+    if width == 0 || height == 0 {
+        return Err("Nothing to read back from an empty render".into());
+    }
+    let pixels = read_texture_rgba32f(device, queue, texture, width, height)?;
+    let flipped = pixels
+        .chunks_exact(width as usize * 4)
+        .rev()
+        .flatten()
+        .copied()
+        .collect();
+    Rgba32FImage::from_raw(width, height, flipped).ok_or("Readback size mismatch".into())
+}
+
+/// Builds a channel top row first from a texture laid out bottom row first.
+pub fn flip_rows(width: usize, height: usize, texel: impl Fn(usize, usize) -> f32) -> Vec<f32> {
+    (0..height)
+        .rev()
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| texel(x, y))
+        .collect()
+}
+
+/// Decodes an sRGB encoded 0 to 1 value to linear with the exact piecewise curve.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+/// Gamma encodes a linear render to 8 bits, scaling the colour but not the alpha by `exposure`.
+/// The colour is dithered to break up banding in smooth gradients like skies, see `dither`.
+pub fn encode_rgba8(image: &Rgba32FImage, exposure: f32) -> RgbaImage {
+    let gamma = |v: f32| v.max(0.0).powf(1.0 / 2.2).min(1.0);
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let p = image.get_pixel(x, y);
+        let noise = dither(x, y);
+        let encode = |v: f32| quantize(gamma(v * exposure), noise);
+        image::Rgba([
+            encode(p[0]),
+            encode(p[1]),
+            encode(p[2]),
+            (gamma(p[3]) * 255.0).round() as u8,
+        ])
+    })
+}
+
+/// Triangular noise from -1 to 1 for the pixel at `x`, `y`, added before rounding to 8 bits. Unlike
+/// uniform noise its error doesn't vary with the signal, so it hides banding without the noise
+/// itself showing. The same for every export of the same pixel, which keeps renders comparable.
+pub fn dither(x: u32, y: u32) -> f32 {
+    // Integer hash by Chris Wellons, split into two uniform 16-bit halves
+    let mut h = x.wrapping_mul(0x9e37_79b9) ^ y.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    let uniform = |bits: u32| (bits & 0xffff) as f32 / 65536.0;
+    uniform(h) + uniform(h >> 16) - 1.0
+}
+
+/// Rounds an encoded 0 to 1 value to 8 bits with `noise` LSBs added. Sharing the noise between
+/// channels shifts a pixel's brightness without tinting it, and pure black and white stay exact.
+fn quantize(v: f32, noise: f32) -> u8 {
+    if v <= 0.0 || v >= 1.0 {
+        return (v * 255.0) as u8;
+    }
+    (v * 255.0 + noise).round().clamp(0.0, 255.0) as u8
+}
+
+/// Blocking readback of a storage buffer holding `u32`s.
+pub fn read_buffer_u32(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Buffer Readback Buffer"),
+        size: buffer.size(),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Buffer Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = staging.slice(..);
+    let mapped = Arc::new(AtomicBool::new(false));
+    let mapped_clone = mapped.clone();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        if result.is_ok() {
+            mapped_clone.store(true, Ordering::SeqCst);
+        }
+    });
+    device.poll(wgpu::MaintainBase::Wait)?;
+    if !mapped.load(Ordering::SeqCst) {
+        return Err("Failed to map readback buffer".into());
+    }
+
+    let data = slice.get_mapped_range();
+    let values = data
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    drop(data);
+    staging.unmap();
+    Ok(values)
+}
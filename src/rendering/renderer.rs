@@ -4,17 +4,29 @@ use egui_wgpu::wgpu::{self, TextureView};
 use wgpu::PipelineCompilationOptions;
 
 use crate::core::app::Params;
+use crate::rendering::picking::Picker;
 
 pub struct Renderer {
+    pub view: ImageView,
+    device: Arc<wgpu::Device>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    exposure_buffer: wgpu::Buffer,
+    processed_view: TextureView,
+    pick_settings_buffer: wgpu::Buffer,
+    pick_ids_buffer: wgpu::Buffer,
 }
 
 impl Renderer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new<'a>(
         device: Arc<wgpu::Device>,
         renderer: &mut egui_wgpu::Renderer,
         texture_view: &TextureView,
         surface_config: &wgpu::SurfaceConfiguration,
         params_buffer: &wgpu::Buffer,
+        exposure_buffer: &wgpu::Buffer,
+        processed_view: &TextureView,
+        picker: &Picker,
     ) -> Option<Self> {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Renderer Bind Group Layout"),
@@ -39,27 +51,81 @@ impl Renderer {
                     },
                     count: None,
                 },
-            ],
-        });
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Renderer Bind Group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: params_buffer.as_entire_binding(),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(texture_view),
+                // Guided upscale shown while moving
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Hover and selection highlight
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
             ],
         });
 
+        let bind_group = Renderer::bind_group(
+            &device,
+            &bind_group_layout,
+            texture_view,
+            params_buffer,
+            exposure_buffer,
+            processed_view,
+            &picker.settings_buffer,
+            &picker.ids_buffer,
+        );
+
+        // Kept apart from the swapped bind group since it follows the central panel, not the tab
+        let view_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Renderer View Bind Group Layout"),
+                entries: &[ViewWindow::layout_entry(0)],
+            });
+        let view_buffer = ViewWindow::create_buffer(&device);
+        let view_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Renderer View Bind Group"),
+            layout: &view_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: view_buffer.as_entire_binding(),
+            }],
+        });
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Renderer Pipeline Layout"),
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&bind_group_layout, &view_bind_group_layout],
             push_constant_ranges: &[],
         });
 
@@ -111,40 +177,270 @@ impl Renderer {
         renderer.callback_resources.insert(RendererResource {
             pipeline,
             bind_group,
+            view_buffer,
+            view_bind_group,
         });
 
-        Some(Self {})
+        Some(Self {
+            view: ImageView::default(),
+            device,
+            bind_group_layout,
+            exposure_buffer: exposure_buffer.clone(),
+            processed_view: processed_view.clone(),
+            pick_settings_buffer: picker.settings_buffer.clone(),
+            pick_ids_buffer: picker.ids_buffer.clone(),
+        })
     }
-    pub fn render_ray_traced_image(&mut self, ui: &mut egui::Ui) -> bool {
+    #[allow(clippy::too_many_arguments)]
+    fn bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture_view: &TextureView,
+        params_buffer: &wgpu::Buffer,
+        exposure_buffer: &wgpu::Buffer,
+        processed_view: &TextureView,
+        pick_settings_buffer: &wgpu::Buffer,
+        pick_ids_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Renderer Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(processed_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: pick_settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: pick_ids_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+    pub fn create_bind_group(
+        &self,
+        texture_view: &TextureView,
+        params_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        Renderer::bind_group(
+            &self.device,
+            &self.bind_group_layout,
+            texture_view,
+            params_buffer,
+            &self.exposure_buffer,
+            &self.processed_view,
+            &self.pick_settings_buffer,
+            &self.pick_ids_buffer,
+        )
+    }
+    /// Swaps the bind group used to display the ray traced image, used when switching tabs.
+    pub fn swap_bind_group(
+        &self,
+        renderer: &mut egui_wgpu::Renderer,
+        bind_group: &mut wgpu::BindGroup,
+    ) {
+        let resources: &mut RendererResource = renderer.callback_resources.get_mut().unwrap();
+        std::mem::swap(&mut resources.bind_group, bind_group);
+    }
+    /// Draws the ray traced image of `size` pixels into a 16:9 box the width of `ui`, fitted or at
+    /// the zoom of `view`. Command + scroll zooms about the pointer and a middle drag pans.
+    /// Returns the box's response along with where the whole image is, which may reach past it.
+    pub fn render_ray_traced_image(
+        &mut self,
+        ui: &mut egui::Ui,
+        size: (u32, u32),
+    ) -> (egui::Response, egui::Rect) {
         let (rect, response) = ui.allocate_exact_size(
             egui::Vec2::new(ui.available_width(), ui.available_width() * 0.5625),
-            egui::Sense::click(),
+            egui::Sense::click_and_drag(),
         );
+        let size = egui::vec2(size.0 as f32, size.1 as f32);
+        let pixels_per_point = ui.ctx().pixels_per_point();
+        let zoom_delta = match response.hovered() {
+            true => ui.input(|i| i.zoom_delta()),
+            false => 1.0,
+        };
+        if zoom_delta != 1.0 {
+            let image = self.view.image_rect(rect, size, pixels_per_point);
+            // Zooming out of the fitted image starts from however large it was drawn
+            let zoom = self
+                .view
+                .zoom
+                .unwrap_or(image.width() * pixels_per_point / size.x);
+            let new_zoom = (zoom * zoom_delta).clamp(MIN_ZOOM, MAX_ZOOM);
+            // Keeps the pixel under the pointer where it is
+            let pointer = response.hover_pos().unwrap_or(rect.center());
+            let center = pointer - (pointer - image.center()) * (new_zoom / zoom);
+            self.view.pan = center - rect.center();
+            self.view.zoom = Some(new_zoom);
+        }
+        if self.view.zoom.is_some() && response.dragged_by(egui::PointerButton::Middle) {
+            self.view.pan += response.drag_delta();
+        }
+        // At least a corner of the image stays in the middle of the box
+        let half = self.view.image_rect(rect, size, pixels_per_point).size() * 0.5;
+        self.view.pan = self.view.pan.clamp(-half, half);
 
-        ui.painter().add(egui_wgpu::Callback::new_paint_callback(
-            rect,
-            EguiRenderCallback {},
-        ));
-        response.clicked()
+        let image = self.view.image_rect(rect, size, pixels_per_point);
+        let visible = image.intersect(rect);
+        if visible.is_positive() {
+            ui.painter().add(egui_wgpu::Callback::new_paint_callback(
+                visible,
+                EguiRenderCallback {
+                    window: ViewWindow::new(image, visible),
+                },
+            ));
+        }
+        (response, image)
+    }
+}
+
+/// Display only cleanup of noisy previews, selected through `Params::despeckle`. The accumulation
+/// stays unbiased and exports never see it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Despeckle {
+    Off = 0,
+    /// Per channel median of each 3×3 neighbourhood, also softens texture detail
+    Median,
+    /// Darkens a pixel brighter than all eight of its neighbours down to the brightest of them,
+    /// leaving everything else alone
+    FireflyClamp,
+}
+
+impl Despeckle {
+    pub const ALL: [Despeckle; 3] = [Despeckle::Off, Despeckle::Median, Despeckle::FireflyClamp];
+    pub fn name(self) -> &'static str {
+        match self {
+            Despeckle::Off => "Off",
+            Despeckle::Median => "3×3 Median",
+            Despeckle::FireflyClamp => "Firefly Clamp",
+        }
+    }
+}
+
+/// Limits of `ImageView::zoom`
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 64.0;
+
+/// How the ray traced image sits in the central panel, stretched to fit or at a fixed zoom.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImageView {
+    /// Screen pixels per rendered pixel, `None` fits the image to the panel
+    pub zoom: Option<f32>,
+    /// Offset of the image's centre from the panel's in points
+    pub pan: egui::Vec2,
+}
+
+impl ImageView {
+    /// Where an image of `size` pixels goes when shown in `rect`. Zoomed, its corner is snapped to
+    /// a screen pixel so each rendered pixel covers whole screen pixels at integer zooms.
+    pub fn image_rect(
+        &self,
+        rect: egui::Rect,
+        size: egui::Vec2,
+        pixels_per_point: f32,
+    ) -> egui::Rect {
+        let Some(zoom) = self.zoom else {
+            return rect;
+        };
+        let size = size * zoom / pixels_per_point;
+        let min = rect.center() + self.pan - size * 0.5;
+        let min = (min.to_vec2() * pixels_per_point).round() / pixels_per_point;
+        egui::Rect::from_min_size(min.to_pos2(), size)
+    }
+}
+
+/// Part of the image a paint callback's rect shows, in the image's texture coordinates with the
+/// bottom row at 0. A zoomed image can reach off screen, where egui would squash its viewport, so
+/// the callbacks only cover what's visible and pick that part out with this.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ViewWindow {
+    min: [f32; 2],
+    max: [f32; 2],
+}
+
+impl ViewWindow {
+    /// The part of `image` that `visible` covers.
+    pub fn new(image: egui::Rect, visible: egui::Rect) -> Self {
+        let min = (visible.min - image.min) / image.size();
+        let max = (visible.max - image.min) / image.size();
+        Self {
+            min: [min.x, 1.0 - max.y],
+            max: [max.x, 1.0 - min.y],
+        }
+    }
+    pub fn create_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("View Window Buffer"),
+            size: mem::size_of::<Self>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    pub fn layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(mem::size_of::<Self>() as _),
+            },
+            count: None,
+        }
     }
 }
 
 pub struct RendererResource {
     pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
+    view_buffer: wgpu::Buffer,
+    view_bind_group: wgpu::BindGroup,
 }
 
 impl RendererResource {
     fn render(&self, render_pass: &mut wgpu::RenderPass<'_>) {
         render_pass.set_pipeline(&self.pipeline);
         render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.view_bind_group, &[]);
         render_pass.draw(0..6, 0..1);
     }
 }
 
-struct EguiRenderCallback {}
+struct EguiRenderCallback {
+    window: ViewWindow,
+}
 
 impl egui_wgpu::CallbackTrait for EguiRenderCallback {
+    fn prepare(
+        &self,
+        _device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        _screen_descriptor: &egui_wgpu::ScreenDescriptor,
+        _encoder: &mut wgpu::CommandEncoder,
+        resources: &mut egui_wgpu::CallbackResources,
+    ) -> Vec<wgpu::CommandBuffer> {
+        let resources: &RendererResource = resources.get().unwrap();
+        queue.write_buffer(&resources.view_buffer, 0, bytemuck::bytes_of(&self.window));
+        vec![]
+    }
     fn paint(
         &self,
         _info: egui::PaintCallbackInfo,
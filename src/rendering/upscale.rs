@@ -0,0 +1,157 @@
+use std::{mem, sync::Arc};
+
+use egui_wgpu::wgpu::{self, PipelineCompilationOptions, TextureView};
+
+use crate::core::app::Params;
+
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+
+/// Upscales the quarter resolution frame rendered while moving to full resolution, using the ray
+/// tracer's full resolution normal and depth guide to keep edges sharp.
+pub struct GuidedUpscaler {
+    device: Arc<wgpu::Device>,
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: Option<wgpu::BindGroup>,
+    width: u32,
+    height: u32,
+    /// Full resolution result, displayed in place of the accumulation texture
+    pub view: TextureView,
+    pub enabled: bool,
+    /// Whether this frame is a moving preview that should be upscaled
+    pub active: bool,
+}
+
+impl GuidedUpscaler {
+    pub fn new(device: Arc<wgpu::Device>, width: u32, height: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Upscale Compute Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/upscale.wgsl").into()),
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Upscale Bind Group Layout"),
+            entries: &[
+                // Params
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(mem::size_of::<Params>() as _),
+                    },
+                    count: None,
+                },
+                // Accumulation Texture
+                texture_entry(1),
+                // Guide
+                texture_entry(2),
+                // Output
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Upscale Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Upscale Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Upscale Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("resolve"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            device,
+            pipeline,
+            bind_group_layout,
+            bind_group: None,
+            width,
+            height,
+            view,
+            enabled: true,
+            active: false,
+        }
+    }
+    /// Points the upscale at a tab's accumulation texture and params.
+    pub fn set_target(
+        &mut self,
+        texture_view: &TextureView,
+        params_buffer: &wgpu::Buffer,
+        guide_view: &TextureView,
+    ) {
+        self.bind_group = Some(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Upscale Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(guide_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.view),
+                },
+            ],
+        }));
+    }
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Upscale Compute Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            self.width.div_ceil(WORKGROUP_SIZE.0),
+            self.height.div_ceil(WORKGROUP_SIZE.1),
+            1,
+        );
+    }
+}
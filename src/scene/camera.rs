@@ -1,7 +1,7 @@
 use std::{f32::consts::FRAC_PI_2, time::Duration};
 
 use egui_wgpu::wgpu;
-use glam::{EulerRot, Quat, Vec3};
+use glam::{EulerRot, Mat4, Quat, Vec2, Vec3};
 #[allow(unused_imports)]
 use wgpu::util::DeviceExt;
 use winit::{
@@ -10,6 +10,7 @@ use winit::{
     keyboard::KeyCode,
 };
 
+use crate::core::bvh::Aabb;
 use crate::scene::components::transform::Transform;
 
 #[repr(C)]
@@ -19,6 +20,47 @@ pub struct CameraUniform {
     pub view_params: [f32; 3],
     pub defocus_strength: f32,
     pub diverge_strength: f32,
+    /// Distances along the view axis camera rays start and stop at, 0 and `f32::MAX` when clipping is off
+    pub near: f32,
+    pub far: f32,
+}
+
+/// Film speed, shutter and aperture of a real camera. When enabled the image is exposed for scene
+/// luminance in cd/m², see `LightUnit`, and the f-stop sets the depth of field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicalExposure {
+    pub enabled: bool,
+    pub iso: f32,
+    /// Shutter time in seconds
+    pub shutter: f32,
+    pub f_stop: f32,
+}
+
+impl Default for PhysicalExposure {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            iso: 100.0,
+            shutter: 1.0 / 125.0,
+            f_stop: 8.0,
+        }
+    }
+}
+
+impl PhysicalExposure {
+    /// Exposure value at ISO 100, higher needs more light for the same image.
+    pub fn ev100(&self) -> f32 {
+        (self.f_stop * self.f_stop / self.shutter * 100.0 / self.iso).log2()
+    }
+    /// Stops added to the exposure compensation, zero when disabled. Uses the usual saturation
+    /// based sensitivity, so a luminance of `2^EV100 * 1.2` maps to one.
+    pub fn stops(&self) -> f32 {
+        if self.enabled {
+            -self.ev100() - 1.2f32.log2()
+        } else {
+            0.0
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -28,10 +70,15 @@ pub struct Camera {
     pub aspect: f32,
     pub near: f32,
     pub far: f32,
+    /// Whether camera rays are clipped to `near` and `far`, for cutaways and very large scenes
+    pub clip: bool,
     pub focus_dist: f32,
     pub controller: CameraController,
     pub defocus_strength: f32,
     pub diverge_strength: f32,
+    /// Local offset layered on top of `transform` by the timeline's camera shake
+    pub shake: Transform,
+    pub exposure: PhysicalExposure,
 }
 
 #[allow(unused)]
@@ -72,34 +119,75 @@ impl Camera {
             aspect: camera_descriptor.aspect,
             near: camera_descriptor.near,
             far: camera_descriptor.far,
+            clip: false,
             focus_dist: camera_descriptor.focus_dist.max(1.0),
             controller: CameraController::new(10.0, 1.8),
             defocus_strength: camera_descriptor.defocus_strength,
             diverge_strength: camera_descriptor.diverge_strength,
+            shake: Transform::default(),
+            exposure: PhysicalExposure::default(),
         }
     }
+    /// Focal length in scene units of a full frame (24mm tall) camera with this field of view,
+    /// assuming the scene is modelled in metres.
+    pub fn focal_length(&self) -> f32 {
+        0.012 / (self.fov * 0.5).to_radians().tan()
+    }
+    /// With physical exposure enabled, matches the defocus to the f-stop's aperture at the given
+    /// render width. Returns whether the defocus changed.
+    pub fn apply_aperture(&mut self, width: u32) -> bool {
+        if !self.exposure.enabled {
+            return false;
+        }
+        // The shader jitters ray origins by `defocus_strength / width`
+        let radius = self.focal_length() / (2.0 * self.exposure.f_stop);
+        let defocus_strength = radius * width as f32;
+        let changed = defocus_strength != self.defocus_strength;
+        self.defocus_strength = defocus_strength;
+        changed
+    }
+    /// Camera to world matrix including any shake.
+    pub fn cam_to_world(&self) -> Mat4 {
+        self.transform.to_matrix() * self.shake.to_matrix()
+    }
     pub fn to_uniform(&self) -> CameraUniform {
         assert!(self.focus_dist != 0.0, "Focus Distance cannot be zero");
         let plane_height = self.focus_dist * (self.fov * 0.5).to_radians().tan() * 2.0;
         let plane_width = plane_height * self.aspect;
         CameraUniform {
-            cam_to_world: self.transform.to_matrix().to_cols_array_2d(),
+            cam_to_world: self.cam_to_world().to_cols_array_2d(),
             view_params: [plane_width, plane_height, self.focus_dist],
             defocus_strength: self.defocus_strength,
             diverge_strength: self.diverge_strength,
+            near: if self.clip { self.near } else { 0.0 },
+            far: if self.clip { self.far } else { f32::MAX },
         }
     }
+    /// Moves the camera back along its view direction until the bounding sphere of `bounds` fits in view.
+    pub fn frame_bounds(&mut self, bounds: &Aabb) {
+        let half_fov_y = (self.fov * 0.5).to_radians();
+        let half_fov_x = (half_fov_y.tan() * self.aspect).atan();
+        let radius = bounds.radius().max(0.01);
+        let distance = radius / half_fov_y.min(half_fov_x).sin();
+        let forward = self.transform.rot * Vec3::Z;
+        self.transform.pos = bounds.centre() - forward * distance;
+        self.focus_dist = distance.max(1.0);
+    }
     pub fn update_camera(&mut self, dt: Duration) -> bool {
         let dt = dt.as_secs_f32();
         let mut moved = false;
         let scalar = self.controller.sensitivity * dt;
 
         // Handle rotation - FPS style (no roll)
-        if self.controller.rotate_horizontal != 0.0 || self.controller.rotate_vertical != 0.0 {
+        let look = self.controller.analog_look * dt;
+        if self.controller.rotate_horizontal != 0.0
+            || self.controller.rotate_vertical != 0.0
+            || look != Vec2::ZERO
+        {
             let (mut yaw, mut pitch, _roll) = self.transform.rot.to_euler(EulerRot::YXZ);
 
-            yaw += self.controller.rotate_horizontal * scalar;
-            pitch += self.controller.rotate_vertical * scalar;
+            yaw += self.controller.rotate_horizontal * scalar + look.x;
+            pitch += self.controller.rotate_vertical * scalar + look.y;
 
             // Clamp pitch to avoid flipping
             const MAX_PITCH: f32 = FRAC_PI_2 - 0.1; // 89 degrees
@@ -126,6 +214,13 @@ impl Camera {
             moved = true;
         }
 
+        // Unlike the keys a stick's deflection sets the pace, up to full speed
+        let analog_move = self.controller.analog_move.clamp_length_max(1.0);
+        if analog_move != Vec3::ZERO {
+            self.transform.pos += self.transform.rot * (analog_move * self.controller.speed * dt);
+            moved = true;
+        }
+
         if self.controller.scroll != 0.0 {
             let zoom_delta =
                 self.transform.rot * Vec3::Z * self.controller.scroll * self.controller.speed * dt;
@@ -136,6 +231,73 @@ impl Camera {
         moved
     }
 }
+/// Procedural handheld motion layered on top of the camera during playback and sequence renders.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraShake {
+    pub enabled: bool,
+    /// Peak rotation in degrees
+    pub rotation: f32,
+    /// Peak translation in world units
+    pub translation: f32,
+    /// Wobbles per second
+    pub frequency: f32,
+    pub seed: u32,
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rotation: 0.5,
+            translation: 0.02,
+            frequency: 1.5,
+            seed: 0,
+        }
+    }
+}
+
+impl CameraShake {
+    /// Offset in the camera's local space `time` seconds into the timeline.
+    pub fn offset(&self, time: f32) -> Transform {
+        let t = time * self.frequency;
+        let channel = |c: u32| fractal_noise(t, self.seed.wrapping_mul(6).wrapping_add(c));
+        let rotation = self.rotation.to_radians();
+        Transform {
+            pos: Vec3::new(channel(3), channel(4), channel(5)) * self.translation,
+            // Roll is kept smaller, a handheld camera twists less than it pans and tilts
+            rot: Quat::from_euler(
+                EulerRot::YXZ,
+                channel(0) * rotation,
+                channel(1) * rotation,
+                channel(2) * rotation * 0.5,
+            ),
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+/// 1D Perlin noise in roughly [-1, 1]
+fn perlin(t: f32, seed: u32) -> f32 {
+    let gradient = |i: i32| {
+        let mut h = (i as u32).wrapping_mul(0x9e37_79b9) ^ seed.wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x7feb_352d);
+        h ^= h >> 15;
+        h as f32 / u32::MAX as f32 * 2.0 - 1.0
+    };
+    let cell = t.floor();
+    let f = t - cell;
+    let a = gradient(cell as i32) * f;
+    let b = gradient(cell as i32 + 1) * (f - 1.0);
+    let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    (a + (b - a) * fade) * 2.0
+}
+
+/// Two octaves so the motion has a slow drift with some jitter on top
+fn fractal_noise(t: f32, seed: u32) -> f32 {
+    (perlin(t, seed) + perlin(t * 2.7, seed ^ 0x5bd1_e995) * 0.35) / 1.35
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct CameraController {
     amount_left: f32,
@@ -147,6 +309,9 @@ pub struct CameraController {
     rotate_horizontal: f32,
     rotate_vertical: f32,
     scroll: f32,
+    /// Gamepad movement and turn rate, held until the next update replaces them
+    analog_move: Vec3,
+    analog_look: Vec2,
     speed: f32,
     sensitivity: f32,
 }
@@ -163,10 +328,24 @@ impl CameraController {
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
             scroll: 0.0,
+            analog_move: Vec3::ZERO,
+            analog_look: Vec2::ZERO,
             speed,
             sensitivity,
         }
     }
+    /// Movement from -1 to 1 along each camera axis and turn rate in radians per second, see
+    /// `GamepadFrame`.
+    pub fn process_gamepad(&mut self, movement: Vec3, look: Vec2) {
+        self.analog_move = movement;
+        self.analog_look = look;
+    }
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+    pub fn scale_speed(&mut self, factor: f32) {
+        self.speed = (self.speed * factor).clamp(0.1, 1000.0);
+    }
 
     pub fn process_keyboard(&mut self, key: KeyCode, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed {
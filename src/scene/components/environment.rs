@@ -0,0 +1,248 @@
+use std::f32::consts::PI;
+
+use glam::{Vec3, Vec4};
+
+use crate::scene::sky;
+
+/// One bucket of an alias table, laid out for direct upload to a storage buffer.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
+pub struct AliasEntry {
+    /// Probability of keeping this bucket rather than jumping to `alias`
+    pub threshold: f32,
+    pub alias: u32,
+    /// Probability of selecting this texel, used to weight samples
+    pub pdf: f32,
+    _p1: f32,
+}
+
+/// Walker alias table for O(1) sampling of a discrete distribution.
+#[derive(Debug, Default)]
+pub struct AliasTable {
+    pub entries: Vec<AliasEntry>,
+}
+
+impl AliasTable {
+    /// Builds the table with Vose's method. Returns an empty table if every weight is zero.
+    pub fn new(weights: &[f32]) -> Self {
+        let n = weights.len();
+        let total_weight: f32 = weights.iter().map(|w| w.max(0.0)).sum();
+        if n == 0 || total_weight <= 0.0 {
+            return Self::default();
+        }
+
+        let mut entries: Vec<AliasEntry> = weights
+            .iter()
+            .enumerate()
+            .map(|(i, w)| AliasEntry {
+                threshold: 1.0,
+                alias: i as u32,
+                pdf: w.max(0.0) / total_weight,
+                ..Default::default()
+            })
+            .collect();
+
+        let mut scaled: Vec<f32> = entries.iter().map(|e| e.pdf * n as f32).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).partition(|&i| scaled[i] < 1.0);
+
+        while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+            entries[s].threshold = scaled[s];
+            entries[s].alias = l as u32;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // Leftovers only differ from 1 by rounding error
+        for i in small.into_iter().chain(large) {
+            entries[i].threshold = 1.0;
+        }
+
+        Self { entries }
+    }
+    /// Maps two uniform random numbers in [0, 1) to a bucket index.
+    pub fn sample(&self, u1: f32, u2: f32) -> Option<usize> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let n = self.entries.len();
+        let i = ((u1 * n as f32) as usize).min(n - 1);
+        let entry = &self.entries[i];
+        Some(if u2 < entry.threshold {
+            i
+        } else {
+            entry.alias as usize
+        })
+    }
+}
+
+/// Luminance weighted sampling distribution over the sky, one alias table bucket per texel of an
+/// equirectangular grid. Row 0 is around +Y and columns turn from +X towards +Z, as
+/// `sample_environment` in the shader reads them.
+#[derive(Debug, Default)]
+pub struct EnvironmentDistribution {
+    pub width: u32,
+    pub height: u32,
+    pub table: AliasTable,
+}
+
+impl EnvironmentDistribution {
+    /// Texels the procedural sky is baked into, fine enough that the sun disc covers a few
+    pub const SIZE: (u32, u32) = (256, 128);
+
+    /// Bakes the procedural sky lit by `sun`, see `Sky::to_uniform`.
+    pub fn from_sky(sun: Vec4) -> Self {
+        let (width, height) = Self::SIZE;
+        Self::from_radiance(width, height, |dir| sky::radiance(sun, dir))
+    }
+    /// Weights each texel by the luminance `radiance` gives through its centre. A black
+    /// environment falls back to sampling uniformly so there is always something to sample.
+    pub fn from_radiance(width: u32, height: u32, radiance: impl Fn(Vec3) -> Vec4) -> Self {
+        let weights: Vec<f32> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (dir, sin_theta) =
+                    Self::direction(width, height, x as f32 + 0.5, y as f32 + 0.5);
+                let light = radiance(dir);
+                let luminance = 0.2126 * light.x + 0.7152 * light.y + 0.0722 * light.z;
+                // Rows near the poles cover less solid angle
+                luminance.max(0.0) * sin_theta
+            })
+            .collect();
+        let mut table = AliasTable::new(&weights);
+        if table.entries.is_empty() {
+            table = AliasTable::new(&vec![1.0; weights.len()]);
+        }
+        Self {
+            width,
+            height,
+            table,
+        }
+    }
+    /// Direction through the point `x`, `y` texels into the grid and the sine of its angle from +Y.
+    fn direction(width: u32, height: u32, x: f32, y: f32) -> (Vec3, f32) {
+        let phi = x / width as f32 * 2.0 * PI;
+        let theta = y / height as f32 * PI;
+        let dir = Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        (dir, theta.sin())
+    }
+    /// Solid angle pdf of sampling `dir`, mirrors `environment_pdf`.
+    pub fn solid_angle_pdf(&self, dir: Vec3) -> f32 {
+        let theta = dir.y.clamp(-1.0, 1.0).acos();
+        let phi = dir.z.atan2(dir.x).rem_euclid(2.0 * PI);
+        let x = ((phi / (2.0 * PI) * self.width as f32) as u32).min(self.width - 1);
+        let y = ((theta / PI * self.height as f32) as u32).min(self.height - 1);
+        let Some(entry) = self.table.entries.get((y * self.width + x) as usize) else {
+            return 0.0;
+        };
+        if theta.sin() <= 0.0 {
+            return 0.0;
+        }
+        entry.pdf * (self.width * self.height) as f32 / (2.0 * PI * PI * theta.sin())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chance of landing in each bucket, either directly or through another bucket's alias.
+    fn bucket_probabilities(table: &AliasTable) -> Vec<f32> {
+        let n = table.entries.len() as f32;
+        let mut probabilities = vec![0.0; table.entries.len()];
+        for (i, entry) in table.entries.iter().enumerate() {
+            probabilities[i] += entry.threshold / n;
+            probabilities[entry.alias as usize] += (1.0 - entry.threshold) / n;
+        }
+        probabilities
+    }
+
+    #[test]
+    fn alias_table_reproduces_weights() {
+        let weights = [1.0, 7.0, 0.0, 2.0, 0.5, 9.5];
+        let table = AliasTable::new(&weights);
+        let total: f32 = weights.iter().sum();
+        for (i, (entry, probability)) in table
+            .entries
+            .iter()
+            .zip(bucket_probabilities(&table))
+            .enumerate()
+        {
+            assert!((0.0..=1.0).contains(&entry.threshold));
+            assert!((entry.pdf - weights[i] / total).abs() < 1e-6);
+            assert!(
+                (probability - entry.pdf).abs() < 1e-5,
+                "bucket {} drawn with {} not {}",
+                i,
+                probability,
+                entry.pdf
+            );
+        }
+    }
+
+    #[test]
+    fn alias_table_never_picks_zero_weights() {
+        let table = AliasTable::new(&[0.0, 3.0, -2.0, 1.0]);
+        for a in 0..32 {
+            for b in 0..32 {
+                let i = table.sample(a as f32 / 32.0, b as f32 / 32.0).unwrap();
+                assert!(i == 1 || i == 3, "picked {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_alias_table_has_nothing_to_sample() {
+        assert!(AliasTable::new(&[]).entries.is_empty());
+        assert!(AliasTable::new(&[0.0, 0.0]).entries.is_empty());
+        assert_eq!(AliasTable::new(&[0.0, 0.0]).sample(0.5, 0.5), None);
+    }
+
+    #[test]
+    fn black_environment_samples_uniformly() {
+        let distribution = EnvironmentDistribution::from_radiance(8, 4, |_| Vec4::ZERO);
+        assert_eq!(distribution.table.entries.len(), 32);
+        assert!(
+            distribution
+                .table
+                .entries
+                .iter()
+                .all(|e| (e.pdf - 1.0 / 32.0).abs() < 1e-6)
+        );
+    }
+
+    #[test]
+    fn environment_pdf_integrates_to_one() {
+        let distribution = EnvironmentDistribution::from_sky(Vec4::new(0.3, 0.8, 0.2, 20.0));
+        // Midpoint rule over a grid finer than the table's
+        let (width, height) = (1024, 512);
+        let mut integral = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                let (dir, sin_theta) = EnvironmentDistribution::direction(
+                    width,
+                    height,
+                    x as f32 + 0.5,
+                    y as f32 + 0.5,
+                );
+                let solid_angle = sin_theta * (2.0 * PI / width as f32) * (PI / height as f32);
+                integral += distribution.solid_angle_pdf(dir) * solid_angle;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-2, "integral {}", integral);
+    }
+
+    #[test]
+    fn sun_is_sampled_most() {
+        let sun = Vec3::new(0.3, 0.8, 0.2).normalize();
+        let distribution = EnvironmentDistribution::from_sky(sun.extend(20.0));
+        let away = Vec3::new(-0.3, 0.8, -0.2).normalize();
+        assert!(distribution.solid_angle_pdf(sun) > 10.0 * distribution.solid_angle_pdf(away));
+    }
+}
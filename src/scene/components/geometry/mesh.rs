@@ -2,8 +2,12 @@ use glam::Vec3;
 use std::sync::Arc;
 
 use crate::scene::components::{
-    geometry::vertex::Vertex, material::MaterialUniform, transform::Transform,
+    geometry::{procedural, vertex::Vertex},
+    material::MaterialUniform,
+    material_rules::MaterialRule,
+    transform::Transform,
 };
+use crate::scene::entity::EntityLabel;
 
 #[derive(Debug)]
 pub struct MeshData {
@@ -17,6 +21,56 @@ pub struct MeshInstance {
     pub data: Arc<MeshData>,
     pub transform: Transform,
     pub material: MaterialUniform,
+    pub uv_view: UvView,
+    /// Asset path of the model the mesh was loaded from, reloaded when it changes on disk
+    pub source: Option<String>,
+    /// Crease angle `source` was imported with, see `MeshDefinition::FromFile`
+    pub crease_angle: Option<f32>,
+    /// Name and notes from the scene definition or the inspector, shown in place of `label`
+    pub user_label: EntityLabel,
+    /// Width and height of a `RectLight`, which is sampled as one rectangle rather than by triangle
+    pub rect_light: Option<[f32; 2]>,
+}
+
+/// Swaps a mesh's material for a matte pattern laid out by its texture coordinates, to spot
+/// stretched, flipped or missing UVs on imported models. `DebugMode::UvChecker` and
+/// `DebugMode::UvFalseColor` show the same over every textured surface at once.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UvView {
+    #[default]
+    Off = 0,
+    /// Eight squares per unit of UV, shaded by the coordinates so flips show
+    Checker,
+    /// U in red and V in green, striped blue past the 0 to 1 square
+    FalseColor,
+}
+
+impl UvView {
+    pub const ALL: [UvView; 3] = [UvView::Off, UvView::Checker, UvView::FalseColor];
+    pub fn name(self) -> &'static str {
+        match self {
+            UvView::Off => "Off",
+            UvView::Checker => "Checker",
+            UvView::FalseColor => "False Color",
+        }
+    }
+}
+
+impl MeshInstance {
+    /// World space surface area, which a light's flux is spread over.
+    pub fn surface_area(&self) -> f32 {
+        let model_to_world = self.transform.to_matrix();
+        self.data
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| {
+                    model_to_world.transform_point3(self.data.vertices[index as usize].pos)
+                });
+                (b - a).cross(c - a).length() * 0.5
+            })
+            .sum()
+    }
 }
 
 impl MeshData {
@@ -28,16 +82,39 @@ impl MeshData {
             Vertex::with_uv(Vec3::new(-1.0, 1.0, 0.0), Vec3::Z, [0.0, 1.0]),
         ]
     }
+    /// Rectangle in the XZ plane centred on the origin, facing down -Y. Index it like `quad`.
+    pub fn rect(width: f32, height: f32) -> Vec<Vertex> {
+        let (x, z) = (width * 0.5, height * 0.5);
+        vec![
+            Vertex::with_uv(Vec3::new(-x, 0.0, -z), Vec3::NEG_Y, [0.0, 0.0]),
+            Vertex::with_uv(Vec3::new(x, 0.0, -z), Vec3::NEG_Y, [1.0, 0.0]),
+            Vertex::with_uv(Vec3::new(x, 0.0, z), Vec3::NEG_Y, [1.0, 1.0]),
+            Vertex::with_uv(Vec3::new(-x, 0.0, z), Vec3::NEG_Y, [0.0, 1.0]),
+        ]
+    }
 }
 pub enum MeshDefinition {
     FromFile {
         path: String,
         use_mtl: bool,
+        /// Materials for parts picked out by name, ahead of the MTL or the entity's material
+        rules: Vec<MaterialRule>,
+        /// Degrees past which an edge stays hard when normals are generated for an OBJ without
+        /// them, on top of the file's smoothing groups. `None` smooths every edge within a group.
+        crease_angle: Option<f32>,
     },
     FromData {
         vertices: Arc<Vec<Vertex>>,
         indices: Arc<Vec<u32>>,
     },
+    /// Unit UV sphere, see `procedural::uv_sphere`
+    Sphere { segments: u32 },
+    /// Box `size` across centred on the origin
+    Box { size: Vec3 },
+    /// Ring around the Y axis, `r1` to the middle of the tube and `r2` the tube's radius
+    Torus { r1: f32, r2: f32 },
+    /// Square from -1 to 1 facing up +Y, `subdivisions` quads along each side
+    Plane { subdivisions: u32 },
 }
 
 impl MeshDefinition {
@@ -47,6 +124,36 @@ impl MeshDefinition {
             indices: Arc::new(indices),
         }
     }
+    /// Geometry of every definition but a file, generating it for the procedural ones.
+    pub fn mesh_data(&self) -> Option<MeshData> {
+        let (vertices, indices) = match self {
+            MeshDefinition::FromFile { .. } => return None,
+            MeshDefinition::FromData { vertices, indices } => {
+                return Some(MeshData {
+                    vertices: vertices.clone(),
+                    indices: indices.clone(),
+                });
+            }
+            MeshDefinition::Sphere { segments } => procedural::uv_sphere(*segments),
+            MeshDefinition::Box { size } => procedural::cuboid(*size),
+            MeshDefinition::Torus { r1, r2 } => procedural::torus(*r1, *r2),
+            MeshDefinition::Plane { subdivisions } => procedural::plane(*subdivisions),
+        };
+        Some(MeshData {
+            vertices: Arc::new(vertices),
+            indices: Arc::new(indices),
+        })
+    }
+    /// Start of the labels given to meshes that aren't loaded from a file.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MeshDefinition::FromFile { .. } | MeshDefinition::FromData { .. } => "mesh",
+            MeshDefinition::Sphere { .. } => "sphere",
+            MeshDefinition::Box { .. } => "box",
+            MeshDefinition::Torus { .. } => "torus",
+            MeshDefinition::Plane { .. } => "plane",
+        }
+    }
 }
 
 #[repr(C)]
@@ -57,6 +164,10 @@ pub struct MeshUniform {
     pub node_offset: u32,
     pub triangles: u32,
     pub triangle_offset: u32,
-    pub _p1: f32,
+    /// `UvView` of the mesh
+    pub uv_view: u32,
     pub material: MaterialUniform,
+    /// Width and height of a rect light, zero for every other mesh
+    pub rect_size: [f32; 2],
+    pub _p1: [f32; 2],
 }
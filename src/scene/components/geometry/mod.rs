@@ -1,3 +1,6 @@
 pub mod mesh;
+pub mod normals;
+pub mod procedural;
 pub mod sphere;
+pub mod validation;
 pub mod vertex;
@@ -0,0 +1,144 @@
+use glam::Vec3;
+use rayon::prelude::*;
+
+/// Smoothing group of every triangle of an OBJ, in the order tobj returns them when triangulating,
+/// or `None` when the file never sets one. Faces before the first `s` and after `s off` are in
+/// group 0, which is flat shaded.
+pub fn obj_smoothing_groups(obj: &str) -> Option<Vec<u32>> {
+    let mut any = false;
+    let mut group = 0;
+    let mut groups = vec![];
+    for line in obj.lines() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("s") => {
+                any = true;
+                group = match tokens.next() {
+                    Some("off") | None => 0,
+                    Some(token) => token.parse().unwrap_or(1),
+                };
+            }
+            // Polygons are fanned into one triangle per corner past the second
+            Some("f") => {
+                groups.extend(std::iter::repeat_n(group, tokens.count().saturating_sub(2)))
+            }
+            _ => {}
+        }
+    }
+    any.then_some(groups)
+}
+
+/// A normal for every corner of `indices`, averaged over the triangles around its position that
+/// are in the same smoothing group and meet its own triangle within `crease_angle` degrees, each
+/// weighted by area. Without `groups` every triangle is in one group, and without a crease angle
+/// edges are smoothed however sharp they are.
+pub fn smooth_normals(
+    positions: &[Vec3],
+    indices: &[u32],
+    groups: Option<&[u32]>,
+    crease_angle: Option<f32>,
+) -> Vec<Vec3> {
+    let face_normals: Vec<Vec3> = indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| positions[i as usize]);
+            (b - a).cross(c - a)
+        })
+        .collect();
+    let mut faces_at: Vec<Vec<usize>> = vec![vec![]; positions.len()];
+    for (face, triangle) in indices.chunks_exact(3).enumerate() {
+        for &i in triangle {
+            faces_at[i as usize].push(face);
+        }
+    }
+    let group = |face: usize| groups.map_or(1, |groups| groups[face]);
+    let min_cos = crease_angle.map(|angle| angle.to_radians().cos());
+    indices
+        .par_iter()
+        .enumerate()
+        .map(|(corner, &i)| {
+            let face = corner / 3;
+            if group(face) == 0 {
+                return face_normals[face].normalize_or_zero();
+            }
+            let direction = face_normals[face].normalize_or_zero();
+            faces_at[i as usize]
+                .iter()
+                .filter(|&&other| {
+                    group(other) == group(face)
+                        && min_cos.is_none_or(|min_cos| {
+                            face_normals[other].normalize_or_zero().dot(direction) >= min_cos
+                        })
+                })
+                .map(|&other| face_normals[other])
+                .sum::<Vec3>()
+                .normalize_or_zero()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles sharing the edge along Z, folded `angle` degrees away from flat
+    fn fold(angle: f32) -> (Vec<Vec3>, Vec<u32>) {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let positions = vec![
+            Vec3::ZERO,
+            Vec3::Z,
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(cos, sin, 0.0),
+        ];
+        (positions, vec![0, 2, 1, 0, 1, 3])
+    }
+
+    #[test]
+    fn smoothing_groups_follow_s_lines() {
+        let obj = "v 0 0 0\nf 1 2 3\ns 1\nf 1 2 3 4\ns off\nf 1 2 3\ns 2\nf 1 2 3\n";
+        assert_eq!(obj_smoothing_groups(obj), Some(vec![0, 1, 1, 0, 2]));
+        assert_eq!(obj_smoothing_groups("f 1 2 3\nf 1 2 3\n"), None);
+    }
+
+    #[test]
+    fn flat_surface_keeps_its_normal() {
+        let (positions, indices) = fold(0.0);
+        for normal in smooth_normals(&positions, &indices, None, None) {
+            assert!(normal.abs_diff_eq(Vec3::Y, 1e-5), "{}", normal);
+        }
+    }
+
+    #[test]
+    fn shared_edge_is_averaged() {
+        let (positions, indices) = fold(90.0);
+        let normals = smooth_normals(&positions, &indices, None, None);
+        let shared = (Vec3::Y + Vec3::NEG_X).normalize();
+        for corner in [0, 2, 3, 4] {
+            assert!(
+                normals[corner].abs_diff_eq(shared, 1e-5),
+                "{}",
+                normals[corner]
+            );
+        }
+        assert!(normals[1].abs_diff_eq(Vec3::Y, 1e-5));
+        assert!(normals[5].abs_diff_eq(Vec3::NEG_X, 1e-5));
+    }
+
+    #[test]
+    fn crease_angle_and_groups_keep_edges_sharp() {
+        let (positions, indices) = fold(90.0);
+        let creased = smooth_normals(&positions, &indices, None, Some(60.0));
+        let grouped = smooth_normals(&positions, &indices, Some(&[1, 2]), None);
+        let flat = smooth_normals(&positions, &indices, Some(&[0, 0]), None);
+        for normals in [creased, grouped, flat] {
+            assert!(normals[..3].iter().all(|n| n.abs_diff_eq(Vec3::Y, 1e-5)));
+            assert!(
+                normals[3..]
+                    .iter()
+                    .all(|n| n.abs_diff_eq(Vec3::NEG_X, 1e-5))
+            );
+        }
+        let smoothed = smooth_normals(&positions, &indices, None, Some(120.0));
+        assert!(!smoothed[0].abs_diff_eq(Vec3::Y, 1e-3));
+    }
+}
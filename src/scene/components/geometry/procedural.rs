@@ -0,0 +1,217 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::Vec3;
+
+use crate::scene::components::geometry::vertex::Vertex;
+
+/// Rings around a torus' hole and around its tube, per unit of radius
+const TORUS_DETAIL: f32 = 48.0;
+
+/// Unit sphere with `segments` slices of longitude and half as many bands of latitude. U runs
+/// once around the equator from +X towards -Z and V from the south pole up, like an equirect map.
+/// The seam is split so the texture wraps cleanly.
+pub fn uv_sphere(segments: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let slices = segments.max(3);
+    let bands = (slices / 2).max(2);
+    let mut vertices = Vec::with_capacity(((slices + 1) * (bands + 1)) as usize);
+    for band in 0..=bands {
+        let v = band as f32 / bands as f32;
+        let latitude = (v - 0.5) * PI;
+        for slice in 0..=slices {
+            let u = slice as f32 / slices as f32;
+            let longitude = u * TAU;
+            let normal = Vec3::new(
+                latitude.cos() * longitude.cos(),
+                latitude.sin(),
+                -latitude.cos() * longitude.sin(),
+            );
+            vertices.push(Vertex::with_uv(normal, normal, [u, v]));
+        }
+    }
+    let mut indices = vec![];
+    let row = slices + 1;
+    for band in 0..bands {
+        for slice in 0..slices {
+            let [a, b] = [band * row + slice, band * row + slice + 1];
+            let [c, d] = [a + row, b + row];
+            // The triangles meeting at a pole would have no area
+            if band > 0 {
+                indices.extend([a, b, c]);
+            }
+            if band < bands - 1 {
+                indices.extend([b, d, c]);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+/// Box centred on the origin `size` across, with flat faces each textured with the whole 0 to 1
+/// UV square.
+pub fn cuboid(size: Vec3) -> (Vec<Vertex>, Vec<u32>) {
+    let half = size * 0.5;
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for normal in [
+        Vec3::X,
+        Vec3::NEG_X,
+        Vec3::Y,
+        Vec3::NEG_Y,
+        Vec3::Z,
+        Vec3::NEG_Z,
+    ] {
+        // Right and up across the face seen from outside, so the corners wind anticlockwise
+        let up = if normal.y == 0.0 { Vec3::Y } else { Vec3::Z };
+        let right = up.cross(normal);
+        let first = vertices.len() as u32;
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let pos = (normal + right * x + up * y) * half;
+            vertices.push(Vertex::with_uv(
+                pos,
+                normal,
+                [(x + 1.0) * 0.5, (y + 1.0) * 0.5],
+            ));
+        }
+        indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+    }
+    (vertices, indices)
+}
+
+/// Ring around the Y axis, `major` from the axis to the middle of its tube and `minor` the
+/// tube's radius. U goes around the hole and V around the tube, starting on its outside.
+pub fn torus(major: f32, minor: f32) -> (Vec<Vertex>, Vec<u32>) {
+    let rings = ((major * TORUS_DETAIL) as u32).clamp(12, 256);
+    let sides = ((minor * TORUS_DETAIL) as u32).clamp(8, 128);
+    let mut vertices = Vec::with_capacity(((rings + 1) * (sides + 1)) as usize);
+    for ring in 0..=rings {
+        let u = ring as f32 / rings as f32;
+        let outward = Vec3::new((u * TAU).cos(), 0.0, -(u * TAU).sin());
+        for side in 0..=sides {
+            let v = side as f32 / sides as f32;
+            let normal = outward * (v * TAU).cos() + Vec3::Y * (v * TAU).sin();
+            vertices.push(Vertex::with_uv(
+                outward * major + normal * minor,
+                normal,
+                [u, v],
+            ));
+        }
+    }
+    let mut indices = Vec::with_capacity((rings * sides * 6) as usize);
+    let row = sides + 1;
+    for ring in 0..rings {
+        for side in 0..sides {
+            let [a, b] = [ring * row + side, ring * row + side + 1];
+            let [c, d] = [a + row, b + row];
+            indices.extend([a, c, b, b, c, d]);
+        }
+    }
+    (vertices, indices)
+}
+
+/// Square from -1 to 1 in the XZ plane facing up +Y, split into `subdivisions` quads along each
+/// side so it can be bent or displaced. V runs towards +Z.
+pub fn plane(subdivisions: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let quads = subdivisions.max(1);
+    let mut vertices = Vec::with_capacity(((quads + 1) * (quads + 1)) as usize);
+    for row in 0..=quads {
+        let v = row as f32 / quads as f32;
+        for column in 0..=quads {
+            let u = column as f32 / quads as f32;
+            let pos = Vec3::new(u * 2.0 - 1.0, 0.0, v * 2.0 - 1.0);
+            vertices.push(Vertex::with_uv(pos, Vec3::Y, [u, v]));
+        }
+    }
+    let mut indices = Vec::with_capacity((quads * quads * 6) as usize);
+    let stride = quads + 1;
+    for row in 0..quads {
+        for column in 0..quads {
+            let [a, b] = [row * stride + column, row * stride + column + 1];
+            let [c, d] = [a + stride, b + stride];
+            indices.extend([a, c, b, b, c, d]);
+        }
+    }
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks indices are in range, each triangle faces the way its vertex normals do, normals
+    /// are unit length and UVs lie in the 0 to 1 square.
+    fn check((vertices, indices): (Vec<Vertex>, Vec<u32>)) {
+        assert!(!indices.is_empty() && indices.len() % 3 == 0);
+        assert!(indices.iter().all(|&i| (i as usize) < vertices.len()));
+        for vertex in &vertices {
+            assert!(
+                (vertex.normal.length() - 1.0).abs() < 1e-4,
+                "{}",
+                vertex.normal
+            );
+            assert!(vertex.uv.iter().all(|uv| (0.0..=1.0).contains(uv)));
+        }
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize]);
+            let geometric = (b.pos - a.pos).cross(c.pos - a.pos);
+            assert!(
+                geometric.length() > 0.0,
+                "degenerate triangle {:?}",
+                triangle
+            );
+            for vertex in [a, b, c] {
+                assert!(
+                    geometric.normalize().dot(vertex.normal) > 0.0,
+                    "triangle {:?} faces away from its normals",
+                    triangle
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn uv_sphere() {
+        for segments in [0, 3, 16, 33] {
+            check(super::uv_sphere(segments));
+        }
+        let (vertices, _) = super::uv_sphere(16);
+        assert!(vertices.iter().all(|v| (v.pos.length() - 1.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn cuboid() {
+        let size = Vec3::new(1.0, 2.0, 3.0);
+        let (vertices, indices) = super::cuboid(size);
+        assert_eq!((vertices.len(), indices.len()), (24, 36));
+        for vertex in &vertices {
+            assert!(vertex.pos.abs().abs_diff_eq(size * 0.5, 1e-6));
+        }
+        check((vertices, indices));
+    }
+
+    #[test]
+    fn torus() {
+        for (major, minor) in [(1.0, 0.25), (0.1, 0.01), (10.0, 3.0)] {
+            let (vertices, indices) = super::torus(major, minor);
+            for vertex in &vertices {
+                let ring = Vec3::new(vertex.pos.x, 0.0, vertex.pos.z).normalize() * major;
+                assert!(((vertex.pos - ring).length() - minor).abs() < 1e-4);
+            }
+            check((vertices, indices));
+        }
+    }
+
+    #[test]
+    fn plane() {
+        for subdivisions in [0, 1, 7] {
+            let (vertices, indices) = super::plane(subdivisions);
+            let quads = subdivisions.max(1) as usize;
+            assert_eq!(indices.len(), quads * quads * 6);
+            assert!(
+                vertices
+                    .iter()
+                    .all(|v| v.pos.y == 0.0 && v.pos.abs().max_element() <= 1.0)
+            );
+            check((vertices, indices));
+        }
+    }
+}
@@ -7,6 +7,11 @@ pub struct Sphere {
     pub pos: [f32; 3],
     pub radius: f32,
     pub material: MaterialUniform,
+    /// Longitude offset of the texture in radians, spinning it around the sphere's Y axis
+    pub texture_rotation: f32,
+    /// Tilt of the texture's poles away from the Y axis in radians
+    pub texture_tilt: f32,
+    _p1: [f32; 2],
 }
 
 impl Sphere {
@@ -15,6 +20,9 @@ impl Sphere {
             pos: pos.to_array(),
             radius,
             material,
+            texture_rotation: 0.0,
+            texture_tilt: 0.0,
+            _p1: [0.0; 2],
         }
     }
 }
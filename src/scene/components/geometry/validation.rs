@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use crate::scene::components::geometry::mesh::MeshData;
+
+/// Triangles with less area than this fraction of their longest edge squared count as zero area
+const MIN_AREA_RATIO: f32 = 1e-8;
+
+/// Problems found in a mesh's geometry, most of which make a model render black or full of holes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MeshReport {
+    pub triangles: usize,
+    /// Triangles whose winding disagrees with their vertex normals, seen from the wrong side
+    pub inverted_triangles: usize,
+    pub zero_area_triangles: usize,
+    /// Edges shared by more than two triangles
+    pub non_manifold_edges: usize,
+    /// Every vertex has the same texture coordinate, so textures show a single texel
+    pub missing_uvs: bool,
+}
+
+impl MeshReport {
+    /// Checks every triangle of `data`. Edges are matched by vertex position, as loaders split
+    /// vertices along UV and normal seams.
+    pub fn check(data: &MeshData) -> MeshReport {
+        let vertices = &data.vertices;
+        let mut report = MeshReport {
+            triangles: data.indices.len() / 3,
+            missing_uvs: !vertices.is_empty()
+                && vertices.iter().all(|vertex| vertex.uv == vertices[0].uv),
+            ..Default::default()
+        };
+        let mut edges: HashMap<([u32; 3], [u32; 3]), u32> = HashMap::new();
+        for triangle in data.indices.chunks_exact(3) {
+            let vertex = |i: usize| vertices.get(triangle[i] as usize);
+            let (Some(a), Some(b), Some(c)) = (vertex(0), vertex(1), vertex(2)) else {
+                continue;
+            };
+            let face_normal = (b.pos - a.pos).cross(c.pos - a.pos);
+            let longest = (b.pos - a.pos)
+                .length_squared()
+                .max((c.pos - b.pos).length_squared())
+                .max((a.pos - c.pos).length_squared());
+            if face_normal.length() * 0.5 <= longest * MIN_AREA_RATIO {
+                report.zero_area_triangles += 1;
+                continue;
+            }
+            let vertex_normal = a.normal + b.normal + c.normal;
+            if vertex_normal != Vec3::ZERO && face_normal.dot(vertex_normal) < 0.0 {
+                report.inverted_triangles += 1;
+            }
+            for (from, to) in [(a.pos, b.pos), (b.pos, c.pos), (c.pos, a.pos)] {
+                let (from, to) = (
+                    from.to_array().map(f32::to_bits),
+                    to.to_array().map(f32::to_bits),
+                );
+                *edges.entry((from.min(to), from.max(to))).or_default() += 1;
+            }
+        }
+        report.non_manifold_edges = edges.values().filter(|&&count| count > 2).count();
+        report
+    }
+    pub fn is_clean(&self) -> bool {
+        self.inverted_triangles == 0
+            && self.zero_area_triangles == 0
+            && self.non_manifold_edges == 0
+            && !self.missing_uvs
+    }
+}
@@ -1,21 +1,33 @@
 use glam::Vec3;
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone)]
 pub struct Vertex {
     pub pos: Vec3,
     pub normal: Vec3,
     pub uv: [f32; 2],
+    /// sRGB colour multiplied into the albedo, white when the file has none
+    pub color: Vec3,
+}
+
+impl Default for Vertex {
+    fn default() -> Self {
+        Self::new(Vec3::ZERO, Vec3::ZERO)
+    }
 }
 
 impl Vertex {
     pub fn new(pos: Vec3, normal: Vec3) -> Self {
+        Self::with_uv(pos, normal, [0.0; 2])
+    }
+    pub fn with_uv(pos: Vec3, normal: Vec3, uv: [f32; 2]) -> Self {
         Self {
             pos,
             normal,
-            uv: [0.0; 2],
+            uv,
+            color: Vec3::ONE,
         }
     }
-    pub fn with_uv(pos: Vec3, normal: Vec3, uv: [f32; 2]) -> Self {
-        Self { pos, normal, uv }
+    pub fn with_color(self, color: Vec3) -> Self {
+        Self { color, ..self }
     }
 }
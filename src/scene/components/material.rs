@@ -1,5 +1,8 @@
 use crate::scene::components::texture::TextureDefinition;
 
+/// Palette slots past this many are ignored by the shader
+pub const MAX_MATERIALS: usize = 32;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct MaterialUniform {
@@ -15,6 +18,34 @@ pub struct MaterialUniform {
     pub flag: i32,
     pub diffuse_index: i32,
     pub normal_index: i32,
+    /// Palette indices mixed by a blend material, the mask picks `blend_b` where it is high
+    pub blend_a: i32,
+    pub blend_b: i32,
+    /// Texture whose red channel is the mask, -1 for procedural noise
+    pub blend_mask: i32,
+    /// Fraction of the surface covered by `blend_b`
+    pub blend_coverage: f32,
+    /// Width of the transition, near zero gives sharp chipped edges
+    pub blend_softness: f32,
+    /// Frequency of the noise mask in the entity's local space
+    pub blend_scale: f32,
+    /// Channel packed occlusion/roughness/metallic texture, -1 for none
+    pub orm_index: i32,
+    /// `OrmChannels::packed`, which channel of `orm_index` feeds each property
+    pub orm_channels: u32,
+    /// `LightFlag` bits
+    pub light_flags: u32,
+    /// Thickness in nanometres of an interference coating over glass or the specular lobe, 0 for none
+    pub film_thickness: f32,
+    pub film_ior: f32,
+    /// `RayVisibility` bits
+    pub visibility: u32,
+    /// `AddressMode` of every texture the material samples
+    pub address_mode: u32,
+    /// Which of overlapping glass objects owns the space they share, higher wins and 0 leaves the
+    /// object out of nesting
+    pub priority: u32,
+    pub _p1: [u32; 2],
 }
 impl Default for MaterialUniform {
     fn default() -> Self {
@@ -31,6 +62,21 @@ impl Default for MaterialUniform {
             flag: 0,
             diffuse_index: -1,
             normal_index: -1,
+            blend_a: -1,
+            blend_b: -1,
+            blend_mask: -1,
+            blend_coverage: 0.5,
+            blend_softness: 0.5,
+            blend_scale: 1.0,
+            orm_index: -1,
+            orm_channels: OrmChannels::default().packed(),
+            light_flags: 0,
+            film_thickness: 0.0,
+            film_ior: 1.33,
+            visibility: 0,
+            address_mode: AddressMode::Repeat as u32,
+            priority: 0,
+            _p1: [0; 2],
         }
     }
 }
@@ -40,6 +86,242 @@ pub enum MaterialFlag {
     DEFAULT = 0,
     GLASS = 1,
     TEXTURE = 2,
+    #[allow(clippy::upper_case_acronyms)]
+    BLEND = 3,
+}
+
+/// Per light visibility options, ORed into `MaterialUniform::light_flags`. They only change how
+/// the emitter itself is seen, the light it casts is unaffected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightFlag {
+    /// Camera rays pass through the emitter
+    HiddenFromCamera = 1,
+    /// Emits from the front face only
+    SingleSided = 2,
+    /// Rays leaving a mirror or glass bounce pass through the emitter, so it stays out of reflections
+    HiddenFromSpecular = 4,
+}
+
+impl LightFlag {
+    pub const ALL: [LightFlag; 3] = [
+        LightFlag::HiddenFromCamera,
+        LightFlag::SingleSided,
+        LightFlag::HiddenFromSpecular,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            LightFlag::HiddenFromCamera => "Invisible to Camera",
+            LightFlag::SingleSided => "Single Sided",
+            LightFlag::HiddenFromSpecular => "Invisible to Specular",
+        }
+    }
+}
+
+/// Kinds of ray an entity can be hidden from, ORed into `MaterialUniform::visibility`. Unlike
+/// `LightFlag` these apply to any entity, and the rays pass straight through it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RayVisibility {
+    HiddenFromCamera = 1,
+    /// Light sampling rays of the direct and Whitted integrators pass through, so it casts no
+    /// shadow there. The path tracer finds lights by bouncing, see `HiddenFromBounces`.
+    NoShadows = 2,
+    /// Every ray after the first bounce passes through, which keeps the entity out of reflections,
+    /// refractions and indirect light, and with path tracing also stops it casting shadows
+    HiddenFromBounces = 4,
+}
+
+impl RayVisibility {
+    pub const ALL: [RayVisibility; 3] = [
+        RayVisibility::HiddenFromCamera,
+        RayVisibility::NoShadows,
+        RayVisibility::HiddenFromBounces,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            RayVisibility::HiddenFromCamera => "Hidden from Camera",
+            RayVisibility::NoShadows => "No Shadows",
+            RayVisibility::HiddenFromBounces => "Hidden from Bounces",
+        }
+    }
+}
+
+/// What a material's textures show outside the 0 to 1 UV range, matching `ADDRESS_*` in the shader.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum AddressMode {
+    /// Tiles the texture
+    #[default]
+    Repeat = 0,
+    /// Stretches the edge texels outwards, for decals and textures that shouldn't tile
+    Clamp = 1,
+    /// Tiles the texture flipped every other time, hiding the seams of textures that don't tile
+    Mirror = 2,
+}
+
+impl AddressMode {
+    pub const ALL: [AddressMode; 3] =
+        [AddressMode::Repeat, AddressMode::Clamp, AddressMode::Mirror];
+    pub fn name(self) -> &'static str {
+        match self {
+            AddressMode::Repeat => "Repeat",
+            AddressMode::Clamp => "Clamp",
+            AddressMode::Mirror => "Mirror",
+        }
+    }
+    pub fn from_u32(mode: u32) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|m| *m as u32 == mode)
+            .unwrap_or_default()
+    }
+}
+
+/// Unit of a light's emission strength. Radiance of one corresponds to a luminance of one cd/m²,
+/// so physical values line up with `PhysicalExposure` on the camera.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum LightUnit {
+    /// Scales the emission colour as is
+    #[default]
+    Unitless,
+    /// Luminance in cd/m², independent of the emitter's size
+    Nits,
+    /// Total luminous flux in lm, spread over the emitter's surface
+    Lumens,
+}
+
+impl LightUnit {
+    pub const ALL: [LightUnit; 3] = [LightUnit::Unitless, LightUnit::Nits, LightUnit::Lumens];
+    pub fn name(self) -> &'static str {
+        match self {
+            LightUnit::Unitless => "Unitless",
+            LightUnit::Nits => "cd/m²",
+            LightUnit::Lumens => "lm",
+        }
+    }
+    /// Emission strength giving `value` in this unit for a Lambertian emitter of the given colour
+    /// and surface area.
+    pub fn to_strength(self, value: f32, color: [f32; 4], area: f32) -> f32 {
+        let luminance = luminance(color);
+        match self {
+            LightUnit::Unitless => value,
+            LightUnit::Nits => value / luminance,
+            LightUnit::Lumens => value / (std::f32::consts::PI * area.max(1e-6) * luminance),
+        }
+    }
+    /// Value in this unit of an emission strength, the inverse of `to_strength`.
+    pub fn strength_in(self, strength: f32, color: [f32; 4], area: f32) -> f32 {
+        let luminance = luminance(color);
+        match self {
+            LightUnit::Unitless => strength,
+            LightUnit::Nits => strength * luminance,
+            LightUnit::Lumens => strength * std::f32::consts::PI * area * luminance,
+        }
+    }
+}
+
+fn luminance(color: [f32; 4]) -> f32 {
+    (0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]).max(1e-6)
+}
+
+/// Common light sources by colour temperature in Kelvin, offered as presets in the inspector
+pub const TEMPERATURE_PRESETS: [(&str, f32); 5] = [
+    ("Candle", 1900.0),
+    ("Tungsten", 2700.0),
+    ("Halogen", 3200.0),
+    ("Daylight", 5600.0),
+    ("Overcast", 7000.0),
+];
+
+/// Linear colour of a black body at `kelvin`, scaled so the brightest channel is 1 and emission
+/// strength alone sets the brightness. Uses Tanner Helland's fit, good from 1000K to 40000K.
+pub fn kelvin_to_rgb(kelvin: f32) -> [f32; 4] {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let r = match t <= 66.0 {
+        true => 255.0,
+        false => 329.69873 * (t - 60.0).powf(-0.13320476),
+    };
+    let g = match t <= 66.0 {
+        true => 99.4708 * t.ln() - 161.11957,
+        false => 288.12216 * (t - 60.0).powf(-0.075514846),
+    };
+    let b = match t {
+        t if t >= 66.0 => 255.0,
+        t if t <= 19.0 => 0.0,
+        t => 138.51773 * (t - 10.0).ln() - 305.0448,
+    };
+    // The fit is in sRGB, the renderer works in linear
+    let linear = [r, g, b].map(|c: f32| {
+        let c = (c / 255.0).clamp(0.0, 1.0);
+        match c <= 0.04045 {
+            true => c / 12.92,
+            false => ((c + 0.055) / 1.055).powf(2.4),
+        }
+    });
+    let max = linear[0].max(linear[1]).max(linear[2]);
+    [linear[0] / max, linear[1] / max, linear[2] / max, 1.0]
+}
+
+/// Channel of an ORM texture read for each property, `None` leaves the material's own value.
+/// Roughness scales `1 - smoothness` and metallic moves the material towards a tinted mirror,
+/// as glTF's factors do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrmChannels {
+    /// Darkens the albedo. glTF keeps occlusion in red, but the path tracer finds occlusion
+    /// itself, so it is off unless a bake holds detail the geometry doesn't
+    pub occlusion: Option<u8>,
+    pub roughness: Option<u8>,
+    pub metallic: Option<u8>,
+}
+
+impl Default for OrmChannels {
+    fn default() -> Self {
+        Self {
+            occlusion: None,
+            roughness: Some(1),
+            metallic: Some(2),
+        }
+    }
+}
+
+impl OrmChannels {
+    /// Marks a property as not read from the texture in `packed`
+    pub const UNUSED: u32 = 0xff;
+    /// One byte per property, occlusion lowest, matching `orm_channel` in the shader.
+    pub fn packed(self) -> u32 {
+        let channel = |c: Option<u8>| c.map_or(Self::UNUSED, |c| c.min(3) as u32);
+        channel(self.occlusion) | channel(self.roughness) << 8 | channel(self.metallic) << 16
+    }
+    pub fn unpack(packed: u32) -> Self {
+        let channel = |shift: u32| match (packed >> shift) & 0xff {
+            Self::UNUSED => None,
+            c => Some(c as u8),
+        };
+        Self {
+            occlusion: channel(0),
+            roughness: channel(8),
+            metallic: channel(16),
+        }
+    }
+}
+
+/// Where a blend material's mix factor comes from
+pub enum BlendMask {
+    /// Red channel of a texture, used as is
+    #[allow(unused)]
+    Texture(TextureDefinition),
+    /// Thresholded fractal noise, for rust patches and paint chips
+    Noise {
+        scale: f32,
+        coverage: f32,
+        softness: f32,
+    },
+}
+
+/// Mixes two palette materials, see `SceneDefinition::add_material`. Each sample takes one of the
+/// two whole materials, so glass, emission and textures blend as well as colour does.
+pub struct MaterialBlend {
+    pub a: usize,
+    pub b: usize,
+    pub mask: BlendMask,
 }
 
 pub struct MaterialDefinition {
@@ -49,12 +331,27 @@ pub struct MaterialDefinition {
     pub absorption: [f32; 4],
     pub absorption_stength: f32,
     pub emission_strength: f32,
+    /// Unit `emission_strength` is given in, converted when the scene is instantiated
+    pub emission_unit: LightUnit,
+    /// `LightFlag` bits
+    pub light_flags: u32,
+    /// `RayVisibility` bits
+    pub visibility: u32,
+    /// Thin film coating as (thickness in nanometres, index of refraction)
+    pub thin_film: Option<(f32, f32)>,
     pub smoothness: f32,
     pub specular: f32,
     pub ior: f32,
+    /// See `MaterialUniform::priority`
+    pub priority: u32,
     pub flag: MaterialFlag,
     pub diffuse_texture: Option<TextureDefinition>,
     pub normal_texture: Option<TextureDefinition>,
+    /// Channel packed occlusion/roughness/metallic, see `OrmChannels`
+    pub orm_texture: Option<(TextureDefinition, OrmChannels)>,
+    /// How every texture above is addressed outside the 0 to 1 UV range
+    pub address_mode: AddressMode,
+    pub blend: Option<MaterialBlend>,
 }
 
 impl MaterialDefinition {
@@ -75,12 +372,20 @@ impl Default for MaterialDefinition {
             absorption: [0.0; 4],
             absorption_stength: 0.0,
             emission_strength: 0.0,
+            emission_unit: LightUnit::Unitless,
+            light_flags: 0,
+            visibility: 0,
+            thin_film: None,
             smoothness: 1.0,
             specular: 0.0,
             ior: 1.0,
+            priority: 0,
             flag: MaterialFlag::DEFAULT,
             diffuse_texture: None,
             normal_texture: None,
+            orm_texture: None,
+            address_mode: AddressMode::Repeat,
+            blend: None,
         }
     }
 }
@@ -95,12 +400,20 @@ impl MaterialDefinition {
             absorption: [0.0, 0.0, 0.0, 0.0],
             absorption_stength: 0.0,
             emission_strength: 0.0,
+            emission_unit: LightUnit::Unitless,
+            light_flags: 0,
+            visibility: 0,
+            thin_film: None,
             smoothness: 0.0,
             specular: 0.1,
             ior: 0.0,
+            priority: 0,
             flag: MaterialFlag::DEFAULT,
             diffuse_texture: None,
             normal_texture: None,
+            orm_texture: None,
+            address_mode: AddressMode::Repeat,
+            blend: None,
         }
     }
     pub fn color(mut self, color: [f32; 4]) -> Self {
@@ -113,11 +426,42 @@ impl MaterialDefinition {
         self.emission_strength = strength;
         self
     }
+    /// Interprets the emission strength in a physical unit.
+    pub fn light_unit(mut self, unit: LightUnit) -> Self {
+        self.emission_unit = unit;
+        self
+    }
+    pub fn light_flags(mut self, flags: &[LightFlag]) -> Self {
+        self.light_flags = flags.iter().fold(0, |bits, f| bits | *f as u32);
+        self
+    }
+    pub fn address_mode(mut self, mode: AddressMode) -> Self {
+        self.address_mode = mode;
+        self
+    }
     pub fn glass(mut self, index_of_refraction: f32) -> Self {
         self.ior = index_of_refraction;
         self.flag = MaterialFlag::GLASS;
         self
     }
+    /// Lets the glass overlap other glass with a priority, the higher priority filling the space
+    /// both take up. A liquid given a higher priority than its glass fills it to the walls.
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Tints light travelling through glass, more strongly the further it goes.
+    pub fn absorption(mut self, color: [f32; 4], strength: f32) -> Self {
+        self.absorption = color;
+        self.absorption_stength = strength;
+        self
+    }
+    /// Coats the surface in a film a few hundred nanometres thick, whose interference tints
+    /// reflections like a soap bubble or oil slick.
+    pub fn thin_film(mut self, thickness: f32, index_of_refraction: f32) -> Self {
+        self.thin_film = Some((thickness, index_of_refraction));
+        self
+    }
     pub fn specular(mut self, color: [f32; 4], specular: f32) -> Self {
         self.specular_color = color;
         self.specular = specular;
@@ -127,4 +471,15 @@ impl MaterialDefinition {
         self.smoothness = smoothness;
         self
     }
+    /// Reads roughness and metallic, and optionally occlusion, from one channel packed texture.
+    pub fn orm(mut self, texture: TextureDefinition, channels: OrmChannels) -> Self {
+        self.orm_texture = Some((texture, channels));
+        self
+    }
+    /// Blends palette materials `a` and `b`, nested blends are not resolved.
+    pub fn blend(mut self, a: usize, b: usize, mask: BlendMask) -> Self {
+        self.flag = MaterialFlag::BLEND;
+        self.blend = Some(MaterialBlend { a, b, mask });
+        self
+    }
 }
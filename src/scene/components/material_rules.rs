@@ -0,0 +1,192 @@
+use crate::scene::components::material::MaterialDefinition;
+
+/// Ready made materials for `MaterialRule`, so a rule only has to say what a part is made of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaterialPreset {
+    Glass,
+    /// Polished metal tinted by the colour
+    Metal,
+    Plastic,
+    Rubber,
+    /// Rough, non-reflective surface such as plaster or cloth
+    Matte,
+    /// White emitter
+    Light,
+}
+
+impl MaterialPreset {
+    pub const ALL: [MaterialPreset; 6] = [
+        MaterialPreset::Glass,
+        MaterialPreset::Metal,
+        MaterialPreset::Plastic,
+        MaterialPreset::Rubber,
+        MaterialPreset::Matte,
+        MaterialPreset::Light,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            MaterialPreset::Glass => "Glass",
+            MaterialPreset::Metal => "Metal",
+            MaterialPreset::Plastic => "Plastic",
+            MaterialPreset::Rubber => "Rubber",
+            MaterialPreset::Matte => "Matte",
+            MaterialPreset::Light => "Light",
+        }
+    }
+    /// The preset called `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.name().eq_ignore_ascii_case(name))
+    }
+    pub fn definition(self) -> MaterialDefinition {
+        let base = MaterialDefinition::new();
+        match self {
+            MaterialPreset::Glass => base.glass(1.5).smooth(1.0),
+            MaterialPreset::Metal => base
+                .color([0.8, 0.8, 0.82, 1.0])
+                .specular([0.8, 0.8, 0.82, 1.0], 1.0)
+                .smooth(0.9),
+            MaterialPreset::Plastic => base
+                .color([0.6, 0.6, 0.6, 1.0])
+                .specular([1.0; 4], 0.08)
+                .smooth(0.9),
+            MaterialPreset::Rubber => base
+                .color([0.05, 0.05, 0.05, 1.0])
+                .specular([1.0; 4], 0.03)
+                .smooth(0.3),
+            MaterialPreset::Matte => base
+                .color([0.7, 0.7, 0.7, 1.0])
+                .specular([1.0; 4], 0.0)
+                .smooth(0.0),
+            MaterialPreset::Light => base
+                .color([0.0, 0.0, 0.0, 1.0])
+                .emissive([1.0; 4], 4.0)
+                .specular([1.0; 4], 0.0),
+        }
+    }
+}
+
+/// Material given to the parts of an imported model whose group or material name matches
+/// `pattern`, see `MeshDefinition::FromFile`. Patterns are globs rather than full regular
+/// expressions, `*` matching any run of characters and `?` any one, and ignore case, so
+/// `*glass*` matches "WindowGlass_02". The first matching rule wins.
+pub struct MaterialRule {
+    pub pattern: String,
+    pub material: MaterialDefinition,
+}
+
+impl MaterialRule {
+    pub fn new(pattern: &str, material: MaterialDefinition) -> Self {
+        Self {
+            pattern: pattern.to_owned(),
+            material,
+        }
+    }
+    pub fn preset(pattern: &str, preset: MaterialPreset) -> Self {
+        Self::new(pattern, preset.definition())
+    }
+    /// Rules for names that commonly give away what a part is made of.
+    pub fn common() -> Vec<MaterialRule> {
+        [
+            ("*glass*", MaterialPreset::Glass),
+            ("*chrome*", MaterialPreset::Metal),
+            ("*metal*", MaterialPreset::Metal),
+            ("*steel*", MaterialPreset::Metal),
+            ("*tyre*", MaterialPreset::Rubber),
+            ("*tire*", MaterialPreset::Rubber),
+            ("*rubber*", MaterialPreset::Rubber),
+            ("*plastic*", MaterialPreset::Plastic),
+            ("*light*", MaterialPreset::Light),
+            ("*lamp*", MaterialPreset::Light),
+            ("*emissive*", MaterialPreset::Light),
+        ]
+        .into_iter()
+        .map(|(pattern, preset)| MaterialRule::preset(pattern, preset))
+        .collect()
+    }
+    /// Whether any of `names` matches the pattern.
+    pub fn matches<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> bool {
+        let pattern: Vec<char> = self.pattern.to_lowercase().chars().collect();
+        names.into_iter().any(|name| {
+            let name: Vec<char> = name.to_lowercase().chars().collect();
+            glob_match(&pattern, &name)
+        })
+    }
+}
+
+/// Matches `*` and `?` wildcards, backtracking only to the last `*` seen.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last star swallow one more character and retry from there
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, name: &str) -> bool {
+        let chars = |text: &str| text.chars().collect::<Vec<char>>();
+        glob_match(&chars(pattern), &chars(name))
+    }
+
+    #[test]
+    fn literal_and_wildcards() {
+        assert!(glob("glass", "glass"));
+        assert!(!glob("glass", "glasses"));
+        assert!(glob("gl?ss", "gloss"));
+        assert!(!glob("gl?ss", "glss"));
+        assert!(glob("*", ""));
+        assert!(glob("**", "anything"));
+        assert!(!glob("", "x"));
+        assert!(glob("", ""));
+    }
+
+    #[test]
+    fn stars_backtrack() {
+        assert!(glob("*glass*", "windowglass_02"));
+        assert!(glob("*a*b", "xaxxaxb"));
+        assert!(!glob("*a*b", "xaxxaxbx"));
+        assert!(glob("a*b*c", "abbbcbc"));
+        assert!(!glob("a*b?c", "abc"));
+    }
+
+    #[test]
+    fn rules_ignore_case_and_first_preset_wins() {
+        let rule = MaterialRule::new("*Glass*", MaterialDefinition::new());
+        assert!(rule.matches(["Body", "WindowGLASS_02"]));
+        assert!(!rule.matches(["Body"]));
+        let common = MaterialRule::common();
+        let first = common
+            .iter()
+            .position(|rule| rule.matches(["ChromeLampShade"]));
+        assert_eq!(first.map(|i| common[i].pattern.as_str()), Some("*chrome*"));
+        assert_eq!(
+            MaterialPreset::from_name("rubber"),
+            Some(MaterialPreset::Rubber)
+        );
+        assert_eq!(MaterialPreset::from_name("wood"), None);
+    }
+}
@@ -1,4 +1,8 @@
+pub mod environment;
 pub mod geometry;
 pub mod material;
+pub mod material_rules;
+pub mod portal;
 pub mod texture;
 pub mod transform;
+pub mod volume;
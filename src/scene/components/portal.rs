@@ -0,0 +1,39 @@
+use glam::Vec3;
+
+/// Portals past this many are ignored by the shader
+pub const MAX_PORTALS: usize = 8;
+
+/// Opening such as a window or skylight that the environment is seen through. Half of the diffuse
+/// bounces aim at a random portal, so interiors lit through small openings converge faster.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Portal {
+    pub corner: Vec3,
+    /// Edges spanning the opening from `corner`
+    pub u: Vec3,
+    pub v: Vec3,
+}
+
+impl Portal {
+    pub fn new(corner: Vec3, u: Vec3, v: Vec3) -> Self {
+        Self { corner, u, v }
+    }
+    pub fn to_uniform(self) -> PortalUniform {
+        PortalUniform {
+            corner: self.corner.to_array(),
+            u: self.u.to_array(),
+            v: self.v.to_array(),
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
+pub struct PortalUniform {
+    corner: [f32; 3],
+    _p1: f32,
+    u: [f32; 3],
+    _p2: f32,
+    v: [f32; 3],
+    _p3: f32,
+}
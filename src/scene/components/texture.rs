@@ -1,3 +1,21 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use image::{RgbaImage, imageops};
+
+use crate::core::{
+    asset::{asset_path, decode_texture},
+    download,
+    jobs::{self, Job, Jobs},
+};
+
+/// Frames an image sequence is looked for up to, so a typo'd pattern can't scan forever
+const MAX_SEQUENCE_FRAMES: usize = 100_000;
+/// Frames of each sequence decoded ahead of the one shown
+pub const PREFETCH_FRAMES: usize = 4;
+
 #[derive(Clone, Copy, Debug)]
 pub struct TextureRef {
     pub width: u32,
@@ -19,6 +37,13 @@ pub enum TextureDefinition {
     FromFile {
         path: String,
     },
+    /// Numbered images shown in turn as the timeline plays, for screens and projected video. The
+    /// run of `#`s in the pattern stands for the zero padded frame number, as in
+    /// `screen/frame_####.png`.
+    Sequence {
+        pattern: String,
+        fps: f32,
+    },
     #[allow(unused)]
     FromData {
         width: u32,
@@ -26,3 +51,201 @@ pub enum TextureDefinition {
         pixels: Vec<u8>,
     },
 }
+
+/// An image sequence in a texture slot, the slot shows whichever frame the timeline is on.
+#[derive(Clone, Debug)]
+pub struct AnimatedTexture {
+    /// Asset paths of the frames in order
+    pub frames: Vec<String>,
+    pub fps: f32,
+}
+
+impl AnimatedTexture {
+    /// Finds the frames of `pattern` in the assets folder, numbered on from 0 or 1 until one is
+    /// missing. Frames of a URL pattern are only asked after, each is downloaded once it is shown.
+    pub fn find(pattern: &str, fps: f32) -> Result<Self, Box<dyn std::error::Error>> {
+        let end = pattern
+            .rfind('#')
+            .ok_or("the pattern has no # for the frame number")?;
+        let start = pattern[..end].trim_end_matches('#').len();
+        let width = end + 1 - start;
+        let frame = |n: usize| {
+            format!(
+                "{}{:0width$}{}",
+                &pattern[..start],
+                n,
+                &pattern[end + 1..],
+                width = width
+            )
+        };
+        let exists = |n: usize| match download::is_url(pattern) {
+            true => download::exists(&frame(n)),
+            false => asset_path(&frame(n)).is_ok_and(|path| path.is_file()),
+        };
+        let first = match exists(0) {
+            true => 0,
+            false => 1,
+        };
+        let frames: Vec<String> = (first..first + MAX_SEQUENCE_FRAMES)
+            .take_while(|n| exists(*n))
+            .map(frame)
+            .collect();
+        if frames.is_empty() {
+            return Err(format!("no frames found, looked for {}", frame(first)).into());
+        }
+        Ok(Self {
+            frames,
+            fps: fps.max(f32::EPSILON),
+        })
+    }
+    /// Frame shown `time` seconds into the timeline, looping once the sequence runs out.
+    pub fn frame_at(&self, time: f32) -> &str {
+        self.frame_after(time, 0)
+    }
+    /// Frame `ahead` frames on from the one shown at `time`, for decoding before it is needed.
+    pub fn frame_after(&self, time: f32, ahead: usize) -> &str {
+        let n = ((time.max(0.0) * self.fps) as usize + ahead) % self.frames.len();
+        &self.frames[n]
+    }
+}
+
+/// A sequence frame for a texture slot, by slot and asset path.
+type FrameKey = (usize, String);
+
+/// Sequence frames decoded on the job pool ahead of the timeline, so playback never waits on a
+/// file. Unlike still textures they skip the mip cache, which would otherwise keep a chain for
+/// every frame of every video.
+#[derive(Default)]
+pub struct SequenceFrames {
+    loading: Vec<Job<(FrameKey, Result<RgbaImage, String>)>>,
+    pending: HashSet<FrameKey>,
+    ready: HashMap<FrameKey, Arc<RgbaImage>>,
+    /// Frames that couldn't be read, left missing rather than tried again every update
+    failed: HashSet<FrameKey>,
+}
+
+impl SequenceFrames {
+    /// Starts decoding `path` for `slot` at mip `level` unless it is decoded or on its way.
+    pub fn request(&mut self, jobs: &Jobs, slot: usize, path: &str, level: u32) {
+        let key = (slot, path.to_owned());
+        if self.ready.contains_key(&key)
+            || self.failed.contains(&key)
+            || !self.pending.insert(key.clone())
+        {
+            return;
+        }
+        let path = path.to_owned();
+        self.loading
+            .push(jobs.spawn(format!("Decode {}", path), move || {
+                let image = decode_frame(&path, level);
+                ((slot, path), image)
+            }));
+    }
+    /// Collects the frames that finished decoding.
+    pub fn poll(&mut self) {
+        for (key, image) in jobs::finished(&mut self.loading) {
+            self.pending.remove(&key);
+            match image {
+                Ok(image) => {
+                    self.ready.insert(key, Arc::new(image));
+                }
+                Err(e) => {
+                    log::error!("Failed to load texture sequence frame {}: {}", key.1, e);
+                    self.failed.insert(key);
+                }
+            }
+        }
+        // Failed jobs never report which frame they were, so anything no longer loading is
+        // free to be asked for again
+        if self.loading.is_empty() {
+            self.pending.clear();
+        }
+    }
+    /// `path` for `slot` if it has been decoded.
+    pub fn get(&self, slot: usize, path: &str) -> Option<Arc<RgbaImage>> {
+        self.ready.get(&(slot, path.to_owned())).cloned()
+    }
+    /// Drops decoded frames that aren't in `wanted`, the ones shown and about to be.
+    pub fn retain(&mut self, wanted: &HashSet<FrameKey>) {
+        self.ready.retain(|key, _| wanted.contains(key));
+    }
+    /// Forgets every frame, for when the scene changes. Jobs still running finish unheard.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Decodes a sequence frame straight from disk, scaled down to mip `level`.
+pub fn decode_frame(path: &str, level: u32) -> Result<RgbaImage, String> {
+    let bytes = asset_path(path)
+        .and_then(|path| Ok(std::fs::read(path)?))
+        .map_err(|e| e.to_string())?;
+    let image = decode_texture(&bytes).map_err(|e| e.to_string())?;
+    if level == 0 {
+        return Ok(image);
+    }
+    let (width, height) = (
+        (image.width() >> level).max(1),
+        (image.height() >> level).max(1),
+    );
+    Ok(imageops::resize(
+        &image,
+        width,
+        height,
+        imageops::FilterType::Triangle,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(frames: usize, fps: f32) -> AnimatedTexture {
+        AnimatedTexture {
+            frames: (0..frames).map(|n| format!("frame_{}.png", n)).collect(),
+            fps,
+        }
+    }
+
+    #[test]
+    fn frame_at_follows_time_and_loops() {
+        let animation = sequence(3, 2.0);
+        assert_eq!(animation.frame_at(0.0), "frame_0.png");
+        assert_eq!(animation.frame_at(0.49), "frame_0.png");
+        assert_eq!(animation.frame_at(0.5), "frame_1.png");
+        assert_eq!(animation.frame_at(1.0), "frame_2.png");
+        assert_eq!(animation.frame_at(1.5), "frame_0.png");
+        assert_eq!(animation.frame_at(-1.0), "frame_0.png");
+    }
+
+    #[test]
+    fn frame_after_wraps_ahead() {
+        let animation = sequence(3, 1.0);
+        assert_eq!(animation.frame_after(1.0, 0), "frame_1.png");
+        assert_eq!(animation.frame_after(1.0, 1), "frame_2.png");
+        assert_eq!(animation.frame_after(1.0, 2), "frame_0.png");
+    }
+
+    #[test]
+    fn find_numbers_frames_until_one_is_missing() {
+        let dir = std::env::temp_dir().join(format!("sequence_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for n in [1, 2, 3, 5] {
+            std::fs::write(dir.join(format!("frame_{:03}.png", n)), []).unwrap();
+        }
+        let pattern = dir.join("frame_###.png");
+        let animation = AnimatedTexture::find(pattern.to_str().unwrap(), 0.0).unwrap();
+        let names: Vec<String> = animation
+            .frames
+            .iter()
+            .map(|frame| frame.rsplit(['/', '\\']).next().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["frame_001.png", "frame_002.png", "frame_003.png"]);
+        assert!(animation.fps > 0.0);
+
+        let missing = dir.join("other_##.png");
+        assert!(AnimatedTexture::find(missing.to_str().unwrap(), 24.0).is_err());
+        assert!(AnimatedTexture::find("frame.png", 24.0).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
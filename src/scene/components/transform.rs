@@ -10,6 +10,15 @@ impl Transform {
     pub fn to_matrix(&self) -> Mat4 {
         Mat4::from_scale_rotation_translation(self.scale, self.rot, self.pos)
     }
+    /// `child` placed relative to this transform, as a parent moves what's attached to it. Exact
+    /// for uniform scales, a non-uniform parent scale can't shear a rotated child.
+    pub fn compose(&self, child: &Transform) -> Self {
+        Self {
+            pos: self.to_matrix().transform_point3(child.pos),
+            rot: self.rot * child.rot,
+            scale: self.scale * child.scale,
+        }
+    }
     pub fn cam(origin: Vec3, look_at: Vec3) -> Self {
         Self {
             pos: origin,
@@ -0,0 +1,347 @@
+use std::{error::Error, sync::Arc};
+
+use glam::{IVec3, UVec3, Vec3};
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+
+/// Voxels along each axis of a brick
+pub const BRICK_SIZE: u32 = 8;
+const BRICK_VOXELS: usize = (BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) as usize;
+/// Brick table entry for a brick without any density, never stored or sampled
+pub const EMPTY_BRICK: u32 = u32::MAX;
+/// Volumes past this many are ignored by the shader
+pub const MAX_VOLUMES: usize = 16;
+
+/// Dense density and emission channels of a heterogeneous medium, stored x fastest then y then z.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    pub dims: UVec3,
+    pub density: Vec<f32>,
+    pub emission: Vec<f32>,
+}
+
+impl VoxelGrid {
+    /// Parses a Mitsuba `.vol` grid. OpenVDB and NanoVDB files aren't read, they have to be
+    /// converted to `.vol` first. The first channel is density, a second channel (if present) is
+    /// emission.
+    pub fn from_vol(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if bytes.len() < 48 || &bytes[..3] != b"VOL" || bytes[3] != 3 {
+            return Err("not a version 3 .vol file".into());
+        }
+        let word = |i: usize| -> [u8; 4] { bytes[i..i + 4].try_into().unwrap() };
+        if i32::from_le_bytes(word(4)) != 1 {
+            return Err("only float32 .vol grids are supported".into());
+        }
+        let res = |i: usize| -> Result<u32, Box<dyn Error>> {
+            u32::try_from(i32::from_le_bytes(word(i))).map_err(|_| "negative resolution".into())
+        };
+        let dims = UVec3::new(res(8)?, res(12)?, res(16)?);
+        let channels = res(20)? as usize;
+        // A corrupt header can claim more data than fits in memory
+        let (voxels, len) = (dims.x as usize)
+            .checked_mul(dims.y as usize)
+            .and_then(|n| n.checked_mul(dims.z as usize))
+            .and_then(|voxels| Some((voxels, voxels.checked_mul(channels)?.checked_mul(4)?)))
+            .ok_or("the .vol resolution is too large")?;
+        // Header is followed by the bounding box, which is replaced by the scene's own bounds
+        let data = &bytes[48..];
+        if channels == 0 || data.len() < len {
+            return Err("truncated .vol data".into());
+        }
+        let value = |i: usize, c: usize| {
+            f32::from_le_bytes(data[(i * channels + c) * 4..][..4].try_into().unwrap())
+        };
+        Ok(Self {
+            dims,
+            density: (0..voxels).map(|i| value(i, 0).max(0.0)).collect(),
+            emission: (0..voxels)
+                .map(|i| {
+                    if channels > 1 {
+                        value(i, 1).max(0.0)
+                    } else {
+                        0.0
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    /// Billowy cloud built from fractal noise, with emission concentrated in its core.
+    pub fn cloud(resolution: u32, seed: u32) -> Self {
+        let n = resolution.max(1) as usize;
+        let mut density = vec![0.0; n * n * n];
+        let mut emission = vec![0.0; n * n * n];
+        density
+            .par_chunks_mut(n * n)
+            .zip(emission.par_chunks_mut(n * n))
+            .enumerate()
+            .for_each(|(z, (density, emission))| {
+                for y in 0..n {
+                    for x in 0..n {
+                        let p = (Vec3::new(x as f32, y as f32, z as f32) + 0.5) / n as f32;
+                        // Squashed vertically so it reads as a cumulus rather than a ball
+                        let r = ((p * 2.0 - 1.0) * Vec3::new(1.0, 1.6, 1.0)).length();
+                        let shape = 0.9 - r + (fbm(p * 4.0, seed) - 0.5) * 0.9;
+                        density[y * n + x] = (shape * 4.0).clamp(0.0, 1.0);
+                        emission[y * n + x] = (1.0 - r * 1.8).clamp(0.0, 1.0).powi(2);
+                    }
+                }
+            });
+        Self {
+            dims: UVec3::splat(n as u32),
+            density,
+            emission,
+        }
+    }
+}
+
+fn hash(p: IVec3, seed: u32) -> f32 {
+    let mut h = (p.x as u32).wrapping_mul(0x8da6_b343)
+        ^ (p.y as u32).wrapping_mul(0xd816_3841)
+        ^ (p.z as u32).wrapping_mul(0xcb1a_b31f)
+        ^ seed.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32
+}
+
+fn value_noise(p: Vec3, seed: u32) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let u = f * f * (3.0 - 2.0 * f);
+    let i = cell.as_ivec3();
+    let corner = |x, y, z| hash(i + IVec3::new(x, y, z), seed);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u.x),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u.x),
+            u.y,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u.x),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u.x),
+            u.y,
+        ),
+        u.z,
+    )
+}
+
+fn fbm(p: Vec3, seed: u32) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.0;
+    for octave in 0..5 {
+        total += value_noise(p * frequency, seed.wrapping_add(octave)) * amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    total / (1.0 - amplitude * 2.0)
+}
+
+/// Sparse form of a `VoxelGrid` as uploaded to the GPU. Every brick holding density is stored as
+/// 16 bit unorm density/emission pairs, empty bricks only cost a table entry.
+#[derive(Debug, Default)]
+pub struct BrickMap {
+    pub dims: UVec3,
+    /// One entry per brick, the brick's index into `bricks` or `EMPTY_BRICK`
+    pub table: Vec<u32>,
+    /// `BRICK_SIZE`³ words per brick, density in the low half and emission in the high half
+    pub bricks: Vec<u32>,
+    pub max_density: f32,
+    pub max_emission: f32,
+}
+
+impl BrickMap {
+    pub fn new(grid: &VoxelGrid) -> Self {
+        let max_density = grid.density.iter().fold(0.0f32, |a, &b| a.max(b));
+        let max_emission = grid.emission.iter().fold(0.0f32, |a, &b| a.max(b));
+        let quantize = |v: f32, max: f32| {
+            if max > 0.0 {
+                (v / max * 65535.0).round() as u32
+            } else {
+                0
+            }
+        };
+        let counts = grid.dims.map(|d| d.div_ceil(BRICK_SIZE));
+        let mut table = vec![EMPTY_BRICK; (counts.x * counts.y * counts.z) as usize];
+        let mut bricks = Vec::new();
+        let mut words = [0u32; BRICK_VOXELS];
+        for bz in 0..counts.z {
+            for by in 0..counts.y {
+                for bx in 0..counts.x {
+                    let origin = UVec3::new(bx, by, bz) * BRICK_SIZE;
+                    let mut occupied = false;
+                    for (i, word) in words.iter_mut().enumerate() {
+                        let i = i as u32;
+                        let local = UVec3::new(
+                            i % BRICK_SIZE,
+                            (i / BRICK_SIZE) % BRICK_SIZE,
+                            i / (BRICK_SIZE * BRICK_SIZE),
+                        );
+                        let v = origin + local;
+                        *word = 0;
+                        if v.cmplt(grid.dims).all() {
+                            let index = ((v.z * grid.dims.y + v.y) * grid.dims.x + v.x) as usize;
+                            let density = quantize(grid.density[index], max_density);
+                            *word = density | quantize(grid.emission[index], max_emission) << 16;
+                            occupied |= density > 0;
+                        }
+                    }
+                    if occupied {
+                        table[((bz * counts.y + by) * counts.x + bx) as usize] =
+                            (bricks.len() / BRICK_VOXELS) as u32;
+                        bricks.extend_from_slice(&words);
+                    }
+                }
+            }
+        }
+        Self {
+            dims: grid.dims,
+            table,
+            bricks,
+            max_density,
+            max_emission,
+        }
+    }
+    /// Size on the GPU in 4 byte words
+    pub fn words(&self) -> usize {
+        self.table.len() + self.bricks.len()
+    }
+    pub fn brick_count(&self) -> usize {
+        self.bricks.len() / BRICK_VOXELS
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum VolumeSource {
+    /// Mitsuba `.vol` grid in the assets folder
+    FromFile {
+        path: String,
+    },
+    Cloud {
+        resolution: u32,
+        seed: u32,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct VolumeDefinition {
+    pub source: VolumeSource,
+    pub bounds_min: Vec3,
+    pub bounds_max: Vec3,
+    pub density_scale: f32,
+    pub color: [f32; 4],
+    pub emission_color: [f32; 4],
+    pub emission_strength: f32,
+}
+
+impl Default for VolumeDefinition {
+    fn default() -> Self {
+        Self {
+            source: VolumeSource::Cloud {
+                resolution: 64,
+                seed: 0,
+            },
+            bounds_min: Vec3::splat(-1.0),
+            bounds_max: Vec3::splat(1.0),
+            density_scale: 8.0,
+            color: [0.95, 0.95, 0.95, 1.0],
+            emission_color: [1.0, 0.45, 0.1, 1.0],
+            emission_strength: 0.0,
+        }
+    }
+}
+
+/// Axis aligned heterogeneous medium, ray marched with delta tracking.
+#[derive(Debug, Clone)]
+pub struct Volume {
+    pub grid: Arc<BrickMap>,
+    pub bounds_min: Vec3,
+    pub bounds_max: Vec3,
+    /// Extinction per unit length where the grid's density is highest
+    pub density_scale: f32,
+    /// Single scattering albedo
+    pub color: [f32; 4],
+    pub emission_color: [f32; 4],
+    /// Radiance added at each collision where the emission channel is highest
+    pub emission_strength: f32,
+}
+
+impl Volume {
+    pub fn new(definition: &VolumeDefinition, grid: &VoxelGrid) -> Self {
+        Self {
+            grid: Arc::new(BrickMap::new(grid)),
+            bounds_min: definition.bounds_min,
+            bounds_max: definition.bounds_max,
+            density_scale: definition.density_scale,
+            color: definition.color,
+            emission_color: definition.emission_color,
+            emission_strength: definition.emission_strength,
+        }
+    }
+    /// `offset` is where this volume's brick table starts in the voxel buffer, its bricks follow directly.
+    pub fn to_uniform(&self, offset: u32) -> VolumeUniform {
+        VolumeUniform {
+            bounds_min: self.bounds_min.to_array(),
+            majorant: self.density_scale * self.grid.max_density,
+            bounds_max: self.bounds_max.to_array(),
+            max_emission: self.grid.max_emission,
+            color: self.color,
+            emission: self.emission_color.map(|c| c * self.emission_strength),
+            dims: self.grid.dims.to_array(),
+            table: offset,
+            data: offset + self.grid.table.len() as u32,
+            _p1: [0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
+pub struct VolumeUniform {
+    pub bounds_min: [f32; 3],
+    /// Upper bound on the extinction inside the volume, zero disables it
+    pub majorant: f32,
+    pub bounds_max: [f32; 3],
+    pub max_emission: f32,
+    pub color: [f32; 4],
+    pub emission: [f32; 4],
+    pub dims: [u32; 3],
+    pub table: u32,
+    pub data: u32,
+    _p1: [u32; 3],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vol(dims: [i32; 3], channels: i32, values: &[f32]) -> Vec<u8> {
+        let mut bytes = b"VOL\x03".to_vec();
+        for word in [1, dims[0], dims[1], dims[2], channels] {
+            bytes.extend(word.to_le_bytes());
+        }
+        bytes.extend([0; 24]);
+        bytes.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn reads_density_and_emission() {
+        let grid = VoxelGrid::from_vol(&vol([2, 1, 1], 2, &[0.5, 1.0, -1.0, 2.0])).unwrap();
+        assert_eq!(grid.dims, UVec3::new(2, 1, 1));
+        assert_eq!(grid.density, [0.5, 0.0]);
+        assert_eq!(grid.emission, [1.0, 2.0]);
+    }
+
+    #[test]
+    fn oversized_or_truncated_grids_are_errors() {
+        assert!(VoxelGrid::from_vol(&vol([i32::MAX; 3], 1, &[])).is_err());
+        assert!(VoxelGrid::from_vol(&vol([65536, 65536, 1], i32::MAX, &[])).is_err());
+        assert!(VoxelGrid::from_vol(&vol([2, 2, 2], 1, &[0.0; 7])).is_err());
+    }
+}
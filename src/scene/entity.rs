@@ -1,16 +1,114 @@
 use glam::Vec3;
 
 use crate::scene::components::{
-    geometry::mesh::MeshDefinition, material::MaterialDefinition, transform::Transform,
+    geometry::mesh::MeshDefinition,
+    material::{LightFlag, LightUnit, MaterialDefinition},
+    transform::Transform,
 };
 
 pub enum Primitive {
-    Sphere { centre: Vec3, radius: f32 },
+    Sphere {
+        centre: Vec3,
+        radius: f32,
+    },
     Mesh(MeshDefinition),
+    /// Built from a `RectLight`, the entity's material holds its emission
+    RectLight {
+        width: f32,
+        height: f32,
+    },
 }
 
 pub struct EntityDefinition {
     pub transform: Transform,
     pub primitive: Primitive,
     pub material: MaterialDefinition,
+    pub label: EntityLabel,
+}
+
+impl EntityDefinition {
+    pub fn named(&mut self, name: &str) -> &mut Self {
+        self.label.name = name.to_owned();
+        self
+    }
+    pub fn notes(&mut self, notes: &str) -> &mut Self {
+        self.label.notes = notes.to_owned();
+        self
+    }
+}
+
+/// Name and notes given to an entity to find it again in a busy scene, set in the scene
+/// definition or edited in the inspector. An empty name falls back to `Scene::entity_name`'s
+/// generic one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntityLabel {
+    pub name: String,
+    pub notes: String,
+}
+
+/// Rectangular area light, lying in its transform's XZ plane and shining down its -Y axis, so an
+/// unrotated light hangs from a ceiling. The direct lighting and Whitted integrators sample the
+/// whole rectangle by the solid angle it covers, which stays smooth close up where picking points
+/// on an emissive quad is noisy.
+pub struct RectLight {
+    pub width: f32,
+    pub height: f32,
+    pub color: [f32; 4],
+    pub intensity: f32,
+    pub unit: LightUnit,
+    /// Whether camera rays see the light, it lights the scene either way
+    pub visible: bool,
+    /// Emit from the back face too
+    pub two_sided: bool,
+}
+
+impl RectLight {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self {
+            width,
+            height,
+            color: [1.0; 4],
+            intensity: 1.0,
+            unit: LightUnit::Unitless,
+            visible: true,
+            two_sided: false,
+        }
+    }
+    pub fn color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+    /// Interprets the intensity in a physical unit.
+    pub fn unit(mut self, unit: LightUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+    pub fn hidden_from_camera(mut self) -> Self {
+        self.visible = false;
+        self
+    }
+    pub fn two_sided(mut self) -> Self {
+        self.two_sided = true;
+        self
+    }
+    /// Black emitter, so the light only adds what it gives off.
+    pub fn material(&self) -> MaterialDefinition {
+        let flags: Vec<LightFlag> = [
+            (!self.visible).then_some(LightFlag::HiddenFromCamera),
+            (!self.two_sided).then_some(LightFlag::SingleSided),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        MaterialDefinition::new()
+            .color([0.0, 0.0, 0.0, 1.0])
+            .specular([1.0; 4], 0.0)
+            .emissive(self.color, self.intensity)
+            .light_unit(self.unit)
+            .light_flags(&flags)
+    }
 }
@@ -0,0 +1,44 @@
+/// Exponential height fog, thinning out with altitude. Applied along every ray segment in closed
+/// form rather than simulated like `Volume`, so it costs next to nothing and never adds noise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeightFog {
+    pub enabled: bool,
+    /// Extinction per world unit at `base_height`
+    pub density: f32,
+    /// How quickly the density falls off per unit of height above `base_height`
+    pub height_falloff: f32,
+    pub base_height: f32,
+    /// Take the fog's colour from the sky's horizon, following the time of day
+    pub sky_color: bool,
+    /// Colour of the fog when it isn't taken from the sky
+    pub color: [f32; 3],
+}
+
+impl Default for HeightFog {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            density: 0.02,
+            height_falloff: 0.2,
+            base_height: 0.0,
+            sky_color: true,
+            color: [0.7, 0.75, 0.8],
+        }
+    }
+}
+
+impl HeightFog {
+    /// Density, falloff, base height and colour as the shader reads them, zero density when off.
+    pub fn to_uniform(self) -> [[f32; 4]; 2] {
+        let density = if self.enabled { self.density } else { 0.0 };
+        [
+            [density, self.height_falloff.max(0.0), self.base_height, 0.0],
+            [
+                self.color[0],
+                self.color[1],
+                self.color[2],
+                self.sky_color as u32 as f32,
+            ],
+        ]
+    }
+}
@@ -1,4 +1,13 @@
 pub mod camera;
 pub mod components;
 pub mod entity;
+pub mod fog;
+pub mod placement;
+pub mod prefab;
+pub mod randomize;
+pub mod render_settings;
+pub mod scatter;
 pub mod scene;
+pub mod sky;
+pub mod timeline;
+pub mod walkthrough;
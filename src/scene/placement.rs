@@ -0,0 +1,142 @@
+use glam::Vec3;
+use rayon::prelude::*;
+
+use crate::bvh::{Aabb, Bvh, Quality, Ray};
+use crate::scene::scene::Scene;
+
+/// Points spread over a sphere's surface, where it is tested for touching meshes
+const SPHERE_SAMPLES: usize = 256;
+/// Left between a dropped entity and what it lands on, so the surfaces don't z-fight
+const CONTACT_GAP: f32 = 1e-4;
+
+/// World space shape of an entity for `drop_distance`. Meshes collide with their own triangles,
+/// tested at the vertices of either side, so only edges crossing each other can slip through.
+enum Collider {
+    Sphere { centre: Vec3, radius: f32 },
+    Mesh { bvh: Bvh, vertices: Vec<Vec3> },
+}
+
+impl Collider {
+    fn new(scene: &Scene, entity: usize) -> Option<Self> {
+        if let Some(sphere) = scene.spheres.get(entity) {
+            return Some(Collider::Sphere {
+                centre: Vec3::from_array(sphere.pos),
+                radius: sphere.radius,
+            });
+        }
+        let mesh = scene.meshes.get(entity - scene.spheres.len())?;
+        let model_to_world = mesh.transform.to_matrix();
+        let vertices: Vec<Vec3> = mesh
+            .data
+            .vertices
+            .iter()
+            .map(|v| model_to_world.transform_point3(v.pos))
+            .collect();
+        let bvh = Bvh::build_indexed(&vertices, &mesh.data.indices, Quality::Low);
+        Some(Collider::Mesh { bvh, vertices })
+    }
+    fn bounds(&self) -> Aabb {
+        match self {
+            Collider::Sphere { centre, radius } => {
+                let mut bounds = Aabb::default();
+                bounds.grow_point(centre - Vec3::splat(*radius));
+                bounds.grow_point(centre + Vec3::splat(*radius));
+                bounds
+            }
+            Collider::Mesh { bvh, .. } => bvh.bounds(),
+        }
+    }
+    /// Where the shape can first touch another, its vertices or a spread over the sphere.
+    fn points(&self) -> Vec<Vec3> {
+        match self {
+            Collider::Sphere { centre, radius } => (0..SPHERE_SAMPLES)
+                .map(|i| {
+                    // Fibonacci lattice, near even spacing without clustering at the poles
+                    let y = 1.0 - (i as f32 + 0.5) / SPHERE_SAMPLES as f32 * 2.0;
+                    let phi = i as f32 * std::f32::consts::PI * (3.0 - 5f32.sqrt());
+                    let r = (1.0 - y * y).sqrt();
+                    centre + Vec3::new(r * phi.cos(), y, r * phi.sin()) * *radius
+                })
+                // The poles exactly, so a sphere lands flush on flat ground
+                .chain([centre - Vec3::Y * *radius, centre + Vec3::Y * *radius])
+                .collect(),
+            Collider::Mesh { vertices, .. } => vertices.clone(),
+        }
+    }
+    /// Distance along `ray` to the shape's surface, rays starting inside a sphere miss it.
+    fn intersect(&self, ray: &Ray) -> Option<f32> {
+        match self {
+            Collider::Sphere { centre, radius } => {
+                let offset = ray.origin - centre;
+                let b = offset.dot(ray.dir);
+                let c = offset.length_squared() - radius * radius;
+                let discriminant = b * b - c;
+                (c >= 0.0 && discriminant >= 0.0)
+                    .then(|| -b - discriminant.sqrt())
+                    .filter(|t| *t >= 0.0)
+            }
+            Collider::Mesh { bvh, .. } => bvh.intersect(ray, f32::INFINITY).map(|hit| hit.t),
+        }
+    }
+    /// How far this shape can move down before touching `other`, which stays where it is.
+    fn fall_onto(&self, points: &[Vec3], other: &Collider) -> Option<f32> {
+        if let (
+            Collider::Sphere { centre, radius },
+            Collider::Sphere {
+                centre: other_centre,
+                radius: other_radius,
+            },
+        ) = (self, other)
+        {
+            // The centre falls onto a sphere of both radii
+            let sum = Collider::Sphere {
+                centre: *other_centre,
+                radius: radius + other_radius,
+            };
+            return sum.intersect(&Ray::new(*centre, Vec3::NEG_Y));
+        }
+        let down = points
+            .par_iter()
+            .filter_map(|p| other.intersect(&Ray::new(*p, Vec3::NEG_Y)))
+            .min_by(f32::total_cmp);
+        // Points of the surface below that would poke up into this shape
+        let footprint = self.bounds();
+        let up = other
+            .points()
+            .into_par_iter()
+            .filter(|p| {
+                p.x >= footprint.min.x
+                    && p.x <= footprint.max.x
+                    && p.z >= footprint.min.z
+                    && p.z <= footprint.max.z
+            })
+            .filter_map(|p| self.intersect(&Ray::new(p, Vec3::Y)))
+            .min_by(f32::total_cmp);
+        match (down, up) {
+            (Some(down), Some(up)) => Some(down.min(up)),
+            (down, up) => down.or(up),
+        }
+    }
+}
+
+/// How far `entity` can fall straight down before resting on another sphere or mesh, or `None`
+/// when there is nothing below it. Entities are indexed like `SceneManager::selected_entity`.
+pub fn drop_distance(scene: &Scene, entity: usize) -> Option<f32> {
+    let collider = Collider::new(scene, entity)?;
+    let bounds = collider.bounds();
+    let points = collider.points();
+    (0..scene.spheres.len() + scene.meshes.len())
+        .filter(|&other| other != entity)
+        .filter_map(|other| {
+            let other_bounds = scene.entity_bounds(other as i32)?;
+            // Only what lies under the footprint and doesn't start above the entity can be hit
+            let below = other_bounds.min.y <= bounds.max.y
+                && other_bounds.min.x <= bounds.max.x
+                && other_bounds.max.x >= bounds.min.x
+                && other_bounds.min.z <= bounds.max.z
+                && other_bounds.max.z >= bounds.min.z;
+            below.then(|| collider.fall_onto(&points, &Collider::new(scene, other)?))?
+        })
+        .min_by(f32::total_cmp)
+        .map(|distance| (distance - CONTACT_GAP).max(0.0))
+}
@@ -0,0 +1,304 @@
+use glam::Vec3;
+
+use crate::scene::components::{
+    geometry::mesh::MeshDefinition, material::MaterialDefinition, transform::Transform,
+};
+use crate::scene::entity::{EntityDefinition, EntityLabel, Primitive, RectLight};
+
+/// Group of entities defined once and placed any number of times, like a streetlight or a chair
+/// with its cushions. Instances refer to the prefab by index rather than copying it, and meshes
+/// built from data share their geometry and BVH between every placement.
+pub struct Prefab {
+    pub name: String,
+    entities: Vec<EntityDefinition>,
+    /// Prefabs nested inside this one, which may only refer to prefabs added before it
+    instances: Vec<PrefabInstance>,
+}
+
+/// One placement of a prefab, its entities moved by `transform` from where the prefab put them.
+pub struct PrefabInstance {
+    /// Index returned by `SceneDefinition::add_prefab`
+    pub prefab: usize,
+    pub transform: Transform,
+    /// Prefixes the names of the placed entities, numbered after the prefab when left empty
+    pub label: EntityLabel,
+}
+
+impl PrefabInstance {
+    pub fn named(&mut self, name: &str) -> &mut Self {
+        self.label.name = name.to_owned();
+        self
+    }
+}
+
+impl Prefab {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            entities: vec![],
+            instances: vec![],
+        }
+    }
+    /// The added entity is returned to be named, see `EntityDefinition::named`.
+    pub fn add_sphere(
+        &mut self,
+        centre: Vec3,
+        radius: f32,
+        material: MaterialDefinition,
+    ) -> &mut EntityDefinition {
+        self.add_entity(
+            Transform::default(),
+            Primitive::Sphere { centre, radius },
+            material,
+        )
+    }
+    pub fn add_mesh(
+        &mut self,
+        transform: Transform,
+        mesh_definition: MeshDefinition,
+        material: MaterialDefinition,
+    ) -> &mut EntityDefinition {
+        self.add_entity(transform, Primitive::Mesh(mesh_definition), material)
+    }
+    pub fn add_rect_light(
+        &mut self,
+        transform: Transform,
+        light: RectLight,
+    ) -> &mut EntityDefinition {
+        self.add_entity(
+            transform,
+            Primitive::RectLight {
+                width: light.width,
+                height: light.height,
+            },
+            light.material(),
+        )
+    }
+    /// Nests an earlier prefab, such as the same lamp head on several posts.
+    pub fn add_instance(&mut self, prefab: usize, transform: Transform) -> &mut PrefabInstance {
+        self.instances.push(PrefabInstance {
+            prefab,
+            transform,
+            label: EntityLabel::default(),
+        });
+        self.instances.last_mut().unwrap()
+    }
+    fn add_entity(
+        &mut self,
+        transform: Transform,
+        primitive: Primitive,
+        material: MaterialDefinition,
+    ) -> &mut EntityDefinition {
+        self.entities.push(EntityDefinition {
+            transform,
+            primitive,
+            material,
+            label: EntityLabel::default(),
+        });
+        self.entities.last_mut().unwrap()
+    }
+}
+
+/// An entity where an instance puts it, with the name it's listed under.
+pub struct PlacedEntity<'a> {
+    pub entity: &'a EntityDefinition,
+    pub transform: Transform,
+    pub label: EntityLabel,
+}
+
+/// Every entity `instances` place, nested prefabs included, in world space under `parent`.
+/// Instances of prefabs that don't exist are skipped with a warning, and a prefab only sees those
+/// defined before it, which rules out one containing itself.
+pub fn place<'a>(
+    prefabs: &'a [Prefab],
+    instances: &[PrefabInstance],
+    parent: Transform,
+    parent_name: &str,
+    placed: &mut Vec<PlacedEntity<'a>>,
+) {
+    for (n, instance) in instances.iter().enumerate() {
+        let Some(prefab) = prefabs.get(instance.prefab) else {
+            log::warn!("Skipping instance of unknown prefab {}", instance.prefab);
+            continue;
+        };
+        let mut name = match instance.label.name.is_empty() {
+            true => format!("{} {}", prefab.name, n + 1),
+            false => instance.label.name.clone(),
+        };
+        if !parent_name.is_empty() {
+            name = format!("{} / {}", parent_name, name);
+        }
+        let transform = parent.compose(&instance.transform);
+        for entity in &prefab.entities {
+            let mut label = entity.label.clone();
+            label.name = match entity.label.name.is_empty() {
+                true => name.clone(),
+                false => format!("{} / {}", name, entity.label.name),
+            };
+            if label.notes.is_empty() {
+                label.notes = instance.label.notes.clone();
+            }
+            placed.push(PlacedEntity {
+                entity,
+                transform: transform.compose(&entity.transform),
+                label,
+            });
+        }
+        // Only the prefabs before this one are visible to it, so nesting always ends
+        place(
+            &prefabs[..instance.prefab],
+            &prefab.instances,
+            transform,
+            &name,
+            placed,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Quat;
+
+    use super::*;
+
+    fn at(pos: Vec3) -> Transform {
+        Transform {
+            pos,
+            ..Default::default()
+        }
+    }
+
+    fn cube() -> MeshDefinition {
+        MeshDefinition::Box { size: Vec3::ONE }
+    }
+
+    fn names<'a>(placed: &'a [PlacedEntity]) -> Vec<&'a str> {
+        placed
+            .iter()
+            .map(|placed| placed.label.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn compose_applies_the_parent_after_the_child() {
+        let parent = Transform {
+            pos: Vec3::new(1.0, 0.0, 0.0),
+            rot: Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+            scale: Vec3::splat(2.0),
+        };
+        let child = Transform {
+            pos: Vec3::new(1.0, 0.0, 0.0),
+            rot: Quat::from_rotation_x(0.5),
+            scale: Vec3::splat(3.0),
+        };
+        let composed = parent.compose(&child);
+        assert!(composed.pos.abs_diff_eq(Vec3::new(1.0, 0.0, -2.0), 1e-5));
+        assert!(composed.rot.abs_diff_eq(parent.rot * child.rot, 1e-6));
+        assert_eq!(composed.scale, Vec3::splat(6.0));
+        let point = Vec3::new(0.2, -0.4, 0.7);
+        assert!(composed.to_matrix().transform_point3(point).abs_diff_eq(
+            (parent.to_matrix() * child.to_matrix()).transform_point3(point),
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn instances_are_numbered_unless_named() {
+        let mut chair = Prefab::new("Chair");
+        chair.add_mesh(Transform::default(), cube(), MaterialDefinition::new());
+        chair
+            .add_sphere(Vec3::ZERO, 0.2, MaterialDefinition::new())
+            .named("Cushion")
+            .notes("Velvet");
+        let prefabs = [chair];
+        let mut instances = vec![
+            PrefabInstance {
+                prefab: 0,
+                transform: Transform::default(),
+                label: EntityLabel::default(),
+            },
+            PrefabInstance {
+                prefab: 0,
+                transform: Transform::default(),
+                label: EntityLabel {
+                    name: String::new(),
+                    notes: "Spare".to_owned(),
+                },
+            },
+        ];
+        instances[1].named("Armchair");
+        let mut placed = vec![];
+        place(&prefabs, &instances, Transform::default(), "", &mut placed);
+        assert_eq!(
+            names(&placed),
+            [
+                "Chair 1",
+                "Chair 1 / Cushion",
+                "Armchair",
+                "Armchair / Cushion"
+            ]
+        );
+        assert_eq!(placed[1].label.notes, "Velvet");
+        assert_eq!(placed[2].label.notes, "Spare");
+    }
+
+    #[test]
+    fn nested_prefabs_compose_transforms_and_names() {
+        let mut lamp = Prefab::new("Lamp");
+        lamp.add_mesh(
+            at(Vec3::new(0.0, -0.1, 0.0)),
+            cube(),
+            MaterialDefinition::new(),
+        )
+        .named("Bulb");
+        let mut post = Prefab::new("Post");
+        post.add_mesh(at(Vec3::Y), cube(), MaterialDefinition::new());
+        post.add_instance(0, at(Vec3::new(0.5, 3.0, 0.0)));
+        let prefabs = [lamp, post];
+        let instances = [PrefabInstance {
+            prefab: 1,
+            transform: Transform {
+                pos: Vec3::new(10.0, 0.0, 0.0),
+                rot: Quat::from_rotation_y(std::f32::consts::PI),
+                scale: Vec3::ONE,
+            },
+            label: EntityLabel::default(),
+        }];
+        let mut placed = vec![];
+        place(&prefabs, &instances, at(Vec3::Z), "Street", &mut placed);
+        assert_eq!(
+            names(&placed),
+            ["Street / Post 1", "Street / Post 1 / Lamp 1 / Bulb"]
+        );
+        assert!(
+            placed[0]
+                .transform
+                .pos
+                .abs_diff_eq(Vec3::new(10.0, 1.0, 1.0), 1e-5)
+        );
+        // The post is turned around, so its arm reaches the other way
+        assert!(
+            placed[1]
+                .transform
+                .pos
+                .abs_diff_eq(Vec3::new(9.5, 2.9, 1.0), 1e-5)
+        );
+    }
+
+    #[test]
+    fn unknown_and_self_referencing_prefabs_are_skipped() {
+        let mut looped = Prefab::new("Loop");
+        looped.add_sphere(Vec3::ZERO, 1.0, MaterialDefinition::new());
+        looped.add_instance(0, Transform::default());
+        looped.add_instance(5, Transform::default());
+        let prefabs = [looped];
+        let instances = [0, 3].map(|prefab| PrefabInstance {
+            prefab,
+            transform: Transform::default(),
+            label: EntityLabel::default(),
+        });
+        let mut placed = vec![];
+        place(&prefabs, &instances, Transform::default(), "", &mut placed);
+        assert_eq!(names(&placed), ["Loop 1"]);
+    }
+}
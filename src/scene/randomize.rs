@@ -0,0 +1,169 @@
+use glam::Vec3;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::scene::scene::Scene;
+
+/// Settings of the randomize materials tool, which gives each selected entity its own colour and
+/// finish drawn from these ranges. Each range is the lowest and highest value, either way round.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RandomizeSettings {
+    pub seed: u64,
+    pub color: bool,
+    /// As a fraction of the way around the colour wheel, wrapping past red when the first is
+    /// larger
+    pub hue: [f32; 2],
+    pub saturation: [f32; 2],
+    /// Brightness of the colour, HSV's value
+    pub value: [f32; 2],
+    pub randomize_smoothness: bool,
+    pub smoothness: [f32; 2],
+    pub randomize_specular: bool,
+    /// Specular probability
+    pub specular: [f32; 2],
+}
+
+impl Default for RandomizeSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            color: true,
+            hue: [0.0, 1.0],
+            saturation: [0.4, 0.9],
+            value: [0.4, 0.9],
+            randomize_smoothness: true,
+            smoothness: [0.0, 1.0],
+            randomize_specular: false,
+            specular: [0.0, 0.5],
+        }
+    }
+}
+
+/// Draws a material for each of `entities` from `settings`, returning how many changed. Each
+/// entity's draw depends only on the seed and its index, so growing the selection and randomizing
+/// again leaves the entities already done as they were.
+pub fn randomize_materials(
+    scene: &mut Scene,
+    entities: &[i32],
+    settings: &RandomizeSettings,
+) -> usize {
+    let mut changed = 0;
+    for &entity in entities {
+        let Some(material) = scene.entity_material_mut(entity) else {
+            continue;
+        };
+        let before = *material;
+        let mut rng = StdRng::seed_from_u64(
+            settings.seed ^ (entity as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+        );
+        if settings.color {
+            let [start, end] = settings.hue;
+            let end = if end < start { end + 1.0 } else { end };
+            let hue = rng.random_range(start..=end).fract();
+            let saturation = draw(&mut rng, settings.saturation);
+            let value = draw(&mut rng, settings.value);
+            material.color[..3].copy_from_slice(&hsv_to_rgb(hue, saturation, value).to_array());
+        }
+        if settings.randomize_smoothness {
+            material.smoothness = draw(&mut rng, settings.smoothness);
+        }
+        if settings.randomize_specular {
+            material.specular = draw(&mut rng, settings.specular);
+        }
+        if bytemuck::bytes_of(material) != bytemuck::bytes_of(&before) {
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// A uniform value between the two ends of `range`.
+fn draw(rng: &mut StdRng, [a, b]: [f32; 2]) -> f32 {
+    rng.random_range(a.min(b)..=a.max(b))
+}
+
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> Vec3 {
+    let channel = |n: f32| {
+        let k = (n + hue * 6.0) % 6.0;
+        value * (1.0 - saturation * k.min(4.0 - k).clamp(0.0, 1.0))
+    };
+    Vec3::new(channel(5.0), channel(3.0), channel(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::components::{geometry::sphere::Sphere, material::MaterialUniform};
+
+    fn scene(spheres: usize) -> Scene {
+        let mut scene = Scene::new();
+        scene.spheres = (0..spheres)
+            .map(|i| Sphere::new(Vec3::X * i as f32, 1.0, MaterialUniform::default()))
+            .collect();
+        scene
+    }
+
+    #[test]
+    fn hsv_primaries() {
+        let close = |a: Vec3, b: Vec3| a.abs_diff_eq(b, 1e-5);
+        assert!(close(hsv_to_rgb(0.0, 1.0, 1.0), Vec3::X));
+        assert!(close(hsv_to_rgb(1.0 / 3.0, 1.0, 1.0), Vec3::Y));
+        assert!(close(hsv_to_rgb(2.0 / 3.0, 1.0, 1.0), Vec3::Z));
+        assert!(close(
+            hsv_to_rgb(1.0 / 6.0, 1.0, 0.5),
+            Vec3::new(0.5, 0.5, 0.0)
+        ));
+        assert!(close(hsv_to_rgb(0.4, 0.0, 0.25), Vec3::splat(0.25)));
+    }
+
+    #[test]
+    fn draws_within_ranges_and_skips_missing_entities() {
+        let settings = RandomizeSettings {
+            saturation: [1.0, 1.0],
+            value: [1.0, 1.0],
+            randomize_specular: true,
+            smoothness: [0.75, 0.25],
+            specular: [0.1, 0.2],
+            ..Default::default()
+        };
+        let mut scene = scene(3);
+        assert_eq!(
+            randomize_materials(&mut scene, &[0, 2, 7, -1], &settings),
+            2
+        );
+        for sphere in [&scene.spheres[0], &scene.spheres[2]] {
+            let material = sphere.material;
+            assert!((0.25..=0.75).contains(&material.smoothness));
+            assert!((0.1..=0.2).contains(&material.specular));
+            // Fully saturated and bright, so one channel is full and another empty
+            let color = Vec3::from_slice(&material.color[..3]);
+            assert!((color.max_element() - 1.0).abs() < 1e-5);
+            assert!(color.min_element().abs() < 1e-5);
+        }
+        assert_eq!(
+            bytemuck::bytes_of(&scene.spheres[1].material),
+            bytemuck::bytes_of(&MaterialUniform::default())
+        );
+    }
+
+    #[test]
+    fn each_entity_draws_the_same_whatever_else_is_selected() {
+        let settings = RandomizeSettings::default();
+        let mut alone = scene(3);
+        let mut together = scene(3);
+        randomize_materials(&mut alone, &[1], &settings);
+        randomize_materials(&mut together, &[0, 1, 2], &settings);
+        assert_eq!(
+            bytemuck::bytes_of(&alone.spheres[1].material),
+            bytemuck::bytes_of(&together.spheres[1].material)
+        );
+        let reseeded = RandomizeSettings {
+            seed: 1,
+            ..settings
+        };
+        randomize_materials(&mut together, &[1], &reseeded);
+        assert_ne!(
+            bytemuck::bytes_of(&alone.spheres[1].material),
+            bytemuck::bytes_of(&together.spheres[1].material)
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::core::app::Params;
+use crate::rendering::post::PostStack;
+
+/// Render settings a scene prefers, loaded into `Params` when the scene is selected unless
+/// `SceneManager::keep_settings` is set. What suits the random balls would take forever to clean
+/// up in Sponza, so each scene sets what matters to it and leaves the rest as `None`.
+#[derive(Debug, Clone, Default)]
+pub struct RenderSettings {
+    pub bounces: Option<i32>,
+    pub rays_per_pixel: Option<i32>,
+    /// See `Params::clamp_direct`
+    pub clamp_direct: Option<f32>,
+    /// See `Params::clamp_indirect`
+    pub clamp_indirect: Option<f32>,
+    pub skybox: Option<bool>,
+    /// Exposure compensation in stops
+    pub exposure: Option<f32>,
+    /// Replaces the global post stack, see `Scene::post`
+    pub post: Option<PostStack>,
+}
+
+impl RenderSettings {
+    /// Writes the settings the scene sets into `params`, returning whether any of them changed.
+    pub fn apply(&self, params: &mut Params) -> bool {
+        let old = *params;
+        if let Some(bounces) = self.bounces {
+            params.number_of_bounces = bounces;
+        }
+        if let Some(rays_per_pixel) = self.rays_per_pixel {
+            params.rays_per_pixel = rays_per_pixel;
+        }
+        if let Some(clamp) = self.clamp_direct {
+            params.clamp_direct = clamp;
+        }
+        if let Some(clamp) = self.clamp_indirect {
+            params.clamp_indirect = clamp;
+        }
+        if let Some(skybox) = self.skybox {
+            params.skybox = skybox as i32;
+        }
+        if let Some(exposure) = self.exposure {
+            params.exposure = exposure;
+        }
+        *params != old
+    }
+    /// What the scene sets, one per line, for a tooltip.
+    pub fn summary(&self) -> String {
+        let mut lines = vec![];
+        if let Some(bounces) = self.bounces {
+            lines.push(format!("{} bounces", bounces));
+        }
+        if let Some(rays_per_pixel) = self.rays_per_pixel {
+            lines.push(format!("{} rays per pixel", rays_per_pixel));
+        }
+        if let Some(clamp) = self.clamp_direct {
+            lines.push(format!("Direct light clamped to {}", clamp));
+        }
+        if let Some(clamp) = self.clamp_indirect {
+            lines.push(format!("Indirect light clamped to {}", clamp));
+        }
+        if let Some(skybox) = self.skybox {
+            lines.push(format!("Skybox {}", if skybox { "on" } else { "off" }));
+        }
+        if let Some(exposure) = self.exposure {
+            lines.push(format!("Exposure {:+.1} stops", exposure));
+        }
+        if self.post.is_some() {
+            lines.push("Its own post stack".to_owned());
+        }
+        lines.join("\n")
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bounces.is_none()
+            && self.rays_per_pixel.is_none()
+            && self.clamp_direct.is_none()
+            && self.clamp_indirect.is_none()
+            && self.skybox.is_none()
+            && self.exposure.is_none()
+            && self.post.is_none()
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use glam::{IVec3, Quat, Vec3};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::bvh::Triangle;
+use crate::rendering::ray_tracer::{MAX_MESHES, MAX_SPHERS};
+use crate::scene::{entity::EntityLabel, scene::Scene};
+
+/// Candidate points drawn per instance wanted when spacing them out
+const POISSON_CANDIDATES: u32 = 30;
+/// Spacing of Poisson-disc instances relative to `sqrt(area / count)`. Points thrown at random
+/// jam well short of a perfect packing, this leaves room to still place them all.
+const POISSON_SPACING: f32 = 0.7;
+
+/// How instances are spread over the target surface.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ScatterMode {
+    /// Independent uniform points, clumps and gaps included
+    #[default]
+    Random,
+    /// No two instances closer than an even spacing for the area, for natural looking cover
+    PoissonDisc,
+}
+
+impl ScatterMode {
+    pub const ALL: [ScatterMode; 2] = [ScatterMode::Random, ScatterMode::PoissonDisc];
+    pub fn name(self) -> &'static str {
+        match self {
+            ScatterMode::Random => "Random",
+            ScatterMode::PoissonDisc => "Poisson Disc",
+        }
+    }
+}
+
+/// Settings of the scatter tool, which copies the selected entity over another's surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScatterSettings {
+    /// Entity scattered over, indexed like `SceneManager::selected_entity`
+    pub target: i32,
+    pub count: u32,
+    pub mode: ScatterMode,
+    pub seed: u64,
+    /// Largest share each instance is randomly grown or shrunk by
+    pub scale_jitter: f32,
+    /// Largest random turn about the up axis, in degrees
+    pub rotation_jitter: f32,
+    /// Stand meshes along the surface normal rather than upright
+    pub align_to_normal: bool,
+}
+
+impl Default for ScatterSettings {
+    fn default() -> Self {
+        Self {
+            target: -1,
+            count: 50,
+            mode: ScatterMode::PoissonDisc,
+            seed: 0,
+            scale_jitter: 0.2,
+            rotation_jitter: 180.0,
+            align_to_normal: false,
+        }
+    }
+}
+
+/// World space surface of the target, sampled uniformly by area.
+enum Surface {
+    Sphere {
+        centre: Vec3,
+        radius: f32,
+    },
+    Mesh {
+        triangles: Vec<Triangle>,
+        /// Running total of the triangles' areas
+        cumulative: Vec<f32>,
+    },
+}
+
+impl Surface {
+    fn new(scene: &Scene, entity: usize) -> Option<Self> {
+        if let Some(sphere) = scene.spheres.get(entity) {
+            return Some(Surface::Sphere {
+                centre: Vec3::from_array(sphere.pos),
+                radius: sphere.radius,
+            });
+        }
+        let mesh = scene.meshes.get(entity - scene.spheres.len())?;
+        let model_to_world = mesh.transform.to_matrix();
+        let triangles: Vec<Triangle> = mesh
+            .data
+            .indices
+            .chunks_exact(3)
+            .map(|corners| {
+                [0, 1, 2].map(|corner| {
+                    model_to_world
+                        .transform_point3(mesh.data.vertices[corners[corner] as usize].pos)
+                })
+            })
+            .collect();
+        let cumulative = triangles
+            .iter()
+            .scan(0.0, |total, [a, b, c]| {
+                *total += (b - a).cross(c - a).length() * 0.5;
+                Some(*total)
+            })
+            .collect();
+        Some(Surface::Mesh {
+            triangles,
+            cumulative,
+        })
+    }
+    fn area(&self) -> f32 {
+        match self {
+            Surface::Sphere { radius, .. } => 4.0 * std::f32::consts::PI * radius * radius,
+            Surface::Mesh { cumulative, .. } => cumulative.last().copied().unwrap_or(0.0),
+        }
+    }
+    /// A uniformly random point and the surface normal there.
+    fn sample(&self, rng: &mut StdRng) -> (Vec3, Vec3) {
+        match self {
+            Surface::Sphere { centre, radius } => {
+                let y = rng.random_range(-1.0..=1.0f32);
+                let phi = rng.random_range(0.0..std::f32::consts::TAU);
+                let r = (1.0 - y * y).max(0.0).sqrt();
+                let normal = Vec3::new(r * phi.cos(), y, r * phi.sin());
+                (centre + normal * *radius, normal)
+            }
+            Surface::Mesh {
+                triangles,
+                cumulative,
+            } => {
+                let picked = rng.random_range(0.0..self.area());
+                let i = cumulative
+                    .partition_point(|&total| total <= picked)
+                    .min(triangles.len() - 1);
+                let [a, b, c] = triangles[i];
+                // Folding the square onto the triangle keeps the points uniform
+                let (mut u, mut v) = (rng.random::<f32>(), rng.random::<f32>());
+                if u + v > 1.0 {
+                    (u, v) = (1.0 - u, 1.0 - v);
+                }
+                let normal = (b - a).cross(c - a).normalize_or(Vec3::Y);
+                (a + (b - a) * u + (c - a) * v, normal)
+            }
+        }
+    }
+}
+
+/// Points at least `spacing` apart, bucketed by cells of that size so only neighbours are compared.
+#[derive(Default)]
+struct SpacedPoints {
+    spacing: f32,
+    cells: HashMap<IVec3, Vec<Vec3>>,
+}
+
+impl SpacedPoints {
+    fn cell(&self, p: Vec3) -> IVec3 {
+        (p / self.spacing).floor().as_ivec3()
+    }
+    /// Adds `p` unless it is too close to a point already added.
+    fn insert(&mut self, p: Vec3) -> bool {
+        let cell = self.cell(p);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let near = self.cells.get(&(cell + IVec3::new(x, y, z)));
+                    if near
+                        .is_some_and(|points| points.iter().any(|q| q.distance(p) < self.spacing))
+                    {
+                        return false;
+                    }
+                }
+            }
+        }
+        self.cells.entry(cell).or_default().push(p);
+        true
+    }
+}
+
+/// Copies `source` over the surface of `settings.target`, each copy resting on the surface with
+/// its scale and turn jittered. Mesh copies share the source's geometry, and so its BVH, so a
+/// field of pebbles takes no more triangle memory than one. Copies are cut short at the GPU buffer
+/// limits, and Poisson-disc spacing can fit fewer than asked. Returns how many were added.
+pub fn scatter(
+    scene: &mut Scene,
+    source: i32,
+    settings: &ScatterSettings,
+) -> Result<usize, String> {
+    let source = usize::try_from(source).map_err(|_| "nothing selected to scatter")?;
+    let target = usize::try_from(settings.target).map_err(|_| "no surface to scatter over")?;
+    if source == target {
+        return Err("can't scatter an entity over itself".into());
+    }
+    let surface = Surface::new(scene, target).ok_or("the target no longer exists")?;
+    let area = surface.area();
+    if area <= 0.0 || !area.is_finite() {
+        return Err("the target has no surface area".into());
+    }
+    let is_sphere = source < scene.spheres.len();
+    let room = match is_sphere {
+        true => MAX_SPHERS.saturating_sub(scene.spheres.len() as u64),
+        false => MAX_MESHES.saturating_sub(scene.meshes.len() as u64),
+    };
+    let count = settings.count.min(room as u32);
+    if count < settings.count {
+        log::warn!(
+            "Only {} of {} instances fit in the scene buffers",
+            count,
+            settings.count
+        );
+    }
+
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let points: Vec<(Vec3, Vec3)> = match settings.mode {
+        ScatterMode::Random => (0..count).map(|_| surface.sample(&mut rng)).collect(),
+        ScatterMode::PoissonDisc => {
+            let mut spaced = SpacedPoints {
+                spacing: (area / count.max(1) as f32).sqrt() * POISSON_SPACING,
+                ..Default::default()
+            };
+            (0..count * POISSON_CANDIDATES)
+                .map(|_| surface.sample(&mut rng))
+                .filter(|(p, _)| spaced.insert(*p))
+                .take(count as usize)
+                .collect()
+        }
+    };
+
+    let name = scene.entity_name(source as i32).unwrap_or_default();
+    let numbered = |n: usize| EntityLabel {
+        name: format!("{} {}", name, n + 1),
+        notes: String::new(),
+    };
+    if is_sphere {
+        let original = scene.spheres[source];
+        for (n, (point, normal)) in points.iter().enumerate() {
+            let mut sphere = original;
+            sphere.radius *= jitter_scale(&mut rng, settings.scale_jitter);
+            // A sphere touches a surface along its normal, however it is turned
+            sphere.pos = (point + normal * sphere.radius).to_array();
+            scene.spheres.push(sphere);
+            scene.sphere_labels.push(numbered(n));
+        }
+        return Ok(points.len());
+    }
+
+    let original = scene.meshes[source - scene.spheres.len()].clone();
+    let (model_min, model_max) = original
+        .data
+        .vertices
+        .iter()
+        .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), v| {
+            (min.min(v.pos), max.max(v.pos))
+        });
+    // Copies are placed and turned about the middle of their bounds, wherever the model's origin is
+    let centre = (model_min + model_max) * 0.5;
+    let corners: Vec<Vec3> = (0..8)
+        .map(|i| {
+            Vec3::select(
+                glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+                model_max,
+                model_min,
+            ) - centre
+        })
+        .collect();
+    for (n, (point, normal)) in points.iter().enumerate() {
+        let up = match settings.align_to_normal {
+            true => *normal,
+            false => Vec3::Y,
+        };
+        let turn = rng.random_range(-1.0..=1.0f32) * settings.rotation_jitter.to_radians();
+        let rot = Quat::from_axis_angle(up, turn)
+            * Quat::from_rotation_arc(Vec3::Y, up)
+            * original.transform.rot;
+        let scale = original.transform.scale * jitter_scale(&mut rng, settings.scale_jitter);
+        // Lifted so the lowest corner of its bounds along `up` sits on the surface
+        let base = corners
+            .iter()
+            .map(|corner| (rot * (corner * scale)).dot(up))
+            .fold(f32::INFINITY, f32::min);
+        let mut mesh = original.clone();
+        mesh.transform.pos = point - rot * (centre * scale) - up * base;
+        mesh.transform.rot = rot;
+        mesh.transform.scale = scale;
+        mesh.user_label = numbered(n);
+        scene.meshes.push(mesh);
+    }
+    // Laid out again with the copies pointing at the source's BVH
+    scene.built_bvh = false;
+    Ok(points.len())
+}
+
+fn jitter_scale(rng: &mut StdRng, jitter: f32) -> f32 {
+    (1.0 + rng.random_range(-1.0..=1.0f32) * jitter.clamp(0.0, 0.99)).max(0.01)
+}
@@ -1,35 +1,59 @@
 use crate::scene::{
     components::{
         geometry::{
-            mesh::{MeshData, MeshDefinition, MeshInstance},
+            mesh::{MeshData, MeshDefinition, MeshInstance, UvView},
             sphere::Sphere,
+            validation::MeshReport,
             vertex::Vertex,
         },
-        material::{MaterialDefinition, MaterialFlag, MaterialUniform},
-        texture::TextureDefinition,
+        material::{
+            AddressMode, BlendMask, LightUnit, MaterialDefinition, MaterialFlag, MaterialUniform,
+        },
+        material_rules::{MaterialPreset, MaterialRule},
+        portal::{MAX_PORTALS, Portal},
+        texture::{AnimatedTexture, SequenceFrames, TextureDefinition},
         transform::Transform,
+        volume::{MAX_VOLUMES, Volume, VolumeDefinition, VolumeSource, VoxelGrid},
     },
-    entity::{EntityDefinition, Primitive},
+    entity::{EntityDefinition, EntityLabel, Primitive, RectLight},
 };
 
 use std::{
-    f32::consts::PI,
+    collections::{HashMap, HashSet, VecDeque, hash_map::Entry},
+    hash::{DefaultHasher, Hasher},
     sync::{
         Arc,
         mpsc::{Receiver, Sender, channel},
     },
 };
 
+use dashmap::DashMap;
 use glam::{Quat, Vec3};
 use image::RgbaImage;
 use rand::Rng;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use crate::core::{
-    asset::AssetManager,
-    bvh::{self, BVH, MeshDataList, Node, Quality},
+    asset::{AssetManager, ModelPart},
+    bvh::{self, Aabb, BVH, BuiltBlas, MeshDataList, Node, PackedTriangle, Quality},
+    engine::RENDER_SIZE,
+    jobs::{self, Job, Jobs},
+    mip_cache::{self, MipChain, ResidentTexture, TextureUse},
+    stream::{self, MeshChunk, PendingStream},
+};
+use crate::rendering::{
+    post::PostStack,
+    ray_tracer::{MAX_MESHES, MAX_TRIANGLES},
 };
 use crate::scene::camera::{Camera, CameraDescriptor, CameraUniform};
+use crate::scene::fog::HeightFog;
+use crate::scene::placement;
+use crate::scene::prefab::{self, PlacedEntity, Prefab, PrefabInstance};
+use crate::scene::render_settings::RenderSettings;
+use crate::scene::sky::Sky;
+use crate::scene::timeline::{
+    AnimProperty, AnimTarget, AnimValue, Timeline, value_vec3, vec3_value,
+};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum SceneName {
@@ -40,6 +64,12 @@ pub enum SceneName {
     Metal,
     Sponza,
     CornellBox,
+    Clouds,
+    Furnace,
+    ReflectanceRamp,
+    MisTest,
+    NestedGlass,
+    Streetlights,
     Empty,
 }
 
@@ -52,11 +82,18 @@ impl SceneName {
             SceneName::Room2 => SceneName::Metal,
             SceneName::Metal => SceneName::Sponza,
             SceneName::Sponza => SceneName::CornellBox,
-            SceneName::CornellBox => SceneName::Balls,
+            SceneName::CornellBox => SceneName::Clouds,
+            SceneName::Clouds => SceneName::Furnace,
+            SceneName::Furnace => SceneName::ReflectanceRamp,
+            SceneName::ReflectanceRamp => SceneName::MisTest,
+            SceneName::MisTest => SceneName::NestedGlass,
+            SceneName::NestedGlass => SceneName::Streetlights,
+            SceneName::Streetlights => SceneName::Balls,
             _ => self,
         }
     }
-    pub const ALL: [SceneName; 7] = [
+    /// New scenes go at the end, distributed renders send scenes by their index in here
+    pub const ALL: [SceneName; 13] = [
         SceneName::Balls,
         SceneName::RandomBalls,
         SceneName::Room,
@@ -64,24 +101,74 @@ impl SceneName {
         SceneName::Metal,
         SceneName::Sponza,
         SceneName::CornellBox,
+        SceneName::Clouds,
+        SceneName::Furnace,
+        SceneName::ReflectanceRamp,
+        SceneName::MisTest,
+        SceneName::NestedGlass,
+        SceneName::Streetlights,
     ];
 }
 
 pub struct SceneDefinition {
     camera: Camera,
+    /// Named viewpoints offered alongside `camera`, see `Scene::views`
+    views: Vec<(String, Camera)>,
+    sky: Sky,
+    fog: HeightFog,
     entities: Vec<EntityDefinition>,
+    volumes: Vec<VolumeDefinition>,
+    portals: Vec<Portal>,
+    materials: Vec<MaterialDefinition>,
+    prefabs: Vec<Prefab>,
+    instances: Vec<PrefabInstance>,
+    settings: RenderSettings,
 }
 
 impl SceneDefinition {
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
     pub fn set_camera(&mut self, camera_description: &CameraDescriptor) {
         self.camera = Camera::new(camera_description);
     }
-    pub fn add_sphere(&mut self, centre: Vec3, radius: f32, material: MaterialDefinition) {
-        self.entities.push(EntityDefinition {
-            transform: Transform::default(),
-            primitive: Primitive::Sphere { centre, radius },
+    /// Adds a fixed viewpoint that can be switched to from the inspector, or rendered by name from
+    /// the render queue.
+    pub fn add_view(&mut self, name: &str, camera_description: &CameraDescriptor) {
+        self.views
+            .push((name.to_owned(), Camera::new(camera_description)));
+    }
+    pub fn set_sky(&mut self, sky: Sky) {
+        self.sky = sky;
+    }
+    pub fn set_fog(&mut self, fog: HeightFog) {
+        self.fog = fog;
+    }
+    pub fn set_render_settings(&mut self, settings: RenderSettings) {
+        self.settings = settings;
+    }
+    /// Named viewpoint, ignoring case. Spaces may be written as underscores, as queue files do.
+    pub fn view(&self, name: &str) -> Option<&Camera> {
+        self.views
+            .iter()
+            .find(|(view, _)| {
+                view.replace(' ', "_")
+                    .eq_ignore_ascii_case(&name.replace(' ', "_"))
+            })
+            .map(|(_, camera)| camera)
+    }
+    /// The added entity is returned to be named, see `EntityDefinition::named`.
+    pub fn add_sphere(
+        &mut self,
+        centre: Vec3,
+        radius: f32,
+        material: MaterialDefinition,
+    ) -> &mut EntityDefinition {
+        self.add_entity(
+            Transform::default(),
+            Primitive::Sphere { centre, radius },
             material,
-        });
+        )
     }
 
     pub fn add_mesh(
@@ -89,42 +176,267 @@ impl SceneDefinition {
         transform: Transform,
         mesh_definition: MeshDefinition,
         material: MaterialDefinition,
-    ) {
+    ) -> &mut EntityDefinition {
+        self.add_entity(transform, Primitive::Mesh(mesh_definition), material)
+    }
+    pub fn add_rect_light(
+        &mut self,
+        transform: Transform,
+        light: RectLight,
+    ) -> &mut EntityDefinition {
+        self.add_entity(
+            transform,
+            Primitive::RectLight {
+                width: light.width,
+                height: light.height,
+            },
+            light.material(),
+        )
+    }
+    fn add_entity(
+        &mut self,
+        transform: Transform,
+        primitive: Primitive,
+        material: MaterialDefinition,
+    ) -> &mut EntityDefinition {
         self.entities.push(EntityDefinition {
             transform,
-            primitive: Primitive::Mesh(mesh_definition),
+            primitive,
             material,
+            label: EntityLabel::default(),
         });
+        self.entities.last_mut().unwrap()
+    }
+    pub fn add_volume(&mut self, volume: VolumeDefinition) {
+        self.volumes.push(volume);
+    }
+    pub fn add_portal(&mut self, portal: Portal) {
+        self.portals.push(portal);
+    }
+    /// Adds a material to the palette and returns its index, for use with `MaterialDefinition::blend`.
+    pub fn add_material(&mut self, material: MaterialDefinition) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+    /// Adds a prefab and returns its index, for placing it with `add_instance`.
+    pub fn add_prefab(&mut self, prefab: Prefab) -> usize {
+        self.prefabs.push(prefab);
+        self.prefabs.len() - 1
+    }
+    /// Places the entities of a prefab, moved by `transform`. The instance is returned to be named.
+    pub fn add_instance(&mut self, prefab: usize, transform: Transform) -> &mut PrefabInstance {
+        self.instances.push(PrefabInstance {
+            prefab,
+            transform,
+            label: EntityLabel::default(),
+        });
+        self.instances.last_mut().unwrap()
+    }
+    /// Black sphere of unit radiance around everything, the uniform environment of the validation
+    /// scenes. The procedural sky isn't uniform, so it can't stand in.
+    fn add_furnace_enclosure(&mut self) {
+        self.add_sphere(
+            Vec3::ZERO,
+            100.0,
+            MaterialDefinition::new()
+                .color([0.0, 0.0, 0.0, 1.0])
+                .specular([0.0; 4], 0.0)
+                .emissive([1.0; 4], 1.0),
+        )
+        .named("Furnace");
     }
 }
 impl Default for SceneDefinition {
     fn default() -> Self {
         Self {
             camera: Camera::new(&CameraDescriptor::default()),
+            views: vec![],
+            sky: Sky::default(),
+            fog: HeightFog::default(),
             entities: vec![],
+            volumes: vec![],
+            portals: vec![],
+            materials: vec![],
+            prefabs: vec![],
+            instances: vec![],
+            settings: RenderSettings::default(),
         }
     }
 }
 
+/// Validates a freshly loaded mesh, logging and returning its report if anything is wrong.
+fn diagnose_mesh(index: usize, mesh: &MeshInstance) -> Option<(String, MeshReport)> {
+    let report = MeshReport::check(&mesh.data);
+    if report.is_clean() {
+        return None;
+    }
+    let label = mesh.label.clone().unwrap_or(format!("Mesh {}", index));
+    log::warn!("{}: {:?}", label, report);
+    Some((label, report))
+}
+
+fn material_uniform(
+    material: &MaterialDefinition,
+    asset_manager: &AssetManager,
+) -> MaterialUniform {
+    let mut flag = material.flag as i32;
+    let diffuse_index = if let Some(diffuse) = &material.diffuse_texture {
+        // Handle loading texture (use asset_manager)
+        match diffuse {
+            TextureDefinition::FromFile { path } => {
+                flag = MaterialFlag::TEXTURE as i32;
+                asset_manager.load_texture(path)
+            }
+            TextureDefinition::Sequence { pattern, fps } => {
+                flag = MaterialFlag::TEXTURE as i32;
+                asset_manager.load_sequence(pattern, *fps)
+            }
+            _ => -1,
+        }
+    } else {
+        -1
+    };
+    let mut uniform = MaterialUniform {
+        color: material.color,
+        emission_color: material.emission_color,
+        specular_color: material.specular_color,
+        absorption: material.absorption,
+        absorption_stength: material.absorption_stength,
+        emission_strength: material.emission_strength,
+        smoothness: material.smoothness,
+        specular: material.specular,
+        ior: material.ior,
+        priority: material.priority,
+        flag,
+        diffuse_index,
+        light_flags: material.light_flags,
+        visibility: material.visibility,
+        address_mode: material.address_mode as u32,
+        ..Default::default()
+    };
+    match &material.orm_texture {
+        Some((TextureDefinition::FromFile { path }, channels)) => {
+            uniform.orm_index = asset_manager.load_texture(path);
+            uniform.orm_channels = channels.packed();
+        }
+        Some(_) => log::warn!("ORM textures must be loaded from a file"),
+        None => {}
+    }
+    if let Some((thickness, ior)) = material.thin_film {
+        uniform.film_thickness = thickness;
+        uniform.film_ior = ior;
+    }
+    if let Some(blend) = &material.blend {
+        uniform.flag = MaterialFlag::BLEND as i32;
+        uniform.blend_a = blend.a as i32;
+        uniform.blend_b = blend.b as i32;
+        match &blend.mask {
+            BlendMask::Texture(TextureDefinition::FromFile { path }) => {
+                uniform.blend_mask = asset_manager.load_texture(path);
+            }
+            BlendMask::Texture(_) => log::warn!("Blend masks must be loaded from a file"),
+            BlendMask::Noise {
+                scale,
+                coverage,
+                softness,
+            } => {
+                uniform.blend_scale = *scale;
+                uniform.blend_coverage = *coverage;
+                uniform.blend_softness = *softness;
+            }
+        }
+    }
+    uniform
+}
+
 pub struct SceneManager {
     pub scene: Scene,
     pub selected_scene: SceneName,
     pub selected_entity: i32,
+    /// Every selected entity including `selected_entity`, which is the last one clicked
+    pub selection: Vec<i32>,
     pub prev_scene: SceneName,
-    pub tx_request: Sender<SceneName>,
-    pub rx_loaded: Receiver<Scene>,
+    /// Id of the tab whose scene is currently held by the manager.
+    pub tab_id: usize,
+    pub tx_request: Sender<(usize, SceneName)>,
+    pub rx_loaded: Receiver<(usize, Scene)>,
+    /// Parts of large meshes that are still being parsed, tagged with the tab they belong to
+    pub rx_chunks: Receiver<(usize, MeshChunk)>,
+    /// Texture indices and decoded images shared with the loader thread's `AssetManager`
+    pub loaded_textures: Arc<DashMap<String, i32>>,
+    pub cpu_textures: Arc<DashMap<String, Arc<RgbaImage>>>,
+    pub mip_chains: Arc<DashMap<String, MipChain>>,
+    pub animated_textures: Arc<DashMap<i32, AnimatedTexture>>,
+    /// Frames of `animated_textures` decoded ahead of the timeline
+    pub sequence_frames: SequenceFrames,
+    /// Model parts shared with the loader thread's `AssetManager`
+    pub loaded_meshes: Arc<DashMap<String, Arc<MeshData>>>,
+    /// Path of a texture the UI asked to re-read from disk
+    pub texture_reload_requested: Option<String>,
+    /// Rebuild a mesh's BVH in the background when edits leave it unsuited, see `update_bvh`
+    pub auto_rebuild_bvh: bool,
+    /// Leave `Params` as they are when a scene loads rather than taking its `RenderSettings`
+    pub keep_settings: bool,
+    /// Meshes whose BVH is being rebuilt in the background
+    pub rebuilding: HashSet<usize>,
+    jobs: Jobs,
+    /// Background BVH rebuilds, by the mesh each is for
+    rebuilds: Vec<(usize, Job<RebuiltBlas>)>,
+    /// Models being read again after changing on disk, see `reload_model`
+    reloads: Vec<Job<(String, Vec<ModelPart>)>>,
+}
+
+/// A mesh's BVH built off the main thread, swapped in if the mesh is still the one it was built for.
+struct RebuiltBlas {
+    mesh: usize,
+    built: BuiltBlas,
+    triangles: Vec<PackedTriangle>,
+    nodes: Vec<Node>,
 }
 
 impl SceneManager {
-    pub fn new(mut asset_manager: AssetManager) -> Self {
-        let (tx_request, rx_request) = channel::<SceneName>();
-        let (tx_loaded, rx_loaded) = channel::<Scene>();
+    pub fn new(mut asset_manager: AssetManager, jobs: Jobs) -> Self {
+        let (tx_request, rx_request) = channel::<(usize, SceneName)>();
+        let (tx_loaded, rx_loaded) = channel::<(usize, Scene)>();
+        let (tx_chunks, rx_chunks) = channel::<(usize, MeshChunk)>();
+        let loaded_textures = asset_manager.loaded_textures.clone();
+        let cpu_textures = asset_manager.cpu_textures.clone();
+        let mip_chains = asset_manager.mip_chains.clone();
+        let animated_textures = asset_manager.animated_textures.clone();
+        let loaded_meshes = asset_manager.loaded_meshes.clone();
 
         std::thread::spawn(move || {
-            while let Ok(scene_name) = rx_request.recv() {
-                let scene =
-                    Scene::instantiate_scene(&Scene::from_name(scene_name), &mut asset_manager);
-                tx_loaded.send(scene).unwrap();
+            let mut pending: VecDeque<(usize, SceneName)> = VecDeque::new();
+            let mut next_stream_id = 0;
+            while let Some((tab_id, scene_name)) =
+                pending.pop_front().or_else(|| rx_request.recv().ok())
+            {
+                let (mut scene, streams) =
+                    Scene::instantiate_streaming(&Scene::from_name(scene_name), &mut asset_manager);
+                if !streams.is_empty() {
+                    next_stream_id += 1;
+                    scene.stream_id = next_stream_id;
+                }
+                tx_loaded.send((tab_id, scene)).unwrap();
+                // Requests that arrive while streaming wait their turn, unless they replace this
+                // tab's scene, which makes the rest of the stream pointless
+                let mut replaced = false;
+                let mut cancelled = || {
+                    while !replaced && let Ok(request) = rx_request.try_recv() {
+                        pending.push_back(request);
+                        replaced = request.0 == tab_id;
+                    }
+                    replaced
+                };
+                for pending_stream in &streams {
+                    stream::stream_model(
+                        pending_stream,
+                        next_stream_id,
+                        tab_id,
+                        &tx_chunks,
+                        &mut cancelled,
+                    );
+                }
             }
         });
 
@@ -133,26 +445,282 @@ impl SceneManager {
             prev_scene: SceneName::Empty,
             selected_scene: SceneName::Empty,
             selected_entity: -1,
+            selection: vec![],
+            tab_id: 0,
             tx_request,
             rx_loaded,
+            rx_chunks,
+            loaded_textures,
+            cpu_textures,
+            mip_chains,
+            animated_textures,
+            sequence_frames: SequenceFrames::default(),
+            loaded_meshes,
+            texture_reload_requested: None,
+            auto_rebuild_bvh: true,
+            keep_settings: false,
+            rebuilding: HashSet::new(),
+            jobs,
+            rebuilds: vec![],
+            reloads: vec![],
+        }
+    }
+    /// Re-reads a model after it changed on disk as a job, whose new geometry `poll_reloads`
+    /// swaps into the meshes loaded from it.
+    pub fn reload_model(&mut self, path: &str) {
+        let meshes: Vec<&MeshInstance> = self
+            .scene
+            .meshes
+            .iter()
+            .filter(|mesh| mesh.source.as_deref() == Some(path))
+            .collect();
+        let Some(first) = meshes.first() else {
+            return;
+        };
+        // Otherwise the parts would come straight back from the session's cache
+        for mesh in &meshes {
+            if let Some(label) = &mesh.label {
+                self.loaded_meshes.remove(label);
+            }
+        }
+        let crease_angle = first.crease_angle;
+        let loaded_meshes = self.loaded_meshes.clone();
+        let path = path.to_owned();
+        self.reloads
+            .push(self.jobs.spawn(format!("Reload {}", path), move || {
+                let parts = AssetManager::sharing_meshes(loaded_meshes).load_model(
+                    &path,
+                    Transform::default(),
+                    false,
+                    crease_angle,
+                );
+                (path, parts)
+            }));
+    }
+    /// Swaps finished reloads into the meshes loaded from them, keeping their transforms and
+    /// materials. Parts are matched by name, or in order when the names changed but not their
+    /// number. The whole BVH is rebuilt on the next frame rather than in the background, so
+    /// accumulation can restart on the new shape straight away. Returns how many meshes changed.
+    pub fn poll_reloads(&mut self) -> usize {
+        jobs::finished(&mut self.reloads)
+            .into_iter()
+            .map(|(path, parts)| self.swap_parts(&path, &parts))
+            .sum()
+    }
+    fn swap_parts(&mut self, path: &str, parts: &[ModelPart]) -> usize {
+        // Looked up again, the scene may have changed while the model was read
+        let meshes: Vec<usize> = (0..self.scene.meshes.len())
+            .filter(|&i| self.scene.meshes[i].source.as_deref() == Some(path))
+            .collect();
+        if meshes.is_empty() || parts.is_empty() {
+            return 0;
+        }
+        let by_name = meshes.iter().all(|&i| {
+            parts
+                .iter()
+                .any(|part| part.mesh.label == self.scene.meshes[i].label)
+        });
+        if !by_name && parts.len() != meshes.len() {
+            log::warn!(
+                "{} now has {} parts instead of {}, reload the scene to pick them up",
+                path,
+                parts.len(),
+                meshes.len()
+            );
+            return 0;
+        }
+        for (n, &i) in meshes.iter().enumerate() {
+            let mesh = &mut self.scene.meshes[i];
+            let part = match by_name {
+                true => parts.iter().find(|part| part.mesh.label == mesh.label),
+                false => parts.get(n),
+            };
+            if let Some(part) = part {
+                mesh.data = part.mesh.data.clone();
+            }
+        }
+        self.scene.built_bvh = false;
+        log::info!("Reloaded {} meshes of {}", meshes.len(), path);
+        meshes.len()
+    }
+    /// Keeps mesh BVHs in step with edits. Transforms only need the instance matrices refreshed,
+    /// while meshes whose BVH `BuiltBlas::needs_rebuild` are rebuilt on a background thread and
+    /// swapped in when done, rendering with the old BVH meanwhile. The geometry is unchanged by a
+    /// swap so accumulation carries on. Returns whether a rebuilt BVH was swapped in.
+    pub fn update_bvh(&mut self) -> bool {
+        let scene = &mut self.scene;
+        let mut swapped = false;
+        scene.bvh_data.update_instances(&scene.meshes);
+        let mut finished = vec![];
+        self.rebuilds.retain_mut(|(mesh, job)| {
+            let Some(result) = job.poll() else {
+                return true;
+            };
+            // Failed rebuilds are cleared too, or the mesh would never be rebuilt again
+            self.rebuilding.remove(mesh);
+            match result {
+                Ok(rebuilt) => finished.push(rebuilt),
+                Err(e) => log::error!("Job {} failed: {}", job.name, e),
+            }
+            false
+        });
+        for rebuilt in finished {
+            // Dropped if the scene was switched or the mesh replaced since the rebuild started
+            let current = scene.meshes.get(rebuilt.mesh).is_some_and(|mesh| {
+                Arc::ptr_eq(&mesh.data, &rebuilt.built.data)
+                    && rebuilt.mesh < scene.bvh_data.mesh_uniforms.len()
+            });
+            if current && scene.built_bvh && scene.bvh_data.is_shared(rebuilt.mesh) {
+                // Splitting an instance off a shared BVH moves the others, lay everything out again
+                scene.built_bvh = false;
+            } else if current && scene.built_bvh {
+                scene.bvh_data.replace_mesh(
+                    rebuilt.mesh,
+                    rebuilt.triangles,
+                    rebuilt.nodes,
+                    rebuilt.built,
+                );
+                swapped = true;
+            }
+        }
+        if !self.auto_rebuild_bvh || !scene.built_bvh {
+            return swapped;
+        }
+        for (i, mesh) in scene.meshes.iter().enumerate() {
+            let stale = scene
+                .bvh_data
+                .built
+                .get(i)
+                .is_some_and(|built| built.needs_rebuild(mesh));
+            if !stale || !self.rebuilding.insert(i) {
+                continue;
+            }
+            let name = format!("Rebuild BVH of {}", mesh.label.as_deref().unwrap_or("mesh"));
+            log::info!("{} in the background", name);
+            let mesh = mesh.clone();
+            let job = self.jobs.spawn(name, move || {
+                // A flattened axis has no shape to fit, build for the mesh as modelled
+                let scale = mesh.transform.scale;
+                let scale = match scale.abs().min_element() > f32::EPSILON {
+                    true => scale,
+                    false => Vec3::ONE,
+                };
+                let (triangles, nodes, _) = BVH::build_scaled(&mesh, Quality::High, scale);
+                RebuiltBlas {
+                    mesh: i,
+                    built: BuiltBlas::new(&mesh, scale),
+                    triangles,
+                    nodes,
+                }
+            });
+            self.rebuilds.push((i, job));
+        }
+        swapped
+    }
+    /// Selects an entity from the entity list. `toggle` adds or removes it from the selection and
+    /// `range` adds every entity between it and the last one clicked, in list order.
+    pub fn select(&mut self, entity: i32, toggle: bool, range: bool) {
+        if range && self.selected_entity != -1 {
+            let (from, to) = (
+                self.selected_entity.min(entity),
+                self.selected_entity.max(entity),
+            );
+            for other in from..=to {
+                if !self.selection.contains(&other) {
+                    self.selection.push(other);
+                }
+            }
+        } else if toggle {
+            match self.selection.iter().position(|&other| other == entity) {
+                Some(i) => {
+                    self.selection.remove(i);
+                    self.selected_entity = self.selection.last().copied().unwrap_or(-1);
+                }
+                None => {
+                    self.selection.push(entity);
+                    self.selected_entity = entity;
+                }
+            }
+        } else {
+            self.selection = vec![entity];
+            self.selected_entity = entity;
+        }
+    }
+    pub fn is_selected(&self, entity: i32) -> bool {
+        entity != -1 && self.selection.contains(&entity)
+    }
+    /// Drops the selection when `selected_entity` was changed without going through `select`,
+    /// such as by picking or a scene reload.
+    pub fn sync_selection(&mut self) {
+        let count = (self.scene.spheres.len() + self.scene.meshes.len()) as i32;
+        self.selection.retain(|&entity| entity < count);
+        if self.selected_entity == -1 {
+            self.selection.clear();
+        } else if !self.selection.contains(&self.selected_entity) {
+            self.selection = vec![self.selected_entity];
         }
     }
     pub fn request_scene(&mut self, name: SceneName) {
         log::info!("Loading Scene: {:?}", name);
+        // The new scene registers its own sequences as it loads
+        self.animated_textures.clear();
+        self.sequence_frames.clear();
         self.selected_scene = name;
         self.prev_scene = self.selected_scene;
-        self.tx_request.send(name).unwrap();
+        self.tx_request.send((self.tab_id, name)).unwrap();
+    }
+    /// Loads a scene for a tab that is not currently active.
+    pub fn request_scene_for_tab(&self, tab_id: usize, name: SceneName) {
+        log::info!("Loading Scene: {:?} (tab {})", name, tab_id);
+        self.tx_request.send((tab_id, name)).unwrap();
     }
 }
 
 pub struct Scene {
     pub camera: Camera,
+    /// Named viewpoints, the scene's own camera first. Selecting one replaces `camera`.
+    pub views: Vec<(String, Camera)>,
+    /// Index into `views` last selected, edits to `camera` aren't written back until saved
+    pub active_view: usize,
+    pub sky: Sky,
+    pub fog: HeightFog,
     pub spheres: Vec<Sphere>,
+    /// Labels of the spheres in order, meshes carry their own in `MeshInstance::user_label`
+    pub sphere_labels: Vec<EntityLabel>,
     pub meshes: Vec<MeshInstance>,
     pub bvh_data: MeshDataList,
     pub bvh_quality: Quality,
     pub built_bvh: bool,
     pub textures: Vec<Arc<RgbaImage>>,
+    /// Where each texture is mapped, used to pick the mip levels worth keeping in `textures`
+    pub texture_uses: Vec<TextureUse>,
+    /// Level of each slot of `textures`, `None` for empty slots
+    pub texture_levels: Vec<Option<ResidentTexture>>,
+    /// Heterogeneous media, drawn on top of the surfaces rather than as selectable entities
+    pub volumes: Vec<Volume>,
+    /// Openings the environment is sampled through, see `Portal`
+    pub portals: Vec<Portal>,
+    /// Palette that blend materials index into
+    pub materials: Vec<MaterialUniform>,
+    pub timeline: Timeline,
+    /// Render settings the scene was defined with, see `RenderSettings::apply`
+    pub settings: RenderSettings,
+    /// Replaces the engine's global post stack while this scene is shown
+    pub post: Option<PostStack>,
+    /// Matches the chunks streamed in for this scene, zero when nothing is streamed
+    pub stream_id: u64,
+    /// Streamed meshes that haven't sent their complete mesh yet
+    pub streaming: usize,
+    /// Meshes with geometry problems found when they were loaded, by mesh label
+    pub diagnostics: Vec<(String, MeshReport)>,
+    /// `fingerprint` once everything the definition builds is in, `None` while meshes stream
+    defined: Option<u64>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[allow(dead_code)]
@@ -168,107 +736,684 @@ impl Scene {
         });
         Self {
             camera,
+            views: vec![("Default".to_owned(), camera)],
+            active_view: 0,
+            sky: Sky::default(),
+            fog: HeightFog::default(),
             spheres: vec![],
+            sphere_labels: vec![],
             meshes: vec![],
             bvh_data: MeshDataList::default(),
             bvh_quality: Quality::default(),
             built_bvh: false,
             textures: vec![],
+            texture_uses: vec![],
+            texture_levels: vec![],
+            volumes: vec![],
+            portals: vec![],
+            materials: vec![],
+            timeline: Timeline::default(),
+            settings: RenderSettings::default(),
+            post: None,
+            stream_id: 0,
+            streaming: 0,
+            diagnostics: vec![],
+            defined: None,
+        }
+    }
+    /// World space bounds of every sphere and mesh, `None` for an empty scene.
+    pub fn compute_bounds(&self) -> Option<Aabb> {
+        let mut bounds = Aabb::default();
+        for i in 0..(self.spheres.len() + self.meshes.len()) as i32 {
+            if let Some(entity_bounds) = self.entity_bounds(i) {
+                bounds.grow_aabb(&entity_bounds);
+            }
+        }
+        bounds.is_valid().then_some(bounds)
+    }
+    /// Display name of an entity, indexed like `SceneManager::selected_entity`. Entities without
+    /// a name of their own go by their mesh's label or a numbered generic one.
+    pub fn entity_name(&self, entity: i32) -> Option<String> {
+        if let Some(label) = self.entity_label(entity)
+            && !label.name.is_empty()
+        {
+            return Some(label.name.clone());
+        }
+        let entity = usize::try_from(entity).ok()?;
+        if entity < self.spheres.len() {
+            return Some(format!("Sphere {}", entity));
+        }
+        let i = entity - self.spheres.len();
+        let mesh = self.meshes.get(i)?;
+        Some(mesh.label.clone().unwrap_or(format!("Mesh {}", i)))
+    }
+    pub fn entity_label(&self, entity: i32) -> Option<&EntityLabel> {
+        let entity = usize::try_from(entity).ok()?;
+        match entity < self.spheres.len() {
+            true => self.sphere_labels.get(entity),
+            false => Some(&self.meshes.get(entity - self.spheres.len())?.user_label),
+        }
+    }
+    pub fn entity_label_mut(&mut self, entity: i32) -> Option<&mut EntityLabel> {
+        let entity = usize::try_from(entity).ok()?;
+        if entity < self.spheres.len() {
+            // Spheres added without a definition have no label yet
+            self.sphere_labels
+                .resize(self.spheres.len(), EntityLabel::default());
+            return self.sphere_labels.get_mut(entity);
+        }
+        Some(&mut self.meshes.get_mut(entity - self.spheres.len())?.user_label)
+    }
+    /// World space bounds of an entity, indexed like `SceneManager::selected_entity`.
+    pub fn entity_bounds(&self, entity: i32) -> Option<Aabb> {
+        if entity < 0 {
+            return None;
+        }
+        let entity = entity as usize;
+        let mut bounds = Aabb::default();
+        if let Some(sphere) = self.spheres.get(entity) {
+            let centre = Vec3::from_array(sphere.pos);
+            bounds.grow_point(centre - Vec3::splat(sphere.radius));
+            bounds.grow_point(centre + Vec3::splat(sphere.radius));
+        } else if let Some(mesh) = self.meshes.get(entity - self.spheres.len()) {
+            let model_to_world = mesh.transform.to_matrix();
+            for vertex in mesh.data.vertices.iter() {
+                bounds.grow_point(model_to_world.transform_point3(vertex.pos));
+            }
+        }
+        bounds.is_valid().then_some(bounds)
+    }
+    /// Material of a sphere or mesh, indexed like `SceneManager::selected_entity`.
+    pub fn entity_material_mut(&mut self, entity: i32) -> Option<&mut MaterialUniform> {
+        let entity = usize::try_from(entity).ok()?;
+        match entity < self.spheres.len() {
+            true => Some(&mut self.spheres[entity].material),
+            false => Some(&mut self.meshes.get_mut(entity - self.spheres.len())?.material),
+        }
+    }
+    /// Gives a sphere or mesh one of the ready made materials, keeping which rays it is hidden
+    /// from.
+    pub fn apply_preset(&mut self, entity: i32, preset: MaterialPreset) {
+        // Presets are untextured, so the manager never loads anything
+        let uniform = material_uniform(&preset.definition(), &AssetManager::new());
+        if let Some(material) = self.entity_material_mut(entity) {
+            *material = MaterialUniform {
+                visibility: material.visibility,
+                ..uniform
+            };
+        }
+    }
+    /// Moves a sphere or mesh by `delta` in world space.
+    pub fn translate_entity(&mut self, entity: i32, delta: Vec3) {
+        let Ok(entity) = usize::try_from(entity) else {
+            return;
+        };
+        if let Some(sphere) = self.spheres.get_mut(entity) {
+            sphere.pos = (Vec3::from_array(sphere.pos) + delta).to_array();
+        } else if let Some(mesh) = self.meshes.get_mut(entity - self.spheres.len()) {
+            mesh.transform.pos += delta;
+        }
+    }
+    /// Lowers each entity straight down until it rests on whatever is below, lowest first so a
+    /// stack settles onto itself. Returns how many moved.
+    pub fn drop_to_floor(&mut self, entities: &[i32]) -> usize {
+        let mut entities: Vec<(i32, f32)> = entities
+            .iter()
+            .filter_map(|&entity| Some((entity, self.entity_bounds(entity)?.min.y)))
+            .collect();
+        entities.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let mut moved = 0;
+        for (entity, _) in entities {
+            match placement::drop_distance(self, entity as usize) {
+                Some(distance) if distance > 0.0 => {
+                    self.translate_entity(entity, Vec3::NEG_Y * distance);
+                    moved += 1;
+                }
+                Some(_) => {}
+                None => log::info!(
+                    "Nothing below {} to drop onto",
+                    self.entity_name(entity).unwrap_or_default()
+                ),
+            }
+        }
+        self.bvh_data.update_instances(&self.meshes);
+        moved
+    }
+    /// World space surface area of a sphere or mesh.
+    pub fn entity_area(&self, entity: i32) -> Option<f32> {
+        let entity = usize::try_from(entity).ok()?;
+        match self.spheres.get(entity) {
+            Some(sphere) => Some(4.0 * std::f32::consts::PI * sphere.radius * sphere.radius),
+            None => Some(self.meshes.get(entity - self.spheres.len())?.surface_area()),
         }
     }
     pub fn instantiate_scene(
         scene_definition: &SceneDefinition,
         asset_manager: &mut AssetManager,
     ) -> Scene {
-        let (spheres, meshes): (Vec<Sphere>, Vec<MeshInstance>) = scene_definition
+        Scene::instantiate(scene_definition, asset_manager, false).0
+    }
+    /// Like `instantiate_scene`, but meshes from very large files are left out and returned to be
+    /// streamed in with `stream::stream_model` once the rest of the scene is shown.
+    pub fn instantiate_streaming(
+        scene_definition: &SceneDefinition,
+        asset_manager: &mut AssetManager,
+    ) -> (Scene, Vec<PendingStream>) {
+        Scene::instantiate(scene_definition, asset_manager, true)
+    }
+    fn instantiate(
+        scene_definition: &SceneDefinition,
+        asset_manager: &mut AssetManager,
+        streaming: bool,
+    ) -> (Scene, Vec<PendingStream>) {
+        let mut placed: Vec<PlacedEntity> = scene_definition
             .entities
+            .iter()
+            .map(|entity| PlacedEntity {
+                entity,
+                transform: entity.transform,
+                label: entity.label.clone(),
+            })
+            .collect();
+        prefab::place(
+            &scene_definition.prefabs,
+            &scene_definition.instances,
+            Transform::default(),
+            "",
+            &mut placed,
+        );
+        // Every placement of a prefab's mesh shares one copy of its geometry, and so one BLAS
+        let mut shared_data: HashMap<usize, Arc<MeshData>> = HashMap::new();
+        for placed in &placed {
+            let data = || match &placed.entity.primitive {
+                Primitive::Mesh(mesh) => mesh.mesh_data(),
+                Primitive::RectLight { width, height } => Some(MeshData {
+                    vertices: Arc::new(MeshData::rect(*width, *height)),
+                    indices: Arc::new(vec![0, 1, 2, 0, 2, 3]),
+                }),
+                _ => None,
+            };
+            if let Entry::Vacant(entry) =
+                shared_data.entry(placed.entity as *const EntityDefinition as usize)
+                && let Some(data) = data()
+            {
+                entry.insert(Arc::new(data));
+            }
+        }
+        let (spheres, sphere_labels, meshes, streams): (
+            Vec<Sphere>,
+            Vec<EntityLabel>,
+            Vec<MeshInstance>,
+            Vec<PendingStream>,
+        ) = placed
             .par_iter()
             .enumerate()
-            .map(|(i, e)| {
+            .map(|(i, placed)| {
+                let e = placed.entity;
                 let mut spheres_chunk: Vec<Sphere> = vec![];
                 let mut meshes_chunk: Vec<MeshInstance> = vec![];
+                let mut streams_chunk: Vec<PendingStream> = vec![];
 
-                let mut flag = e.material.flag as i32;
-                let diffuse_index = if let Some(diffuse) = &e.material.diffuse_texture {
-                    // Handle loading texture (use asset_manager)
-                    match diffuse {
-                        TextureDefinition::FromFile { path } => {
-                            flag = MaterialFlag::TEXTURE as i32;
-                            asset_manager.load_texture(&path)
-                        }
-                        _ => -1,
-                    }
-                } else {
-                    -1
-                };
-                let material = MaterialUniform {
-                    color: e.material.color,
-                    emission_color: e.material.emission_color,
-                    specular_color: e.material.specular_color,
-                    absorption: e.material.absorption,
-                    absorption_stength: e.material.absorption_stength,
-                    emission_strength: e.material.emission_strength,
-                    smoothness: e.material.smoothness,
-                    specular: e.material.specular,
-                    ior: e.material.ior,
-                    flag,
-                    diffuse_index,
-                    ..Default::default()
-                };
+                let material = material_uniform(&e.material, asset_manager);
                 match &e.primitive {
                     Primitive::Sphere { centre, radius } => {
-                        spheres_chunk.push(Sphere::new(*centre, *radius, material));
+                        let transform = placed.transform;
+                        spheres_chunk.push(Sphere::new(
+                            transform.to_matrix().transform_point3(*centre),
+                            *radius * transform.scale.abs().max_element(),
+                            material,
+                        ));
                     }
                     Primitive::Mesh(mesh_def) => {
                         match mesh_def {
-                            MeshDefinition::FromFile { path, use_mtl } => {
-                                // Load mesh using asset manager
+                            MeshDefinition::FromFile {
+                                path,
+                                use_mtl,
+                                rules,
+                                crease_angle,
+                            } if streaming
+                                && !*use_mtl
+                                && rules.is_empty()
+                                && crease_angle.is_none()
+                                && stream::should_stream(path) =>
+                            {
+                                streams_chunk.push(PendingStream {
+                                    path: path.clone(),
+                                    transform: placed.transform,
+                                    material,
+                                    label: placed.label.clone(),
+                                });
+                            }
+                            MeshDefinition::FromFile {
+                                path,
+                                use_mtl,
+                                rules,
+                                crease_angle,
+                            } => {
+                                let rules: Vec<(&MaterialRule, MaterialUniform)> = rules
+                                    .iter()
+                                    .map(|rule| {
+                                        (rule, material_uniform(&rule.material, asset_manager))
+                                    })
+                                    .collect();
                                 let mut m = asset_manager.load_model_with_material(
                                     path,
-                                    e.transform,
+                                    placed.transform,
                                     *use_mtl,
                                     material,
+                                    &rules,
+                                    *crease_angle,
                                 );
                                 meshes_chunk.append(&mut m);
                             }
-                            MeshDefinition::FromData { vertices, indices } => {
-                                meshes_chunk.push(MeshInstance {
-                                    label: Some(format!("mesh_{}", i)),
-                                    transform: e.transform,
-                                    data: Arc::new(MeshData {
-                                        vertices: vertices.clone(),
-                                        indices: indices.clone(),
-                                    }),
-                                    material,
-                                })
-                            }
+                            _ => meshes_chunk.push(MeshInstance {
+                                label: Some(format!("{}_{}", mesh_def.kind(), i)),
+                                transform: placed.transform,
+                                data: shared_data[&(e as *const EntityDefinition as usize)].clone(),
+                                material,
+                                uv_view: UvView::Off,
+                                source: None,
+                                rect_light: None,
+                                crease_angle: None,
+                                user_label: EntityLabel::default(),
+                            }),
                         };
                     }
+                    Primitive::RectLight { width, height } => {
+                        meshes_chunk.push(MeshInstance {
+                            label: Some(format!("rect_light_{}", i)),
+                            transform: placed.transform,
+                            data: shared_data[&(e as *const EntityDefinition as usize)].clone(),
+                            material,
+                            uv_view: UvView::Off,
+                            source: None,
+                            rect_light: Some([*width, *height]),
+                            crease_angle: None,
+                            user_label: EntityLabel::default(),
+                        });
+                    }
+                }
+                // Parts of a model share the entity's name, told apart by their own
+                let parts = meshes_chunk.len();
+                for mesh in meshes_chunk.iter_mut() {
+                    mesh.user_label = placed.label.clone();
+                    if parts > 1
+                        && !placed.label.name.is_empty()
+                        && let Some(part) = &mesh.label
+                    {
+                        mesh.user_label.name = format!("{} / {}", placed.label.name, part);
+                    }
+                }
+                let sphere_labels = vec![placed.label.clone(); spheres_chunk.len()];
+                let unit = e.material.emission_unit;
+                if unit == LightUnit::Lumens && !streams_chunk.is_empty() {
+                    log::warn!("Streamed meshes don't support lumens, using the strength as is");
+                } else if unit != LightUnit::Unitless {
+                    // Flux is shared by every part of the entity, luminance applies to each
+                    let area = spheres_chunk
+                        .iter()
+                        .map(|s| 4.0 * std::f32::consts::PI * s.radius * s.radius)
+                        .chain(meshes_chunk.iter().map(MeshInstance::surface_area))
+                        .sum();
+                    let materials = spheres_chunk
+                        .iter_mut()
+                        .map(|s| &mut s.material)
+                        .chain(meshes_chunk.iter_mut().map(|m| &mut m.material));
+                    for material in materials {
+                        material.emission_strength = unit.to_strength(
+                            e.material.emission_strength,
+                            material.emission_color,
+                            area,
+                        );
+                    }
                 }
 
-                (spheres_chunk, meshes_chunk)
+                (spheres_chunk, sphere_labels, meshes_chunk, streams_chunk)
             })
             .reduce(
-                || (vec![], vec![]),
-                |(mut s1, mut m1), (s2, m2)| {
+                || (vec![], vec![], vec![], vec![]),
+                |(mut s1, mut l1, mut m1, mut p1), (s2, l2, m2, p2)| {
                     s1.extend(s2);
+                    l1.extend(l2);
                     m1.extend(m2);
-                    (s1, m1)
+                    p1.extend(p2);
+                    (s1, l1, m1, p1)
                 },
             );
 
-        let bvh_data = BVH::build_per_mesh(&meshes, bvh::Quality::High);
-        let textures = asset_manager.create_texture_array();
-        Self {
+        let materials = scene_definition
+            .materials
+            .iter()
+            .map(|material| material_uniform(material, asset_manager))
+            .collect();
+        // Neither needs the other, so checking the meshes fills in around the BVH build
+        let (bvh_data, diagnostics) = rayon::join(
+            || BVH::build_per_mesh(&meshes, bvh::Quality::High),
+            || {
+                meshes
+                    .par_iter()
+                    .enumerate()
+                    .filter_map(|(i, mesh)| diagnose_mesh(i, mesh))
+                    .collect()
+            },
+        );
+        let texture_uses = mip_cache::texture_uses(&spheres, &meshes);
+        let (textures, texture_levels) = asset_manager.create_texture_array(|index, chain| {
+            let mut uses = texture_uses
+                .iter()
+                .filter(|texture_use| texture_use.texture == index)
+                .peekable();
+            uses.peek()?;
+            Some(chain.needed_level(uses, &scene_definition.camera, RENDER_SIZE.1))
+        });
+        let volumes = scene_definition
+            .volumes
+            .iter()
+            .filter_map(|definition| {
+                let grid = match &definition.source {
+                    VolumeSource::FromFile { path } => match asset_manager.load_volume(path) {
+                        Ok(grid) => grid,
+                        Err(e) => {
+                            log::error!("Failed to load volume {}: {}", path, e);
+                            return None;
+                        }
+                    },
+                    VolumeSource::Cloud { resolution, seed } => {
+                        VoxelGrid::cloud(*resolution, *seed)
+                    }
+                };
+                Some(Volume::new(definition, &grid))
+            })
+            .collect();
+        let views = std::iter::once(("Default".to_owned(), scene_definition.camera))
+            .chain(scene_definition.views.iter().cloned())
+            .collect();
+        let mut scene = Self {
             camera: scene_definition.camera,
+            views,
+            active_view: 0,
+            sky: scene_definition.sky,
+            fog: scene_definition.fog,
             spheres,
+            sphere_labels,
             meshes,
             bvh_data,
             bvh_quality: bvh::Quality::High,
             built_bvh: true,
             textures,
+            texture_uses,
+            texture_levels,
+            volumes,
+            portals: scene_definition.portals.clone(),
+            materials,
+            timeline: Timeline::default(),
+            settings: scene_definition.settings.clone(),
+            post: scene_definition.settings.post.clone(),
+            stream_id: 0,
+            streaming: streams.len(),
+            diagnostics,
+            defined: None,
+        };
+        if streams.is_empty() {
+            scene.defined = Some(scene.fingerprint());
+        }
+        (scene, streams)
+    }
+    /// Hash of what an edit can change besides the camera: the entities, their materials and
+    /// geometry, the palette, sky, fog, volumes and portals. Geometry and volume grids are compared
+    /// by identity rather than content, textures are left out.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let floats = |hasher: &mut DefaultHasher, values: &[f32]| {
+            for value in values {
+                hasher.write_u32(value.to_bits());
+            }
+        };
+        hasher.write(bytemuck::cast_slice(&self.spheres));
+        hasher.write_usize(self.meshes.len());
+        for mesh in &self.meshes {
+            let transform = mesh.transform;
+            hasher.write_usize(Arc::as_ptr(&mesh.data) as usize);
+            floats(&mut hasher, &transform.pos.to_array());
+            floats(&mut hasher, &transform.rot.to_array());
+            floats(&mut hasher, &transform.scale.to_array());
+            hasher.write(bytemuck::bytes_of(&mesh.material));
+            hasher.write_u32(mesh.uv_view as u32);
+            floats(&mut hasher, &mesh.rect_light.unwrap_or([-1.0; 2]));
+        }
+        hasher.write(bytemuck::cast_slice(&self.materials));
+        let sky = self.sky;
+        hasher.write_u8(sky.enabled as u8);
+        floats(
+            &mut hasher,
+            &[
+                sky.time_of_day,
+                sky.day_of_year,
+                sky.latitude,
+                sky.north,
+                sky.sun_intensity,
+            ],
+        );
+        let fog = self.fog;
+        hasher.write_u8(fog.enabled as u8 | (fog.sky_color as u8) << 1);
+        floats(
+            &mut hasher,
+            &[fog.density, fog.height_falloff, fog.base_height],
+        );
+        floats(&mut hasher, &fog.color);
+        hasher.write_usize(self.volumes.len());
+        for volume in &self.volumes {
+            hasher.write_usize(Arc::as_ptr(&volume.grid) as usize);
+            floats(&mut hasher, &volume.bounds_min.to_array());
+            floats(&mut hasher, &volume.bounds_max.to_array());
+            floats(
+                &mut hasher,
+                &[volume.density_scale, volume.emission_strength],
+            );
+            floats(&mut hasher, &volume.color);
+            floats(&mut hasher, &volume.emission_color);
+        }
+        for portal in &self.portals {
+            hasher.write(bytemuck::bytes_of(&portal.to_uniform()));
+        }
+        hasher.finish()
+    }
+    /// Whether the scene is still exactly as its definition built it, apart from the camera. Edits,
+    /// moved or added entities and meshes still streaming in all count as changes.
+    pub fn matches_definition(&self) -> bool {
+        self.defined == Some(self.fingerprint())
+    }
+    /// Adds a streamed chunk, or swaps every chunk of its source for the complete mesh. Chunks past
+    /// the GPU buffer limits are dropped.
+    pub fn apply_chunk(&mut self, chunk: MeshChunk) {
+        self.add_chunk(chunk);
+        if self.streaming == 0 && self.defined.is_none() {
+            self.defined = Some(self.fingerprint());
         }
     }
+    fn add_chunk(&mut self, chunk: MeshChunk) {
+        if chunk.complete {
+            let parts = format!("{} [part ", chunk.source);
+            let mut i = 0;
+            while i < self.meshes.len() {
+                if self.meshes[i]
+                    .label
+                    .as_ref()
+                    .is_some_and(|label| label.starts_with(&parts))
+                {
+                    self.meshes.remove(i);
+                    self.bvh_data.remove_mesh(i);
+                } else {
+                    i += 1;
+                }
+            }
+            self.streaming = self.streaming.saturating_sub(1);
+            if chunk.mesh.data.vertices.is_empty() {
+                return;
+            }
+            self.diagnostics
+                .extend(diagnose_mesh(self.meshes.len(), &chunk.mesh));
+        }
+        if self.meshes.len() as u64 >= MAX_MESHES
+            || (self.bvh_data.triangles.len() + chunk.triangles.len()) as u64 > MAX_TRIANGLES
+            || self.bvh_data.nodes.len() + chunk.nodes.len() > BVH::MAX_NODES as usize
+        {
+            log::warn!("{} doesn't fit in the scene buffers", chunk.source);
+            return;
+        }
+        self.bvh_data.degenerate_triangles += chunk.degenerate_triangles;
+        self.bvh_data
+            .push_mesh(&chunk.mesh, chunk.triangles, chunk.nodes);
+        self.meshes.push(chunk.mesh);
+    }
+    /// Current value of an animatable property, `None` if the target doesn't have it.
+    pub fn read_property(&self, target: AnimTarget, property: AnimProperty) -> Option<AnimValue> {
+        let (transform, material, sphere) = match target {
+            AnimTarget::Camera => {
+                return match property {
+                    AnimProperty::Position => Some(vec3_value(self.camera.transform.pos)),
+                    AnimProperty::Rotation => Some(self.camera.transform.rot.to_array()),
+                    AnimProperty::Fov => Some([self.camera.fov, 0.0, 0.0, 0.0]),
+                    _ => None,
+                };
+            }
+            AnimTarget::Sky => {
+                return match property {
+                    AnimProperty::TimeOfDay => Some([self.sky.time_of_day, 0.0, 0.0, 0.0]),
+                    AnimProperty::DayOfYear => Some([self.sky.day_of_year, 0.0, 0.0, 0.0]),
+                    _ => None,
+                };
+            }
+            AnimTarget::Entity(i) => match self.spheres.get(i) {
+                Some(sphere) => (None, &sphere.material, Some(sphere)),
+                None => {
+                    let mesh = self.meshes.get(i - self.spheres.len())?;
+                    (Some(&mesh.transform), &mesh.material, None)
+                }
+            },
+        };
+        let scalar = |v: f32| Some([v, 0.0, 0.0, 0.0]);
+        match property {
+            AnimProperty::Position => match sphere {
+                Some(sphere) => Some(vec3_value(Vec3::from_array(sphere.pos))),
+                None => transform.map(|t| vec3_value(t.pos)),
+            },
+            AnimProperty::Rotation => transform.map(|t| t.rot.to_array()),
+            AnimProperty::Scale => transform.map(|t| vec3_value(t.scale)),
+            AnimProperty::Radius => sphere.and_then(|s| scalar(s.radius)),
+            AnimProperty::Fov | AnimProperty::TimeOfDay | AnimProperty::DayOfYear => None,
+            AnimProperty::Color => Some(material.color),
+            AnimProperty::EmissionColor => Some(material.emission_color),
+            AnimProperty::EmissionStrength => scalar(material.emission_strength),
+            AnimProperty::Smoothness => scalar(material.smoothness),
+            AnimProperty::Specular => scalar(material.specular),
+            AnimProperty::Ior => scalar(material.ior),
+        }
+    }
+    pub fn write_property(&mut self, target: AnimTarget, property: AnimProperty, value: AnimValue) {
+        let sphere_count = self.spheres.len();
+        let (transform, material, sphere_pos, sphere_radius) = match target {
+            AnimTarget::Camera => {
+                match property {
+                    AnimProperty::Position => self.camera.transform.pos = value_vec3(value),
+                    AnimProperty::Rotation => {
+                        self.camera.transform.rot = Quat::from_array(value).normalize()
+                    }
+                    AnimProperty::Fov => self.camera.fov = value[0],
+                    _ => {}
+                }
+                return;
+            }
+            AnimTarget::Sky => {
+                match property {
+                    // Wraps so keys from 0 to 48 run through two days
+                    AnimProperty::TimeOfDay => self.sky.time_of_day = value[0].rem_euclid(24.0),
+                    AnimProperty::DayOfYear => self.sky.day_of_year = value[0].clamp(1.0, 365.0),
+                    _ => {}
+                }
+                return;
+            }
+            AnimTarget::Entity(i) if i < sphere_count => {
+                let sphere = &mut self.spheres[i];
+                (
+                    None,
+                    &mut sphere.material,
+                    Some(&mut sphere.pos),
+                    Some(&mut sphere.radius),
+                )
+            }
+            AnimTarget::Entity(i) => {
+                let Some(mesh) = self.meshes.get_mut(i - sphere_count) else {
+                    return;
+                };
+                (Some(&mut mesh.transform), &mut mesh.material, None, None)
+            }
+        };
+        match property {
+            AnimProperty::Position => {
+                if let Some(pos) = sphere_pos {
+                    *pos = value_vec3(value).to_array();
+                } else if let Some(transform) = transform {
+                    transform.pos = value_vec3(value);
+                }
+            }
+            AnimProperty::Rotation => {
+                if let Some(transform) = transform {
+                    transform.rot = Quat::from_array(value).normalize();
+                }
+            }
+            AnimProperty::Scale => {
+                if let Some(transform) = transform {
+                    transform.scale = value_vec3(value);
+                }
+            }
+            AnimProperty::Radius => {
+                if let Some(radius) = sphere_radius {
+                    *radius = value[0].max(0.0);
+                }
+            }
+            AnimProperty::Fov | AnimProperty::TimeOfDay | AnimProperty::DayOfYear => {}
+            AnimProperty::Color => material.color = value,
+            AnimProperty::EmissionColor => material.emission_color = value,
+            AnimProperty::EmissionStrength => material.emission_strength = value[0].max(0.0),
+            AnimProperty::Smoothness => material.smoothness = value[0].clamp(0.0, 1.0),
+            AnimProperty::Specular => material.specular = value[0].clamp(0.0, 1.0),
+            AnimProperty::Ior => material.ior = value[0],
+        }
+    }
+    /// Keys a property of `target` at the timeline's current frame with its current value.
+    pub fn key_property(&mut self, target: AnimTarget, property: AnimProperty) {
+        if let Some(value) = self.read_property(target, property) {
+            self.timeline.insert_key(target, property, value);
+        }
+    }
+    /// Writes every animated property at the timeline's current frame into the scene.
+    pub fn apply_timeline(&mut self) {
+        for (target, property, value) in self.timeline.evaluate() {
+            self.write_property(target, property, value);
+        }
+        self.camera.shake = self.timeline.shake_offset();
+        self.bvh_data.update_instances(&self.meshes);
+    }
+    /// Writes every audio bound property for the given band levels, see `AudioBinding`.
+    pub fn apply_audio(&mut self, levels: &[f32]) {
+        for i in 0..self.timeline.audio.len() {
+            let binding = self.timeline.audio[i];
+            let level = levels.get(binding.band).copied().unwrap_or(0.0);
+            self.write_property(binding.target, binding.property, binding.value(level));
+        }
+        self.bvh_data.update_instances(&self.meshes);
+    }
+    /// Switches to a named viewpoint, keeping the viewport's aspect ratio.
+    pub fn select_view(&mut self, index: usize) {
+        let Some((_, view)) = self.views.get(index) else {
+            return;
+        };
+        let aspect = self.camera.aspect;
+        self.camera = *view;
+        self.camera.aspect = aspect;
+        self.active_view = index;
+    }
     pub fn bvh_nodes(&mut self) -> &Vec<Node> {
         if !self.built_bvh && self.meshes.len() > 0 {
             self.bvh_data = BVH::build_per_mesh(&self.meshes, bvh::Quality::High);
@@ -294,14 +1439,22 @@ impl Scene {
                 absorption: [0.0; 4],
                 absorption_stength: 0.0,
                 emission_strength: 0.0,
+                emission_unit: LightUnit::Unitless,
+                light_flags: 0,
+                visibility: 0,
+                thin_film: None,
                 smoothness: 0.0,
                 specular: 0.05,
                 ior: 1.0,
+                priority: 0,
                 flag: MaterialFlag::TEXTURE,
                 diffuse_texture: Some(TextureDefinition::FromFile {
                     path: "earthmap.png".to_string(),
                 }),
                 normal_texture: None,
+                orm_texture: None,
+                address_mode: AddressMode::Repeat,
+                blend: None,
             },
         );
 
@@ -322,6 +1475,8 @@ impl Scene {
             MeshDefinition::FromFile {
                 path: "dragon.obj".to_string(),
                 use_mtl: false,
+                rules: vec![],
+                crease_angle: None,
             },
             MaterialDefinition::new(),
         );
@@ -440,6 +1595,14 @@ impl Scene {
             }
         }
 
+        // Open sky and mostly one bounce off each ball, deep glass paths are what need the bounces
+        scene_def.set_render_settings(RenderSettings {
+            bounces: Some(8),
+            skybox: Some(true),
+            clamp_direct: Some(0.0),
+            clamp_indirect: Some(0.0),
+            ..Default::default()
+        });
         scene_def
     }
     pub fn room() -> SceneDefinition {
@@ -569,6 +1732,13 @@ impl Scene {
                 .specular([1.0, 1.0, 1.0, 1.0], 0.2),
         );
 
+        // The open front of the room
+        scene_def.add_portal(Portal::new(
+            Vec3::new(-2.0, 0.0, -2.0),
+            Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(0.0, 4.0, 0.0),
+        ));
+
         scene_def
     }
     pub fn room_2() -> SceneDefinition {
@@ -598,6 +1768,8 @@ impl Scene {
             MeshDefinition::FromFile {
                 path: "Dragon_80K.obj".to_string(),
                 use_mtl: false,
+                rules: vec![],
+                crease_angle: None,
             },
             MaterialDefinition::new()
                 .color([0.96078, 0.11372, 0.4039, 1.0])
@@ -613,6 +1785,8 @@ impl Scene {
             MeshDefinition::FromFile {
                 path: "Dragon_80K.obj".to_string(),
                 use_mtl: false,
+                rules: vec![],
+                crease_angle: None,
             },
             MaterialDefinition::new()
                 .color([0.96078, 0.11372, 0.4039, 1.0])
@@ -767,6 +1941,27 @@ impl Scene {
             focus_dist: 0.1,
             ..Default::default()
         });
+        for (name, origin, look_at) in [
+            (
+                "Bubble",
+                Vec3::new(-0.9, 0.0, 0.8),
+                Vec3::new(-0.35, -0.25, -0.2),
+            ),
+            ("Above", Vec3::new(0.0, 2.5, 0.5), Vec3::new(0.0, 0.0, -1.0)),
+            ("Low", Vec3::new(1.8, -0.3, 1.2), Vec3::new(0.0, 0.0, -1.0)),
+        ] {
+            scene.add_view(
+                name,
+                &CameraDescriptor {
+                    transform: Transform::cam(origin, look_at),
+                    fov: 45.0,
+                    near: 0.1,
+                    far: 100.0,
+                    focus_dist: 0.1,
+                    ..Default::default()
+                },
+            );
+        }
 
         // Add spheres
         scene.add_sphere(
@@ -775,10 +1970,26 @@ impl Scene {
             MaterialDefinition::new().color([0.8, 0.8, 0.0, 1.0]),
         );
 
+        // Paint worn through to rust in patches
+        let paint = scene.add_material(
+            MaterialDefinition::new()
+                .color([0.7, 0.3, 0.3, 1.0])
+                .specular([1.0; 4], 0.1)
+                .smooth(0.8),
+        );
+        let rust = scene.add_material(MaterialDefinition::new().color([0.35, 0.15, 0.06, 1.0]));
         scene.add_sphere(
             Vec3::new(0.0, 0.0, -1.0),
             0.5,
-            MaterialDefinition::new().color([0.7, 0.3, 0.3, 1.0]),
+            MaterialDefinition::new().blend(
+                paint,
+                rust,
+                BlendMask::Noise {
+                    scale: 4.0,
+                    coverage: 0.35,
+                    softness: 0.04,
+                },
+            ),
         );
 
         scene.add_sphere(
@@ -797,6 +2008,18 @@ impl Scene {
                 .specular([1.0; 4], 0.15),
         );
 
+        // Soap bubble, a film of water with air on both sides
+        scene.add_sphere(
+            Vec3::new(-0.35, -0.25, -0.2),
+            0.2,
+            MaterialDefinition::new()
+                .color([1.0; 4])
+                .glass(1.0)
+                .specular([1.0; 4], 1.0)
+                .smooth(1.0)
+                .thin_film(380.0, 1.33),
+        );
+
         scene
     }
     pub fn balls() -> SceneDefinition {
@@ -878,18 +2101,21 @@ impl Scene {
             MeshDefinition::FromFile {
                 path: "sponza.obj".to_string(),
                 use_mtl: true,
+                rules: vec![],
+                crease_angle: None,
             },
             MaterialDefinition::texture_from_obj(),
         );
-        scene_def.add_mesh(
-            Transform {
-                pos: Vec3::new(-15.0, 60.0, 0.0),
-                rot: Quat::from_rotation_x(PI / 2.0),
-                scale: Vec3::new(40.0, 20.0, 1.0),
-            },
-            MeshDefinition::from_data(MeshData::quad(), vec![0, 1, 2, 0, 2, 3]),
-            MaterialDefinition::default().emissive([1.0; 4], 4.0),
-        );
+        scene_def
+            .add_rect_light(
+                Transform {
+                    pos: Vec3::new(-15.0, 60.0, 0.0),
+                    ..Default::default()
+                },
+                RectLight::new(80.0, 40.0).intensity(4.0),
+            )
+            .named("Atrium Light")
+            .notes("Fills the atrium from under the open roof");
 
         scene_def.add_sphere(
             Vec3::new(5.0, 2.0, 0.0),
@@ -906,6 +2132,26 @@ impl Scene {
                 ..Default::default()
             },
         );
+        // Open roof of the atrium
+        scene_def.add_portal(Portal::new(
+            Vec3::new(-70.0, 65.0, -11.0),
+            Vec3::new(135.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 20.0),
+        ));
+        // Late afternoon sun raking through the atrium
+        scene_def.set_sky(Sky {
+            enabled: true,
+            time_of_day: 16.5,
+            ..Default::default()
+        });
+        // Sunlight bouncing around the atrium finds the small sphere light rarely, clamping
+        // indirect light keeps its fireflies from dominating the first minutes of accumulation
+        scene_def.set_render_settings(RenderSettings {
+            bounces: Some(4),
+            skybox: Some(true),
+            clamp_indirect: Some(10.0),
+            ..Default::default()
+        });
         scene_def
     }
     pub fn cornell_box() -> SceneDefinition {
@@ -925,10 +2171,356 @@ impl Scene {
             MeshDefinition::FromFile {
                 path: "CornellBox-Original.obj".to_string(),
                 use_mtl: true,
+                rules: vec![],
+                crease_angle: None,
             },
             MaterialDefinition::texture_from_obj(),
         );
 
+        // Lit only by its ceiling light, the open front looks out onto black
+        scene_def.set_render_settings(RenderSettings {
+            bounces: Some(6),
+            skybox: Some(false),
+            ..Default::default()
+        });
+        scene_def
+    }
+    pub fn clouds() -> SceneDefinition {
+        let mut scene_def = SceneDefinition::default();
+        scene_def.set_camera(&CameraDescriptor {
+            transform: Transform::cam(Vec3::new(0.0, 1.5, -7.0), Vec3::new(0.0, 1.5, 0.0)),
+            fov: 45.0,
+            near: 0.1,
+            far: 100.0,
+            focus_dist: 7.0,
+            ..Default::default()
+        });
+        scene_def.add_sphere(
+            // Floor
+            Vec3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            MaterialDefinition::new().color([0.5, 0.5, 0.5, 1.0]),
+        );
+        scene_def.add_sphere(
+            Vec3::new(-6.0, 8.0, -4.0),
+            2.0,
+            MaterialDefinition::default().emissive([1.0, 0.95, 0.85, 1.0], 12.0),
+        );
+        scene_def.add_volume(VolumeDefinition {
+            source: VolumeSource::Cloud {
+                resolution: 96,
+                seed: 7,
+            },
+            bounds_min: Vec3::new(-3.0, 1.0, -1.5),
+            bounds_max: Vec3::new(1.0, 3.5, 2.5),
+            density_scale: 6.0,
+            ..Default::default()
+        });
+        scene_def.add_volume(VolumeDefinition {
+            source: VolumeSource::Cloud {
+                resolution: 48,
+                seed: 21,
+            },
+            bounds_min: Vec3::new(1.5, 0.0, -0.5),
+            bounds_max: Vec3::new(3.0, 1.5, 1.0),
+            density_scale: 10.0,
+            color: [0.4, 0.4, 0.4, 1.0],
+            emission_strength: 6.0,
+            ..Default::default()
+        });
+        scene_def
+    }
+    /// Sphere lit from every side by a surrounding light of unit radiance, so the camera and every
+    /// surface see the same radiance. Without emission or absorption in between, anything white
+    /// and lossless reflects exactly that back and the whole frame converges to 1. Darker or
+    /// brighter patches point at energy being lost or gained.
+    pub fn furnace() -> SceneDefinition {
+        let mut scene_def = SceneDefinition::default();
+        scene_def.set_camera(&CameraDescriptor {
+            transform: Transform::cam(Vec3::new(0.0, 0.0, -4.0), Vec3::ZERO),
+            ..Default::default()
+        });
+        scene_def.add_furnace_enclosure();
+        scene_def
+            .add_sphere(
+                Vec3::ZERO,
+                1.0,
+                MaterialDefinition::new()
+                    .color([1.0; 4])
+                    .specular([1.0; 4], 0.0),
+            )
+            .named("White Sphere");
+        scene_def
+    }
+    /// Diffuse spheres from 10% to 100% albedo in the furnace, each converging to its own albedo.
+    /// They are spaced apart so they barely see each other, which keeps the darkening from light
+    /// bouncing between them well under a percent.
+    pub fn reflectance_ramp() -> SceneDefinition {
+        let mut scene_def = SceneDefinition::default();
+        scene_def.set_camera(&CameraDescriptor {
+            transform: Transform::cam(Vec3::new(0.0, 0.0, -13.0), Vec3::ZERO),
+            fov: 45.0,
+            ..Default::default()
+        });
+        scene_def.add_furnace_enclosure();
+        for i in 0..10 {
+            let albedo = (i + 1) as f32 * 0.1;
+            scene_def
+                .add_sphere(
+                    Vec3::new((i as f32 - 4.5) * 1.2, 0.0, 0.0),
+                    0.4,
+                    MaterialDefinition::new()
+                        .color([albedo, albedo, albedo, 1.0])
+                        .specular([1.0; 4], 0.0),
+                )
+                .named(&format!("Albedo {}%", (i + 1) * 10));
+        }
+        scene_def
+    }
+    /// Veach's multiple importance sampling scene: glossy plates from near mirror to rough, each
+    /// reflecting a row of lights from small and bright to large and dim, all of the same power.
+    /// Sampling only the BSDF leaves the small lights on the sharp plates noisy, sampling only the
+    /// lights does the same for the large lights on the rough plates.
+    pub fn mis_test() -> SceneDefinition {
+        let mut scene_def = SceneDefinition::default();
+        let camera = Vec3::new(0.0, 2.0, -12.0);
+        scene_def.set_camera(&CameraDescriptor {
+            transform: Transform::cam(camera, Vec3::new(0.0, 0.5, 0.0)),
+            fov: 40.0,
+            ..Default::default()
+        });
+        let lights = [
+            (0.03, [1.0, 0.3, 0.3, 1.0]),
+            (0.1, [1.0, 1.0, 0.3, 1.0]),
+            (0.3, [0.3, 1.0, 0.3, 1.0]),
+            (0.9, [0.3, 0.5, 1.0, 1.0]),
+        ];
+        for (i, (radius, color)) in lights.into_iter().enumerate() {
+            // Radiance falls with the area so every light gives off the same power
+            let strength = 2.0 * (0.9 / radius) * (0.9 / radius);
+            scene_def
+                .add_sphere(
+                    Vec3::new(-3.75 + i as f32 * 2.5, 4.0, 4.0),
+                    radius,
+                    MaterialDefinition::new()
+                        .color([0.0, 0.0, 0.0, 1.0])
+                        .emissive(color, strength),
+                )
+                .named(&format!("Light {}", i + 1));
+        }
+        let light_row = Vec3::new(0.0, 4.0, 4.0);
+        for (i, smoothness) in [0.98, 0.92, 0.8, 0.6].into_iter().enumerate() {
+            let centre = Vec3::new(0.0, -0.5 + i as f32 * 0.6, -1.5 + i as f32 * 1.3);
+            // Tilted to reflect the middle of the row of lights into the camera
+            let normal =
+                ((light_row - centre).normalize() + (camera - centre).normalize()).normalize();
+            scene_def
+                .add_mesh(
+                    Transform {
+                        pos: centre,
+                        rot: Quat::from_rotation_arc(Vec3::Z, normal),
+                        scale: Vec3::new(4.0, 0.5, 1.0),
+                    },
+                    MeshDefinition::from_data(MeshData::quad(), vec![0, 1, 2, 0, 2, 3]),
+                    MaterialDefinition::new()
+                        .color([0.05, 0.05, 0.05, 1.0])
+                        .specular([1.0; 4], 1.0)
+                        .smooth(smoothness),
+                )
+                .named(&format!("Plate {}", i + 1));
+        }
+        scene_def
+    }
+    /// Ice in water in a glass ball, twice: on the left with dielectric priorities so the water
+    /// fills the glass and the ice displaces the water, on the right without, where every surface
+    /// refracts as if it met air.
+    pub fn nested_glass() -> SceneDefinition {
+        let mut scene_def = SceneDefinition::default();
+        scene_def.set_camera(&CameraDescriptor {
+            transform: Transform::cam(Vec3::new(0.0, 2.5, -7.0), Vec3::new(0.0, 1.0, 0.0)),
+            fov: 40.0,
+            ..Default::default()
+        });
+        scene_def
+            .add_sphere(
+                Vec3::new(0.0, -1000.0, 0.0),
+                1000.0,
+                MaterialDefinition::new().color([0.6, 0.6, 0.6, 1.0]),
+            )
+            .named("Floor");
+        let clear = |ior: f32| {
+            MaterialDefinition::new()
+                .glass(ior)
+                .smooth(1.0)
+                .specular([1.0; 4], 1.0)
+        };
+        for (x, nested) in [(-1.3, true), (1.3, false)] {
+            let priority = |priority: u32| if nested { priority } else { 0 };
+            let side = if nested { "Nested" } else { "Unnested" };
+            let centre = Vec3::new(x, 1.0, 0.0);
+            scene_def
+                .add_sphere(centre, 1.0, clear(1.5).priority(priority(1)))
+                .named(&format!("{} Glass", side));
+            scene_def
+                .add_sphere(
+                    centre,
+                    0.85,
+                    clear(1.33)
+                        .absorption([0.8, 0.3, 0.1, 1.0], 0.6)
+                        .priority(priority(2)),
+                )
+                .named(&format!("{} Water", side));
+            // Pokes through the water into the glass wall
+            scene_def
+                .add_sphere(
+                    centre + Vec3::new(0.35, 0.4, -0.2),
+                    0.35,
+                    clear(1.31).priority(priority(3)),
+                )
+                .named(&format!("{} Ice", side));
+        }
+        scene_def.set_render_settings(RenderSettings {
+            bounces: Some(12),
+            skybox: Some(true),
+            ..Default::default()
+        });
+        scene_def
+    }
+    /// Two rows of streetlights at dusk, every one placed from the same prefab with its lamp a
+    /// prefab nested inside, so all the posts share one copy of each mesh.
+    pub fn streetlights() -> SceneDefinition {
+        let mut scene_def = SceneDefinition::default();
+        scene_def.set_camera(&CameraDescriptor {
+            transform: Transform::cam(Vec3::new(-0.5, 1.6, -5.0), Vec3::new(0.0, 1.8, 8.0)),
+            fov: 50.0,
+            ..Default::default()
+        });
+        scene_def
+            .add_mesh(
+                Transform {
+                    pos: Vec3::new(0.0, 0.0, 8.0),
+                    scale: Vec3::new(12.0, 1.0, 20.0),
+                    ..Default::default()
+                },
+                MeshDefinition::Plane { subdivisions: 1 },
+                MaterialDefinition::new()
+                    .color([0.2, 0.2, 0.22, 1.0])
+                    .specular([1.0; 4], 0.05)
+                    .smooth(0.4),
+            )
+            .named("Road");
+        let painted = || {
+            MaterialDefinition::new()
+                .color([0.08, 0.1, 0.09, 1.0])
+                .specular([1.0; 4], 0.2)
+                .smooth(0.7)
+        };
+        let mut lamp = Prefab::new("Lamp");
+        lamp.add_mesh(
+            Transform::default(),
+            MeshDefinition::Box {
+                size: Vec3::new(0.5, 0.1, 0.3),
+            },
+            painted(),
+        )
+        .named("Hood");
+        lamp.add_rect_light(
+            Transform {
+                pos: Vec3::new(0.0, -0.06, 0.0),
+                ..Default::default()
+            },
+            RectLight::new(0.4, 0.22)
+                .color([1.0, 0.75, 0.45, 1.0])
+                .intensity(25.0),
+        )
+        .named("Bulb");
+        let lamp = scene_def.add_prefab(lamp);
+        let mut streetlight = Prefab::new("Streetlight");
+        streetlight
+            .add_mesh(
+                Transform {
+                    pos: Vec3::new(0.0, 1.5, 0.0),
+                    ..Default::default()
+                },
+                MeshDefinition::Box {
+                    size: Vec3::new(0.12, 3.0, 0.12),
+                },
+                painted(),
+            )
+            .named("Post");
+        streetlight
+            .add_mesh(
+                Transform {
+                    pos: Vec3::new(0.35, 2.95, 0.0),
+                    ..Default::default()
+                },
+                MeshDefinition::Box {
+                    size: Vec3::new(0.7, 0.06, 0.06),
+                },
+                painted(),
+            )
+            .named("Arm");
+        streetlight.add_instance(
+            lamp,
+            Transform {
+                pos: Vec3::new(0.7, 2.87, 0.0),
+                ..Default::default()
+            },
+        );
+        let streetlight = scene_def.add_prefab(streetlight);
+        // Staggered down both sides of the road, the far side turned to lean over it too
+        for i in 0..5 {
+            let z = i as f32 * 4.0;
+            scene_def.add_instance(
+                streetlight,
+                Transform {
+                    pos: Vec3::new(-2.0, 0.0, z),
+                    ..Default::default()
+                },
+            );
+            scene_def.add_instance(
+                streetlight,
+                Transform {
+                    pos: Vec3::new(2.0, 0.0, z + 2.0),
+                    rot: Quat::from_rotation_y(std::f32::consts::PI),
+                    ..Default::default()
+                },
+            );
+        }
+        scene_def.set_sky(Sky {
+            enabled: true,
+            time_of_day: 21.0,
+            ..Default::default()
+        });
+        scene_def.set_render_settings(RenderSettings {
+            bounces: Some(4),
+            skybox: Some(true),
+            ..Default::default()
+        });
+        scene_def
+    }
+    /// Expects a grid exported from OpenVDB/NanoVDB to Mitsuba's `.vol` format at `assets/smoke.vol`.
+    pub fn smoke() -> SceneDefinition {
+        let mut scene_def = SceneDefinition::default();
+        scene_def.set_camera(&CameraDescriptor {
+            transform: Transform::cam(Vec3::new(0.0, 1.0, -4.0), Vec3::new(0.0, 1.0, 0.0)),
+            ..Default::default()
+        });
+        scene_def.add_sphere(
+            Vec3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            MaterialDefinition::new().color([0.5, 0.5, 0.5, 1.0]),
+        );
+        scene_def.add_volume(VolumeDefinition {
+            source: VolumeSource::FromFile {
+                path: "smoke.vol".to_string(),
+            },
+            bounds_min: Vec3::new(-1.0, 0.0, -1.0),
+            bounds_max: Vec3::new(1.0, 2.0, 1.0),
+            density_scale: 20.0,
+            emission_strength: 4.0,
+            ..Default::default()
+        });
         scene_def
     }
     pub fn bugatti() -> SceneDefinition {
@@ -948,18 +2540,21 @@ impl Scene {
             MeshDefinition::FromFile {
                 path: "f1/f1.obj".to_string(),
                 use_mtl: true,
+                rules: vec![],
+                crease_angle: None,
             },
             MaterialDefinition::texture_from_obj(),
         );
-        scene_def.add_mesh(
-            Transform {
-                pos: Vec3::new(-15.0, 60.0, 0.0),
-                rot: Quat::from_rotation_x(PI / 2.0),
-                scale: Vec3::new(40.0, 20.0, 1.0),
-            },
-            MeshDefinition::from_data(MeshData::quad(), vec![0, 1, 2, 0, 2, 3]),
-            MaterialDefinition::default().emissive([1.0; 4], 4.0),
-        );
+        scene_def
+            .add_rect_light(
+                Transform {
+                    pos: Vec3::new(-15.0, 60.0, 0.0),
+                    ..Default::default()
+                },
+                RectLight::new(80.0, 40.0).intensity(4.0),
+            )
+            .named("Atrium Light")
+            .notes("Fills the atrium from under the open roof");
 
         scene_def.add_sphere(
             Vec3::new(5.0, 2.0, 0.0),
@@ -967,6 +2562,10 @@ impl Scene {
             MaterialDefinition {
                 emission_color: [1.0; 4],
                 emission_strength: 10.0,
+                emission_unit: LightUnit::Unitless,
+                light_flags: 0,
+                visibility: 0,
+                thin_film: None,
                 color: [1.0; 4],
                 specular_color: [1.0; 4],
                 absorption: [0.0; 4],
@@ -974,9 +2573,13 @@ impl Scene {
                 smoothness: 0.0,
                 specular: 0.0,
                 ior: 1.0,
+                priority: 0,
                 flag: MaterialFlag::DEFAULT,
                 diffuse_texture: None,
                 normal_texture: None,
+                orm_texture: None,
+                address_mode: AddressMode::Repeat,
+                blend: None,
             },
         );
         scene_def
@@ -995,12 +2598,17 @@ impl Scene {
             n_indices,
             meshes: self.meshes.len() as u32,
             camera: self.camera.to_uniform(),
+            _p1: 0,
             nodes: self.bvh_data.nodes.len() as u32,
-            padding: [0.0; 6],
+            volumes: self.volumes.len().min(MAX_VOLUMES) as u32,
+            portals: self.portals.len().min(MAX_PORTALS) as u32,
+            _p2: 0,
+            sun: self.sky.to_uniform(),
+            fog: self.fog.to_uniform(),
         }
     }
 
-    fn from_name(scene_name: SceneName) -> SceneDefinition {
+    pub fn from_name(scene_name: SceneName) -> SceneDefinition {
         match scene_name {
             SceneName::Balls => Scene::balls(),
             SceneName::RandomBalls => Scene::random_balls(),
@@ -1009,6 +2617,12 @@ impl Scene {
             SceneName::Metal => Scene::metal(),
             SceneName::Sponza => Scene::sponza(),
             SceneName::CornellBox => Scene::cornell_box(),
+            SceneName::Clouds => Scene::clouds(),
+            SceneName::Furnace => Scene::furnace(),
+            SceneName::ReflectanceRamp => Scene::reflectance_ramp(),
+            SceneName::MisTest => Scene::mis_test(),
+            SceneName::NestedGlass => Scene::nested_glass(),
+            SceneName::Streetlights => Scene::streetlights(),
             SceneName::Empty => todo!(),
         }
     }
@@ -1021,6 +2635,89 @@ pub struct SceneUniform {
     n_indices: u32,
     meshes: u32,
     camera: CameraUniform,
+    /// The shader pads the camera struct to a multiple of 16 bytes
+    _p1: u32,
     nodes: u32,
-    padding: [f32; 6],
+    volumes: u32,
+    portals: u32,
+    _p2: u32,
+    /// Direction to the sky's sun and its brightness, see `Sky::to_uniform`
+    sun: [f32; 4],
+    /// See `HeightFog::to_uniform`
+    fog: [[f32; 4]; 2],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::components::material::RayVisibility;
+
+    fn scene() -> Scene {
+        let mut definition = SceneDefinition::default();
+        definition.add_sphere(Vec3::ZERO, 1.0, MaterialDefinition::new());
+        definition.add_mesh(
+            Transform::default(),
+            MeshDefinition::Plane { subdivisions: 1 },
+            MaterialDefinition::new(),
+        );
+        Scene::instantiate_scene(&definition, &mut AssetManager::new())
+    }
+
+    #[test]
+    fn edits_no_longer_match_the_definition() {
+        let mut scene = scene();
+        assert!(scene.matches_definition());
+        scene.camera.transform.pos = Vec3::ONE;
+        assert!(scene.matches_definition());
+
+        scene.meshes[0].transform.pos.y += 1.0;
+        assert!(!scene.matches_definition());
+        scene.meshes[0].transform.pos.y -= 1.0;
+        assert!(scene.matches_definition());
+
+        let mut recoloured = self::scene();
+        recoloured.spheres[0].material.color = [1.0, 0.0, 0.0, 1.0];
+        assert!(!recoloured.matches_definition());
+        let mut scattered = self::scene();
+        scattered.meshes.push(scattered.meshes[0].clone());
+        assert!(!scattered.matches_definition());
+        let mut foggy = self::scene();
+        foggy.fog.enabled = !foggy.fog.enabled;
+        assert!(!foggy.matches_definition());
+    }
+
+    #[test]
+    fn presets_keep_visibility() {
+        let mut scene = scene();
+        scene.spheres[0].material.visibility = RayVisibility::HiddenFromCamera as u32;
+        scene.apply_preset(0, MaterialPreset::Glass);
+        let material = scene.spheres[0].material;
+        assert_eq!(material.flag, MaterialFlag::GLASS as i32);
+        assert_eq!(material.visibility, RayVisibility::HiddenFromCamera as u32);
+        scene.apply_preset(1, MaterialPreset::Light);
+        assert!(scene.meshes[0].material.emission_strength > 0.0);
+        // Out of range entities are ignored
+        scene.apply_preset(2, MaterialPreset::Metal);
+    }
+
+    #[test]
+    fn streaming_scenes_match_once_complete() {
+        let mut scene = scene();
+        scene.defined = None;
+        scene.streaming = 1;
+        assert!(!scene.matches_definition());
+        let mut chunk_mesh = scene.meshes[0].clone();
+        chunk_mesh.label = Some("model.obj".to_owned());
+        scene.apply_chunk(MeshChunk {
+            stream_id: 0,
+            source: "model.obj".to_owned(),
+            complete: true,
+            mesh: chunk_mesh,
+            triangles: vec![],
+            nodes: vec![],
+            degenerate_triangles: 0,
+        });
+        assert_eq!(scene.streaming, 0);
+        assert!(scene.matches_definition());
+    }
 }
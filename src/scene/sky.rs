@@ -0,0 +1,95 @@
+use glam::{Quat, Vec3, Vec4, Vec4Swizzles};
+
+/// Direction of the procedural sky's sun when it isn't placed by time of day, slightly off zenith
+const FIXED_SUN: [f32; 3] = [0.1, 1.0, 0.1];
+/// Sun disc brightness of the fixed sun
+const FIXED_SUN_INTENSITY: f32 = 0.1;
+const SKY_HORIZON: Vec4 = Vec4::new(1.0, 1.0, 1.0, 0.0);
+const SKY_ZENITH: Vec4 = Vec4::new(0.0788092, 0.36480793, 0.7264151, 0.0);
+const GROUND_COLOR: Vec4 = Vec4::new(0.35, 0.3, 0.35, 0.0);
+const SUN_FOCUS: f32 = 500.0;
+const SUNSET_HORIZON: Vec4 = Vec4::new(1.0, 0.45, 0.2, 0.0);
+const SUNSET_SUN: Vec4 = Vec4::new(1.0, 0.5, 0.25, 1.0);
+const NIGHT_LEVEL: f32 = 0.01;
+
+/// Places the procedural sky's sun from a local solar time, date and latitude, so a scene can be
+/// lit at any hour and animated through a day.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+    /// Use the time of day, otherwise the sun stays fixed near the zenith
+    pub enabled: bool,
+    /// Local solar time in hours, 12 is solar noon
+    pub time_of_day: f32,
+    /// Day of the year from 1 to 365, sets the sun's declination
+    pub day_of_year: f32,
+    /// Degrees, positive north of the equator
+    pub latitude: f32,
+    /// Degrees north is turned from -Z towards +X, so the sun can be lined up with a building
+    pub north: f32,
+    /// Brightness of the sun disc
+    pub sun_intensity: f32,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            time_of_day: 12.0,
+            day_of_year: 172.0,
+            latitude: 51.5,
+            north: 0.0,
+            sun_intensity: 20.0,
+        }
+    }
+}
+
+impl Sky {
+    /// Unit vector towards the sun, below the horizon at night.
+    pub fn sun_direction(self) -> Vec3 {
+        let declination = (-23.44f32).to_radians()
+            * (std::f32::consts::TAU / 365.0 * (self.day_of_year + 10.0)).cos();
+        let hour_angle = (15.0 * (self.time_of_day - 12.0)).to_radians();
+        let latitude = self.latitude.to_radians();
+        // East, north and up components of the direction to the sun
+        let east = -declination.cos() * hour_angle.sin();
+        let north = declination.sin() * latitude.cos()
+            - declination.cos() * hour_angle.cos() * latitude.sin();
+        let up = declination.sin() * latitude.sin()
+            + declination.cos() * hour_angle.cos() * latitude.cos();
+        let local = Vec3::new(east, up, -north);
+        (Quat::from_rotation_y(-self.north.to_radians()) * local).normalize()
+    }
+    /// Sun direction and disc brightness as the shader reads them.
+    pub fn to_uniform(self) -> [f32; 4] {
+        match self.enabled {
+            true => self.sun_direction().extend(self.sun_intensity).to_array(),
+            false => [
+                FIXED_SUN[0],
+                FIXED_SUN[1],
+                FIXED_SUN[2],
+                FIXED_SUN_INTENSITY,
+            ],
+        }
+    }
+}
+
+/// Light the procedural sky sends back along `dir` with the sun from `Sky::to_uniform`, mirrors
+/// `get_environment_light`.
+pub fn radiance(sun: Vec4, dir: Vec3) -> Vec4 {
+    let sky_gradient_t = smoothstep(0.0, 0.4, dir.y).powf(0.35);
+    let ground_to_sky_t = smoothstep(-0.01, 0.0, dir.y);
+    let day = smoothstep(-0.1, 0.15, sun.y).max(NIGHT_LEVEL);
+    let dusk = 1.0 - smoothstep(0.0, 0.3, sun.y);
+    let sky_gradient = SKY_HORIZON
+        .lerp(SUNSET_HORIZON, dusk)
+        .lerp(SKY_ZENITH, sky_gradient_t);
+    let sun_disc =
+        dir.dot(sun.xyz()).max(0.0).powf(SUN_FOCUS) * sun.w * Vec4::ONE.lerp(SUNSET_SUN, dusk);
+    let above = (ground_to_sky_t >= 1.0) as u32 as f32;
+    GROUND_COLOR.lerp(sky_gradient, ground_to_sky_t) * day + sun_disc * above
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
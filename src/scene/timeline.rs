@@ -0,0 +1,394 @@
+use std::path::PathBuf;
+
+use glam::{Quat, Vec3};
+
+use crate::scene::{camera::CameraShake, components::transform::Transform};
+
+/// What a track animates. Entities are indexed like `SceneManager::selected_entity`,
+/// spheres first then meshes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnimTarget {
+    Camera,
+    Entity(usize),
+    Sky,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnimProperty {
+    Position,
+    /// Quaternion, interpolated component wise and renormalised
+    Rotation,
+    Scale,
+    Fov,
+    Radius,
+    Color,
+    EmissionColor,
+    EmissionStrength,
+    Smoothness,
+    Specular,
+    Ior,
+    /// Hours, see `Sky::time_of_day`
+    TimeOfDay,
+    DayOfYear,
+}
+
+impl AnimProperty {
+    pub const CAMERA: [AnimProperty; 3] = [
+        AnimProperty::Position,
+        AnimProperty::Rotation,
+        AnimProperty::Fov,
+    ];
+    pub const SKY: [AnimProperty; 2] = [AnimProperty::TimeOfDay, AnimProperty::DayOfYear];
+    /// Properties that can follow an audio band, each entity only offers those it has
+    pub const AUDIO: [AnimProperty; 5] = [
+        AnimProperty::EmissionStrength,
+        AnimProperty::EmissionColor,
+        AnimProperty::Color,
+        AnimProperty::Scale,
+        AnimProperty::Radius,
+    ];
+    pub const SPHERE: [AnimProperty; 8] = [
+        AnimProperty::Position,
+        AnimProperty::Radius,
+        AnimProperty::Color,
+        AnimProperty::EmissionColor,
+        AnimProperty::EmissionStrength,
+        AnimProperty::Smoothness,
+        AnimProperty::Specular,
+        AnimProperty::Ior,
+    ];
+    pub const MESH: [AnimProperty; 9] = [
+        AnimProperty::Position,
+        AnimProperty::Rotation,
+        AnimProperty::Scale,
+        AnimProperty::Color,
+        AnimProperty::EmissionColor,
+        AnimProperty::EmissionStrength,
+        AnimProperty::Smoothness,
+        AnimProperty::Specular,
+        AnimProperty::Ior,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    /// Cubic Bezier with handles along the Catmull-Rom tangent, passing smoothly through each key
+    Bezier,
+}
+
+/// Property values are stored in up to four channels, unused channels are ignored.
+pub type AnimValue = [f32; 4];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub frame: u32,
+    pub value: AnimValue,
+    /// Interpolation used from this key to the next
+    pub interpolation: Interpolation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub target: AnimTarget,
+    pub property: AnimProperty,
+    /// Sorted by frame, at most one key per frame
+    pub keys: Vec<Keyframe>,
+}
+
+impl Track {
+    pub fn evaluate(&self, frame: f32) -> Option<AnimValue> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if frame <= first.frame as f32 {
+            return Some(first.value);
+        }
+        if frame >= last.frame as f32 {
+            return Some(last.value);
+        }
+        let next = self.keys.iter().position(|k| k.frame as f32 > frame)?;
+        let (k1, k2) = (&self.keys[next - 1], &self.keys[next]);
+        let t = (frame - k1.frame as f32) / (k2.frame - k1.frame) as f32;
+
+        let rotation = self.property == AnimProperty::Rotation;
+        let p1 = k1.value;
+        let p2 = align(p1, k2.value, rotation);
+        let mut value = [0.0; 4];
+        match k1.interpolation {
+            Interpolation::Linear => {
+                for c in 0..4 {
+                    value[c] = p1[c] + (p2[c] - p1[c]) * t;
+                }
+            }
+            Interpolation::Bezier => {
+                let k0 = &self.keys[next.saturating_sub(2)];
+                let k3 = &self.keys[(next + 1).min(self.keys.len() - 1)];
+                let p0 = align(p1, k0.value, rotation);
+                let p3 = align(p2, k3.value, rotation);
+                let span = (k2.frame - k1.frame) as f32;
+                // Catmull-Rom tangents scaled to this segment, so uneven key spacing doesn't overshoot
+                let m1_scale = span / (k2.frame - k0.frame).max(1) as f32;
+                let m2_scale = span / (k3.frame - k1.frame).max(1) as f32;
+                let (t2, t3) = (t * t, t * t * t);
+                let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+                let h10 = t3 - 2.0 * t2 + t;
+                let h01 = -2.0 * t3 + 3.0 * t2;
+                let h11 = t3 - t2;
+                for c in 0..4 {
+                    let m1 = (p2[c] - p0[c]) * m1_scale;
+                    let m2 = (p3[c] - p1[c]) * m2_scale;
+                    value[c] = h00 * p1[c] + h10 * m1 + h01 * p2[c] + h11 * m2;
+                }
+            }
+        }
+        if rotation {
+            let q = Quat::from_array(value).normalize();
+            value = q.to_array();
+        }
+        Some(value)
+    }
+}
+
+/// Flips quaternion `b` into the same hemisphere as `a` so interpolation takes the short way round.
+fn align(a: AnimValue, b: AnimValue, rotation: bool) -> AnimValue {
+    if rotation && Quat::from_array(a).dot(Quat::from_array(b)) < 0.0 {
+        b.map(|v| -v)
+    } else {
+        b
+    }
+}
+
+/// Drives a property from the level of one audio band instead of keys, see `AudioInput`. The
+/// value moves from `quiet` in silence to `loud` at the band's peak.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioBinding {
+    pub band: usize,
+    pub target: AnimTarget,
+    pub property: AnimProperty,
+    pub quiet: AnimValue,
+    pub loud: AnimValue,
+}
+
+impl AudioBinding {
+    /// Binds `property` starting from its current value, with a loud value that makes the
+    /// music easy to see.
+    pub fn new(
+        band: usize,
+        target: AnimTarget,
+        property: AnimProperty,
+        current: AnimValue,
+    ) -> Self {
+        let loud = match property {
+            AnimProperty::EmissionStrength => [current[0].max(1.0) * 4.0, 0.0, 0.0, 0.0],
+            AnimProperty::EmissionColor | AnimProperty::Color => [1.0; 4],
+            AnimProperty::Scale | AnimProperty::Radius => current.map(|v| v * 1.5),
+            _ => current,
+        };
+        Self {
+            band,
+            target,
+            property,
+            quiet: current,
+            loud,
+        }
+    }
+    pub fn value(&self, level: f32) -> AnimValue {
+        std::array::from_fn(|c| self.quiet[c] + (self.loud[c] - self.quiet[c]) * level)
+    }
+}
+
+/// Progress of rendering frames of the timeline to disk.
+#[derive(Debug, Clone)]
+pub struct SequenceRender {
+    pub frame: u32,
+    pub first: u32,
+    /// Where to stop, short of the timeline's end when only some frames are rendered again
+    pub last: u32,
+    pub dir: PathBuf,
+}
+
+/// Keyframed properties of a scene, scrubbed and played back from the timeline panel.
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    pub tracks: Vec<Track>,
+    /// Properties following the audio input, applied over the keyed values
+    pub audio: Vec<AudioBinding>,
+    pub frame: u32,
+    pub start: u32,
+    pub end: u32,
+    pub fps: f32,
+    pub playing: bool,
+    /// Interpolation given to newly inserted keys
+    pub interpolation: Interpolation,
+    /// Property keyed by the timeline panel's Key button
+    pub key_property: AnimProperty,
+    /// Accumulated frames per sequence frame
+    pub samples_per_frame: u32,
+    pub sequence: Option<SequenceRender>,
+    /// First and last frame to render, set by the UI and started by the app
+    pub sequence_requested: Option<(u32, u32)>,
+    /// Set when the current frame changed and the scene needs re-evaluating
+    pub dirty: bool,
+    pub shake: CameraShake,
+    /// Whether the shake is currently applied, only while playing or rendering a sequence
+    shaking: bool,
+    play_time: f32,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            tracks: vec![],
+            audio: vec![],
+            frame: 0,
+            start: 0,
+            end: 120,
+            fps: 24.0,
+            playing: false,
+            interpolation: Interpolation::Bezier,
+            key_property: AnimProperty::Position,
+            samples_per_frame: 64,
+            sequence: None,
+            sequence_requested: None,
+            dirty: false,
+            shake: CameraShake::default(),
+            shaking: false,
+            play_time: 0.0,
+        }
+    }
+}
+
+impl Timeline {
+    /// Adds a key at the current frame, replacing any key already there.
+    pub fn insert_key(&mut self, target: AnimTarget, property: AnimProperty, value: AnimValue) {
+        let key = Keyframe {
+            frame: self.frame,
+            value,
+            interpolation: self.interpolation,
+        };
+        let track = match self
+            .tracks
+            .iter_mut()
+            .position(|t| t.target == target && t.property == property)
+        {
+            Some(i) => &mut self.tracks[i],
+            None => {
+                self.tracks.push(Track {
+                    target,
+                    property,
+                    keys: vec![],
+                });
+                self.tracks.last_mut().unwrap()
+            }
+        };
+        match track.keys.binary_search_by_key(&key.frame, |k| k.frame) {
+            Ok(i) => track.keys[i] = key,
+            Err(i) => track.keys.insert(i, key),
+        }
+    }
+    /// Removes the key at the current frame from every track, dropping tracks left empty.
+    pub fn remove_keys_at_frame(&mut self) {
+        let frame = self.frame;
+        for track in self.tracks.iter_mut() {
+            track.keys.retain(|k| k.frame != frame);
+        }
+        self.tracks.retain(|t| !t.keys.is_empty());
+    }
+    pub fn has_key_at_frame(&self) -> bool {
+        self.tracks
+            .iter()
+            .any(|t| t.keys.iter().any(|k| k.frame == self.frame))
+    }
+    /// Changes the interpolation of every key on the current frame.
+    pub fn set_interpolation_at_frame(&mut self, interpolation: Interpolation) {
+        let frame = self.frame;
+        for key in self.tracks.iter_mut().flat_map(|t| t.keys.iter_mut()) {
+            if key.frame == frame {
+                key.interpolation = interpolation;
+            }
+        }
+    }
+    pub fn set_frame(&mut self, frame: u32) {
+        self.frame = frame.clamp(self.start, self.end.max(self.start));
+        self.play_time = (self.frame - self.start) as f32;
+        self.dirty = true;
+    }
+    /// Advances playback, returning true when the scene should be re-evaluated. Scenes without
+    /// keys still play while `textures_animated`, for their image sequences.
+    pub fn update(&mut self, dt: f32, textures_animated: bool) -> bool {
+        let animated = !self.tracks.is_empty() || self.shake.enabled || textures_animated;
+        if self.playing && self.sequence.is_none() && animated {
+            let length = (self.end.max(self.start) - self.start + 1) as f32;
+            self.play_time = (self.play_time + dt * self.fps) % length;
+            let frame = self.start + self.play_time as u32;
+            if frame != self.frame {
+                self.frame = frame;
+                self.dirty = true;
+            }
+        }
+        let shaking = self.shake.enabled && (self.playing || self.sequence.is_some());
+        if shaking != self.shaking {
+            self.shaking = shaking;
+            self.dirty = true;
+        }
+        std::mem::take(&mut self.dirty)
+    }
+    /// Camera shake at the current frame, identity unless playing or rendering a sequence.
+    pub fn shake_offset(&self) -> Transform {
+        if self.shaking {
+            self.shake.offset(self.frame as f32 / self.fps)
+        } else {
+            Transform::default()
+        }
+    }
+    /// Value of every track at the current frame.
+    pub fn evaluate(&self) -> Vec<(AnimTarget, AnimProperty, AnimValue)> {
+        self.tracks
+            .iter()
+            .filter_map(|t| Some((t.target, t.property, t.evaluate(self.frame as f32)?)))
+            .collect()
+    }
+    /// Seconds into the timeline at the current frame.
+    pub fn time(&self) -> f32 {
+        self.frame as f32 / self.fps
+    }
+    /// Renders `first` to `last` into `dir`, replacing any frames already there. With the seed
+    /// following the frame, a frame rendered again comes out the same as the first time.
+    pub fn start_sequence(&mut self, dir: PathBuf, first: u32, last: u32) {
+        self.playing = false;
+        self.set_frame(first);
+        self.sequence = Some(SequenceRender {
+            frame: self.frame,
+            first: self.frame,
+            last: last.clamp(self.frame, self.end.max(self.start)),
+            dir,
+        });
+    }
+    /// Moves the sequence on to the next frame, returning false once it has finished.
+    pub fn next_sequence_frame(&mut self) -> bool {
+        let Some(sequence) = self.sequence.as_mut() else {
+            return false;
+        };
+        if sequence.frame >= sequence.last {
+            self.sequence = None;
+            return false;
+        }
+        sequence.frame += 1;
+        let frame = sequence.frame;
+        self.set_frame(frame);
+        true
+    }
+    pub fn sequence_progress(&self) -> Option<f32> {
+        let sequence = self.sequence.as_ref()?;
+        let length = (sequence.last - sequence.first + 1) as f32;
+        Some((sequence.frame - sequence.first) as f32 / length)
+    }
+}
+
+pub fn vec3_value(v: Vec3) -> AnimValue {
+    [v.x, v.y, v.z, 0.0]
+}
+
+pub fn value_vec3(v: AnimValue) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
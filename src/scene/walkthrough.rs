@@ -0,0 +1,333 @@
+use std::sync::Arc;
+
+use glam::{Mat4, Vec3};
+
+use crate::bvh::{Aabb, Bvh, Quality, Ray, Triangle};
+use crate::scene::{components::geometry::mesh::MeshData, scene::Scene};
+
+/// Furthest the body moves between collision checks, as a share of its radius, so walking fast
+/// can't tunnel through thin walls
+const MAX_STEP: f32 = 0.5;
+/// Steps a single move is split into at most, a teleport across the scene isn't walked
+const MAX_STEPS: usize = 256;
+/// Rounds of pushing the body out of what it overlaps per step, corners take more than one
+const RESOLVE_ITERATIONS: usize = 4;
+/// Furthest below the feet a floor is looked for, without one the height is left as it is
+const MAX_FALL: f32 = 100.0;
+
+/// First-person navigation that keeps the camera at eye height over the floor and stops it at
+/// walls, for walking through architecture rather than flying through it. The body is a sphere of
+/// `radius` at the eye and another resting just above `step_height`, anything lower is stepped
+/// onto. Movement is kept level, flying up and down does nothing while walking.
+pub struct Walkthrough {
+    pub enabled: bool,
+    pub eye_height: f32,
+    pub radius: f32,
+    pub step_height: f32,
+    /// Set on enabling, so the camera settles at eye height without having to move first
+    pub snap_requested: bool,
+    world: World,
+}
+
+/// A BVH of each mesh in its own model space, built once per mesh data so moving a mesh costs
+/// nothing. Queries are taken into each mesh's space instead.
+#[derive(Default)]
+struct World {
+    blas: Vec<(Arc<MeshData>, Bvh)>,
+}
+
+impl Default for Walkthrough {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            eye_height: 1.7,
+            radius: 0.25,
+            step_height: 0.35,
+            snap_requested: false,
+            world: World::default(),
+        }
+    }
+}
+
+impl Walkthrough {
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.snap_requested = self.enabled;
+    }
+    /// Where the camera at `from` ends up when it tries to move to `to`: slid along whatever it
+    /// walks into, then stood at eye height over the floor below.
+    pub fn constrain(&mut self, scene: &Scene, from: Vec3, to: Vec3) -> Vec3 {
+        self.world.update(scene);
+        let radius = self.radius.max(0.01);
+        let delta = Vec3::new(to.x - from.x, 0.0, to.z - from.z);
+        let steps = ((delta.length() / (radius * MAX_STEP)).ceil() as usize).clamp(1, MAX_STEPS);
+        let mut pos = from;
+        for _ in 0..steps {
+            pos += delta / steps as f32;
+            pos = self.resolve(scene, pos, radius);
+        }
+        self.stand(scene, pos, radius)
+    }
+    /// Centres of the body's spheres for the eye at `eye`, the lower one never above the eye.
+    fn body(&self, eye: Vec3, radius: f32) -> [Vec3; 2] {
+        let knee = (self.step_height + radius - self.eye_height).min(0.0);
+        [eye, eye + Vec3::Y * knee]
+    }
+    /// Pushes the body level out of the triangles and spheres it overlaps.
+    fn resolve(&self, scene: &Scene, mut eye: Vec3, radius: f32) -> Vec3 {
+        for _ in 0..RESOLVE_ITERATIONS {
+            let mut pushed = false;
+            for offset in self.body(eye, radius).map(|centre| centre - eye) {
+                let centre = eye + offset;
+                let mut bounds = Aabb::default();
+                bounds.grow_point(centre - Vec3::splat(radius));
+                bounds.grow_point(centre + Vec3::splat(radius));
+                let triangle_points = self
+                    .world
+                    .query(scene, &bounds)
+                    .into_iter()
+                    .map(|triangle| closest_on_triangle(centre, &triangle));
+                // Spheres the camera is inside, like a sky dome or the furnace, are left alone
+                let sphere_points = scene.spheres.iter().filter_map(|sphere| {
+                    let sphere_centre = Vec3::from_array(sphere.pos);
+                    let outward = centre - sphere_centre;
+                    (outward.length() > sphere.radius)
+                        .then(|| sphere_centre + outward.normalize() * sphere.radius)
+                });
+                let points: Vec<Vec3> = triangle_points.chain(sphere_points).collect();
+                for point in points {
+                    let away = eye + offset - point;
+                    let distance = away.length();
+                    if distance >= radius || distance <= f32::EPSILON {
+                        continue;
+                    }
+                    // Floors and ceilings are left to `stand`, only walls push the body sideways
+                    let push = away / distance * (radius - distance);
+                    eye += Vec3::new(push.x, 0.0, push.z);
+                    pushed |= push.x != 0.0 || push.z != 0.0;
+                }
+            }
+            if !pushed {
+                break;
+            }
+        }
+        eye
+    }
+    /// Raises or lowers the eye to `eye_height` over the floor under the lower sphere.
+    fn stand(&self, scene: &Scene, eye: Vec3, radius: f32) -> Vec3 {
+        let [_, knee] = self.body(eye, radius);
+        let ray = Ray::new(knee, Vec3::NEG_Y);
+        let mesh_floor = self.world.intersect(scene, &ray, MAX_FALL);
+        let sphere_floor = scene
+            .spheres
+            .iter()
+            .filter_map(|sphere| {
+                ray_sphere(&ray, Vec3::from_array(sphere.pos), sphere.radius)
+                    .filter(|t| *t < MAX_FALL)
+            })
+            .min_by(f32::total_cmp);
+        let floor = match (mesh_floor, sphere_floor) {
+            (Some(mesh), Some(sphere)) => Some(mesh.min(sphere)),
+            (mesh, sphere) => mesh.or(sphere),
+        };
+        match floor {
+            Some(t) => Vec3::new(eye.x, knee.y - t + self.eye_height, eye.z),
+            None => eye,
+        }
+    }
+}
+
+impl World {
+    fn update(&mut self, scene: &Scene) {
+        self.blas.truncate(scene.meshes.len());
+        for (i, mesh) in scene.meshes.iter().enumerate() {
+            if self
+                .blas
+                .get(i)
+                .is_some_and(|(data, _)| Arc::ptr_eq(data, &mesh.data))
+            {
+                continue;
+            }
+            let positions: Vec<Vec3> = mesh.data.vertices.iter().map(|v| v.pos).collect();
+            let bvh = Bvh::build_indexed(&positions, &mesh.data.indices, Quality::Low);
+            match i < self.blas.len() {
+                true => self.blas[i] = (mesh.data.clone(), bvh),
+                false => self.blas.push((mesh.data.clone(), bvh)),
+            }
+        }
+    }
+    /// Each mesh's BVH with its model to world and world to model matrices, leaving out meshes
+    /// scaled flat, which can't be taken back into model space.
+    fn meshes<'a>(
+        &'a self,
+        scene: &'a Scene,
+    ) -> impl Iterator<Item = (&'a MeshData, &'a Bvh, Mat4, Mat4)> {
+        scene
+            .meshes
+            .iter()
+            .zip(&self.blas)
+            .filter_map(|(mesh, (data, bvh))| {
+                let model_to_world = mesh.transform.to_matrix();
+                (model_to_world.determinant().abs() > f32::EPSILON)
+                    .then(|| (data.as_ref(), bvh, model_to_world, model_to_world.inverse()))
+            })
+    }
+    /// World space triangles whose bounds may overlap `bounds`.
+    fn query(&self, scene: &Scene, bounds: &Aabb) -> Vec<Triangle> {
+        let mut found = vec![];
+        for (data, bvh, model_to_world, world_to_model) in self.meshes(scene) {
+            // The box around the query's corners in model space, looser than the query when rotated
+            let mut model_bounds = Aabb::default();
+            for corner in 0..8 {
+                let pick = |bit: usize| (corner >> bit) & 1 == 1;
+                let point = Vec3::select(
+                    glam::BVec3::new(pick(0), pick(1), pick(2)),
+                    bounds.max,
+                    bounds.min,
+                );
+                model_bounds.grow_point(world_to_model.transform_point3(point));
+            }
+            found.extend(bvh.query(&model_bounds).into_iter().map(|triangle| {
+                let corners = &data.indices[triangle as usize * 3..][..3];
+                [0, 1, 2].map(|corner| {
+                    model_to_world.transform_point3(data.vertices[corners[corner] as usize].pos)
+                })
+            }));
+        }
+        found
+    }
+    /// Distance to the closest triangle along `ray` nearer than `max_t`, in multiples of its
+    /// direction, which a ray moved into model space keeps.
+    fn intersect(&self, scene: &Scene, ray: &Ray, max_t: f32) -> Option<f32> {
+        self.meshes(scene)
+            .filter_map(|(_, bvh, _, world_to_model)| {
+                let model_ray = Ray::new(
+                    world_to_model.transform_point3(ray.origin),
+                    world_to_model.transform_vector3(ray.dir),
+                );
+                bvh.intersect(&model_ray, max_t).map(|hit| hit.t)
+            })
+            .min_by(f32::total_cmp)
+    }
+}
+
+/// Distance along `ray` to the outside of a sphere, rays starting inside miss it.
+fn ray_sphere(ray: &Ray, centre: Vec3, radius: f32) -> Option<f32> {
+    let offset = ray.origin - centre;
+    let b = offset.dot(ray.dir);
+    let c = offset.length_squared() - radius * radius;
+    let discriminant = b * b - c;
+    (c >= 0.0 && discriminant >= 0.0)
+        .then(|| -b - discriminant.sqrt())
+        .filter(|t| *t >= 0.0)
+}
+
+/// Point of `triangle` closest to `p`, by which of its regions `p` falls in.
+fn closest_on_triangle(p: Vec3, triangle: &Triangle) -> Vec3 {
+    let [a, b, c] = *triangle;
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: Triangle = [Vec3::ZERO, Vec3::X, Vec3::Y];
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        a.abs_diff_eq(b, 1e-5)
+    }
+
+    #[test]
+    fn closest_on_triangle_by_region() {
+        // Over the face, projected straight onto it
+        assert!(close(
+            closest_on_triangle(Vec3::new(0.25, 0.25, 3.0), &TRIANGLE),
+            Vec3::new(0.25, 0.25, 0.0)
+        ));
+        // Past each corner
+        assert!(close(
+            closest_on_triangle(Vec3::new(-1.0, -1.0, 1.0), &TRIANGLE),
+            Vec3::ZERO
+        ));
+        assert!(close(
+            closest_on_triangle(Vec3::new(2.0, -0.5, 0.0), &TRIANGLE),
+            Vec3::X
+        ));
+        assert!(close(
+            closest_on_triangle(Vec3::new(-0.5, 2.0, 0.0), &TRIANGLE),
+            Vec3::Y
+        ));
+        // Beside each edge
+        assert!(close(
+            closest_on_triangle(Vec3::new(0.5, -1.0, 0.5), &TRIANGLE),
+            Vec3::new(0.5, 0.0, 0.0)
+        ));
+        assert!(close(
+            closest_on_triangle(Vec3::new(-1.0, 0.5, 0.0), &TRIANGLE),
+            Vec3::new(0.0, 0.5, 0.0)
+        ));
+        assert!(close(
+            closest_on_triangle(Vec3::new(1.0, 1.0, -2.0), &TRIANGLE),
+            Vec3::new(0.5, 0.5, 0.0)
+        ));
+    }
+
+    #[test]
+    fn closest_on_triangle_beats_sampled_points() {
+        let triangle = [
+            Vec3::new(0.3, -0.2, 1.0),
+            Vec3::new(2.0, 0.5, -0.4),
+            Vec3::new(-0.7, 1.6, 0.2),
+        ];
+        let samples: Vec<Vec3> = (0..=20)
+            .flat_map(|i| (0..=20 - i).map(move |j| (i as f32 / 20.0, j as f32 / 20.0)))
+            .map(|(u, v)| {
+                triangle[0] + (triangle[1] - triangle[0]) * u + (triangle[2] - triangle[0]) * v
+            })
+            .collect();
+        for i in 0..64 {
+            let t = i as f32;
+            let p = Vec3::new((t * 1.7).sin(), (t * 0.9).cos(), (t * 2.3).sin()) * 3.0;
+            let closest = closest_on_triangle(p, &triangle);
+            let nearest_sample = samples
+                .iter()
+                .map(|sample| sample.distance(p))
+                .fold(f32::INFINITY, f32::min);
+            assert!(closest.distance(p) <= nearest_sample + 1e-4, "{}", p);
+        }
+    }
+}